@@ -0,0 +1,435 @@
+//! Animation state machine: states play a clip or a blend tree of clips, transitions fire on
+//! parameter conditions and crossfade between states. Each `update` produces the weighted clip
+//! samples the final pose is blended from, whatever a clip animates (skeletons, morph weights).
+
+use std::collections::HashMap;
+
+use jobs;
+use lang::{Float, TimeSec};
+
+/// Application defined clip identifier, e.g. an index into its clip list
+pub type ClipId = usize;
+
+/// Index of a state in the controller
+pub type StateId = usize;
+
+/// A clip as the controller sees it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Clip {
+    pub id: ClipId,
+    pub duration: TimeSec,
+    pub looped: bool,
+}
+
+impl Clip {
+    pub fn new(id: ClipId, duration: TimeSec, looped: bool) -> Clip {
+        Clip { id, duration, looped }
+    }
+
+    /// Time in the clip at the normalized time `phase`, 1 per playthrough
+    pub fn time_at(&self, phase: Float) -> TimeSec {
+        let phase = if self.looped { phase.rem_euclid(1.0) } else { phase.clamp(0.0, 1.0) };
+        phase as TimeSec * self.duration
+    }
+}
+
+/// What a state plays
+#[derive(Debug, Clone, PartialEq)]
+pub enum Motion {
+    Clip(Clip),
+    /// clips placed along a float parameter, the two around its value are blended,
+    /// e.g. idle, walk and run by speed
+    Blend1D { parameter: String, clips: Vec<(Float, Clip)> },
+    /// clips placed on a plane of two float parameters, weighted by inverse distance,
+    /// e.g. strafing by velocity x and z
+    Blend2D { x: String, y: String, clips: Vec<([Float; 2], Clip)> },
+}
+
+impl Motion {
+    fn clips(&self) -> Vec<Clip> {
+        match *self {
+            Motion::Clip(clip) => vec![clip],
+            Motion::Blend1D { ref clips, .. } => clips.iter().map(|&(_, clip)| clip).collect(),
+            Motion::Blend2D { ref clips, .. } => clips.iter().map(|&(_, clip)| clip).collect(),
+        }
+    }
+}
+
+/// Weight of each child of a 1D blend at `value`, children sorted by threshold
+pub fn blend_1d(thresholds: &[Float], value: Float) -> Vec<Float> {
+    let mut weights = vec![0.0; thresholds.len()];
+    if thresholds.is_empty() {
+        return weights;
+    }
+    let last = thresholds.len() - 1;
+    if value <= thresholds[0] {
+        weights[0] = 1.0;
+    } else if value >= thresholds[last] {
+        weights[last] = 1.0;
+    } else {
+        let next = thresholds.iter().position(|&threshold| threshold > value).unwrap_or(last);
+        let (a, b) = (thresholds[next - 1], thresholds[next]);
+        let t = (value - a) / (b - a);
+        weights[next - 1] = 1.0 - t;
+        weights[next] = t;
+    }
+    weights
+}
+
+/// Weight of each child of a 2D blend at `point`, inverse squared distance, 1 for a child
+/// exactly at the point
+pub fn blend_2d(positions: &[[Float; 2]], point: [Float; 2]) -> Vec<Float> {
+    let distances: Vec<Float> = positions.iter()
+        .map(|p| (p[0] - point[0]).powi(2) + (p[1] - point[1]).powi(2))
+        .collect();
+    if let Some(exact) = distances.iter().position(|&distance| distance < 1e-8) {
+        return (0..positions.len()).map(|i| if i == exact { 1.0 } else { 0.0 }).collect();
+    }
+    let inverse: Vec<Float> = distances.iter().map(|&distance| 1.0 / distance).collect();
+    let total: Float = inverse.iter().sum();
+    inverse.iter().map(|&weight| weight / total).collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationState {
+    pub name: String,
+    pub motion: Motion,
+    pub speed: Float,
+}
+
+impl AnimationState {
+    pub fn new(name: &str, motion: Motion) -> AnimationState {
+        AnimationState { name: name.to_string(), motion, speed: 1.0 }
+    }
+
+    pub fn speed(mut self, speed: Float) -> AnimationState {
+        self.speed = speed;
+        self
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Parameter {
+    Float(Float),
+    Bool(bool),
+    /// set until a transition depending on it fires
+    Trigger(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Greater(String, Float),
+    Less(String, Float),
+    Bool(String, bool),
+    Trigger(String),
+    /// the state played this many times through, 0.9 starts the crossfade before the end
+    ExitTime(Float),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// `None` fires from any other state
+    pub from: Option<StateId>,
+    pub to: StateId,
+    /// all of them have to hold
+    pub conditions: Vec<Condition>,
+    /// seconds of crossfade, 0 switches at once
+    pub duration: TimeSec,
+}
+
+impl Transition {
+    pub fn new(from: Option<StateId>, to: StateId, duration: TimeSec) -> Transition {
+        Transition { from, to, conditions: Vec::new(), duration }
+    }
+
+    pub fn when(mut self, condition: Condition) -> Transition {
+        self.conditions.push(condition);
+        self
+    }
+}
+
+/// Clip to sample for the final pose
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClipSample {
+    pub clip: ClipId,
+    pub time: TimeSec,
+    /// the samples of an update sum to 1
+    pub weight: Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Playback {
+    state: StateId,
+    /// normalized time, 1 per playthrough
+    phase: Float,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Crossfade {
+    from: Playback,
+    elapsed: TimeSec,
+    duration: TimeSec,
+}
+
+/// Plays the states of an animation graph: set parameters from gameplay, call `update`
+/// every frame and blend the pose from the returned samples, e.g. with `blend_samples`.
+/// Starts in the first state added.
+#[derive(Debug, Clone, Default)]
+pub struct AnimatorController {
+    states: Vec<AnimationState>,
+    transitions: Vec<Transition>,
+    parameters: HashMap<String, Parameter>,
+    current: Playback,
+    crossfade: Option<Crossfade>,
+}
+
+impl Default for Playback {
+    fn default() -> Playback {
+        Playback { state: 0, phase: 0.0 }
+    }
+}
+
+impl AnimatorController {
+    pub fn new() -> AnimatorController {
+        AnimatorController::default()
+    }
+
+    pub fn add_state(&mut self, state: AnimationState) -> StateId {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    pub fn add_transition(&mut self, transition: Transition) {
+        self.transitions.push(transition);
+    }
+
+    pub fn state(&self, id: StateId) -> Option<&AnimationState> {
+        self.states.get(id)
+    }
+
+    pub fn find_state(&self, name: &str) -> Option<StateId> {
+        self.states.iter().position(|state| state.name == name)
+    }
+
+    /// State playing, the target of a running crossfade
+    pub fn current_state(&self) -> StateId {
+        self.current.state
+    }
+
+    pub fn is_crossfading(&self) -> bool {
+        self.crossfade.is_some()
+    }
+
+    pub fn set_float(&mut self, name: &str, value: Float) {
+        self.parameters.insert(name.to_string(), Parameter::Float(value));
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.parameters.insert(name.to_string(), Parameter::Bool(value));
+    }
+
+    pub fn set_trigger(&mut self, name: &str) {
+        self.parameters.insert(name.to_string(), Parameter::Trigger(true));
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<Parameter> {
+        self.parameters.get(name).cloned()
+    }
+
+    fn float(&self, name: &str) -> Float {
+        match self.parameters.get(name) {
+            Some(&Parameter::Float(value)) => value,
+            _ => 0.0,
+        }
+    }
+
+    /// Jumps to `state` without a transition, or crossfades to it over `duration`
+    pub fn play(&mut self, state: StateId, duration: TimeSec) {
+        self.crossfade = if duration > 0.0 {
+            Some(Crossfade { from: self.current, elapsed: 0.0, duration })
+        } else {
+            None
+        };
+        self.current = Playback { state, phase: 0.0 };
+    }
+
+    /// Advances the states, fires the first transition whose conditions hold and returns the
+    /// clips to blend
+    pub fn update(&mut self, delta_time: TimeSec) -> Vec<ClipSample> {
+        if self.states.is_empty() {
+            return Vec::new();
+        }
+        self.current.phase = self.advance(self.current, delta_time);
+        if let Some(mut crossfade) = self.crossfade {
+            crossfade.from.phase = self.advance(crossfade.from, delta_time);
+            crossfade.elapsed += delta_time;
+            self.crossfade = if crossfade.elapsed < crossfade.duration { Some(crossfade) } else { None };
+        }
+
+        if let Some(index) = self.ready_transition() {
+            let transition = self.transitions[index].clone();
+            for condition in &transition.conditions {
+                if let Condition::Trigger(ref name) = *condition {
+                    self.parameters.insert(name.clone(), Parameter::Trigger(false));
+                }
+            }
+            self.play(transition.to, transition.duration);
+        }
+        self.samples()
+    }
+
+    fn ready_transition(&self) -> Option<usize> {
+        let current = self.current.state;
+        self.transitions.iter().position(|transition| {
+            transition.from.map_or(transition.to != current, |from| from == current)
+                && transition.to < self.states.len()
+                && transition.conditions.iter().all(|condition| self.holds(condition))
+        })
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        match *condition {
+            Condition::Greater(ref name, value) => self.float(name) > value,
+            Condition::Less(ref name, value) => self.float(name) < value,
+            Condition::Bool(ref name, value) => self.parameters.get(name) == Some(&Parameter::Bool(value)),
+            Condition::Trigger(ref name) => self.parameters.get(name) == Some(&Parameter::Trigger(true)),
+            Condition::ExitTime(phase) => self.current.phase >= phase,
+        }
+    }
+
+    /// Phase after `delta_time`, blended clips advance at their weighted average duration so
+    /// they stay in step
+    fn advance(&self, playback: Playback, delta_time: TimeSec) -> Float {
+        let state = &self.states[playback.state];
+        let duration: TimeSec = self.weights(&state.motion).iter()
+            .map(|&(clip, weight)| clip.duration * weight as TimeSec)
+            .sum();
+        if duration <= 0.0 {
+            return playback.phase;
+        }
+        playback.phase + (delta_time / duration) as Float * state.speed
+    }
+
+    fn weights(&self, motion: &Motion) -> Vec<(Clip, Float)> {
+        let weights = match *motion {
+            Motion::Clip(_) => vec![1.0],
+            Motion::Blend1D { ref parameter, ref clips } => {
+                let thresholds: Vec<Float> = clips.iter().map(|&(threshold, _)| threshold).collect();
+                blend_1d(&thresholds, self.float(parameter))
+            },
+            Motion::Blend2D { ref x, ref y, ref clips } => {
+                let positions: Vec<[Float; 2]> = clips.iter().map(|&(position, _)| position).collect();
+                blend_2d(&positions, [self.float(x), self.float(y)])
+            },
+        };
+        motion.clips().into_iter().zip(weights).collect()
+    }
+
+    fn playback_samples(&self, playback: Playback, scale: Float, samples: &mut Vec<ClipSample>) {
+        for (clip, weight) in self.weights(&self.states[playback.state].motion) {
+            if weight * scale > 0.0 {
+                samples.push(ClipSample { clip: clip.id, time: clip.time_at(playback.phase), weight: weight * scale });
+            }
+        }
+    }
+
+    /// Samples of the current frame without advancing
+    pub fn samples(&self) -> Vec<ClipSample> {
+        let mut samples = Vec::new();
+        if self.states.is_empty() {
+            return samples;
+        }
+        match self.crossfade {
+            Some(crossfade) => {
+                let t = (crossfade.elapsed / crossfade.duration) as Float;
+                self.playback_samples(crossfade.from, 1.0 - t, &mut samples);
+                self.playback_samples(self.current, t, &mut samples);
+            },
+            None => self.playback_samples(self.current, 1.0, &mut samples),
+        }
+        samples
+    }
+}
+
+/// `update` of every controller on the global job system, the samples in the same order
+pub fn update_all(controllers: &mut [AnimatorController], delta_time: TimeSec) -> Vec<Vec<ClipSample>> {
+    jobs::global().parallel_map(controllers, |controller| controller.update(delta_time))
+}
+
+/// Weighted sum of what `sample` returns for each clip sample, for poses stored as flat float
+/// vectors such as morph weights or joint parameters
+pub fn blend_samples<F: FnMut(&ClipSample) -> Vec<Float>>(samples: &[ClipSample], mut sample: F) -> Vec<Float> {
+    let mut pose: Vec<Float> = Vec::new();
+    for clip in samples {
+        let values = sample(clip);
+        if pose.len() < values.len() {
+            pose.resize(values.len(), 0.0);
+        }
+        for (i, value) in values.iter().enumerate() {
+            pose[i] += value * clip.weight;
+        }
+    }
+    pose
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locomotion() -> (AnimatorController, StateId, StateId) {
+        let mut animator = AnimatorController::new();
+        let moving = animator.add_state(AnimationState::new("move", Motion::Blend1D {
+            parameter: "speed".to_string(),
+            clips: vec![(0.0, Clip::new(0, 2.0, true)), (2.0, Clip::new(1, 1.0, true)), (6.0, Clip::new(2, 0.5, true))],
+        }));
+        let jump = animator.add_state(AnimationState::new("jump", Motion::Clip(Clip::new(3, 1.0, false))));
+        animator.add_transition(Transition::new(None, jump, 0.5).when(Condition::Trigger("jump".to_string())));
+        animator.add_transition(Transition::new(Some(jump), moving, 0.0).when(Condition::ExitTime(1.0)));
+        (animator, moving, jump)
+    }
+
+    #[test]
+    fn blend_trees_weigh_their_clips() {
+        assert_eq!(blend_1d(&[0.0, 2.0, 6.0], 3.0), vec![0.0, 0.75, 0.25]);
+        assert_eq!(blend_1d(&[0.0, 2.0], -1.0), vec![1.0, 0.0]);
+        let weights = blend_2d(&[[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]], [0.5, 0.0]);
+        assert!((weights[0] - weights[1]).abs() < 1e-5 && weights[0] > weights[2]);
+        assert_eq!(blend_2d(&[[0.0, 0.0], [1.0, 0.0]], [1.0, 0.0]), vec![0.0, 1.0]);
+
+        let (mut animator, _, _) = locomotion();
+        animator.set_float("speed", 1.0);
+        let samples = animator.update(0.75);
+        // half idle (2 s) and half walk (1 s) advance over a 1.5 s cycle
+        assert_eq!(samples.len(), 2);
+        assert!((samples[0].time - 1.0).abs() < 1e-5 && (samples[1].time - 0.5).abs() < 1e-5);
+
+        let mut crowd = vec![animator.clone(), animator];
+        crowd[1].set_float("speed", 6.0);
+        let samples = update_all(&mut crowd, 0.1);
+        assert_eq!(samples[0], crowd[0].samples());
+        assert_eq!(samples[1][0].clip, 2);
+    }
+
+    #[test]
+    fn transitions_crossfade_and_consume_triggers() {
+        let (mut animator, moving, jump) = locomotion();
+        animator.update(0.1);
+        animator.set_trigger("jump");
+        animator.update(0.1);
+        assert_eq!(animator.current_state(), jump);
+        assert_eq!(animator.parameter("jump"), Some(Parameter::Trigger(false)));
+
+        let samples = animator.update(0.25);
+        let jumping: Float = samples.iter().filter(|s| s.clip == 3).map(|s| s.weight).sum();
+        assert!((jumping - 0.5).abs() < 1e-5);
+        assert!((samples.iter().map(|s| s.weight).sum::<Float>() - 1.0).abs() < 1e-5);
+
+        animator.update(0.3);
+        assert!(!animator.is_crossfading());
+        animator.update(0.5);
+        assert_eq!(animator.current_state(), moving);
+
+        let pose = blend_samples(&animator.samples(), |sample| vec![sample.clip as Float; 2]);
+        assert_eq!(pose, vec![0.0, 0.0]);
+    }
+}
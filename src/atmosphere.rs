@@ -0,0 +1,359 @@
+use cgmath::prelude::*;
+use gl;
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, Point3, Vector3, Matrix4};
+use color::Color;
+use gl_state::GlState;
+use shader::Shader;
+
+/// `applyFog(color, worldPos)` with the uniforms `Fog::bind` sets, used by the built-in lit
+/// and unlit shaders. `fogMode` 0 leaves the color unchanged.
+pub const FOG_GLSL: &str = r#"
+uniform int fogMode;
+uniform vec3 fogColor;
+uniform float fogStart;
+uniform float fogEnd;
+uniform float fogDensity;
+uniform float fogHeightFalloff;
+uniform float fogBaseHeight;
+uniform vec3 cameraPosition;
+
+vec3 applyFog(vec3 color, vec3 worldPos)
+{
+    if (fogMode == 0)
+        return color;
+    float distance = length(worldPos - cameraPosition);
+    float fog;
+    if (fogMode == 1)
+        fog = clamp((distance - fogStart) / (fogEnd - fogStart), 0.0, 1.0);
+    else if (fogMode == 2)
+        fog = 1.0 - exp(-fogDensity * distance);
+    else
+        fog = 1.0 - exp(-pow(fogDensity * distance, 2.0));
+    // thinner further above the base height
+    if (fogHeightFalloff > 0.0)
+        fog *= exp(-fogHeightFalloff * max(worldPos.y - fogBaseHeight, 0.0));
+    return mix(color, fogColor, fog);
+}
+"#;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FogMode {
+    #[default]
+    None,
+    /// from nothing at `start` to full at `end`
+    Linear,
+    /// `1 - exp(-density * distance)`
+    Exponential,
+    /// `1 - exp(-(density * distance)^2)`, clearer close to the camera
+    ExponentialSquared,
+}
+
+/// Distance fog, optionally thinning out with height
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: Color,
+    pub start: Float,
+    pub end: Float,
+    pub density: Float,
+    /// 0 for uniform fog, otherwise how fast it thins per unit above `base_height`
+    pub height_falloff: Float,
+    pub base_height: Float,
+}
+
+impl Fog {
+    pub fn linear(color: Color, start: Float, end: Float) -> Fog {
+        Fog { mode: FogMode::Linear, color, start, end, ..Fog::default() }
+    }
+
+    pub fn exponential(color: Color, density: Float) -> Fog {
+        Fog { mode: FogMode::Exponential, color, density, ..Fog::default() }
+    }
+
+    pub fn exponential_squared(color: Color, density: Float) -> Fog {
+        Fog { mode: FogMode::ExponentialSquared, color, density, ..Fog::default() }
+    }
+
+    pub fn height(mut self, falloff: Float, base_height: Float) -> Fog {
+        self.height_falloff = falloff;
+        self.base_height = base_height;
+        self
+    }
+
+    /// Fog amount in [0, 1] at `distance` from the camera and world `height`, as `FOG_GLSL`
+    /// computes it
+    pub fn factor(&self, distance: Float, height: Float) -> Float {
+        let fog = match self.mode {
+            FogMode::None => return 0.0,
+            FogMode::Linear => ((distance - self.start) / (self.end - self.start)).clamp(0.0, 1.0),
+            FogMode::Exponential => 1.0 - (-self.density * distance).exp(),
+            FogMode::ExponentialSquared => 1.0 - (-(self.density * distance).powi(2)).exp(),
+        };
+        if self.height_falloff > 0.0 {
+            fog * (-self.height_falloff * (height - self.base_height).max(0.0)).exp()
+        } else {
+            fog
+        }
+    }
+
+    /// Sets the `FOG_GLSL` uniforms of `shader`, which has to be in use
+    pub fn bind(&self, shader: &Shader, camera_position: Point3) {
+        let mode = match self.mode {
+            FogMode::None => 0,
+            FogMode::Linear => 1,
+            FogMode::Exponential => 2,
+            FogMode::ExponentialSquared => 3,
+        };
+        unsafe {
+            shader.setInt(c_str!("fogMode"), mode);
+            shader.setVec3(c_str!("fogColor"), self.color.r, self.color.g, self.color.b);
+            shader.setFloat(c_str!("fogStart"), self.start);
+            shader.setFloat(c_str!("fogEnd"), self.end);
+            shader.setFloat(c_str!("fogDensity"), self.density);
+            shader.setFloat(c_str!("fogHeightFalloff"), self.height_falloff);
+            shader.setFloat(c_str!("fogBaseHeight"), self.base_height);
+            shader.setVec3(c_str!("cameraPosition"), camera_position.x, camera_position.y, camera_position.z);
+        }
+    }
+}
+
+impl Default for Fog {
+    fn default() -> Fog {
+        Fog {
+            mode: FogMode::None,
+            color: Color::srgb(0.6, 0.7, 0.8, 1.0),
+            start: 10.0,
+            end: 100.0,
+            density: 0.02,
+            height_falloff: 0.0,
+            base_height: 0.0,
+        }
+    }
+}
+
+/// Rayleigh scattering coefficients of air at sea level, per meter
+const RAYLEIGH: [Float; 3] = [5.8e-6, 13.5e-6, 33.1e-6];
+const RAYLEIGH_HEIGHT: Float = 8000.0;
+/// Mie extinction of aerosols at sea level, per meter
+const MIE: Float = 21e-6;
+const MIE_HEIGHT: Float = 1200.0;
+
+/// Direction towards the sun from its angle above the horizon and its compass angle from
+/// -Z towards +X, both in degrees
+pub fn sun_direction(elevation: Float, azimuth: Float) -> Vector3 {
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    Vector3::new(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos())
+}
+
+/// Linear color of sunlight `to_sun` after crossing the atmosphere: white at noon, orange
+/// and dim at sunset, black once the sun is well below the horizon
+pub fn sun_color(to_sun: Vector3, intensity: Float) -> Color {
+    let elevation = to_sun.normalize().y.clamp(-1.0, 1.0).asin().to_degrees();
+    // Kasten-Young air mass, finite down to the horizon
+    let zenith = (90.0 - elevation).min(93.0);
+    let air_mass = 1.0 / (zenith.to_radians().cos().max(0.0) + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+    // sunlight fades out over the few degrees below the horizon
+    let horizon = ((elevation + 4.0) / 4.0).clamp(0.0, 1.0);
+    let channel = |rayleigh: Float| {
+        intensity * horizon * (-(rayleigh * RAYLEIGH_HEIGHT + MIE * MIE_HEIGHT) * air_mass).exp()
+    };
+    Color::linear(channel(RAYLEIGH[0]), channel(RAYLEIGH[1]), channel(RAYLEIGH[2]), 1.0)
+}
+
+const SKY_VERTEX_SHADER: &str = r#"
+#version 330 core
+out vec3 Direction;
+
+uniform mat4 inverseViewProjection;
+
+void main()
+{
+    // one triangle covering the screen, at the far plane
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    vec4 far = inverseViewProjection * vec4(ndc, 1.0, 1.0);
+    Direction = far.xyz / far.w;
+    gl_Position = vec4(ndc, 1.0, 1.0);
+}
+"#;
+
+/// single scattering through a spherical atmosphere, few samples but smooth enough for a sky
+const SKY_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+in vec3 Direction;
+
+uniform vec3 sunDirection;
+uniform float sunIntensity;
+uniform float mieG;
+uniform float turbidity;
+
+const float PI = 3.14159265;
+const float EARTH = 6360e3;
+const float ATMOSPHERE = 6420e3;
+const vec3 RAYLEIGH = vec3(5.8e-6, 13.5e-6, 33.1e-6);
+const float RAYLEIGH_HEIGHT = 8000.0;
+const float MIE = 21e-6;
+const float MIE_HEIGHT = 1200.0;
+
+// distance along the ray from origin to the atmosphere's outer shell
+float exitDistance(vec3 origin, vec3 direction)
+{
+    float b = dot(origin, direction);
+    float c = dot(origin, origin) - ATMOSPHERE * ATMOSPHERE;
+    return -b + sqrt(max(b * b - c, 0.0));
+}
+
+void main()
+{
+    vec3 direction = normalize(Direction);
+    vec3 origin = vec3(0.0, EARTH + 2.0, 0.0);
+    float mu = dot(direction, sunDirection);
+    float rayleighPhase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    float g2 = mieG * mieG;
+    float miePhase = 3.0 / (8.0 * PI) * ((1.0 - g2) * (1.0 + mu * mu)) / ((2.0 + g2) * pow(1.0 + g2 - 2.0 * mieG * mu, 1.5));
+    float mie = MIE * turbidity;
+
+    const int STEPS = 12;
+    const int LIGHT_STEPS = 4;
+    float stepLength = exitDistance(origin, direction) / float(STEPS);
+    vec2 depth = vec2(0.0);
+    vec3 rayleighSum = vec3(0.0);
+    vec3 mieSum = vec3(0.0);
+    for (int i = 0; i < STEPS; ++i) {
+        vec3 point = origin + direction * (float(i) + 0.5) * stepLength;
+        float height = length(point) - EARTH;
+        vec2 density = exp(-height / vec2(RAYLEIGH_HEIGHT, MIE_HEIGHT)) * stepLength;
+        depth += density;
+
+        float lightStep = exitDistance(point, sunDirection) / float(LIGHT_STEPS);
+        vec2 lightDepth = vec2(0.0);
+        bool shadowed = false;
+        for (int j = 0; j < LIGHT_STEPS; ++j) {
+            vec3 lightPoint = point + sunDirection * (float(j) + 0.5) * lightStep;
+            float lightHeight = length(lightPoint) - EARTH;
+            if (lightHeight < 0.0) {
+                shadowed = true;
+                break;
+            }
+            lightDepth += exp(-lightHeight / vec2(RAYLEIGH_HEIGHT, MIE_HEIGHT)) * lightStep;
+        }
+        if (shadowed)
+            continue;
+        vec3 attenuation = exp(-(RAYLEIGH * (depth.x + lightDepth.x) + mie * 1.1 * (depth.y + lightDepth.y)));
+        rayleighSum += density.x * attenuation;
+        mieSum += density.y * attenuation;
+    }
+    vec3 color = sunIntensity * (rayleighSum * RAYLEIGH * rayleighPhase + mieSum * mie * miePhase);
+    // below the horizon fades to the ground color
+    color = mix(color, vec3(0.05, 0.05, 0.06) * max(sunDirection.y + 0.1, 0.0), smoothstep(0.0, -0.05, direction.y));
+    FragColor = vec4(color, 1.0);
+}
+"#;
+
+/// Physically based sky replacing a static skybox: single Rayleigh and Mie scattering
+/// towards the camera for a sun set with `set_sun`, whose light color `sun_color` should
+/// drive the scene's directional light
+pub struct Sky {
+    /// towards the sun
+    pub sun: Vector3,
+    pub sun_intensity: Float,
+    /// haze, 1 for a clear day
+    pub turbidity: Float,
+    /// Mie anisotropy, how tight the glow around the sun is
+    pub mie_g: Float,
+    shader: Shader,
+    vao: u32,
+}
+
+impl Sky {
+    pub fn new() -> Sky {
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+        Sky {
+            sun: sun_direction(45.0, 0.0),
+            sun_intensity: 20.0,
+            turbidity: 1.0,
+            mie_g: 0.76,
+            shader: Shader::from_source(SKY_VERTEX_SHADER, SKY_FRAGMENT_SHADER),
+            vao,
+        }
+    }
+
+    /// Moves the sun, see `sun_direction`
+    pub fn set_sun(&mut self, elevation: Float, azimuth: Float) {
+        self.sun = sun_direction(elevation, azimuth);
+    }
+
+    /// Light reaching the ground from the sun, for the directional light
+    pub fn sun_color(&self) -> Color {
+        sun_color(self.sun, 1.0)
+    }
+
+    /// Draws behind everything with depth `LEQUAL` at the far plane, after the opaque pass, so
+    /// only uncovered pixels are shaded. `rotation_view_projection` is the projection times
+    /// `Camera::rotation_view_matrix`.
+    pub fn draw(&self, state: &mut GlState, rotation_view_projection: &Matrix4) {
+        let inverse = match rotation_view_projection.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let sun = self.sun.normalize();
+
+        state.use_program(self.shader.ID);
+        state.bind_vertex_array(self.vao);
+        state.set_blend(false);
+        state.set_cull_face(false);
+        unsafe {
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+            self.shader.setMat4(c_str!("inverseViewProjection"), &inverse);
+            self.shader.setVec3(c_str!("sunDirection"), sun.x, sun.y, sun.z);
+            self.shader.setFloat(c_str!("sunIntensity"), self.sun_intensity);
+            self.shader.setFloat(c_str!("mieG"), self.mie_g);
+            self.shader.setFloat(c_str!("turbidity"), self.turbidity);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.vao = 0;
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Sky {
+        Sky::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fog_and_sun_color() {
+        let fog = Fog::linear(Color::WHITE, 10.0, 20.0);
+        assert_eq!(fog.factor(5.0, 0.0), 0.0);
+        assert_eq!(fog.factor(15.0, 0.0), 0.5);
+        let fog = Fog::exponential(Color::WHITE, 0.1).height(1.0, 0.0);
+        assert!(fog.factor(10.0, 2.0) < fog.factor(10.0, 0.0));
+        assert_eq!(Fog::default().factor(1000.0, 0.0), 0.0);
+
+        let noon = sun_color(sun_direction(90.0, 0.0), 1.0);
+        let sunset = sun_color(sun_direction(2.0, 0.0), 1.0);
+        assert!(noon.b > 0.7 && noon.r > noon.b);
+        assert!(sunset.r > 4.0 * sunset.b && sunset.r < noon.r);
+        assert_eq!(sun_color(sun_direction(-10.0, 0.0), 1.0).r, 0.0);
+    }
+}
@@ -0,0 +1,362 @@
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Vector3, Vector4, Matrix4};
+use picking::PickShape;
+use ray::Ray;
+
+/// Axis aligned bounding box
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Box that contains nothing, merging anything into it yields the other box
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY),
+            max: Point3::new(Float::NEG_INFINITY, Float::NEG_INFINITY, Float::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point3>>(points: I) -> Aabb {
+        points.into_iter().fold(Aabb::empty(), |aabb, point| aabb.include(*point))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Point3 {
+        self.min.midpoint(self.max)
+    }
+
+    /// Half size of the box along each axis
+    pub fn extents(&self) -> Vector3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn include(&self, point: Point3) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+            max: Point3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+        }
+    }
+
+    pub fn merge(&self, other: &Aabb) -> Aabb {
+        if other.is_empty() {
+            return *self;
+        }
+        self.include(other.min).include(other.max)
+    }
+
+    pub fn contains(&self, point: Point3) -> bool {
+        point.x >= self.min.x && point.x <= self.max.x &&
+            point.y >= self.min.y && point.y <= self.max.y &&
+            point.z >= self.min.z && point.z <= self.max.z
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x &&
+            self.min.y <= other.max.y && self.max.y >= other.min.y &&
+            self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    pub fn corners(&self) -> [Point3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Bounds of this box after the transformation, e.g. local to world space
+    pub fn transform(&self, matrix: &Matrix4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let mut corners = self.corners();
+        for corner in corners.iter_mut() {
+            *corner = matrix.transform_point(*corner);
+        }
+        Aabb::from_points(corners.iter())
+    }
+
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<Float> {
+        ray.intersect_aabb(self.min, self.max)
+    }
+
+    /// Twice the sum of the face areas, how likely a random ray or query hits the box
+    pub fn surface_area(&self) -> Float {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// Box grown by `margin` on every side
+    pub fn expand(&self, margin: Float) -> Aabb {
+        let margin = Vector3::new(margin, margin, margin);
+        Aabb { min: self.min - margin, max: self.max + margin }
+    }
+
+    /// Distance from `point` to the closest point of the box, 0 inside
+    pub fn distance_to(&self, point: Point3) -> Float {
+        let clamped = Point3::new(
+            point.x.max(self.min.x).min(self.max.x),
+            point.y.max(self.min.y).min(self.max.y),
+            point.z.max(self.min.z).min(self.max.z),
+        );
+        point.distance(clamped)
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::empty()
+    }
+}
+
+impl From<Aabb> for PickShape {
+    fn from(aabb: Aabb) -> PickShape {
+        PickShape::Aabb { min: aabb.min, max: aabb.max }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoundingSphere {
+    pub center: Point3,
+    pub radius: Float,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Point3, radius: Float) -> BoundingSphere {
+        BoundingSphere { center, radius }
+    }
+
+    /// Sphere around the center of the points' bounding box, not the minimal one
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point3> + Clone>(points: I) -> BoundingSphere {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points.into_iter()
+            .map(|p| p.distance(center))
+            .fold(0.0, Float::max);
+        BoundingSphere { center, radius }
+    }
+
+    pub fn from_aabb(aabb: &Aabb) -> BoundingSphere {
+        BoundingSphere {
+            center: aabb.center(),
+            radius: aabb.extents().magnitude(),
+        }
+    }
+
+    pub fn merge(&self, other: &BoundingSphere) -> BoundingSphere {
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        BoundingSphere { center, radius }
+    }
+
+    pub fn contains(&self, point: Point3) -> bool {
+        self.center.distance2(point) <= self.radius * self.radius
+    }
+
+    /// Conservative bounds after the transformation, the radius is scaled by the largest axis scale
+    pub fn transform(&self, matrix: &Matrix4) -> BoundingSphere {
+        let scale = matrix.x.truncate().magnitude()
+            .max(matrix.y.truncate().magnitude())
+            .max(matrix.z.truncate().magnitude());
+        BoundingSphere {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<Float> {
+        ray.intersect_sphere(self.center, self.radius)
+    }
+}
+
+impl From<BoundingSphere> for PickShape {
+    fn from(sphere: BoundingSphere) -> PickShape {
+        PickShape::Sphere { center: sphere.center, radius: sphere.radius }
+    }
+}
+
+/// Plane of the points `p` where `normal · p + distance = 0`, `normal` has unit length
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Plane {
+    pub normal: Vector3,
+    pub distance: Float,
+}
+
+impl Plane {
+    pub fn new(normal: Vector3, point: Point3) -> Plane {
+        let normal = normal.normalize();
+        Plane { normal, distance: -normal.dot(point.to_vec()) }
+    }
+
+    /// Positive on the side the normal points to
+    pub fn signed_distance(&self, point: Point3) -> Float {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+
+    pub fn reflect_point(&self, point: Point3) -> Point3 {
+        point - self.normal * (2.0 * self.signed_distance(point))
+    }
+
+    pub fn reflect_vector(&self, vector: Vector3) -> Vector3 {
+        vector - self.normal * (2.0 * self.normal.dot(vector))
+    }
+
+    /// Mirror transformation across the plane
+    pub fn reflection_matrix(&self) -> Matrix4 {
+        let n = self.normal;
+        let d = self.distance;
+        Matrix4::new(
+            1.0 - 2.0 * n.x * n.x, -2.0 * n.x * n.y, -2.0 * n.x * n.z, 0.0,
+            -2.0 * n.y * n.x, 1.0 - 2.0 * n.y * n.y, -2.0 * n.y * n.z, 0.0,
+            -2.0 * n.z * n.x, -2.0 * n.z * n.y, 1.0 - 2.0 * n.z * n.z, 0.0,
+            -2.0 * d * n.x, -2.0 * d * n.y, -2.0 * d * n.z, 1.0,
+        )
+    }
+
+    /// `(normal, distance)`, the form clip plane uniforms take
+    pub fn to_vector4(&self) -> Vector4 {
+        self.normal.extend(self.distance)
+    }
+
+    /// Plane of `a·x + b·y + c·z + d = 0` for `(a, b, c, d)`, normalized
+    pub fn from_vector4(plane: Vector4) -> Plane {
+        let length = plane.truncate().magnitude();
+        Plane { normal: plane.truncate() / length, distance: plane.w / length }
+    }
+}
+
+/// The six planes bounding what a camera sees, normals pointing inside
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Frustum {
+    /// left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Frustum of a view-projection matrix with OpenGL clip space
+    pub fn from_matrix(view_projection: &Matrix4) -> Frustum {
+        let m = view_projection;
+        let (x, y, z, w) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        Frustum {
+            planes: [
+                Plane::from_vector4(w + x),
+                Plane::from_vector4(w - x),
+                Plane::from_vector4(w + y),
+                Plane::from_vector4(w - y),
+                Plane::from_vector4(w + z),
+                Plane::from_vector4(w - z),
+            ],
+        }
+    }
+
+    pub fn contains(&self, point: Point3) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Conservative test, boxes near the frustum's corners may pass while outside of it
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the normal
+            let corner = Point3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aabb_from_points_and_merge() {
+        let points = [Point3::new(1.0, -2.0, 0.0), Point3::new(-1.0, 2.0, 3.0)];
+        let aabb = Aabb::from_points(points.iter());
+        assert_eq!(aabb.min, Point3::new(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, Point3::new(1.0, 2.0, 3.0));
+        assert!(Aabb::empty().is_empty());
+        assert_eq!(Aabb::empty().merge(&aabb), aabb);
+        assert_eq!(aabb.merge(&Aabb::empty()), aabb);
+    }
+
+    #[test]
+    fn aabb_transform() {
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let moved = aabb.transform(&Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)));
+        assert_eq!(moved.center(), Point3::new(5.0, 0.0, 0.0));
+        let scaled = aabb.transform(&Matrix4::from_scale(2.0));
+        assert_eq!(scaled.extents(), Vector3::new(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn sphere_merge() {
+        let a = BoundingSphere::new(Point3::new(0.0, 0.0, 0.0), 1.0);
+        let b = BoundingSphere::new(Point3::new(4.0, 0.0, 0.0), 1.0);
+        let merged = a.merge(&b);
+        assert_eq!(merged.center, Point3::new(2.0, 0.0, 0.0));
+        assert_eq!(merged.radius, 3.0);
+        assert_eq!(merged.merge(&a), merged);
+    }
+
+    #[test]
+    fn plane_reflection() {
+        let plane = Plane::new(Vector3::new(0.0, 2.0, 0.0), Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(plane.signed_distance(Point3::new(4.0, 3.0, 0.0)), 2.0);
+        let point = Point3::new(1.0, 3.0, -2.0);
+        assert_eq!(plane.reflect_point(point), Point3::new(1.0, -1.0, -2.0));
+        assert_eq!(plane.reflection_matrix().transform_point(point), plane.reflect_point(point));
+        assert_eq!(plane.reflect_vector(Vector3::new(1.0, -1.0, 0.0)), Vector3::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn frustum_culls_boxes_and_spheres() {
+        let projection = ::cgmath::perspective(::cgmath::Deg(90.0), 1.0, 1.0, 10.0);
+        let frustum = Frustum::from_matrix(&projection);
+        let unit = |z: Float| Aabb::new(Point3::new(-0.5, -0.5, z - 0.5), Point3::new(0.5, 0.5, z + 0.5));
+        assert!(frustum.contains(Point3::new(0.0, 0.0, -5.0)));
+        assert!(frustum.intersects_aabb(&unit(-5.0)));
+        // straddling the near plane
+        assert!(frustum.intersects_aabb(&unit(-1.0)));
+        assert!(!frustum.intersects_aabb(&unit(5.0)));
+        assert!(!frustum.intersects_aabb(&unit(-12.0)));
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(Point3::new(5.5, 0.0, -5.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(Point3::new(7.0, 0.0, -5.0), 1.0)));
+        assert_eq!(unit(0.0).surface_area(), 6.0);
+        assert_eq!(unit(0.0).distance_to(Point3::new(3.5, 0.0, 0.0)), 3.0);
+    }
+}
@@ -0,0 +1,167 @@
+use std::ffi::CStr;
+use std::mem;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+/// Number of frames the GPU may lag behind before the CPU waits
+const REGIONS: usize = 3;
+
+/// Per-frame dynamic data (sprite batches, debug lines, particle instances) written into a
+/// ring of three regions of one buffer, each region guarded by a fence so the CPU never
+/// overwrites data the GPU is still reading.
+///
+/// Uses a persistent, coherent mapping where `ARB_buffer_storage` (GL 4.4) is available, and
+/// falls back to unsynchronized `glMapBufferRange` writes into the fenced regions otherwise.
+#[derive(Debug)]
+pub struct StreamingBuffer {
+    pub buffer: u32,
+    pub target: GLenum,
+    /// bytes available per frame
+    pub region_size: usize,
+    /// offsets returned by `write` are multiples of this
+    pub alignment: usize,
+    fences: [GLsync; REGIONS],
+    mapped: *mut u8,
+    region: usize,
+    write_offset: usize,
+}
+
+impl StreamingBuffer {
+    pub fn new(target: GLenum, region_size: usize) -> StreamingBuffer {
+        let mut buffer = StreamingBuffer {
+            buffer: 0,
+            target,
+            region_size,
+            alignment: 16,
+            fences: [ptr::null(); REGIONS],
+            mapped: ptr::null_mut(),
+            region: 0,
+            write_offset: 0,
+        };
+        let total_size = (region_size * REGIONS) as GLsizeiptr;
+
+        unsafe {
+            gl::GenBuffers(1, &mut buffer.buffer);
+            gl::BindBuffer(target, buffer.buffer);
+            if supports_buffer_storage() {
+                let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+                gl::BufferStorage(target, total_size, ptr::null(), flags);
+                buffer.mapped = gl::MapBufferRange(target, 0, total_size, flags) as *mut u8;
+            } else {
+                gl::BufferData(target, total_size, ptr::null(), gl::STREAM_DRAW);
+            }
+            gl::BindBuffer(target, 0);
+        }
+
+        buffer
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        !self.mapped.is_null()
+    }
+
+    /// Moves to the next region, waiting for the GPU to finish reading it if needed
+    pub fn begin_frame(&mut self) {
+        self.region = (self.region + 1) % REGIONS;
+        self.write_offset = 0;
+
+        let fence = self.fences[self.region];
+        if !fence.is_null() {
+            unsafe {
+                loop {
+                    let result = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000);
+                    if result == gl::ALREADY_SIGNALED || result == gl::CONDITION_SATISFIED || result == gl::WAIT_FAILED {
+                        break;
+                    }
+                }
+                gl::DeleteSync(fence);
+            }
+            self.fences[self.region] = ptr::null();
+        }
+    }
+
+    /// Copies `data` into the current region and returns its byte offset in `buffer`,
+    /// `None` when the region has no room left this frame
+    pub fn write<T: Copy>(&mut self, data: &[T]) -> Option<usize> {
+        let size = mem::size_of_val(data);
+        let local_offset = align(self.write_offset, self.alignment);
+        if local_offset + size > self.region_size {
+            return None;
+        }
+        let offset = self.region * self.region_size + local_offset;
+
+        unsafe {
+            if self.is_persistent() {
+                ptr::copy_nonoverlapping(data.as_ptr() as *const u8, self.mapped.add(offset), size);
+            } else {
+                gl::BindBuffer(self.target, self.buffer);
+                let flags = gl::MAP_WRITE_BIT | gl::MAP_UNSYNCHRONIZED_BIT | gl::MAP_INVALIDATE_RANGE_BIT;
+                let mapped = gl::MapBufferRange(self.target, offset as GLintptr, size as GLsizeiptr, flags) as *mut u8;
+                if !mapped.is_null() {
+                    ptr::copy_nonoverlapping(data.as_ptr() as *const u8, mapped, size);
+                    gl::UnmapBuffer(self.target);
+                }
+                gl::BindBuffer(self.target, 0);
+            }
+        }
+
+        self.write_offset = local_offset + size;
+        Some(offset)
+    }
+
+    /// Fences the current region, call it after the frame's draws reading from it were issued
+    pub fn end_frame(&mut self) {
+        unsafe {
+            self.fences[self.region] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            for fence in self.fences.iter_mut() {
+                if !fence.is_null() {
+                    gl::DeleteSync(*fence);
+                    *fence = ptr::null();
+                }
+            }
+            if self.is_persistent() {
+                gl::BindBuffer(self.target, self.buffer);
+                gl::UnmapBuffer(self.target);
+                gl::BindBuffer(self.target, 0);
+                self.mapped = ptr::null_mut();
+            }
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+        self.buffer = 0;
+    }
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// GL 4.4 or `GL_ARB_buffer_storage`
+pub fn supports_buffer_storage() -> bool {
+    gl::BufferStorage::is_loaded() && supports((4, 4), "GL_ARB_buffer_storage")
+}
+
+/// Whether the context is at least GL `version` or has the extension
+pub fn supports(version: (i32, i32), extension: &str) -> bool {
+    unsafe {
+        let (mut major, mut minor) = (0, 0);
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        if (major, minor) >= version {
+            return true;
+        }
+
+        let mut count = 0;
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+        (0..count as GLuint).any(|i| {
+            let name = gl::GetStringi(gl::EXTENSIONS, i);
+            !name.is_null() && CStr::from_ptr(name as *const _).to_bytes() == extension.as_bytes()
+        })
+    }
+}
@@ -0,0 +1,395 @@
+use gl;
+
+use atmosphere::FOG_GLSL;
+use color::ColorSpace;
+use error::EngineResult;
+use logging;
+use renderer::point_shadow::{CubeShadowMode, POINT_SHADOW_GLSL};
+use renderer::shadow::CASCADE_SHADOW_GLSL;
+use renderer::ssao::SSAO_GLSL;
+use shader::Shader;
+use texture::Texture;
+use vfs::{MemoryFiles, Vfs};
+
+/// Where `files` is meant to be mounted, so `builtin/shaders/unlit.vert` can't clash with game assets
+pub const MOUNT: &str = "builtin";
+
+const UNLIT_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 2) in vec2 aTexCoords;
+out vec2 TexCoords;
+out vec3 WorldPos;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+// keeps the side in front of a reflection or refraction plane, when CLIP_DISTANCE0 is enabled
+uniform vec4 clipPlane;
+
+void main()
+{
+    vec4 world = model * vec4(aPos, 1.0);
+    TexCoords = aTexCoords;
+    WorldPos = world.xyz;
+    gl_ClipDistance[0] = dot(world, clipPlane);
+    gl_Position = projection * view * world;
+}
+"#;
+
+/// after the version line and `FOG_GLSL`
+const UNLIT_FRAGMENT_BODY: &str = r#"
+out vec4 FragColor;
+in vec2 TexCoords;
+in vec3 WorldPos;
+
+uniform sampler2D texture1;
+uniform vec4 color;
+
+void main()
+{
+    vec4 albedo = texture(texture1, TexCoords) * color;
+    FragColor = vec4(applyFog(albedo.rgb, WorldPos), albedo.a);
+}
+"#;
+
+/// Also the vertex stage of compiled materials, see `material`
+pub(crate) const LIT_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec3 aNormal;
+layout (location = 2) in vec2 aTexCoords;
+layout (location = 3) in vec3 aTangent;
+layout (location = 4) in vec3 aBitangent;
+out vec3 WorldPos;
+out vec3 Normal;
+out vec3 Tangent;
+out vec3 Bitangent;
+out vec2 TexCoords;
+out float ViewDepth;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+uniform vec4 clipPlane;
+
+void main()
+{
+    vec4 world = model * vec4(aPos, 1.0);
+    vec4 viewPos = view * world;
+    gl_ClipDistance[0] = dot(world, clipPlane);
+    WorldPos = world.xyz;
+    Normal = mat3(transpose(inverse(model))) * aNormal;
+    // tangents lie in the surface and follow the model matrix itself
+    Tangent = mat3(model) * aTangent;
+    Bitangent = mat3(model) * aBitangent;
+    TexCoords = aTexCoords;
+    ViewDepth = -viewPos.z;
+    gl_Position = projection * viewPos;
+}
+"#;
+
+/// `#include "reactor/normal_map.glsl"`: `perturbNormal(normal, tangent, bitangent, texel)`
+/// turns a texel of a tangent-space normal map (linear, +Y up) into a world-space normal with
+/// the interpolated tangent frame of `LIT_VERTEX_SHADER`. Without tangents the normal is kept.
+pub const NORMAL_MAP_GLSL: &str = r#"
+vec3 perturbNormal(vec3 normal, vec3 tangent, vec3 bitangent, vec3 texel)
+{
+    vec3 n = normalize(normal);
+    // interpolation skews the frame, orthogonalize it again
+    vec3 t = tangent - n * dot(n, tangent);
+    if (dot(t, t) < 1e-10)
+        return n;
+    t = normalize(t);
+    // mirrored UVs flip the bitangent
+    vec3 b = cross(n, t) * (dot(cross(n, t), bitangent) < 0.0 ? -1.0 : 1.0);
+    vec3 m = texel * 2.0 - 1.0;
+    return normalize(t * m.x + b * m.y + n * m.z);
+}
+"#;
+
+/// after the version line, `FOG_GLSL`, `CASCADE_SHADOW_GLSL`, `NORMAL_MAP_GLSL` and `SSAO_GLSL`
+const LIT_FRAGMENT_BODY: &str = r#"
+out vec4 FragColor;
+in vec3 WorldPos;
+in vec3 Normal;
+in vec3 Tangent;
+in vec3 Bitangent;
+in vec2 TexCoords;
+in float ViewDepth;
+
+uniform sampler2D texture1;
+uniform vec4 color;
+// read only with normalMapping set
+uniform sampler2D normalMap;
+uniform bool normalMapping;
+// direction the light travels in
+uniform vec3 lightDirection;
+uniform vec3 lightColor;
+uniform vec3 ambient;
+
+void main()
+{
+    vec3 normal = normalMapping ? perturbNormal(Normal, Tangent, Bitangent, texture(normalMap, TexCoords).rgb)
+                                : normalize(Normal);
+    float diffuse = max(dot(normal, -normalize(lightDirection)), 0.0);
+    float bias = max(0.002 * (1.0 - diffuse), 0.0005);
+    float lit = cascadeShadow(WorldPos, ViewDepth, bias);
+    vec4 albedo = texture(texture1, TexCoords) * color;
+    FragColor = vec4(applyFog(albedo.rgb * (ambient * ambientOcclusion() + lightColor * diffuse * lit), WorldPos), albedo.a);
+}
+"#;
+
+/// Depth only, for shadow maps
+const SHADOW_DEPTH_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+
+uniform mat4 model;
+uniform mat4 lightMatrix;
+
+void main()
+{
+    gl_Position = lightMatrix * model * vec4(aPos, 1.0);
+}
+"#;
+
+const SHADOW_DEPTH_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+}
+"#;
+
+/// Distance to the light over `farPlane`, for `PointShadow` in six pass mode
+const POINT_SHADOW_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+out vec3 WorldPos;
+
+uniform mat4 model;
+uniform mat4 lightMatrix;
+
+void main()
+{
+    vec4 world = model * vec4(aPos, 1.0);
+    WorldPos = world.xyz;
+    gl_Position = lightMatrix * world;
+}
+"#;
+
+/// World positions for the geometry shader of the layered mode
+const POINT_SHADOW_LAYERED_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+
+uniform mat4 model;
+
+void main()
+{
+    gl_Position = model * vec4(aPos, 1.0);
+}
+"#;
+
+const POINT_SHADOW_GEOMETRY_SHADER: &str = r#"
+#version 330 core
+layout (triangles) in;
+layout (triangle_strip, max_vertices = 18) out;
+out vec3 WorldPos;
+
+uniform mat4 shadowMatrices[6];
+
+void main()
+{
+    for (int face = 0; face < 6; ++face) {
+        gl_Layer = face;
+        for (int i = 0; i < 3; ++i) {
+            WorldPos = gl_in[i].gl_Position.xyz;
+            gl_Position = shadowMatrices[face] * gl_in[i].gl_Position;
+            EmitVertex();
+        }
+        EndPrimitive();
+    }
+}
+"#;
+
+const POINT_SHADOW_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+in vec3 WorldPos;
+
+uniform vec3 lightPosition;
+uniform float farPlane;
+
+void main()
+{
+    gl_FragDepth = length(WorldPos - lightPosition) / farPlane;
+}
+"#;
+
+/// `texture1 * color` with fog, see `Fog::bind`
+fn unlit_fragment_shader() -> String {
+    format!("#version 330 core\n{}{}", FOG_GLSL, UNLIT_FRAGMENT_BODY)
+}
+
+/// Directional light with cascaded shadows, see `CascadeShadows::bind`, fog and optional
+/// normal mapping. Without cascades (`cascadeCount` 0) everything is lit.
+fn lit_fragment_shader() -> String {
+    format!("#version 330 core\n{}{}{}{}{}", FOG_GLSL, CASCADE_SHADOW_GLSL, NORMAL_MAP_GLSL, SSAO_GLSL, LIT_FRAGMENT_BODY)
+}
+
+/// Flat magenta, used in place of shaders that failed to build
+const ERROR_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+void main()
+{
+    FragColor = vec4(1.0, 0.0, 1.0, 1.0);
+}
+"#;
+
+/// The built-in shader sources, mount them with `mount`
+pub fn files() -> MemoryFiles {
+    let mut files = MemoryFiles::new();
+    files.add_static("shaders/unlit.vert", UNLIT_VERTEX_SHADER.as_bytes());
+    files.add("shaders/unlit.frag", unlit_fragment_shader().into_bytes());
+    files.add_static("shaders/error.frag", ERROR_FRAGMENT_SHADER.as_bytes());
+    files.add_static("shaders/lit.vert", LIT_VERTEX_SHADER.as_bytes());
+    files.add("shaders/lit.frag", lit_fragment_shader().into_bytes());
+    files.add_static("shaders/fog.glsl", FOG_GLSL.as_bytes());
+    files.add_static("shaders/cascade_shadow.glsl", CASCADE_SHADOW_GLSL.as_bytes());
+    files.add_static("shaders/normal_map.glsl", NORMAL_MAP_GLSL.as_bytes());
+    files.add_static("shaders/ssao.glsl", SSAO_GLSL.as_bytes());
+    files.add_static("shaders/shadow_depth.vert", SHADOW_DEPTH_VERTEX_SHADER.as_bytes());
+    files.add_static("shaders/shadow_depth.frag", SHADOW_DEPTH_FRAGMENT_SHADER.as_bytes());
+    files.add_static("shaders/point_shadow.glsl", POINT_SHADOW_GLSL.as_bytes());
+    files.add_static("shaders/point_shadow.vert", POINT_SHADOW_VERTEX_SHADER.as_bytes());
+    files.add_static("shaders/point_shadow_layered.vert", POINT_SHADOW_LAYERED_VERTEX_SHADER.as_bytes());
+    files.add_static("shaders/point_shadow.geom", POINT_SHADOW_GEOMETRY_SHADER.as_bytes());
+    files.add_static("shaders/point_shadow.frag", POINT_SHADOW_FRAGMENT_SHADER.as_bytes());
+    files
+}
+
+/// Mounts `files` at `MOUNT`, below anything mounted later so games can override them
+pub fn mount(vfs: &mut Vfs) {
+    vfs.mount(MOUNT, files());
+}
+
+/// RGBA8 checkerboard of magenta and black `cell`-sized squares, the missing texture pattern
+pub fn checkerboard_pixels(size: usize, cell: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let magenta = (x / cell + y / cell).is_multiple_of(2);
+            pixels.extend_from_slice(if magenta { &[255, 0, 255, 255] } else { &[0, 0, 0, 255] });
+        }
+    }
+    pixels
+}
+
+/// GL resources every scene can fall back to, create them once the context exists
+pub struct Builtins {
+    /// 1x1 white, for untextured materials
+    pub white: Texture,
+    /// 1x1 (0.5, 0.5, 1.0) linear, a normal map that leaves normals unchanged
+    pub flat_normal: Texture,
+    /// replaces textures that failed to load
+    pub checkerboard: Texture,
+    /// `texture1 * color`, with the model / view / projection uniforms and fog
+    pub unlit: Shader,
+    /// `unlit` with a directional light and cascaded shadows. Setting `normalMapping` perturbs
+    /// the normal by the `normalMap` sampler with the mesh tangents, `flat_normal` leaves it as is
+    pub lit: Shader,
+    /// `lightMatrix * model`, for `CascadeShadows::render`
+    pub shadow_depth: Shader,
+    /// distance depth for `PointShadow::render` in six pass mode
+    pub point_shadow: Shader,
+    /// the same in a single pass through a geometry shader
+    pub point_shadow_layered: Shader,
+    /// replaces shaders that failed to build
+    pub error_shader: Shader,
+}
+
+impl Builtins {
+    pub fn new() -> Builtins {
+        Builtins {
+            white: Texture::new(1, 1, &[255, 255, 255, 255]),
+            flat_normal: Texture::with_color_space(1, 1, &[128, 128, 255, 255], ColorSpace::Linear),
+            checkerboard: Texture::new(64, 64, &checkerboard_pixels(64, 8)),
+            unlit: Shader::from_source(UNLIT_VERTEX_SHADER, &unlit_fragment_shader()),
+            lit: Shader::from_source(LIT_VERTEX_SHADER, &lit_fragment_shader()),
+            shadow_depth: Shader::from_source(SHADOW_DEPTH_VERTEX_SHADER, SHADOW_DEPTH_FRAGMENT_SHADER),
+            point_shadow: Shader::from_source(POINT_SHADOW_VERTEX_SHADER, POINT_SHADOW_FRAGMENT_SHADER),
+            point_shadow_layered: Shader::from_source_with_geometry(POINT_SHADOW_LAYERED_VERTEX_SHADER,
+                                                                    POINT_SHADOW_FRAGMENT_SHADER,
+                                                                    POINT_SHADOW_GEOMETRY_SHADER),
+            error_shader: Shader::from_source(UNLIT_VERTEX_SHADER, ERROR_FRAGMENT_SHADER),
+        }
+    }
+
+    /// The loaded texture, or the checkerboard after logging why it failed
+    pub fn texture_or_fallback(&self, result: EngineResult<Texture>) -> Texture {
+        result.unwrap_or_else(|error| {
+            engine_error!(logging::RESOURCES, "{}, using the checkerboard texture", error);
+            self.checkerboard
+        })
+    }
+
+    /// The built shader, or the magenta one after logging why it failed
+    pub fn shader_or_fallback(&self, result: EngineResult<Shader>) -> Shader {
+        result.unwrap_or_else(|error| {
+            engine_error!(logging::RESOURCES, "{}, using the error shader", error);
+            self.error_shader
+        })
+    }
+
+    /// Depth shader matching a `PointShadow`'s mode
+    pub fn point_shadow_shader(&self, mode: CubeShadowMode) -> Shader {
+        match mode {
+            CubeShadowMode::SixPass => self.point_shadow,
+            CubeShadowMode::GeometryShader => self.point_shadow_layered,
+        }
+    }
+
+    /// `Shader::try_from_vfs`, falling back to the error shader
+    pub fn load_shader(&self, vfs: &Vfs, vertex_path: &str, fragment_path: &str) -> Shader {
+        self.shader_or_fallback(Shader::try_from_vfs(vfs, vertex_path, fragment_path))
+    }
+
+    pub fn delete(&mut self) {
+        self.white.delete();
+        self.flat_normal.delete();
+        self.checkerboard.delete();
+        unsafe {
+            gl::DeleteProgram(self.unlit.ID);
+            gl::DeleteProgram(self.lit.ID);
+            gl::DeleteProgram(self.shadow_depth.ID);
+            gl::DeleteProgram(self.point_shadow.ID);
+            gl::DeleteProgram(self.point_shadow_layered.ID);
+            gl::DeleteProgram(self.error_shader.ID);
+        }
+    }
+}
+
+impl Default for Builtins {
+    fn default() -> Builtins {
+        Builtins::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mounts_builtin_shaders() {
+        let mut vfs = Vfs::new();
+        mount(&mut vfs);
+        assert!(vfs.read_to_string("builtin/shaders/unlit.frag").unwrap().contains("texture1"));
+        assert!(vfs.read_to_string("builtin/shaders/lit.vert").unwrap().contains("layout (location = 4) in vec3 aBitangent"));
+        assert!(vfs.read_to_string("builtin/shaders/lit.frag").unwrap().contains("perturbNormal(Normal, Tangent, Bitangent"));
+        assert!(vfs.read_to_string("builtin/shaders/lit.frag").unwrap().contains("ambient * ambientOcclusion()"));
+        assert_eq!(&checkerboard_pixels(2, 1)[4..8], &[0, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, TimeSec, Point3, lerp, smoothstep, wrap_degrees};
+use super::Camera;
+
+/// The part of a camera's state a bookmark restores
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraView {
+    pub position: Point3,
+    pub yaw: Float,
+    pub pitch: Float,
+    pub zoom: Float,
+}
+
+impl CameraView {
+    pub fn of(camera: &Camera) -> CameraView {
+        CameraView {
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            zoom: camera.zoom,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.zoom = self.zoom;
+        let roll = camera.roll;
+        camera.set_angles(self.yaw, self.pitch, roll);
+    }
+
+    /// Interpolates towards `other`, turning the yaw the short way around
+    pub fn lerp(&self, other: &CameraView, t: Float) -> CameraView {
+        CameraView {
+            position: self.position + (other.position - self.position) * t,
+            yaw: self.yaw + wrap_degrees(other.yaw - self.yaw) * t,
+            pitch: lerp(self.pitch, other.pitch, t),
+            zoom: lerp(self.zoom, other.zoom, t),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Transition {
+    from: CameraView,
+    to: CameraView,
+    duration: TimeSec,
+    elapsed: TimeSec,
+}
+
+/// Named camera views, to jump or animate back to a saved viewpoint
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CameraBookmarks {
+    views: BTreeMap<String, CameraView>,
+    #[serde(skip)]
+    transition: Option<Transition>,
+}
+
+impl CameraBookmarks {
+    pub fn new() -> CameraBookmarks {
+        CameraBookmarks::default()
+    }
+
+    /// Saves the current view of the camera, replacing a bookmark with the same name
+    pub fn save(&mut self, name: &str, camera: &Camera) {
+        self.views.insert(name.to_string(), CameraView::of(camera));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CameraView> {
+        self.views.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<CameraView> {
+        self.views.remove(name)
+    }
+
+    /// Bookmark names in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.views.keys().map(|name| name.as_str())
+    }
+
+    /// Moves the camera to the bookmark immediately, returns false if there is none with that name
+    pub fn jump(&mut self, name: &str, camera: &mut Camera) -> bool {
+        match self.views.get(name) {
+            Some(view) => {
+                self.transition = None;
+                view.apply(camera);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Starts moving the camera to the bookmark over `duration` seconds, driven by `update`
+    pub fn animate_to(&mut self, name: &str, camera: &Camera, duration: TimeSec) -> bool {
+        match self.views.get(name) {
+            Some(view) => {
+                self.transition = Some(Transition {
+                    from: CameraView::of(camera),
+                    to: *view,
+                    duration,
+                    elapsed: 0.0,
+                });
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Advances a running `animate_to`, call it once per frame
+    pub fn update(&mut self, camera: &mut Camera, delta_time: TimeSec) {
+        let finished = match self.transition {
+            Some(ref mut transition) => {
+                transition.elapsed += delta_time;
+                let t = if transition.duration > 0.0 {
+                    (transition.elapsed / transition.duration).min(1.0) as Float
+                } else {
+                    1.0
+                };
+                if t >= 1.0 {
+                    transition.to.apply(camera);
+                    true
+                } else {
+                    // ease in and out
+                    transition.from.lerp(&transition.to, smoothstep(0.0, 1.0, t)).apply(camera);
+                    false
+                }
+            },
+            None => false,
+        };
+        if finished {
+            self.transition = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn round_trips_through_json() {
+        let camera = Camera { position: Point3::new(1.0, 2.0, 3.0), ..Camera::default() };
+        let mut bookmarks = CameraBookmarks::new();
+        bookmarks.save("start", &camera);
+
+        let camera_json = serde_json::to_string(&camera).unwrap();
+        assert_eq!(serde_json::from_str::<Camera>(&camera_json).unwrap(), camera);
+        let bookmarks_json = serde_json::to_string(&bookmarks).unwrap();
+        assert_eq!(serde_json::from_str::<CameraBookmarks>(&bookmarks_json).unwrap(), bookmarks);
+    }
+
+    #[test]
+    fn animates_to_bookmark() {
+        let mut camera = Camera { yaw: 170.0, ..Camera::default() };
+        let mut bookmarks = CameraBookmarks::new();
+        bookmarks.save("here", &camera);
+
+        camera.yaw = -170.0;
+        camera.position = Point3::new(10.0, 0.0, 0.0);
+        assert!(bookmarks.animate_to("here", &camera, 1.0));
+        bookmarks.update(&mut camera, 0.5);
+        // halfway, having turned through 180 rather than 0
+        assert!((camera.yaw + 180.0).abs() < 1e-4);
+        bookmarks.update(&mut camera, 0.6);
+        assert!(!bookmarks.is_animating());
+        assert_eq!(CameraView::of(&camera), *bookmarks.get("here").unwrap());
+    }
+}
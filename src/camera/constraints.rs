@@ -0,0 +1,141 @@
+use std::fmt;
+use std::sync::Arc;
+
+use lang::prelude::*;
+use lang::{Float, Point3, Vector3};
+use bounds::Aabb;
+
+/// Ground height at world `(x, z)`, `None` where there's no ground
+pub type HeightFn = Arc<dyn Fn(Float, Float) -> Option<Float> + Send + Sync>;
+
+/// Sweeps the camera's collision sphere from a point along a motion vector, returns the
+/// fraction of the motion in [0, 1] done before the first contact and the contact normal
+pub type SweepFn = Arc<dyn Fn(Point3, Vector3) -> Option<(Float, Vector3)> + Send + Sync>;
+
+/// Contacts resolved per move, enough for corners between two walls and a floor
+const MAX_SLIDES: usize = 3;
+/// Distance kept from surfaces, so the next sweep doesn't start in contact
+const SKIN: Float = 1e-3;
+
+/// Optional limits applied to every `Camera::movement`, all disabled by default
+#[derive(Clone, Default)]
+pub struct CameraConstraints {
+    /// the position is clamped into the box
+    pub bounds: Option<Aabb>,
+    /// keeps the camera at least `min_height` above the ground
+    pub height: Option<HeightFn>,
+    pub min_height: Float,
+    /// collide-and-slide against the scene instead of moving through it
+    pub sweep: Option<SweepFn>,
+}
+
+impl CameraConstraints {
+    pub fn bounds(mut self, bounds: Aabb) -> CameraConstraints {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    pub fn terrain<F: Fn(Float, Float) -> Option<Float> + Send + Sync + 'static>(mut self, height: F, min_height: Float) -> CameraConstraints {
+        self.height = Some(Arc::new(height));
+        self.min_height = min_height;
+        self
+    }
+
+    pub fn collide<F: Fn(Point3, Vector3) -> Option<(Float, Vector3)> + Send + Sync + 'static>(mut self, sweep: F) -> CameraConstraints {
+        self.sweep = Some(Arc::new(sweep));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_none() && self.height.is_none() && self.sweep.is_none()
+    }
+
+    /// Where a camera moving from `from` towards `to` ends up
+    pub fn constrain(&self, from: Point3, to: Point3) -> Point3 {
+        let mut position = match self.sweep {
+            Some(ref sweep) => slide(sweep, from, to - from),
+            None => to,
+        };
+        if let Some(ref height) = self.height {
+            if let Some(ground) = height(position.x, position.z) {
+                position.y = position.y.max(ground + self.min_height);
+            }
+        }
+        if let Some(ref bounds) = self.bounds {
+            position.x = position.x.clamp(bounds.min.x, bounds.max.x);
+            position.y = position.y.clamp(bounds.min.y, bounds.max.y);
+            position.z = position.z.clamp(bounds.min.z, bounds.max.z);
+        }
+        position
+    }
+}
+
+/// moves up to each contact, then along the surface with what's left of the motion
+fn slide(sweep: &SweepFn, mut position: Point3, mut motion: Vector3) -> Point3 {
+    for _ in 0..MAX_SLIDES {
+        let length = motion.magnitude();
+        if length < SKIN {
+            break;
+        }
+        match sweep(position, motion) {
+            None => return position + motion,
+            Some((fraction, normal)) => {
+                let travelled = (fraction * length - SKIN).max(0.0);
+                position += motion * (travelled / length);
+                let remaining = motion * (1.0 - travelled / length);
+                motion = remaining - normal * remaining.dot(normal).min(0.0);
+            },
+        }
+    }
+    position
+}
+
+impl PartialEq for CameraConstraints {
+    /// callbacks compare by identity
+    fn eq(&self, other: &CameraConstraints) -> bool {
+        fn same<T: ?Sized>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+        self.bounds == other.bounds && self.min_height == other.min_height &&
+            same(&self.height, &other.height) && same(&self.sweep, &other.sweep)
+    }
+}
+
+impl fmt::Debug for CameraConstraints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CameraConstraints")
+            .field("bounds", &self.bounds)
+            .field("height", &self.height.is_some())
+            .field("min_height", &self.min_height)
+            .field("sweep", &self.sweep.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slides_along_floor_and_stays_above_terrain() {
+        // floor at y = 0: any downward motion stops there
+        let constraints = CameraConstraints::default().collide(|from: Point3, motion: Vector3| {
+            if motion.y >= 0.0 || from.y + motion.y > 0.0 {
+                return None;
+            }
+            Some((from.y / -motion.y, Vector3::unit_y()))
+        });
+        let to = constraints.constrain(Point3::new(0.0, 1.0, 0.0), Point3::new(2.0, -1.0, 0.0));
+        assert!((to.x - 2.0).abs() < 1e-2 && to.y.abs() < 1e-2);
+
+        let constraints = CameraConstraints::default()
+            .terrain(|x, _| Some(x * 0.5), 1.8)
+            .bounds(Aabb::new(Point3::new(-10.0, -10.0, -10.0), Point3::new(10.0, 10.0, 10.0)));
+        let to = constraints.constrain(Point3::new(0.0, 5.0, 0.0), Point3::new(4.0, 0.0, 20.0));
+        assert!((to - Point3::new(4.0, 3.8, 10.0)).magnitude() < 1e-5);
+    }
+}
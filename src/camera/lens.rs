@@ -0,0 +1,72 @@
+use serde::{Serialize, Deserialize};
+
+use lang::Float;
+
+/// Physical lens and shutter of a camera, read by the depth of field and motion blur of
+/// `renderer::post`. Distances are in world units taken as meters.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lens {
+    /// distance of the sharp plane from the camera
+    pub focus_distance: Float,
+    /// focal length over aperture diameter, smaller is blurrier away from the focus
+    pub f_stop: Float,
+    /// height of the sensor, 24 mm for full frame; with the field of view it sets the focal length
+    pub sensor_height: Float,
+    /// degrees of a frame the shutter is open, 180 is the film look and 0 disables motion blur
+    pub shutter_angle: Float,
+}
+
+impl Default for Lens {
+    fn default() -> Lens {
+        Lens { focus_distance: 10.0, f_stop: 2.8, sensor_height: 0.024, shutter_angle: 180.0 }
+    }
+}
+
+impl Lens {
+    /// Focal length giving a vertical field of view of `fov` degrees on the sensor
+    pub fn focal_length(&self, fov: Float) -> Float {
+        self.sensor_height / (2.0 * (fov.to_radians() / 2.0).tan())
+    }
+
+    /// Diameter on the sensor of the blur circle of a point `distance` away, thin lens model
+    pub fn circle_of_confusion(&self, distance: Float, fov: Float) -> Float {
+        self.circle_at_infinity(fov) * ((distance - self.focus(fov)) / distance).abs()
+    }
+
+    /// Diameter of the blur circle that distant points approach, the largest behind the focus
+    pub fn circle_at_infinity(&self, fov: Float) -> Float {
+        let focal_length = self.focal_length(fov);
+        let aperture = focal_length / self.f_stop;
+        aperture * focal_length / (self.focus(fov) - focal_length)
+    }
+
+    /// `focus_distance`, kept beyond the focal length where the thin lens can't focus
+    pub(crate) fn focus(&self, fov: Float) -> Float {
+        self.focus_distance.max(self.focal_length(fov) * 1.001)
+    }
+
+    /// Fraction of the frame's motion recorded in the image
+    pub fn exposure(&self) -> Float {
+        self.shutter_angle.clamp(0.0, 360.0) / 360.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blur_grows_away_from_the_focus() {
+        let lens = Lens { focus_distance: 2.0, f_stop: 1.4, ..Lens::default() };
+        // 50 mm on full frame
+        assert!((lens.focal_length(27.0) - 0.05).abs() < 1e-3);
+        assert!(lens.circle_of_confusion(2.0, 27.0) < 1e-9);
+        let near = lens.circle_of_confusion(1.0, 27.0);
+        let far = lens.circle_of_confusion(4.0, 27.0);
+        let very_far = lens.circle_of_confusion(1000.0, 27.0);
+        assert!(near > far && very_far > far && very_far < near, "{} {} {}", near, far, very_far);
+        assert!(very_far < lens.circle_at_infinity(27.0));
+        assert!((Lens::default().exposure() - 0.5).abs() < 1e-9);
+    }
+}
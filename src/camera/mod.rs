@@ -0,0 +1,422 @@
+pub mod bookmarks;
+pub mod constraints;
+pub mod lens;
+pub mod path;
+
+use cgmath::perspective;
+use glfw::{Action, Key, MouseButtonLeft};
+use serde::{Serialize, Deserialize};
+
+use lang::prelude::*;
+use lang::wrap_degrees;
+use lang::{Float, RasterFloat, TimeSec, Point2, Point3, Vector2, Vector3, Vector4, Matrix4, Quaternion, Direction,
+           deg, rad, quat_from_yaw_pitch_roll};
+use input::{AxisCurves, Gesture, InputControl, KeyEvent, MouseEvent, ScrollSmoother};
+use window::InputState;
+use ray::Ray;
+use viewport::Viewport;
+
+pub use self::bookmarks::{CameraBookmarks, CameraView};
+pub use self::constraints::CameraConstraints;
+pub use self::lens::Lens;
+pub use self::path::{CameraPath, Easing};
+
+/// How mouse look and roll rotate the camera
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OrientationMode {
+    /// yaw and pitch around `world_up`, the horizon stays level unless rolled
+    #[default]
+    Euler,
+    /// rotations around the camera's own axes accumulate in `orientation`, with no gimbal
+    /// lock or pitch limit: for space and flight controls
+    Free,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Camera {
+    // Camera Attributes
+    pub position: Point3,
+    pub front: Vector3,
+    pub up: Vector3,
+    pub right: Vector3,
+    pub world_up: Vector3,
+    pub near: Float,
+    pub far: Float,
+
+    // Euler Angles
+    pub yaw: Float,
+    pub pitch: Float,
+    /// degrees around `front`, positive tilts the view clockwise
+    pub roll: Float,
+    pub constrain_pitch: bool,
+
+    pub orientation_mode: OrientationMode,
+    /// rotation from the -Z looking, Y up frame, kept in sync in both modes
+    pub orientation: Quaternion,
+
+    // Camera options
+    pub rotate_enabled: bool,
+    pub movement_speed: Float,
+    pub mouse_sensitivity: Float,
+    /// dead zone, exponent and inversion of mouse look, before `mouse_sensitivity`
+    #[serde(default)]
+    pub look_curves: AxisCurves,
+    pub zoom: Float,
+    /// eases scroll wheel and trackpad zoom over a few frames
+    #[serde(default)]
+    pub zoom_smoothing: ScrollSmoother,
+    /// degrees per second of the Q / E roll keys
+    pub roll_speed: Float,
+    /// focus, aperture and shutter for depth of field and motion blur
+    #[serde(default)]
+    pub lens: Lens,
+    /// sub-pixel offset of the projection in pixels, set every frame by `TemporalAA::jitter`
+    #[serde(skip, default = "Vector2::zero")]
+    pub jitter: Vector2,
+
+    /// bounds, terrain and collision limits of `movement`, not serialized
+    #[serde(skip)]
+    pub constraints: CameraConstraints,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        let mut camera = Camera {
+            position: Point3::new(0.0, 0.0, 3.0),
+            front: Vector3::new(0.0, 0.0, -1.0),
+            up: Vector3::zero(),
+            right: Vector3::zero(),
+            world_up: Vector3::unit_y(),
+            near: 0.1,
+            far: 100.0,
+            yaw: -90.0,
+            pitch: 0.0,
+            roll: 0.0,
+            constrain_pitch: true,
+            orientation_mode: OrientationMode::Euler,
+            orientation: Quaternion::one(),
+            rotate_enabled: false,
+            movement_speed: 2.5,
+            mouse_sensitivity: 0.1,
+            look_curves: AxisCurves::default(),
+            zoom: 45.0,
+            zoom_smoothing: ScrollSmoother::default(),
+            roll_speed: 90.0,
+            lens: Lens::default(),
+            jitter: Vector2::zero(),
+            constraints: CameraConstraints::default(),
+        };
+        camera.update_vectors();
+        camera
+    }
+}
+
+impl Camera {
+    /// Returns the view matrix calculated using Eular Angles and the LookAt Matrix
+    pub fn view_matrix(&self) -> Matrix4 {
+        Matrix4::look_at(self.position, self.position + self.front, self.up)
+    }
+
+    /// View matrix of a camera sitting at the origin, for camera-relative rendering
+    pub fn rotation_view_matrix(&self) -> Matrix4 {
+        Matrix4::look_at(Point3::origin(), Point3::from_vec(self.front), self.up)
+    }
+
+    /// Perspective projection, shifted by `jitter`
+    pub fn projection_matrix(&self, width: i32, height: i32) -> Matrix4 {
+        let mut projection = self.unjittered_projection_matrix(width, height);
+        // clip w is the negated view depth, so this moves the image by `jitter` pixels
+        projection.z.x -= 2.0 * self.jitter.x / width as Float;
+        projection.z.y -= 2.0 * self.jitter.y / height as Float;
+        projection
+    }
+
+    /// Perspective projection ignoring `jitter`, for picking and motion vectors
+    pub fn unjittered_projection_matrix(&self, width: i32, height: i32) -> Matrix4 {
+        perspective(deg(self.zoom), width as Float / height as Float, self.near, self.far)
+    }
+
+    /// Returns the world-space ray going through the window position `(x, y)`,
+    /// where `(0, 0)` is the top left corner of a `width` x `height` viewport.
+    /// `None` when the viewport is empty, e.g. while the window is minimized, or the camera has
+    /// no usable projection.
+    pub fn screen_ray(&self, x: RasterFloat, y: RasterFloat, width: i32, height: i32) -> Option<Ray> {
+        if width <= 0 || height <= 0 {
+            return None;
+        }
+        let ndc_x = 2.0 * x as Float / width as Float - 1.0;
+        let ndc_y = 1.0 - 2.0 * y as Float / height as Float;
+
+        let inverse = (self.unjittered_projection_matrix(width, height) * self.view_matrix()).invert()?;
+        let near = Point3::from_homogeneous(inverse * Vector4::new(ndc_x, ndc_y, -1.0, 1.0));
+        let far = Point3::from_homogeneous(inverse * Vector4::new(ndc_x, ndc_y, 1.0, 1.0));
+
+        Some(Ray::new(near, far - near))
+    }
+
+    /// Projects a world position into the viewport: `x` and `y` in pixels from its top left
+    /// corner, as `screen_ray` takes them, and `z` the depth-buffer depth in [0, 1].
+    /// `None` when the point is behind the camera.
+    pub fn project(&self, world: Point3, viewport: Viewport) -> Option<Point3> {
+        let clip = self.unjittered_projection_matrix(viewport.width, viewport.height) * self.view_matrix() * world.to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(Point3::new((ndc.x + 1.0) * 0.5 * viewport.width as Float,
+                         (1.0 - ndc.y) * 0.5 * viewport.height as Float,
+                         (ndc.z + 1.0) * 0.5))
+    }
+
+    /// World position under the viewport pixel `screen` (top left origin) at `depth` in [0, 1],
+    /// e.g. a value read back from the depth buffer. 0 is on the near plane and 1 on the far plane.
+    /// `None` for an empty viewport or a camera with no usable projection, like `screen_ray`.
+    pub fn unproject(&self, screen: Point2, depth: Float, viewport: Viewport) -> Option<Point3> {
+        if viewport.is_empty() {
+            return None;
+        }
+        let ndc = Vector4::new(2.0 * screen.x / viewport.width as Float - 1.0,
+                               1.0 - 2.0 * screen.y / viewport.height as Float,
+                               2.0 * depth - 1.0,
+                               1.0);
+        let inverse = (self.unjittered_projection_matrix(viewport.width, viewport.height) * self.view_matrix()).invert()?;
+        Some(Point3::from_homogeneous(inverse * ndc))
+    }
+
+    /// Calculates the front vector from the Camera's (updated) Eular Angles, or from
+    /// `orientation` in `OrientationMode::Free`
+    pub fn update_vectors(&mut self) {
+        if self.orientation_mode == OrientationMode::Free {
+            self.orientation = self.orientation.normalize();
+            self.front = self.orientation * Vector3::new(0.0, 0.0, -1.0);
+            self.up = self.orientation * Vector3::unit_y();
+            self.right = self.orientation * Vector3::unit_x();
+            self.sync_angles();
+            return;
+        }
+        // Calculate the new Front vector
+        let front = Vector3 {
+            x: self.yaw.to_radians().cos() * self.pitch.to_radians().cos(),
+            y: self.pitch.to_radians().sin(),
+            z: self.yaw.to_radians().sin() * self.pitch.to_radians().cos(),
+        };
+        self.front = front.normalize();
+        // Also re-calculate the Right and Up vector
+        self.right = self.front.cross(self.world_up).normalize(); // Normalize the vectors, because their length gets closer to 0 the more you look up or down which results in slower movement.
+        self.up = self.right.cross(self.front).normalize();
+        if self.roll != 0.0 {
+            let (sin, cos) = self.roll.to_radians().sin_cos();
+            let (right, up) = (self.right, self.up);
+            self.right = right * cos - up * sin;
+            self.up = up * cos + right * sin;
+        }
+        // assumes `world_up` is +Y, like the quaternion's reference frame
+        self.orientation = quat_from_yaw_pitch_roll(rad(-(self.yaw + 90.0).to_radians()), rad(self.pitch.to_radians()),
+                                                    rad(-self.roll.to_radians()));
+    }
+
+    /// Sets the angles in either mode, e.g. to restore a saved view
+    pub fn set_angles(&mut self, yaw: Float, pitch: Float, roll: Float) {
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.roll = roll;
+        self.orientation = quat_from_yaw_pitch_roll(rad(-(yaw + 90.0).to_radians()), rad(pitch.to_radians()),
+                                                    rad(-roll.to_radians()));
+        self.update_vectors();
+    }
+
+    /// Switches modes keeping the current view. Going back to Euler keeps the roll, and
+    /// `constrain_pitch` applies again from the next mouse move.
+    pub fn set_orientation_mode(&mut self, mode: OrientationMode) {
+        if mode == OrientationMode::Free {
+            let (yaw, pitch, roll) = (self.yaw, self.pitch, self.roll);
+            self.set_angles(yaw, pitch, roll);
+        }
+        self.orientation_mode = mode;
+        self.update_vectors();
+    }
+
+    /// Rotates around the camera's own axes (degrees): up, right and front
+    pub fn rotate_local(&mut self, yaw: Float, pitch: Float, roll: Float) {
+        match self.orientation_mode {
+            OrientationMode::Free => {
+                self.orientation = self.orientation
+                    * Quaternion::from_angle_y(deg(-yaw))
+                    * Quaternion::from_angle_x(deg(pitch))
+                    * Quaternion::from_angle_z(deg(-roll));
+            },
+            OrientationMode::Euler => {
+                self.yaw += yaw;
+                self.pitch += pitch;
+                self.roll = wrap_degrees(self.roll + roll);
+                if self.constrain_pitch {
+                    self.pitch = self.pitch.clamp(-89.0, 89.0);
+                }
+            },
+        }
+        self.update_vectors();
+    }
+
+    /// yaw / pitch / roll of the free orientation, for bookmarks and display
+    fn sync_angles(&mut self) {
+        self.pitch = self.front.y.clamp(-1.0, 1.0).asin().to_degrees();
+        self.yaw = self.front.z.atan2(self.front.x).to_degrees();
+        let level_right = self.front.cross(Vector3::unit_y());
+        if level_right.magnitude2() > 1e-8 {
+            let level_right = level_right.normalize();
+            let level_up = level_right.cross(self.front);
+            self.roll = self.up.dot(level_right).atan2(self.up.dot(level_up)).to_degrees();
+        }
+    }
+
+    pub fn movement(&mut self, direction: Direction, delta_time: TimeSec) {
+        let from = self.position;
+        match direction {
+            Direction::FORWARD => {
+                self.position += self.front * self.movement_speed * delta_time as Float;
+            },
+            Direction::BACKWARD => {
+                self.position += -(self.front * self.movement_speed * delta_time as Float);
+            },
+            Direction::LEFT => {
+                self.position += -(self.right * self.movement_speed * delta_time as Float);
+            },
+            Direction::RIGHT => {
+                self.position += self.right * self.movement_speed * delta_time as Float;
+            }
+        }
+        if !self.constraints.is_empty() {
+            self.position = self.constraints.constrain(from, self.position);
+        }
+    }
+}
+
+impl InputControl for Camera {
+    fn on_mouse(&mut self, mouse: MouseEvent, _delta_time: TimeSec) {
+        if mouse.is_scroll {
+            // Processes input received from a mouse scroll-wheel event.
+            // Only requires input on the vertical wheel-axis, applied over the next frames in on_input
+            self.zoom_smoothing.add(mouse.y_offset as Float);
+        } else {
+            // Mouse cursor pos event
+
+            if self.rotate_enabled {
+                let (x_offset, y_offset) = self.look_curves.apply(mouse.x_offset as Float, mouse.y_offset as Float);
+                let (x_offset, y_offset) = (x_offset * self.mouse_sensitivity, y_offset * self.mouse_sensitivity);
+
+                // pitch is kept within +-89 degrees in Euler mode so the screen doesn't get flipped
+                self.rotate_local(x_offset, y_offset, 0.0);
+            }
+        }
+    }
+
+    fn on_keyboard(&mut self, _key: KeyEvent, _delta_time: TimeSec) {
+    }
+
+    /// drag looks around like the mouse, pinch zooms and a twist turns
+    fn on_gesture(&mut self, gesture: Gesture, _delta_time: TimeSec) {
+        match gesture {
+            Gesture::Drag { x_offset, y_offset } => {
+                let (x_offset, y_offset) = self.look_curves.apply(x_offset as Float, y_offset as Float);
+                let sensitivity = self.mouse_sensitivity;
+                self.rotate_local(x_offset * sensitivity, y_offset * sensitivity, 0.0);
+            },
+            Gesture::Pinch { scale } => {
+                self.zoom = (self.zoom / scale as Float).clamp(1.0, 45.0);
+            },
+            Gesture::Rotate { degrees } => {
+                self.rotate_local(-degrees as Float, 0.0, 0.0);
+            },
+        }
+    }
+
+    fn on_input(&mut self, window: &dyn InputState, delta_time: TimeSec) {
+        match window.get_mouse_button(MouseButtonLeft) {
+            Action::Press if !self.rotate_enabled => {
+                self.rotate_enabled = true
+            },
+            Action::Release if self.rotate_enabled => {
+                self.rotate_enabled = false
+            },
+            _ => {}
+        }
+
+        let zoom_step = self.zoom_smoothing.update(delta_time);
+        if zoom_step != 0.0 {
+            let zoom = self.zoom - zoom_step;
+            self.zoom = zoom.clamp(1.0, 45.0);
+            if self.zoom != zoom {
+                // don't keep pushing against the limit
+                self.zoom_smoothing.stop();
+            }
+        }
+
+        if window.get_key(Key::W) == Action::Press {
+            self.movement(Direction::FORWARD, delta_time);
+        }
+        if window.get_key(Key::S) == Action::Press {
+            self.movement(Direction::BACKWARD, delta_time);
+        }
+        if window.get_key(Key::A) == Action::Press {
+            self.movement(Direction::LEFT, delta_time);
+        }
+        if window.get_key(Key::D) == Action::Press {
+            self.movement(Direction::RIGHT, delta_time);
+        }
+        let roll = self.roll_speed * delta_time as Float;
+        if window.get_key(Key::Q) == Action::Press {
+            self.rotate_local(0.0, 0.0, -roll);
+        }
+        if window.get_key(Key::E) == Action::Press {
+            self.rotate_local(0.0, 0.0, roll);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_and_unprojects() {
+        let camera = Camera::default();
+        let viewport = Viewport::new(100, 50, 800, 600);
+        let center = camera.project(Point3::new(0.0, 0.0, 0.0), viewport).unwrap();
+        assert!((center.x - 400.0).abs() < 1e-3 && (center.y - 300.0).abs() < 1e-3);
+        assert!(camera.project(Point3::new(0.0, 0.0, 10.0), viewport).is_none());
+
+        let world = Point3::new(0.5, -0.25, -1.0);
+        let screen = camera.project(world, viewport).unwrap();
+        let back = camera.unproject(Point2::new(screen.x, screen.y), screen.z, viewport).unwrap();
+        assert!((back - world).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn empty_viewport_has_no_screen_ray() {
+        let camera = Camera::default();
+        assert!(camera.screen_ray(400.0, 300.0, 800, 600).is_some());
+        assert!(camera.screen_ray(0.0, 0.0, 800, 0).is_none());
+        assert!(camera.screen_ray(0.0, 0.0, 0, 0).is_none());
+    }
+
+    #[test]
+    fn empty_viewport_does_not_unproject() {
+        let camera = Camera::default();
+        assert!(camera.unproject(Point2::new(0.0, 0.0), 0.5, Viewport::new(0, 0, 0, 0)).is_none());
+        assert!(camera.unproject(Point2::new(0.0, 0.0), 0.5, Viewport::new(10, 10, 640, 0)).is_none());
+    }
+
+    #[test]
+    fn free_orientation_matches_euler() {
+        let mut camera = Camera::default();
+        camera.set_angles(30.0, 20.0, 10.0);
+        let (front, up) = (camera.front, camera.up);
+
+        camera.set_orientation_mode(OrientationMode::Free);
+        assert!((camera.front - front).magnitude() < 1e-4 && (camera.up - up).magnitude() < 1e-4);
+        assert!((camera.roll - 10.0).abs() < 1e-3 && (camera.yaw - 30.0).abs() < 1e-3);
+
+        // straight up and over, which Euler mode clamps
+        camera.rotate_local(0.0, 120.0, 0.0);
+        assert!(camera.front.y < 0.9 && camera.up.y < 0.0);
+    }
+}
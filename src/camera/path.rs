@@ -0,0 +1,254 @@
+use serde::{Serialize, Deserialize};
+
+use lang::prelude::*;
+use lang::{Float, TimeSec, Point3, smoothstep, wrap_degrees};
+use super::{Camera, CameraView};
+
+/// Arc length samples per span for `constant_speed`
+const ARC_SAMPLES: usize = 16;
+
+/// Timing curve applied to the playback progress
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn apply(self, t: Float) -> Float {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => smoothstep(0.0, 1.0, t),
+        }
+    }
+}
+
+/// Fly-through along a Catmull-Rom spline through the keyframes: positions, yaw, pitch and zoom
+/// are all interpolated smoothly, yaw the short way around between consecutive keys.
+///
+/// Keys are evenly spaced in time unless `constant_speed` is set, in which case the camera
+/// moves along the curve at a constant speed whatever the key spacing. Start it with `play`,
+/// then `update` drives the camera every frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keys: Vec<CameraView>,
+    /// seconds from the first to the last key (back to the first when looped)
+    pub duration: TimeSec,
+    pub easing: Easing,
+    pub looped: bool,
+    pub constant_speed: bool,
+    /// cumulative arc length at each sample, rebuilt on `play`
+    #[serde(skip)]
+    arc_lengths: Vec<Float>,
+    #[serde(skip)]
+    elapsed: Option<TimeSec>,
+}
+
+impl CameraPath {
+    pub fn new(keys: Vec<CameraView>, duration: TimeSec) -> CameraPath {
+        CameraPath {
+            keys,
+            duration,
+            easing: Easing::Linear,
+            looped: false,
+            constant_speed: false,
+            arc_lengths: Vec::new(),
+            elapsed: None,
+        }
+    }
+
+    pub fn easing(mut self, easing: Easing) -> CameraPath {
+        self.easing = easing;
+        self
+    }
+
+    pub fn looped(mut self, looped: bool) -> CameraPath {
+        self.looped = looped;
+        self
+    }
+
+    pub fn constant_speed(mut self, constant_speed: bool) -> CameraPath {
+        self.constant_speed = constant_speed;
+        self
+    }
+
+    /// Adds a key at the end, e.g. the camera's current view while recording a path
+    pub fn push(&mut self, key: CameraView) {
+        self.keys.push(key);
+        self.arc_lengths.clear();
+    }
+
+    fn spans(&self) -> usize {
+        match self.keys.len() {
+            0 | 1 => 0,
+            count if self.looped => count,
+            count => count - 1,
+        }
+    }
+
+    /// View at `t` in [0, 1] of the path, after easing and speed reparameterization, `None`
+    /// for a path without keys
+    pub fn sample(&self, t: Float) -> Option<CameraView> {
+        let spans = self.spans();
+        if spans == 0 {
+            return self.keys.first().cloned();
+        }
+        let t = self.easing.apply(t);
+        let u = if self.constant_speed && !self.arc_lengths.is_empty() { self.reparameterize(t) } else { t };
+        let position = u * spans as Float;
+        let span = (position.floor() as usize).min(spans - 1);
+        Some(self.evaluate(span, position - span as Float))
+    }
+
+    /// Starts playback from the first key
+    pub fn play(&mut self) {
+        if self.constant_speed {
+            self.build_arc_lengths();
+        }
+        self.elapsed = Some(0.0);
+    }
+
+    pub fn stop(&mut self) {
+        self.elapsed = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    /// Advances playback and moves the camera, call it once per frame. A path that isn't
+    /// looped stops on its last key. A path without keys leaves the camera where it is.
+    pub fn update(&mut self, camera: &mut Camera, delta_time: TimeSec) {
+        let elapsed = match self.elapsed {
+            Some(elapsed) => elapsed + delta_time,
+            None => return,
+        };
+        let mut t = if self.duration > 0.0 { elapsed / self.duration } else { 1.0 };
+        if self.looped {
+            t = t.fract();
+            self.elapsed = Some(elapsed % self.duration.max(TimeSec::EPSILON));
+        } else if t >= 1.0 {
+            t = 1.0;
+            self.elapsed = None;
+        } else {
+            self.elapsed = Some(elapsed);
+        }
+        if let Some(view) = self.sample(t as Float) {
+            view.apply(camera);
+        }
+    }
+
+    fn key(&self, index: isize) -> &CameraView {
+        let count = self.keys.len() as isize;
+        let index = if self.looped { index.rem_euclid(count) } else { index.clamp(0, count - 1) };
+        &self.keys[index as usize]
+    }
+
+    /// Catmull-Rom between keys `span` and `span + 1`
+    fn evaluate(&self, span: usize, s: Float) -> CameraView {
+        let i = span as isize;
+        let (k0, k1, k2, k3) = (self.key(i - 1), self.key(i), self.key(i + 1), self.key(i + 2));
+        // yaw unwrapped around the span's first key so the curve never turns the long way
+        let yaw1 = k1.yaw;
+        let yaw0 = yaw1 + wrap_degrees(k0.yaw - yaw1);
+        let yaw2 = yaw1 + wrap_degrees(k2.yaw - yaw1);
+        let yaw3 = yaw2 + wrap_degrees(k3.yaw - yaw2);
+        CameraView {
+            position: Point3::from_vec(catmull_rom(k0.position.to_vec(), k1.position.to_vec(),
+                                                   k2.position.to_vec(), k3.position.to_vec(), s)),
+            yaw: catmull_rom(yaw0, yaw1, yaw2, yaw3, s),
+            pitch: catmull_rom(k0.pitch, k1.pitch, k2.pitch, k3.pitch, s),
+            zoom: catmull_rom(k0.zoom, k1.zoom, k2.zoom, k3.zoom, s),
+        }
+    }
+
+    fn build_arc_lengths(&mut self) {
+        let spans = self.spans();
+        let samples = spans * ARC_SAMPLES;
+        let mut lengths = Vec::with_capacity(samples + 1);
+        let mut total = 0.0;
+        let mut previous = self.keys.first().map(|key| key.position);
+        lengths.push(0.0);
+        for sample in 1..=samples {
+            let (span, s) = ((sample - 1) / ARC_SAMPLES, ((sample - 1) % ARC_SAMPLES + 1) as Float / ARC_SAMPLES as Float);
+            let point = self.evaluate(span, s).position;
+            if let Some(previous) = previous {
+                total += (point - previous).magnitude();
+            }
+            previous = Some(point);
+            lengths.push(total);
+        }
+        self.arc_lengths = lengths;
+    }
+
+    /// curve parameter at which the fraction `t` of the arc length is covered
+    fn reparameterize(&self, t: Float) -> Float {
+        let lengths = &self.arc_lengths;
+        let total = *lengths.last().unwrap();
+        if total <= 0.0 {
+            return t;
+        }
+        let target = t * total;
+        let index = lengths.partition_point(|&length| length < target).clamp(1, lengths.len() - 1);
+        let (before, after) = (lengths[index - 1], lengths[index]);
+        let local = if after > before { (target - before) / (after - before) } else { 0.0 };
+        (index - 1) as Float / (lengths.len() - 1) as Float + local / (lengths.len() - 1) as Float
+    }
+}
+
+/// Uniform Catmull-Rom through `p1` (s = 0) and `p2` (s = 1)
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, s: Float) -> T
+    where T: Copy + ::std::ops::Add<Output = T> + ::std::ops::Sub<Output = T> + ::std::ops::Mul<Float, Output = T> {
+    let (s2, s3) = (s * s, s * s * s);
+    (p1 * 2.0 + (p2 - p0) * s + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * s2 + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * s3) * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(x: Float, yaw: Float) -> CameraView {
+        CameraView { position: Point3::new(x, 0.0, 0.0), yaw, pitch: 0.0, zoom: 45.0 }
+    }
+
+    #[test]
+    fn passes_through_keys_at_constant_speed() {
+        let mut path = CameraPath::new(vec![view(0.0, 170.0), view(1.0, -170.0), view(10.0, -150.0)], 2.0)
+            .constant_speed(true);
+        path.play();
+        assert_eq!(path.sample(0.0).unwrap().position, Point3::new(0.0, 0.0, 0.0));
+        assert!((path.sample(1.0).unwrap().position.x - 10.0).abs() < 1e-4);
+
+        // the short 0..1 span takes about a tenth of the time, not half
+        assert!(path.sample(0.1).unwrap().position.x < 1.5);
+        assert!(path.sample(0.5).unwrap().position.x > 4.0);
+        // 170 -> -170 turns through 180, not back through 0
+        let yaw = path.evaluate(0, 0.5).yaw;
+        assert!(wrap_degrees(yaw - 180.0).abs() < 5.0);
+
+        let mut camera = Camera::default();
+        path.update(&mut camera, 3.0);
+        assert!(!path.is_playing());
+        assert!((camera.position.x - 10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn empty_paths_leave_the_camera_alone() {
+        let mut path = CameraPath::new(vec![], 2.0);
+        assert_eq!(path.sample(0.5), None);
+        path.play();
+        let mut camera = Camera::default();
+        let position = camera.position;
+        path.update(&mut camera, 0.5);
+        assert_eq!(camera.position, position);
+
+        path.push(view(3.0, 0.0));
+        assert_eq!(path.sample(0.5), Some(view(3.0, 0.0)));
+    }
+}
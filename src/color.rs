@@ -0,0 +1,160 @@
+use gl;
+use gl::types::GLenum;
+use serde::{Serialize, Deserialize};
+
+use lang::Float;
+
+/// How the 8 bit channels of a texture are stored: color images are authored in sRGB and
+/// decoded to linear when sampled, data (normals, masks, lookup tables) is read as is
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// Internal format for RGBA8 pixels in this space
+    pub fn internal_format(self) -> GLenum {
+        match self {
+            ColorSpace::Srgb => gl::SRGB8_ALPHA8,
+            ColorSpace::Linear => gl::RGBA8,
+        }
+    }
+}
+
+/// Decodes one sRGB channel in [0, 1] to linear
+pub fn srgb_to_linear(value: Float) -> Float {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes one linear channel in [0, 1] to sRGB
+pub fn linear_to_srgb(value: Float) -> Float {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear RGBA color with straight alpha. Shading, blending and uniforms work in linear space:
+/// build colors picked in an editor or written as hex with the sRGB constructors, and let the
+/// sRGB framebuffer encode the result.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Color {
+    pub r: Float,
+    pub g: Float,
+    pub b: Float,
+    pub a: Float,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 };
+    pub const WHITE: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+    pub const TRANSPARENT: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
+
+    pub fn linear(r: Float, g: Float, b: Float, a: Float) -> Color {
+        Color { r, g, b, a }
+    }
+
+    /// From sRGB encoded channels, alpha is always linear
+    pub fn srgb(r: Float, g: Float, b: Float, a: Float) -> Color {
+        Color { r: srgb_to_linear(r), g: srgb_to_linear(g), b: srgb_to_linear(b), a }
+    }
+
+    pub fn srgb8(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color::srgb(r as Float / 255.0, g as Float / 255.0, b as Float / 255.0, a as Float / 255.0)
+    }
+
+    /// Parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` (the `#` is optional) as sRGB
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.is_ascii() {
+            return None;
+        }
+        let digit = |index: usize, width: usize| {
+            u8::from_str_radix(&hex[index * width..(index + 1) * width], 16).ok()
+                .map(|value| if width == 1 { value * 17 } else { value })
+        };
+        let (width, alpha) = match hex.len() {
+            3 => (1, false),
+            4 => (1, true),
+            6 => (2, false),
+            8 => (2, true),
+            _ => return None,
+        };
+        let a = if alpha { digit(3, width)? } else { 255 };
+        Some(Color::srgb8(digit(0, width)?, digit(1, width)?, digit(2, width)?, a))
+    }
+
+    pub fn with_alpha(mut self, a: Float) -> Color {
+        self.a = a;
+        self
+    }
+
+    pub fn lerp(self, other: Color, t: Float) -> Color {
+        Color {
+            r: self.r + (other.r - self.r) * t,
+            g: self.g + (other.g - self.g) * t,
+            b: self.b + (other.b - self.b) * t,
+            a: self.a + (other.a - self.a) * t,
+        }
+    }
+
+    /// Channels multiplied by alpha, for `BlendMode::Premultiplied`
+    pub fn premultiplied(self) -> Color {
+        Color { r: self.r * self.a, g: self.g * self.a, b: self.b * self.a, a: self.a }
+    }
+
+    /// Linear channels, what shaders and `glClearColor` expect with an sRGB framebuffer
+    pub fn to_array(self) -> [Float; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn to_srgb(self) -> [Float; 4] {
+        [linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b), self.a]
+    }
+
+    pub fn to_srgb8(self) -> [u8; 4] {
+        let byte = |value: Float| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let [r, g, b, a] = self.to_srgb();
+        [byte(r), byte(g), byte(b), byte(a)]
+    }
+}
+
+impl From<Color> for [Float; 4] {
+    fn from(color: Color) -> [Float; 4] {
+        color.to_array()
+    }
+}
+
+/// Enables the sRGB encoding of writes and blends to sRGB framebuffers, the window's default
+/// framebuffer among them when `WindowConfig::srgb` is set
+pub fn set_framebuffer_srgb(enabled: bool) {
+    unsafe {
+        if enabled {
+            gl::Enable(gl::FRAMEBUFFER_SRGB);
+        } else {
+            gl::Disable(gl::FRAMEBUFFER_SRGB);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_as_srgb() {
+        let color = Color::from_hex("#ff8000").unwrap();
+        assert_eq!(color.to_srgb8(), [255, 128, 0, 255]);
+        assert!((color.g - 0.2158).abs() < 1e-3);
+        assert_eq!(Color::from_hex("f80c").unwrap().to_srgb8(), [255, 136, 0, 204]);
+        assert_eq!(Color::from_hex("#12345"), None);
+        assert_eq!(Color::from_hex("#gg0000"), None);
+    }
+}
@@ -0,0 +1,372 @@
+use std::mem;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use buffer::supports;
+use lang::{Float, Matrix4, gl_float};
+use gl_state::GlState;
+use shader::Shader;
+
+/// GL 4.3 or `GL_ARB_compute_shader`, with shader storage buffers
+pub fn supports_compute() -> bool {
+    gl::DispatchCompute::is_loaded() && supports((4, 3), "GL_ARB_compute_shader")
+}
+
+/// Memory barrier bits, combined with `|`, to make writes of a dispatch visible to
+/// the following reads of the given kind
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Barriers(pub GLbitfield);
+
+impl Barriers {
+    pub const SHADER_STORAGE: Barriers = Barriers(gl::SHADER_STORAGE_BARRIER_BIT);
+    pub const VERTEX_ATTRIB: Barriers = Barriers(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    pub const ELEMENT_ARRAY: Barriers = Barriers(gl::ELEMENT_ARRAY_BARRIER_BIT);
+    /// indirect draw and dispatch arguments
+    pub const COMMAND: Barriers = Barriers(gl::COMMAND_BARRIER_BIT);
+    pub const TEXTURE_FETCH: Barriers = Barriers(gl::TEXTURE_FETCH_BARRIER_BIT);
+    pub const IMAGE_ACCESS: Barriers = Barriers(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    pub const BUFFER_UPDATE: Barriers = Barriers(gl::BUFFER_UPDATE_BARRIER_BIT);
+    pub const ALL: Barriers = Barriers(gl::ALL_BARRIER_BITS);
+
+    /// Issues `glMemoryBarrier`
+    pub fn wait(self) {
+        unsafe {
+            gl::MemoryBarrier(self.0);
+        }
+    }
+}
+
+impl ::std::ops::BitOr for Barriers {
+    type Output = Barriers;
+
+    fn bitor(self, other: Barriers) -> Barriers {
+        Barriers(self.0 | other.0)
+    }
+}
+
+/// Runs the compute program in use over `x * y * z` work groups
+pub fn dispatch(x: u32, y: u32, z: u32) {
+    unsafe {
+        gl::DispatchCompute(x, y, z);
+    }
+}
+
+/// Number of work groups of `group_size` covering `count` items
+pub fn group_count(count: usize, group_size: usize) -> u32 {
+    count.div_ceil(group_size.max(1)) as u32
+}
+
+/// Shader storage buffer, bound to an indexed `binding` point of the shaders
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StorageBuffer {
+    pub buffer: u32,
+    /// size in bytes
+    pub size: usize,
+}
+
+impl StorageBuffer {
+    /// Zero-filled buffer of `size` bytes
+    pub fn new(size: usize) -> StorageBuffer {
+        let zeros = vec![0u8; size];
+        StorageBuffer::with_data(&zeros)
+    }
+
+    pub fn with_data<T: Copy>(data: &[T]) -> StorageBuffer {
+        let mut buffer = StorageBuffer { buffer: 0, size: mem::size_of_val(data) };
+        unsafe {
+            gl::GenBuffers(1, &mut buffer.buffer);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, buffer.buffer);
+            gl::BufferData(gl::SHADER_STORAGE_BUFFER, buffer.size as GLsizeiptr, data.as_ptr() as *const GLvoid,
+                           gl::DYNAMIC_COPY);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+        buffer
+    }
+
+    /// Overwrites the start of the buffer, `data` must fit
+    pub fn upload<T: Copy>(&self, data: &[T]) {
+        let size = mem::size_of_val(data).min(self.size);
+        unsafe {
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.buffer);
+            gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, 0, size as GLsizeiptr, data.as_ptr() as *const GLvoid);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+    }
+
+    /// Reads the buffer back, stalling until the GPU is done with it
+    pub fn download<T: Copy + Default>(&self) -> Vec<T> {
+        let mut data = vec![T::default(); self.size / mem::size_of::<T>().max(1)];
+        unsafe {
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.buffer);
+            gl::GetBufferSubData(gl::SHADER_STORAGE_BUFFER, 0, mem::size_of_val(&data[..]) as GLsizeiptr,
+                                 data.as_mut_ptr() as *mut GLvoid);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+        data
+    }
+
+    /// `layout(std430, binding = index)` in the shaders
+    pub fn bind(&self, index: u32) {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, index, self.buffer);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+        *self = StorageBuffer::default();
+    }
+}
+
+/// Two storage buffers swapped every step, so a dispatch reads last step's state while
+/// writing the next one
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PingPongBuffer {
+    pub buffers: [StorageBuffer; 2],
+    current: usize,
+}
+
+impl PingPongBuffer {
+    /// Both buffers start with `data`
+    pub fn with_data<T: Copy>(data: &[T]) -> PingPongBuffer {
+        PingPongBuffer { buffers: [StorageBuffer::with_data(data), StorageBuffer::with_data(data)], current: 0 }
+    }
+
+    /// Buffer holding the latest state
+    pub fn read(&self) -> StorageBuffer {
+        self.buffers[self.current]
+    }
+
+    pub fn write(&self) -> StorageBuffer {
+        self.buffers[1 - self.current]
+    }
+
+    /// Binds the read buffer to `read_index` and the write buffer to `write_index`
+    pub fn bind(&self, read_index: u32, write_index: u32) {
+        self.read().bind(read_index);
+        self.write().bind(write_index);
+    }
+
+    /// Makes the written buffer the one read next
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+    }
+
+    pub fn delete(&mut self) {
+        self.buffers[0].delete();
+        self.buffers[1].delete();
+    }
+}
+
+/// Arguments of `glDispatchComputeIndirect`
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DispatchIndirectCommand {
+    pub groups_x: u32,
+    pub groups_y: u32,
+    pub groups_z: u32,
+}
+
+/// Work group counts stored on the GPU, so a compute pass can size the next one
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DispatchIndirectBuffer {
+    pub storage: StorageBuffer,
+}
+
+impl DispatchIndirectBuffer {
+    pub fn new(commands: &[DispatchIndirectCommand]) -> DispatchIndirectBuffer {
+        DispatchIndirectBuffer { storage: StorageBuffer::with_data(commands) }
+    }
+
+    /// Dispatches with the `index`th command, issue a `Barriers::COMMAND` after writing it on the GPU
+    pub fn dispatch(&self, index: usize) {
+        unsafe {
+            gl::BindBuffer(gl::DISPATCH_INDIRECT_BUFFER, self.storage.buffer);
+            gl::DispatchComputeIndirect((index * mem::size_of::<DispatchIndirectCommand>()) as GLintptr);
+            gl::BindBuffer(gl::DISPATCH_INDIRECT_BUFFER, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        self.storage.delete();
+    }
+}
+
+const PARTICLE_GROUP_SIZE: usize = 256;
+
+const PARTICLE_COMPUTE_SHADER: &str = r#"
+#version 430 core
+layout (local_size_x = 256) in;
+
+struct Particle {
+    vec4 position; // w: remaining life in seconds
+    vec4 velocity;
+};
+
+layout (std430, binding = 0) readonly buffer Previous { Particle previous[]; };
+layout (std430, binding = 1) writeonly buffer Next { Particle next[]; };
+
+uniform float deltaTime;
+uniform vec3 gravity;
+uniform uint count;
+
+void main()
+{
+    uint i = gl_GlobalInvocationID.x;
+    if (i >= count)
+        return;
+    Particle particle = previous[i];
+    particle.velocity.xyz += gravity * deltaTime;
+    particle.position.xyz += particle.velocity.xyz * deltaTime;
+    particle.position.w -= deltaTime;
+    next[i] = particle;
+}
+"#;
+
+const PARTICLE_VERTEX_SHADER: &str = r#"
+#version 430 core
+layout (location = 0) in vec4 aPosition;
+
+uniform mat4 viewProjection;
+uniform float pointSize;
+
+out float Life;
+
+void main()
+{
+    Life = aPosition.w;
+    gl_Position = viewProjection * vec4(aPosition.xyz, 1.0);
+    gl_PointSize = Life > 0.0 ? pointSize : 0.0;
+}
+"#;
+
+const PARTICLE_FRAGMENT_SHADER: &str = r#"
+#version 430 core
+out vec4 FragColor;
+in float Life;
+
+void main()
+{
+    if (Life <= 0.0)
+        discard;
+    vec2 offset = gl_PointCoord * 2.0 - 1.0;
+    float alpha = 1.0 - dot(offset, offset);
+    if (alpha <= 0.0)
+        discard;
+    FragColor = vec4(1.0, 0.7, 0.3, alpha * min(Life, 1.0));
+}
+"#;
+
+/// Particle state as stored on the GPU, `position.w` is the remaining life in seconds
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct GpuParticle {
+    pub position: [f32; 4],
+    pub velocity: [f32; 4],
+}
+
+/// Example compute path: particles integrated by a compute shader in ping-pong storage
+/// buffers and drawn as points straight from the buffer, without a CPU round trip
+pub struct GpuParticles {
+    pub gravity: [Float; 3],
+    pub point_size: Float,
+    buffers: PingPongBuffer,
+    count: usize,
+    update: Shader,
+    draw: Shader,
+    vao: u32,
+}
+
+impl GpuParticles {
+    pub fn new(particles: &[GpuParticle]) -> GpuParticles {
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+        GpuParticles {
+            gravity: [0.0, -9.81, 0.0],
+            point_size: 4.0,
+            buffers: PingPongBuffer::with_data(particles),
+            count: particles.len(),
+            update: Shader::compute(PARTICLE_COMPUTE_SHADER),
+            draw: Shader::from_source(PARTICLE_VERTEX_SHADER, PARTICLE_FRAGMENT_SHADER),
+            vao,
+        }
+    }
+
+    /// Overwrites the first `particles.len()` particles, e.g. to respawn dead ones
+    pub fn respawn(&mut self, particles: &[GpuParticle]) {
+        self.buffers.read().upload(particles);
+    }
+
+    pub fn update(&mut self, state: &mut GlState, delta_time: Float) {
+        state.use_program(self.update.ID);
+        unsafe {
+            self.update.setFloat(c_str!("deltaTime"), delta_time);
+            self.update.setVec3(c_str!("gravity"), self.gravity[0], self.gravity[1], self.gravity[2]);
+            self.update.setUint(c_str!("count"), self.count as u32);
+        }
+        self.buffers.bind(0, 1);
+        dispatch(group_count(self.count, PARTICLE_GROUP_SIZE), 1, 1);
+        self.buffers.swap();
+        // the draw reads the new state as vertex attributes
+        (Barriers::VERTEX_ATTRIB | Barriers::SHADER_STORAGE).wait();
+    }
+
+    /// Draws with additive blending, without depth writes
+    pub fn draw(&self, state: &mut GlState, view_projection: &Matrix4) {
+        state.use_program(self.draw.ID);
+        state.bind_vertex_array(self.vao);
+        state.set_blend(true);
+        state.blend_func(gl::SRC_ALPHA, gl::ONE);
+        state.depth_mask(false);
+        unsafe {
+            self.draw.setMat4(c_str!("viewProjection"), view_projection);
+            self.draw.setFloat(c_str!("pointSize"), self.point_size);
+            gl::Enable(gl::PROGRAM_POINT_SIZE);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffers.read().buffer);
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, mem::size_of::<GpuParticle>() as GLsizei, ptr::null());
+            gl::DrawArrays(gl::POINTS, 0, self.count as GLsizei);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        state.depth_mask(true);
+    }
+
+    pub fn delete(&mut self) {
+        self.buffers.delete();
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.update.ID);
+            gl::DeleteProgram(self.draw.ID);
+        }
+    }
+}
+
+impl GpuParticle {
+    pub fn new(position: [Float; 3], velocity: [Float; 3], life: Float) -> GpuParticle {
+        GpuParticle {
+            position: [gl_float(position[0]), gl_float(position[1]), gl_float(position[2]), gl_float(life)],
+            velocity: [gl_float(velocity[0]), gl_float(velocity[1]), gl_float(velocity[2]), 0.0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_gl_layouts() {
+        assert_eq!(mem::size_of::<DispatchIndirectCommand>(), 12);
+        // two std430 vec4s
+        assert_eq!(mem::size_of::<GpuParticle>(), 32);
+        assert_eq!(group_count(257, 256), 2);
+        assert_eq!((Barriers::COMMAND | Barriers::SHADER_STORAGE).0,
+                   gl::COMMAND_BARRIER_BIT | gl::SHADER_STORAGE_BARRIER_BIT);
+    }
+}
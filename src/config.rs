@@ -0,0 +1,268 @@
+//! `EngineConfig`: settings loaded from a TOML file, overridable from the environment
+//! (`REACTOR_WINDOW__WIDTH=1280`, `__` separating nested keys) and at runtime with `set`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glfw::Key;
+use log::Level;
+use num_traits::FromPrimitive;
+use serde::{Serialize, Deserialize};
+use toml;
+
+use error::{EngineError, EngineResult};
+use logging;
+
+/// Prefix of the environment variables read by `apply_env`
+pub const ENV_PREFIX: &str = "REACTOR_";
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub vsync: bool,
+    /// MSAA samples of the default framebuffer, 0 disables it
+    pub msaa: u32,
+    /// sRGB default framebuffer: shaders output linear colors, encoded (and blended) by GL
+    pub srgb: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> WindowConfig {
+        WindowConfig {
+            title: "reactor".to_string(),
+            width: 800,
+            height: 600,
+            vsync: true,
+            msaa: 4,
+            srgb: true,
+        }
+    }
+}
+
+/// Minification filtering of mipmapped textures
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    #[default]
+    Trilinear,
+}
+
+/// How a texture's mip chain is built from its base level, see `texture::mipmap`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MipFilter {
+    /// `glGenerateMipmap`, a box filter on most drivers
+    #[default]
+    Gl,
+    /// 2x2 average on the CPU, in linear space for sRGB textures
+    Box,
+    /// Kaiser-windowed sinc, sharper than a box and with less shimmering
+    Kaiser,
+    /// box filtered normals renormalized at each level, for tangent space normal maps
+    NormalMap,
+}
+
+/// Sampling defaults of every texture, see `texture::set_quality`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextureConfig {
+    pub filter: TextureFilter,
+    /// max anisotropic samples, 1 disables it, clamped to what the driver supports
+    pub anisotropy: f32,
+    /// added to the mip level, positive values blur and negative sharpen
+    pub mip_bias: f32,
+    /// how `Texture::new` builds mipmaps, applies to textures created afterwards
+    pub mip_filter: MipFilter,
+}
+
+impl Default for TextureConfig {
+    fn default() -> TextureConfig {
+        TextureConfig {
+            filter: TextureFilter::Trilinear,
+            anisotropy: 8.0,
+            mip_bias: 0.0,
+            mip_filter: MipFilter::Gl,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    pub window: WindowConfig,
+    pub textures: TextureConfig,
+    /// action name to glfw key name, e.g. `forward = "W"`
+    pub key_bindings: BTreeMap<String, String>,
+    pub asset_root: PathBuf,
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub log_level: String,
+}
+
+impl Default for EngineConfig {
+    fn default() -> EngineConfig {
+        EngineConfig {
+            window: WindowConfig::default(),
+            textures: TextureConfig::default(),
+            key_bindings: BTreeMap::new(),
+            asset_root: PathBuf::from("resources"),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Reads the file, missing keys take their default value
+    pub fn load<P: AsRef<Path>>(path: P) -> EngineResult<EngineConfig> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| EngineError::asset_io(path, error))?;
+        EngineConfig::from_toml(&text)
+    }
+
+    /// `load` when the file exists, the defaults otherwise, then the environment overrides
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> EngineResult<EngineConfig> {
+        let mut config = if path.as_ref().exists() {
+            EngineConfig::load(path)?
+        } else {
+            EngineConfig::default()
+        };
+        config.apply_env(env::vars())?;
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> EngineResult<EngineConfig> {
+        toml::from_str(text).map_err(|error| EngineError::Config(error.to_string()))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("EngineConfig is always representable as TOML")
+    }
+
+    /// Writes the current settings, e.g. after changing them in an options menu
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> EngineResult<()> {
+        let path = path.as_ref();
+        fs::write(path, self.to_toml()).map_err(|error| EngineError::asset_io(path, error))
+    }
+
+    /// Sets a value by its dotted key (`window.width`, `key_bindings.jump`). The value is parsed
+    /// as TOML, falling back to a plain string.
+    pub fn set(&mut self, key: &str, value: &str) -> EngineResult<()> {
+        let path: Vec<&str> = key.split('.').collect();
+        self.set_path(&path, value)
+    }
+
+    /// Applies the `REACTOR_`-prefixed variables among `vars`, pass `std::env::vars()`.
+    /// Variables not naming a setting or section, like `REACTOR_UPDATE_GOLDEN`, are skipped;
+    /// bad values are errors.
+    pub fn apply_env<I: IntoIterator<Item = (String, String)>>(&mut self, vars: I) -> EngineResult<()> {
+        let root = toml::Value::try_from(&*self).map_err(|error| EngineError::Config(error.to_string()))?;
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                let key = key.to_lowercase();
+                let path: Vec<&str> = key.split("__").collect();
+                if root.get(path[0]).is_none() {
+                    engine_debug!(logging::RESOURCES, "{} is not a setting, ignored", name);
+                    continue;
+                }
+                self.set_path(&path, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` if `log_level` isn't a level name
+    pub fn log_level(&self) -> Option<Level> {
+        Level::from_str(&self.log_level).ok()
+    }
+
+    /// The key bound to an action, `None` if unbound or the name isn't a glfw key
+    pub fn key_binding(&self, action: &str) -> Option<Key> {
+        self.key_bindings.get(action).and_then(|name| key_from_name(name))
+    }
+
+    fn set_path(&mut self, path: &[&str], value: &str) -> EngineResult<()> {
+        let mut root = toml::Value::try_from(&*self).map_err(|error| EngineError::Config(error.to_string()))?;
+        let (last, parents) = path.split_last().ok_or_else(|| EngineError::Config("empty key".to_string()))?;
+
+        let mut table = root.as_table_mut().expect("EngineConfig serializes to a table");
+        for parent in parents {
+            table = match table.get_mut(*parent).and_then(|value| value.as_table_mut()) {
+                Some(table) => table,
+                None => return Err(EngineError::Config(format!("unknown setting {}", path.join(".")))),
+            };
+        }
+        // maps like key_bindings accept new keys, structs only their fields
+        if parents.is_empty() && !table.contains_key(*last) {
+            return Err(EngineError::Config(format!("unknown setting {}", path.join("."))));
+        }
+        let parsed = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| toml::Value::String(value.to_string()));
+        table.insert(last.to_string(), parsed);
+
+        *self = root.try_into().map_err(|error: toml::de::Error| {
+            EngineError::Config(format!("invalid value for {}: {}", path.join("."), error))
+        })?;
+        Ok(())
+    }
+}
+
+/// Parses glfw key names as printed by `{:?}`: `W`, `Space`, `Escape`, `F1`, `Num0`...
+pub fn key_from_name(name: &str) -> Option<Key> {
+    (-1..=348).filter_map(Key::from_i32).find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_and_overrides() {
+        let mut config = EngineConfig::from_toml("log_level = \"debug\"\n[window]\nwidth = 1280\n\n[key_bindings]\nforward = \"W\"\n").unwrap();
+        assert_eq!(config.window.width, 1280);
+        assert_eq!(config.window.height, 600);
+        assert_eq!(config.log_level(), Some(Level::Debug));
+        assert_eq!(config.key_binding("forward"), Some(Key::W));
+
+        let vars = vec![
+            ("REACTOR_WINDOW__VSYNC".to_string(), "false".to_string()),
+            ("REACTOR_KEY_BINDINGS__JUMP".to_string(), "Space".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        config.apply_env(vars).unwrap();
+        assert!(!config.window.vsync);
+        assert_eq!(config.key_binding("jump"), Some(Key::Space));
+
+        config.set("window.title", "Demo").unwrap();
+        config.set("textures.filter", "bilinear").unwrap();
+        assert_eq!(config.textures.filter, TextureFilter::Bilinear);
+        assert_eq!(config.window.title, "Demo");
+        assert!(config.set("window.width", "wide").is_err());
+        assert!(config.set("nothing", "1").is_err());
+        assert_eq!(EngineConfig::from_toml(&config.to_toml()).unwrap(), config);
+    }
+
+    #[test]
+    fn skips_unrelated_environment_variables() {
+        let mut config = EngineConfig::default();
+        let vars = vec![
+            ("REACTOR_UPDATE_GOLDEN".to_string(), "1".to_string()),
+            ("REACTOR_FOO".to_string(), "bar".to_string()),
+            ("REACTOR_WINDOW__WIDTH".to_string(), "1024".to_string()),
+        ];
+        config.apply_env(vars).unwrap();
+        assert_eq!(config.window.width, 1024);
+        assert_eq!(EngineConfig { window: config.window.clone(), ..EngineConfig::default() }, config);
+
+        let bad = vec![("REACTOR_WINDOW__WIDTH".to_string(), "wide".to_string())];
+        assert!(config.apply_env(bad).is_err());
+    }
+}
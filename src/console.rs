@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use glfw::{Key, Action};
+
+use input::{KeyEvent, MouseEvent, InputControl};
+use lang::{Float, TimeSec};
+use logging;
+use window::InputState;
+
+/// result text of a command, or the error to print
+pub type CommandResult = Result<String, String>;
+pub type CommandFn = Box<dyn FnMut(&[&str]) -> CommandResult + Send>;
+
+const MAX_LINES: usize = 200;
+const MAX_HISTORY: usize = 50;
+
+/// Value of a console variable, the type is fixed by `register_var`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleValue {
+    Bool(bool),
+    Int(i64),
+    Float(Float),
+    Str(String),
+}
+
+impl ConsoleValue {
+    /// Parses `text` as a value of the same type as `self`
+    fn parse_like(&self, text: &str) -> Result<ConsoleValue, String> {
+        match *self {
+            ConsoleValue::Bool(_) => match text {
+                "1" | "true" | "on" => Ok(ConsoleValue::Bool(true)),
+                "0" | "false" | "off" => Ok(ConsoleValue::Bool(false)),
+                _ => Err(format!("expected a bool, got \"{}\"", text)),
+            },
+            ConsoleValue::Int(_) => text.parse().map(ConsoleValue::Int)
+                .map_err(|_| format!("expected an integer, got \"{}\"", text)),
+            ConsoleValue::Float(_) => text.parse().map(ConsoleValue::Float)
+                .map_err(|_| format!("expected a number, got \"{}\"", text)),
+            ConsoleValue::Str(_) => Ok(ConsoleValue::Str(text.to_string())),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self { ConsoleValue::Bool(value) => Some(value), _ => None }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self { ConsoleValue::Int(value) => Some(value), _ => None }
+    }
+
+    pub fn as_float(&self) -> Option<Float> {
+        match *self { ConsoleValue::Float(value) => Some(value), _ => None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { ConsoleValue::Str(ref value) => Some(value), _ => None }
+    }
+}
+
+impl fmt::Display for ConsoleValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConsoleValue::Bool(value) => write!(f, "{}", if value { 1 } else { 0 }),
+            ConsoleValue::Int(value) => write!(f, "{}", value),
+            ConsoleValue::Float(value) => write!(f, "{}", value),
+            ConsoleValue::Str(ref value) => write!(f, "\"{}\"", value),
+        }
+    }
+}
+
+struct Command {
+    help: String,
+    run: CommandFn,
+}
+
+struct Variable {
+    help: String,
+    value: ConsoleValue,
+}
+
+/// In-engine developer console. Add it to `Window::controls` to toggle it with the toggle key
+/// (`~` by default) and type into it. Typing a variable name prints it, a name followed by a
+/// value sets it.
+///
+/// The console only reads the input it is given, the other controls still get the keys typed
+/// into it and Escape still closes the window. Keep gameplay controls in an `InputContext`
+/// and suspend it, or push a blocking one above it, while `is_visible`.
+///
+/// There is no text rendering in the engine, the application draws `lines` and `prompt`.
+pub struct Console {
+    pub toggle_key: Key,
+    visible: bool,
+    /// set by a press of the toggle key, so the character it types isn't entered
+    skip_char: bool,
+    input: String,
+    history: VecDeque<String>,
+    history_cursor: Option<usize>,
+    lines: VecDeque<String>,
+    commands: BTreeMap<String, Command>,
+    variables: BTreeMap<String, Variable>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            toggle_key: Key::GraveAccent,
+            visible: false,
+            skip_char: false,
+            input: String::new(),
+            history: VecDeque::new(),
+            history_cursor: None,
+            lines: VecDeque::new(),
+            commands: BTreeMap::new(),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// Registers a command, replacing one with the same name
+    pub fn register<F>(&mut self, name: &str, help: &str, run: F)
+        where F: FnMut(&[&str]) -> CommandResult + Send + 'static
+    {
+        self.commands.insert(name.to_string(), Command { help: help.to_string(), run: Box::new(run) });
+    }
+
+    /// Registers a variable with its default value, keeps the value if it already exists with the same type
+    pub fn register_var(&mut self, name: &str, help: &str, value: ConsoleValue) {
+        let keep = match self.variables.get(name) {
+            Some(variable) => ::std::mem::discriminant(&variable.value) == ::std::mem::discriminant(&value),
+            None => false,
+        };
+        if keep {
+            self.variables.get_mut(name).unwrap().help = help.to_string();
+        } else {
+            self.variables.insert(name.to_string(), Variable { help: help.to_string(), value });
+        }
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&ConsoleValue> {
+        self.variables.get(name).map(|variable| &variable.value)
+    }
+
+    /// Sets a registered variable from text, parsed as the registered type
+    pub fn set_var(&mut self, name: &str, text: &str) -> Result<(), String> {
+        match self.variables.get_mut(name) {
+            Some(variable) => {
+                variable.value = variable.value.parse_like(text)?;
+                Ok(())
+            },
+            None => Err(format!("unknown variable \"{}\"", name)),
+        }
+    }
+
+    /// Runs a command line and prints the echo and result to the output
+    pub fn execute(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        self.print(format!("> {}", line));
+        if self.history.back().map(|last| last != line).unwrap_or(true) {
+            self.history.push_back(line.to_string());
+            if self.history.len() > MAX_HISTORY {
+                self.history.pop_front();
+            }
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = (words[0], &words[1..]);
+        engine_debug!(logging::INPUT, "console: {}", line);
+        match self.run(name, args) {
+            Ok(ref output) if output.is_empty() => (),
+            Ok(output) => self.print(output),
+            Err(error) => self.print(format!("error: {}", error)),
+        }
+    }
+
+    fn run(&mut self, name: &str, args: &[&str]) -> CommandResult {
+        if name == "help" {
+            return Ok(self.help());
+        }
+        if let Some(command) = self.commands.get_mut(name) {
+            return (command.run)(args);
+        }
+        if self.variables.contains_key(name) {
+            if !args.is_empty() {
+                self.set_var(name, &args.join(" "))?;
+            }
+            return Ok(format!("{} = {}", name, self.variables[name].value));
+        }
+        Err(format!("unknown command \"{}\", try \"help\"", name))
+    }
+
+    fn help(&self) -> String {
+        let commands = self.commands.iter()
+            .map(|(name, command)| format!("{} - {}", name, command.help));
+        let variables = self.variables.iter()
+            .map(|(name, variable)| format!("{} = {} - {}", name, variable.value, variable.help));
+        commands.chain(variables).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Adds text to the output, one line per line of text
+    pub fn print<S: AsRef<str>>(&mut self, text: S) {
+        for line in text.as_ref().lines() {
+            self.lines.push_back(line.to_string());
+        }
+        while self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    /// Output lines, oldest first
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| line.as_str())
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// The line being typed
+    pub fn prompt(&self) -> &str {
+        &self.input
+    }
+
+    fn recall(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        let last = self.history.len() - 1;
+        self.history_cursor = match (self.history_cursor, older) {
+            (None, true) => Some(last),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index < last => Some(index + 1),
+            (Some(_), false) => None,
+        };
+        self.input = match self.history_cursor {
+            Some(index) => self.history[index].clone(),
+            None => String::new(),
+        };
+    }
+}
+
+impl Default for Console {
+    fn default() -> Console {
+        Console::new()
+    }
+}
+
+impl InputControl for Console {
+    fn on_mouse(&mut self, _mouse: MouseEvent, _delta_time: TimeSec) {}
+
+    fn on_keyboard(&mut self, key: KeyEvent, _delta_time: TimeSec) {
+        let KeyEvent(key, _, action, _) = key;
+        // a character follows its key press before the next key event, if the key types one
+        self.skip_char = false;
+        if action == Action::Release {
+            return;
+        }
+        if key == self.toggle_key {
+            if action == Action::Press {
+                self.visible = !self.visible;
+            }
+            self.skip_char = true;
+            return;
+        }
+        if !self.visible {
+            return;
+        }
+        match key {
+            Key::Enter | Key::KpEnter => {
+                let line = ::std::mem::take(&mut self.input);
+                self.history_cursor = None;
+                self.execute(&line);
+            },
+            Key::Backspace => {
+                self.input.pop();
+            },
+            Key::Up => self.recall(true),
+            Key::Down => self.recall(false),
+            _ => (),
+        }
+    }
+
+    fn on_input(&mut self, _window: &dyn InputState, _delta_time: TimeSec) {}
+
+    fn on_char(&mut self, character: char, _delta_time: TimeSec) {
+        if ::std::mem::take(&mut self.skip_char) {
+            return;
+        }
+        if self.visible && !character.is_control() {
+            self.input.push(character);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_commands_and_sets_variables() {
+        let mut console = Console::new();
+        console.register("echo", "prints its arguments", |args| Ok(args.join(" ")));
+        console.register_var("r_wireframe", "draw edges only", ConsoleValue::Bool(false));
+
+        console.execute("echo hello there");
+        console.execute("r_wireframe 1");
+        console.execute("r_wireframe maybe");
+        console.execute("nope");
+
+        assert_eq!(console.get_var("r_wireframe"), Some(&ConsoleValue::Bool(true)));
+        let lines: Vec<&str> = console.lines().collect();
+        assert_eq!(lines[1], "hello there");
+        assert_eq!(lines[3], "r_wireframe = 1");
+        assert!(lines[5].starts_with("error: expected a bool"));
+        assert!(lines[7].starts_with("error: unknown command"));
+
+        console.recall(true);
+        assert_eq!(console.prompt(), "nope");
+        console.recall(true);
+        assert_eq!(console.prompt(), "r_wireframe maybe");
+    }
+
+    #[test]
+    fn only_the_toggle_press_is_not_typed() {
+        let press = |key| KeyEvent(key, 0, Action::Press, ::glfw::Modifiers::empty());
+        let mut console = Console::new();
+        console.toggle_key = Key::F1;
+        console.on_keyboard(press(Key::F1), 0.0);
+        assert!(console.is_visible());
+        // F1 types nothing, the next character isn't lost
+        console.on_keyboard(press(Key::A), 0.0);
+        console.on_char('a', 0.0);
+        console.on_keyboard(press(Key::GraveAccent), 0.0);
+        console.on_char('`', 0.0);
+        console.on_char('~', 0.0);
+        assert_eq!(console.prompt(), "a`~");
+
+        console.toggle_key = Key::GraveAccent;
+        console.on_keyboard(press(Key::GraveAccent), 0.0);
+        console.on_char('`', 0.0);
+        assert!(!console.is_visible());
+        console.on_keyboard(press(Key::GraveAccent), 0.0);
+        console.on_char('`', 0.0);
+        assert!(console.is_visible());
+        assert_eq!(console.prompt(), "a`~");
+    }
+}
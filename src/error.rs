@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use glfw;
+
+/// Failures of the engine's fallible constructors and loaders
+#[derive(Debug)]
+pub enum EngineError {
+    GlfwInit(glfw::InitError),
+    WindowCreation(String),
+    /// `stage` is "VERTEX", "FRAGMENT" or "GEOMETRY", `log` the driver's info log
+    ShaderCompile { stage: &'static str, log: String },
+    ShaderLink { log: String },
+    /// source text that can't be passed to GL (it contains a nul byte)
+    InvalidSource(String),
+    AssetIo { path: PathBuf, source: io::Error },
+    /// invalid configuration file or value
+    Config(String),
+    /// passes that can't be scheduled, e.g. a dependency cycle
+    FrameGraph(String),
+}
+
+pub type EngineResult<T> = Result<T, EngineError>;
+
+impl EngineError {
+    pub fn asset_io<P: Into<PathBuf>>(path: P, source: io::Error) -> EngineError {
+        EngineError::AssetIo { path: path.into(), source }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EngineError::GlfwInit(ref error) => write!(f, "Failed to initialize GLFW: {:?}", error),
+            EngineError::WindowCreation(ref reason) => write!(f, "Failed to create window: {}", reason),
+            EngineError::ShaderCompile { stage, ref log } => write!(f, "Failed to compile {} shader:\n{}", stage, log),
+            EngineError::ShaderLink { ref log } => write!(f, "Failed to link shader program:\n{}", log),
+            EngineError::InvalidSource(ref what) => write!(f, "Invalid source: {}", what),
+            EngineError::AssetIo { ref path, ref source } => write!(f, "Failed to access {}: {}", path.display(), source),
+            EngineError::Config(ref reason) => write!(f, "Invalid configuration: {}", reason),
+            EngineError::FrameGraph(ref reason) => write!(f, "Invalid frame graph: {}", reason),
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            EngineError::AssetIo { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<glfw::InitError> for EngineError {
+    fn from(error: glfw::InitError) -> EngineError {
+        EngineError::GlfwInit(error)
+    }
+}
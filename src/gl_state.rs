@@ -0,0 +1,317 @@
+use gl;
+use gl::types::*;
+
+use lang::{Float, gl_float};
+use color::Color;
+
+/// Texture units tracked by the cache, binding a higher unit always issues the call
+pub const TRACKED_TEXTURE_UNITS: usize = 16;
+
+/// Shadow copy of the GL state the engine changes, skipping the `gl*` call when a value is
+/// already set. `None` means unknown: call `invalidate` after issuing raw GL state changes
+/// so the next setter goes through.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct GlState {
+    program: Option<u32>,
+    vertex_array: Option<u32>,
+    active_texture_unit: Option<u32>,
+    textures: [Option<(GLenum, u32)>; TRACKED_TEXTURE_UNITS],
+    samplers: [Option<u32>; TRACKED_TEXTURE_UNITS],
+    blend: Option<bool>,
+    /// source and destination factors of color, then of alpha
+    blend_func: Option<(GLenum, GLenum, GLenum, GLenum)>,
+    depth_test: Option<bool>,
+    depth_mask: Option<bool>,
+    depth_func: Option<GLenum>,
+    cull_face: Option<bool>,
+    cull_mode: Option<GLenum>,
+    color_mask: Option<bool>,
+    polygon_mode: Option<GLenum>,
+    /// calls issued / skipped since the last `reset_counters`
+    pub issued: usize,
+    pub skipped: usize,
+}
+
+impl GlState {
+    pub fn new() -> GlState {
+        GlState::default()
+    }
+
+    /// Forgets every cached value, counters are kept
+    pub fn invalidate(&mut self) {
+        *self = GlState {
+            issued: self.issued,
+            skipped: self.skipped,
+            ..GlState::default()
+        };
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.issued = 0;
+        self.skipped = 0;
+    }
+
+    /// Each setter returns whether the GL call was issued
+    pub fn use_program(&mut self, program: u32) -> bool {
+        let changed = self.track(|s| &mut s.program, program);
+        if changed {
+            unsafe { gl::UseProgram(program) }
+        }
+        changed
+    }
+
+    pub fn bind_vertex_array(&mut self, vertex_array: u32) -> bool {
+        let changed = self.track(|s| &mut s.vertex_array, vertex_array);
+        if changed {
+            unsafe { gl::BindVertexArray(vertex_array) }
+        }
+        changed
+    }
+
+    /// Binds a `TEXTURE_2D` on the texture unit (0 based)
+    pub fn bind_texture(&mut self, unit: u32, texture: u32) -> bool {
+        self.bind_texture_target(unit, gl::TEXTURE_2D, texture)
+    }
+
+    /// Binds a texture of any target (`TEXTURE_2D_ARRAY`, `TEXTURE_CUBE_MAP`..) on the texture unit
+    pub fn bind_texture_target(&mut self, unit: u32, target: GLenum, texture: u32) -> bool {
+        if unit as usize >= TRACKED_TEXTURE_UNITS {
+            self.active_texture_unit = Some(unit);
+            self.issued += 1;
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+                gl::BindTexture(target, texture);
+            }
+            return true;
+        }
+
+        let changed = self.track(|s| &mut s.textures[unit as usize], (target, texture));
+        if changed {
+            if self.active_texture_unit != Some(unit) {
+                self.active_texture_unit = Some(unit);
+                unsafe { gl::ActiveTexture(gl::TEXTURE0 + unit) }
+            }
+            unsafe { gl::BindTexture(target, texture) }
+        }
+        changed
+    }
+
+    /// Binds a sampler object on the texture unit, 0 samples with the texture's own parameters
+    pub fn bind_sampler(&mut self, unit: u32, sampler: u32) -> bool {
+        let changed = if unit as usize >= TRACKED_TEXTURE_UNITS {
+            self.issued += 1;
+            true
+        } else {
+            self.track(|s| &mut s.samplers[unit as usize], sampler)
+        };
+        if changed {
+            unsafe { gl::BindSampler(unit, sampler) }
+        }
+        changed
+    }
+
+    pub fn set_blend(&mut self, enabled: bool) -> bool {
+        let changed = self.track(|s| &mut s.blend, enabled);
+        if changed {
+            set_capability(gl::BLEND, enabled);
+        }
+        changed
+    }
+
+    pub fn blend_func(&mut self, src: GLenum, dst: GLenum) -> bool {
+        let changed = self.track(|s| &mut s.blend_func, (src, dst, src, dst));
+        if changed {
+            unsafe { gl::BlendFunc(src, dst) }
+        }
+        changed
+    }
+
+    /// Different factors for the alpha channel
+    pub fn blend_func_separate(&mut self, src_rgb: GLenum, dst_rgb: GLenum, src_alpha: GLenum, dst_alpha: GLenum) -> bool {
+        let changed = self.track(|s| &mut s.blend_func, (src_rgb, dst_rgb, src_alpha, dst_alpha));
+        if changed {
+            unsafe { gl::BlendFuncSeparate(src_rgb, dst_rgb, src_alpha, dst_alpha) }
+        }
+        changed
+    }
+
+    pub fn set_depth_test(&mut self, enabled: bool) -> bool {
+        let changed = self.track(|s| &mut s.depth_test, enabled);
+        if changed {
+            set_capability(gl::DEPTH_TEST, enabled);
+        }
+        changed
+    }
+
+    pub fn depth_mask(&mut self, write: bool) -> bool {
+        let changed = self.track(|s| &mut s.depth_mask, write);
+        if changed {
+            unsafe { gl::DepthMask(if write { gl::TRUE } else { gl::FALSE }) }
+        }
+        changed
+    }
+
+    pub fn depth_func(&mut self, func: GLenum) -> bool {
+        let changed = self.track(|s| &mut s.depth_func, func);
+        if changed {
+            unsafe { gl::DepthFunc(func) }
+        }
+        changed
+    }
+
+    pub fn set_cull_face(&mut self, enabled: bool) -> bool {
+        let changed = self.track(|s| &mut s.cull_face, enabled);
+        if changed {
+            set_capability(gl::CULL_FACE, enabled);
+        }
+        changed
+    }
+
+    pub fn cull_mode(&mut self, mode: GLenum) -> bool {
+        let changed = self.track(|s| &mut s.cull_mode, mode);
+        if changed {
+            unsafe { gl::CullFace(mode) }
+        }
+        changed
+    }
+
+    /// Enables or disables writes to all color channels
+    pub fn color_mask(&mut self, write: bool) -> bool {
+        let changed = self.track(|s| &mut s.color_mask, write);
+        if changed {
+            let value = if write { gl::TRUE } else { gl::FALSE };
+            unsafe { gl::ColorMask(value, value, value, value) }
+        }
+        changed
+    }
+
+    /// `FILL`, `LINE` or `POINT` for both faces
+    pub fn polygon_mode(&mut self, mode: GLenum) -> bool {
+        let changed = self.track(|s| &mut s.polygon_mode, mode);
+        if changed {
+            unsafe { gl::PolygonMode(gl::FRONT_AND_BACK, mode) }
+        }
+        changed
+    }
+
+    fn track<T: PartialEq, F: FnOnce(&mut GlState) -> &mut Option<T>>(&mut self, slot: F, value: T) -> bool {
+        let changed = {
+            let slot = slot(self);
+            let changed = slot.as_ref() != Some(&value);
+            *slot = Some(value);
+            changed
+        };
+        if changed {
+            self.issued += 1;
+        } else {
+            self.skipped += 1;
+        }
+        changed
+    }
+}
+
+/// Buffers cleared at the start of a pass and the values they are cleared to, `None` keeps the buffer
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ClearSpec {
+    pub color: Option<[Float; 4]>,
+    pub depth: Option<f64>,
+    pub stencil: Option<i32>,
+}
+
+impl ClearSpec {
+    /// Clears nothing
+    pub fn none() -> ClearSpec {
+        ClearSpec { color: None, depth: None, stencil: None }
+    }
+
+    pub fn color(mut self, r: Float, g: Float, b: Float, a: Float) -> ClearSpec {
+        self.color = Some([r, g, b, a]);
+        self
+    }
+
+    pub fn clear_color(mut self, color: Color) -> ClearSpec {
+        self.color = Some(color.to_array());
+        self
+    }
+
+    pub fn depth(mut self, depth: f64) -> ClearSpec {
+        self.depth = Some(depth);
+        self
+    }
+
+    pub fn stencil(mut self, stencil: i32) -> ClearSpec {
+        self.stencil = Some(stencil);
+        self
+    }
+
+    /// Clears the bound framebuffer, enabling the color / depth writes `glClear` is masked by
+    pub fn apply(&self, state: &mut GlState) {
+        let mut mask = 0;
+        unsafe {
+            if let Some([r, g, b, a]) = self.color {
+                state.color_mask(true);
+                gl::ClearColor(gl_float(r), gl_float(g), gl_float(b), gl_float(a));
+                mask |= gl::COLOR_BUFFER_BIT;
+            }
+            if let Some(depth) = self.depth {
+                state.depth_mask(true);
+                gl::ClearDepth(depth);
+                mask |= gl::DEPTH_BUFFER_BIT;
+            }
+            if let Some(stencil) = self.stencil {
+                gl::StencilMask(!0);
+                gl::ClearStencil(stencil);
+                mask |= gl::STENCIL_BUFFER_BIT;
+            }
+            if mask != 0 {
+                gl::Clear(mask);
+            }
+        }
+    }
+}
+
+/// The engine's long-standing clear: color `(0.2, 0.3, 0.3)` and depth 1
+impl Default for ClearSpec {
+    fn default() -> ClearSpec {
+        ClearSpec::none().clear_color(Color::srgb(0.2, 0.3, 0.3, 1.0)).depth(1.0)
+    }
+}
+
+/// Depth testing configuration of a pass
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DepthState {
+    pub test: bool,
+    pub write: bool,
+    pub func: GLenum,
+}
+
+impl DepthState {
+    pub fn disabled() -> DepthState {
+        DepthState { test: false, write: false, func: gl::ALWAYS }
+    }
+
+    pub fn apply(&self, state: &mut GlState) {
+        state.set_depth_test(self.test);
+        state.depth_mask(self.write);
+        if self.test {
+            state.depth_func(self.func);
+        }
+    }
+}
+
+/// Test and write with `LESS`
+impl Default for DepthState {
+    fn default() -> DepthState {
+        DepthState { test: true, write: true, func: gl::LESS }
+    }
+}
+
+fn set_capability(capability: GLenum, enabled: bool) {
+    unsafe {
+        if enabled {
+            gl::Enable(capability);
+        } else {
+            gl::Disable(capability);
+        }
+    }
+}
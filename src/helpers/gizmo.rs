@@ -0,0 +1,357 @@
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Vector3, Quaternion, PI, rad};
+use lines::{LineBatch, LineStyle};
+use picking::{NodeId, PickShape};
+use ray::Ray;
+
+/// Handle length in world units per unit of distance from the camera, keeping the
+/// gizmo about the same size on screen
+const SCREEN_SCALE: Float = 0.15;
+/// Segments of a rotation ring
+const RING_SEGMENTS: usize = 48;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub fn all() -> [Axis; 3] {
+        [Axis::X, Axis::Y, Axis::Z]
+    }
+
+    pub fn direction(self) -> Vector3 {
+        match self {
+            Axis::X => Vector3::unit_x(),
+            Axis::Y => Vector3::unit_y(),
+            Axis::Z => Vector3::unit_z(),
+        }
+    }
+
+    fn color(self) -> [Float; 4] {
+        match self {
+            Axis::X => [0.9, 0.2, 0.2, 1.0],
+            Axis::Y => [0.2, 0.8, 0.2, 1.0],
+            Axis::Z => [0.2, 0.3, 0.9, 1.0],
+        }
+    }
+}
+
+/// Change of the selected node's transform since the drag started, to be applied on
+/// top of the transform it had then
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TransformEdit {
+    pub node: NodeId,
+    pub translation: Vector3,
+    pub rotation: Quaternion,
+    pub scale: Vector3,
+}
+
+impl TransformEdit {
+    fn identity(node: NodeId) -> TransformEdit {
+        TransformEdit {
+            node,
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Drag {
+    axis: Axis,
+    /// handle parameter where the drag started: distance along the axis, or the
+    /// starting direction in the ring's plane
+    start_along: Float,
+    start_direction: Vector3,
+    edit: TransformEdit,
+}
+
+/// Translate / rotate / scale manipulator for the selected node, working in world axes.
+///
+/// Picking selects the node (`select`), the gizmo then tests the mouse ray against its own
+/// handles: call `hover` on mouse move, `begin_drag` on press, `drag` while held to get the
+/// edit to preview, and `end_drag` on release to get the edit to commit.
+#[derive(Clone, Debug)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    /// handle size factor, 1 is about a seventh of the view height
+    pub size: Float,
+    selected: Option<(NodeId, Point3)>,
+    hovered: Option<Axis>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn new(mode: GizmoMode) -> Gizmo {
+        Gizmo { mode, size: 1.0, selected: None, hovered: None, drag: None }
+    }
+
+    /// Attaches the gizmo to a node at its world position
+    pub fn select(&mut self, node: NodeId, position: Point3) {
+        self.selected = Some((node, position));
+        self.drag = None;
+    }
+
+    pub fn deselect(&mut self) {
+        self.selected = None;
+        self.hovered = None;
+        self.drag = None;
+    }
+
+    pub fn selected(&self) -> Option<NodeId> {
+        self.selected.map(|(node, _)| node)
+    }
+
+    pub fn hovered(&self) -> Option<Axis> {
+        self.hovered
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    fn handle_length(&self, eye: Point3) -> Float {
+        match self.selected {
+            Some((_, position)) => (position - eye).magnitude() * SCREEN_SCALE * self.size,
+            None => 0.0,
+        }
+    }
+
+    /// World-space shapes of the handles, for ray tests with the picking code
+    pub fn pick_shapes(&self, eye: Point3) -> Vec<(Axis, PickShape)> {
+        let position = match self.selected {
+            Some((_, position)) => position,
+            None => return vec![],
+        };
+        let length = self.handle_length(eye);
+        let thickness = length * 0.06;
+        Axis::all().iter().map(|&axis| {
+            let shape = match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    let end = position + axis.direction() * length;
+                    let pad = Vector3::new(thickness, thickness, thickness);
+                    PickShape::Aabb {
+                        min: Point3::new(position.x.min(end.x), position.y.min(end.y), position.z.min(end.z)) - pad,
+                        max: Point3::new(position.x.max(end.x), position.y.max(end.y), position.z.max(end.z)) + pad,
+                    }
+                },
+                GizmoMode::Rotate => PickShape::Triangles(ring_triangles(position, axis, length, thickness)),
+            };
+            (axis, shape)
+        }).collect()
+    }
+
+    /// Updates and returns the handle under the ray, the nearest if several are
+    pub fn hover(&mut self, ray: &Ray) -> Option<Axis> {
+        if self.drag.is_none() {
+            self.hovered = self.pick_shapes(ray.origin).into_iter()
+                .filter_map(|(axis, shape)| shape.intersect(ray).map(|t| (axis, t)))
+                .fold(None, |nearest: Option<(Axis, Float)>, (axis, t)| match nearest {
+                    Some((_, n)) if n <= t => nearest,
+                    _ => Some((axis, t)),
+                })
+                .map(|(axis, _)| axis);
+        }
+        self.hovered
+    }
+
+    /// Starts dragging the handle under the ray, returns false if there is none
+    pub fn begin_drag(&mut self, ray: &Ray) -> bool {
+        let (node, position) = match self.selected {
+            Some(selected) => selected,
+            None => return false,
+        };
+        let axis = match self.hover(ray) {
+            Some(axis) => axis,
+            None => return false,
+        };
+        let (start_along, start_direction) = match self.mode {
+            GizmoMode::Rotate => match ring_direction(position, axis, ray) {
+                Some(direction) => (0.0, direction),
+                None => return false,
+            },
+            _ => match closest_along_axis(position, axis, ray) {
+                Some(along) => (along, Vector3::zero()),
+                None => return false,
+            },
+        };
+        self.drag = Some(Drag { axis, start_along, start_direction, edit: TransformEdit::identity(node) });
+        true
+    }
+
+    /// Edit for the current ray, relative to the start of the drag
+    pub fn drag(&mut self, ray: &Ray) -> Option<TransformEdit> {
+        let position = self.selected?.1;
+        let mode = self.mode;
+        let drag = self.drag.as_mut()?;
+        let axis = drag.axis.direction();
+        match mode {
+            GizmoMode::Translate => {
+                if let Some(along) = closest_along_axis(position, drag.axis, ray) {
+                    drag.edit.translation = axis * (along - drag.start_along);
+                }
+            },
+            GizmoMode::Scale => {
+                if let Some(along) = closest_along_axis(position, drag.axis, ray) {
+                    if drag.start_along.abs() > 1e-6 {
+                        let factor = along / drag.start_along;
+                        drag.edit.scale = Vector3::new(1.0, 1.0, 1.0) + axis * (factor - 1.0);
+                    }
+                }
+            },
+            GizmoMode::Rotate => {
+                if let Some(direction) = ring_direction(position, drag.axis, ray) {
+                    let cos = drag.start_direction.dot(direction).clamp(-1.0, 1.0);
+                    let sign = drag.start_direction.cross(direction).dot(axis).signum();
+                    drag.edit.rotation = Quaternion::from_axis_angle(axis, rad(cos.acos() * sign));
+                }
+            },
+        }
+        Some(drag.edit)
+    }
+
+    /// Finishes the drag and returns the final edit. The gizmo moves with a translation.
+    pub fn end_drag(&mut self) -> Option<TransformEdit> {
+        let drag = self.drag.take()?;
+        if let Some((_, ref mut position)) = self.selected {
+            *position += drag.edit.translation;
+        }
+        Some(drag.edit)
+    }
+
+    /// Adds the handles to a line batch whose view matches the camera at `eye`
+    pub fn draw(&self, lines: &mut LineBatch, eye: Point3) {
+        let position = match self.selected {
+            Some((_, position)) => position + self.drag.map(|drag| drag.edit.translation).unwrap_or_else(Vector3::zero),
+            None => return,
+        };
+        let length = self.handle_length(eye);
+        for &axis in Axis::all().iter() {
+            let active = self.drag.map(|drag| drag.axis).or(self.hovered) == Some(axis);
+            let [r, g, b, a] = if active { [1.0, 0.9, 0.1, 1.0] } else { axis.color() };
+            let style = LineStyle::new(if active { 4.0 } else { 3.0 }).color(r, g, b, a);
+            match self.mode {
+                GizmoMode::Translate | GizmoMode::Scale => {
+                    let end = position + axis.direction() * length;
+                    lines.world_polyline(&[position, end], &style);
+                    if self.mode == GizmoMode::Scale {
+                        // a small cross at the end
+                        let (u, v) = ring_basis(axis);
+                        let tip = length * 0.06;
+                        lines.world_polyline(&[end - u * tip, end + u * tip], &style);
+                        lines.world_polyline(&[end - v * tip, end + v * tip], &style);
+                    }
+                },
+                GizmoMode::Rotate => {
+                    let (u, v) = ring_basis(axis);
+                    let ring: Vec<Point3> = (0..=RING_SEGMENTS).map(|i| {
+                        let angle = 2.0 * PI * i as Float / RING_SEGMENTS as Float;
+                        position + (u * angle.cos() + v * angle.sin()) * length
+                    }).collect();
+                    lines.world_polyline(&ring, &style);
+                },
+            }
+        }
+    }
+}
+
+impl Default for Gizmo {
+    fn default() -> Gizmo {
+        Gizmo::new(GizmoMode::Translate)
+    }
+}
+
+/// Two unit vectors spanning the plane perpendicular to the axis
+fn ring_basis(axis: Axis) -> (Vector3, Vector3) {
+    match axis {
+        Axis::X => (Vector3::unit_y(), Vector3::unit_z()),
+        Axis::Y => (Vector3::unit_z(), Vector3::unit_x()),
+        Axis::Z => (Vector3::unit_x(), Vector3::unit_y()),
+    }
+}
+
+/// Flat band around a rotation ring, for ray tests
+fn ring_triangles(center: Point3, axis: Axis, radius: Float, thickness: Float) -> Vec<[Point3; 3]> {
+    let (u, v) = ring_basis(axis);
+    let point = |i: usize, r: Float| {
+        let angle = 2.0 * PI * i as Float / RING_SEGMENTS as Float;
+        center + (u * angle.cos() + v * angle.sin()) * r
+    };
+    let (inner, outer) = (radius - thickness * 2.0, radius + thickness * 2.0);
+    (0..RING_SEGMENTS).flat_map(|i| {
+        let (a, b, c, d) = (point(i, inner), point(i, outer), point(i + 1, inner), point(i + 1, outer));
+        vec![[a, b, c], [c, b, d]]
+    }).collect()
+}
+
+/// Distance along the axis line through `origin` of the point closest to the ray
+fn closest_along_axis(origin: Point3, axis: Axis, ray: &Ray) -> Option<Float> {
+    let u = axis.direction();
+    let w = origin - ray.origin;
+    let b = u.dot(ray.direction);
+    let denominator = 1.0 - b * b;
+    // looking straight down the axis
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    let (d, e) = (u.dot(w), ray.direction.dot(w));
+    Some((b * e - d) / denominator)
+}
+
+/// Direction from the ring's center to where the ray hits the ring's plane
+fn ring_direction(center: Point3, axis: Axis, ray: &Ray) -> Option<Vector3> {
+    let normal = axis.direction();
+    let facing = ray.direction.dot(normal);
+    if facing.abs() < 1e-6 {
+        return None;
+    }
+    let t = (center - ray.origin).dot(normal) / facing;
+    let offset = ray.at(t) - center;
+    if offset.magnitude2() < 1e-12 {
+        None
+    } else {
+        Some(offset.normalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_along_the_dragged_axis() {
+        let mut gizmo = Gizmo::new(GizmoMode::Translate);
+        gizmo.select(7, Point3::origin());
+        // camera at z = 10 looking at the x handle's middle
+        let eye = Point3::new(0.0, 0.0, 10.0);
+        let handle = Point3::new(0.5, 0.0, 0.0);
+        assert!(gizmo.begin_drag(&Ray::new(eye, handle - eye)));
+        assert_eq!(gizmo.hovered(), Some(Axis::X));
+
+        let edit = gizmo.drag(&Ray::new(eye, Point3::new(2.5, 0.0, 0.0) - eye)).unwrap();
+        assert_eq!(edit.node, 7);
+        assert!((edit.translation - Vector3::new(2.0, 0.0, 0.0)).magnitude() < 1e-4);
+        gizmo.end_drag();
+        assert!(!gizmo.is_dragging());
+
+        gizmo.mode = GizmoMode::Rotate;
+        gizmo.select(7, Point3::origin());
+        let eye = Point3::new(0.0, 0.0, 10.0);
+        assert!(gizmo.begin_drag(&Ray::new(eye, Point3::new(1.5, 0.0, 0.0) - eye)));
+        let edit = gizmo.drag(&Ray::new(eye, Point3::new(0.0, 1.5, 0.0) - eye)).unwrap();
+        let turned = edit.rotation.rotate_vector(Vector3::unit_x());
+        assert!((turned - Vector3::unit_y()).magnitude() < 1e-4);
+    }
+}
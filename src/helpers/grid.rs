@@ -0,0 +1,163 @@
+use cgmath::prelude::*;
+use gl;
+
+use lang::{Float, Point3, Matrix4};
+use gl_state::GlState;
+use shader::Shader;
+
+const GRID_VERTEX_SHADER: &str = r#"
+#version 330 core
+out vec3 NearPoint;
+out vec3 FarPoint;
+
+uniform mat4 inverseViewProjection;
+
+vec3 unproject(vec2 ndc, float z)
+{
+    vec4 point = inverseViewProjection * vec4(ndc, z, 1.0);
+    return point.xyz / point.w;
+}
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    NearPoint = unproject(ndc, -1.0);
+    FarPoint = unproject(ndc, 1.0);
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+const GRID_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+in vec3 NearPoint;
+in vec3 FarPoint;
+
+uniform mat4 viewProjection;
+uniform vec3 cameraPosition;
+uniform float spacing;
+uniform float majorEvery;
+// half size of a finite grid, 0 for an infinite one
+uniform float extent;
+uniform float fadeDistance;
+uniform vec4 minorColor;
+uniform vec4 majorColor;
+
+float lines(vec2 coord)
+{
+    vec2 cell = abs(fract(coord - 0.5) - 0.5) / fwidth(coord);
+    return 1.0 - min(min(cell.x, cell.y), 1.0);
+}
+
+void main()
+{
+    float t = -NearPoint.y / (FarPoint.y - NearPoint.y);
+    if (t <= 0.0)
+        discard;
+    vec3 point = NearPoint + t * (FarPoint - NearPoint);
+    if (extent > 0.0 && (abs(point.x) > extent || abs(point.z) > extent))
+        discard;
+
+    vec2 coord = point.xz / spacing;
+    float minor = lines(coord);
+    float major = lines(coord / majorEvery);
+    vec4 color = mix(minorColor * minor, majorColor, major);
+    // x axis in red, z axis in blue
+    vec2 axis = abs(point.zx) / fwidth(point.zx);
+    if (axis.x < 1.0) color = vec4(0.9, 0.2, 0.2, 1.0);
+    if (axis.y < 1.0) color = vec4(0.2, 0.3, 0.9, 1.0);
+
+    float fade = 1.0 - clamp(length(point - cameraPosition) / fadeDistance, 0.0, 1.0);
+    color.a *= fade;
+    if (color.a <= 0.001)
+        discard;
+
+    vec4 clip = viewProjection * vec4(point, 1.0);
+    gl_FragDepth = clip.z / clip.w * 0.5 + 0.5;
+    FragColor = color;
+}
+"#;
+
+/// Reference grid on the y = 0 plane, drawn as one screen-covering triangle that is
+/// intersected with the plane per pixel, so it has no visible edge when infinite
+pub struct Grid {
+    /// distance between minor lines
+    pub spacing: Float,
+    /// minor cells per major line
+    pub major_every: Float,
+    /// half size of the grid, `None` for an infinite one
+    pub extent: Option<Float>,
+    /// distance from the camera at which the grid has faded out
+    pub fade_distance: Float,
+    pub minor_color: [Float; 4],
+    pub major_color: [Float; 4],
+    shader: Shader,
+    vao: u32,
+}
+
+impl Grid {
+    pub fn new() -> Grid {
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+        Grid {
+            spacing: 1.0,
+            major_every: 10.0,
+            extent: None,
+            fade_distance: 100.0,
+            minor_color: [0.5, 0.5, 0.5, 0.5],
+            major_color: [0.8, 0.8, 0.8, 0.8],
+            shader: Shader::from_source(GRID_VERTEX_SHADER, GRID_FRAGMENT_SHADER),
+            vao,
+        }
+    }
+
+    /// Finite grid of `size` x `size` around the origin
+    pub fn finite(size: Float) -> Grid {
+        Grid { extent: Some(size * 0.5), ..Grid::new() }
+    }
+
+    /// Draws with depth testing and alpha blending, after the opaque geometry
+    pub fn draw(&self, state: &mut GlState, view_projection: &Matrix4, camera_position: Point3) {
+        let inverse = match view_projection.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let [r, g, b, a] = self.minor_color;
+        let [major_r, major_g, major_b, major_a] = self.major_color;
+
+        state.use_program(self.shader.ID);
+        state.bind_vertex_array(self.vao);
+        state.set_blend(true);
+        state.blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        state.set_cull_face(false);
+        unsafe {
+            self.shader.setMat4(c_str!("inverseViewProjection"), &inverse);
+            self.shader.setMat4(c_str!("viewProjection"), view_projection);
+            self.shader.setVec3(c_str!("cameraPosition"), camera_position.x, camera_position.y, camera_position.z);
+            self.shader.setFloat(c_str!("spacing"), self.spacing);
+            self.shader.setFloat(c_str!("majorEvery"), self.major_every);
+            self.shader.setFloat(c_str!("extent"), self.extent.unwrap_or(0.0));
+            self.shader.setFloat(c_str!("fadeDistance"), self.fade_distance);
+            self.shader.setVec4(c_str!("minorColor"), r, g, b, a);
+            self.shader.setVec4(c_str!("majorColor"), major_r, major_g, major_b, major_a);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.vao = 0;
+    }
+}
+
+impl Default for Grid {
+    fn default() -> Grid {
+        Grid::new()
+    }
+}
@@ -0,0 +1,5 @@
+pub mod gizmo;
+pub mod grid;
+
+pub use self::gizmo::{Axis, Gizmo, GizmoMode, TransformEdit};
+pub use self::grid::Grid;
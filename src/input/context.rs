@@ -0,0 +1,117 @@
+use lang::ObjectPar;
+use super::InputControl;
+
+/// Named group of controls activated and suspended together, e.g. "gameplay" or "menu"
+pub struct InputContext {
+    pub name: String,
+    pub controls: Vec<ObjectPar<dyn InputControl>>,
+    /// contexts below this one get no input while it's active, like a menu over gameplay
+    pub blocking: bool,
+    /// kept on the stack but skipped
+    pub suspended: bool,
+}
+
+impl InputContext {
+    pub fn new(name: &str) -> InputContext {
+        InputContext { name: name.to_string(), controls: vec![], blocking: false, suspended: false }
+    }
+
+    pub fn control(mut self, control: ObjectPar<dyn InputControl>) -> InputContext {
+        self.controls.push(control);
+        self
+    }
+
+    pub fn blocking(mut self, blocking: bool) -> InputContext {
+        self.blocking = blocking;
+        self
+    }
+}
+
+/// Stack of input contexts, the window routes events to the active ones from the top down
+#[derive(Default)]
+pub struct InputContextStack {
+    contexts: Vec<InputContext>,
+}
+
+impl InputContextStack {
+    pub fn new() -> InputContextStack {
+        InputContextStack::default()
+    }
+
+    /// Pushes `context` on top, replacing a context with the same name
+    pub fn push(&mut self, context: InputContext) {
+        self.remove(&context.name);
+        self.contexts.push(context);
+    }
+
+    pub fn pop(&mut self) -> Option<InputContext> {
+        self.contexts.pop()
+    }
+
+    /// Removes the named context wherever it is in the stack
+    pub fn remove(&mut self, name: &str) -> Option<InputContext> {
+        let index = self.contexts.iter().position(|context| context.name == name)?;
+        Some(self.contexts.remove(index))
+    }
+
+    pub fn top(&self) -> Option<&InputContext> {
+        self.contexts.last()
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut InputContext> {
+        self.contexts.iter_mut().find(|context| context.name == name)
+    }
+
+    /// Returns false if there is no such context
+    pub fn set_suspended(&mut self, name: &str, suspended: bool) -> bool {
+        match self.get_mut(name) {
+            Some(context) => {
+                context.suspended = suspended;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Whether the named context currently receives input
+    pub fn is_active(&self, name: &str) -> bool {
+        self.active().any(|context| context.name == name)
+    }
+
+    /// Contexts receiving input, from the top down to the first blocking one
+    pub fn active(&self) -> impl Iterator<Item = &InputContext> {
+        let mut blocked = false;
+        self.contexts.iter().rev()
+            .filter(|context| !context.suspended)
+            .take_while(move |context| {
+                let take = !blocked;
+                blocked |= context.blocking;
+                take
+            })
+    }
+
+    pub fn active_controls(&self) -> impl Iterator<Item = &ObjectPar<dyn InputControl>> {
+        self.active().flat_map(|context| context.controls.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocking_context_hides_lower_ones() {
+        let mut stack = InputContextStack::new();
+        stack.push(InputContext::new("gameplay"));
+        stack.push(InputContext::new("hud"));
+        assert!(stack.is_active("gameplay") && stack.is_active("hud"));
+
+        stack.push(InputContext::new("menu").blocking(true));
+        assert!(stack.is_active("menu") && !stack.is_active("hud") && !stack.is_active("gameplay"));
+
+        assert!(stack.set_suspended("menu", true));
+        assert!(stack.is_active("gameplay"));
+        assert_eq!(stack.pop().map(|context| context.name), Some("menu".to_string()));
+        assert_eq!(stack.top().map(|context| context.name.as_str()), Some("hud"));
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Serialize, Deserialize};
+
+use lang::Float;
+
+/// Maps a raw analog value, a stick axis or a mouse delta, to the value controls act on:
+/// values within `dead_zone` give 0, the rest is rescaled so `saturation` maps to 1, raised
+/// to `exponent` and multiplied by `sensitivity`. The sign is kept, or flipped by `invert`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseCurve {
+    pub dead_zone: Float,
+    /// input magnitude mapped to 1, 1 for sticks, larger values keep following the curve
+    pub saturation: Float,
+    /// 1 is linear, above 1 gives finer control near the center
+    pub exponent: Float,
+    pub sensitivity: Float,
+    pub invert: bool,
+}
+
+impl ResponseCurve {
+    /// Identity curve
+    pub fn linear() -> ResponseCurve {
+        ResponseCurve { dead_zone: 0.0, saturation: 1.0, exponent: 1.0, sensitivity: 1.0, invert: false }
+    }
+
+    /// Typical gamepad stick: small dead zone, squared response
+    pub fn stick() -> ResponseCurve {
+        ResponseCurve { dead_zone: 0.15, exponent: 2.0, ..ResponseCurve::linear() }
+    }
+
+    pub fn dead_zone(mut self, dead_zone: Float) -> ResponseCurve {
+        self.dead_zone = dead_zone;
+        self
+    }
+
+    pub fn exponent(mut self, exponent: Float) -> ResponseCurve {
+        self.exponent = exponent;
+        self
+    }
+
+    pub fn sensitivity(mut self, sensitivity: Float) -> ResponseCurve {
+        self.sensitivity = sensitivity;
+        self
+    }
+
+    pub fn invert(mut self, invert: bool) -> ResponseCurve {
+        self.invert = invert;
+        self
+    }
+
+    pub fn apply(&self, value: Float) -> Float {
+        let sign = if self.invert { -value.signum() } else { value.signum() };
+        sign * self.sensitivity * self.shape(value.abs())
+    }
+
+    /// dead zone, rescale and exponent of a magnitude
+    fn shape(&self, magnitude: Float) -> Float {
+        if magnitude <= self.dead_zone {
+            return 0.0;
+        }
+        let range = (self.saturation - self.dead_zone).max(Float::EPSILON);
+        ((magnitude - self.dead_zone) / range).powf(self.exponent)
+    }
+}
+
+impl Default for ResponseCurve {
+    fn default() -> ResponseCurve {
+        ResponseCurve::linear()
+    }
+}
+
+/// Curves of a two-axis input, a stick or mouse look
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AxisCurves {
+    pub x: ResponseCurve,
+    pub y: ResponseCurve,
+}
+
+impl AxisCurves {
+    pub fn new(curve: ResponseCurve) -> AxisCurves {
+        AxisCurves { x: curve, y: curve }
+    }
+
+    /// Each axis through its own curve, for mouse deltas
+    pub fn apply(&self, x: Float, y: Float) -> (Float, Float) {
+        (self.x.apply(x), self.y.apply(y))
+    }
+
+    /// Dead zone and exponent of `x` applied to the stick's distance from the center, so
+    /// diagonals aren't snapped to the axes; then each axis' sensitivity and inversion
+    pub fn apply_radial(&self, x: Float, y: Float) -> (Float, Float) {
+        let magnitude = x.hypot(y);
+        if magnitude <= self.x.dead_zone {
+            return (0.0, 0.0);
+        }
+        let scale = self.x.shape(magnitude) / magnitude;
+        let axis = |curve: &ResponseCurve, value: Float| {
+            if curve.invert { -value * scale * curve.sensitivity } else { value * scale * curve.sensitivity }
+        };
+        (axis(&self.x, x), axis(&self.y, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_dead_zone_exponent_and_inversion() {
+        let curve = ResponseCurve::linear().dead_zone(0.2).exponent(2.0).sensitivity(2.0);
+        assert_eq!(curve.apply(0.1), 0.0);
+        assert!((curve.apply(-0.6) + 0.5).abs() < 1e-5);
+        assert!((curve.invert(true).apply(1.0) + 2.0).abs() < 1e-5);
+
+        let stick = AxisCurves::new(ResponseCurve::linear().dead_zone(0.5));
+        assert_eq!(stick.apply_radial(0.3, 0.3), (0.0, 0.0));
+        let (x, y) = stick.apply_radial(0.6, 0.8);
+        assert!((x - 0.6).abs() < 1e-5 && (y - 0.8).abs() < 1e-5);
+    }
+}
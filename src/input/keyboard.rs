@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use glfw::{Action, Key};
+
+use lang::TimeSec;
+use super::KeyEvent;
+
+/// Held keys and when they went down, synthesizing long presses
+#[derive(Debug, Clone)]
+pub struct KeyboardState {
+    /// seconds a key has to be held to count as a long press
+    pub long_press_threshold: TimeSec,
+    /// press time and whether the long press was already reported
+    pressed: BTreeMap<i32, (Key, TimeSec, bool)>,
+}
+
+impl KeyboardState {
+    pub fn new(long_press_threshold: TimeSec) -> KeyboardState {
+        KeyboardState { long_press_threshold, pressed: BTreeMap::new() }
+    }
+
+    /// Tracks a key event received at `time`, repeats don't restart the press
+    pub fn on_key(&mut self, event: &KeyEvent, time: TimeSec) {
+        let KeyEvent(key, _, action, _) = *event;
+        match action {
+            Action::Press => {
+                self.pressed.insert(key as i32, (key, time, false));
+            },
+            Action::Release => {
+                self.pressed.remove(&(key as i32));
+            },
+            Action::Repeat => {},
+        }
+    }
+
+    pub fn is_down(&self, key: Key) -> bool {
+        self.pressed.contains_key(&(key as i32))
+    }
+
+    /// Seconds `key` has been held at `time`, `None` if it's up
+    pub fn held_for(&self, key: Key, time: TimeSec) -> Option<TimeSec> {
+        self.pressed.get(&(key as i32)).map(|&(_, pressed_at, _)| time - pressed_at)
+    }
+
+    /// Keys that crossed the threshold since the last call, each reported once per press
+    pub fn long_presses(&mut self, time: TimeSec) -> Vec<Key> {
+        let threshold = self.long_press_threshold;
+        self.pressed.values_mut()
+            .filter(|&&mut (_, pressed_at, reported)| !reported && time - pressed_at >= threshold)
+            .map(|entry| {
+                entry.2 = true;
+                entry.0
+            })
+            .collect()
+    }
+
+    /// Forgets every held key, e.g. when the window loses focus
+    pub fn clear(&mut self) {
+        self.pressed.clear();
+    }
+}
+
+impl Default for KeyboardState {
+    /// half a second long presses
+    fn default() -> KeyboardState {
+        KeyboardState::new(0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glfw::Modifiers;
+
+    use super::*;
+
+    fn event(key: Key, action: Action) -> KeyEvent {
+        KeyEvent(key, 0, action, Modifiers::empty())
+    }
+
+    #[test]
+    fn reports_long_press_once() {
+        let mut keyboard = KeyboardState::default();
+        keyboard.on_key(&event(Key::Space, Action::Press), 1.0);
+        keyboard.on_key(&event(Key::Space, Action::Repeat), 1.3);
+        assert!(keyboard.long_presses(1.4).is_empty());
+        assert_eq!(keyboard.long_presses(1.6), vec![Key::Space]);
+        assert!(keyboard.long_presses(2.0).is_empty());
+        assert_eq!(keyboard.held_for(Key::Space, 2.0), Some(1.0));
+
+        keyboard.on_key(&event(Key::Space, Action::Release), 2.1);
+        assert!(!keyboard.is_down(Key::Space));
+    }
+}
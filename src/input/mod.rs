@@ -0,0 +1,69 @@
+pub mod context;
+pub mod curve;
+pub mod keyboard;
+pub mod recording;
+pub mod smoothing;
+pub mod touch;
+
+use glfw::{Key, MouseButton, Scancode, Action, Modifiers};
+
+use lang::{RasterFloat, TimeSec};
+use window::InputState;
+
+pub use self::context::{InputContext, InputContextStack};
+pub use self::curve::{AxisCurves, ResponseCurve};
+pub use self::keyboard::KeyboardState;
+pub use self::recording::{Recording, Recorder, Player};
+pub use self::smoothing::ScrollSmoother;
+pub use self::touch::{Gesture, TouchGestures, TouchPhase, TouchPoint};
+
+
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct KeyEvent(pub Key, pub Scancode, pub Action, pub Modifiers);
+
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct MouseButtonEvent(pub MouseButton, pub Action, pub Modifiers);
+
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub struct MouseEvent {
+    pub x_pos: RasterFloat,
+    pub y_pos: RasterFloat,
+    pub x_offset: RasterFloat,
+    pub y_offset: RasterFloat,
+    pub is_scroll: bool,
+    pub button_event: Option<MouseButtonEvent>,
+    /// the offsets come from unaccelerated motion, see `Window::set_cursor_grabbed`
+    pub is_raw: bool,
+}
+
+pub trait InputEvent {
+    fn mouse_event(&mut self, event: MouseEvent);
+    fn keyboard_event(&mut self, event: KeyEvent);
+    fn char_event(&mut self, character: char);
+    fn touch_event(&mut self, touch: TouchPoint);
+}
+
+pub trait InputControl {
+    fn on_mouse(&mut self, mouse: MouseEvent, delta_time: TimeSec);
+    fn on_keyboard(&mut self, key: KeyEvent, delta_time: TimeSec);
+    /// Polls held keys and buttons once per frame. A `glfw::Window` is an `InputState`, so code
+    /// passing one keeps compiling; implementations take `&dyn InputState` instead of it.
+    fn on_input(&mut self, window: &dyn InputState, delta_time: TimeSec);
+
+    /// text input, for controls accepting typed text
+    fn on_char(&mut self, _character: char, _delta_time: TimeSec) {}
+
+    /// key held long enough for the OS to repeat it, override to tell repeats from presses
+    fn on_key_repeat(&mut self, key: KeyEvent, delta_time: TimeSec) {
+        self.on_keyboard(key, delta_time)
+    }
+
+    /// key held past `KeyboardState::long_press_threshold`, once per press
+    fn on_long_press(&mut self, _key: Key, _delta_time: TimeSec) {}
+
+    /// raw touches, on backends with a touch screen
+    fn on_touch(&mut self, _touch: TouchPoint, _delta_time: TimeSec) {}
+
+    /// gestures recognized from the touches
+    fn on_gesture(&mut self, _gesture: Gesture, _delta_time: TimeSec) {}
+}
\ No newline at end of file
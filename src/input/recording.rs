@@ -0,0 +1,407 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use glfw::{Key, MouseButton, Action, Modifiers};
+use num_traits::FromPrimitive;
+use serde::{Serialize, Deserialize};
+use serde_json;
+
+use lang::TimeSec;
+use input::{KeyEvent, MouseButtonEvent, TouchPoint};
+use window::{BackendEvent, InputState, WindowBackend};
+
+/// `BackendEvent` with the glfw enums stored as their integer values
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    FramebufferSize(i32, i32),
+    CursorPos(f64, f64),
+    Scroll(f64, f64),
+    MouseButton { button: i32, action: i32, modifiers: i32 },
+    Key { key: i32, scancode: i32, action: i32, modifiers: i32 },
+    Char(char),
+    Touch(TouchPoint),
+}
+
+impl RecordedEvent {
+    pub fn from_event(event: &BackendEvent) -> RecordedEvent {
+        match *event {
+            BackendEvent::FramebufferSize(width, height) => RecordedEvent::FramebufferSize(width, height),
+            BackendEvent::CursorPos(x, y) => RecordedEvent::CursorPos(x, y),
+            BackendEvent::Scroll(x, y) => RecordedEvent::Scroll(x, y),
+            BackendEvent::MouseButton(MouseButtonEvent(button, action, modifiers)) => RecordedEvent::MouseButton {
+                button: button as i32,
+                action: action as i32,
+                modifiers: modifiers.bits(),
+            },
+            BackendEvent::Key(KeyEvent(key, scancode, action, modifiers)) => RecordedEvent::Key {
+                key: key as i32,
+                scancode,
+                action: action as i32,
+                modifiers: modifiers.bits(),
+            },
+            BackendEvent::Char(character) => RecordedEvent::Char(character),
+            BackendEvent::Touch(touch) => RecordedEvent::Touch(touch),
+        }
+    }
+
+    /// `None` if the recording holds values unknown to glfw
+    pub fn to_event(&self) -> Option<BackendEvent> {
+        Some(match *self {
+            RecordedEvent::FramebufferSize(width, height) => BackendEvent::FramebufferSize(width, height),
+            RecordedEvent::CursorPos(x, y) => BackendEvent::CursorPos(x, y),
+            RecordedEvent::Scroll(x, y) => BackendEvent::Scroll(x, y),
+            RecordedEvent::MouseButton { button, action, modifiers } => BackendEvent::MouseButton(MouseButtonEvent(
+                MouseButton::from_i32(button)?,
+                action_from_i32(action)?,
+                Modifiers::from_bits_truncate(modifiers),
+            )),
+            RecordedEvent::Key { key, scancode, action, modifiers } => BackendEvent::Key(KeyEvent(
+                Key::from_i32(key)?,
+                scancode,
+                action_from_i32(action)?,
+                Modifiers::from_bits_truncate(modifiers),
+            )),
+            RecordedEvent::Char(character) => BackendEvent::Char(character),
+            RecordedEvent::Touch(touch) => BackendEvent::Touch(touch),
+        })
+    }
+}
+
+fn action_from_i32(action: i32) -> Option<Action> {
+    [Action::Release, Action::Press, Action::Repeat].iter().cloned().find(|&a| a as i32 == action)
+}
+
+/// Input of one frame: the events and the key / mouse button states queried through `InputState`
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub time: TimeSec,
+    pub events: Vec<RecordedEvent>,
+    pub keys: BTreeMap<i32, i32>,
+    pub mouse_buttons: BTreeMap<i32, i32>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(io::Error::from)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+}
+
+/// `WindowBackend` wrapper capturing the input the window receives each frame, use it with
+/// `Window::with_backend`. Frames end on `poll_events`, as in `Window::events_loop`.
+pub struct Recorder<B: WindowBackend> {
+    backend: B,
+    fixed_timestep: Option<TimeSec>,
+    recording: Recording,
+    current: RefCell<RecordedFrame>,
+}
+
+impl<B: WindowBackend> Recorder<B> {
+    pub fn new(backend: B) -> Recorder<B> {
+        Recorder {
+            backend,
+            fixed_timestep: None,
+            recording: Recording::default(),
+            current: RefCell::new(RecordedFrame::default()),
+        }
+    }
+
+    /// Reports a time advancing by `delta_time` every frame instead of the wall clock,
+    /// so the recorded session itself runs with the timing it will be replayed with
+    pub fn fixed_timestep(mut self, delta_time: TimeSec) -> Recorder<B> {
+        self.fixed_timestep = Some(delta_time);
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Frames completed so far
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    pub fn into_recording(self) -> Recording {
+        self.recording
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.recording.save(path)
+    }
+}
+
+impl<B: WindowBackend> InputState for Recorder<B> {
+    fn get_key(&self, key: Key) -> Action {
+        let action = self.backend.get_key(key);
+        self.current.borrow_mut().keys.insert(key as i32, action as i32);
+        action
+    }
+
+    fn get_mouse_button(&self, button: MouseButton) -> Action {
+        let action = self.backend.get_mouse_button(button);
+        self.current.borrow_mut().mouse_buttons.insert(button as i32, action as i32);
+        action
+    }
+}
+
+impl<B: WindowBackend> WindowBackend for Recorder<B> {
+    fn should_close(&self) -> bool {
+        self.backend.should_close()
+    }
+
+    fn set_should_close(&mut self, value: bool) {
+        self.backend.set_should_close(value)
+    }
+
+    fn time(&self) -> TimeSec {
+        let time = match self.fixed_timestep {
+            Some(delta_time) => self.recording.frames.len() as TimeSec * delta_time,
+            None => self.backend.time(),
+        };
+        self.current.borrow_mut().time = time;
+        time
+    }
+
+    fn framebuffer_size(&self) -> (i32, i32) {
+        self.backend.framebuffer_size()
+    }
+
+    fn swap_buffers(&mut self) {
+        self.backend.swap_buffers()
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.backend.set_title(title)
+    }
+
+    fn set_cursor_grabbed(&mut self, grabbed: bool) -> bool {
+        self.backend.set_cursor_grabbed(grabbed)
+    }
+
+    fn poll_events(&mut self) {
+        let frame = self.current.replace(RecordedFrame::default());
+        self.recording.frames.push(frame);
+        self.backend.poll_events()
+    }
+
+    fn flush_events(&mut self) -> Vec<BackendEvent> {
+        let events = self.backend.flush_events();
+        self.current.borrow_mut().events.extend(events.iter().map(RecordedEvent::from_event));
+        events
+    }
+}
+
+/// `WindowBackend` wrapper replaying a `Recording` instead of the backend's input, with the
+/// recorded frame times. The window closes after the last frame.
+pub struct Player<B: WindowBackend> {
+    backend: B,
+    recording: Recording,
+    frame: usize,
+}
+
+impl<B: WindowBackend> Player<B> {
+    pub fn new(backend: B, recording: Recording) -> Player<B> {
+        Player {
+            backend,
+            recording,
+            frame: 0,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(backend: B, path: P) -> io::Result<Player<B>> {
+        Ok(Player::new(backend, Recording::load(path)?))
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Index of the frame being replayed
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.recording.frames.len()
+    }
+
+    fn current(&self) -> Option<&RecordedFrame> {
+        self.recording.frames.get(self.frame)
+    }
+}
+
+impl<B: WindowBackend> InputState for Player<B> {
+    fn get_key(&self, key: Key) -> Action {
+        self.current()
+            .and_then(|frame| frame.keys.get(&(key as i32)))
+            .and_then(|&action| action_from_i32(action))
+            .unwrap_or(Action::Release)
+    }
+
+    fn get_mouse_button(&self, button: MouseButton) -> Action {
+        self.current()
+            .and_then(|frame| frame.mouse_buttons.get(&(button as i32)))
+            .and_then(|&action| action_from_i32(action))
+            .unwrap_or(Action::Release)
+    }
+}
+
+impl<B: WindowBackend> WindowBackend for Player<B> {
+    fn should_close(&self) -> bool {
+        self.is_finished() || self.backend.should_close()
+    }
+
+    fn set_should_close(&mut self, value: bool) {
+        self.backend.set_should_close(value)
+    }
+
+    fn time(&self) -> TimeSec {
+        match self.current() {
+            Some(frame) => frame.time,
+            None => self.recording.frames.last().map_or(0.0, |frame| frame.time),
+        }
+    }
+
+    fn framebuffer_size(&self) -> (i32, i32) {
+        self.backend.framebuffer_size()
+    }
+
+    fn swap_buffers(&mut self) {
+        self.backend.swap_buffers()
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.backend.set_title(title)
+    }
+
+    fn set_cursor_grabbed(&mut self, grabbed: bool) -> bool {
+        self.backend.set_cursor_grabbed(grabbed)
+    }
+
+    fn poll_events(&mut self) {
+        self.frame += 1;
+        // keep the real window responsive, its own input is dropped
+        self.backend.poll_events();
+        self.backend.flush_events();
+    }
+
+    fn flush_events(&mut self) -> Vec<BackendEvent> {
+        match self.current() {
+            Some(frame) => frame.events.iter().filter_map(RecordedEvent::to_event).collect(),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use lang::ObjectPar;
+    use input::{InputControl, MouseEvent};
+    use window::Window;
+
+    /// Scripted input, one entry per frame
+    struct FakeBackend {
+        frames: Vec<(Vec<BackendEvent>, bool)>,
+        frame: usize,
+        closed: bool,
+    }
+
+    impl InputState for FakeBackend {
+        fn get_key(&self, key: Key) -> Action {
+            match self.frames.get(self.frame) {
+                Some(&(_, true)) if key == Key::W => Action::Press,
+                _ => Action::Release,
+            }
+        }
+
+        fn get_mouse_button(&self, _button: MouseButton) -> Action {
+            Action::Release
+        }
+    }
+
+    impl WindowBackend for FakeBackend {
+        fn should_close(&self) -> bool { self.closed || self.frame >= self.frames.len() }
+        fn set_should_close(&mut self, value: bool) { self.closed = value }
+        fn time(&self) -> TimeSec { 1000.0 + self.frame as TimeSec * 0.5 }
+        fn framebuffer_size(&self) -> (i32, i32) { (640, 480) }
+        fn swap_buffers(&mut self) {}
+        fn poll_events(&mut self) { self.frame += 1 }
+        fn flush_events(&mut self) -> Vec<BackendEvent> {
+            self.frames.get(self.frame).map_or(vec![], |frame| frame.0.clone())
+        }
+    }
+
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl InputControl for Log {
+        fn on_mouse(&mut self, mouse: MouseEvent, delta_time: TimeSec) {
+            self.0.push(format!("mouse {} {} {}", mouse.x_pos, mouse.y_pos, delta_time));
+        }
+        fn on_keyboard(&mut self, key: KeyEvent, delta_time: TimeSec) {
+            self.0.push(format!("key {:?} {}", key, delta_time));
+        }
+        fn on_input(&mut self, window: &dyn InputState, delta_time: TimeSec) {
+            self.0.push(format!("input {:?} {}", window.get_key(Key::W), delta_time));
+        }
+    }
+
+    fn run<B: WindowBackend>(backend: B) -> (Vec<String>, B) {
+        let log = Arc::new(Mutex::new(Log::default()));
+        let mut window = Window::with_backend(backend);
+        window.controls.push(log.clone() as ObjectPar<dyn InputControl>);
+        window.events_loop(Some(|_: &mut Window<B>| {}));
+        let lines = log.lock().unwrap().0.clone();
+        (lines, window.into_backend())
+    }
+
+    #[test]
+    fn replays_recorded_input() {
+        let key = KeyEvent(Key::W, 17, Action::Press, Modifiers::Shift);
+        let backend = FakeBackend {
+            frames: vec![
+                (vec![BackendEvent::CursorPos(1.0, 2.0)], false),
+                (vec![BackendEvent::Key(key), BackendEvent::Scroll(0.0, 1.0)], true),
+                (vec![], true),
+            ],
+            frame: 0,
+            closed: false,
+        };
+
+        let (recorded, recorder) = run(Recorder::new(backend).fixed_timestep(0.25));
+        assert_eq!(recorder.recording().frames.len(), 3);
+        assert!(recorded.contains(&"input Press 0.25".to_string()));
+
+        let json = serde_json::to_string(recorder.recording()).unwrap();
+        let recording: Recording = serde_json::from_str(&json).unwrap();
+        let idle = FakeBackend { frames: vec![(vec![], false); 10], frame: 0, closed: false };
+        let (replayed, player) = run(Player::new(idle, recording));
+        assert_eq!(player.frame(), 3);
+        assert_eq!(replayed, recorded);
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, TimeSec};
+
+/// Below this the rest of the scroll is applied at once
+const SETTLE: Float = 1e-3;
+
+/// Accumulates scroll offsets and spends them over the following frames with exponential
+/// decay, so coarse mouse wheel detents and streams of small trackpad offsets both turn
+/// into smooth motion
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScrollSmoother {
+    /// fraction of the pending scroll applied per second is `1 - exp(-rate)`, 0 disables
+    /// the smoothing
+    pub rate: Float,
+    #[serde(skip)]
+    pending: Float,
+}
+
+impl ScrollSmoother {
+    pub fn new(rate: Float) -> ScrollSmoother {
+        ScrollSmoother { rate, pending: 0.0 }
+    }
+
+    pub fn add(&mut self, offset: Float) {
+        self.pending += offset;
+    }
+
+    /// Scroll not applied yet
+    pub fn pending(&self) -> Float {
+        self.pending
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.pending == 0.0
+    }
+
+    /// Part of the pending scroll to apply this frame
+    pub fn update(&mut self, delta_time: TimeSec) -> Float {
+        let step = if self.rate <= 0.0 || self.pending.abs() < SETTLE {
+            self.pending
+        } else {
+            self.pending * (1.0 - (-self.rate * delta_time as Float).exp())
+        };
+        self.pending -= step;
+        step
+    }
+
+    /// Drops the pending scroll
+    pub fn stop(&mut self) {
+        self.pending = 0.0;
+    }
+}
+
+impl Default for ScrollSmoother {
+    fn default() -> ScrollSmoother {
+        ScrollSmoother::new(15.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spends_scroll_frame_rate_independently() {
+        let (mut fast, mut slow) = (ScrollSmoother::default(), ScrollSmoother::default());
+        fast.add(3.0);
+        slow.add(3.0);
+        let fast_total: Float = (0..4).map(|_| fast.update(0.025)).sum();
+        let slow_total = slow.update(0.1);
+        assert!((fast_total - slow_total).abs() < 1e-4);
+        assert!(slow_total > 2.0 && slow_total < 3.0);
+
+        let total: Float = slow_total + (0..100).map(|_| slow.update(0.1)).sum::<Float>();
+        assert!(slow.is_settled());
+        assert!((total - 3.0).abs() < 1e-5);
+    }
+}
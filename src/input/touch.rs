@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, RasterFloat, wrap_degrees};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TouchPhase {
+    Began,
+    Moved,
+    Ended,
+    /// the platform took the touch away, e.g. for a system gesture
+    Cancelled,
+}
+
+/// One finger on a touch screen, `id` stays the same from `Began` to `Ended`
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TouchPoint {
+    pub id: u64,
+    pub phase: TouchPhase,
+    /// window coordinates in pixels, like the cursor position
+    pub x: RasterFloat,
+    pub y: RasterFloat,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Gesture {
+    /// one finger moved, offsets in pixels with y up like `MouseEvent`
+    Drag { x_offset: RasterFloat, y_offset: RasterFloat },
+    /// two fingers spread (> 1) or closed (< 1) by this factor
+    Pinch { scale: RasterFloat },
+    /// two fingers twisted counter-clockwise on screen by this many degrees
+    Rotate { degrees: RasterFloat },
+}
+
+/// Turns raw touches into drag, pinch and rotate gestures. Two-finger gestures use the
+/// first two fingers down, any further fingers are tracked but ignored.
+#[derive(Debug, Clone, Default)]
+pub struct TouchGestures {
+    touches: BTreeMap<u64, (RasterFloat, RasterFloat)>,
+}
+
+impl TouchGestures {
+    pub fn new() -> TouchGestures {
+        TouchGestures::default()
+    }
+
+    /// Fingers currently down
+    pub fn touch_count(&self) -> usize {
+        self.touches.len()
+    }
+
+    /// Tracks `touch`, returns the gestures it completes
+    pub fn update(&mut self, touch: &TouchPoint) -> Vec<Gesture> {
+        let before = self.pair();
+        let previous = self.touches.get(&touch.id).cloned();
+        match touch.phase {
+            TouchPhase::Began | TouchPhase::Moved => {
+                self.touches.insert(touch.id, (touch.x, touch.y));
+            },
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+            },
+        }
+        if touch.phase != TouchPhase::Moved {
+            return vec![];
+        }
+
+        let mut gestures = vec![];
+        match (self.touches.len(), previous, before, self.pair()) {
+            (1, Some((x, y)), _, _) => {
+                gestures.push(Gesture::Drag { x_offset: touch.x - x, y_offset: y - touch.y });
+            },
+            (_, _, Some((ids_before, a0, b0)), Some((ids_after, a1, b1))) if ids_before == ids_after => {
+                let (distance0, distance1) = (distance(a0, b0), distance(a1, b1));
+                if distance0 > 0.0 && distance1 > 0.0 && distance0 != distance1 {
+                    gestures.push(Gesture::Pinch { scale: distance1 / distance0 });
+                }
+                let degrees = wrap_degrees((angle(a1, b1) - angle(a0, b0)) as Float) as RasterFloat;
+                if degrees != 0.0 {
+                    gestures.push(Gesture::Rotate { degrees });
+                }
+            },
+            _ => {},
+        }
+        gestures
+    }
+
+    /// ids and positions of the first two fingers
+    #[allow(clippy::type_complexity)]
+    fn pair(&self) -> Option<((u64, u64), (RasterFloat, RasterFloat), (RasterFloat, RasterFloat))> {
+        let mut touches = self.touches.iter();
+        match (touches.next(), touches.next()) {
+            (Some((&a, &pos_a)), Some((&b, &pos_b))) => Some(((a, b), pos_a, pos_b)),
+            _ => None,
+        }
+    }
+}
+
+fn distance(a: (RasterFloat, RasterFloat), b: (RasterFloat, RasterFloat)) -> RasterFloat {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+/// screen y points down, negated so positive angles are counter-clockwise
+fn angle(a: (RasterFloat, RasterFloat), b: (RasterFloat, RasterFloat)) -> RasterFloat {
+    (a.1 - b.1).atan2(b.0 - a.0).to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(id: u64, phase: TouchPhase, x: RasterFloat, y: RasterFloat) -> TouchPoint {
+        TouchPoint { id, phase, x, y }
+    }
+
+    #[test]
+    fn recognizes_drag_pinch_and_rotate() {
+        let mut gestures = TouchGestures::new();
+        gestures.update(&touch(1, TouchPhase::Began, 100.0, 100.0));
+        assert_eq!(gestures.update(&touch(1, TouchPhase::Moved, 110.0, 90.0)),
+                   vec![Gesture::Drag { x_offset: 10.0, y_offset: 10.0 }]);
+
+        // second finger 100px right of the first, then 200px
+        gestures.update(&touch(2, TouchPhase::Began, 210.0, 90.0));
+        assert_eq!(gestures.update(&touch(2, TouchPhase::Moved, 310.0, 90.0)),
+                   vec![Gesture::Pinch { scale: 2.0 }]);
+
+        // quarter turn counter-clockwise around the first finger: straight up on screen
+        match gestures.update(&touch(2, TouchPhase::Moved, 110.0, -110.0))[..] {
+            [Gesture::Rotate { degrees }] => assert!((degrees - 90.0).abs() < 1e-3),
+            ref other => panic!("unexpected {:?}", other),
+        }
+
+        gestures.update(&touch(1, TouchPhase::Ended, 110.0, 90.0));
+        assert_eq!(gestures.touch_count(), 1);
+    }
+}
@@ -0,0 +1,492 @@
+//! Work-stealing job system for engine and game work: `spawn` runs a function on a worker and
+//! returns a handle to its result, `scope` runs jobs borrowing from the caller's stack,
+//! `parallel_for` and `map_chunks` split slices over the workers and `JobGraph` runs jobs
+//! after the ones they depend on.
+//!
+//! Each worker pushes the jobs it spawns onto its own queue and takes them back newest first,
+//! idle workers steal the oldest jobs of the others. Threads waiting for a job run queued jobs
+//! meanwhile, so waiting inside a job doesn't deadlock the pool.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+type Panic = Box<dyn Any + Send + 'static>;
+
+/// How long a waiting thread with nothing to run sleeps before looking for work again
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+thread_local! {
+    /// Pool and queue of the worker running on this thread, `(0, 0)` elsewhere
+    static WORKER: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+struct Shared {
+    injector: Mutex<VecDeque<Job>>,
+    locals: Vec<Mutex<VecDeque<Job>>>,
+    /// queued jobs, read under `sleep` so a push can't slip past a worker going to sleep
+    queued: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    fn id(self: &Arc<Shared>) -> usize {
+        Arc::as_ptr(self) as usize
+    }
+
+    /// Queue of the current thread when it is one of this pool's workers
+    fn local_index(self: &Arc<Shared>) -> Option<usize> {
+        let (pool, index) = WORKER.with(|worker| worker.get());
+        if pool == self.id() { Some(index) } else { None }
+    }
+
+    fn push(self: &Arc<Shared>, job: Job) {
+        match self.local_index() {
+            Some(index) => self.locals[index].lock().unwrap().push_back(job),
+            None => self.injector.lock().unwrap().push_back(job),
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let _guard = self.sleep.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    /// Own newest job, else the oldest injected one, else one stolen from another worker
+    fn find(self: &Arc<Shared>) -> Option<Job> {
+        let local = self.local_index();
+        let job = local.and_then(|index| self.locals[index].lock().unwrap().pop_back())
+            .or_else(|| self.injector.lock().unwrap().pop_front())
+            .or_else(|| {
+                let start = local.map_or(0, |index| index + 1);
+                (0..self.locals.len())
+                    .map(|offset| (start + offset) % self.locals.len())
+                    .filter(|&victim| Some(victim) != local)
+                    .find_map(|victim| self.locals[victim].lock().unwrap().pop_front())
+            });
+        if job.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+
+    /// Runs one queued job if there is any, for threads waiting on other jobs
+    fn help(self: &Arc<Shared>) -> bool {
+        match self.find() {
+            Some(job) => {
+                job();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn work(shared: Arc<Shared>, index: usize) {
+    WORKER.with(|worker| worker.set((shared.id(), index)));
+    loop {
+        if let Some(job) = shared.find() {
+            job();
+            continue;
+        }
+        let guard = shared.sleep.lock().unwrap();
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        if shared.queued.load(Ordering::SeqCst) == 0 {
+            drop(shared.wake.wait(guard).unwrap());
+        }
+    }
+}
+
+/// Pool of worker threads, see the module documentation. Most code uses the `global` one.
+pub struct JobSystem {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl JobSystem {
+    /// Starts `threads` workers, at least one
+    pub fn new(threads: usize) -> JobSystem {
+        let threads = threads.max(1);
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            locals: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            queued: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = (0..threads)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("reactor-job-{}", index))
+                    .spawn(move || work(shared, index))
+                    .expect("Failed to start a job thread")
+            })
+            .collect();
+        JobSystem { shared, workers }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `job` on a worker, its result or panic comes out of the handle's `join`
+    pub fn spawn<T, F>(&self, job: F) -> JobHandle<T>
+        where T: Send + 'static, F: FnOnce() -> T + Send + 'static
+    {
+        let slot = Arc::new(Slot { result: Mutex::new(None), done: Condvar::new() });
+        let filled = slot.clone();
+        self.shared.push(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            *filled.result.lock().unwrap() = Some(result);
+            filled.done.notify_all();
+        }));
+        JobHandle { slot, shared: self.shared.clone() }
+    }
+
+    /// Calls `body` with a `Scope` whose jobs may borrow from the caller, and waits for them.
+    /// A panic in a job is resumed here once all of them finished.
+    pub fn scope<'env, F, R>(&self, body: F) -> R
+        where F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R
+    {
+        let scope = Scope {
+            shared: self.shared.clone(),
+            latch: Arc::new(Latch { pending: AtomicUsize::new(0), panic: Mutex::new(None), done: Mutex::new(()), wake: Condvar::new() }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| body(&scope)));
+        // the jobs borrow from the caller, they have to finish before anything unwinds
+        scope.wait();
+        let job_panic = scope.latch.panic.lock().unwrap().take();
+        match (result, job_panic) {
+            (Err(panic), _) | (Ok(_), Some(panic)) => panic::resume_unwind(panic),
+            (Ok(result), None) => result,
+        }
+    }
+
+    /// Calls `f` on every item, a few items per job
+    pub fn parallel_for<T, F>(&self, items: &mut [T], f: F)
+        where T: Send, F: Fn(&mut T) + Sync
+    {
+        self.parallel_map(items, |item| f(item));
+    }
+
+    /// `f` of every item, in order
+    pub fn parallel_map<T, R, F>(&self, items: &mut [T], f: F) -> Vec<R>
+        where T: Send, R: Send, F: Fn(&mut T) -> R + Sync
+    {
+        let grain = self.grain(items.len());
+        let mut results: Vec<Option<R>> = (0..items.len()).map(|_| None).collect();
+        let f = &f;
+        self.scope(|scope| {
+            for (items, results) in items.chunks_mut(grain).zip(results.chunks_mut(grain)) {
+                scope.spawn(move || {
+                    for (item, result) in items.iter_mut().zip(results.iter_mut()) {
+                        *result = Some(f(item));
+                    }
+                });
+            }
+        });
+        results.into_iter().map(|result| result.expect("Parallel job skipped an item")).collect()
+    }
+
+    /// Splits `items` in up to `chunks` runs and returns `f` of each, in order
+    pub fn map_chunks<T, R, F>(&self, items: &[T], chunks: usize, f: F) -> Vec<R>
+        where T: Sync, R: Send, F: Fn(&[T]) -> R + Sync
+    {
+        if items.is_empty() {
+            return Vec::new();
+        }
+        let chunk_size = items.len().div_ceil(chunks.max(1));
+        let mut runs: Vec<&[T]> = items.chunks(chunk_size).collect();
+        if runs.len() == 1 {
+            return vec![f(runs[0])];
+        }
+        self.parallel_map(&mut runs, |run| f(run))
+    }
+
+    /// Items per job: a few jobs per worker, so stealing evens out uneven items
+    fn grain(&self, len: usize) -> usize {
+        len.div_ceil(self.threads() * 4).max(1)
+    }
+}
+
+impl Drop for JobSystem {
+    /// Lets the workers finish the queued jobs and stops them
+    fn drop(&mut self) {
+        while self.shared.help() {}
+        {
+            let _guard = self.shared.sleep.lock().unwrap();
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            self.shared.wake.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Job system shared by the engine and the application, one worker per core but one,
+/// which is left to the main thread
+pub fn global() -> &'static JobSystem {
+    static GLOBAL: OnceLock<JobSystem> = OnceLock::new();
+    GLOBAL.get_or_init(|| {
+        let cores = thread::available_parallelism().map_or(2, |cores| cores.get());
+        JobSystem::new(cores.saturating_sub(1))
+    })
+}
+
+/// `JobSystem::spawn` on the `global` job system
+pub fn spawn<T, F>(job: F) -> JobHandle<T>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static
+{
+    global().spawn(job)
+}
+
+/// `JobSystem::scope` on the `global` job system
+pub fn scope<'env, F, R>(body: F) -> R
+    where F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R
+{
+    global().scope(body)
+}
+
+/// `JobSystem::parallel_for` on the `global` job system
+pub fn parallel_for<T, F>(items: &mut [T], f: F)
+    where T: Send, F: Fn(&mut T) + Sync
+{
+    global().parallel_for(items, f)
+}
+
+struct Slot<T> {
+    result: Mutex<Option<thread::Result<T>>>,
+    done: Condvar,
+}
+
+/// Result of a spawned job
+pub struct JobHandle<T> {
+    slot: Arc<Slot<T>>,
+    shared: Arc<Shared>,
+}
+
+impl<T> JobHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.slot.result.lock().unwrap().is_some()
+    }
+
+    /// Waits for the result, running other jobs meanwhile. Resumes the job's panic.
+    pub fn join(self) -> T {
+        loop {
+            if let Some(result) = self.slot.result.lock().unwrap().take() {
+                return result.unwrap_or_else(|panic| panic::resume_unwind(panic));
+            }
+            if !self.shared.help() {
+                let result = self.slot.result.lock().unwrap();
+                if result.is_none() {
+                    drop(self.slot.done.wait_timeout(result, IDLE_WAIT).unwrap());
+                }
+            }
+        }
+    }
+}
+
+struct Latch {
+    pending: AtomicUsize,
+    /// first panic of the scope's jobs
+    panic: Mutex<Option<Panic>>,
+    done: Mutex<()>,
+    wake: Condvar,
+}
+
+/// Spawns jobs that may borrow what outlives the `JobSystem::scope` call, see there
+pub struct Scope<'scope, 'env: 'scope> {
+    shared: Arc<Shared>,
+    latch: Arc<Latch>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    pub fn spawn<F: FnOnce() + Send + 'scope>(&'scope self, job: F) {
+        self.latch.pending.fetch_add(1, Ordering::SeqCst);
+        let latch = self.latch.clone();
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                latch.panic.lock().unwrap().get_or_insert(panic);
+            }
+            if latch.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                let _guard = latch.done.lock().unwrap();
+                latch.wake.notify_all();
+            }
+        });
+        // the scope waits for every job before returning, nothing it borrows can go away
+        // while the job is queued or running
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.shared.push(job);
+    }
+
+    fn wait(&self) {
+        while self.latch.pending.load(Ordering::SeqCst) > 0 {
+            if !self.shared.help() {
+                let guard = self.latch.done.lock().unwrap();
+                if self.latch.pending.load(Ordering::SeqCst) > 0 {
+                    drop(self.latch.wake.wait_timeout(guard, IDLE_WAIT).unwrap());
+                }
+            }
+        }
+    }
+}
+
+/// Index of a job in its `JobGraph`
+pub type JobId = usize;
+
+struct GraphJob<'a> {
+    name: String,
+    run: Mutex<Option<Box<dyn FnOnce() + Send + 'a>>>,
+    dependents: Vec<JobId>,
+    /// dependencies not finished yet
+    waiting: AtomicUsize,
+}
+
+/// Jobs of a frame with the jobs they have to wait for, e.g. animation before skinning before
+/// culling. Jobs may borrow frame data, `run` returns once all of them finished. Dependencies
+/// are jobs added before, so a graph can't have cycles.
+#[derive(Default)]
+pub struct JobGraph<'a> {
+    jobs: Vec<GraphJob<'a>>,
+}
+
+impl<'a> JobGraph<'a> {
+    pub fn new() -> JobGraph<'a> {
+        JobGraph { jobs: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn name(&self, job: JobId) -> &str {
+        &self.jobs[job].name
+    }
+
+    pub fn add<F: FnOnce() + Send + 'a>(&mut self, name: &str, job: F) -> JobId {
+        self.add_after(name, &[], job)
+    }
+
+    /// Adds a job starting once the jobs of `dependencies` finished
+    pub fn add_after<F: FnOnce() + Send + 'a>(&mut self, name: &str, dependencies: &[JobId], job: F) -> JobId {
+        let id = self.jobs.len();
+        for &dependency in dependencies {
+            assert!(dependency < id, "Job '{}' depends on job {} that isn't in the graph yet", name, dependency);
+            self.jobs[dependency].dependents.push(id);
+        }
+        self.jobs.push(GraphJob {
+            name: name.to_string(),
+            run: Mutex::new(Some(Box::new(job))),
+            dependents: Vec::new(),
+            waiting: AtomicUsize::new(dependencies.len()),
+        });
+        id
+    }
+
+    /// Runs the jobs on `jobs` in dependency order, as parallel as the dependencies allow
+    pub fn run(self, jobs: &JobSystem) {
+        let graph = &self;
+        jobs.scope(|scope| {
+            for (id, job) in graph.jobs.iter().enumerate() {
+                if job.waiting.load(Ordering::SeqCst) == 0 {
+                    graph.start(scope, id);
+                }
+            }
+        });
+    }
+
+    fn start<'scope>(&'scope self, scope: &'scope Scope<'scope, '_>, id: JobId) {
+        scope.spawn(move || {
+            let job = &self.jobs[id];
+            if let Some(run) = job.run.lock().unwrap().take() {
+                run();
+            }
+            for &dependent in &job.dependents {
+                if self.jobs[dependent].waiting.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    self.start(scope, dependent);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawns_and_splits_work_over_the_workers() {
+        let jobs = JobSystem::new(3);
+        let handles: Vec<JobHandle<u64>> = (0..20u64).map(|i| jobs.spawn(move || i * i)).collect();
+        assert_eq!(handles.into_iter().map(JobHandle::join).sum::<u64>(), (0..20u64).map(|i| i * i).sum::<u64>());
+
+        // waiting on a job from a job runs queued work instead of blocking the worker
+        let outer = global().spawn(|| {
+            let inner: Vec<JobHandle<u32>> = (0..8).map(|i| global().spawn(move || i)).collect();
+            inner.into_iter().map(JobHandle::join).sum::<u32>()
+        });
+        assert_eq!(outer.join(), 28);
+
+        let mut values: Vec<u32> = (0..1000).collect();
+        jobs.parallel_for(&mut values, |value| *value *= 2);
+        assert!(values.iter().enumerate().all(|(i, &value)| value == i as u32 * 2));
+        assert_eq!(jobs.parallel_map(&mut values, |value| *value + 1)[999], 1999);
+        assert_eq!(jobs.map_chunks(&values, 4, |run| run.len()), vec![250; 4]);
+
+        let total = AtomicUsize::new(0);
+        jobs.scope(|scope| {
+            for i in 0..10 {
+                let total = &total;
+                scope.spawn(move || { total.fetch_add(i, Ordering::SeqCst); });
+            }
+        });
+        assert_eq!(total.load(Ordering::SeqCst), 45);
+
+        let failed = panic::catch_unwind(AssertUnwindSafe(|| jobs.spawn(|| panic!("job failed")).join()));
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn runs_a_graph_in_dependency_order() {
+        let jobs = JobSystem::new(4);
+        let order = Mutex::new(Vec::new());
+        let mut graph = JobGraph::new();
+        let record = |name: &'static str| {
+            let order = &order;
+            move || order.lock().unwrap().push(name)
+        };
+        let animate = graph.add("animate", record("animate"));
+        let physics = graph.add("physics", record("physics"));
+        let skin = graph.add_after("skin", &[animate], record("skin"));
+        let cull = graph.add_after("cull", &[skin, physics], record("cull"));
+        assert_eq!(graph.name(cull), "cull");
+        graph.run(&jobs);
+
+        let order = order.into_inner().unwrap();
+        let position = |name| order.iter().position(|&done| done == name).unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(position("animate") < position("skin"));
+        assert!(position("skin") < position("cull") && position("physics") < position("cull"));
+    }
+}
@@ -1,11 +1,79 @@
 use cgmath;
+use cgmath::prelude::*;
 
+#[cfg(not(feature = "f64"))]
 pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
 pub type RasterFloat = f32;
 pub type TimeSec = f64;
+pub type Point2 = cgmath::Point2<Float>;
 pub type Point3 = cgmath::Point3<Float>;
+pub type Vector2 = cgmath::Vector2<Float>;
 pub type Vector3 = cgmath::Vector3<Float>;
+pub type Vector4 = cgmath::Vector4<Float>;
+pub type Matrix3 = cgmath::Matrix3<Float>;
 pub type Matrix4 = cgmath::Matrix4<Float>;
+pub type Quaternion = cgmath::Quaternion<Float>;
+pub type Rad = cgmath::Rad<Float>;
+pub type Deg = cgmath::Deg<Float>;
+
+/// Absolute positions in large worlds, always double precision (see `large_world`)
+pub type WorldPoint3 = cgmath::Point3<f64>;
+pub type WorldVector3 = cgmath::Vector3<f64>;
+
+/// Single precision copies for GL uploads, no-ops unless the `f64` feature is enabled
+#[allow(clippy::unnecessary_cast)]
+pub fn gl_float(value: Float) -> f32 {
+    value as f32
+}
+
+pub fn gl_vector3(vector: &Vector3) -> cgmath::Vector3<f32> {
+    vector.cast().expect("Vector3 component out of f32 range")
+}
+
+pub fn gl_matrix4(matrix: &Matrix4) -> cgmath::Matrix4<f32> {
+    matrix.cast().expect("Matrix4 component out of f32 range")
+}
+
+pub fn vec2(x: Float, y: Float) -> Vector2 {
+    Vector2::new(x, y)
+}
+
+pub fn vec3(x: Float, y: Float, z: Float) -> Vector3 {
+    Vector3::new(x, y, z)
+}
+
+pub fn vec4(x: Float, y: Float, z: Float, w: Float) -> Vector4 {
+    Vector4::new(x, y, z, w)
+}
+
+pub fn point3(x: Float, y: Float, z: Float) -> Point3 {
+    Point3::new(x, y, z)
+}
+
+pub fn deg(degrees: Float) -> Deg {
+    cgmath::Deg(degrees)
+}
+
+pub fn rad(radians: Float) -> Rad {
+    cgmath::Rad(radians)
+}
+
+/// Rotation applying `roll` (around Z), then `pitch` (around X), then `yaw` (around Y)
+pub fn quat_from_yaw_pitch_roll(yaw: Rad, pitch: Rad, roll: Rad) -> Quaternion {
+    Quaternion::from_angle_y(yaw) * Quaternion::from_angle_x(pitch) * Quaternion::from_angle_z(roll)
+}
+
+/// Upper 3x3 of a matrix, drops the translation
+pub fn matrix3_from(matrix: &Matrix4) -> Matrix3 {
+    Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate())
+}
+
+/// Inverse transpose of the model matrix's upper 3x3, transforms normals under non-uniform scale
+pub fn normal_matrix(model: &Matrix4) -> Matrix3 {
+    matrix3_from(model).invert().unwrap_or_else(Matrix3::identity).transpose()
+}
 
 #[derive(Debug, PartialEq)]
 pub enum Direction {
@@ -0,0 +1,46 @@
+use lang::common::Float;
+
+pub const PI: Float = ::std::f64::consts::PI as Float;
+
+pub fn clamp(value: Float, min: Float, max: Float) -> Float {
+    value.clamp(min, max)
+}
+
+pub fn lerp(from: Float, to: Float, t: Float) -> Float {
+    from + (to - from) * t
+}
+
+/// Hermite interpolation between 0 at `edge0` and 1 at `edge1`, like GLSL's `smoothstep`
+pub fn smoothstep(edge0: Float, edge1: Float, x: Float) -> Float {
+    let t = clamp((x - edge0) / (edge1 - edge0), 0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Wraps an angle in radians into `(-PI, PI]`
+pub fn wrap_angle(angle: Float) -> Float {
+    let wrapped = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if wrapped == -PI { PI } else { wrapped }
+}
+
+/// Wraps an angle in degrees into `(-180, 180]`
+pub fn wrap_degrees(angle: Float) -> Float {
+    let wrapped = (angle + 180.0).rem_euclid(360.0) - 180.0;
+    if wrapped == -180.0 { 180.0 } else { wrapped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_and_wraps() {
+        assert_eq!(lerp(2.0, 4.0, 0.25), 2.5);
+        assert_eq!(smoothstep(0.0, 2.0, -1.0), 0.0);
+        assert_eq!(smoothstep(0.0, 2.0, 1.0), 0.5);
+        assert_eq!(smoothstep(0.0, 2.0, 3.0), 1.0);
+        assert_eq!(wrap_degrees(190.0), -170.0);
+        assert_eq!(wrap_degrees(-180.0), 180.0);
+        assert_eq!(wrap_degrees(-340.0), 20.0);
+        assert!((wrap_angle(3.0 * PI / 2.0) + PI / 2.0).abs() < 1e-5);
+    }
+}
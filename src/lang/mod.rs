@@ -1,7 +1,13 @@
 pub mod common;
+pub mod math;
 pub mod object;
+pub mod rng;
 pub mod str;
 
+/// The math traits (`InnerSpace`, `SquareMatrix`, `Rotation3`...) the `lang` types need in scope
+pub use cgmath::prelude;
+
 pub use self::common::*;
+pub use self::math::*;
 pub use self::object::*;
 pub use self::str::*;
@@ -0,0 +1,336 @@
+//! Deterministic random numbers: a seedable PCG32 generator whose streams let each system
+//! (particles, terrain, AI) draw its own sequence from one world seed, so adding draws to one
+//! system doesn't change what the others get. The same seed always gives the same values on
+//! every platform, which replays and tests rely on.
+//!
+//! The gradient noise functions hash the lattice coordinates with `hash` instead of a
+//! permutation table, so a shader can compute the same values with unsigned integer math.
+
+use cgmath::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use lang::common::{Float, Vector2, Vector3, Point3};
+use lang::math::PI;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+/// PCG32 generator (O'Neill), 64 bits of state, 2^63 selectable streams
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::new(0)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_stream(seed, 0)
+    }
+
+    /// Generator for one of the independent sequences of `seed`
+    pub fn with_stream(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Stream of `seed` named after a system, e.g. `Rng::for_system(seed, "particles")`
+    pub fn for_system(seed: u64, system: &str) -> Rng {
+        // FNV-1a of the name
+        let stream = system.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        Rng::with_stream(seed, stream)
+    }
+
+    /// New generator seeded from this one, e.g. one per spawned emitter
+    pub fn fork(&mut self) -> Rng {
+        let seed = self.next_u64();
+        let stream = self.next_u64();
+        Rng::with_stream(seed, stream)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_float(&mut self) -> Float {
+        // 24 bits are exact in f32, the result never rounds up to 1
+        (self.next_u32() >> 8) as Float / 16777216.0
+    }
+
+    /// Uniform in `[min, max)`
+    pub fn range(&mut self, min: Float, max: Float) -> Float {
+        min + (max - min) * self.next_float()
+    }
+
+    /// Uniform integer in `[min, max)`, `min` when the range is empty
+    pub fn range_int(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        let span = (max as i64 - min as i64) as u64;
+        min + ((self.next_u32() as u64 * span) >> 32) as i32
+    }
+
+    /// True with probability `p`
+    pub fn chance(&mut self, p: Float) -> bool {
+        self.next_float() < p
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        Some(&items[self.range_int(0, items.len() as i32) as usize])
+    }
+
+    /// Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.range_int(0, i as i32 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+
+    /// Standard normal distribution, Box-Muller
+    pub fn gaussian(&mut self) -> Float {
+        let u = 1.0 - self.next_float();
+        let v = self.next_float();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+
+    /// Uniform direction in the plane
+    pub fn unit_vector2(&mut self) -> Vector2 {
+        let angle = self.range(0.0, 2.0 * PI);
+        Vector2::new(angle.cos(), angle.sin())
+    }
+
+    /// Uniform direction in space, a point on the unit sphere
+    pub fn unit_vector(&mut self) -> Vector3 {
+        let z = self.range(-1.0, 1.0);
+        let angle = self.range(0.0, 2.0 * PI);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        Vector3::new(r * angle.cos(), r * angle.sin(), z)
+    }
+
+    /// Uniform point on the sphere surface
+    pub fn on_sphere(&mut self, center: Point3, radius: Float) -> Point3 {
+        center + self.unit_vector() * radius
+    }
+
+    /// Uniform point inside the sphere
+    pub fn in_sphere(&mut self, center: Point3, radius: Float) -> Point3 {
+        center + self.unit_vector() * (radius * self.next_float().cbrt())
+    }
+
+    /// Uniform point inside the disk
+    pub fn in_disk(&mut self, radius: Float) -> Vector2 {
+        self.unit_vector2() * (radius * self.next_float().sqrt())
+    }
+
+    /// Uniform direction within `angle` radians of `axis`, e.g. for emitter cones
+    pub fn in_cone(&mut self, axis: Vector3, angle: Float) -> Vector3 {
+        let axis = axis.normalize();
+        let z = self.range(angle.cos(), 1.0);
+        let around = self.range(0.0, 2.0 * PI);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let helper = if axis.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let tangent = axis.cross(helper).normalize();
+        let bitangent = axis.cross(tangent);
+        tangent * (r * around.cos()) + bitangent * (r * around.sin()) + axis * z
+    }
+}
+
+/// PCG integer hash (Jarzynski and Olano), the GLSL side uses the same constants
+pub fn hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Hash of a 2D lattice point
+pub fn hash2(x: i32, y: i32, seed: u32) -> u32 {
+    hash((x as u32) ^ hash((y as u32) ^ hash(seed)))
+}
+
+/// Hash of a 3D lattice point
+pub fn hash3(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    hash((x as u32) ^ hash((y as u32) ^ hash((z as u32) ^ hash(seed))))
+}
+
+fn fade(t: Float) -> Float {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: Float, b: Float, t: Float) -> Float {
+    a + (b - a) * t
+}
+
+/// Dot of the offset with one of 8 directions picked by the hash
+fn gradient2(hash: u32, x: Float, y: Float) -> Float {
+    match hash & 7 {
+        0 => x + y,
+        1 => x - y,
+        2 => -x + y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+/// Dot of the offset with one of the 12 cube edge directions picked by the hash
+fn gradient3(hash: u32, x: Float, y: Float, z: Float) -> Float {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Improved Perlin noise, about `[-1, 1]`, 0 at integer coordinates
+pub fn perlin2(x: Float, y: Float, seed: u32) -> Float {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (fx, fy) = (x - x0, y - y0);
+    let (u, v) = (fade(fx), fade(fy));
+    let corner = |dx: i32, dy: i32| gradient2(hash2(ix + dx, iy + dy, seed), fx - dx as Float, fy - dy as Float);
+    lerp(lerp(corner(0, 0), corner(1, 0), u), lerp(corner(0, 1), corner(1, 1), u), v)
+}
+
+/// Improved Perlin noise, about `[-1, 1]`, 0 at integer coordinates
+pub fn perlin3(point: Point3, seed: u32) -> Float {
+    let floor = Point3::new(point.x.floor(), point.y.floor(), point.z.floor());
+    let (ix, iy, iz) = (floor.x as i32, floor.y as i32, floor.z as i32);
+    let f = point - floor;
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        gradient3(hash3(ix + dx, iy + dy, iz + dz, seed), f.x - dx as Float, f.y - dy as Float, f.z - dz as Float)
+    };
+    lerp(
+        lerp(lerp(corner(0, 0, 0), corner(1, 0, 0), u), lerp(corner(0, 1, 0), corner(1, 1, 0), u), v),
+        lerp(lerp(corner(0, 0, 1), corner(1, 0, 1), u), lerp(corner(0, 1, 1), corner(1, 1, 1), u), v),
+        w,
+    )
+}
+
+/// Simplex noise (Perlin 2001, after Gustavson), about `[-1, 1]`, cheaper than `perlin2` and
+/// without its axis aligned artifacts
+pub fn simplex2(x: Float, y: Float, seed: u32) -> Float {
+    let skew = 0.5 * (3.0 as Float).sqrt() - 0.5;
+    let unskew = (3.0 - (3.0 as Float).sqrt()) / 6.0;
+    let s = (x + y) * skew;
+    let (i, j) = ((x + s).floor(), (y + s).floor());
+    let t = (i + j) * unskew;
+    let (x0, y0) = (x - (i - t), y - (j - t));
+    // the triangle of the skewed cell the point is in
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let corners = [
+        (0, 0, x0, y0),
+        (i1, j1, x0 - i1 as Float + unskew, y0 - j1 as Float + unskew),
+        (1, 1, x0 - 1.0 + 2.0 * unskew, y0 - 1.0 + 2.0 * unskew),
+    ];
+    let (i, j) = (i as i32, j as i32);
+    let total: Float = corners.iter()
+        .map(|&(di, dj, dx, dy)| {
+            let falloff = 0.5 - dx * dx - dy * dy;
+            if falloff <= 0.0 {
+                0.0
+            } else {
+                let falloff = falloff * falloff;
+                falloff * falloff * gradient2(hash2(i + di, j + dj, seed), dx, dy)
+            }
+        })
+        .sum();
+    70.0 * total
+}
+
+/// Simplex noise in 3D, about `[-1, 1]`
+pub fn simplex3(point: Point3, seed: u32) -> Float {
+    let (skew, unskew) = (1.0 / 3.0, 1.0 / 6.0);
+    let s = (point.x + point.y + point.z) * skew;
+    let (i, j, k) = ((point.x + s).floor(), (point.y + s).floor(), (point.z + s).floor());
+    let t = (i + j + k) * unskew;
+    let (x0, y0, z0) = (point.x - (i - t), point.y - (j - t), point.z - (k - t));
+    // the two middle corners of the cell's tetrahedron the point is in
+    let ((i1, j1, k1), (i2, j2, k2)) = if x0 >= y0 {
+        if y0 >= z0 { ((1, 0, 0), (1, 1, 0)) }
+        else if x0 >= z0 { ((1, 0, 0), (1, 0, 1)) }
+        else { ((0, 0, 1), (1, 0, 1)) }
+    } else if y0 < z0 { ((0, 0, 1), (0, 1, 1)) }
+    else if x0 < z0 { ((0, 1, 0), (0, 1, 1)) }
+    else { ((0, 1, 0), (1, 1, 0)) };
+    let corner = |di: i32, dj: i32, dk: i32, n: Float| {
+        let (dx, dy, dz) = (x0 - di as Float + n * unskew, y0 - dj as Float + n * unskew, z0 - dk as Float + n * unskew);
+        let falloff = 0.6 - dx * dx - dy * dy - dz * dz;
+        if falloff <= 0.0 {
+            return 0.0;
+        }
+        let falloff = falloff * falloff;
+        falloff * falloff * gradient3(hash3(i as i32 + di, j as i32 + dj, k as i32 + dk, seed), dx, dy, dz)
+    };
+    32.0 * (corner(0, 0, 0, 0.0) + corner(i1, j1, k1, 1.0) + corner(i2, j2, k2, 2.0) + corner(1, 1, 1, 3.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_repeat_per_seed_and_stream() {
+        let draw = |mut rng: Rng| (0..4).map(|_| rng.next_u32()).collect::<Vec<_>>();
+        assert_eq!(draw(Rng::new(42)), draw(Rng::new(42)));
+        assert_ne!(draw(Rng::new(42)), draw(Rng::new(43)));
+        assert_ne!(draw(Rng::for_system(42, "particles")), draw(Rng::for_system(42, "terrain")));
+        // the reference PCG32 output for seed 42, stream 54
+        assert_eq!(draw(Rng::with_stream(42, 54)), vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293]);
+
+        let mut rng = Rng::new(7);
+        let mut forked = rng.fork();
+        assert_ne!(rng.next_u32(), forked.next_u32());
+        for _ in 0..1000 {
+            let value = rng.range(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&value));
+            assert!((5..9).contains(&rng.range_int(5, 9)));
+            assert!((rng.unit_vector().magnitude() - 1.0).abs() < 1e-4);
+            assert!(rng.in_disk(2.0).magnitude() <= 2.0);
+            assert!(rng.in_cone(Vector3::unit_y(), 0.3).angle(Vector3::unit_y()).0 <= 0.3 + 1e-4);
+        }
+        let mut items = [1, 2, 3, 4, 5];
+        rng.shuffle(&mut items);
+        items.sort();
+        assert_eq!(items, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn noise_is_smooth_and_bounded() {
+        assert_eq!(perlin2(3.0, -2.0, 1), 0.0);
+        assert_eq!(perlin3(Point3::new(1.0, 2.0, 3.0), 1), 0.0);
+        let mut rng = Rng::new(1);
+        for _ in 0..2000 {
+            let (x, y, z) = (rng.range(-50.0, 50.0), rng.range(-50.0, 50.0), rng.range(-50.0, 50.0));
+            let p = Point3::new(x, y, z);
+            for value in [perlin2(x, y, 3), perlin3(p, 3), simplex2(x, y, 3), simplex3(p, 3)].iter() {
+                assert!(value.abs() <= 1.05, "{}", value);
+            }
+            // a small step barely changes the value
+            assert!((simplex3(p, 3) - simplex3(p + Vector3::new(0.001, 0.0, 0.0), 3)).abs() < 0.05);
+            assert!((perlin2(x, y, 3) - perlin2(x, y + 0.001, 3)).abs() < 0.05);
+        }
+        assert_ne!(simplex2(0.3, 0.7, 1), simplex2(0.3, 0.7, 2));
+    }
+}
@@ -0,0 +1,142 @@
+//! Floating origin for worlds larger than f32 can address precisely: absolute positions are
+//! kept as `WorldPoint3` (f64), the engine works in local coordinates around an origin that is
+//! periodically moved to the camera, and shaders get camera-relative matrices.
+
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Vector3, Matrix4, WorldPoint3, WorldVector3};
+use bounds::{Aabb, BoundingSphere};
+use camera::Camera;
+
+/// Something with local coordinates that has to move when the origin does
+pub trait Rebase {
+    /// `shift` is added to every local position
+    fn rebase(&mut self, shift: Vector3);
+}
+
+impl Rebase for Point3 {
+    fn rebase(&mut self, shift: Vector3) {
+        *self += shift;
+    }
+}
+
+/// Moves the translation of a model matrix
+impl Rebase for Matrix4 {
+    fn rebase(&mut self, shift: Vector3) {
+        self.w += shift.extend(0.0);
+    }
+}
+
+impl Rebase for Aabb {
+    fn rebase(&mut self, shift: Vector3) {
+        if !self.is_empty() {
+            self.min += shift;
+            self.max += shift;
+        }
+    }
+}
+
+impl Rebase for BoundingSphere {
+    fn rebase(&mut self, shift: Vector3) {
+        self.center += shift;
+    }
+}
+
+impl Rebase for Camera {
+    fn rebase(&mut self, shift: Vector3) {
+        self.position += shift;
+    }
+}
+
+pub fn rebase_all<T: Rebase>(items: &mut [T], shift: Vector3) {
+    for item in items.iter_mut() {
+        item.rebase(shift);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FloatingOrigin {
+    /// world position of the local origin
+    pub origin: WorldPoint3,
+    /// distance from the local origin the camera may move before the origin follows it
+    pub threshold: Float,
+}
+
+impl FloatingOrigin {
+    pub fn new(threshold: Float) -> FloatingOrigin {
+        FloatingOrigin { origin: WorldPoint3::origin(), threshold }
+    }
+
+    pub fn to_local(&self, world: WorldPoint3) -> Point3 {
+        Point3::from_vec(to_local_vector(world - self.origin))
+    }
+
+    pub fn to_world(&self, local: Point3) -> WorldPoint3 {
+        self.origin + WorldVector3::new(local.x as f64, local.y as f64, local.z as f64)
+    }
+
+    /// Moves the origin to the camera once it is farther than `threshold` from it. The camera
+    /// is rebased, and the returned shift has to be applied to every other local position,
+    /// e.g. with `rebase_all`.
+    pub fn update(&mut self, camera: &mut Camera) -> Option<Vector3> {
+        if camera.position.to_vec().magnitude() <= self.threshold {
+            return None;
+        }
+
+        let new_origin = self.to_world(camera.position);
+        let shift = to_local_vector(self.origin - new_origin);
+        self.origin = new_origin;
+        camera.rebase(shift);
+        Some(shift)
+    }
+
+    /// Offset of a world position from the camera, computed in double precision
+    pub fn camera_relative(&self, world: WorldPoint3, camera: &Camera) -> Vector3 {
+        to_local_vector(world - self.to_world(camera.position))
+    }
+}
+
+/// Model-view matrix computed without ever forming the (large) world translation in single
+/// precision: the model translation is made relative to the camera first. Upload it as the
+/// shader's model-view matrix together with the plain projection.
+pub fn camera_relative_model_view(camera: &Camera, model: &Matrix4) -> Matrix4 {
+    let mut relative = *model;
+    relative.rebase(-camera.position.to_vec());
+    camera.rotation_view_matrix() * relative
+}
+
+fn to_local_vector(vector: WorldVector3) -> Vector3 {
+    Vector3::new(vector.x as Float, vector.y as Float, vector.z as Float)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebases_around_camera() {
+        let mut origin = FloatingOrigin::new(100.0);
+        let mut camera = Camera { position: Point3::new(50.0, 0.0, 0.0), ..Camera::default() };
+        assert_eq!(origin.update(&mut camera), None);
+
+        camera.position = Point3::new(150.0, 0.0, 0.0);
+        let mut objects = [Point3::new(160.0, 1.0, 0.0)];
+        let shift = origin.update(&mut camera).unwrap();
+        rebase_all(&mut objects, shift);
+        assert_eq!(origin.origin, WorldPoint3::new(150.0, 0.0, 0.0));
+        assert_eq!(camera.position, Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(objects[0], Point3::new(10.0, 1.0, 0.0));
+        assert_eq!(origin.to_world(objects[0]), WorldPoint3::new(160.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn relative_model_view_matches_view_times_model() {
+        let camera = Camera { position: Point3::new(3.0, 2.0, 1.0), ..Camera::default() };
+        let model = Matrix4::from_translation(Vector3::new(5.0, -1.0, 2.0)) * Matrix4::from_scale(2.0);
+        let expected = camera.view_matrix() * model;
+        let actual = camera_relative_model_view(&camera, &model);
+        for (a, b) in AsRef::<[Float; 16]>::as_ref(&actual).iter().zip(AsRef::<[Float; 16]>::as_ref(&expected).iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+}
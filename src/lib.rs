@@ -1,10 +1,52 @@
 pub extern crate gl;
 pub extern crate glfw;
 pub extern crate cgmath;
+extern crate log;
+extern crate num_traits;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
 
+#[macro_use]
 pub mod lang;
+#[macro_use]
+pub mod logging;
+pub mod animation;
+pub mod atmosphere;
+pub mod bounds;
+pub mod buffer;
+pub mod builtin;
 pub mod camera;
+pub mod color;
+pub mod compute;
+pub mod config;
+pub mod console;
+pub mod error;
+pub mod gl_state;
+pub mod helpers;
 pub mod input;
+pub mod jobs;
+pub mod large_world;
+pub mod lines;
+pub mod material;
+pub mod mesh;
+pub mod nav;
+pub mod noise;
+pub mod picking;
+pub mod profiling;
+pub mod ray;
+pub mod readback;
+pub mod recorder;
+pub mod render_target;
+pub mod renderer;
 pub mod shader;
+pub mod spatial;
+pub mod streaming;
+pub mod terrain;
+pub mod testing;
+pub mod texture;
 pub mod timing;
+pub mod vfs;
+pub mod viewport;
+pub mod water;
 pub mod window;
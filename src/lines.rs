@@ -0,0 +1,434 @@
+use std::mem;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{Float, Point2, Point3, Vector2, Vector4, Matrix4, PI, gl_float, vec2};
+use gl_state::GlState;
+use shader::Shader;
+use viewport::Viewport;
+
+const LINE_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec2 aEdgeAlong;
+layout (location = 2) in vec4 aStyle;
+layout (location = 3) in vec4 aColor;
+
+out float Edge;
+out float Along;
+out vec4 Style;
+out vec4 Color;
+
+uniform vec2 viewportSize;
+
+void main()
+{
+    Edge = aEdgeAlong.x;
+    Along = aEdgeAlong.y;
+    Style = aStyle;
+    Color = aColor;
+    gl_Position = vec4(aPos.xy / viewportSize * 2.0 - 1.0, aPos.z, 1.0);
+}
+"#;
+
+const LINE_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+in float Edge;
+in float Along;
+// half width, feather, dash length, gap length
+in vec4 Style;
+in vec4 Color;
+
+void main()
+{
+    if (Style.z > 0.0 && mod(Along, Style.z + Style.w) > Style.z)
+        discard;
+    float alpha = clamp((Style.x + Style.y * 0.5 - abs(Edge)) / max(Style.y, 0.0001), 0.0, 1.0);
+    FragColor = vec4(Color.rgb, Color.a * alpha);
+}
+"#;
+
+/// Joins longer than this many half widths are beveled instead of mitered
+const MITER_LIMIT: Float = 4.0;
+/// `w` of the plane world lines are clipped against, just in front of the eye
+const CLIP_W: Float = 1e-5;
+
+/// How consecutive segments are connected
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LineJoin {
+    Miter,
+    Bevel,
+    Round,
+}
+
+/// Look of a line, `width`, `feather` and dashes are in pixels
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LineStyle {
+    pub width: Float,
+    pub color: [Float; 4],
+    pub join: LineJoin,
+    /// dash and gap length, `None` draws a solid line
+    pub dash: Option<(Float, Float)>,
+    /// width of the anti-aliased fade at both edges
+    pub feather: Float,
+}
+
+impl LineStyle {
+    pub fn new(width: Float) -> LineStyle {
+        LineStyle { width, color: [1.0, 1.0, 1.0, 1.0], join: LineJoin::Miter, dash: None, feather: 1.0 }
+    }
+
+    pub fn color(mut self, r: Float, g: Float, b: Float, a: Float) -> LineStyle {
+        self.color = [r, g, b, a];
+        self
+    }
+
+    pub fn join(mut self, join: LineJoin) -> LineStyle {
+        self.join = join;
+        self
+    }
+
+    pub fn dashed(mut self, dash: Float, gap: Float) -> LineStyle {
+        self.dash = Some((dash, gap));
+        self
+    }
+
+    pub fn feather(mut self, feather: Float) -> LineStyle {
+        self.feather = feather;
+        self
+    }
+}
+
+impl Default for LineStyle {
+    fn default() -> LineStyle {
+        LineStyle::new(1.0)
+    }
+}
+
+/// position in viewport pixels and NDC depth, signed distance from the center line and
+/// distance along it in pixels, then the style and color
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub edge_along: [f32; 2],
+    pub style: [f32; 4],
+    pub color: [f32; 4],
+}
+
+/// Expands polylines into triangles on the CPU, see `LineRenderer`
+#[derive(Clone, Debug)]
+pub struct LineBatch {
+    pub vertices: Vec<LineVertex>,
+    viewport: Viewport,
+    view_projection: Matrix4,
+}
+
+impl LineBatch {
+    pub fn new(viewport: Viewport) -> LineBatch {
+        LineBatch { vertices: vec![], viewport, view_projection: Matrix4::identity() }
+    }
+
+    /// Viewport and camera the following world-space lines are projected with
+    pub fn set_view(&mut self, viewport: Viewport, view_projection: Matrix4) {
+        self.viewport = viewport;
+        self.view_projection = view_projection;
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    /// Polyline in viewport pixels with a bottom-left origin, drawn in front of everything
+    pub fn screen_polyline(&mut self, points: &[Point2], style: &LineStyle) {
+        let run: Vec<(Vector2, Float)> = points.iter().map(|point| (point.to_vec(), -1.0)).collect();
+        self.add_run(&run, style);
+    }
+
+    /// Polyline in world space, keeping its pixel width at any distance.
+    /// Segments are clipped where they pass behind the camera, splitting the line there.
+    pub fn world_polyline(&mut self, points: &[Point3], style: &LineStyle) {
+        let size = vec2(self.viewport.width as Float, self.viewport.height as Float);
+        let to_screen = |clip: Vector4| {
+            let ndc = clip.truncate() / clip.w;
+            (vec2((ndc.x * 0.5 + 0.5) * size.x, (ndc.y * 0.5 + 0.5) * size.y), ndc.z)
+        };
+        let mut run = vec![];
+        let mut previous: Option<Vector4> = None;
+        for point in points {
+            let clip = self.view_projection * point.to_homogeneous();
+            let visible = clip.w > CLIP_W;
+            match previous {
+                None if visible => run.push(to_screen(clip)),
+                Some(previous) if (previous.w > CLIP_W) != visible => {
+                    // the segment crosses the plane, cut it there before the divide
+                    let t = (CLIP_W - previous.w) / (clip.w - previous.w);
+                    run.push(to_screen(previous + (clip - previous) * t));
+                    if visible {
+                        run.push(to_screen(clip));
+                    } else {
+                        self.add_run(&run, style);
+                        run.clear();
+                    }
+                },
+                Some(_) if visible => run.push(to_screen(clip)),
+                _ => (),
+            }
+            previous = Some(clip);
+        }
+        self.add_run(&run, style);
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    fn add_run(&mut self, points: &[(Vector2, Float)], style: &LineStyle) {
+        let mut run: Vec<(Vector2, Float)> = Vec::with_capacity(points.len());
+        for &point in points {
+            match run.last() {
+                Some(last) if (last.0 - point.0).magnitude2() < 1e-8 => (),
+                _ => run.push(point),
+            }
+        }
+        if run.len() < 2 {
+            return;
+        }
+
+        let extent = (style.width + style.feather) * 0.5;
+        let mut along = 0.0;
+        let mut previous_normal: Option<Vector2> = None;
+        for segment in run.windows(2) {
+            let ((a, a_depth), (b, b_depth)) = (segment[0], segment[1]);
+            let length = (b - a).magnitude();
+            let direction = (b - a) / length;
+            let normal = vec2(-direction.y, direction.x);
+
+            if let Some(previous_normal) = previous_normal {
+                self.join(a, a_depth, previous_normal, normal, along, extent, style);
+            }
+
+            let offset = normal * extent;
+            let corners = [
+                (a + offset, a_depth, extent, along),
+                (a - offset, a_depth, -extent, along),
+                (b + offset, b_depth, extent, along + length),
+                (b - offset, b_depth, -extent, along + length),
+            ];
+            for &i in [0, 1, 2, 2, 1, 3].iter() {
+                let (position, depth, edge, along) = corners[i];
+                self.push(position, depth, edge, along, style);
+            }
+
+            along += length;
+            previous_normal = Some(normal);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn join(&mut self, point: Vector2, depth: Float, from: Vector2, to: Vector2, along: Float,
+            extent: Float, style: &LineStyle) {
+        // z of the cross product of the segment normals, positive for a left turn
+        let turn = from.x * to.y - from.y * to.x;
+        if turn.abs() < 1e-6 {
+            return;
+        }
+        let side = if turn > 0.0 { -1.0 } else { 1.0 };
+        let (from, to) = (from * side, to * side);
+        let edge = extent * side;
+        let outer_from = point + from * extent;
+        let outer_to = point + to * extent;
+
+        let triangle = |batch: &mut LineBatch, b: Vector2, c: Vector2| {
+            batch.push(point, depth, 0.0, along, style);
+            batch.push(b, depth, edge, along, style);
+            batch.push(c, depth, edge, along, style);
+        };
+
+        match style.join {
+            LineJoin::Miter => {
+                let miter = (from + to).normalize();
+                let miter_length = extent / miter.dot(from);
+                if miter_length > MITER_LIMIT * extent {
+                    triangle(self, outer_from, outer_to);
+                } else {
+                    let tip = point + miter * miter_length;
+                    triangle(self, outer_from, tip);
+                    triangle(self, tip, outer_to);
+                }
+            },
+            LineJoin::Bevel => triangle(self, outer_from, outer_to),
+            LineJoin::Round => {
+                let angle = from.angle(to).0;
+                let steps = (angle.abs() / (PI / 8.0)).ceil().max(1.0) as usize;
+                let mut last = outer_from;
+                for step in 1..=steps {
+                    let t = angle * step as Float / steps as Float;
+                    let (sin, cos) = t.sin_cos();
+                    let rotated = vec2(from.x * cos - from.y * sin, from.x * sin + from.y * cos);
+                    let next = point + rotated * extent;
+                    triangle(self, last, next);
+                    last = next;
+                }
+            },
+        }
+    }
+
+    fn push(&mut self, position: Vector2, depth: Float, edge: Float, along: Float, style: &LineStyle) {
+        let (dash, gap) = style.dash.unwrap_or((0.0, 0.0));
+        let [r, g, b, a] = style.color;
+        self.vertices.push(LineVertex {
+            position: [gl_float(position.x), gl_float(position.y), gl_float(depth)],
+            edge_along: [gl_float(edge), gl_float(along)],
+            style: [gl_float(style.width * 0.5), gl_float(style.feather), gl_float(dash), gl_float(gap)],
+            color: [gl_float(r), gl_float(g), gl_float(b), gl_float(a)],
+        });
+    }
+}
+
+/// Points on a cubic Bézier curve, `segments + 1` of them, for use as a polyline
+pub fn cubic_bezier<P: EuclideanSpace<Scalar = Float>>(p0: P, p1: P, p2: P, p3: P, segments: usize) -> Vec<P> {
+    let segments = segments.max(1);
+    (0..=segments).map(|i| {
+        let t = i as Float / segments as Float;
+        let u = 1.0 - t;
+        P::from_vec(p0.to_vec() * (u * u * u) + p1.to_vec() * (3.0 * u * u * t) +
+                    p2.to_vec() * (3.0 * u * t * t) + p3.to_vec() * (t * t * t))
+    }).collect()
+}
+
+/// Draws lines of any pixel width as triangles, since `glLineWidth` above 1 is not
+/// supported on core profiles. Fill `lines` during the frame, then `draw` uploads and
+/// draws them with alpha blending and empties the batch.
+pub struct LineRenderer {
+    pub lines: LineBatch,
+    shader: Shader,
+    vao: u32,
+    vbo: u32,
+    capacity: usize,
+}
+
+impl LineRenderer {
+    pub fn new(viewport: Viewport) -> LineRenderer {
+        let mut renderer = LineRenderer {
+            lines: LineBatch::new(viewport),
+            shader: Shader::from_source(LINE_VERTEX_SHADER, LINE_FRAGMENT_SHADER),
+            vao: 0,
+            vbo: 0,
+            capacity: 0,
+        };
+        unsafe {
+            gl::GenVertexArrays(1, &mut renderer.vao);
+            gl::GenBuffers(1, &mut renderer.vbo);
+            gl::BindVertexArray(renderer.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, renderer.vbo);
+
+            let stride = mem::size_of::<LineVertex>() as GLsizei;
+            let mut offset = 0;
+            for (location, &size) in [3, 2, 4, 4].iter().enumerate() {
+                gl::EnableVertexAttribArray(location as GLuint);
+                gl::VertexAttribPointer(location as GLuint, size, gl::FLOAT, gl::FALSE, stride,
+                                        (offset * mem::size_of::<GLfloat>()) as *const GLvoid);
+                offset += size as usize;
+            }
+            gl::BindVertexArray(0);
+        }
+        renderer
+    }
+
+    pub fn draw(&mut self, state: &mut GlState) {
+        let vertices = &self.lines.vertices;
+        if vertices.is_empty() {
+            return;
+        }
+        let viewport = self.lines.viewport();
+
+        state.use_program(self.shader.ID);
+        state.bind_vertex_array(self.vao);
+        state.set_blend(true);
+        state.blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        // joins and segments don't share a winding
+        state.set_cull_face(false);
+        unsafe {
+            self.shader.setVec2(c_str!("viewportSize"), viewport.width as Float, viewport.height as Float);
+
+            let size = (vertices.len() * mem::size_of::<LineVertex>()) as GLsizeiptr;
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            if vertices.len() > self.capacity {
+                gl::BufferData(gl::ARRAY_BUFFER, size, vertices.as_ptr() as *const GLvoid, gl::STREAM_DRAW);
+                self.capacity = vertices.len();
+            } else {
+                gl::BufferSubData(gl::ARRAY_BUFFER, 0, size, vertices.as_ptr() as *const GLvoid);
+            }
+            gl::DrawArrays(gl::TRIANGLES, 0, vertices.len() as GLsizei);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.lines.clear();
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.vao = 0;
+        self.vbo = 0;
+        self.capacity = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Camera;
+
+    #[test]
+    fn expands_polylines_with_joins() {
+        let mut batch = LineBatch::new(Viewport::full(100, 100));
+        let points = [Point2::new(0.0, 0.0), Point2::new(10.0, 0.0), Point2::new(10.0, 10.0)];
+        batch.screen_polyline(&points, &LineStyle::new(4.0).feather(0.0).join(LineJoin::Bevel));
+        // two quads and one bevel triangle
+        assert_eq!(batch.vertices.len(), 6 + 6 + 3);
+        assert!(batch.vertices.iter().all(|vertex| vertex.edge_along[0].abs() <= 2.0));
+        assert_eq!(batch.vertices.last().unwrap().edge_along[1], 20.0);
+
+        batch.clear();
+        batch.screen_polyline(&points, &LineStyle::new(4.0).feather(0.0).join(LineJoin::Miter));
+        // the miter tip lies on the outer corner of the turn
+        let tip = batch.vertices[8].position;
+        assert!((tip[0] - 12.0).abs() < 1e-4 && (tip[1] + 2.0).abs() < 1e-4);
+
+        let curve = cubic_bezier(Point2::new(0.0, 0.0), Point2::new(0.0, 1.0), Point2::new(1.0, 1.0), Point2::new(1.0, 0.0), 4);
+        assert_eq!(curve.len(), 5);
+        assert_eq!(curve[2], Point2::new(0.5, 0.75));
+    }
+
+    #[test]
+    fn clips_world_lines_behind_the_eye() {
+        let camera = Camera::default();
+        let viewport = Viewport::full(800, 600);
+        let mut batch = LineBatch::new(viewport);
+        batch.set_view(viewport, camera.projection_matrix(800, 600) * camera.view_matrix());
+        // from in front of the camera to behind it
+        let (front, behind) = (camera.position + camera.front * 5.0 + camera.up, camera.position - camera.front * 5.0);
+        batch.world_polyline(&[front, behind], &LineStyle::new(2.0));
+        assert_eq!(batch.vertices.len(), 6);
+
+        // the visible end stays where it projects, the other flies off towards the clip plane
+        let start = camera.project(front, viewport).unwrap();
+        let start = vec2(start.x, 600.0 - start.y);
+        let lengths: Vec<Float> = batch.vertices.iter()
+            .map(|vertex| (vec2(vertex.position[0] as Float, vertex.position[1] as Float) - start).magnitude())
+            .collect();
+        assert!(lengths.iter().any(|&length| length < 2.0) && lengths.iter().any(|&length| length > 1000.0), "{:?}", lengths);
+
+        batch.clear();
+        batch.world_polyline(&[behind, behind - camera.up], &LineStyle::new(2.0));
+        assert!(batch.vertices.is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+//! Engine logging through the `log` facade. Messages use one target per subsystem so they can
+//! be filtered, and are tagged with the number of the frame they were emitted in. Install any
+//! `log` implementation, or `StderrLogger` for a quick start.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{self, Log, Level, Metadata, Record, SetLoggerError};
+
+pub const WINDOW: &str = "reactor::window";
+pub const INPUT: &str = "reactor::input";
+pub const SHADER: &str = "reactor::shader";
+pub const RESOURCES: &str = "reactor::resources";
+pub const RENDERER: &str = "reactor::renderer";
+
+static FRAME: AtomicUsize = AtomicUsize::new(0);
+
+/// Frames started by `Window::events_loop` so far
+pub fn frame() -> usize {
+    FRAME.load(Ordering::Relaxed)
+}
+
+pub(crate) fn next_frame() {
+    FRAME.fetch_add(1, Ordering::Relaxed);
+}
+
+macro_rules! engine_log {
+    ($level:expr, $target:expr, $($arg:tt)+) => {
+        ::log::log!(target: $target, $level, "[frame {}] {}", $crate::logging::frame(), format_args!($($arg)+))
+    }
+}
+
+macro_rules! engine_error {
+    ($target:expr, $($arg:tt)+) => { engine_log!(::log::Level::Error, $target, $($arg)+) }
+}
+
+macro_rules! engine_warn {
+    ($target:expr, $($arg:tt)+) => { engine_log!(::log::Level::Warn, $target, $($arg)+) }
+}
+
+macro_rules! engine_info {
+    ($target:expr, $($arg:tt)+) => { engine_log!(::log::Level::Info, $target, $($arg)+) }
+}
+
+macro_rules! engine_debug {
+    ($target:expr, $($arg:tt)+) => { engine_log!(::log::Level::Debug, $target, $($arg)+) }
+}
+
+/// Minimal logger writing `LEVEL target: message` lines to stderr
+pub struct StderrLogger {
+    level: Level,
+}
+
+impl StderrLogger {
+    /// Installs the logger globally, fails if another logger is already set
+    pub fn init(level: Level) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(StderrLogger { level }))?;
+        log::set_max_level(level.to_level_filter());
+        Ok(())
+    }
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(io::stderr(), "{:<5} {}: {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
@@ -0,0 +1,435 @@
+//! Data-driven materials compiled to GLSL at runtime
+//!
+//! A material file declares parameters and a stack of layers, each a GLSL snippet that
+//! edits a `Surface` (albedo, alpha, normal, emission), optionally blended over the layers
+//! below by a mask expression:
+//!
+//! ```toml
+//! name = "mossy_rock"
+//! includes = ["reactor/noise.glsl"]
+//!
+//! [parameters]
+//! albedoMap = { type = "sampler2D" }
+//! tint = { type = "vec3", default = [1.0, 1.0, 1.0] }
+//! moss = { type = "float", default = 0.5, min = 0.0, max = 1.0 }
+//!
+//! [[layers]]
+//! name = "base"
+//! code = "surface.albedo = texture(albedoMap, TexCoords).rgb * tint;"
+//!
+//! [[layers]]
+//! name = "moss"
+//! mask = "smoothstep(0.4, 0.6, fbmNoise3(WorldPos * 2.0, 7u, 4, 2.0, 0.5) * 0.5 + 0.5) * moss"
+//! code = "surface.albedo = vec3(0.2, 0.35, 0.1);"
+//! ```
+//!
+//! Snippets see `WorldPos`, `Normal`, `Tangent`, `Bitangent`, `TexCoords` and the parameters,
+//! and `normalFromMap(texel)` gives the world normal of a tangent-space normal map texel:
+//! `surface.normal = normalFromMap(texture(normalMap, TexCoords).rgb);`. The vertex stage is
+//! the builtin lit one and lit materials take the same light, shadow, occlusion and fog uniforms as
+//! `Builtins::lit`, so they render anywhere the builtin shaders do.
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt::Write;
+
+use gl;
+use serde::{Serialize, Deserialize};
+use toml;
+
+use builtin::LIT_VERTEX_SHADER;
+use error::{EngineError, EngineResult};
+use logging;
+use shader::{Defines, Preprocessed, Preprocessor, ProgramCache, Shader, ShaderReflection, UniformType, UniformValue};
+use texture::Texture;
+use vfs::Vfs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shading {
+    /// directional light, cascaded shadows and fog
+    #[default]
+    Lit,
+    /// albedo and emission with fog
+    Unlit,
+}
+
+/// A uniform the material exposes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParameterDefinition {
+    /// GLSL type, e.g. `vec3` or `sampler2D`
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// zero if missing, samplers have none
+    #[serde(default)]
+    pub default: Option<UniformValue>,
+    /// range hint for editors
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerDefinition {
+    pub name: String,
+    /// GLSL expression blending the layer over the ones below, in 0..1; the layer replaces
+    /// them if there's none
+    #[serde(default)]
+    pub mask: Option<String>,
+    /// statements editing `surface`
+    pub code: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct MaterialDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub shading: Shading,
+    /// files or engine snippets included before the layers
+    #[serde(default)]
+    pub includes: Vec<String>,
+    /// helper functions the layers can call
+    #[serde(default)]
+    pub functions: String,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, ParameterDefinition>,
+    #[serde(default)]
+    pub layers: Vec<LayerDefinition>,
+}
+
+/// Generated, not yet preprocessed sources of a material
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaterialSource {
+    pub vertex: String,
+    pub fragment: String,
+}
+
+const SURFACE_GLSL: &str = r#"
+struct Surface
+{
+    vec3 albedo;
+    float alpha;
+    vec3 normal;
+    vec3 emission;
+};
+
+Surface mixSurface(Surface below, Surface above, float mask)
+{
+    mask = clamp(mask, 0.0, 1.0);
+    return Surface(mix(below.albedo, above.albedo, mask), mix(below.alpha, above.alpha, mask),
+                   normalize(mix(below.normal, above.normal, mask)), mix(below.emission, above.emission, mask));
+}
+"#;
+
+const NORMAL_FROM_MAP_GLSL: &str = r#"
+vec3 normalFromMap(vec3 texel)
+{
+    return perturbNormal(Normal, Tangent, Bitangent, texel);
+}
+"#;
+
+const LIT_INPUTS_GLSL: &str = r#"
+// direction the light travels in
+uniform vec3 lightDirection;
+uniform vec3 lightColor;
+uniform vec3 ambient;
+"#;
+
+impl MaterialDefinition {
+    pub fn from_toml(text: &str) -> EngineResult<MaterialDefinition> {
+        toml::from_str(text).map_err(|error| EngineError::Config(error.to_string()))
+    }
+
+    pub fn load(vfs: &Vfs, path: &str) -> EngineResult<MaterialDefinition> {
+        let text = vfs.read_to_string(path)?;
+        MaterialDefinition::from_toml(&text).map_err(|error| EngineError::Config(format!("{}: {}", path, error)))
+    }
+
+    /// Declared type of every parameter, an error for unknown types or invalid names
+    pub fn parameter_types(&self) -> EngineResult<BTreeMap<&str, UniformType>> {
+        let mut types = BTreeMap::new();
+        for (name, parameter) in &self.parameters {
+            if !is_identifier(name) {
+                return Err(EngineError::InvalidSource(format!("material {}: parameter name {:?}", self.name, name)));
+            }
+            let ty = UniformType::from_glsl(&parameter.ty).ok_or_else(|| {
+                EngineError::InvalidSource(format!("material {}: unknown type {} of {}", self.name, parameter.ty, name))
+            })?;
+            types.insert(name.as_str(), ty);
+        }
+        Ok(types)
+    }
+
+    /// Generates the GLSL, see `Material::build` for the preprocessed program
+    pub fn compile(&self) -> EngineResult<MaterialSource> {
+        let types = self.parameter_types()?;
+        let mut fragment = String::from("#version 330 core\n#include \"reactor/fog.glsl\"\n#include \"reactor/normal_map.glsl\"\n");
+        if self.shading == Shading::Lit {
+            fragment.push_str("#include \"reactor/cascade_shadow.glsl\"\n#include \"reactor/ssao.glsl\"\n");
+        }
+        for include in &self.includes {
+            let _ = writeln!(fragment, "#include \"{}\"", include);
+        }
+        fragment.push_str("\nout vec4 FragColor;\nin vec3 WorldPos;\nin vec3 Normal;\nin vec3 Tangent;\nin vec3 Bitangent;\nin vec2 TexCoords;\nin float ViewDepth;\n");
+        if self.shading == Shading::Lit {
+            fragment.push_str(LIT_INPUTS_GLSL);
+        }
+
+        fragment.push_str("\n// parameters\n");
+        for (name, ty) in &types {
+            let _ = writeln!(fragment, "uniform {} {};", ty.glsl().unwrap_or("float"), name);
+        }
+        fragment.push_str(SURFACE_GLSL);
+        fragment.push_str(NORMAL_FROM_MAP_GLSL);
+        if !self.functions.trim().is_empty() {
+            let _ = writeln!(fragment, "\n{}", self.functions.trim_end());
+        }
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let _ = write!(fragment, "\n// layer {}\nvoid layer{}(inout Surface surface)\n{{\n{}\n}}\n",
+                           layer.name, index, layer.code.trim_end());
+            if let Some(ref mask) = layer.mask {
+                let _ = write!(fragment, "float layer{}Mask(Surface surface)\n{{\n    return {};\n}}\n", index, mask.trim());
+            }
+        }
+
+        fragment.push_str("\nvoid main()\n{\n    Surface surface = Surface(vec3(1.0), 1.0, normalize(Normal), vec3(0.0));\n");
+        for (index, layer) in self.layers.iter().enumerate() {
+            if layer.mask.is_some() {
+                let _ = writeln!(fragment, "    {{\n        Surface above = surface;\n        layer{0}(above);\n        \
+                                            surface = mixSurface(surface, above, layer{0}Mask(surface));\n    }}", index);
+            } else {
+                let _ = writeln!(fragment, "    layer{}(surface);", index);
+            }
+        }
+        fragment.push_str(match self.shading {
+            Shading::Lit => "    vec3 normal = normalize(surface.normal);\n    \
+                             float diffuse = max(dot(normal, -normalize(lightDirection)), 0.0);\n    \
+                             float bias = max(0.002 * (1.0 - diffuse), 0.0005);\n    \
+                             float lit = cascadeShadow(WorldPos, ViewDepth, bias);\n    \
+                             vec3 color = surface.albedo * (ambient * ambientOcclusion() + lightColor * diffuse * lit) + surface.emission;\n",
+            Shading::Unlit => "    vec3 color = surface.albedo + surface.emission;\n",
+        });
+        fragment.push_str("    FragColor = vec4(applyFog(color, WorldPos), surface.alpha);\n}\n");
+
+        Ok(MaterialSource { vertex: LIT_VERTEX_SHADER.trim_start().to_string(), fragment })
+    }
+
+    /// `compile` through `preprocessor`, errors point at `materials/<name>.frag` lines
+    pub fn preprocess(&self, preprocessor: &Preprocessor, defines: &Defines) -> EngineResult<(Preprocessed, Preprocessed)> {
+        let source = self.compile()?;
+        let vertex = preprocessor.process(&format!("materials/{}.vert", self.name), &source.vertex, defines)?;
+        let fragment = preprocessor.process(&format!("materials/{}.frag", self.name), &source.fragment, defines)?;
+        Ok((vertex, fragment))
+    }
+}
+
+/// An exposed parameter as an editor lists it
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialParameter<'a> {
+    pub name: &'a str,
+    pub ty: UniformType,
+    /// `None` for samplers
+    pub value: Option<&'a UniformValue>,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+/// A built material: program, reflection and current parameter values
+pub struct Material {
+    pub definition: MaterialDefinition,
+    pub shader: Shader,
+    pub reflection: ShaderReflection,
+    values: BTreeMap<String, UniformValue>,
+    /// texture per sampler parameter, bound on consecutive units from 0
+    textures: BTreeMap<String, u32>,
+}
+
+impl Material {
+    pub fn build(definition: MaterialDefinition, preprocessor: &Preprocessor, defines: &Defines) -> EngineResult<Material> {
+        let (vertex, fragment) = definition.preprocess(preprocessor, defines)?;
+        let shader = Shader::try_from_preprocessed(&vertex, &fragment)?;
+        Material::with_shader(definition, shader)
+    }
+
+    /// `build` loading the program from `cache` when it's there
+    pub fn build_cached(definition: MaterialDefinition, preprocessor: &Preprocessor, defines: &Defines,
+                        cache: &mut ProgramCache) -> EngineResult<Material> {
+        let (vertex, fragment) = definition.preprocess(preprocessor, defines)?;
+        let shader = cache.from_preprocessed(&vertex, &fragment)?;
+        Material::with_shader(definition, shader)
+    }
+
+    fn with_shader(definition: MaterialDefinition, shader: Shader) -> EngineResult<Material> {
+        let reflection = shader.reflect();
+        let mut values = BTreeMap::new();
+        for (name, ty) in definition.parameter_types()? {
+            if ty.is_sampler() {
+                continue;
+            }
+            if reflection.uniform(name).is_none() {
+                engine_debug!(logging::SHADER, "material {}: parameter {} is unused", definition.name, name);
+            }
+            let value = definition.parameters[name].default.clone().unwrap_or_else(|| UniformValue::zero(ty));
+            values.insert(name.to_string(), value);
+        }
+        engine_debug!(logging::SHADER, "built material {} as program {}", definition.name, shader.ID);
+        Ok(Material { definition, shader, reflection, values, textures: BTreeMap::new() })
+    }
+
+    /// The parameters the program actually uses, in name order
+    pub fn parameters(&self) -> Vec<MaterialParameter<'_>> {
+        let mut parameters = vec![];
+        for (name, definition) in &self.definition.parameters {
+            let ty = match (self.reflection.uniform(name), self.reflection.sampler(name)) {
+                (Some(uniform), _) => uniform.ty,
+                (None, Some(sampler)) => UniformType::Sampler(sampler.ty),
+                (None, None) => continue,
+            };
+            parameters.push(MaterialParameter {
+                name,
+                ty,
+                value: self.values.get(name),
+                min: definition.min,
+                max: definition.max,
+            });
+        }
+        parameters
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UniformValue> {
+        self.values.get(name)
+    }
+
+    /// Changes a value parameter, false if the material has none of that name
+    pub fn set(&mut self, name: &str, value: UniformValue) -> bool {
+        match self.values.get_mut(name) {
+            Some(current) => {
+                *current = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Textures a sampler parameter, false if the material has none of that name
+    pub fn set_texture(&mut self, name: &str, texture: &Texture) -> bool {
+        let declared = self.definition.parameters.get(name)
+            .and_then(|parameter| UniformType::from_glsl(&parameter.ty))
+            .is_some_and(UniformType::is_sampler);
+        if declared {
+            self.textures.insert(name.to_string(), texture.id);
+        }
+        declared
+    }
+
+    /// Texture units `bind` uses, starting at 0; bind shadow maps after them
+    pub fn texture_units(&self) -> u32 {
+        self.reflection.samplers.iter()
+            .filter(|sampler| self.definition.parameters.contains_key(&sampler.name))
+            .count() as u32
+    }
+
+    /// Uses the program and sets the parameters and textures
+    pub fn bind(&self) {
+        unsafe {
+            self.shader.useProgram();
+        }
+        self.apply(&self.shader);
+    }
+
+    /// Sets the parameters and textures on the program in use, for `Renderer::on_material`
+    pub fn apply(&self, shader: &Shader) {
+        for (name, value) in &self.values {
+            if let Some(uniform) = self.reflection.uniform(name) {
+                value.apply(uniform.location, uniform.ty);
+            }
+        }
+
+        let samplers = self.reflection.samplers.iter().filter(|sampler| self.definition.parameters.contains_key(&sampler.name));
+        for (unit, sampler) in samplers.enumerate() {
+            let texture = self.textures.get(&sampler.name).cloned().unwrap_or(0);
+            let name = CString::new(sampler.name.as_bytes()).expect("parameter names are identifiers");
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+                gl::BindTexture(sampler.ty.target(), texture);
+                shader.setInt(&name, unit as i32);
+            }
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.shader.ID);
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("gl_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOSSY_ROCK: &str = r#"
+name = "mossy_rock"
+includes = ["reactor/noise.glsl"]
+
+[parameters]
+albedoMap = { type = "sampler2D" }
+tint = { type = "vec3", default = [1.0, 0.9, 0.8] }
+moss = { type = "float", default = 0.5, min = 0.0, max = 1.0 }
+
+[[layers]]
+name = "base"
+code = "surface.albedo = texture(albedoMap, TexCoords).rgb * tint;"
+
+[[layers]]
+name = "moss"
+mask = "smoothstep(0.4, 0.6, fbmNoise3(WorldPos * 2.0, 7u, 4, 2.0, 0.5) * 0.5 + 0.5) * moss"
+code = "surface.albedo = vec3(0.2, 0.35, 0.1);"
+"#;
+
+    #[test]
+    fn compiles_layers_to_glsl() {
+        let definition = MaterialDefinition::from_toml(MOSSY_ROCK).unwrap();
+        assert_eq!(definition.shading, Shading::Lit);
+        assert_eq!(definition.parameters["tint"].default, Some(UniformValue::Vector(vec![1.0, 0.9, 0.8])));
+        assert_eq!(definition.parameter_types().unwrap()["albedoMap"], UniformType::from_glsl("sampler2D").unwrap());
+
+        let source = definition.compile().unwrap();
+        assert!(source.fragment.starts_with("#version 330 core\n"));
+        assert!(source.fragment.contains("uniform sampler2D albedoMap;\nuniform float moss;\nuniform vec3 tint;\n"));
+        assert!(source.fragment.contains("void layer1(inout Surface surface)"));
+        assert!(source.fragment.contains("    layer0(surface);\n"));
+        assert!(source.fragment.contains("surface = mixSurface(surface, above, layer1Mask(surface));"));
+        assert!(source.fragment.contains("cascadeShadow(WorldPos, ViewDepth, bias)"));
+
+        let (_, fragment) = definition.preprocess(&Preprocessor::new(), &Defines::new()).unwrap();
+        assert_eq!(fragment.files, vec!["materials/mossy_rock.frag", "reactor/fog.glsl", "reactor/normal_map.glsl",
+                                        "reactor/cascade_shadow.glsl", "reactor/ssao.glsl", "reactor/noise.glsl"]);
+        assert!(fragment.source.contains("float fbmNoise3("));
+        assert!(fragment.source.contains("vec3 perturbNormal(") && fragment.source.contains("vec3 normalFromMap("));
+    }
+
+    #[test]
+    fn rejects_bad_parameters() {
+        let mut definition = MaterialDefinition { name: "broken".into(), shading: Shading::Unlit, ..MaterialDefinition::default() };
+        definition.parameters.insert("weight".into(), ParameterDefinition { ty: "double".into(), default: None, min: None, max: None });
+        assert!(definition.compile().is_err());
+
+        definition.parameters.clear();
+        definition.parameters.insert("gl_weight".into(), ParameterDefinition { ty: "float".into(), default: None, min: None, max: None });
+        assert!(definition.compile().is_err());
+
+        definition.parameters.clear();
+        let source = definition.compile().unwrap();
+        assert!(!source.fragment.contains("cascade_shadow"));
+        assert!(source.fragment.contains("vec3 color = surface.albedo + surface.emission;"));
+    }
+}
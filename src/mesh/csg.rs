@@ -0,0 +1,554 @@
+//! Constructive solid geometry on closed meshes with BSP trees, after Evan Wallace's csg.js.
+//! Faces within `EPSILON` of a splitting plane are treated as coplanar and kept on the side
+//! their normal agrees with, so touching and flush solids merge cleanly. When converting
+//! back to a mesh, positions are welded and the T-junctions left by splits are stitched, so
+//! closed inputs give closed, edge-manifold outputs.
+
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Vector2, Vector3};
+use bounds::Plane;
+use super::MeshData;
+
+/// Distance under which points count as on a plane, an edge or each other
+pub const EPSILON: Float = 1e-4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Vertex {
+    position: Point3,
+    normal: Vector3,
+    uv: Vector2,
+}
+
+impl Vertex {
+    fn lerp(&self, other: &Vertex, t: Float) -> Vertex {
+        Vertex {
+            position: self.position + (other.position - self.position) * t,
+            normal: self.normal.lerp(other.normal, t),
+            uv: self.uv.lerp(other.uv, t),
+        }
+    }
+}
+
+/// Convex planar polygon, counter-clockwise seen from the side the plane normal points to
+#[derive(Clone, PartialEq, Debug)]
+struct Polygon {
+    vertices: Vec<Vertex>,
+    plane: Plane,
+}
+
+impl Polygon {
+    fn flip(&mut self) {
+        self.vertices.reverse();
+        for vertex in self.vertices.iter_mut() {
+            vertex.normal = -vertex.normal;
+        }
+        self.plane = flipped(&self.plane);
+    }
+}
+
+fn flipped(plane: &Plane) -> Plane {
+    Plane { normal: -plane.normal, distance: -plane.distance }
+}
+
+const COPLANAR: u8 = 0;
+const FRONT: u8 = 1;
+const BACK: u8 = 2;
+const SPANNING: u8 = 3;
+
+/// Sorts `polygon` against `plane` into the four lists, cutting it when it spans the plane
+fn split(plane: &Plane, polygon: Polygon, coplanar_front: &mut Vec<Polygon>, coplanar_back: &mut Vec<Polygon>,
+         front: &mut Vec<Polygon>, back: &mut Vec<Polygon>) {
+    let side = |vertex: &Vertex| {
+        let distance = plane.signed_distance(vertex.position);
+        if distance < -EPSILON { BACK } else if distance > EPSILON { FRONT } else { COPLANAR }
+    };
+
+    // polygons rarely span a plane, they are moved to their list without allocating
+    match polygon.vertices.iter().fold(COPLANAR, |all, vertex| all | side(vertex)) {
+        COPLANAR => {
+            if plane.normal.dot(polygon.plane.normal) > 0.0 {
+                coplanar_front.push(polygon);
+            } else {
+                coplanar_back.push(polygon);
+            }
+        }
+        FRONT => front.push(polygon),
+        BACK => back.push(polygon),
+        _ => {
+            let sides: Vec<u8> = polygon.vertices.iter().map(side).collect();
+            let (mut f, mut b) = (Vec::new(), Vec::new());
+            let count = polygon.vertices.len();
+            for i in 0..count {
+                let j = (i + 1) % count;
+                let (vi, vj) = (&polygon.vertices[i], &polygon.vertices[j]);
+                if sides[i] != BACK {
+                    f.push(*vi);
+                }
+                if sides[i] != FRONT {
+                    b.push(*vi);
+                }
+                if sides[i] | sides[j] == SPANNING {
+                    let di = plane.signed_distance(vi.position);
+                    let dj = plane.signed_distance(vj.position);
+                    let cut = vi.lerp(vj, di / (di - dj));
+                    f.push(cut);
+                    b.push(cut);
+                }
+            }
+            if f.len() >= 3 {
+                front.push(Polygon { vertices: f, plane: polygon.plane });
+            }
+            if b.len() >= 3 {
+                back.push(Polygon { vertices: b, plane: polygon.plane });
+            }
+        }
+    }
+}
+
+/// Node of a solid BSP tree: everything behind the planes is inside
+#[derive(Clone, Default, Debug)]
+struct Node {
+    plane: Option<Plane>,
+    front: Option<Box<Node>>,
+    back: Option<Box<Node>>,
+    polygons: Vec<Polygon>,
+}
+
+impl Node {
+    fn new(polygons: Vec<Polygon>) -> Node {
+        let mut node = Node::default();
+        node.build(polygons);
+        node
+    }
+
+    fn build(&mut self, polygons: Vec<Polygon>) {
+        if polygons.is_empty() {
+            return;
+        }
+        let plane = *self.plane.get_or_insert(polygons[0].plane);
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        let (mut coplanar_front, mut coplanar_back) = (Vec::new(), Vec::new());
+        for polygon in polygons {
+            split(&plane, polygon, &mut coplanar_front, &mut coplanar_back, &mut front, &mut back);
+        }
+        self.polygons.append(&mut coplanar_front);
+        self.polygons.append(&mut coplanar_back);
+        if !front.is_empty() {
+            self.front.get_or_insert_with(Box::default).build(front);
+        }
+        if !back.is_empty() {
+            self.back.get_or_insert_with(Box::default).build(back);
+        }
+    }
+
+    /// Swaps inside and outside
+    fn invert(&mut self) {
+        for polygon in self.polygons.iter_mut() {
+            polygon.flip();
+        }
+        self.plane = self.plane.map(|plane| flipped(&plane));
+        if let Some(front) = self.front.as_mut() {
+            front.invert();
+        }
+        if let Some(back) = self.back.as_mut() {
+            back.invert();
+        }
+        std::mem::swap(&mut self.front, &mut self.back);
+    }
+
+    /// The parts of `polygons` outside this solid
+    fn clip_polygons(&self, polygons: Vec<Polygon>) -> Vec<Polygon> {
+        let plane = match self.plane {
+            Some(plane) => plane,
+            None => return polygons,
+        };
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        let (mut coplanar_front, mut coplanar_back) = (Vec::new(), Vec::new());
+        for polygon in polygons {
+            split(&plane, polygon, &mut coplanar_front, &mut coplanar_back, &mut front, &mut back);
+        }
+        front.append(&mut coplanar_front);
+        back.append(&mut coplanar_back);
+
+        let mut kept = match self.front {
+            Some(ref node) => node.clip_polygons(front),
+            None => front,
+        };
+        if let Some(ref node) = self.back {
+            kept.append(&mut node.clip_polygons(back));
+        }
+        kept
+    }
+
+    /// Removes the parts of this tree's polygons inside `other`
+    fn clip_to(&mut self, other: &Node) {
+        self.polygons = other.clip_polygons(std::mem::take(&mut self.polygons));
+        if let Some(front) = self.front.as_mut() {
+            front.clip_to(other);
+        }
+        if let Some(back) = self.back.as_mut() {
+            back.clip_to(other);
+        }
+    }
+
+    fn all_polygons(&self) -> Vec<Polygon> {
+        let mut polygons = self.polygons.clone();
+        if let Some(ref front) = self.front {
+            polygons.extend(front.all_polygons());
+        }
+        if let Some(ref back) = self.back {
+            polygons.extend(back.all_polygons());
+        }
+        polygons
+    }
+}
+
+/// Closed solid as a set of polygons, combined without going through meshes in between
+#[derive(Clone, Default, Debug)]
+pub struct Solid {
+    polygons: Vec<Polygon>,
+    has_uvs: bool,
+}
+
+impl Solid {
+    /// Solid bounded by the triangles of a closed mesh; missing normals use the face normal
+    /// and degenerate triangles are dropped
+    pub fn from_mesh(mesh: &MeshData) -> Solid {
+        let has_normals = mesh.normals.len() == mesh.vertex_count();
+        let has_uvs = mesh.uvs.len() == mesh.vertex_count();
+        let mut polygons = Vec::with_capacity(mesh.triangle_count());
+        for corners in mesh.triangles() {
+            let [a, b, c] = corners.map(|i| mesh.positions[i as usize]);
+            let face = (b - a).cross(c - a);
+            if face.magnitude2() <= EPSILON * EPSILON {
+                continue;
+            }
+            let plane = Plane::new(face, a);
+            let vertices = corners.iter()
+                .map(|&i| Vertex {
+                    position: mesh.positions[i as usize],
+                    normal: if has_normals { mesh.normals[i as usize] } else { plane.normal },
+                    uv: if has_uvs { mesh.uvs[i as usize] } else { Vector2::zero() },
+                })
+                .collect();
+            polygons.push(Polygon { vertices, plane });
+        }
+        Solid { polygons, has_uvs }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// Everything inside either solid
+    pub fn union(&self, other: &Solid) -> Solid {
+        let (mut a, mut b) = (Node::new(self.polygons.clone()), Node::new(other.polygons.clone()));
+        a.clip_to(&b);
+        b.clip_to(&a);
+        b.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.build(b.all_polygons());
+        self.with_polygons(other, a.all_polygons())
+    }
+
+    /// Everything inside this solid but not inside `other`
+    pub fn subtract(&self, other: &Solid) -> Solid {
+        let (mut a, mut b) = (Node::new(self.polygons.clone()), Node::new(other.polygons.clone()));
+        a.invert();
+        a.clip_to(&b);
+        b.clip_to(&a);
+        b.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.build(b.all_polygons());
+        a.invert();
+        self.with_polygons(other, a.all_polygons())
+    }
+
+    /// Everything inside both solids
+    pub fn intersect(&self, other: &Solid) -> Solid {
+        let (mut a, mut b) = (Node::new(self.polygons.clone()), Node::new(other.polygons.clone()));
+        a.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.clip_to(&b);
+        b.clip_to(&a);
+        a.build(b.all_polygons());
+        a.invert();
+        self.with_polygons(other, a.all_polygons())
+    }
+
+    fn with_polygons(&self, other: &Solid, polygons: Vec<Polygon>) -> Solid {
+        Solid { polygons, has_uvs: self.has_uvs || other.has_uvs }
+    }
+
+    /// Triangle mesh of the boundary, with tangents when the inputs had UVs. Vertices closer
+    /// than `EPSILON` are welded and vertices lying on another polygon's edge are inserted
+    /// into it, so neighbouring faces share every edge.
+    pub fn to_mesh(&self) -> MeshData {
+        let mut welded = Weld::default();
+        let mut polygons = self.polygons.clone();
+        for polygon in polygons.iter_mut() {
+            for vertex in polygon.vertices.iter_mut() {
+                vertex.position = welded.snap(vertex.position);
+            }
+            polygon.vertices.dedup_by(|a, b| a.position == b.position);
+            while polygon.vertices.len() > 1 && polygon.vertices[0].position == polygon.vertices.last().unwrap().position {
+                polygon.vertices.pop();
+            }
+        }
+
+        let grid = PointGrid::new(&welded.points, mean_edge_length(&polygons));
+        let mut mesh = MeshData::new();
+        let mut indices = HashMap::new();
+        let mut near = Vec::new();
+        for polygon in polygons.iter().filter(|polygon| polygon.vertices.len() >= 3) {
+            let (ring, stitched) = stitch(polygon, &grid, &mut near);
+            let ring: Vec<u32> = ring.iter().map(|vertex| add_vertex(&mut mesh, &mut indices, vertex)).collect();
+            if stitched {
+                // a fan around the centre never produces slivers from the collinear points
+                let count = polygon.vertices.len() as Float;
+                let mut centre = polygon.vertices.iter().fold(Vertex {
+                    position: Point3::origin(),
+                    normal: Vector3::zero(),
+                    uv: Vector2::zero(),
+                }, |sum, vertex| Vertex {
+                    position: sum.position + vertex.position.to_vec(),
+                    normal: sum.normal + vertex.normal,
+                    uv: sum.uv + vertex.uv,
+                });
+                centre.position = Point3::from_vec(centre.position.to_vec() / count);
+                centre.normal = centre.normal.normalize();
+                centre.uv /= count;
+                let centre = add_vertex(&mut mesh, &mut indices, &centre);
+                for i in 0..ring.len() {
+                    mesh.indices.extend_from_slice(&[centre, ring[i], ring[(i + 1) % ring.len()]]);
+                }
+            } else {
+                for i in 1..ring.len() - 1 {
+                    mesh.indices.extend_from_slice(&[ring[0], ring[i], ring[i + 1]]);
+                }
+            }
+        }
+        if self.has_uvs {
+            mesh.compute_tangents();
+        }
+        mesh
+    }
+}
+
+type VertexKey = ([i64; 3], [i64; 3], [i64; 3]);
+
+/// Index of the output vertex with these attributes, added on first use
+fn add_vertex(mesh: &mut MeshData, indices: &mut HashMap<VertexKey, u32>, vertex: &Vertex) -> u32 {
+    let key = (quantize(vertex.position.to_vec()), quantize(vertex.normal), quantize(vertex.uv.extend(0.0)));
+    *indices.entry(key).or_insert_with(|| {
+        mesh.positions.push(vertex.position);
+        mesh.normals.push(vertex.normal);
+        mesh.uvs.push(vertex.uv);
+        (mesh.positions.len() - 1) as u32
+    })
+}
+
+/// Unique positions, found through their cell on an `EPSILON` grid
+#[derive(Default)]
+struct Weld {
+    points: Vec<Point3>,
+    cells: HashMap<[i64; 3], usize>,
+}
+
+impl Weld {
+    fn snap(&mut self, position: Point3) -> Point3 {
+        let key = quantize(position.to_vec());
+        let points = &mut self.points;
+        let index = *self.cells.entry(key).or_insert_with(|| {
+            points.push(position);
+            points.len() - 1
+        });
+        self.points[index]
+    }
+}
+
+fn quantize(v: Vector3) -> [i64; 3] {
+    [(v.x / EPSILON).round() as i64, (v.y / EPSILON).round() as i64, (v.z / EPSILON).round() as i64]
+}
+
+/// Welded points bucketed in cells about an edge long, so stitching an edge only tests the
+/// points around it
+struct PointGrid<'a> {
+    points: &'a [Point3],
+    cell: Float,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl<'a> PointGrid<'a> {
+    fn new(points: &'a [Point3], cell: Float) -> PointGrid<'a> {
+        let mut grid = PointGrid { points, cell: cell.max(EPSILON * 16.0), cells: HashMap::new() };
+        for (i, &point) in points.iter().enumerate() {
+            let key = grid.key(point);
+            grid.cells.entry(key).or_default().push(i);
+        }
+        grid
+    }
+
+    fn key(&self, p: Point3) -> [i64; 3] {
+        [(p.x / self.cell).floor() as i64, (p.y / self.cell).floor() as i64, (p.z / self.cell).floor() as i64]
+    }
+
+    /// Points in the cells overlapping the bounds of segment `ab`, into `near`
+    fn near_segment(&self, a: Point3, b: Point3, near: &mut Vec<Point3>) {
+        near.clear();
+        let margin = Vector3::new(EPSILON, EPSILON, EPSILON);
+        let lo = self.key(Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)) - margin);
+        let hi = self.key(Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)) + margin);
+        let spans = [hi[0] - lo[0] + 1, hi[1] - lo[1] + 1, hi[2] - lo[2] + 1];
+        let inside = |key: &[i64; 3]| (0..3).all(|axis| key[axis] >= lo[axis] && key[axis] <= hi[axis]);
+        // a long edge spans more cells than there are occupied ones
+        if spans.iter().fold(1i64, |product, &span| product.saturating_mul(span)) > self.cells.len() as i64 {
+            let indices = self.cells.iter().filter(|&(key, _)| inside(key)).flat_map(|(_, indices)| indices);
+            near.extend(indices.map(|&i| self.points[i]));
+            return;
+        }
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    if let Some(indices) = self.cells.get(&[x, y, z]) {
+                        near.extend(indices.iter().map(|&i| self.points[i]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Average edge length of the polygons, the grid cell size of stitching
+fn mean_edge_length(polygons: &[Polygon]) -> Float {
+    let (sum, count) = polygons.iter()
+        .filter(|polygon| polygon.vertices.len() >= 3)
+        .flat_map(|polygon| {
+            let count = polygon.vertices.len();
+            (0..count).map(move |i| (polygon.vertices[(i + 1) % count].position - polygon.vertices[i].position).magnitude())
+        })
+        .fold((0.0, 0usize), |(sum, count), length| (sum + length, count + 1));
+    if count == 0 { 1.0 } else { sum / count as Float }
+}
+
+/// The polygon's vertices with the welded points lying inside its edges inserted in order,
+/// and whether any were. `near` is scratch space.
+fn stitch(polygon: &Polygon, grid: &PointGrid, near: &mut Vec<Point3>) -> (Vec<Vertex>, bool) {
+    let mut ring = Vec::with_capacity(polygon.vertices.len());
+    let mut stitched = false;
+    let count = polygon.vertices.len();
+    for i in 0..count {
+        let (a, b) = (&polygon.vertices[i], &polygon.vertices[(i + 1) % count]);
+        ring.push(*a);
+        let edge = b.position - a.position;
+        let length2 = edge.magnitude2();
+        grid.near_segment(a.position, b.position, near);
+        let mut inside: Vec<(Float, Point3)> = near.iter()
+            .filter_map(|&point| {
+                let t = (point - a.position).dot(edge) / length2;
+                let off = (point - (a.position + edge * t)).magnitude2();
+                if t > 0.0 && t < 1.0 && off < EPSILON * EPSILON && point != a.position && point != b.position {
+                    Some((t, point))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        inside.sort_by(|x, y| x.0.total_cmp(&y.0));
+        stitched |= !inside.is_empty();
+        ring.extend(inside.into_iter().map(|(t, point)| Vertex { position: point, ..a.lerp(b, t) }));
+    }
+    (ring, stitched)
+}
+
+pub fn union(a: &MeshData, b: &MeshData) -> MeshData {
+    Solid::from_mesh(a).union(&Solid::from_mesh(b)).to_mesh()
+}
+
+pub fn subtract(a: &MeshData, b: &MeshData) -> MeshData {
+    Solid::from_mesh(a).subtract(&Solid::from_mesh(b)).to_mesh()
+}
+
+pub fn intersect(a: &MeshData, b: &MeshData) -> MeshData {
+    Solid::from_mesh(a).intersect(&Solid::from_mesh(b)).to_mesh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::primitives;
+
+    fn moved(mut mesh: MeshData, offset: Vector3) -> MeshData {
+        for position in mesh.positions.iter_mut() {
+            *position += offset;
+        }
+        mesh
+    }
+
+    fn volume(mesh: &MeshData) -> Float {
+        mesh.triangles()
+            .map(|[a, b, c]| {
+                let (a, b, c) = (mesh.positions[a as usize], mesh.positions[b as usize], mesh.positions[c as usize]);
+                a.to_vec().dot(b.to_vec().cross(c.to_vec())) / 6.0
+            })
+            .sum()
+    }
+
+    /// Every directed edge between positions has its reverse in a neighbouring triangle
+    fn assert_watertight(mesh: &MeshData) {
+        let mut edges = HashMap::new();
+        for [a, b, c] in mesh.triangles() {
+            for &(from, to) in &[(a, b), (b, c), (c, a)] {
+                let key = (quantize(mesh.positions[from as usize].to_vec()), quantize(mesh.positions[to as usize].to_vec()));
+                *edges.entry(key).or_insert(0) += 1;
+            }
+        }
+        for (&(from, to), &count) in edges.iter() {
+            assert_eq!(edges.get(&(to, from)), Some(&count), "open edge {:?} -> {:?}", from, to);
+        }
+    }
+
+    #[test]
+    fn sphere_union_stays_bounded() {
+        let a = primitives::uv_sphere(1.0, 16, 8);
+        let b = moved(primitives::uv_sphere(1.0, 16, 8), Vector3::new(0.5, 0.3, 0.2));
+        let union = union(&a, &b);
+        assert_watertight(&union);
+        // the splits and stitches multiply the faces by about six
+        let inputs = a.triangle_count() + b.triangle_count();
+        assert!(union.triangle_count() < inputs * 8, "{} triangles from {}", union.triangle_count(), inputs);
+        assert!(volume(&union) > volume(&a) && volume(&union) < volume(&a) + volume(&b));
+    }
+
+    #[test]
+    fn volumes_of_overlapping_cubes() {
+        let a = primitives::cube(2.0);
+        let b = moved(primitives::cube(2.0), Vector3::new(1.0, 1.0, 1.0));
+
+        let union = union(&a, &b);
+        let difference = subtract(&a, &b);
+        let intersection = intersect(&a, &b);
+        assert!((volume(&union) - 15.0).abs() < 1e-3);
+        assert!((volume(&difference) - 7.0).abs() < 1e-3);
+        assert!((volume(&intersection) - 1.0).abs() < 1e-3);
+        assert_watertight(&union);
+        assert_watertight(&difference);
+        assert_watertight(&intersection);
+    }
+
+    #[test]
+    fn flush_faces_merge() {
+        let a = primitives::cube(1.0);
+        let b = moved(primitives::cube(1.0), Vector3::new(1.0, 0.25, 0.0));
+        let union = union(&a, &b);
+        assert!((volume(&union) - 2.0).abs() < 1e-3);
+        assert_watertight(&union);
+        assert!(!union.positions.iter().any(|p| (p.x - 0.5).abs() < EPSILON && p.y.abs() < 0.2 && p.z.abs() < 0.4));
+    }
+}
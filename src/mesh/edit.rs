@@ -0,0 +1,318 @@
+//! Geometry that changes after creation, for procedural generation and editor tools. The CPU
+//! copy is authoritative: edits record the vertex and index ranges they touch and `upload`
+//! sends only those with `BufferSubData`, reallocating the buffers with headroom when they
+//! overflow.
+
+use std::ops::Range;
+
+use lang::{Point3, Vector2, Vector3};
+use bounds::Aabb;
+use super::{interleave_range, Mesh, MeshData};
+
+/// Vertices and indices of one part of an `EditableMesh`, the indices are absolute
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Submesh {
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+impl Submesh {
+    pub fn vertices(&self) -> Range<usize> {
+        self.first_vertex as usize..(self.first_vertex + self.vertex_count) as usize
+    }
+
+    pub fn indices(&self) -> Range<usize> {
+        self.first_index as usize..(self.first_index + self.index_count) as usize
+    }
+}
+
+/// Union of the modified elements since the last upload
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+struct Dirty(Option<Range<usize>>);
+
+impl Dirty {
+    fn mark(&mut self, range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+        self.0 = Some(match self.0.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// The range clipped to `len`, elements removed since they were marked need no upload
+    fn clamped(&self, len: usize) -> Option<Range<usize>> {
+        self.0.clone()
+            .map(|dirty| dirty.start.min(len)..dirty.end.min(len))
+            .filter(|dirty| dirty.start < dirty.end)
+    }
+}
+
+/// Mesh made of submeshes sharing one vertex and index buffer, with every attribute filled
+/// for every vertex. Draw through `mesh()` after `upload`; a reallocation replaces the GPU
+/// objects, so do not keep the returned `Mesh` across uploads.
+#[derive(Default, Debug)]
+pub struct EditableMesh {
+    data: MeshData,
+    submeshes: Vec<Submesh>,
+    mesh: Mesh,
+    vertex_capacity: usize,
+    index_capacity: usize,
+    dirty_vertices: Dirty,
+    dirty_indices: Dirty,
+    bounds: Option<Aabb>,
+}
+
+impl EditableMesh {
+    /// Starts with `data` as the only submesh, nothing is on the GPU before the first `upload`
+    pub fn new(data: &MeshData) -> EditableMesh {
+        let mut mesh = EditableMesh::default();
+        if !data.positions.is_empty() {
+            mesh.append_submesh(data);
+        }
+        mesh
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn submeshes(&self) -> &[Submesh] {
+        &self.submeshes
+    }
+
+    pub fn submesh(&self, index: usize) -> Option<&Submesh> {
+        self.submeshes.get(index)
+    }
+
+    pub fn set_positions(&mut self, first_vertex: usize, positions: &[Point3]) {
+        write(&mut self.data.positions, first_vertex, positions);
+        self.dirty_vertices.mark(first_vertex..first_vertex + positions.len());
+        self.bounds = None;
+    }
+
+    pub fn set_normals(&mut self, first_vertex: usize, normals: &[Vector3]) {
+        write(&mut self.data.normals, first_vertex, normals);
+        self.dirty_vertices.mark(first_vertex..first_vertex + normals.len());
+    }
+
+    pub fn set_uvs(&mut self, first_vertex: usize, uvs: &[Vector2]) {
+        write(&mut self.data.uvs, first_vertex, uvs);
+        self.dirty_vertices.mark(first_vertex..first_vertex + uvs.len());
+    }
+
+    /// Overwrites absolute indices, they must stay within the submesh that owns them
+    pub fn set_indices(&mut self, first_index: usize, indices: &[u32]) {
+        debug_assert!(indices.iter().all(|&i| (i as usize) < self.data.positions.len()));
+        write(&mut self.data.indices, first_index, indices);
+        self.dirty_indices.mark(first_index..first_index + indices.len());
+    }
+
+    /// Lets `edit` change any attribute of the vertices in `vertices`, for deformers touching
+    /// several attributes at once. Only that range is uploaded, and the vertex count must not change.
+    pub fn edit_vertices<F: FnOnce(&mut MeshData)>(&mut self, vertices: Range<usize>, edit: F) {
+        let count = self.data.positions.len();
+        edit(&mut self.data);
+        assert_eq!(self.data.positions.len(), count, "edit_vertices cannot add or remove vertices");
+        self.dirty_vertices.mark(vertices);
+        self.bounds = None;
+    }
+
+    /// Adds `data` as a new submesh after the others and returns its index,
+    /// missing attributes are zero-filled
+    pub fn append_submesh(&mut self, data: &MeshData) -> usize {
+        let count = data.positions.len();
+        let submesh = Submesh {
+            first_vertex: self.data.positions.len() as u32,
+            vertex_count: count as u32,
+            first_index: self.data.indices.len() as u32,
+            index_count: data.indices.len() as u32,
+        };
+        let mut padded = data.clone();
+        padded.normals.resize(count, Vector3::new(0.0, 0.0, 0.0));
+        padded.uvs.resize(count, Vector2::new(0.0, 0.0));
+        padded.tangents.resize(count, Vector3::new(0.0, 0.0, 0.0));
+        padded.bitangents.resize(count, Vector3::new(0.0, 0.0, 0.0));
+        self.data.append(&padded);
+
+        self.dirty_vertices.mark(submesh.vertices());
+        self.dirty_indices.mark(submesh.indices());
+        self.bounds = None;
+        self.submeshes.push(submesh);
+        self.submeshes.len() - 1
+    }
+
+    /// Removes a submesh and returns its geometry with local indices. The submeshes after it
+    /// move down, so their vertices and indices are uploaded again.
+    pub fn remove_submesh(&mut self, index: usize) -> MeshData {
+        let submesh = self.submeshes.remove(index);
+        let (vertices, indices) = (submesh.vertices(), submesh.indices());
+
+        let removed = MeshData {
+            positions: self.data.positions.drain(vertices.clone()).collect(),
+            normals: self.data.normals.drain(vertices.clone()).collect(),
+            uvs: self.data.uvs.drain(vertices.clone()).collect(),
+            tangents: self.data.tangents.drain(vertices.clone()).collect(),
+            bitangents: self.data.bitangents.drain(vertices.clone()).collect(),
+            indices: self.data.indices.drain(indices.clone()).map(|i| i - submesh.first_vertex).collect(),
+        };
+        for i in self.data.indices[indices.start..].iter_mut() {
+            *i -= submesh.vertex_count;
+        }
+        for later in self.submeshes[index..].iter_mut() {
+            later.first_vertex -= submesh.vertex_count;
+            later.first_index -= submesh.index_count;
+        }
+
+        self.dirty_vertices.mark(vertices.start..self.data.positions.len());
+        self.dirty_indices.mark(indices.start..self.data.indices.len());
+        self.bounds = None;
+        removed
+    }
+
+    /// Smooth normals from the current positions, submeshes share no vertices so each is
+    /// computed on its own
+    pub fn recompute_normals(&mut self) {
+        self.data.compute_normals();
+        self.dirty_vertices.mark(0..self.data.positions.len());
+    }
+
+    /// Tangents and bitangents from the current positions, normals and UVs
+    pub fn recompute_tangents(&mut self) {
+        self.data.compute_tangents();
+        self.dirty_vertices.mark(0..self.data.positions.len());
+    }
+
+    /// Bounds of all submeshes, cached until positions change
+    pub fn aabb(&mut self) -> Aabb {
+        let data = &self.data;
+        *self.bounds.get_or_insert_with(|| data.aabb())
+    }
+
+    pub fn submesh_aabb(&self, index: usize) -> Aabb {
+        Aabb::from_points(self.data.positions[self.submeshes[index].vertices()].iter())
+    }
+
+    /// Vertices `upload` would send
+    pub fn dirty_vertices(&self) -> Option<Range<usize>> {
+        self.dirty_vertices.clamped(self.data.positions.len())
+    }
+
+    /// Indices `upload` would send
+    pub fn dirty_indices(&self) -> Option<Range<usize>> {
+        self.dirty_indices.clamped(self.data.indices.len())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_vertices().is_some() || self.dirty_indices().is_some()
+    }
+
+    /// Sends the modified ranges to the GPU, growing the buffers to at least twice their size
+    /// when the geometry no longer fits. Returns the number of bytes uploaded.
+    pub fn upload(&mut self) -> usize {
+        let (vertex_count, index_count) = (self.data.positions.len(), self.data.indices.len());
+        if self.mesh.vao == 0 || vertex_count > self.vertex_capacity || index_count > self.index_capacity {
+            self.vertex_capacity = grown(self.vertex_capacity, vertex_count);
+            self.index_capacity = grown(self.index_capacity, index_count);
+            self.mesh.delete();
+            self.mesh = Mesh::dynamic(self.vertex_capacity, self.index_capacity);
+            self.dirty_vertices.mark(0..vertex_count);
+            self.dirty_indices.mark(0..index_count);
+        }
+
+        let mut bytes = 0;
+        if let Some(range) = self.dirty_vertices() {
+            let vertices = interleave_range(&self.data, range.clone());
+            self.mesh.update_vertices(range.start, &vertices);
+            bytes += vertices.len() * 4;
+        }
+        if let Some(range) = self.dirty_indices() {
+            self.mesh.update_indices(range.start, &self.data.indices[range.clone()]);
+            bytes += range.len() * 4;
+        }
+        self.dirty_vertices = Dirty::default();
+        self.dirty_indices = Dirty::default();
+        self.mesh.index_count = index_count as i32;
+        bytes
+    }
+
+    /// The GPU mesh as of the last `upload`
+    pub fn mesh(&self) -> Mesh {
+        self.mesh
+    }
+
+    pub fn draw(&self) {
+        self.mesh.draw();
+    }
+
+    pub fn draw_submesh(&self, index: usize) {
+        let submesh = self.submeshes[index];
+        self.mesh.draw_range(submesh.first_index, submesh.index_count);
+    }
+
+    pub fn delete(&mut self) {
+        self.mesh.delete();
+        *self = EditableMesh::default();
+    }
+}
+
+fn write<T: Copy>(into: &mut [T], first: usize, values: &[T]) {
+    into[first..first + values.len()].copy_from_slice(values);
+}
+
+fn grown(capacity: usize, needed: usize) -> usize {
+    if needed <= capacity {
+        capacity.max(1)
+    } else {
+        needed.max(capacity * 2).max(64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::primitives;
+
+    #[test]
+    fn removing_shifts_later_submeshes() {
+        let cube = primitives::cube(1.0);
+        let mut mesh = EditableMesh::new(&cube);
+        let second = mesh.append_submesh(&cube);
+        let third = mesh.append_submesh(&cube);
+        assert_eq!((second, third), (1, 2));
+
+        let removed = mesh.remove_submesh(1);
+        assert_eq!(removed.indices, cube.indices);
+        assert_eq!(mesh.submeshes()[1], Submesh {
+            first_vertex: cube.positions.len() as u32,
+            vertex_count: cube.positions.len() as u32,
+            first_index: cube.indices.len() as u32,
+            index_count: cube.indices.len() as u32,
+        });
+        assert_eq!(&mesh.data().indices[cube.indices.len()..], &mesh.data().indices[..cube.indices.len()]
+            .iter().map(|i| i + cube.positions.len() as u32).collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn tracks_dirty_ranges_and_bounds() {
+        let mut mesh = EditableMesh::new(&primitives::cube(1.0));
+        mesh.dirty_vertices = Dirty::default();
+        mesh.dirty_indices = Dirty::default();
+        assert!(!mesh.is_dirty());
+
+        let before = mesh.aabb();
+        mesh.set_positions(3, &[Point3::new(5.0, 0.0, 0.0)]);
+        mesh.set_uvs(7, &[Vector2::new(1.0, 1.0)]);
+        assert_eq!(mesh.dirty_vertices(), Some(3..8));
+        assert_eq!(mesh.dirty_indices(), None);
+        assert!(mesh.aabb().max.x > before.max.x);
+
+        let count = mesh.data().vertex_count();
+        mesh.append_submesh(&primitives::cube(1.0));
+        mesh.remove_submesh(1);
+        assert_eq!(mesh.dirty_vertices(), Some(3..count));
+    }
+}
@@ -0,0 +1,486 @@
+//! glTF 2.0 export of meshes, materials and node transforms, so procedural content can be
+//! opened in DCC tools. `.glb` files embed the binary chunk, `.gltf` files reference a
+//! `.bin` written next to them.
+use std::fs;
+use std::path::Path;
+
+use cgmath::prelude::*;
+use serde::Serialize;
+use serde_json;
+
+use color::Color;
+use error::{EngineError, EngineResult};
+use lang::{Float, Matrix4, gl_float};
+use super::MeshData;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const CHUNK_JSON: u32 = 0x4e4f_534a;
+const CHUNK_BIN: u32 = 0x004e_4942;
+
+/// Metallic-roughness material, factors only
+#[derive(Clone, PartialEq, Debug)]
+pub struct GltfMaterial {
+    pub name: String,
+    /// linear
+    pub base_color: Color,
+    pub metallic: Float,
+    pub roughness: Float,
+    /// linear RGB
+    pub emissive: [Float; 3],
+    pub double_sided: bool,
+}
+
+impl GltfMaterial {
+    pub fn new(name: &str, base_color: Color) -> GltfMaterial {
+        GltfMaterial { name: name.to_string(), base_color, metallic: 0.0, roughness: 1.0, emissive: [0.0; 3], double_sided: false }
+    }
+
+    pub fn metallic_roughness(mut self, metallic: Float, roughness: Float) -> GltfMaterial {
+        self.metallic = metallic;
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn emissive(mut self, emissive: [Float; 3]) -> GltfMaterial {
+        self.emissive = emissive;
+        self
+    }
+
+    pub fn double_sided(mut self, double_sided: bool) -> GltfMaterial {
+        self.double_sided = double_sided;
+        self
+    }
+}
+
+struct ExportMesh {
+    name: String,
+    data: MeshData,
+    material: Option<usize>,
+}
+
+struct ExportNode {
+    name: String,
+    mesh: Option<usize>,
+    transform: Matrix4,
+    children: Vec<usize>,
+    has_parent: bool,
+}
+
+/// Scene being assembled for export; meshes and materials can be shared by several nodes
+#[derive(Default)]
+pub struct GltfExport {
+    meshes: Vec<ExportMesh>,
+    materials: Vec<GltfMaterial>,
+    nodes: Vec<ExportNode>,
+}
+
+impl GltfExport {
+    pub fn new() -> GltfExport {
+        GltfExport::default()
+    }
+
+    pub fn add_material(&mut self, material: GltfMaterial) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Adds `data` drawn with `material` (an index from `add_material`)
+    pub fn add_mesh(&mut self, name: &str, data: &MeshData, material: Option<usize>) -> usize {
+        self.meshes.push(ExportMesh { name: name.to_string(), data: data.clone(), material });
+        self.meshes.len() - 1
+    }
+
+    /// Adds a root node placing `mesh` (an index from `add_mesh`) with `transform`
+    pub fn add_node(&mut self, name: &str, mesh: Option<usize>, transform: Matrix4) -> usize {
+        self.nodes.push(ExportNode { name: name.to_string(), mesh, transform, children: vec![], has_parent: false });
+        self.nodes.len() - 1
+    }
+
+    /// Moves `child` under `parent`, its transform becomes relative to the parent's
+    pub fn add_child(&mut self, parent: usize, child: usize) {
+        if !self.nodes[child].has_parent && parent != child {
+            self.nodes[child].has_parent = true;
+            self.nodes[parent].children.push(child);
+        }
+    }
+
+    /// Shorthand for a single mesh at the origin
+    pub fn single(name: &str, data: &MeshData, material: Option<GltfMaterial>) -> GltfExport {
+        let mut export = GltfExport::new();
+        let material = material.map(|material| export.add_material(material));
+        let mesh = export.add_mesh(name, data, material);
+        export.add_node(name, Some(mesh), Matrix4::from_scale(1.0));
+        export
+    }
+
+    /// The JSON document and the binary buffer it indexes, without the buffer's `uri`
+    pub fn to_document(&self) -> (serde_json::Value, Vec<u8>) {
+        let mut document = Document::default();
+        let mut bin = vec![];
+
+        for mesh in &self.meshes {
+            let data = &mesh.data;
+            let count = data.vertex_count();
+            let has = |len: usize| count > 0 && len == count;
+
+            let mut attributes = Attributes { position: None, normal: None, tangent: None, texcoord_0: None };
+            let positions: Vec<[f32; 3]> = data.positions.iter().map(|p| [gl_float(p.x), gl_float(p.y), gl_float(p.z)]).collect();
+            let (min, max) = bounds(&positions);
+            attributes.position = Some(document.push(&mut bin, &positions, "VEC3", ARRAY_BUFFER, Some((min, max))));
+            if has(data.normals.len()) {
+                let normals: Vec<[f32; 3]> = data.normals.iter().map(|n| [gl_float(n.x), gl_float(n.y), gl_float(n.z)]).collect();
+                attributes.normal = Some(document.push(&mut bin, &normals, "VEC3", ARRAY_BUFFER, None));
+            }
+            if has(data.tangents.len()) && has(data.normals.len()) {
+                // glTF stores the bitangent as a handedness sign
+                let tangents: Vec<[f32; 4]> = (0..count).map(|i| {
+                    let (n, t) = (data.normals[i], data.tangents[i]);
+                    let w = match data.bitangents.get(i) {
+                        Some(&b) if n.cross(t).dot(b) < 0.0 => -1.0,
+                        _ => 1.0,
+                    };
+                    [gl_float(t.x), gl_float(t.y), gl_float(t.z), w]
+                }).collect();
+                attributes.tangent = Some(document.push(&mut bin, &tangents, "VEC4", ARRAY_BUFFER, None));
+            }
+            if has(data.uvs.len()) {
+                // glTF puts the UV origin at the top left
+                let uvs: Vec<[f32; 2]> = data.uvs.iter().map(|uv| [gl_float(uv.x), 1.0 - gl_float(uv.y)]).collect();
+                attributes.texcoord_0 = Some(document.push(&mut bin, &uvs, "VEC2", ARRAY_BUFFER, None));
+            }
+            let indices = document.push_indices(&mut bin, &data.indices);
+            document.meshes.push(MeshJson {
+                name: mesh.name.clone(),
+                primitives: vec![Primitive { attributes, indices, material: mesh.material, mode: 4 }],
+            });
+        }
+
+        for material in &self.materials {
+            let color = material.base_color.to_array();
+            document.materials.push(MaterialJson {
+                name: material.name.clone(),
+                pbr_metallic_roughness: Pbr {
+                    base_color_factor: [gl_float(color[0]), gl_float(color[1]), gl_float(color[2]), gl_float(color[3])],
+                    metallic_factor: gl_float(material.metallic),
+                    roughness_factor: gl_float(material.roughness),
+                },
+                emissive_factor: [gl_float(material.emissive[0]), gl_float(material.emissive[1]), gl_float(material.emissive[2])],
+                alpha_mode: if color[3] < 1.0 { "BLEND" } else { "OPAQUE" },
+                double_sided: material.double_sided,
+            });
+        }
+
+        let mut roots = vec![];
+        for (index, node) in self.nodes.iter().enumerate() {
+            let matrix: &[Float; 16] = node.transform.as_ref();
+            let is_identity = node.transform == Matrix4::from_scale(1.0);
+            document.nodes.push(NodeJson {
+                name: node.name.clone(),
+                mesh: node.mesh,
+                matrix: if is_identity { None } else { Some(matrix.iter().map(|&value| gl_float(value)).collect()) },
+                children: node.children.clone(),
+            });
+            if !node.has_parent {
+                roots.push(index);
+            }
+        }
+        document.scenes.push(SceneJson { nodes: roots });
+
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+        document.buffers.push(BufferJson { byte_length: bin.len(), uri: None });
+        let value = serde_json::to_value(&document).expect("glTF documents serialize");
+        (value, bin)
+    }
+
+    /// Binary glTF: header, JSON chunk and BIN chunk
+    pub fn to_glb(&self) -> Vec<u8> {
+        let (document, bin) = self.to_document();
+        let mut json = serde_json::to_vec(&document).expect("glTF documents serialize");
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = Vec::with_capacity(total);
+        for word in &[GLB_MAGIC, 2, total as u32, json.len() as u32, CHUNK_JSON] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    /// Writes a `.glb`, or a `.gltf` with its buffer in a `.bin` of the same name
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> EngineResult<()> {
+        let path = path.as_ref();
+        let write = |path: &Path, bytes: &[u8]| fs::write(path, bytes).map_err(|error| EngineError::asset_io(path, error));
+        if path.extension().is_some_and(|extension| extension == "glb") {
+            return write(path, &self.to_glb());
+        }
+
+        let (mut document, bin) = self.to_document();
+        let bin_path = path.with_extension("bin");
+        let uri = bin_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        document["buffers"][0]["uri"] = serde_json::Value::String(uri);
+        write(&bin_path, &bin)?;
+        let json = serde_json::to_vec_pretty(&document).expect("glTF documents serialize");
+        write(path, &json)
+    }
+}
+
+fn bounds(points: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for point in points {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+    if points.is_empty() { ([0.0; 3], [0.0; 3]) } else { (min, max) }
+}
+
+#[derive(Serialize, Default)]
+struct Document {
+    asset: Asset,
+    scene: usize,
+    scenes: Vec<SceneJson>,
+    nodes: Vec<NodeJson>,
+    meshes: Vec<MeshJson>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    materials: Vec<MaterialJson>,
+    accessors: Vec<Accessor>,
+    #[serde(rename = "bufferViews")]
+    buffer_views: Vec<BufferView>,
+    buffers: Vec<BufferJson>,
+}
+
+impl Document {
+    /// Appends `items` as a buffer view with one accessor, returns the accessor
+    fn push<T: Components>(&mut self, bin: &mut Vec<u8>, items: &[T], kind: &'static str, target: u32,
+                           bounds: Option<([f32; 3], [f32; 3])>) -> usize {
+        let offset = bin.len();
+        for item in items {
+            for value in item.components() {
+                bin.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        self.view_and_accessor(offset, bin.len() - offset, target, Accessor {
+            buffer_view: 0,
+            component_type: FLOAT,
+            count: items.len(),
+            kind,
+            min: bounds.map(|(min, _)| min.to_vec()),
+            max: bounds.map(|(_, max)| max.to_vec()),
+        })
+    }
+
+    fn push_indices(&mut self, bin: &mut Vec<u8>, indices: &[u32]) -> usize {
+        let offset = bin.len();
+        for index in indices {
+            bin.extend_from_slice(&index.to_le_bytes());
+        }
+        self.view_and_accessor(offset, bin.len() - offset, ELEMENT_ARRAY_BUFFER, Accessor {
+            buffer_view: 0,
+            component_type: UNSIGNED_INT,
+            count: indices.len(),
+            kind: "SCALAR",
+            min: None,
+            max: None,
+        })
+    }
+
+    fn view_and_accessor(&mut self, offset: usize, length: usize, target: u32, mut accessor: Accessor) -> usize {
+        self.buffer_views.push(BufferView { buffer: 0, byte_offset: offset, byte_length: length, target });
+        accessor.buffer_view = self.buffer_views.len() - 1;
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+trait Components {
+    fn components(&self) -> &[f32];
+}
+
+impl Components for [f32; 2] {
+    fn components(&self) -> &[f32] {
+        self
+    }
+}
+
+impl Components for [f32; 3] {
+    fn components(&self) -> &[f32] {
+        self
+    }
+}
+
+impl Components for [f32; 4] {
+    fn components(&self) -> &[f32] {
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Asset {
+    version: &'static str,
+    generator: &'static str,
+}
+
+impl Default for Asset {
+    fn default() -> Asset {
+        Asset { version: "2.0", generator: "reactor_engine" }
+    }
+}
+
+#[derive(Serialize)]
+struct SceneJson {
+    nodes: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct NodeJson {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mesh: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matrix: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct MeshJson {
+    name: String,
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Serialize)]
+struct Primitive {
+    attributes: Attributes,
+    indices: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    material: Option<usize>,
+    mode: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct Attributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normal: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tangent: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    texcoord_0: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MaterialJson {
+    name: String,
+    pbr_metallic_roughness: Pbr,
+    emissive_factor: [f32; 3],
+    alpha_mode: &'static str,
+    double_sided: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Pbr {
+    base_color_factor: [f32; 4],
+    metallic_factor: f32,
+    roughness_factor: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: usize,
+    component_type: u32,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<Vec<f32>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    target: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferJson {
+    byte_length: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use lang::Vector3;
+    use mesh::primitives;
+
+    #[test]
+    fn exports_meshes_nodes_and_materials() {
+        let cube = primitives::cube(2.0);
+        let mut export = GltfExport::new();
+        let red = export.add_material(GltfMaterial::new("red", Color::linear(1.0, 0.0, 0.0, 1.0)).metallic_roughness(0.0, 0.5));
+        let mesh = export.add_mesh("cube", &cube, Some(red));
+        let parent = export.add_node("parent", None, Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)));
+        let child = export.add_node("child", Some(mesh), Matrix4::from_scale(1.0));
+        export.add_child(parent, child);
+
+        let (document, bin) = export.to_document();
+        assert_eq!(document["asset"]["version"], "2.0");
+        assert_eq!(document["scenes"][0]["nodes"], serde_json::json!([0]));
+        assert_eq!(document["nodes"][0]["children"], serde_json::json!([1]));
+        assert_eq!(document["nodes"][0]["matrix"][12], 1.0);
+        assert!(document["nodes"][1].get("matrix").is_none());
+        assert_eq!(document["materials"][0]["pbrMetallicRoughness"]["roughnessFactor"], 0.5);
+
+        let primitive = &document["meshes"][0]["primitives"][0];
+        assert_eq!(primitive["material"], 0);
+        let position = &document["accessors"][primitive["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(position["count"], cube.vertex_count());
+        assert_eq!(position["max"], serde_json::json!([1.0, 1.0, 1.0]));
+        let tangent = &document["accessors"][primitive["attributes"]["TANGENT"].as_u64().unwrap() as usize];
+        assert_eq!(tangent["type"], "VEC4");
+        let indices = &document["accessors"][primitive["indices"].as_u64().unwrap() as usize];
+        assert_eq!(indices["count"], cube.indices.len());
+        assert_eq!(document["buffers"][0]["byteLength"], bin.len());
+        assert_eq!(bin.len() % 4, 0);
+
+        let glb = export.to_glb();
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32::from_le_bytes([glb[8], glb[9], glb[10], glb[11]]) as usize, glb.len());
+        let json_length = u32::from_le_bytes([glb[12], glb[13], glb[14], glb[15]]) as usize;
+        assert_eq!(&glb[20 + json_length + 4..20 + json_length + 8], b"BIN\0");
+
+        let path = env::temp_dir().join(format!("reactor_export_{}.gltf", ::std::process::id()));
+        GltfExport::single("cube", &cube, None).save(&path).unwrap();
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written["buffers"][0]["uri"], path.with_extension("bin").file_name().unwrap().to_str().unwrap());
+        fs::remove_file(path.with_extension("bin")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use cgmath::prelude::*;
+
+use lang::{Float, Point3};
+use camera::Camera;
+use super::Mesh;
+
+/// GLSL helper for dithered cross-fades: call `lodDither(fade)` in the fragment shader with
+/// the `LodSelection` fade of the draw, and `lodDither(-fade)` for the level fading in
+pub const LOD_DITHER_GLSL: &str = r#"
+void lodDither(float fade)
+{
+    const float bayer[16] = float[16](
+         0.0 / 16.0,  8.0 / 16.0,  2.0 / 16.0, 10.0 / 16.0,
+        12.0 / 16.0,  4.0 / 16.0, 14.0 / 16.0,  6.0 / 16.0,
+         3.0 / 16.0, 11.0 / 16.0,  1.0 / 16.0,  9.0 / 16.0,
+        15.0 / 16.0,  7.0 / 16.0, 13.0 / 16.0,  5.0 / 16.0);
+    ivec2 p = ivec2(gl_FragCoord.xy) % 4;
+    float threshold = bayer[p.y * 4 + p.x];
+    if (fade >= 0.0 ? threshold < fade : threshold >= -fade) discard;
+}
+"#;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LodLevel<T> {
+    pub mesh: T,
+    /// the level is used up to this camera distance
+    pub max_distance: Float,
+}
+
+/// Which level(s) of a `LodGroup` to draw this frame
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LodSelection {
+    Single(usize),
+    /// both levels are drawn, `fade` goes 0 to 1 while `from` dithers out and `to` dithers in
+    CrossFade { from: usize, to: usize, fade: Float },
+}
+
+/// Meshes of decreasing detail, ordered by increasing `max_distance`
+#[derive(Clone, PartialEq, Debug)]
+pub struct LodGroup<T = Mesh> {
+    pub levels: Vec<LodLevel<T>>,
+    /// distance before a switch over which the two levels are cross-faded, 0 switches instantly
+    pub fade_range: Float,
+    /// scales distances, values > 1 keep the detailed levels longer
+    pub bias: Float,
+}
+
+impl<T> LodGroup<T> {
+    pub fn new() -> LodGroup<T> {
+        LodGroup {
+            levels: vec![],
+            fade_range: 0.0,
+            bias: 1.0,
+        }
+    }
+
+    pub fn level(mut self, mesh: T, max_distance: Float) -> LodGroup<T> {
+        self.levels.push(LodLevel { mesh, max_distance });
+        self.levels.sort_by(|a, b| a.max_distance.total_cmp(&b.max_distance));
+        self
+    }
+
+    pub fn fade_range(mut self, fade_range: Float) -> LodGroup<T> {
+        self.fade_range = fade_range;
+        self
+    }
+
+    /// Level(s) for an object at `distance` from the camera, `None` past the last level
+    pub fn select(&self, distance: Float) -> Option<LodSelection> {
+        let distance = distance / self.bias;
+        let index = self.levels.iter().position(|level| distance <= level.max_distance)?;
+
+        let switch_at = self.levels[index].max_distance;
+        let fade_start = switch_at - self.fade_range;
+        if self.fade_range > 0.0 && distance > fade_start && index + 1 < self.levels.len() {
+            let fade = (distance - fade_start) / self.fade_range;
+            return Some(LodSelection::CrossFade { from: index, to: index + 1, fade });
+        }
+
+        Some(LodSelection::Single(index))
+    }
+
+    /// Level(s) for an object whose (world space) bounds center is `center`
+    pub fn select_for_camera(&self, camera: &Camera, center: Point3) -> Option<LodSelection> {
+        self.select(camera.position.distance(center))
+    }
+}
+
+impl<T> Default for LodGroup<T> {
+    fn default() -> LodGroup<T> {
+        LodGroup::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_level_by_distance() {
+        let group = LodGroup::new().level("high", 10.0).level("low", 50.0).level("mid", 20.0);
+        assert_eq!(group.levels[1].mesh, "mid");
+        assert_eq!(group.select(5.0), Some(LodSelection::Single(0)));
+        assert_eq!(group.select(15.0), Some(LodSelection::Single(1)));
+        assert_eq!(group.select(50.0), Some(LodSelection::Single(2)));
+        assert_eq!(group.select(51.0), None);
+    }
+
+    #[test]
+    fn cross_fades_before_switch() {
+        let group = LodGroup::new().level(0, 10.0).level(1, 20.0).fade_range(2.0);
+        assert_eq!(group.select(7.0), Some(LodSelection::Single(0)));
+        assert_eq!(group.select(9.0), Some(LodSelection::CrossFade { from: 0, to: 1, fade: 0.5 }));
+        // no level to fade into after the last one
+        assert_eq!(group.select(19.0), Some(LodSelection::Single(1)));
+    }
+
+    #[test]
+    fn nan_distances_sort_last() {
+        let group = LodGroup::new().level("broken", Float::NAN).level("high", 10.0);
+        assert_eq!(group.levels[0].mesh, "high");
+        assert_eq!(group.select(5.0), Some(LodSelection::Single(0)));
+        assert_eq!(group.select(20.0), None);
+    }
+}
@@ -0,0 +1,258 @@
+pub mod csg;
+pub mod edit;
+pub mod gltf;
+pub mod lod;
+pub mod morph;
+pub mod optimize;
+pub mod pool;
+pub mod primitives;
+pub mod simplify;
+pub mod tangents;
+
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{Point3, Vector2, Vector3};
+use bounds::{Aabb, BoundingSphere};
+use gl_state::GlState;
+
+/// CPU side triangle mesh, every attribute vector is either empty or has one entry per position
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<Vector2>,
+    pub tangents: Vec<Vector3>,
+    pub bitangents: Vec<Vector3>,
+    /// counter-clockwise front faces
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn new() -> MeshData {
+        MeshData::default()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Pushes a vertex with all the attributes and returns its index,
+    /// the bitangent is `normal x tangent` (right-handed UV mapping)
+    pub fn push_vertex(&mut self, position: Point3, normal: Vector3, uv: Vector2, tangent: Vector3) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        self.tangents.push(tangent);
+        self.bitangents.push(normal.cross(tangent));
+        (self.positions.len() - 1) as u32
+    }
+
+    pub fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    /// Corner indices of every triangle
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.indices.chunks(3).map(|t| [t[0], t[1], t[2]])
+    }
+
+    /// Appends the vertices and triangles of another mesh
+    pub fn append(&mut self, other: &MeshData) {
+        let offset = self.positions.len() as u32;
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.tangents.extend_from_slice(&other.tangents);
+        self.bitangents.extend_from_slice(&other.bitangents);
+        self.indices.extend(other.indices.iter().map(|i| i + offset));
+    }
+
+    /// (Re)computes smooth normals, averaging the faces around each vertex weighted by their area
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vector3::zero(); self.positions.len()];
+        for [a, b, c] in self.triangles() {
+            let (pa, pb, pc) = (self.positions[a as usize], self.positions[b as usize], self.positions[c as usize]);
+            let face = (pb - pa).cross(pc - pa);
+            for &corner in &[a, b, c] {
+                normals[corner as usize] += face;
+            }
+        }
+        for normal in normals.iter_mut() {
+            if normal.magnitude2() > 0.0 {
+                *normal = normal.normalize();
+            }
+        }
+        self.normals = normals;
+    }
+
+    /// (Re)computes tangents and bitangents from positions, normals and UVs
+    pub fn compute_tangents(&mut self) {
+        let (tangents, bitangents) = tangents::compute(self);
+        self.tangents = tangents;
+        self.bitangents = bitangents;
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.positions.iter())
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(self.positions.iter())
+    }
+}
+
+/// Number of floats per vertex in the interleaved buffer:
+/// position (location 0), normal (1), uv (2), tangent (3), bitangent (4)
+const VERTEX_FLOATS: usize = 3 + 3 + 2 + 3 + 3;
+
+/// Mesh uploaded to GPU buffers, drawn as indexed triangles
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mesh {
+    pub vao: u32,
+    pub vbo: u32,
+    pub ebo: u32,
+    pub index_count: i32,
+}
+
+impl Mesh {
+    pub fn new(data: &MeshData) -> Mesh {
+        let vertices = interleave(data);
+        let mut mesh = Mesh::create(vertices.as_ptr(), vertices.len(), data.indices.as_ptr(), data.indices.len(),
+                                    gl::STATIC_DRAW);
+        mesh.index_count = data.indices.len() as i32;
+        mesh
+    }
+
+    /// Mesh with room for `vertex_capacity` vertices and `index_capacity` indices, left undefined
+    /// until written with `update_vertices`/`update_indices`, and `index_count` zero
+    pub fn dynamic(vertex_capacity: usize, index_capacity: usize) -> Mesh {
+        Mesh::create(ptr::null(), vertex_capacity * VERTEX_FLOATS, ptr::null(), index_capacity, gl::DYNAMIC_DRAW)
+    }
+
+    fn create(vertices: *const GLfloat, float_count: usize, indices: *const GLuint, index_count: usize,
+              usage: GLenum) -> Mesh {
+        let mut mesh = Mesh::default();
+        unsafe {
+            gl::GenVertexArrays(1, &mut mesh.vao);
+            gl::GenBuffers(1, &mut mesh.vbo);
+            gl::GenBuffers(1, &mut mesh.ebo);
+
+            gl::BindVertexArray(mesh.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (float_count * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           vertices as *const GLvoid,
+                           usage);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, mesh.ebo);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+                           (index_count * mem::size_of::<GLuint>()) as GLsizeiptr,
+                           indices as *const GLvoid,
+                           usage);
+
+            let stride = (VERTEX_FLOATS * mem::size_of::<GLfloat>()) as GLsizei;
+            let mut offset = 0;
+            for (location, &size) in [3, 3, 2, 3, 3].iter().enumerate() {
+                gl::EnableVertexAttribArray(location as GLuint);
+                gl::VertexAttribPointer(location as GLuint, size, gl::FLOAT, gl::FALSE, stride,
+                                        (offset * mem::size_of::<GLfloat>()) as *const GLvoid);
+                offset += size as usize;
+            }
+
+            gl::BindVertexArray(0);
+        }
+        mesh
+    }
+
+    /// Overwrites interleaved vertices starting at `first_vertex`, which must fit the buffer.
+    /// Goes through the copy target so no vertex array binding is disturbed.
+    pub fn update_vertices(&self, first_vertex: usize, vertices: &[GLfloat]) {
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.vbo);
+            gl::BufferSubData(gl::COPY_WRITE_BUFFER,
+                              (first_vertex * VERTEX_FLOATS * mem::size_of::<GLfloat>()) as GLintptr,
+                              mem::size_of_val(vertices) as GLsizeiptr,
+                              vertices.as_ptr() as *const GLvoid);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    /// Overwrites indices starting at `first_index`, which must fit the buffer
+    pub fn update_indices(&self, first_index: usize, indices: &[GLuint]) {
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.ebo);
+            gl::BufferSubData(gl::COPY_WRITE_BUFFER,
+                              (first_index * mem::size_of::<GLuint>()) as GLintptr,
+                              mem::size_of_val(indices) as GLsizeiptr,
+                              indices.as_ptr() as *const GLvoid);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    pub fn draw(&self) {
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawElements(gl::TRIANGLES, self.index_count, gl::UNSIGNED_INT, ptr::null());
+            gl::BindVertexArray(0);
+        }
+    }
+
+    /// Draws `index_count` indices starting at `first_index`
+    pub fn draw_range(&self, first_index: u32, index_count: u32) {
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawElements(gl::TRIANGLES, index_count as GLsizei, gl::UNSIGNED_INT,
+                             (first_index as usize * mem::size_of::<GLuint>()) as *const GLvoid);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    /// Draws without unbinding, binding the vertex array through the state cache
+    pub fn draw_with_state(&self, state: &mut GlState) {
+        state.bind_vertex_array(self.vao);
+        unsafe {
+            gl::DrawElements(gl::TRIANGLES, self.index_count, gl::UNSIGNED_INT, ptr::null());
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteBuffers(1, &self.ebo);
+        }
+        *self = Mesh::default();
+    }
+}
+
+/// missing attributes are filled with zeros
+fn interleave(data: &MeshData) -> Vec<GLfloat> {
+    interleave_range(data, 0..data.positions.len())
+}
+
+fn interleave_range(data: &MeshData, range: Range<usize>) -> Vec<GLfloat> {
+    let mut vertices = Vec::with_capacity(range.len() * VERTEX_FLOATS);
+    for i in range {
+        let position = data.positions[i];
+        let normal = data.normals.get(i).cloned().unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0));
+        let uv = data.uvs.get(i).cloned().unwrap_or_else(|| Vector2::new(0.0, 0.0));
+        let tangent = data.tangents.get(i).cloned().unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0));
+        let bitangent = data.bitangents.get(i).cloned().unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0));
+        let vertex = [position.x, position.y, position.z,
+                      normal.x, normal.y, normal.z,
+                      uv.x, uv.y,
+                      tangent.x, tangent.y, tangent.z,
+                      bitangent.x, bitangent.y, bitangent.z];
+        vertices.extend(vertex.iter().map(|&value| value as GLfloat));
+    }
+    vertices
+}
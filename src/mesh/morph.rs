@@ -0,0 +1,246 @@
+//! Morph targets (blend shapes): per-vertex position and normal offsets blended by weights,
+//! for facial animation and corrective shapes. The offsets live in a texture buffer the vertex
+//! shader reads with `MORPH_GLSL`, so the mesh buffers stay untouched.
+
+use std::ffi::CString;
+use std::mem;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{Float, Vector3, TimeSec};
+use logging;
+use shader::Shader;
+use super::MeshData;
+
+/// Targets with a non-zero weight the shader blends at once, the largest weights win
+pub const MAX_ACTIVE_MORPHS: usize = 8;
+
+/// `applyMorph(position, normal)` adds the weighted offsets of the vertex `gl_VertexID` with
+/// the uniforms `MorphTargets::bind` sets; call it in the vertex shader before transforming.
+pub const MORPH_GLSL: &str = r#"
+uniform samplerBuffer morphDeltas;
+uniform int morphVertexCount;
+uniform int morphActive;
+uniform int morphIndices[8];
+uniform float morphWeights[8];
+
+void applyMorph(inout vec3 position, inout vec3 normal)
+{
+    for (int i = 0; i < morphActive; ++i) {
+        int texel = (morphIndices[i] * morphVertexCount + gl_VertexID) * 2;
+        position += texelFetch(morphDeltas, texel).xyz * morphWeights[i];
+        normal += texelFetch(morphDeltas, texel + 1).xyz * morphWeights[i];
+    }
+}
+"#;
+
+/// Offsets from the base mesh to one shape, one entry per base vertex
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MorphTarget {
+    pub name: String,
+    pub position_deltas: Vec<Vector3>,
+    pub normal_deltas: Vec<Vector3>,
+}
+
+impl MorphTarget {
+    /// Target reaching `shape`, which has the vertices of `base` moved
+    pub fn from_shape(name: &str, base: &MeshData, shape: &MeshData) -> MorphTarget {
+        let position_deltas = base.positions.iter().zip(shape.positions.iter())
+            .map(|(base, shape)| shape - base)
+            .collect();
+        let normal_deltas = base.normals.iter().zip(shape.normals.iter())
+            .map(|(base, shape)| shape - base)
+            .collect();
+        MorphTarget { name: name.to_string(), position_deltas, normal_deltas }
+    }
+}
+
+/// `base` with the targets blended in on the CPU, normals renormalized; for picking, physics
+/// or drivers without texture buffers
+pub fn blend(base: &MeshData, targets: &[MorphTarget], weights: &[Float]) -> MeshData {
+    let mut mesh = base.clone();
+    for (target, &weight) in targets.iter().zip(weights.iter()) {
+        if weight == 0.0 {
+            continue;
+        }
+        for (position, delta) in mesh.positions.iter_mut().zip(target.position_deltas.iter()) {
+            *position += delta * weight;
+        }
+        for (normal, delta) in mesh.normals.iter_mut().zip(target.normal_deltas.iter()) {
+            *normal += delta * weight;
+        }
+    }
+    for normal in mesh.normals.iter_mut() {
+        if normal.magnitude2() > 0.0 {
+            *normal = normal.normalize();
+        }
+    }
+    mesh
+}
+
+/// Indices and weights of the targets the shader blends: the `MAX_ACTIVE_MORPHS` largest
+/// non-zero weights
+pub fn active_weights(weights: &[Float]) -> Vec<(usize, Float)> {
+    let mut active: Vec<(usize, Float)> = weights.iter().cloned().enumerate()
+        .filter(|&(_, weight)| weight != 0.0)
+        .collect();
+    active.sort_by(|a, b| b.1.abs().partial_cmp(&a.1.abs()).unwrap_or(::std::cmp::Ordering::Equal));
+    active.truncate(MAX_ACTIVE_MORPHS);
+    active
+}
+
+/// The offsets of a mesh's targets uploaded to a texture buffer, two RGB32F texels per
+/// vertex and target
+pub struct MorphTargets {
+    pub vertex_count: usize,
+    pub target_count: usize,
+    buffer: u32,
+    texture: u32,
+}
+
+impl MorphTargets {
+    pub fn new(targets: &[MorphTarget], vertex_count: usize) -> MorphTargets {
+        let mut data: Vec<GLfloat> = Vec::with_capacity(targets.len() * vertex_count * 6);
+        for target in targets {
+            for v in 0..vertex_count {
+                let position = target.position_deltas.get(v).cloned().unwrap_or_else(Vector3::zero);
+                let normal = target.normal_deltas.get(v).cloned().unwrap_or_else(Vector3::zero);
+                data.extend([position.x, position.y, position.z, normal.x, normal.y, normal.z]
+                    .iter().map(|&value| value as GLfloat));
+            }
+        }
+
+        let mut morphs = MorphTargets { vertex_count, target_count: targets.len(), buffer: 0, texture: 0 };
+        unsafe {
+            gl::GenBuffers(1, &mut morphs.buffer);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, morphs.buffer);
+            gl::BufferData(gl::TEXTURE_BUFFER, (data.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           data.as_ptr() as *const GLvoid, gl::STATIC_DRAW);
+            gl::GenTextures(1, &mut morphs.texture);
+            gl::BindTexture(gl::TEXTURE_BUFFER, morphs.texture);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGB32F, morphs.buffer);
+            gl::BindTexture(gl::TEXTURE_BUFFER, 0);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        }
+        engine_debug!(logging::RENDERER, "{} morph targets of {} vertices", targets.len(), vertex_count);
+        morphs
+    }
+
+    /// Binds the offsets on texture `unit` and sets the `MORPH_GLSL` uniforms of `shader`,
+    /// which has to be in use, for the target `weights`
+    pub fn bind(&self, shader: &Shader, unit: u32, weights: &[Float]) {
+        let active = active_weights(&weights[..weights.len().min(self.target_count)]);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_BUFFER, self.texture);
+            shader.setInt(c_str!("morphDeltas"), unit as i32);
+            shader.setInt(c_str!("morphVertexCount"), self.vertex_count as i32);
+            shader.setInt(c_str!("morphActive"), active.len() as i32);
+            for (i, &(target, weight)) in active.iter().enumerate() {
+                let index = CString::new(format!("morphIndices[{}]", i)).unwrap();
+                let name = CString::new(format!("morphWeights[{}]", i)).unwrap();
+                shader.setInt(&index, target as i32);
+                shader.setFloat(&name, weight);
+            }
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+        self.texture = 0;
+        self.buffer = 0;
+    }
+}
+
+/// Target weights at a point in time
+#[derive(Clone, PartialEq, Debug)]
+pub struct MorphKey {
+    pub time: TimeSec,
+    pub weights: Vec<Float>,
+}
+
+/// Keyframed weights, interpolated linearly between keys, e.g. a facial expression or a
+/// lip sync track. Keys are kept sorted by time.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MorphAnimation {
+    pub keys: Vec<MorphKey>,
+    pub looped: bool,
+}
+
+impl MorphAnimation {
+    pub fn new(looped: bool) -> MorphAnimation {
+        MorphAnimation { keys: Vec::new(), looped }
+    }
+
+    pub fn key(mut self, time: TimeSec, weights: Vec<Float>) -> MorphAnimation {
+        let index = self.keys.partition_point(|key| key.time <= time);
+        self.keys.insert(index, MorphKey { time, weights });
+        self
+    }
+
+    pub fn duration(&self) -> TimeSec {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    /// Weights at `time`, holding the first and last keys outside of them unless looped
+    pub fn sample(&self, time: TimeSec) -> Vec<Float> {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Vec::new(),
+        };
+        let duration = self.duration();
+        let time = if self.looped && duration > 0.0 { time.rem_euclid(duration) } else { time };
+        if time <= first.time {
+            return first.weights.clone();
+        }
+        if time >= last.time {
+            return last.weights.clone();
+        }
+
+        let next = self.keys.partition_point(|key| key.time <= time);
+        let (a, b) = (&self.keys[next - 1], &self.keys[next]);
+        let t = ((time - a.time) / (b.time - a.time)) as Float;
+        let count = a.weights.len().max(b.weights.len());
+        (0..count)
+            .map(|i| {
+                let from = a.weights.get(i).cloned().unwrap_or(0.0);
+                let to = b.weights.get(i).cloned().unwrap_or(0.0);
+                from + (to - from) * t
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::Point3;
+
+    #[test]
+    fn blends_targets_and_samples_weights() {
+        let mut base = MeshData::new();
+        base.positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)];
+        base.normals = vec![Vector3::unit_y(), Vector3::unit_y()];
+        let mut smile = base.clone();
+        smile.positions[1] = Point3::new(1.0, 2.0, 0.0);
+        let target = MorphTarget::from_shape("smile", &base, &smile);
+        assert_eq!(target.position_deltas[1], Vector3::new(0.0, 2.0, 0.0));
+
+        let blended = blend(&base, &[target], &[0.25]);
+        assert_eq!(blended.positions[1], Point3::new(1.0, 0.5, 0.0));
+        assert_eq!(blended.normals[0], Vector3::unit_y());
+
+        let active = active_weights(&[0.0, 0.2, -0.9, 0.0, 0.5]);
+        assert_eq!(active, vec![(2, -0.9), (4, 0.5), (1, 0.2)]);
+
+        let animation = MorphAnimation::new(true).key(2.0, vec![1.0, 0.0]).key(0.0, vec![0.0]);
+        assert_eq!(animation.sample(1.0), vec![0.5, 0.0]);
+        assert_eq!(animation.sample(3.0), vec![0.5, 0.0]);
+        assert_eq!(MorphAnimation::new(false).key(1.0, vec![0.3]).sample(5.0), vec![0.3]);
+    }
+}
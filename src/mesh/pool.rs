@@ -0,0 +1,82 @@
+use lang::{Vector2, Vector3};
+use super::{Mesh, MeshData};
+
+/// Where one mesh of a `MeshPool` lives in the shared buffers
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MeshRange {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+}
+
+/// Many meshes in one vertex and index buffer, so they can be drawn with a single
+/// multi-draw call. Indices stay local to each mesh and are offset by `base_vertex`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MeshPool {
+    pub mesh: Mesh,
+    pub ranges: Vec<MeshRange>,
+}
+
+impl MeshPool {
+    pub fn new(meshes: &[MeshData]) -> MeshPool {
+        let (data, ranges) = merge(meshes);
+        MeshPool { mesh: Mesh::new(&data), ranges }
+    }
+
+    pub fn vao(&self) -> u32 {
+        self.mesh.vao
+    }
+
+    pub fn delete(&mut self) {
+        self.mesh.delete();
+        self.ranges.clear();
+    }
+}
+
+/// Concatenates the meshes without offsetting their indices, missing attributes are zero-filled
+pub fn merge(meshes: &[MeshData]) -> (MeshData, Vec<MeshRange>) {
+    let mut merged = MeshData::new();
+    let mut ranges = Vec::with_capacity(meshes.len());
+    for data in meshes {
+        let count = data.positions.len();
+        ranges.push(MeshRange {
+            first_index: merged.indices.len() as u32,
+            index_count: data.indices.len() as u32,
+            base_vertex: merged.positions.len() as i32,
+        });
+        merged.positions.extend_from_slice(&data.positions);
+        extend_padded(&mut merged.normals, &data.normals, count, Vector3::new(0.0, 0.0, 0.0));
+        extend_padded(&mut merged.uvs, &data.uvs, count, Vector2::new(0.0, 0.0));
+        extend_padded(&mut merged.tangents, &data.tangents, count, Vector3::new(0.0, 0.0, 0.0));
+        extend_padded(&mut merged.bitangents, &data.bitangents, count, Vector3::new(0.0, 0.0, 0.0));
+        merged.indices.extend_from_slice(&data.indices);
+    }
+    (merged, ranges)
+}
+
+fn extend_padded<T: Copy>(into: &mut Vec<T>, from: &[T], count: usize, zero: T) {
+    into.extend((0..count).map(|i| from.get(i).cloned().unwrap_or(zero)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::primitives;
+
+    #[test]
+    fn keeps_indices_local() {
+        let cube = primitives::cube(1.0);
+        let mut bare = MeshData::new();
+        bare.positions = cube.positions.clone();
+        bare.indices = cube.indices.clone();
+
+        let (merged, ranges) = merge(&[cube.clone(), bare]);
+        assert_eq!(ranges[1], MeshRange {
+            first_index: cube.indices.len() as u32,
+            index_count: cube.indices.len() as u32,
+            base_vertex: cube.positions.len() as i32,
+        });
+        assert_eq!(merged.normals.len(), merged.positions.len());
+        assert_eq!(&merged.indices[cube.indices.len()..], &cube.indices[..]);
+    }
+}
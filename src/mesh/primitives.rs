@@ -0,0 +1,384 @@
+//! Parametric shapes centered at the origin, with outward facing normals,
+//! counter-clockwise front faces, and tangents pointing along increasing `u`.
+
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Vector2, Vector3, PI};
+use super::MeshData;
+
+/// Axis aligned cube with 4 vertices per face, so every face has its own normal and full UV square
+pub fn cube(size: Float) -> MeshData {
+    let mut mesh = MeshData::new();
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_z()),
+        (-Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_y(), Vector3::unit_x()),
+        (-Vector3::unit_y(), Vector3::unit_x()),
+        (Vector3::unit_z(), Vector3::unit_x()),
+        (-Vector3::unit_z(), -Vector3::unit_x()),
+    ];
+    for &(normal, tangent) in faces.iter() {
+        grid_face(&mut mesh, Point3::from_vec(normal * size * 0.5), normal, tangent, size, size, 1, 1);
+    }
+    mesh
+}
+
+/// Single quad in the XZ plane facing +Y
+pub fn plane(width: Float, depth: Float) -> MeshData {
+    grid(width, depth, 1, 1)
+}
+
+/// Plane in the XZ plane facing +Y, split into `segments_x` x `segments_z` quads
+pub fn grid(width: Float, depth: Float, segments_x: u32, segments_z: u32) -> MeshData {
+    let mut mesh = MeshData::new();
+    grid_face(&mut mesh, Point3::origin(), Vector3::unit_y(), Vector3::unit_x(),
+              width, depth, segments_x.max(1), segments_z.max(1));
+    mesh
+}
+
+/// Latitude/longitude sphere, `sectors` around the Y axis and `stacks` from pole to pole
+pub fn uv_sphere(radius: Float, sectors: u32, stacks: u32) -> MeshData {
+    let sectors = sectors.max(3);
+    let stacks = stacks.max(2);
+    let rings: Vec<(Float, Float)> = (0..=stacks)
+        .map(|i| (PI * i as Float / stacks as Float, 0.0))
+        .collect();
+    let mut mesh = MeshData::new();
+    lat_long(&mut mesh, radius, sectors, &rings, |i| 1.0 - i as Float / stacks as Float);
+    mesh
+}
+
+/// Recursively subdivided icosahedron, evenly distributed vertices without pole pinching
+pub fn icosphere(radius: Float, subdivisions: u32) -> MeshData {
+    let t = (1.0 + (5.0 as Float).sqrt()) / 2.0;
+    let mut directions: Vec<Vector3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| Vector3::new(x, y, z).normalize()).collect();
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, directions: &mut Vec<Vector3>| -> u32 {
+            let key = if a < b { (a, b) } else { (b, a) };
+            *midpoints.entry(key).or_insert_with(|| {
+                let direction = (directions[a as usize] + directions[b as usize]).normalize();
+                directions.push(direction);
+                (directions.len() - 1) as u32
+            })
+        };
+
+        let mut subdivided = Vec::with_capacity(faces.len() * 4);
+        for &[a, b, c] in faces.iter() {
+            let ab = midpoint(a, b, &mut directions);
+            let bc = midpoint(b, c, &mut directions);
+            let ca = midpoint(c, a, &mut directions);
+            subdivided.push([a, ab, ca]);
+            subdivided.push([b, bc, ab]);
+            subdivided.push([c, ca, bc]);
+            subdivided.push([ab, bc, ca]);
+        }
+        faces = subdivided;
+    }
+
+    let mut mesh = MeshData::new();
+    for direction in directions.iter() {
+        let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+        let v = 0.5 + direction.y.asin() / PI;
+        mesh.push_vertex(Point3::from_vec(direction * radius), *direction, Vector2::new(u, v),
+                         around_y_tangent(*direction));
+    }
+
+    // triangles crossing the u seam get their own copies of the vertices with u + 1,
+    // otherwise they'd interpolate over the whole texture
+    let mut seam_copies: HashMap<u32, u32> = HashMap::new();
+    for face in faces.iter_mut() {
+        let us: Vec<Float> = face.iter().map(|&i| mesh.uvs[i as usize].x).collect();
+        let max_u = us.iter().cloned().fold(Float::MIN, Float::max);
+        let min_u = us.iter().cloned().fold(Float::MAX, Float::min);
+        if max_u - min_u > 0.5 {
+            for index in face.iter_mut() {
+                if mesh.uvs[*index as usize].x < 0.5 {
+                    let original = *index as usize;
+                    *index = *seam_copies.entry(*index).or_insert_with(|| {
+                        let uv = mesh.uvs[original] + Vector2::unit_x();
+                        let (position, normal, tangent) =
+                            (mesh.positions[original], mesh.normals[original], mesh.tangents[original]);
+                        mesh.push_vertex(position, normal, uv, tangent)
+                    });
+                }
+            }
+        }
+        mesh.push_triangle(face[0], face[1], face[2]);
+    }
+    mesh
+}
+
+/// Cylinder along the Y axis with capped ends
+pub fn cylinder(radius: Float, height: Float, sectors: u32) -> MeshData {
+    let sectors = sectors.max(3);
+    let half = height * 0.5;
+    let mut mesh = MeshData::new();
+
+    for ring in 0..2 {
+        let y = if ring == 0 { -half } else { half };
+        for j in 0..=sectors {
+            let theta = 2.0 * PI * j as Float / sectors as Float;
+            let normal = Vector3::new(theta.sin(), 0.0, theta.cos());
+            mesh.push_vertex(Point3::new(normal.x * radius, y, normal.z * radius), normal,
+                             Vector2::new(j as Float / sectors as Float, ring as Float),
+                             Vector3::new(theta.cos(), 0.0, -theta.sin()));
+        }
+    }
+    for j in 0..sectors {
+        let bottom = j;
+        let top = sectors + 1 + j;
+        mesh.push_triangle(bottom, bottom + 1, top + 1);
+        mesh.push_triangle(bottom, top + 1, top);
+    }
+
+    cap(&mut mesh, radius, half, sectors, true);
+    cap(&mut mesh, radius, -half, sectors, false);
+    mesh
+}
+
+/// Cone along the Y axis with the apex at `+height / 2` and a capped base
+pub fn cone(radius: Float, height: Float, sectors: u32) -> MeshData {
+    let sectors = sectors.max(3);
+    let half = height * 0.5;
+    let mut mesh = MeshData::new();
+    let side_normal = |theta: Float| Vector3::new(height * theta.sin(), radius, height * theta.cos()).normalize();
+
+    for j in 0..=sectors {
+        let theta = 2.0 * PI * j as Float / sectors as Float;
+        mesh.push_vertex(Point3::new(radius * theta.sin(), -half, radius * theta.cos()), side_normal(theta),
+                         Vector2::new(j as Float / sectors as Float, 0.0),
+                         Vector3::new(theta.cos(), 0.0, -theta.sin()));
+    }
+    // one apex vertex per sector, with the normal of the sector's middle
+    for j in 0..sectors {
+        let theta = 2.0 * PI * (j as Float + 0.5) / sectors as Float;
+        let apex = mesh.push_vertex(Point3::new(0.0, half, 0.0), side_normal(theta),
+                                    Vector2::new((j as Float + 0.5) / sectors as Float, 1.0),
+                                    Vector3::new(theta.cos(), 0.0, -theta.sin()));
+        mesh.push_triangle(j, j + 1, apex);
+    }
+
+    cap(&mut mesh, radius, -half, sectors, false);
+    mesh
+}
+
+/// Cylinder of `height` along the Y axis closed by two hemispheres, total height is `height + 2 * radius`
+pub fn capsule(radius: Float, height: Float, sectors: u32, hemisphere_stacks: u32) -> MeshData {
+    let sectors = sectors.max(3);
+    let hemisphere_stacks = hemisphere_stacks.max(1);
+    let half = height * 0.5;
+
+    // the two equator rings are duplicated at +half and -half, the band between them is the cylinder
+    let mut rings = Vec::new();
+    for i in 0..=hemisphere_stacks {
+        rings.push((0.5 * PI * i as Float / hemisphere_stacks as Float, half));
+    }
+    for i in 0..=hemisphere_stacks {
+        rings.push((0.5 * PI * (1.0 + i as Float / hemisphere_stacks as Float), -half));
+    }
+
+    // v follows the arc length from the top
+    let total = PI * radius + height;
+    let arc_lengths: Vec<Float> = rings.iter().enumerate()
+        .map(|(i, &(phi, _))| if i <= hemisphere_stacks as usize { radius * phi } else { height + radius * phi })
+        .collect();
+
+    let mut mesh = MeshData::new();
+    lat_long(&mut mesh, radius, sectors, &rings, |i| 1.0 - arc_lengths[i as usize] / total);
+    mesh
+}
+
+/// Torus around the Y axis, `major_radius` to the center of the tube of radius `minor_radius`
+pub fn torus(major_radius: Float, minor_radius: Float, major_segments: u32, minor_segments: u32) -> MeshData {
+    let major_segments = major_segments.max(3);
+    let minor_segments = minor_segments.max(3);
+    let mut mesh = MeshData::new();
+
+    for i in 0..=major_segments {
+        let theta = 2.0 * PI * i as Float / major_segments as Float;
+        let direction = Vector3::new(theta.sin(), 0.0, theta.cos());
+        let tangent = Vector3::new(theta.cos(), 0.0, -theta.sin());
+        for j in 0..=minor_segments {
+            let phi = 2.0 * PI * j as Float / minor_segments as Float;
+            let normal = direction * phi.cos() + Vector3::unit_y() * phi.sin();
+            let position = direction * major_radius + normal * minor_radius;
+            mesh.push_vertex(Point3::from_vec(position), normal,
+                             Vector2::new(i as Float / major_segments as Float, j as Float / minor_segments as Float),
+                             tangent);
+        }
+    }
+
+    let row = minor_segments + 1;
+    for i in 0..major_segments {
+        for j in 0..minor_segments {
+            let a = i * row + j;
+            let b = (i + 1) * row + j;
+            mesh.push_triangle(a, b, a + 1);
+            mesh.push_triangle(a + 1, b, b + 1);
+        }
+    }
+    mesh
+}
+
+/// Tangent along increasing longitude, for vertices on a sphere-like surface around the Y axis
+fn around_y_tangent(normal: Vector3) -> Vector3 {
+    let tangent = Vector3::new(normal.z, 0.0, -normal.x);
+    if tangent.magnitude2() < 1e-12 {
+        Vector3::unit_x()
+    } else {
+        tangent.normalize()
+    }
+}
+
+/// Flat rectangle facing `normal`, `u` along `tangent` and `v` along `normal x tangent`
+#[allow(clippy::too_many_arguments)]
+fn grid_face(mesh: &mut MeshData, center: Point3, normal: Vector3, tangent: Vector3,
+             size_u: Float, size_v: Float, segments_u: u32, segments_v: u32) {
+    let bitangent = normal.cross(tangent);
+    let first = mesh.vertex_count() as u32;
+
+    for iv in 0..=segments_v {
+        for iu in 0..=segments_u {
+            let u = iu as Float / segments_u as Float;
+            let v = iv as Float / segments_v as Float;
+            let position = center + tangent * ((u - 0.5) * size_u) + bitangent * ((v - 0.5) * size_v);
+            mesh.push_vertex(position, normal, Vector2::new(u, v), tangent);
+        }
+    }
+
+    let row = segments_u + 1;
+    for iv in 0..segments_v {
+        for iu in 0..segments_u {
+            let a = first + iv * row + iu;
+            let b = a + 1;
+            let c = a + row + 1;
+            let d = a + row;
+            mesh.push_triangle(a, b, c);
+            mesh.push_triangle(a, c, d);
+        }
+    }
+}
+
+/// Rings of a sphere-like surface: each ring is `(polar angle from +Y, y offset)`,
+/// `v_of_ring` gives the v texture coordinate of a ring
+fn lat_long<F: Fn(u32) -> Float>(mesh: &mut MeshData, radius: Float, sectors: u32,
+                                 rings: &[(Float, Float)], v_of_ring: F) {
+    let first = mesh.vertex_count() as u32;
+
+    for (i, &(phi, y_offset)) in rings.iter().enumerate() {
+        for j in 0..=sectors {
+            let theta = 2.0 * PI * j as Float / sectors as Float;
+            let normal = Vector3::new(phi.sin() * theta.sin(), phi.cos(), phi.sin() * theta.cos());
+            let position = Point3::new(normal.x * radius, normal.y * radius + y_offset, normal.z * radius);
+            mesh.push_vertex(position, normal,
+                             Vector2::new(j as Float / sectors as Float, v_of_ring(i as u32)),
+                             Vector3::new(theta.cos(), 0.0, -theta.sin()));
+        }
+    }
+
+    let row = sectors + 1;
+    let last_ring = rings.len() as u32 - 1;
+    for i in 0..last_ring {
+        for j in 0..sectors {
+            let k1 = first + i * row + j;
+            let k2 = k1 + row;
+            // the first and the last ring are the poles, skip the degenerate triangles there
+            if i != 0 {
+                mesh.push_triangle(k1, k2, k1 + 1);
+            }
+            if i != last_ring - 1 {
+                mesh.push_triangle(k1 + 1, k2, k2 + 1);
+            }
+        }
+    }
+}
+
+/// Disc closing a shape along the Y axis at height `y`, facing up or down
+fn cap(mesh: &mut MeshData, radius: Float, y: Float, sectors: u32, up: bool) {
+    let normal = if up { Vector3::unit_y() } else { -Vector3::unit_y() };
+    // v runs along normal x tangent, like in grid_face
+    let v_sign = if up { -1.0 } else { 1.0 };
+    let center = mesh.push_vertex(Point3::new(0.0, y, 0.0), normal, Vector2::new(0.5, 0.5), Vector3::unit_x());
+
+    let first = mesh.vertex_count() as u32;
+    for j in 0..=sectors {
+        let theta = 2.0 * PI * j as Float / sectors as Float;
+        let (x, z) = (theta.sin(), theta.cos());
+        mesh.push_vertex(Point3::new(x * radius, y, z * radius), normal,
+                         Vector2::new(0.5 + 0.5 * x, 0.5 + 0.5 * v_sign * z), Vector3::unit_x());
+    }
+    for j in 0..sectors {
+        if up {
+            mesh.push_triangle(center, first + j, first + j + 1);
+        } else {
+            mesh.push_triangle(center, first + j + 1, first + j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_well_formed(mesh: &MeshData) {
+        assert_eq!(mesh.normals.len(), mesh.vertex_count());
+        assert_eq!(mesh.uvs.len(), mesh.vertex_count());
+        assert_eq!(mesh.tangents.len(), mesh.vertex_count());
+        assert_eq!(mesh.indices.len() % 3, 0);
+        assert!(mesh.indices.iter().all(|&i| (i as usize) < mesh.vertex_count()));
+
+        for (normal, tangent) in mesh.normals.iter().zip(mesh.tangents.iter()) {
+            assert!((normal.magnitude() - 1.0).abs() < 1e-4);
+            assert!((tangent.magnitude() - 1.0).abs() < 1e-4);
+        }
+
+        // counter-clockwise winding: the face normal agrees with the vertex normals
+        for [a, b, c] in mesh.triangles() {
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            let face = (mesh.positions[b] - mesh.positions[a]).cross(mesh.positions[c] - mesh.positions[a]);
+            let vertex_normals = mesh.normals[a] + mesh.normals[b] + mesh.normals[c];
+            assert!(face.dot(vertex_normals) > 0.0, "triangle {} {} {} is wound clockwise", a, b, c);
+        }
+    }
+
+    #[test]
+    fn primitives_are_well_formed() {
+        assert_well_formed(&cube(2.0));
+        assert_well_formed(&grid(4.0, 2.0, 4, 2));
+        assert_well_formed(&uv_sphere(1.0, 16, 8));
+        assert_well_formed(&icosphere(1.0, 2));
+        assert_well_formed(&cylinder(1.0, 2.0, 12));
+        assert_well_formed(&cone(1.0, 2.0, 12));
+        assert_well_formed(&capsule(0.5, 1.0, 12, 4));
+        assert_well_formed(&torus(1.0, 0.25, 16, 8));
+    }
+
+    #[test]
+    fn primitive_sizes() {
+        let cube = cube(2.0);
+        assert_eq!(cube.vertex_count(), 24);
+        assert_eq!(cube.triangle_count(), 12);
+        assert_eq!(cube.aabb().max, Point3::new(1.0, 1.0, 1.0));
+
+        assert_eq!(grid(1.0, 1.0, 3, 2).triangle_count(), 12);
+        assert_eq!(icosphere(1.0, 1).triangle_count(), 80);
+
+        let capsule = capsule(0.5, 1.0, 8, 2);
+        let aabb = capsule.aabb();
+        assert!((aabb.max.y - 1.0).abs() < 1e-5 && (aabb.min.y + 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,454 @@
+//! Mesh decimation with quadric error metrics (Garland and Heckbert). Edges are collapsed
+//! onto one of their existing vertices, so normals, UVs and tangents are kept as authored
+//! instead of interpolated. Vertices sharing a position collapse together; vertices on UV
+//! seams and open borders only slide along them, stay put where seams meet, and border edges
+//! add perpendicular planes to the quadrics so outlines keep their shape.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use cgmath::prelude::*;
+
+use lang::{Float, PI, Point3, Vector3};
+use super::MeshData;
+use super::lod::LodGroup;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SimplifyOptions {
+    /// Stops before a collapse moving the surface further than this, relative to the mesh radius
+    pub max_error: Float,
+    /// Largest rotation, in radians, a collapse may give any remaining triangle
+    pub max_normal_change: Float,
+}
+
+impl Default for SimplifyOptions {
+    fn default() -> SimplifyOptions {
+        SimplifyOptions { max_error: 0.05, max_normal_change: PI / 4.0 }
+    }
+}
+
+/// Symmetric 4x4 matrix summing squared distances to planes
+#[derive(Copy, Clone, Default, Debug)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: Vector3, point: Point3, weight: f64) -> Quadric {
+        let (a, b, c) = (f64::from(normal.x), f64::from(normal.y), f64::from(normal.z));
+        let d = -(a * f64::from(point.x) + b * f64::from(point.y) + c * f64::from(point.z));
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|q| q * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.0.iter_mut().zip(other.0.iter()) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, p: Point3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (f64::from(p.x), f64::from(p.y), f64::from(p.z));
+        let value = q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9];
+        value.max(0.0)
+    }
+}
+
+/// Candidate collapse of the position `from` onto `to`, smallest cost first in the heap
+#[derive(Copy, Clone, Debug)]
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+struct Simplifier<'a> {
+    mesh: &'a MeshData,
+    /// position group of every vertex
+    group_of: Vec<usize>,
+    positions: Vec<Point3>,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    alive: Vec<bool>,
+    /// triangles that reference each group, possibly stale
+    incident: Vec<Vec<usize>>,
+    triangles: Vec<[u32; 3]>,
+    removed: Vec<bool>,
+    min_normal_dot: Float,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(mesh: &'a MeshData, options: &SimplifyOptions) -> Simplifier<'a> {
+        let mut keys = HashMap::new();
+        let mut positions = Vec::new();
+        let group_of: Vec<usize> = mesh.positions.iter()
+            .map(|p| {
+                let key = [f64::from(p.x).to_bits(), f64::from(p.y).to_bits(), f64::from(p.z).to_bits()];
+                *keys.entry(key).or_insert_with(|| {
+                    positions.push(*p);
+                    positions.len() - 1
+                })
+            })
+            .collect();
+
+        let count = positions.len();
+        let triangles: Vec<[u32; 3]> = mesh.triangles().collect();
+        let mut simplifier = Simplifier {
+            mesh,
+            group_of,
+            positions,
+            quadrics: vec![Quadric::default(); count],
+            versions: vec![0; count],
+            alive: vec![true; count],
+            incident: vec![Vec::new(); count],
+            removed: vec![false; triangles.len()],
+            triangles,
+            min_normal_dot: options.max_normal_change.cos(),
+        };
+
+        let mut edge_faces: HashMap<(usize, usize), usize> = HashMap::new();
+        for (t, triangle) in simplifier.triangles.iter().enumerate() {
+            let groups = triangle.map(|v| simplifier.group_of[v as usize]);
+            let face = simplifier.face(&groups);
+            let area = face.magnitude();
+            if area > 0.0 {
+                let plane = Quadric::plane(face / area, simplifier.positions[groups[0]], f64::from(area));
+                for &g in groups.iter() {
+                    simplifier.quadrics[g].add(&plane);
+                }
+            }
+            for i in 0..3 {
+                simplifier.incident[groups[i]].push(t);
+                let (a, b) = (groups[i], groups[(i + 1) % 3]);
+                *edge_faces.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+
+        // planes through open border edges, perpendicular to their face, keep the outline in place
+        for triangle in simplifier.triangles.iter() {
+            let groups = triangle.map(|v| simplifier.group_of[v as usize]);
+            for i in 0..3 {
+                let (a, b) = (groups[i], groups[(i + 1) % 3]);
+                if edge_faces[&(a.min(b), a.max(b))] != 1 {
+                    continue;
+                }
+                let edge = simplifier.positions[b] - simplifier.positions[a];
+                let normal = edge.cross(simplifier.face(&groups));
+                if normal.magnitude2() > 0.0 {
+                    let plane = Quadric::plane(normal.normalize(), simplifier.positions[a], f64::from(edge.magnitude2()) * 10.0);
+                    simplifier.quadrics[a].add(&plane);
+                    simplifier.quadrics[b].add(&plane);
+                }
+            }
+        }
+        simplifier
+    }
+
+    /// Unnormalized face normal, twice the area long
+    fn face(&self, groups: &[usize; 3]) -> Vector3 {
+        let (a, b, c) = (self.positions[groups[0]], self.positions[groups[1]], self.positions[groups[2]]);
+        (b - a).cross(c - a)
+    }
+
+    fn groups(&self, triangle: usize) -> [usize; 3] {
+        self.triangles[triangle].map(|v| self.group_of[v as usize])
+    }
+
+    fn live_triangles(&self, group: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut seen = Vec::new();
+        self.incident[group].iter().cloned()
+            .filter(move |&t| {
+                let fresh = !seen.contains(&t);
+                seen.push(t);
+                fresh
+            })
+            .filter(move |&t| !self.removed[t] && self.groups(t).contains(&group))
+    }
+
+    fn neighbours(&self, group: usize) -> Vec<usize> {
+        let mut neighbours = Vec::new();
+        for t in self.live_triangles(group) {
+            for g in self.groups(t).iter().cloned() {
+                if g != group && !neighbours.contains(&g) {
+                    neighbours.push(g);
+                }
+            }
+        }
+        neighbours
+    }
+
+    /// Edges of `group` on a UV seam or an open border: used by one triangle, or by two whose
+    /// corners are different vertices
+    fn special_edges(&self, group: usize) -> Vec<usize> {
+        let mut special = Vec::new();
+        for other in self.neighbours(group) {
+            let mut wedges: Vec<(u32, u32)> = Vec::new();
+            for t in self.live_triangles(group) {
+                let groups = self.groups(t);
+                if groups.contains(&other) {
+                    let corner = |g: usize| self.triangles[t][groups.iter().position(|&x| x == g).unwrap()];
+                    wedges.push((corner(group), corner(other)));
+                }
+            }
+            if wedges.len() != 2 || wedges[0] != wedges[1] {
+                special.push(other);
+            }
+        }
+        special
+    }
+
+    fn is_special(&self, group: usize) -> bool {
+        let mut wedge = None;
+        let multiple_wedges = self.live_triangles(group).any(|t| {
+            let groups = self.groups(t);
+            let corner = self.triangles[t][groups.iter().position(|&g| g == group).unwrap()];
+            *wedge.get_or_insert(corner) != corner
+        });
+        multiple_wedges || !self.special_edges(group).is_empty()
+    }
+
+    /// Vertex of `to` each vertex of `from` becomes, `None` when the collapse would tear a
+    /// seam, lose a seam corner or fold the surface
+    fn plan(&self, from: usize, to: usize) -> Option<Vec<(u32, u32)>> {
+        if self.is_special(from) {
+            let special = self.special_edges(from);
+            if special.len() != 2 || !special.contains(&to) {
+                return None;
+            }
+        }
+
+        let mut wedges: Vec<(u32, u32)> = Vec::new();
+        for t in self.live_triangles(from) {
+            let groups = self.groups(t);
+            if let Some(at) = groups.iter().position(|&g| g == to) {
+                let from_vertex = self.triangles[t][groups.iter().position(|&g| g == from).unwrap()];
+                let to_vertex = self.triangles[t][at];
+                match wedges.iter().find(|w| w.0 == from_vertex) {
+                    Some(w) if w.1 != to_vertex => return None,
+                    Some(_) => {}
+                    None => wedges.push((from_vertex, to_vertex)),
+                }
+            }
+        }
+        if wedges.is_empty() {
+            return None;
+        }
+
+        for t in self.live_triangles(from) {
+            let groups = self.groups(t);
+            if groups.contains(&to) {
+                continue;
+            }
+            let from_vertex = self.triangles[t][groups.iter().position(|&g| g == from).unwrap()];
+            if !wedges.iter().any(|w| w.0 == from_vertex) {
+                return None;
+            }
+            let before = self.face(&groups);
+            let after = self.face(&groups.map(|g| if g == from { to } else { g }));
+            if after.magnitude2() <= 0.0 || before.magnitude2() <= 0.0 {
+                return None;
+            }
+            if before.normalize().dot(after.normalize()) < self.min_normal_dot {
+                return None;
+            }
+        }
+        Some(wedges)
+    }
+
+    fn collapse(&mut self, from: usize, to: usize, wedges: &[(u32, u32)]) -> usize {
+        let triangles: Vec<usize> = self.live_triangles(from).collect();
+        let mut removed = 0;
+        for t in triangles {
+            if self.groups(t).contains(&to) {
+                self.removed[t] = true;
+                removed += 1;
+                continue;
+            }
+            for corner in self.triangles[t].iter_mut() {
+                if let Some(&(_, to_vertex)) = wedges.iter().find(|w| w.0 == *corner) {
+                    *corner = to_vertex;
+                }
+            }
+            self.incident[to].push(t);
+        }
+        let quadric = self.quadrics[from];
+        self.quadrics[to].add(&quadric);
+        self.alive[from] = false;
+        self.versions[to] += 1;
+        removed
+    }
+
+    fn candidate(&self, from: usize, to: usize) -> Collapse {
+        let mut quadric = self.quadrics[from];
+        quadric.add(&self.quadrics[to]);
+        Collapse {
+            cost: quadric.error(self.positions[to]),
+            from,
+            to,
+            from_version: self.versions[from],
+            to_version: self.versions[to],
+        }
+    }
+
+    fn push_around(&self, heap: &mut BinaryHeap<Collapse>, group: usize) {
+        for other in self.neighbours(group) {
+            heap.push(self.candidate(group, other));
+            heap.push(self.candidate(other, group));
+        }
+    }
+
+    fn run(&mut self, target_triangles: usize, max_error: f64) {
+        let mut heap = BinaryHeap::new();
+        for group in 0..self.positions.len() {
+            for other in self.neighbours(group) {
+                heap.push(self.candidate(group, other));
+            }
+        }
+
+        let mut remaining = self.triangles.len();
+        while remaining > target_triangles {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            if !self.alive[collapse.from] || !self.alive[collapse.to]
+                || self.versions[collapse.from] != collapse.from_version
+                || self.versions[collapse.to] != collapse.to_version {
+                continue;
+            }
+            if collapse.cost > max_error {
+                break;
+            }
+            if let Some(wedges) = self.plan(collapse.from, collapse.to) {
+                remaining -= self.collapse(collapse.from, collapse.to, &wedges);
+                self.push_around(&mut heap, collapse.to);
+            }
+        }
+    }
+
+    /// Remaining triangles with the unused vertices dropped, keeping the vertex order
+    fn finish(&self) -> MeshData {
+        let mut used = vec![false; self.mesh.vertex_count()];
+        let triangles: Vec<[u32; 3]> = (0..self.triangles.len())
+            .filter(|&t| !self.removed[t])
+            .map(|t| self.triangles[t])
+            .collect();
+        for triangle in triangles.iter() {
+            for &v in triangle.iter() {
+                used[v as usize] = true;
+            }
+        }
+
+        let mut remap = vec![0; used.len()];
+        let mut out = MeshData::new();
+        let source = self.mesh;
+        for (v, _) in used.iter().enumerate().filter(|&(_, &used)| used) {
+            remap[v] = out.positions.len() as u32;
+            out.positions.push(source.positions[v]);
+            for (into, from) in [(&mut out.normals, &source.normals), (&mut out.tangents, &source.tangents),
+                                 (&mut out.bitangents, &source.bitangents)] {
+                if let Some(&value) = from.get(v) {
+                    into.push(value);
+                }
+            }
+            if let Some(&uv) = source.uvs.get(v) {
+                out.uvs.push(uv);
+            }
+        }
+        out.indices = triangles.iter().flat_map(|t| t.iter().map(|&v| remap[v as usize])).collect();
+        out
+    }
+}
+
+/// `mesh` reduced to about `target_triangles` triangles, or fewer collapses when the next
+/// one would exceed `options.max_error`
+pub fn simplify(mesh: &MeshData, target_triangles: usize, options: &SimplifyOptions) -> MeshData {
+    if mesh.triangle_count() <= target_triangles {
+        return mesh.clone();
+    }
+    let radius = f64::from(mesh.bounding_sphere().radius);
+    let max_error = f64::from(options.max_error) * radius;
+
+    let mut simplifier = Simplifier::new(mesh, options);
+    simplifier.run(target_triangles, max_error * max_error);
+    simplifier.finish()
+}
+
+/// LOD group of `mesh` with one level per `(triangle_ratio, max_distance)`, each simplified
+/// from the previous one; a ratio of 1 keeps the mesh as is
+pub fn generate_lods(mesh: &MeshData, levels: &[(Float, Float)], options: &SimplifyOptions) -> LodGroup<MeshData> {
+    let original = mesh.triangle_count() as Float;
+    let mut group = LodGroup::new();
+    let mut previous = mesh.clone();
+    for &(ratio, max_distance) in levels {
+        let target = (original * ratio).round() as usize;
+        previous = simplify(&previous, target, options);
+        group = group.level(previous.clone(), max_distance);
+    }
+    group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::primitives;
+
+    #[test]
+    fn reduces_sphere_and_keeps_shape() {
+        let sphere = primitives::icosphere(1.0, 3);
+        let options = SimplifyOptions { max_error: 1.0, ..SimplifyOptions::default() };
+        let simple = simplify(&sphere, sphere.triangle_count() / 4, &options);
+        assert!(simple.triangle_count() <= sphere.triangle_count() / 4 + 2);
+        assert!(simple.triangle_count() > 20);
+        assert_eq!(simple.normals.len(), simple.vertex_count());
+        for position in simple.positions.iter() {
+            assert!((position.to_vec().magnitude() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn keeps_flat_border_and_seams() {
+        let grid = primitives::grid(2.0, 2.0, 8, 8);
+        let simple = simplify(&grid, 2, &SimplifyOptions::default());
+        assert!(simple.triangle_count() < grid.triangle_count() / 4);
+        let bounds = simple.aabb();
+        assert_eq!((bounds.min.x, bounds.max.x, bounds.min.z, bounds.max.z), (-1.0, 1.0, -1.0, 1.0));
+
+        // every vertex of a single quad is a border corner
+        let quad = primitives::grid(1.0, 1.0, 1, 1);
+        assert_eq!(simplify(&quad, 0, &SimplifyOptions::default()).triangle_count(), 2);
+    }
+
+    #[test]
+    fn generates_levels() {
+        let sphere = primitives::icosphere(1.0, 3);
+        let options = SimplifyOptions { max_error: 1.0, ..SimplifyOptions::default() };
+        let group = generate_lods(&sphere, &[(1.0, 10.0), (0.5, 20.0), (0.1, 40.0)], &options);
+        let counts: Vec<usize> = group.levels.iter().map(|level| level.mesh.triangle_count()).collect();
+        assert_eq!(counts[0], sphere.triangle_count());
+        assert!(counts[1] < counts[0] && counts[2] < counts[1]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Per-vertex tangent frames from UV derivatives, in the spirit of MikkTSpace:
+//! triangle tangents are weighted by the corner angle, orthogonalized against
+//! the vertex normal, and the bitangent keeps the handedness of the UV mapping.
+
+use cgmath::prelude::*;
+
+use lang::{Float, Vector3};
+use super::MeshData;
+
+const EPSILON: Float = 1e-8;
+
+/// Returns `(tangents, bitangents)` for every vertex of the mesh,
+/// which needs positions, normals and UVs
+pub fn compute(mesh: &MeshData) -> (Vec<Vector3>, Vec<Vector3>) {
+    let count = mesh.vertex_count();
+    let zero = Vector3::zero();
+    let mut tangents = vec![zero; count];
+    let mut bitangents = vec![zero; count];
+
+    if mesh.uvs.len() == count && mesh.normals.len() == count {
+        for [a, b, c] in mesh.triangles() {
+            accumulate_triangle(mesh, [a as usize, b as usize, c as usize], &mut tangents, &mut bitangents);
+        }
+    }
+
+    for i in 0..count {
+        let normal = mesh.normals.get(i).cloned().unwrap_or_else(Vector3::unit_y);
+        let (tangent, bitangent) = orthogonalize(normal, tangents[i], bitangents[i]);
+        tangents[i] = tangent;
+        bitangents[i] = bitangent;
+    }
+
+    (tangents, bitangents)
+}
+
+fn accumulate_triangle(mesh: &MeshData, corners: [usize; 3],
+                       tangents: &mut [Vector3], bitangents: &mut [Vector3]) {
+    let [a, b, c] = corners;
+    let edge1 = mesh.positions[b] - mesh.positions[a];
+    let edge2 = mesh.positions[c] - mesh.positions[a];
+    let duv1 = mesh.uvs[b] - mesh.uvs[a];
+    let duv2 = mesh.uvs[c] - mesh.uvs[a];
+
+    let det = duv1.x * duv2.y - duv2.x * duv1.y;
+    if det.abs() < EPSILON {
+        // degenerate UVs, the triangle can't orient a tangent frame
+        return;
+    }
+    let r = 1.0 / det;
+    let tangent = (edge1 * duv2.y - edge2 * duv1.y) * r;
+    let bitangent = (edge2 * duv1.x - edge1 * duv2.x) * r;
+
+    for k in 0..3 {
+        let corner = corners[k];
+        let to_next = mesh.positions[corners[(k + 1) % 3]] - mesh.positions[corner];
+        let to_prev = mesh.positions[corners[(k + 2) % 3]] - mesh.positions[corner];
+        let weight = corner_angle(to_next, to_prev);
+        tangents[corner] += tangent * weight;
+        bitangents[corner] += bitangent * weight;
+    }
+}
+
+fn corner_angle(a: Vector3, b: Vector3) -> Float {
+    let lengths = a.magnitude() * b.magnitude();
+    if lengths < EPSILON {
+        return 0.0;
+    }
+    (a.dot(b) / lengths).clamp(-1.0, 1.0).acos()
+}
+
+/// Gram-Schmidt against the normal, and the bitangent rebuilt from `normal x tangent`
+/// with the sign of the accumulated one
+fn orthogonalize(normal: Vector3, tangent: Vector3, bitangent: Vector3) -> (Vector3, Vector3) {
+    let mut t = tangent - normal * normal.dot(tangent);
+    if t.magnitude2() < EPSILON {
+        t = any_perpendicular(normal);
+    }
+    let t = t.normalize();
+
+    let handedness = if normal.cross(t).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+    (t, normal.cross(t) * handedness)
+}
+
+fn any_perpendicular(normal: Vector3) -> Vector3 {
+    let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    axis - normal * normal.dot(axis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::Vector2;
+    use mesh::primitives;
+
+    fn assert_close(a: &[Vector3], b: &[Vector3]) {
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn matches_analytic_tangents() {
+        for mesh in [primitives::cube(1.0), primitives::grid(2.0, 1.0, 3, 3)].iter() {
+            let (tangents, bitangents) = compute(mesh);
+            assert_close(&tangents, &mesh.tangents);
+            assert_close(&bitangents, &mesh.bitangents);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_bitangent() {
+        let mut mesh = primitives::plane(1.0, 1.0);
+        for uv in mesh.uvs.iter_mut() {
+            *uv = Vector2::new(1.0 - uv.x, uv.y);
+        }
+        mesh.compute_tangents();
+        for ((normal, tangent), bitangent) in mesh.normals.iter().zip(&mesh.tangents).zip(&mesh.bitangents) {
+            assert!(normal.cross(*tangent).dot(*bitangent) < 0.0);
+            assert!(tangent.dot(Vector3::unit_x()) < -0.99);
+        }
+    }
+}
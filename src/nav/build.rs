@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Matrix4};
+use bounds::Aabb;
+use mesh::MeshData;
+use super::{NavConfig, NavMesh, NavPolygon, Portal};
+
+/// Solid vertical interval of a heightfield column, in cell heights above the bounds' bottom
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Span {
+    min: i32,
+    max: i32,
+    /// the top is a surface an agent can stand on
+    walkable: bool,
+}
+
+/// Top of a walkable span with enough clearance above it
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Cell {
+    x: i32,
+    z: i32,
+    floor: i32,
+}
+
+const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+/// Collects the walkable geometry of a level and builds its `NavMesh`: the triangles are
+/// voxelized into a heightfield, the tops of walkable spans with room for an agent become
+/// cells, the cells are eroded by the agent radius and merged into rectangular polygons.
+pub struct NavMeshBuilder {
+    pub config: NavConfig,
+    triangles: Vec<[Point3; 3]>,
+}
+
+impl NavMeshBuilder {
+    pub fn new(config: NavConfig) -> NavMeshBuilder {
+        NavMeshBuilder { config, triangles: Vec::new() }
+    }
+
+    /// Adds the triangles of `mesh` placed in the world by `transform`
+    pub fn add_mesh(mut self, mesh: &MeshData, transform: &Matrix4) -> NavMeshBuilder {
+        let positions: Vec<Point3> = mesh.positions.iter().map(|&p| transform.transform_point(p)).collect();
+        for [a, b, c] in mesh.triangles() {
+            self.triangles.push([positions[a as usize], positions[b as usize], positions[c as usize]]);
+        }
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn build(&self) -> NavMesh {
+        let config = self.config;
+        let bounds = Aabb::from_points(self.triangles.iter().flat_map(|triangle| triangle.iter()));
+        if self.triangles.is_empty() {
+            return NavMesh::new(config, Vec::new());
+        }
+        let columns = self.rasterize(&bounds);
+        let mut cells = walkable_cells(&columns, &config);
+        let climb = (config.max_climb / config.cell_height).floor() as i32;
+        let erosion = (config.agent_radius / config.cell_size).ceil() as i32;
+        for _ in 0..erosion {
+            cells = erode(&cells, climb);
+        }
+        NavMesh::new(config, polygonize(&cells, climb, &bounds, &config))
+    }
+
+    fn rasterize(&self, bounds: &Aabb) -> HashMap<(i32, i32), Vec<Span>> {
+        let (cs, ch) = (self.config.cell_size, self.config.cell_height);
+        let min_normal_y = self.config.max_slope.to_radians().cos();
+        let mut columns: HashMap<(i32, i32), Vec<Span>> = HashMap::new();
+        for triangle in &self.triangles {
+            let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+            if normal.magnitude2() == 0.0 {
+                continue;
+            }
+            let walkable = normal.normalize().y >= min_normal_y;
+            let local: Vec<Point3> = triangle.iter().map(|p| Point3::from_vec(p - bounds.min)).collect();
+            let (low, high) = local.iter().fold((local[0], local[0]), |(low, high), p| {
+                (Point3::new(low.x.min(p.x), 0.0, low.z.min(p.z)), Point3::new(high.x.max(p.x), 0.0, high.z.max(p.z)))
+            });
+            for z in (low.z / cs).floor() as i32..=(high.z / cs).floor() as i32 {
+                let row = clip(clip(local.clone(), 2, z as Float * cs, true), 2, (z + 1) as Float * cs, false);
+                if row.is_empty() {
+                    continue;
+                }
+                for x in (low.x / cs).floor() as i32..=(high.x / cs).floor() as i32 {
+                    let cell = clip(clip(row.clone(), 0, x as Float * cs, true), 0, (x + 1) as Float * cs, false);
+                    if cell.is_empty() {
+                        continue;
+                    }
+                    let (bottom, top) = cell.iter().fold((Float::MAX, Float::MIN), |(b, t), p| (b.min(p.y), t.max(p.y)));
+                    let min = (bottom / ch).floor() as i32;
+                    let max = ((top / ch).ceil() as i32).max(min + 1);
+                    add_span(columns.entry((x, z)).or_default(), Span { min, max, walkable });
+                }
+            }
+        }
+        columns
+    }
+}
+
+/// Part of the polygon on one side of the plane `axis = value`
+fn clip(polygon: Vec<Point3>, axis: usize, value: Float, keep_above: bool) -> Vec<Point3> {
+    let side = |p: &Point3| if keep_above { p[axis] - value } else { value - p[axis] };
+    let mut result = Vec::with_capacity(polygon.len() + 1);
+    for i in 0..polygon.len() {
+        let (a, b) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+        let (da, db) = (side(&a), side(&b));
+        if da >= 0.0 {
+            result.push(a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            result.push(a + (b - a) * (da / (da - db)));
+        }
+    }
+    result
+}
+
+/// Inserts a span keeping the column sorted, merging it with the spans it overlaps. The top
+/// decides whether the merged span is walkable, on equal tops an unwalkable one wins so the
+/// bottom of an object resting on the floor hides the floor under it.
+fn add_span(column: &mut Vec<Span>, mut span: Span) {
+    let mut i = 0;
+    while i < column.len() {
+        let other = column[i];
+        if other.max < span.min || other.min > span.max {
+            i += 1;
+            continue;
+        }
+        if other.max > span.max {
+            span.walkable = other.walkable;
+        } else if other.max == span.max {
+            span.walkable &= other.walkable;
+        }
+        span.min = span.min.min(other.min);
+        span.max = span.max.max(other.max);
+        column.remove(i);
+    }
+    let index = column.iter().position(|other| other.min > span.min).unwrap_or(column.len());
+    column.insert(index, span);
+}
+
+fn walkable_cells(columns: &HashMap<(i32, i32), Vec<Span>>, config: &NavConfig) -> Vec<Cell> {
+    let clearance = (config.agent_height / config.cell_height).ceil() as i32;
+    let mut cells = Vec::new();
+    for (&(x, z), column) in columns {
+        for (i, span) in column.iter().enumerate() {
+            let ceiling = column.get(i + 1).map_or(i32::MAX, |above| above.min);
+            if span.walkable && ceiling - span.max >= clearance {
+                cells.push(Cell { x, z, floor: span.max });
+            }
+        }
+    }
+    cells.sort_by_key(|cell| (cell.z, cell.x, cell.floor));
+    cells
+}
+
+/// Cell indices of each column
+fn column_index(cells: &[Cell]) -> HashMap<(i32, i32), Vec<usize>> {
+    let mut index: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (i, cell) in cells.iter().enumerate() {
+        index.entry((cell.x, cell.z)).or_default().push(i);
+    }
+    index
+}
+
+/// The cell an agent steps to from `cell` in the direction, the closest floor within `climb`
+fn neighbor(cells: &[Cell], index: &HashMap<(i32, i32), Vec<usize>>, cell: usize, (dx, dz): (i32, i32), climb: i32) -> Option<usize> {
+    let from = cells[cell];
+    index.get(&(from.x + dx, from.z + dz))?.iter().cloned()
+        .filter(|&other| (cells[other].floor - from.floor).abs() <= climb)
+        .min_by_key(|&other| (cells[other].floor - from.floor).abs())
+}
+
+/// Removes the cells along the edges, where an agent would be closer than a cell to a wall
+/// or a drop
+fn erode(cells: &[Cell], climb: i32) -> Vec<Cell> {
+    let index = column_index(cells);
+    (0..cells.len())
+        .filter(|&i| DIRECTIONS.iter().all(|&direction| neighbor(cells, &index, i, direction, climb).is_some()))
+        .map(|i| cells[i])
+        .collect()
+}
+
+/// Greedily merges the cells into rectangles of connected cells within `climb` of the first,
+/// then links the rectangles through the cell edges they share
+fn polygonize(cells: &[Cell], climb: i32, bounds: &Aabb, config: &NavConfig) -> Vec<NavPolygon> {
+    let index = column_index(cells);
+    let mut owner: Vec<Option<usize>> = vec![None; cells.len()];
+    let mut rectangles: Vec<Vec<Vec<usize>>> = Vec::new();
+
+    for start in 0..cells.len() {
+        if owner[start].is_some() {
+            continue;
+        }
+        let id = rectangles.len();
+        let fits = |cell: Option<usize>, owner: &[Option<usize>]| {
+            cell.filter(|&c| owner[c].is_none() && (cells[c].floor - cells[start].floor).abs() <= climb)
+        };
+        let mut row = vec![start];
+        owner[start] = Some(id);
+        while let Some(next) = fits(neighbor(cells, &index, *row.last().unwrap(), (1, 0), climb), &owner) {
+            owner[next] = Some(id);
+            row.push(next);
+        }
+        let mut rows = vec![row];
+        loop {
+            let above: Vec<Option<usize>> = rows.last().unwrap().iter()
+                .map(|&cell| fits(neighbor(cells, &index, cell, (0, 1), climb), &owner))
+                .collect();
+            if above.iter().any(Option::is_none) {
+                break;
+            }
+            let above: Vec<usize> = above.into_iter().flatten().collect();
+            for &cell in &above {
+                owner[cell] = Some(id);
+            }
+            rows.push(above);
+        }
+        rectangles.push(rows);
+    }
+
+    let (cs, ch) = (config.cell_size, config.cell_height);
+    let corner = |cell: &Cell, dx: i32, dz: i32| {
+        Point3::new(bounds.min.x + (cell.x + dx) as Float * cs,
+                    bounds.min.y + cell.floor as Float * ch,
+                    bounds.min.z + (cell.z + dz) as Float * cs)
+    };
+    let mut polygons: Vec<NavPolygon> = rectangles.iter()
+        .map(|rows| {
+            let first = &rows[0];
+            let last = &rows[rows.len() - 1];
+            let vertices = [
+                corner(&cells[first[0]], 0, 0),
+                corner(&cells[*first.last().unwrap()], 1, 0),
+                corner(&cells[*last.last().unwrap()], 1, 1),
+                corner(&cells[last[0]], 0, 1),
+            ];
+            NavPolygon::new(vertices)
+        })
+        .collect();
+
+    // shared edges between cells of different rectangles, extended to cover the whole border
+    let mut portals: HashMap<(usize, usize), (Point3, Point3)> = HashMap::new();
+    for (i, cell) in cells.iter().enumerate() {
+        for &direction in &DIRECTIONS {
+            let other = match neighbor(cells, &index, i, direction, climb) {
+                Some(other) => other,
+                None => continue,
+            };
+            let (from, to) = (owner[i].unwrap(), owner[other].unwrap());
+            if from == to {
+                continue;
+            }
+            let (a, b) = match direction {
+                (1, 0) => (corner(cell, 1, 0), corner(cell, 1, 1)),
+                (-1, 0) => (corner(cell, 0, 0), corner(cell, 0, 1)),
+                (0, 1) => (corner(cell, 0, 1), corner(cell, 1, 1)),
+                _ => (corner(cell, 0, 0), corner(cell, 1, 0)),
+            };
+            let portal = portals.entry((from, to)).or_insert((a, b));
+            let axis = if direction.0 != 0 { 2 } else { 0 };
+            if a[axis] < portal.0[axis] {
+                portal.0 = a;
+            }
+            if b[axis] > portal.1[axis] {
+                portal.1 = b;
+            }
+        }
+    }
+    let mut links: Vec<((usize, usize), (Point3, Point3))> = portals.into_iter().collect();
+    links.sort_by_key(|&(pair, _)| pair);
+    for ((from, to), (a, b)) in links {
+        polygons[from].neighbors.push(Portal { polygon: to, a, b });
+    }
+    polygons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_merge_with_the_top_deciding() {
+        let mut column = Vec::new();
+        add_span(&mut column, Span { min: 0, max: 1, walkable: true });
+        add_span(&mut column, Span { min: 5, max: 6, walkable: true });
+        add_span(&mut column, Span { min: 0, max: 3, walkable: false });
+        assert_eq!(column, vec![Span { min: 0, max: 3, walkable: false }, Span { min: 5, max: 6, walkable: true }]);
+
+        let square = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, 0.0, 0.0), Point3::new(2.0, 0.0, 2.0)];
+        let clipped = clip(square, 0, 1.0, true);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|p| p.x >= 1.0));
+    }
+}
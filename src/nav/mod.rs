@@ -0,0 +1,173 @@
+//! Navigation meshes for AI agents: built from the level geometry by `NavMeshBuilder`, queried
+//! with `NavMesh::find_path`, which runs A* over the polygons and pulls the string through the
+//! portals between them.
+
+pub mod build;
+pub mod path;
+
+use cgmath::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, Point3};
+use lines::{LineBatch, LineStyle};
+
+pub use self::build::NavMeshBuilder;
+
+/// Size of the voxels and of the agents the mesh is built for, in world units and degrees
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NavConfig {
+    /// horizontal voxel size, smaller follows the geometry closer and builds slower
+    pub cell_size: Float,
+    pub cell_height: Float,
+    /// free space an agent needs above the floor
+    pub agent_height: Float,
+    /// how far walkable areas keep from walls and drops
+    pub agent_radius: Float,
+    /// highest step an agent walks up
+    pub max_climb: Float,
+    /// steepest walkable slope in degrees
+    pub max_slope: Float,
+}
+
+impl Default for NavConfig {
+    fn default() -> NavConfig {
+        NavConfig {
+            cell_size: 0.3,
+            cell_height: 0.2,
+            agent_height: 2.0,
+            agent_radius: 0.6,
+            max_climb: 0.9,
+            max_slope: 45.0,
+        }
+    }
+}
+
+/// Edge an agent crosses from one polygon into `polygon`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Portal {
+    pub polygon: usize,
+    pub a: Point3,
+    pub b: Point3,
+}
+
+/// Convex walkable area, an axis aligned rectangle seen from above whose corners follow
+/// the floor height
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPolygon {
+    /// counter-clockwise seen from above
+    pub vertices: [Point3; 4],
+    pub center: Point3,
+    pub neighbors: Vec<Portal>,
+}
+
+impl NavPolygon {
+    pub fn new(vertices: [Point3; 4]) -> NavPolygon {
+        NavPolygon { vertices, center: Point3::centroid(&vertices), neighbors: Vec::new() }
+    }
+
+    /// Whether `(x, z)` is inside the polygon seen from above
+    pub fn contains(&self, x: Float, z: Float) -> bool {
+        let (min, max) = (self.vertices[0], self.vertices[2]);
+        x >= min.x - 1e-4 && x <= max.x + 1e-4 && z >= min.z - 1e-4 && z <= max.z + 1e-4
+    }
+
+    /// Floor height at `(x, z)`, interpolated between the corners
+    pub fn height_at(&self, x: Float, z: Float) -> Float {
+        let [a, b, c, d] = self.vertices;
+        let u = ((x - a.x) / (b.x - a.x)).clamp(0.0, 1.0);
+        let v = ((z - a.z) / (d.z - a.z)).clamp(0.0, 1.0);
+        let near = a.y + (b.y - a.y) * u;
+        let far = d.y + (c.y - d.y) * u;
+        near + (far - near) * v
+    }
+}
+
+/// Walkable polygons linked by portals, see `NavMeshBuilder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavMesh {
+    pub config: NavConfig,
+    pub polygons: Vec<NavPolygon>,
+}
+
+impl NavMesh {
+    pub fn new(config: NavConfig, polygons: Vec<NavPolygon>) -> NavMesh {
+        NavMesh { config, polygons }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// Polygon under `point`, the one whose floor is closest when several levels overlap;
+    /// `None` when the point is off the mesh or an agent height away from any floor
+    pub fn find_polygon(&self, point: Point3) -> Option<usize> {
+        self.polygons.iter().enumerate()
+            .filter(|&(_, polygon)| polygon.contains(point.x, point.z))
+            .map(|(i, polygon)| (i, (polygon.height_at(point.x, point.z) - point.y).abs()))
+            .filter(|&(_, distance)| distance <= self.config.agent_height)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(i, _)| i)
+    }
+
+    /// Shortest corridor from `start` to `end` and the straightened path through it, corners
+    /// only; `None` when either point is off the mesh or they aren't connected
+    pub fn find_path(&self, start: Point3, end: Point3) -> Option<Vec<Point3>> {
+        let from = self.find_polygon(start)?;
+        let to = self.find_polygon(end)?;
+        let corridor = path::find_corridor(self, from, to, start, end)?;
+        Some(path::string_pull(self, &corridor, start, end))
+    }
+
+    /// Outlines of the polygons, and the portals between them with `portal_style`
+    pub fn debug_draw(&self, lines: &mut LineBatch, style: &LineStyle, portal_style: &LineStyle) {
+        for (i, polygon) in self.polygons.iter().enumerate() {
+            let v = polygon.vertices;
+            lines.world_polyline(&[v[0], v[1], v[2], v[3], v[0]], style);
+            // each portal is stored on both sides, draw it once
+            for portal in polygon.neighbors.iter().filter(|portal| portal.polygon > i) {
+                lines.world_polyline(&[portal.a, portal.b], portal_style);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::{Matrix4, Vector3};
+    use mesh::primitives;
+
+    #[test]
+    fn builds_a_floor_around_a_pillar_and_walks_around_it() {
+        let floor = primitives::plane(10.0, 10.0);
+        let pillar = primitives::cube(2.0);
+        let navmesh = NavMeshBuilder::new(NavConfig::default())
+            .add_mesh(&floor, &Matrix4::identity())
+            .add_mesh(&pillar, &(Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0)) * Matrix4::from_nonuniform_scale(1.0, 2.0, 1.0)))
+            .build();
+        assert!(!navmesh.is_empty());
+
+        // the floor under the pillar and within the agent radius of it isn't walkable
+        assert!(navmesh.find_polygon(Point3::new(0.0, 0.0, 0.0)).is_none());
+        assert!(navmesh.find_polygon(Point3::new(1.2, 0.0, 0.0)).is_none());
+        assert!(navmesh.find_polygon(Point3::new(-4.8, 0.0, 0.0)).is_none());
+
+        let start = Point3::new(-3.5, 0.0, 0.2);
+        let end = Point3::new(3.5, 0.0, 0.2);
+        let path = navmesh.find_path(start, end).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&end));
+        assert!(path.len() >= 3, "{:?}", path);
+        let length: Float = path.windows(2).map(|pair| pair[0].distance(pair[1])).sum();
+        assert!(length > 7.0 && length < 9.5, "{}", length);
+        // every segment stays clear of the pillar
+        for pair in path.windows(2) {
+            for step in 0..=20 {
+                let p = pair[0] + (pair[1] - pair[0]) * (step as Float / 20.0);
+                assert!(p.x.abs() > 1.0 || p.z.abs() > 1.0, "{:?} in {:?}", p, path);
+                assert!(navmesh.find_polygon(p).is_some(), "{:?} off the mesh", p);
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use cgmath::prelude::*;
+
+use lang::{Float, Point3};
+use super::NavMesh;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Open {
+    estimate: Float,
+    polygon: usize,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    /// lowest estimate first out of the max-heap
+    fn cmp(&self, other: &Open) -> Ordering {
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+            .then(other.polygon.cmp(&self.polygon))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Polygons from `from` to `to` of the shortest route over the portal midpoints, A* with the
+/// straight distance to `end` as the heuristic
+pub fn find_corridor(mesh: &NavMesh, from: usize, to: usize, start: Point3, end: Point3) -> Option<Vec<usize>> {
+    let count = mesh.polygons.len();
+    let mut cost = vec![Float::INFINITY; count];
+    // where each polygon was entered, for the cost of the next step
+    let mut entry = vec![start; count];
+    let mut parent: Vec<Option<usize>> = vec![None; count];
+    let mut open = BinaryHeap::new();
+    cost[from] = 0.0;
+    open.push(Open { estimate: start.distance(end), polygon: from });
+
+    while let Some(Open { polygon, .. }) = open.pop() {
+        if polygon == to {
+            let mut corridor = vec![to];
+            while let Some(previous) = parent[*corridor.last().unwrap()] {
+                corridor.push(previous);
+            }
+            corridor.reverse();
+            return Some(corridor);
+        }
+        for portal in &mesh.polygons[polygon].neighbors {
+            let midpoint = portal.a.midpoint(portal.b);
+            let step = if portal.polygon == to {
+                entry[polygon].distance(midpoint) + midpoint.distance(end)
+            } else {
+                entry[polygon].distance(midpoint)
+            };
+            let reached = cost[polygon] + step;
+            if reached < cost[portal.polygon] {
+                cost[portal.polygon] = reached;
+                entry[portal.polygon] = midpoint;
+                parent[portal.polygon] = Some(polygon);
+                open.push(Open { estimate: reached + midpoint.distance(end), polygon: portal.polygon });
+            }
+        }
+    }
+    None
+}
+
+/// Twice the signed area of the triangle seen from above, positive when `c` is to the left
+/// of `a` to `b`
+fn turn(a: Point3, b: Point3, c: Point3) -> Float {
+    // x right and -z forward when looking down the Y axis
+    (b.x - a.x) * (a.z - c.z) - (a.z - b.z) * (c.x - a.x)
+}
+
+fn same(a: Point3, b: Point3) -> bool {
+    a.distance2(b) < 1e-8
+}
+
+/// Portal endpoints along the corridor as (left, right) seen when walking through them
+fn portals(mesh: &NavMesh, corridor: &[usize]) -> Vec<(Point3, Point3)> {
+    corridor.windows(2)
+        .filter_map(|pair| {
+            let (from, to) = (&mesh.polygons[pair[0]], pair[1]);
+            let portal = from.neighbors.iter().find(|portal| portal.polygon == to)?;
+            let center = from.center;
+            if turn(center, portal.b, portal.a) > 0.0 { Some((portal.a, portal.b)) } else { Some((portal.b, portal.a)) }
+        })
+        .collect()
+}
+
+/// Shortest path through the portals of `corridor` with the funnel algorithm, the corners it
+/// bends around and the two ends
+pub fn string_pull(mesh: &NavMesh, corridor: &[usize], start: Point3, end: Point3) -> Vec<Point3> {
+    let mut gates = vec![(start, start)];
+    gates.extend(portals(mesh, corridor));
+    gates.push((end, end));
+
+    let mut path = vec![start];
+    let (mut apex, mut left, mut right) = (start, start, start);
+    let (mut left_index, mut right_index) = (0, 0);
+    let mut i = 1;
+    while i < gates.len() {
+        let (next_left, next_right) = gates[i];
+
+        // narrow the right side while it stays right of the left one
+        if turn(apex, right, next_right) >= 0.0 {
+            if same(apex, right) || turn(apex, left, next_right) < 0.0 {
+                right = next_right;
+                right_index = i;
+            } else {
+                // the right side crossed the left one, its corner is on the path
+                path.push(left);
+                apex = left;
+                right = apex;
+                right_index = left_index;
+                i = left_index + 1;
+                continue;
+            }
+        }
+
+        if turn(apex, left, next_left) <= 0.0 {
+            if same(apex, left) || turn(apex, right, next_left) > 0.0 {
+                left = next_left;
+                left_index = i;
+            } else {
+                path.push(right);
+                apex = right;
+                left = apex;
+                left_index = right_index;
+                i = right_index + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    if !same(*path.last().unwrap(), end) {
+        path.push(end);
+    }
+    path.dedup_by(|a, b| same(*a, *b));
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nav::{NavConfig, NavPolygon, Portal};
+
+    fn square(x: Float, z: Float) -> NavPolygon {
+        NavPolygon::new([
+            Point3::new(x, 0.0, z), Point3::new(x + 1.0, 0.0, z),
+            Point3::new(x + 1.0, 0.0, z + 1.0), Point3::new(x, 0.0, z + 1.0),
+        ])
+    }
+
+    fn link(polygons: &mut [NavPolygon], a: usize, b: usize, p: Point3, q: Point3) {
+        polygons[a].neighbors.push(Portal { polygon: b, a: p, b: q });
+        polygons[b].neighbors.push(Portal { polygon: a, a: p, b: q });
+    }
+
+    #[test]
+    fn pulls_the_string_around_a_corner() {
+        // an L: two squares along x, then one along z
+        let mut polygons = vec![square(0.0, 0.0), square(1.0, 0.0), square(1.0, 1.0)];
+        link(&mut polygons, 0, 1, Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 0.0, 1.0));
+        link(&mut polygons, 1, 2, Point3::new(1.0, 0.0, 1.0), Point3::new(2.0, 0.0, 1.0));
+        let mesh = NavMesh::new(NavConfig::default(), polygons);
+
+        let (start, end) = (Point3::new(0.2, 0.0, 0.5), Point3::new(1.5, 0.0, 1.8));
+        let corridor = find_corridor(&mesh, 0, 2, start, end).unwrap();
+        assert_eq!(corridor, vec![0, 1, 2]);
+        let path = string_pull(&mesh, &corridor, start, end);
+        assert_eq!(path, vec![start, Point3::new(1.0, 0.0, 1.0), end]);
+
+        // straight through when nothing is in the way
+        let (start, end) = (Point3::new(1.1, 0.0, 0.5), Point3::new(1.9, 0.0, 1.5));
+        let corridor = find_corridor(&mesh, 1, 2, start, end).unwrap();
+        assert_eq!(string_pull(&mesh, &corridor, start, end), vec![start, end]);
+    }
+}
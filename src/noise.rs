@@ -0,0 +1,361 @@
+//! Procedural noise for terrain, clouds and materials, on the CPU and in GLSL. `NOISE_GLSL`
+//! implements the same functions with the same integer hash, so a heightmap generated here
+//! lines up with the detail a shader adds on the GPU.
+
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, Point3};
+pub use lang::rng::{hash, hash2, hash3, perlin2, perlin3, simplex2, simplex3};
+
+/// GLSL versions of the noise functions, matching the CPU ones for the same seed:
+///
+/// - `perlinNoise2(vec2, uint seed)`, `perlinNoise3(vec3, uint seed)`: `perlin2`, `perlin3`
+/// - `simplexNoise2(vec2, uint seed)`, `simplexNoise3(vec3, uint seed)`: `simplex2`, `simplex3`
+/// - `worleyNoise2(vec2, uint seed)`, `worleyNoise3(vec3, uint seed)`: the `vec2(f1, f2)`
+///   of `worley2`, `worley3`
+/// - `fbmNoise2(vec2, uint seed, int octaves, float lacunarity, float gain)` and `fbmNoise3`,
+///   `ridgedNoise3`: `Fractal` over the simplex noise, with the frequency applied by the caller
+pub const NOISE_GLSL: &str = r#"
+uint noiseHash(uint value)
+{
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+uint noiseHash2(ivec2 c, uint seed)
+{
+    return noiseHash(uint(c.x) ^ noiseHash(uint(c.y) ^ noiseHash(seed)));
+}
+
+uint noiseHash3(ivec3 c, uint seed)
+{
+    return noiseHash(uint(c.x) ^ noiseHash(uint(c.y) ^ noiseHash(uint(c.z) ^ noiseHash(seed))));
+}
+
+float noiseFade(float t)
+{
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+float noiseGradient2(uint h, vec2 d)
+{
+    switch (h & 7u) {
+        case 0u: return d.x + d.y;
+        case 1u: return d.x - d.y;
+        case 2u: return -d.x + d.y;
+        case 3u: return -d.x - d.y;
+        case 4u: return d.x;
+        case 5u: return -d.x;
+        case 6u: return d.y;
+        default: return -d.y;
+    }
+}
+
+float noiseGradient3(uint hash, vec3 d)
+{
+    uint h = hash & 15u;
+    float u = h < 8u ? d.x : d.y;
+    float v = h < 4u ? d.y : (h == 12u || h == 14u ? d.x : d.z);
+    return ((h & 1u) == 0u ? u : -u) + ((h & 2u) == 0u ? v : -v);
+}
+
+float perlinNoise2(vec2 p, uint seed)
+{
+    vec2 i = floor(p);
+    ivec2 c = ivec2(i);
+    vec2 f = p - i;
+    vec2 u = vec2(noiseFade(f.x), noiseFade(f.y));
+    float a = noiseGradient2(noiseHash2(c, seed), f);
+    float b = noiseGradient2(noiseHash2(c + ivec2(1, 0), seed), f - vec2(1.0, 0.0));
+    float d = noiseGradient2(noiseHash2(c + ivec2(0, 1), seed), f - vec2(0.0, 1.0));
+    float e = noiseGradient2(noiseHash2(c + ivec2(1, 1), seed), f - vec2(1.0, 1.0));
+    return mix(mix(a, b, u.x), mix(d, e, u.x), u.y);
+}
+
+float perlinCorner3(ivec3 c, vec3 f, ivec3 o, uint seed)
+{
+    return noiseGradient3(noiseHash3(c + o, seed), f - vec3(o));
+}
+
+float perlinNoise3(vec3 p, uint seed)
+{
+    vec3 i = floor(p);
+    ivec3 c = ivec3(i);
+    vec3 f = p - i;
+    vec3 u = vec3(noiseFade(f.x), noiseFade(f.y), noiseFade(f.z));
+    float near = mix(mix(perlinCorner3(c, f, ivec3(0, 0, 0), seed), perlinCorner3(c, f, ivec3(1, 0, 0), seed), u.x),
+                     mix(perlinCorner3(c, f, ivec3(0, 1, 0), seed), perlinCorner3(c, f, ivec3(1, 1, 0), seed), u.x), u.y);
+    float far = mix(mix(perlinCorner3(c, f, ivec3(0, 0, 1), seed), perlinCorner3(c, f, ivec3(1, 0, 1), seed), u.x),
+                    mix(perlinCorner3(c, f, ivec3(0, 1, 1), seed), perlinCorner3(c, f, ivec3(1, 1, 1), seed), u.x), u.y);
+    return mix(near, far, u.z);
+}
+
+float simplexCorner2(vec2 d, uint h)
+{
+    float t = 0.5 - dot(d, d);
+    if (t <= 0.0) return 0.0;
+    t *= t;
+    return t * t * noiseGradient2(h, d);
+}
+
+float simplexNoise2(vec2 p, uint seed)
+{
+    const float skew = 0.36602540378;
+    const float unskew = 0.21132486540;
+    vec2 i = floor(p + (p.x + p.y) * skew);
+    vec2 x0 = p - (i - (i.x + i.y) * unskew);
+    ivec2 o = x0.x > x0.y ? ivec2(1, 0) : ivec2(0, 1);
+    ivec2 c = ivec2(i);
+    return 70.0 * (simplexCorner2(x0, noiseHash2(c, seed))
+        + simplexCorner2(x0 - vec2(o) + unskew, noiseHash2(c + o, seed))
+        + simplexCorner2(x0 - 1.0 + 2.0 * unskew, noiseHash2(c + ivec2(1), seed)));
+}
+
+float simplexCorner3(vec3 d, uint h)
+{
+    float t = 0.6 - dot(d, d);
+    if (t <= 0.0) return 0.0;
+    t *= t;
+    return t * t * noiseGradient3(h, d);
+}
+
+float simplexNoise3(vec3 p, uint seed)
+{
+    vec3 i = floor(p + (p.x + p.y + p.z) / 3.0);
+    vec3 x0 = p - (i - (i.x + i.y + i.z) / 6.0);
+    ivec3 o1, o2;
+    if (x0.x >= x0.y) {
+        if (x0.y >= x0.z) { o1 = ivec3(1, 0, 0); o2 = ivec3(1, 1, 0); }
+        else if (x0.x >= x0.z) { o1 = ivec3(1, 0, 0); o2 = ivec3(1, 0, 1); }
+        else { o1 = ivec3(0, 0, 1); o2 = ivec3(1, 0, 1); }
+    } else {
+        if (x0.y < x0.z) { o1 = ivec3(0, 0, 1); o2 = ivec3(0, 1, 1); }
+        else if (x0.x < x0.z) { o1 = ivec3(0, 1, 0); o2 = ivec3(0, 1, 1); }
+        else { o1 = ivec3(0, 1, 0); o2 = ivec3(1, 1, 0); }
+    }
+    ivec3 c = ivec3(i);
+    return 32.0 * (simplexCorner3(x0, noiseHash3(c, seed))
+        + simplexCorner3(x0 - vec3(o1) + 1.0 / 6.0, noiseHash3(c + o1, seed))
+        + simplexCorner3(x0 - vec3(o2) + 2.0 / 6.0, noiseHash3(c + o2, seed))
+        + simplexCorner3(x0 - 0.5, noiseHash3(c + ivec3(1), seed)));
+}
+
+vec2 worleyNoise2(vec2 p, uint seed)
+{
+    ivec2 c = ivec2(floor(p));
+    vec2 f = vec2(1e9);
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            ivec2 cell = c + ivec2(x, y);
+            uint h = noiseHash2(cell, seed);
+            vec2 feature = vec2(cell) + vec2(float(h & 0xffffu), float(h >> 16u)) / 65536.0;
+            float d = dot(p - feature, p - feature);
+            f = d < f.x ? vec2(d, f.x) : vec2(f.x, min(f.y, d));
+        }
+    }
+    return sqrt(f);
+}
+
+vec2 worleyNoise3(vec3 p, uint seed)
+{
+    ivec3 c = ivec3(floor(p));
+    vec2 f = vec2(1e9);
+    for (int z = -1; z <= 1; ++z) {
+        for (int y = -1; y <= 1; ++y) {
+            for (int x = -1; x <= 1; ++x) {
+                ivec3 cell = c + ivec3(x, y, z);
+                uint h = noiseHash3(cell, seed);
+                vec3 feature = vec3(cell) + vec3(float(h & 1023u), float((h >> 10u) & 1023u), float((h >> 20u) & 1023u)) / 1024.0;
+                float d = dot(p - feature, p - feature);
+                f = d < f.x ? vec2(d, f.x) : vec2(f.x, min(f.y, d));
+            }
+        }
+    }
+    return sqrt(f);
+}
+
+float fbmNoise2(vec2 p, uint seed, int octaves, float lacunarity, float gain)
+{
+    float sum = 0.0, amplitude = 1.0, total = 0.0;
+    for (int i = 0; i < octaves; ++i) {
+        sum += simplexNoise2(p, seed + uint(i)) * amplitude;
+        total += amplitude;
+        p *= lacunarity;
+        amplitude *= gain;
+    }
+    return sum / total;
+}
+
+float fbmNoise3(vec3 p, uint seed, int octaves, float lacunarity, float gain)
+{
+    float sum = 0.0, amplitude = 1.0, total = 0.0;
+    for (int i = 0; i < octaves; ++i) {
+        sum += simplexNoise3(p, seed + uint(i)) * amplitude;
+        total += amplitude;
+        p *= lacunarity;
+        amplitude *= gain;
+    }
+    return sum / total;
+}
+
+float ridgedNoise3(vec3 p, uint seed, int octaves, float lacunarity, float gain)
+{
+    float sum = 0.0, amplitude = 1.0, total = 0.0;
+    for (int i = 0; i < octaves; ++i) {
+        float ridge = 1.0 - abs(simplexNoise3(p, seed + uint(i)));
+        sum += ridge * ridge * amplitude;
+        total += amplitude;
+        p *= lacunarity;
+        amplitude *= gain;
+    }
+    return sum / total;
+}
+"#;
+
+/// Distances from a point to the closest and second closest feature points, one feature point
+/// jittered in each unit cell
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Worley {
+    pub f1: Float,
+    pub f2: Float,
+}
+
+impl Worley {
+    fn nearest(mut self, distance2: Float) -> Worley {
+        if distance2 < self.f1 {
+            self.f2 = self.f1;
+            self.f1 = distance2;
+        } else if distance2 < self.f2 {
+            self.f2 = distance2;
+        }
+        self
+    }
+
+    /// `f2 - f1`, bright along the cell borders, e.g. for cracks and scales
+    pub fn edges(&self) -> Float {
+        self.f2 - self.f1
+    }
+}
+
+/// Cellular noise in 2D
+pub fn worley2(x: Float, y: Float, seed: u32) -> Worley {
+    let (cx, cy) = (x.floor() as i32, y.floor() as i32);
+    let mut worley = Worley { f1: Float::MAX, f2: Float::MAX };
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let (ix, iy) = (cx + dx, cy + dy);
+            let h = hash2(ix, iy, seed);
+            let fx = ix as Float + (h & 0xffff) as Float / 65536.0;
+            let fy = iy as Float + (h >> 16) as Float / 65536.0;
+            worley = worley.nearest((x - fx) * (x - fx) + (y - fy) * (y - fy));
+        }
+    }
+    Worley { f1: worley.f1.sqrt(), f2: worley.f2.sqrt() }
+}
+
+/// Cellular noise in 3D
+pub fn worley3(point: Point3, seed: u32) -> Worley {
+    let cell = (point.x.floor() as i32, point.y.floor() as i32, point.z.floor() as i32);
+    let mut worley = Worley { f1: Float::MAX, f2: Float::MAX };
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (ix, iy, iz) = (cell.0 + dx, cell.1 + dy, cell.2 + dz);
+                let h = hash3(ix, iy, iz, seed);
+                let feature = Point3::new(
+                    ix as Float + (h & 1023) as Float / 1024.0,
+                    iy as Float + ((h >> 10) & 1023) as Float / 1024.0,
+                    iz as Float + ((h >> 20) & 1023) as Float / 1024.0,
+                );
+                let offset = point - feature;
+                worley = worley.nearest(offset.x * offset.x + offset.y * offset.y + offset.z * offset.z);
+            }
+        }
+    }
+    Worley { f1: worley.f1.sqrt(), f2: worley.f2.sqrt() }
+}
+
+/// Octaves of a noise function summed at rising frequencies and falling amplitudes, each with
+/// its own seed, normalized back to the base noise's range
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fractal {
+    pub octaves: u32,
+    /// of the first octave
+    pub frequency: Float,
+    /// frequency factor from one octave to the next
+    pub lacunarity: Float,
+    /// amplitude factor from one octave to the next
+    pub gain: Float,
+}
+
+impl Default for Fractal {
+    fn default() -> Fractal {
+        Fractal { octaves: 5, frequency: 1.0, lacunarity: 2.0, gain: 0.5 }
+    }
+}
+
+impl Fractal {
+    /// Fractal Brownian motion of `noise`, e.g. `fractal.fbm2(x, z, seed, simplex2)`
+    pub fn fbm2<F: Fn(Float, Float, u32) -> Float>(&self, x: Float, y: Float, seed: u32, noise: F) -> Float {
+        self.sum(|octave, frequency| noise(x * frequency, y * frequency, seed.wrapping_add(octave)))
+    }
+
+    pub fn fbm3<F: Fn(Point3, u32) -> Float>(&self, point: Point3, seed: u32, noise: F) -> Float {
+        self.sum(|octave, frequency| noise(point * frequency, seed.wrapping_add(octave)))
+    }
+
+    /// Sharp crests where `noise` crosses 0, for mountain ranges; in `[0, 1]`
+    pub fn ridged3<F: Fn(Point3, u32) -> Float>(&self, point: Point3, seed: u32, noise: F) -> Float {
+        self.sum(|octave, frequency| {
+            let ridge = 1.0 - noise(point * frequency, seed.wrapping_add(octave)).abs();
+            ridge * ridge
+        })
+    }
+
+    fn sum<F: Fn(u32, Float) -> Float>(&self, octave: F) -> Float {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.frequency, 1.0);
+        for i in 0..self.octaves {
+            sum += octave(i, frequency) * amplitude;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        if total > 0.0 { sum / total } else { 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::rng::Rng;
+
+    #[test]
+    fn worley_distances_and_fractals() {
+        let mut rng = Rng::new(5);
+        for _ in 0..500 {
+            let (x, y) = (rng.range(-20.0, 20.0), rng.range(-20.0, 20.0));
+            let worley = worley2(x, y, 9);
+            assert!(worley.f1 <= worley.f2 && worley.f1 < 1.5);
+            let cells = worley3(Point3::new(x, y, 0.5), 9);
+            assert!(cells.f1 <= cells.f2 && cells.edges() >= 0.0);
+        }
+        // the feature point of a cell has distance 0 from itself
+        let h = hash2(2, 3, 9);
+        let feature = (2.0 + (h & 0xffff) as Float / 65536.0, 3.0 + (h >> 16) as Float / 65536.0);
+        assert!(worley2(feature.0, feature.1, 9).f1 < 1e-5);
+
+        let fractal = Fractal::default();
+        for _ in 0..500 {
+            let p = Point3::new(rng.range(-20.0, 20.0), rng.range(-20.0, 20.0), rng.range(-20.0, 20.0));
+            assert!(fractal.fbm3(p, 1, simplex3).abs() <= 1.0);
+            assert!((0.0..=1.0).contains(&fractal.ridged3(p, 1, perlin3)));
+        }
+        // one octave is the base noise
+        let single = Fractal { octaves: 1, frequency: 2.0, ..Fractal::default() };
+        assert_eq!(single.fbm2(0.3, 0.4, 7, simplex2), simplex2(0.6, 0.8, 7));
+        assert!(NOISE_GLSL.contains("float simplexNoise3(vec3 p, uint seed)"));
+    }
+}
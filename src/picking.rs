@@ -0,0 +1,234 @@
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use lang::{Float, Point3};
+use logging;
+use ray::Ray;
+use readback::{AsyncReadback, Readback, ReadbackFormat, ReadbackHandle, ReadbackTarget};
+use shader::Shader;
+
+/// Identifier of a pickable (or otherwise tracked) object, chosen by the application.
+/// `NodeId::MAX` can't be picked on the GPU, the picking buffer stores ids + 1.
+pub type NodeId = u32;
+
+/// World-space shape used to test a pickable object against a ray
+#[derive(Clone, PartialEq, Debug)]
+pub enum PickShape {
+    Aabb { min: Point3, max: Point3 },
+    Sphere { center: Point3, radius: Float },
+    Triangles(Vec<[Point3; 3]>),
+}
+
+impl PickShape {
+    pub fn intersect(&self, ray: &Ray) -> Option<Float> {
+        match *self {
+            PickShape::Aabb { min, max } => ray.intersect_aabb(min, max),
+            PickShape::Sphere { center, radius } => ray.intersect_sphere(center, radius),
+            PickShape::Triangles(ref triangles) => {
+                triangles.iter()
+                    .filter_map(|t| ray.intersect_triangle(t[0], t[1], t[2]))
+                    .fold(None, |nearest, t| match nearest {
+                        Some(n) if n <= t => Some(n),
+                        _ => Some(t),
+                    })
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PickHit {
+    pub id: NodeId,
+    pub distance: Float,
+    pub point: Point3,
+}
+
+/// Fragment shader for the picking pass: writes the `pickId` uniform set with
+/// `PickingBuffer::set_id` into the integer color attachment
+pub const PICKING_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+uniform uint pickId;
+out uint FragId;
+
+void main()
+{
+    FragId = pickId;
+}
+"#;
+
+/// Offscreen integer framebuffer the picking pass renders object IDs into.
+/// The buffer stores `id + 1`, so cleared pixels read back as no hit.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PickingBuffer {
+    pub fbo: u32,
+    pub id_texture: u32,
+    pub depth_rbo: u32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl PickingBuffer {
+    pub fn new(width: i32, height: i32) -> PickingBuffer {
+        let mut buffer = PickingBuffer { width, height, ..PickingBuffer::default() };
+        unsafe {
+            gl::GenFramebuffers(1, &mut buffer.fbo);
+            gl::GenTextures(1, &mut buffer.id_texture);
+            gl::GenRenderbuffers(1, &mut buffer.depth_rbo);
+        }
+        buffer.resize(width, height);
+        buffer
+    }
+
+    /// (re)allocates the attachments, call it when the framebuffer size changes
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R32UI as GLint, width, height, 0,
+                           gl::RED_INTEGER, gl::UNSIGNED_INT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth_rbo);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, width, height);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.id_texture, 0);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, self.depth_rbo);
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                engine_error!(logging::RENDERER, "picking framebuffer is not complete");
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// binds the framebuffer and clears it, draw the pickable objects after this
+    pub fn begin(&self) {
+        let clear_id: [GLuint; 4] = [0; 4];
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.width, self.height);
+            gl::ClearBufferuiv(gl::COLOR, 0, clear_id.as_ptr());
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    /// restores the default framebuffer
+    pub fn end(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// sets the id written by `PICKING_FRAGMENT_SHADER`, the shader must be in use.
+    /// `NodeId::MAX` is rejected and draws as the background.
+    pub fn set_id(shader: &Shader, id: NodeId) {
+        let value = id.checked_add(1).unwrap_or_else(|| {
+            engine_error!(logging::RENDERER, "NodeId::MAX can't be picked");
+            0
+        });
+        unsafe {
+            shader.setUint(c_str!("pickId"), value);
+        }
+    }
+
+    /// Reads back the id under the window position `(x, y)` (origin at the top left), waiting
+    /// for the GPU to finish the frame; `request_id` doesn't
+    pub fn read_id(&self, x: i32, y: i32) -> Option<NodeId> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let mut value: GLuint = 0;
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::ReadPixels(x, self.height - 1 - y, 1, 1, gl::RED_INTEGER, gl::UNSIGNED_INT,
+                           &mut value as *mut GLuint as *mut GLvoid);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+
+        if value == 0 {
+            None
+        } else {
+            Some(value - 1)
+        }
+    }
+
+    /// Like `read_id` without stalling, the id comes from `id_from` once `readback` has it
+    pub fn request_id(&self, readback: &mut AsyncReadback, x: i32, y: i32) -> Option<ReadbackHandle> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let target = ReadbackTarget::framebuffer(self.fbo, gl::COLOR_ATTACHMENT0, self.width, self.height)
+            .region(x, self.height - 1 - y, 1, 1)
+            .format(ReadbackFormat::RedUint);
+        Some(readback.request(target))
+    }
+
+    /// The id of a finished `request_id`
+    pub fn id_from(readback: &Readback) -> Option<NodeId> {
+        readback.uint(0, 0).and_then(|value| value.checked_sub(1))
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.id_texture);
+            gl::DeleteRenderbuffers(1, &self.depth_rbo);
+        }
+        *self = PickingBuffer::default();
+    }
+}
+
+/// Tests a set of registered shapes against a ray and returns the nearest hit
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Picker {
+    pub targets: Vec<(NodeId, PickShape)>,
+    /// filled by the application's picking pass, see `PickingBuffer`
+    pub gpu_buffer: Option<PickingBuffer>,
+}
+
+impl Picker {
+    pub fn new() -> Picker {
+        Picker::default()
+    }
+
+    pub fn add(&mut self, id: NodeId, shape: PickShape) {
+        self.targets.push((id, shape));
+    }
+
+    pub fn remove(&mut self, id: NodeId) {
+        self.targets.retain(|&(target_id, _)| target_id != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+
+    pub fn pick(&self, ray: &Ray) -> Option<PickHit> {
+        let mut nearest: Option<PickHit> = None;
+        for &(id, ref shape) in self.targets.iter() {
+            if let Some(distance) = shape.intersect(ray) {
+                if nearest.is_none_or(|hit| distance < hit.distance) {
+                    nearest = Some(PickHit {
+                        id,
+                        distance,
+                        point: ray.at(distance),
+                    });
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Per-pixel picking through the `gpu_buffer` rendered this frame
+    pub fn pick_gpu(&self, x: i32, y: i32) -> Option<NodeId> {
+        self.gpu_buffer.and_then(|buffer| buffer.read_id(x, y))
+    }
+}
@@ -0,0 +1,284 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use gl;
+use serde::Serialize;
+use serde_json;
+
+/// Thread id the GPU events are shown on
+const GPU_THREAD: usize = 0;
+
+static NEXT_THREAD: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static THREAD: Cell<usize> = const { Cell::new(0) };
+}
+
+fn thread_id() -> usize {
+    THREAD.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_THREAD.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+/// One event in the Chrome trace event format, times in microseconds
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct TraceEvent {
+    pub name: String,
+    pub cat: &'static str,
+    /// `X` for a scope with a duration, `i` for an instant
+    pub ph: &'static str,
+    pub ts: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dur: Option<f64>,
+    pub pid: u32,
+    pub tid: usize,
+}
+
+#[derive(Serialize)]
+struct Trace<'a> {
+    #[serde(rename = "traceEvents")]
+    trace_events: &'a [TraceEvent],
+    #[serde(rename = "displayTimeUnit")]
+    display_time_unit: &'static str,
+}
+
+struct GpuScope {
+    name: String,
+    begin: u32,
+    end: u32,
+}
+
+/// Records CPU scopes, GPU passes and frame boundaries over many frames and exports them
+/// as a `chrome://tracing` / Perfetto compatible JSON trace. CPU scopes can be recorded on
+/// any thread, GPU passes only on the GL thread.
+pub struct Profiler {
+    pub enabled: bool,
+    /// oldest events are dropped beyond this
+    pub max_events: usize,
+    start: Instant,
+    events: Mutex<VecDeque<TraceEvent>>,
+    gpu_pending: Vec<GpuScope>,
+    gpu_open: Vec<(String, u32)>,
+    /// GPU timestamp (ns) minus CPU trace time (ns), measured on the first GPU pass
+    gpu_offset: Option<i64>,
+    frame_start: Option<(usize, f64)>,
+}
+
+/// Records a CPU scope when dropped, see `Profiler::scope`
+pub struct Scope<'a> {
+    profiler: &'a Profiler,
+    name: &'static str,
+    start: f64,
+}
+
+impl<'a> Drop for Scope<'a> {
+    fn drop(&mut self) {
+        let end = self.profiler.now();
+        self.profiler.push(TraceEvent {
+            name: self.name.to_string(),
+            cat: "cpu",
+            ph: "X",
+            ts: self.start,
+            dur: Some(end - self.start),
+            pid: 1,
+            tid: thread_id(),
+        });
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            enabled: true,
+            max_events: 1_000_000,
+            start: Instant::now(),
+            events: Mutex::new(VecDeque::new()),
+            gpu_pending: vec![],
+            gpu_open: vec![],
+            gpu_offset: None,
+            frame_start: None,
+        }
+    }
+
+    /// Microseconds since the profiler was created
+    pub fn now(&self) -> f64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as f64 * 1e6 + f64::from(elapsed.subsec_nanos()) / 1e3
+    }
+
+    fn push(&self, event: TraceEvent) {
+        if !self.enabled {
+            return;
+        }
+        if let Ok(mut events) = self.events.lock() {
+            while events.len() >= self.max_events && events.pop_front().is_some() {}
+            events.push_back(event);
+        }
+    }
+
+    /// Times the CPU work until the returned guard is dropped
+    pub fn scope(&self, name: &'static str) -> Scope<'_> {
+        Scope { profiler: self, name, start: self.now() }
+    }
+
+    /// Ends the previous frame's scope and starts the next one, call it once per frame
+    pub fn frame(&mut self, frame: usize) {
+        let now = self.now();
+        if let Some((previous, start)) = self.frame_start {
+            self.push(TraceEvent {
+                name: format!("frame {}", previous),
+                cat: "frame",
+                ph: "X",
+                ts: start,
+                dur: Some(now - start),
+                pid: 1,
+                tid: thread_id(),
+            });
+        }
+        self.frame_start = Some((frame, now));
+        self.collect_gpu();
+    }
+
+    /// Starts timing a GPU pass with a timestamp query, passes may nest
+    pub fn gpu_begin(&mut self, name: &str) {
+        if !self.enabled {
+            return;
+        }
+        if self.gpu_offset.is_none() {
+            let mut gpu_now = 0;
+            unsafe {
+                gl::GetInteger64v(gl::TIMESTAMP, &mut gpu_now);
+            }
+            self.gpu_offset = Some(gpu_now - (self.now() * 1e3) as i64);
+        }
+        let query = timestamp_query();
+        self.gpu_open.push((name.to_string(), query));
+    }
+
+    pub fn gpu_end(&mut self) {
+        if let Some((name, begin)) = self.gpu_open.pop() {
+            let end = timestamp_query();
+            self.gpu_pending.push(GpuScope { name, begin, end });
+        }
+    }
+
+    /// Turns finished GPU queries into events without waiting on the GPU, done by `frame`
+    pub fn collect_gpu(&mut self) {
+        let offset = self.gpu_offset.unwrap_or(0);
+        let mut finished = vec![];
+        self.gpu_pending.retain(|scope| {
+            let mut available = 0;
+            unsafe {
+                gl::GetQueryObjectiv(scope.end, gl::QUERY_RESULT_AVAILABLE, &mut available);
+            }
+            if available == 0 {
+                return true;
+            }
+            let (mut begin, mut end) = (0u64, 0u64);
+            unsafe {
+                gl::GetQueryObjectui64v(scope.begin, gl::QUERY_RESULT, &mut begin);
+                gl::GetQueryObjectui64v(scope.end, gl::QUERY_RESULT, &mut end);
+                gl::DeleteQueries(1, &scope.begin);
+                gl::DeleteQueries(1, &scope.end);
+            }
+            finished.push(TraceEvent {
+                name: scope.name.clone(),
+                cat: "gpu",
+                ph: "X",
+                ts: (begin as i64 - offset) as f64 / 1e3,
+                dur: Some(end.saturating_sub(begin) as f64 / 1e3),
+                pid: 1,
+                tid: GPU_THREAD,
+            });
+            false
+        });
+        for event in finished {
+            self.push(event);
+        }
+    }
+
+    /// Marks a point in time, e.g. a level load or a hitch
+    pub fn instant(&self, name: &str) {
+        self.push(TraceEvent {
+            name: name.to_string(),
+            cat: "marker",
+            ph: "i",
+            ts: self.now(),
+            dur: None,
+            pid: 1,
+            tid: thread_id(),
+        });
+    }
+
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().map(|events| events.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut events) = self.events.lock() {
+            events.clear();
+        }
+    }
+
+    /// The trace as JSON for `chrome://tracing` or ui.perfetto.dev
+    pub fn to_chrome_json(&self) -> String {
+        let events = self.events();
+        let trace = Trace { trace_events: &events, display_time_unit: "ms" };
+        serde_json::to_string(&trace).unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let events = self.events();
+        let trace = Trace { trace_events: &events, display_time_unit: "ms" };
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, &trace).map_err(io::Error::from)
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+fn timestamp_query() -> u32 {
+    let mut query = 0;
+    unsafe {
+        gl::GenQueries(1, &mut query);
+        gl::QueryCounter(query, gl::TIMESTAMP);
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_nested_scopes() {
+        let mut profiler = Profiler::new();
+        profiler.frame(1);
+        {
+            let _update = profiler.scope("update");
+            let _physics = profiler.scope("physics");
+        }
+        profiler.frame(2);
+
+        let events = profiler.events();
+        assert_eq!(events.iter().map(|event| event.name.as_str()).collect::<Vec<_>>(),
+                   vec!["physics", "update", "frame 1"]);
+        assert!(events[1].ts <= events[0].ts && events[1].dur >= events[0].dur);
+
+        let json = profiler.to_chrome_json();
+        assert!(json.starts_with("{\"traceEvents\":[{\"name\":\"physics\",\"cat\":\"cpu\",\"ph\":\"X\""));
+    }
+}
@@ -0,0 +1,156 @@
+use cgmath::prelude::*;
+
+use lang::{Float, Point3, Vector3};
+
+const EPSILON: Float = 1e-6;
+
+/// Half-line in world space, `direction` is always normalized
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Ray {
+    pub origin: Point3,
+    pub direction: Vector3,
+}
+
+impl Ray {
+    pub fn new(origin: Point3, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// Point on the ray at distance `t` from the origin
+    pub fn at(&self, t: Float) -> Point3 {
+        self.origin + self.direction * t
+    }
+
+    /// Slab test against an axis aligned box, returns the distance to the entry point
+    /// (or 0 when the origin is inside the box)
+    pub fn intersect_aabb(&self, min: Point3, max: Point3) -> Option<Float> {
+        let mut t_min: Float = 0.0;
+        let mut t_max = Float::INFINITY;
+
+        for axis in 0..3 {
+            let origin = self.origin[axis];
+            let direction = self.direction[axis];
+
+            if direction.abs() < EPSILON {
+                // parallel to the slab: miss unless the origin lies between the planes
+                if origin < min[axis] || origin > max[axis] {
+                    return None;
+                }
+            } else {
+                let inv = 1.0 / direction;
+                let mut t0 = (min[axis] - origin) * inv;
+                let mut t1 = (max[axis] - origin) * inv;
+                if t0 > t1 {
+                    ::std::mem::swap(&mut t0, &mut t1);
+                }
+                t_min = t_min.max(t0);
+                t_max = t_max.min(t1);
+                if t_min > t_max {
+                    return None;
+                }
+            }
+        }
+
+        Some(t_min)
+    }
+
+    /// Returns the distance to the nearest intersection in front of the origin
+    pub fn intersect_sphere(&self, center: Point3, radius: Float) -> Option<Float> {
+        let oc = self.origin - center;
+        let b = oc.dot(self.direction);
+        let c = oc.magnitude2() - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let sqrt_d = discriminant.sqrt();
+        let t_near = -b - sqrt_d;
+        let t_far = -b + sqrt_d;
+        if t_near >= 0.0 {
+            Some(t_near)
+        } else if t_far >= 0.0 {
+            // origin is inside the sphere
+            Some(0.0)
+        } else {
+            None
+        }
+    }
+
+    /// Möller–Trumbore ray/triangle intersection, both faces are hit
+    pub fn intersect_triangle(&self, a: Point3, b: Point3, c: Point3) -> Option<Float> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        if t >= 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_hits_aabb() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let min = Point3::new(-1.0, -1.0, -1.0);
+        let max = Point3::new(1.0, 1.0, 1.0);
+        assert_eq!(ray.intersect_aabb(min, max), Some(4.0));
+
+        let inside = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::unit_x());
+        assert_eq!(inside.intersect_aabb(min, max), Some(0.0));
+
+        let miss = Ray::new(Point3::new(0.0, 2.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(miss.intersect_aabb(min, max), None);
+
+        let behind = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::unit_z());
+        assert_eq!(behind.intersect_aabb(min, max), None);
+    }
+
+    #[test]
+    fn ray_hits_sphere() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(ray.intersect_sphere(Point3::new(0.0, 0.0, 0.0), 1.0), Some(4.0));
+        assert_eq!(ray.intersect_sphere(Point3::new(3.0, 0.0, 0.0), 1.0), None);
+        assert_eq!(ray.intersect_sphere(Point3::new(0.0, 0.0, 10.0), 1.0), None);
+    }
+
+    #[test]
+    fn ray_hits_triangle() {
+        let ray = Ray::new(Point3::new(0.25, 0.25, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        let a = Point3::new(0.0, 0.0, 0.0);
+        let b = Point3::new(1.0, 0.0, 0.0);
+        let c = Point3::new(0.0, 1.0, 0.0);
+        assert_eq!(ray.intersect_triangle(a, b, c), Some(1.0));
+        assert_eq!(ray.intersect_triangle(a, c, b), Some(1.0));
+
+        let miss = Ray::new(Point3::new(0.75, 0.75, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(miss.intersect_triangle(a, b, c), None);
+    }
+}
@@ -0,0 +1,355 @@
+//! Pixel readback without stalls: `glReadPixels` into a pixel buffer object returns at
+//! once, a fence tells when the copy is done, and only then is the buffer mapped. Results
+//! usually arrive a frame or two after the request.
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
+
+use gl;
+use gl::types::*;
+
+use logging;
+use render_target::RenderTarget;
+use testing::Image;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadbackFormat {
+    Rgb8,
+    Rgba8,
+    /// one `u32` per pixel from an integer attachment, e.g. picking ids
+    RedUint,
+}
+
+impl ReadbackFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            ReadbackFormat::Rgb8 => 3,
+            ReadbackFormat::Rgba8 | ReadbackFormat::RedUint => 4,
+        }
+    }
+
+    /// `glReadPixels` format and type
+    fn gl(self) -> (GLenum, GLenum) {
+        match self {
+            ReadbackFormat::Rgb8 => (gl::RGB, gl::UNSIGNED_BYTE),
+            ReadbackFormat::Rgba8 => (gl::RGBA, gl::UNSIGNED_BYTE),
+            ReadbackFormat::RedUint => (gl::RED_INTEGER, gl::UNSIGNED_INT),
+        }
+    }
+}
+
+/// What to read: a framebuffer's color buffer and a region of it, origin at the bottom left
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReadbackTarget {
+    pub framebuffer: GLuint,
+    /// `BACK` for the default framebuffer, a color attachment otherwise
+    pub buffer: GLenum,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub format: ReadbackFormat,
+}
+
+impl ReadbackTarget {
+    /// The whole `width` x `height` back buffer as RGB
+    pub fn back_buffer(width: i32, height: i32) -> ReadbackTarget {
+        ReadbackTarget { framebuffer: 0, buffer: gl::BACK, x: 0, y: 0, width, height, format: ReadbackFormat::Rgb8 }
+    }
+
+    /// The whole (resolved) color attachment of `target` as RGB
+    pub fn render_target(target: &RenderTarget) -> ReadbackTarget {
+        ReadbackTarget { framebuffer: target.fbo, buffer: gl::COLOR_ATTACHMENT0, ..ReadbackTarget::back_buffer(target.width, target.height) }
+    }
+
+    pub fn framebuffer(framebuffer: GLuint, buffer: GLenum, width: i32, height: i32) -> ReadbackTarget {
+        ReadbackTarget { framebuffer, buffer, ..ReadbackTarget::back_buffer(width, height) }
+    }
+
+    pub fn region(mut self, x: i32, y: i32, width: i32, height: i32) -> ReadbackTarget {
+        self.x = x;
+        self.y = y;
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn format(mut self, format: ReadbackFormat) -> ReadbackTarget {
+        self.format = format;
+        self
+    }
+
+    fn size(&self) -> usize {
+        self.width.max(0) as usize * self.height.max(0) as usize * self.format.bytes_per_pixel()
+    }
+}
+
+/// Ticket for a requested readback, see `AsyncReadback::take`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ReadbackHandle(u64);
+
+/// Pixels of a finished readback, rows bottom to top as GL reads them
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Readback {
+    pub width: i32,
+    pub height: i32,
+    pub format: ReadbackFormat,
+    pub data: Vec<u8>,
+}
+
+impl Readback {
+    /// RGB image with rows top to bottom, `None` unless the format is `Rgb8`
+    pub fn into_image(self) -> Option<Image> {
+        if self.format != ReadbackFormat::Rgb8 {
+            return None;
+        }
+        let mut image = Image { width: self.width as usize, height: self.height as usize, pixels: self.data };
+        image.flip_vertical();
+        Some(image)
+    }
+
+    /// `RedUint` value at `(x, y)` from the bottom left of the region
+    pub fn uint(&self, x: i32, y: i32) -> Option<u32> {
+        if self.format != ReadbackFormat::RedUint || x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.data[offset..offset + 4]);
+        Some(u32::from_ne_bytes(bytes))
+    }
+}
+
+struct Pending {
+    handle: ReadbackHandle,
+    buffer: usize,
+    fence: GLsync,
+    target: ReadbackTarget,
+}
+
+/// Pool of pixel buffers serving readback requests, call `poll` once a frame
+#[derive(Default)]
+pub struct AsyncReadback {
+    /// PBOs and their sizes, `free` indexes into them
+    buffers: Vec<(GLuint, usize)>,
+    free: Vec<usize>,
+    pending: VecDeque<Pending>,
+    finished: HashMap<ReadbackHandle, Readback>,
+    next: u64,
+}
+
+impl AsyncReadback {
+    pub fn new() -> AsyncReadback {
+        AsyncReadback::default()
+    }
+
+    /// Starts copying `target` into a pixel buffer without waiting for it
+    pub fn request(&mut self, target: ReadbackTarget) -> ReadbackHandle {
+        self.next += 1;
+        let handle = ReadbackHandle(self.next);
+        let size = target.size();
+        let buffer = self.buffer(size);
+        let (format, kind) = target.format.gl();
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, target.framebuffer);
+            gl::ReadBuffer(target.buffer);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.buffers[buffer].0);
+            gl::ReadPixels(target.x, target.y, target.width, target.height, format, kind, ptr::null_mut());
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            self.pending.push_back(Pending { handle, buffer, fence, target });
+        }
+        handle
+    }
+
+    /// A free buffer of at least `size` bytes, grown or created if needed
+    fn buffer(&mut self, size: usize) -> usize {
+        let index = match self.free.iter().position(|&index| self.buffers[index].1 >= size) {
+            Some(position) => self.free.swap_remove(position),
+            None => match self.free.pop() {
+                Some(index) => index,
+                None => {
+                    let mut pbo = 0;
+                    unsafe {
+                        gl::GenBuffers(1, &mut pbo);
+                    }
+                    self.buffers.push((pbo, 0));
+                    self.buffers.len() - 1
+                },
+            },
+        };
+        let (pbo, capacity) = self.buffers[index];
+        if capacity < size {
+            unsafe {
+                gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+                gl::BufferData(gl::PIXEL_PACK_BUFFER, size as GLsizeiptr, ptr::null(), gl::STREAM_READ);
+                gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            }
+            self.buffers[index].1 = size;
+        }
+        index
+    }
+
+    /// Collects the readbacks the GPU is done with, never waits
+    pub fn poll(&mut self) {
+        // fences signal in submission order
+        while self.pending.front().is_some_and(|pending| unsafe { is_signaled(pending.fence, 0) }) {
+            let pending = self.pending.pop_front().expect("checked above");
+            self.complete(pending);
+        }
+    }
+
+    pub fn is_ready(&self, handle: ReadbackHandle) -> bool {
+        self.finished.contains_key(&handle)
+    }
+
+    /// Whether `handle` is still waiting for the GPU
+    pub fn is_pending(&self, handle: ReadbackHandle) -> bool {
+        self.pending.iter().any(|pending| pending.handle == handle)
+    }
+
+    /// Requests not finished yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The finished readback, `None` if it isn't done yet (or was taken already)
+    pub fn take(&mut self, handle: ReadbackHandle) -> Option<Readback> {
+        self.finished.remove(&handle)
+    }
+
+    /// Blocks until `handle` is done, for when the result is needed this frame
+    pub fn wait(&mut self, handle: ReadbackHandle) -> Option<Readback> {
+        if let Some(position) = self.pending.iter().position(|pending| pending.handle == handle) {
+            // everything before it finishes first anyway
+            for _ in 0..=position {
+                let pending = self.pending.pop_front().expect("position is in the queue");
+                while !unsafe { is_signaled(pending.fence, 1_000_000) } {}
+                self.complete(pending);
+            }
+        }
+        self.take(handle)
+    }
+
+    /// Drops the result of `handle` once it arrives
+    pub fn cancel(&mut self, handle: ReadbackHandle) {
+        self.finished.remove(&handle);
+        if let Some(position) = self.pending.iter().position(|pending| pending.handle == handle) {
+            let pending = self.pending.remove(position).expect("position is in the queue");
+            unsafe {
+                gl::DeleteSync(pending.fence);
+            }
+            self.free.push(pending.buffer);
+        }
+    }
+
+    fn complete(&mut self, pending: Pending) {
+        let size = pending.target.size();
+        let mut data = vec![0; size];
+        unsafe {
+            gl::DeleteSync(pending.fence);
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.buffers[pending.buffer].0);
+            let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, size as GLsizeiptr, gl::MAP_READ_BIT);
+            if mapped.is_null() {
+                engine_error!(logging::RENDERER, "couldn't map readback buffer {}", self.buffers[pending.buffer].0);
+            } else {
+                data.copy_from_slice(slice::from_raw_parts(mapped as *const u8, size));
+                gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+            }
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+        self.free.push(pending.buffer);
+        let target = pending.target;
+        self.finished.insert(pending.handle, Readback { width: target.width, height: target.height, format: target.format, data });
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            for pending in self.pending.drain(..) {
+                gl::DeleteSync(pending.fence);
+            }
+            for &(pbo, _) in self.buffers.iter() {
+                gl::DeleteBuffers(1, &pbo);
+            }
+        }
+        *self = AsyncReadback::default();
+    }
+}
+
+unsafe fn is_signaled(fence: GLsync, timeout: GLuint64) -> bool {
+    let result = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, timeout);
+    result == gl::ALREADY_SIGNALED || result == gl::CONDITION_SATISFIED || result == gl::WAIT_FAILED
+}
+
+/// Screenshots saved as PPM once their readback arrives
+#[derive(Debug, Default)]
+pub struct Screenshots {
+    requested: Vec<(ReadbackHandle, PathBuf)>,
+}
+
+impl Screenshots {
+    pub fn new() -> Screenshots {
+        Screenshots::default()
+    }
+
+    /// Reads the `width` x `height` back buffer for `path`, call before swapping buffers
+    pub fn request<P: Into<PathBuf>>(&mut self, readback: &mut AsyncReadback, width: i32, height: i32, path: P) {
+        let handle = readback.request(ReadbackTarget::back_buffer(width, height));
+        self.requested.push((handle, path.into()));
+    }
+
+    pub fn pending(&self) -> usize {
+        self.requested.len()
+    }
+
+    /// Saves the screenshots whose pixels arrived, after `AsyncReadback::poll`
+    pub fn save_finished(&mut self, readback: &mut AsyncReadback) -> Vec<(PathBuf, io::Result<()>)> {
+        let mut saved = vec![];
+        self.requested.retain(|&(handle, ref path)| {
+            match readback.take(handle).and_then(Readback::into_image) {
+                Some(image) => {
+                    let result = image.save(path);
+                    match result {
+                        Ok(()) => engine_info!(logging::RENDERER, "saved screenshot {}", path.display()),
+                        Err(ref error) => engine_error!(logging::RENDERER, "couldn't save {}: {}", path.display(), error),
+                    }
+                    saved.push((path.clone(), result));
+                    false
+                },
+                None => true,
+            }
+        });
+        saved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use picking::PickingBuffer;
+
+    #[test]
+    fn targets_and_results() {
+        let target = ReadbackTarget::back_buffer(640, 360).region(10, 20, 2, 3).format(ReadbackFormat::RedUint);
+        assert_eq!((target.framebuffer, target.buffer), (0, gl::BACK));
+        assert_eq!(target.size(), 2 * 3 * 4);
+
+        let ids = Readback { width: 2, height: 1, format: ReadbackFormat::RedUint,
+                             data: [7u32.to_ne_bytes(), 9u32.to_ne_bytes()].concat() };
+        assert_eq!(ids.uint(1, 0), Some(9));
+        assert_eq!(ids.uint(2, 0), None);
+        // picking ids are stored off by one, 0 is the background
+        assert_eq!(PickingBuffer::id_from(&ids), Some(6));
+        assert_eq!(ids.clone().into_image(), None);
+
+        // bottom row first in, top row first out
+        let pixels = Readback { width: 1, height: 2, format: ReadbackFormat::Rgb8, data: vec![1, 1, 1, 2, 2, 2] };
+        let image = pixels.into_image().unwrap();
+        assert_eq!(image.pixel(0, 0), [2, 2, 2]);
+        assert_eq!(image.pixel(0, 1), [1, 1, 1]);
+    }
+}
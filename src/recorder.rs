@@ -0,0 +1,371 @@
+//! Frame recorder for demo footage: reads the back buffer with `AsyncReadback`, so a frame
+//! is copied while the GPU is already on the next ones, and encodes on a background thread,
+//! either to a PPM image sequence or through an `ffmpeg` pipe.
+//!
+//! Call `Recorder::capture` after rendering and before swapping buffers. Stepping the game
+//! with `Recorder::frame_time` while recording keeps the footage smooth even when encoding
+//! makes the real frames uneven.
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use glfw::{Action, Key};
+
+use input::{InputControl, KeyEvent, MouseEvent};
+use lang::TimeSec;
+use logging;
+use readback::{AsyncReadback, Readback, ReadbackHandle, ReadbackTarget};
+use testing::Image;
+use window::InputState;
+
+/// Frames read before the recorder waits for the oldest one
+const MAX_IN_FLIGHT: usize = 3;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RecordingFormat {
+    /// `recording_NNN/frame_NNNNN.ppm`
+    ImageSequence,
+    /// `recording_NNN.<extension>` encoded by `ffmpeg`, e.g. `mp4` or `gif`
+    Ffmpeg { extension: String },
+}
+
+/// Receives the frames, top row first, on the encoder thread
+trait FrameSink: Send {
+    fn write(&mut self, frame: &Image) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+struct ImageSequence {
+    dir: PathBuf,
+    next: usize,
+}
+
+impl FrameSink for ImageSequence {
+    fn write(&mut self, frame: &Image) -> io::Result<()> {
+        self.next += 1;
+        frame.save(self.dir.join(format!("frame_{:05}.ppm", self.next)))
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct FfmpegPipe {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl FrameSink for FfmpegPipe {
+    fn write(&mut self, frame: &Image) -> io::Result<()> {
+        match self.stdin {
+            Some(ref mut stdin) => stdin.write_all(&frame.pixels),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "ffmpeg input is closed")),
+        }
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        // closing the input ends the stream
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg exited with {}", status)))
+        }
+    }
+}
+
+/// `ffmpeg` command line reading raw RGB frames from stdin
+pub fn ffmpeg_arguments(width: i32, height: i32, fps: u32, output: &Path) -> Vec<String> {
+    let mut arguments: Vec<String> = ["-y", "-loglevel", "error", "-f", "rawvideo", "-pixel_format", "rgb24"]
+        .iter().map(|argument| argument.to_string()).collect();
+    arguments.extend(vec!["-video_size".to_string(), format!("{}x{}", width, height),
+                          "-framerate".to_string(), fps.to_string(), "-i".to_string(), "-".to_string()]);
+    match output.extension().and_then(|extension| extension.to_str()) {
+        // a palette made from the whole clip looks far better than the default one
+        Some("gif") => arguments.extend(vec!["-vf".to_string(), "split[a][b];[a]palettegen[p];[b][p]paletteuse".to_string()]),
+        // yuv420p for players that don't support anything else, it needs even sizes
+        _ => arguments.extend(vec!["-vf".to_string(), "pad=ceil(iw/2)*2:ceil(ih/2)*2".to_string(),
+                                   "-pix_fmt".to_string(), "yuv420p".to_string()]),
+    }
+    arguments.push(output.to_string_lossy().into_owned());
+    arguments
+}
+
+/// First `recording_NNN` in `dir` that doesn't exist yet, with `extension` if given
+fn next_output(dir: &Path, extension: Option<&str>) -> PathBuf {
+    (1..).map(|index| {
+        let name = format!("recording_{:03}", index);
+        match extension {
+            Some(extension) => dir.join(name).with_extension(extension),
+            None => dir.join(name),
+        }
+    }).find(|path| !path.exists()).expect("unbounded range")
+}
+
+/// Sink and thread writing the frames sent to it
+struct Encoder {
+    frames: Option<Sender<Readback>>,
+    thread: Option<JoinHandle<io::Result<usize>>>,
+}
+
+impl Encoder {
+    fn start(mut sink: Box<dyn FrameSink>) -> io::Result<Encoder> {
+        let (frames, received) = mpsc::channel::<Readback>();
+        let thread = thread::Builder::new().name("reactor-recorder".to_string()).spawn(move || {
+            let mut written = 0;
+            // flipped to top row first here rather than on the render thread
+            for frame in received.into_iter().filter_map(Readback::into_image) {
+                sink.write(&frame)?;
+                written += 1;
+            }
+            sink.finish().map(|()| written)
+        })?;
+        Ok(Encoder { frames: Some(frames), thread: Some(thread) })
+    }
+
+    /// False once the encoder failed
+    fn send(&self, frame: Readback) -> bool {
+        self.frames.as_ref().is_some_and(|frames| frames.send(frame).is_ok())
+    }
+
+    /// Waits for the queued frames to be written
+    fn finish(&mut self) -> io::Result<usize> {
+        drop(self.frames.take());
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| Err(io::Error::other("encoder thread panicked"))),
+            None => Ok(0),
+        }
+    }
+}
+
+struct Session {
+    output: PathBuf,
+    encoder: Encoder,
+    width: i32,
+    height: i32,
+    /// frames requested and not sent to the encoder yet, oldest first
+    frames: VecDeque<ReadbackHandle>,
+    captured: usize,
+}
+
+impl Session {
+    /// Sends the frames that arrived to the encoder, waiting for the oldest once `MAX_IN_FLIGHT`
+    /// are queued or if `all`; false if the encoder failed
+    fn flush(&mut self, readback: &mut AsyncReadback, all: bool) -> bool {
+        readback.poll();
+        while let Some(&handle) = self.frames.front() {
+            let wait = all || self.frames.len() > MAX_IN_FLIGHT;
+            let frame = if wait { readback.wait(handle) } else { readback.take(handle) };
+            if frame.is_none() && !wait {
+                break;
+            }
+            self.frames.pop_front();
+            if frame.is_some_and(|frame| !self.encoder.send(frame)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Records the back buffer while toggled on, by `toggle_key` or `toggle`
+pub struct Recorder {
+    /// starts and stops a recording, F9 by default
+    pub toggle_key: Key,
+    /// frame rate of the encoded video and of `frame_time`
+    pub fps: u32,
+    dir: PathBuf,
+    format: RecordingFormat,
+    wanted: bool,
+    session: Option<Session>,
+    readback: AsyncReadback,
+}
+
+impl Recorder {
+    /// Recordings go to numbered files or directories in `dir`
+    pub fn new<P: Into<PathBuf>>(dir: P, format: RecordingFormat) -> Recorder {
+        Recorder { toggle_key: Key::F9, fps: 60, dir: dir.into(), format, wanted: false, session: None,
+                   readback: AsyncReadback::new() }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Starts or stops recording with the next `capture`
+    pub fn toggle(&mut self) {
+        self.wanted = !self.wanted;
+    }
+
+    pub fn start(&mut self) {
+        self.wanted = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.wanted = false;
+    }
+
+    /// Where the current recording goes
+    pub fn output(&self) -> Option<&Path> {
+        self.session.as_ref().map(|session| session.output.as_path())
+    }
+
+    /// Frames captured in the current recording
+    pub fn frames(&self) -> usize {
+        self.session.as_ref().map_or(0, |session| session.captured)
+    }
+
+    /// Fixed time step matching `fps` while recording
+    pub fn frame_time(&self) -> Option<TimeSec> {
+        if self.is_recording() { Some(1.0 / self.fps.max(1) as TimeSec) } else { None }
+    }
+
+    /// Reads the `width` x `height` back buffer if recording, call before swapping buffers.
+    /// A size change ends the recording.
+    pub fn capture(&mut self, width: i32, height: i32) {
+        let resized = self.session.as_ref().is_some_and(|session| (session.width, session.height) != (width, height));
+        if resized {
+            engine_info!(logging::RENDERER, "window resized, ending the recording");
+            self.wanted = false;
+        }
+        if !self.wanted {
+            self.finish();
+            return;
+        }
+        if self.session.is_none() {
+            match self.begin(width, height) {
+                Ok(session) => {
+                    engine_info!(logging::RENDERER, "recording {}x{} to {}", width, height, session.output.display());
+                    self.session = Some(session);
+                },
+                Err(error) => {
+                    engine_error!(logging::RENDERER, "couldn't start recording: {}", error);
+                    self.wanted = false;
+                    return;
+                },
+            }
+        }
+
+        let readback = &mut self.readback;
+        let failed = self.session.as_mut().is_some_and(|session| {
+            session.frames.push_back(readback.request(ReadbackTarget::back_buffer(width, height)));
+            session.captured += 1;
+            !session.flush(readback, false)
+        });
+        if failed {
+            self.wanted = false;
+            self.finish();
+        }
+    }
+
+    /// Ends the recording now, waiting for the frames in flight to be encoded
+    pub fn finish(&mut self) {
+        let mut session = match self.session.take() {
+            Some(session) => session,
+            None => return,
+        };
+        session.flush(&mut self.readback, true);
+        for handle in session.frames.drain(..) {
+            self.readback.cancel(handle);
+        }
+        match session.encoder.finish() {
+            Ok(frames) => engine_info!(logging::RENDERER, "recorded {} frames to {}", frames, session.output.display()),
+            Err(error) => engine_error!(logging::RENDERER, "recording to {} failed: {}", session.output.display(), error),
+        }
+    }
+
+    fn begin(&self, width: i32, height: i32) -> io::Result<Session> {
+        fs::create_dir_all(&self.dir)?;
+        let (output, sink): (PathBuf, Box<dyn FrameSink>) = match self.format {
+            RecordingFormat::ImageSequence => {
+                let dir = next_output(&self.dir, None);
+                fs::create_dir_all(&dir)?;
+                (dir.clone(), Box::new(ImageSequence { dir, next: 0 }))
+            },
+            RecordingFormat::Ffmpeg { ref extension } => {
+                let path = next_output(&self.dir, Some(extension));
+                let mut child = Command::new("ffmpeg")
+                    .args(ffmpeg_arguments(width, height, self.fps, &path))
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                let stdin = child.stdin.take();
+                (path, Box::new(FfmpegPipe { child, stdin }))
+            },
+        };
+
+        Ok(Session { output, encoder: Encoder::start(sink)?, width, height, frames: VecDeque::new(), captured: 0 })
+    }
+
+    /// Ends the recording and frees the readback buffers
+    pub fn delete(&mut self) {
+        self.wanted = false;
+        self.finish();
+        self.readback.delete();
+    }
+}
+
+impl InputControl for Recorder {
+    fn on_mouse(&mut self, _mouse: MouseEvent, _delta_time: TimeSec) {}
+
+    fn on_keyboard(&mut self, key: KeyEvent, _delta_time: TimeSec) {
+        let KeyEvent(key, _, action, _) = key;
+        if key == self.toggle_key && action == Action::Press {
+            self.toggle();
+        }
+    }
+
+    fn on_input(&mut self, _window: &dyn InputState, _delta_time: TimeSec) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use readback::ReadbackFormat;
+
+    #[test]
+    fn encodes_image_sequences() {
+        let dir = env::temp_dir().join(format!("reactor_recorder_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = next_output(&dir, None);
+        assert_eq!(output, dir.join("recording_001"));
+        fs::create_dir_all(&output).unwrap();
+        assert_eq!(next_output(&dir, None), dir.join("recording_002"));
+        assert_eq!(next_output(&dir, Some("gif")), dir.join("recording_001.gif"));
+
+        // frames arrive bottom row first and are written top row first
+        let mut encoder = Encoder::start(Box::new(ImageSequence { dir: output.clone(), next: 0 })).unwrap();
+        for shade in 0..3u8 {
+            let mut data = vec![0; 12];
+            data[0] = shade;
+            assert!(encoder.send(Readback { width: 2, height: 2, format: ReadbackFormat::Rgb8, data }));
+        }
+        assert_eq!(encoder.finish().unwrap(), 3);
+        let last = Image::load(output.join("frame_00003.ppm")).unwrap();
+        assert_eq!(last.pixel(0, 1), [2, 0, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let arguments = ffmpeg_arguments(640, 360, 30, Path::new("clip.gif"));
+        assert_eq!(&arguments[arguments.len() - 3..], ["-vf", "split[a][b];[a]palettegen[p];[b][p]paletteuse", "clip.gif"]);
+        assert!(arguments.windows(2).any(|pair| pair == ["-video_size", "640x360"]));
+        assert!(ffmpeg_arguments(640, 360, 30, Path::new("clip.mp4")).contains(&"yuv420p".to_string()));
+    }
+
+    #[test]
+    fn toggles_with_the_hotkey() {
+        let mut recorder = Recorder::new(env::temp_dir(), RecordingFormat::ImageSequence);
+        assert_eq!(recorder.frame_time(), None);
+        recorder.on_keyboard(KeyEvent(Key::F9, 0, Action::Press, ::glfw::Modifiers::empty()), 0.0);
+        assert!(recorder.wanted);
+        recorder.on_keyboard(KeyEvent(Key::F9, 0, Action::Release, ::glfw::Modifiers::empty()), 0.0);
+        assert!(recorder.wanted);
+        recorder.on_keyboard(KeyEvent(Key::F9, 0, Action::Press, ::glfw::Modifiers::empty()), 0.0);
+        assert!(!recorder.wanted);
+    }
+}
@@ -0,0 +1,239 @@
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use gl_state::{ClearSpec, GlState};
+use logging;
+use viewport::Viewport;
+
+/// Storage of a `RenderTarget`'s color buffer
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum ColorFormat {
+    #[default]
+    Rgba8,
+    /// half floats, for HDR lighting above 1 before `renderer::hdr` tone maps it
+    Rgba16F,
+}
+
+impl ColorFormat {
+    pub fn internal_format(self) -> GLenum {
+        match self {
+            ColorFormat::Rgba8 => gl::RGBA8,
+            ColorFormat::Rgba16F => gl::RGBA16F,
+        }
+    }
+
+    fn pixel_type(self) -> GLenum {
+        match self {
+            ColorFormat::Rgba8 => gl::UNSIGNED_BYTE,
+            ColorFormat::Rgba16F => gl::FLOAT,
+        }
+    }
+}
+
+/// Offscreen framebuffer with a sampleable color texture in `format` and a depth/stencil buffer.
+///
+/// With `samples > 0` drawing goes into multisampled renderbuffers, and
+/// `resolve` blits them into `color_texture` before it can be sampled.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct RenderTarget {
+    pub fbo: u32,
+    pub color_texture: u32,
+    pub depth_rbo: u32,
+    pub msaa_fbo: u32,
+    pub msaa_color_rbo: u32,
+    pub msaa_depth_rbo: u32,
+    pub width: i32,
+    pub height: i32,
+    pub samples: i32,
+    pub format: ColorFormat,
+    /// applied by `begin`
+    pub clear: ClearSpec,
+}
+
+impl RenderTarget {
+    pub fn new(width: i32, height: i32, samples: i32) -> RenderTarget {
+        RenderTarget::with_format(width, height, samples, ColorFormat::Rgba8)
+    }
+
+    pub fn with_format(width: i32, height: i32, samples: i32, format: ColorFormat) -> RenderTarget {
+        let mut target = RenderTarget { width, height, samples, format, ..RenderTarget::default() };
+        unsafe {
+            gl::GenFramebuffers(1, &mut target.fbo);
+            gl::GenTextures(1, &mut target.color_texture);
+            gl::GenRenderbuffers(1, &mut target.depth_rbo);
+        }
+        target.allocate();
+        target
+    }
+
+    /// Target with a half float color buffer, for HDR rendering
+    pub fn hdr(width: i32, height: i32, samples: i32) -> RenderTarget {
+        RenderTarget::with_format(width, height, samples, ColorFormat::Rgba16F)
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.samples > 0
+    }
+
+    /// (re)allocates the attachments, call it when the framebuffer size changes
+    pub fn resize(&mut self, width: i32, height: i32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.allocate();
+    }
+
+    /// Switches antialiasing at runtime, 0 disables MSAA
+    pub fn set_samples(&mut self, samples: i32) {
+        if self.samples == samples {
+            return;
+        }
+        self.samples = samples;
+        self.allocate();
+    }
+
+    /// Switches the color buffer format at runtime, reallocating the attachments
+    pub fn set_format(&mut self, format: ColorFormat) {
+        if self.format == format {
+            return;
+        }
+        self.format = format;
+        self.allocate();
+    }
+
+    /// binds the framebuffer to draw into and sets the viewport to cover it
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.draw_fbo());
+        }
+        self.viewport().apply();
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        Viewport::full(self.width, self.height)
+    }
+
+    /// Starts a pass: binds the target and clears it according to `clear`
+    pub fn begin(&self, state: &mut GlState) {
+        self.bind();
+        self.clear.apply(state);
+    }
+
+    /// restores the default framebuffer
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// framebuffer drawing goes into, the multisampled one when MSAA is enabled
+    pub fn draw_fbo(&self) -> u32 {
+        if self.is_multisampled() { self.msaa_fbo } else { self.fbo }
+    }
+
+    /// Blits the multisampled color buffer into `color_texture`, no-op without MSAA
+    pub fn resolve(&self) {
+        if !self.is_multisampled() {
+            return;
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.msaa_fbo);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.fbo);
+            gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, self.width, self.height,
+                                gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// Resolves (if needed) and copies the color buffer to the default framebuffer,
+    /// scaled to `width` x `height`
+    pub fn blit_to_screen(&self, width: i32, height: i32) {
+        self.blit_region_to_screen(self.viewport(), width, height);
+    }
+
+    /// Like `blit_to_screen` for only the `region` of the target that was drawn into
+    pub fn blit_region_to_screen(&self, region: Viewport, width: i32, height: i32) {
+        self.resolve();
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::BlitFramebuffer(region.x, region.y, region.x + region.width, region.y + region.height,
+                                0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::LINEAR);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            self.delete_msaa();
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.color_texture);
+            gl::DeleteRenderbuffers(1, &self.depth_rbo);
+        }
+        *self = RenderTarget::default();
+    }
+
+    fn allocate(&mut self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.color_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, self.format.internal_format() as GLint, self.width, self.height, 0,
+                           gl::RGBA, self.format.pixel_type(), ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth_rbo);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, self.width, self.height);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.color_texture, 0);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, self.depth_rbo);
+            check_status("RenderTarget");
+
+            self.delete_msaa();
+            if self.is_multisampled() {
+                gl::GenFramebuffers(1, &mut self.msaa_fbo);
+                gl::GenRenderbuffers(1, &mut self.msaa_color_rbo);
+                gl::GenRenderbuffers(1, &mut self.msaa_depth_rbo);
+
+                gl::BindRenderbuffer(gl::RENDERBUFFER, self.msaa_color_rbo);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, self.samples, self.format.internal_format(), self.width, self.height);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, self.msaa_depth_rbo);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, self.samples, gl::DEPTH24_STENCIL8, self.width, self.height);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.msaa_fbo);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, self.msaa_color_rbo);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, self.msaa_depth_rbo);
+                check_status("RenderTarget (multisampled)");
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    unsafe fn delete_msaa(&mut self) {
+        if self.msaa_fbo != 0 {
+            gl::DeleteFramebuffers(1, &self.msaa_fbo);
+            gl::DeleteRenderbuffers(1, &self.msaa_color_rbo);
+            gl::DeleteRenderbuffers(1, &self.msaa_depth_rbo);
+            self.msaa_fbo = 0;
+            self.msaa_color_rbo = 0;
+            self.msaa_depth_rbo = 0;
+        }
+    }
+}
+
+/// logs an error if the currently bound framebuffer is incomplete
+unsafe fn check_status(type_: &str) {
+    let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+    if status != gl::FRAMEBUFFER_COMPLETE {
+        engine_error!(logging::RENDERER, "{} framebuffer is not complete (status 0x{:X})", type_, status);
+    }
+}
@@ -0,0 +1,166 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use gl;
+use gl::types::*;
+use serde::Serialize;
+use serde_json;
+
+use gl_state::DepthState;
+use shader::UniformType;
+use super::{BlendMode, DebugMode, DrawCommand, MaterialId};
+
+/// Current value of an active uniform, arrays are captured by their first element
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct CapturedUniform {
+    pub name: String,
+    /// GL type enum, e.g. `FLOAT_MAT4`
+    pub kind: u32,
+    pub location: i32,
+    pub floats: Vec<f32>,
+    pub ints: Vec<i32>,
+}
+
+/// One draw call as issued by the renderer
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct CapturedDraw {
+    /// index into the views of `render_views`, `None` for `render`
+    pub view: Option<usize>,
+    /// program the draw ran with, a debug program if `debug` is set
+    pub program: u32,
+    pub material: MaterialId,
+    pub texture: u32,
+    pub blend: BlendMode,
+    pub debug: DebugMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_func: u32,
+    /// uniforms after the draw, including the ones its callback set
+    pub uniforms: Vec<CapturedUniform>,
+}
+
+/// Draw calls of one frame, see `Renderer::capture_next_frame`
+#[derive(Clone, PartialEq, Debug, Default, Serialize)]
+pub struct FrameCapture {
+    pub frame: usize,
+    pub draws: Vec<CapturedDraw>,
+    /// draws skipped by occlusion culling
+    pub occluded: usize,
+}
+
+impl FrameCapture {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+
+    pub(crate) fn record(&mut self, command: &DrawCommand, program: u32, debug: DebugMode, view: Option<usize>,
+                         blended: bool, depth: &DepthState) {
+        self.draws.push(CapturedDraw {
+            view,
+            program,
+            material: command.material,
+            texture: command.texture,
+            blend: command.blend,
+            debug,
+            depth_test: depth.test && debug != DebugMode::Overdraw,
+            depth_write: depth.write && !blended,
+            depth_func: depth.func,
+            uniforms: active_uniforms(program),
+        });
+    }
+}
+
+/// Queries the active uniforms of a program and their values
+pub fn active_uniforms(program: u32) -> Vec<CapturedUniform> {
+    let mut uniforms = vec![];
+    unsafe {
+        let mut count = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+        for index in 0..count.max(0) as GLuint {
+            let mut name = vec![0u8; 256];
+            let (mut length, mut size, mut kind) = (0, 0, 0);
+            gl::GetActiveUniform(program, index, name.len() as GLsizei, &mut length, &mut size, &mut kind,
+                                 name.as_mut_ptr() as *mut GLchar);
+            name.truncate(length.max(0) as usize);
+            let name = String::from_utf8_lossy(&name).into_owned();
+            let location = uniform_location(program, &name);
+
+            let ty = UniformType::from_gl(kind);
+            let mut uniform = CapturedUniform { name, kind, location, floats: vec![], ints: vec![] };
+            match readback_len(ty) {
+                Some(len) if location >= 0 && ty.is_float() => {
+                    uniform.floats = vec![0.0; len];
+                    gl::GetUniformfv(program, location, uniform.floats.as_mut_ptr());
+                },
+                Some(len) if location >= 0 => {
+                    // samplers read back their texture unit
+                    uniform.ints = vec![0; len];
+                    gl::GetUniformiv(program, location, uniform.ints.as_mut_ptr());
+                },
+                _ => {},
+            }
+            uniforms.push(uniform);
+        }
+    }
+    uniforms
+}
+
+/// Scalars `glGetUniform*v` writes for one value of `ty`, `None` for the `Other` types
+/// (doubles, non-square matrices..) whose size isn't known and that are left unread
+fn readback_len(ty: UniformType) -> Option<usize> {
+    match ty {
+        UniformType::Other(_) => None,
+        ty => Some(ty.components()),
+    }
+}
+
+fn uniform_location(program: u32, name: &str) -> GLint {
+    match CString::new(name) {
+        Ok(name) => unsafe { gl::GetUniformLocation(program, name.as_ptr()) },
+        Err(_) => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shader::Shader;
+
+    #[test]
+    fn serializes_to_json() {
+        let command = DrawCommand::new(Shader { ID: 0 }, |_, _| ()).material(2).blend(BlendMode::Alpha);
+        let mut capture = FrameCapture { frame: 12, ..FrameCapture::default() };
+        capture.draws.push(CapturedDraw {
+            view: Some(1),
+            program: 3,
+            material: command.material,
+            texture: 0,
+            blend: command.blend,
+            debug: DebugMode::Off,
+            depth_test: true,
+            depth_write: false,
+            depth_func: gl::LESS,
+            uniforms: vec![CapturedUniform { name: "model".into(), kind: gl::FLOAT_MAT4, location: 0, floats: vec![1.0], ints: vec![] }],
+        });
+        let json = capture.to_json();
+        assert!(json.contains("\"frame\": 12"));
+        assert!(json.contains("\"blend\": \"Alpha\""));
+        assert!(json.contains("\"name\": \"model\""));
+    }
+
+    #[test]
+    fn unknown_uniform_types_are_not_read_back() {
+        // glGetUniformiv would write 16 and 12 values for these
+        assert_eq!(readback_len(UniformType::from_gl(gl::DOUBLE_MAT4)), None);
+        assert_eq!(readback_len(UniformType::from_gl(gl::FLOAT_MAT3x4)), None);
+        assert_eq!(readback_len(UniformType::from_gl(gl::FLOAT_MAT4)), Some(16));
+        assert_eq!(readback_len(UniformType::from_gl(gl::SAMPLER_2D)), Some(1));
+    }
+}
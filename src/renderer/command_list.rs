@@ -0,0 +1,82 @@
+use jobs;
+use super::DrawCommand;
+
+/// Draw commands recorded away from the renderer, typically on a worker thread,
+/// and queued with `Renderer::submit_list`
+#[derive(Default)]
+pub struct CommandList {
+    pub commands: Vec<DrawCommand>,
+}
+
+impl CommandList {
+    pub fn new() -> CommandList {
+        CommandList::default()
+    }
+
+    pub fn push(&mut self, command: DrawCommand) {
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Sorts by render state on the recording thread, so the renderer's
+    /// final sort only has to merge already sorted runs
+    pub fn sort(&mut self) {
+        self.commands.sort_by(|a, b| a.state_order(b));
+    }
+}
+
+/// Splits `items` in up to `threads` chunks and records each one into its own sorted list on
+/// the global job system
+pub(crate) fn record_parallel<T, F>(items: &[T], threads: usize, record: &F) -> Vec<CommandList>
+    where T: Sync, F: Fn(&T, &mut CommandList) + Sync
+{
+    if items.is_empty() {
+        return vec![];
+    }
+
+    let record_chunk = |chunk: &[T]| {
+        let mut list = CommandList::new();
+        for item in chunk {
+            record(item, &mut list);
+        }
+        list.sort();
+        list
+    };
+    jobs::global().map_chunks(items, threads, record_chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use lang::ObjectPar;
+    use renderer::{DrawCommand, Renderer};
+    use shader::Shader;
+
+    #[test]
+    fn records_on_worker_threads() {
+        let items: Vec<u32> = (0..100).collect();
+        let visible: ObjectPar<Vec<u32>> = Arc::new(Mutex::new(vec![]));
+
+        let mut renderer = Renderer::new();
+        renderer.record_parallel(&items, 4, |&item, list| {
+            // cull odd items
+            if item % 2 == 0 {
+                let visible = visible.clone();
+                list.push(DrawCommand::new(Shader { ID: item % 3 }, move |_, _| {
+                    visible.lock().unwrap().push(item);
+                }));
+            }
+        });
+
+        assert_eq!(renderer.queued(), 50);
+        assert!(visible.lock().unwrap().is_empty());
+    }
+}
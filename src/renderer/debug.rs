@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use gl;
+use serde::Serialize;
+
+use lang::Float;
+use gl_state::GlState;
+use shader::Shader;
+use super::MaterialId;
+
+const DEBUG_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec3 aNormal;
+
+out vec3 Normal;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+
+void main()
+{
+    Normal = mat3(transpose(inverse(model))) * aNormal;
+    gl_Position = projection * view * model * vec4(aPos, 1.0);
+}
+"#;
+
+const NORMALS_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+in vec3 Normal;
+
+void main()
+{
+    FragColor = vec4(normalize(Normal) * 0.5 + 0.5, 1.0);
+}
+"#;
+
+const OVERDRAW_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+void main()
+{
+    // added up per layer, goes from dark red over orange to white
+    FragColor = vec4(0.1, 0.04, 0.02, 1.0);
+}
+"#;
+
+const DEPTH_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform float near;
+uniform float far;
+
+void main()
+{
+    float ndc = gl_FragCoord.z * 2.0 - 1.0;
+    float linear = (2.0 * near * far) / (far + near - ndc * (far - near));
+    FragColor = vec4(vec3((linear - near) / (far - near)), 1.0);
+}
+"#;
+
+/// How the renderer draws a command, for diagnosing geometry without touching its shaders
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize)]
+pub enum DebugMode {
+    #[default]
+    Off,
+    /// the command's own shader with polygons drawn as lines
+    Wireframe,
+    /// world-space normals as colors
+    Normals,
+    /// additive heatmap of how often each pixel is drawn, ignoring depth
+    Overdraw,
+    /// linear depth between `DebugView::depth_range`, near is black
+    Depth,
+}
+
+struct DebugPrograms {
+    normals: Shader,
+    overdraw: Shader,
+    depth: Shader,
+}
+
+/// Debug render modes of a `Renderer`, switchable at runtime.
+///
+/// The replacement programs read position (location 0) and normal (1) and the `model`,
+/// `view` and `projection` uniforms, which the draw and view callbacks set on the shader
+/// they are given.
+pub struct DebugView {
+    /// mode for materials without their own
+    pub mode: DebugMode,
+    /// per-material modes, overriding `mode`
+    pub materials: HashMap<MaterialId, DebugMode>,
+    /// near and far distance of the `Depth` mode
+    pub depth_range: (Float, Float),
+    programs: Option<DebugPrograms>,
+}
+
+impl DebugView {
+    pub fn mode_for(&self, material: MaterialId) -> DebugMode {
+        self.materials.get(&material).cloned().unwrap_or(self.mode)
+    }
+
+    pub fn set_material_mode(&mut self, material: MaterialId, mode: DebugMode) {
+        self.materials.insert(material, mode);
+    }
+
+    /// The program drawing in `mode` instead of `shader`, built on first use
+    pub(crate) fn program(&mut self, mode: DebugMode, shader: Shader) -> Shader {
+        if mode == DebugMode::Off || mode == DebugMode::Wireframe {
+            return shader;
+        }
+        let programs = self.programs.get_or_insert_with(|| DebugPrograms {
+            normals: Shader::from_source(DEBUG_VERTEX_SHADER, NORMALS_FRAGMENT_SHADER),
+            overdraw: Shader::from_source(DEBUG_VERTEX_SHADER, OVERDRAW_FRAGMENT_SHADER),
+            depth: Shader::from_source(DEBUG_VERTEX_SHADER, DEPTH_FRAGMENT_SHADER),
+        });
+        match mode {
+            DebugMode::Normals => programs.normals,
+            DebugMode::Overdraw => programs.overdraw,
+            _ => programs.depth,
+        }
+    }
+
+    /// Called after `program` is put in use
+    pub(crate) fn bind_program(&self, mode: DebugMode, program: &Shader) {
+        if mode == DebugMode::Depth {
+            unsafe {
+                program.setFloat(c_str!("near"), self.depth_range.0);
+                program.setFloat(c_str!("far"), self.depth_range.1);
+            }
+        }
+    }
+
+    /// Sets the state of `mode` over the command's own
+    pub(crate) fn apply(&self, mode: DebugMode, state: &mut GlState) {
+        state.polygon_mode(if mode == DebugMode::Wireframe { gl::LINE } else { gl::FILL });
+        if mode == DebugMode::Overdraw {
+            state.set_depth_test(false);
+            state.set_blend(true);
+            state.blend_func(gl::ONE, gl::ONE);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if let Some(programs) = self.programs.take() {
+            unsafe {
+                gl::DeleteProgram(programs.normals.ID);
+                gl::DeleteProgram(programs.overdraw.ID);
+                gl::DeleteProgram(programs.depth.ID);
+            }
+        }
+    }
+}
+
+impl Default for DebugView {
+    fn default() -> DebugView {
+        DebugView {
+            mode: DebugMode::Off,
+            materials: HashMap::new(),
+            depth_range: (0.1, 100.0),
+            programs: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn material_modes_override_the_global_one() {
+        let mut debug = DebugView { mode: DebugMode::Wireframe, ..DebugView::default() };
+        debug.set_material_mode(3, DebugMode::Off);
+        assert_eq!(debug.mode_for(1), DebugMode::Wireframe);
+        assert_eq!(debug.mode_for(3), DebugMode::Off);
+        // no GL needed for the modes drawing with the command's shader
+        assert_eq!(debug.program(DebugMode::Wireframe, Shader { ID: 7 }), Shader { ID: 7 });
+    }
+}
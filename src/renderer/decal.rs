@@ -0,0 +1,333 @@
+use cgmath::prelude::*;
+use gl;
+
+use lang::{Float, Point3, Vector3, Matrix4, TimeSec, deg};
+use color::Color;
+use gl_state::GlState;
+use mesh::{primitives, Mesh};
+use renderer::SceneView;
+use renderer::depth_copy::DepthCopy;
+use shader::Shader;
+
+/// Identifies a decal added to `Decals`
+pub type DecalId = u64;
+
+const DECAL_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+
+uniform mat4 model;
+uniform mat4 viewProjection;
+
+void main()
+{
+    gl_Position = viewProjection * model * vec4(aPos, 1.0);
+}
+"#;
+
+const DECAL_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D sceneDepth;
+uniform sampler2D decalTexture;
+uniform mat4 inverseViewProjection;
+uniform mat4 inverseModel;
+uniform vec4 viewportRect;
+uniform vec4 decalColor;
+uniform vec3 decalUp;
+uniform float angleFade;
+
+void main()
+{
+    // world position of the opaque surface behind this pixel
+    float depth = texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r;
+    vec2 ndc = (gl_FragCoord.xy - viewportRect.xy) / viewportRect.zw * 2.0 - 1.0;
+    vec4 world = inverseViewProjection * vec4(ndc, depth * 2.0 - 1.0, 1.0);
+    world /= world.w;
+
+    vec3 local = (inverseModel * world).xyz;
+    if (any(greaterThan(abs(local), vec3(0.5))))
+        discard;
+
+    // surfaces turned away from the projection direction don't stretch the texture
+    vec3 normal = normalize(cross(dFdx(world.xyz), dFdy(world.xyz)));
+    float facing = dot(normal, decalUp);
+    float fade = smoothstep(angleFade, angleFade + 0.2, facing);
+
+    vec4 albedo = texture(decalTexture, local.xz + 0.5) * decalColor;
+    FragColor = vec4(albedo.rgb, albedo.a * fade);
+}
+"#;
+
+/// Texture projected onto the opaque geometry inside a box: `transform` takes the unit cube
+/// centered at the origin to the world, and the texture is projected along the box's -Y axis
+/// onto whatever the box encloses.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Decal {
+    pub transform: Matrix4,
+    pub texture: u32,
+    /// multiplies the texture, its alpha scales the opacity
+    pub color: Color,
+    /// decals of a higher layer are drawn over lower ones, within a layer newer ones on top
+    pub layer: i32,
+    /// seconds before it starts fading out, `None` keeps it until removed
+    pub lifetime: Option<TimeSec>,
+    /// seconds the fade out takes
+    pub fade_out: TimeSec,
+    /// cosine between the surface normal and the box's Y axis below which the decal fades out
+    pub angle_fade: Float,
+    age: TimeSec,
+}
+
+impl Decal {
+    pub fn new(transform: Matrix4, texture: u32) -> Decal {
+        Decal {
+            transform,
+            texture,
+            color: Color::WHITE,
+            layer: 0,
+            lifetime: None,
+            fade_out: 1.0,
+            angle_fade: 0.2,
+            age: 0.0,
+        }
+    }
+
+    /// `size` wide square decal on the surface at `point` facing `normal`, reaching `depth`
+    /// into and out of it, turned by `rotation` degrees around the normal
+    pub fn on_surface(point: Point3, normal: Vector3, size: Float, depth: Float, rotation: Float, texture: u32) -> Decal {
+        let up = normal.normalize();
+        let reference = if up.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+        let x = reference.cross(up).normalize();
+        let z = x.cross(up);
+        let basis = Matrix4::from_cols(x.extend(0.0), up.extend(0.0), z.extend(0.0), point.to_homogeneous());
+        let transform = basis * Matrix4::from_angle_y(deg(rotation))
+            * Matrix4::from_nonuniform_scale(size, depth, size);
+        Decal::new(transform, texture)
+    }
+
+    pub fn color(mut self, color: Color) -> Decal {
+        self.color = color;
+        self
+    }
+
+    pub fn layer(mut self, layer: i32) -> Decal {
+        self.layer = layer;
+        self
+    }
+
+    /// Fades the decal out over `fade_out` seconds after `lifetime`, then removes it
+    pub fn lifetime(mut self, lifetime: TimeSec, fade_out: TimeSec) -> Decal {
+        self.lifetime = Some(lifetime);
+        self.fade_out = fade_out;
+        self
+    }
+
+    pub fn angle_fade(mut self, angle_fade: Float) -> Decal {
+        self.angle_fade = angle_fade;
+        self
+    }
+
+    pub fn age(&self) -> TimeSec {
+        self.age
+    }
+
+    /// 1 until the lifetime ends, then down to 0 over the fade out
+    pub fn opacity(&self) -> Float {
+        match self.lifetime {
+            Some(lifetime) if self.age > lifetime => {
+                (1.0 - (self.age - lifetime) / self.fade_out.max(1e-6)).max(0.0) as Float
+            },
+            _ => 1.0,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.lifetime.is_some_and(|lifetime| self.age >= lifetime + self.fade_out)
+    }
+}
+
+/// Screen-space decals: after the opaque pass of each view the depth buffer is copied, and
+/// every decal's box is drawn reconstructing the world position of the geometry behind it
+/// from that depth. Set it as `Renderer::decals` to have it drawn by `render_views` before
+/// the blended commands.
+pub struct Decals {
+    /// the oldest decals are removed past this count, for bullet holes and the like
+    pub max_decals: usize,
+    /// sorted by layer, then by id
+    decals: Vec<(DecalId, Decal)>,
+    next_id: DecalId,
+    shader: Shader,
+    box_mesh: Mesh,
+    depth: DepthCopy,
+}
+
+impl Decals {
+    pub fn new() -> Decals {
+        let mut decals = Decals::with_capacity(256);
+        decals.shader = Shader::from_source(DECAL_VERTEX_SHADER, DECAL_FRAGMENT_SHADER);
+        decals.box_mesh = Mesh::new(&primitives::cube(1.0));
+        decals.depth = DepthCopy::new();
+        decals
+    }
+
+    /// The decal list without GL objects
+    fn with_capacity(max_decals: usize) -> Decals {
+        Decals {
+            max_decals,
+            decals: Vec::new(),
+            next_id: 0,
+            shader: Shader { ID: 0 },
+            box_mesh: Mesh::default(),
+            depth: DepthCopy::default(),
+        }
+    }
+
+    pub fn add(&mut self, decal: Decal) -> DecalId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let index = self.decals.partition_point(|(_, other)| other.layer <= decal.layer);
+        self.decals.insert(index, (id, decal));
+        if self.decals.len() > self.max_decals {
+            if let Some(oldest) = self.decals.iter().enumerate().min_by_key(|&(_, &(id, _))| id).map(|(i, _)| i) {
+                self.decals.remove(oldest);
+            }
+        }
+        id
+    }
+
+    pub fn get(&self, id: DecalId) -> Option<&Decal> {
+        self.decals.iter().find(|&&(other, _)| other == id).map(|(_, decal)| decal)
+    }
+
+    pub fn remove(&mut self, id: DecalId) -> bool {
+        let count = self.decals.len();
+        self.decals.retain(|&(other, _)| other != id);
+        self.decals.len() != count
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    /// Ids in drawing order
+    pub fn ids(&self) -> Vec<DecalId> {
+        self.decals.iter().map(|&(id, _)| id).collect()
+    }
+
+    /// Ages the decals and removes the ones that faded out
+    pub fn update(&mut self, delta_time: TimeSec) {
+        for (_, decal) in self.decals.iter_mut() {
+            decal.age += delta_time;
+        }
+        self.decals.retain(|(_, decal)| !decal.is_expired());
+    }
+
+    /// Copies the depth of the framebuffer being drawn into within the view's viewport, then
+    /// blends the decals over it. Leaves blending on, depth testing off and front faces culled.
+    pub fn draw(&mut self, state: &mut GlState, view: &SceneView) {
+        let view_projection = view.view_projection();
+        let inverse_view_projection = match view_projection.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        self.depth.copy(&view.viewport, "decal");
+
+        state.use_program(self.shader.ID);
+        state.set_blend(true);
+        state.blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        state.set_depth_test(false);
+        state.depth_mask(false);
+        // back faces still cover the screen when the camera is inside a box
+        state.set_cull_face(true);
+        state.cull_mode(gl::FRONT);
+        state.bind_texture(1, self.depth.texture);
+        let v = view.viewport;
+        unsafe {
+            self.shader.setInt(c_str!("decalTexture"), 0);
+            self.shader.setInt(c_str!("sceneDepth"), 1);
+            self.shader.setMat4(c_str!("viewProjection"), &view_projection);
+            self.shader.setMat4(c_str!("inverseViewProjection"), &inverse_view_projection);
+            self.shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+        }
+        for (_, decal) in &self.decals {
+            let inverse = match decal.transform.invert() {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+            let up = decal.transform.y.truncate().normalize();
+            let mut color = decal.color;
+            color.a *= decal.opacity();
+            state.bind_texture(0, decal.texture);
+            unsafe {
+                self.shader.setMat4(c_str!("model"), &decal.transform);
+                self.shader.setMat4(c_str!("inverseModel"), &inverse);
+                self.shader.setColor(c_str!("decalColor"), &color);
+                self.shader.setVec3(c_str!("decalUp"), up.x, up.y, up.z);
+                self.shader.setFloat(c_str!("angleFade"), decal.angle_fade);
+            }
+            self.box_mesh.draw_with_state(state);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        self.box_mesh.delete();
+        unsafe {
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.depth.delete();
+    }
+}
+
+impl Default for Decals {
+    fn default() -> Decals {
+        Decals::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decals_sort_evict_and_fade() {
+        let mut decals = Decals::with_capacity(3);
+        let unit = Matrix4::from_scale(1.0);
+        let road = decals.add(Decal::new(unit, 1).layer(-1));
+        let first = decals.add(Decal::new(unit, 2).lifetime(1.0, 2.0));
+        let second = decals.add(Decal::new(unit, 3));
+        assert_eq!(decals.ids(), vec![road, first, second]);
+
+        // the oldest decal goes, whatever its layer
+        let third = decals.add(Decal::new(unit, 4).layer(-1));
+        assert_eq!(decals.ids(), vec![third, first, second]);
+
+        decals.update(2.0);
+        assert_eq!(decals.get(first).unwrap().opacity(), 0.5);
+        assert_eq!(decals.get(second).unwrap().opacity(), 1.0);
+        decals.update(1.0);
+        assert!(decals.get(first).is_none());
+        assert!(decals.remove(third) && !decals.remove(third));
+        assert_eq!(decals.len(), 1);
+    }
+
+    #[test]
+    fn surface_decal_projects_along_the_normal() {
+        let decal = Decal::on_surface(Point3::new(1.0, 2.0, 3.0), Vector3::new(0.0, 0.0, 2.0), 2.0, 0.5, 30.0, 0);
+        let inverse = decal.transform.invert().unwrap();
+        let center = inverse.transform_point(Point3::new(1.0, 2.0, 3.0));
+        assert!(center.to_vec().magnitude() < 1e-5);
+        // a point just off the surface is near the middle of the box's height
+        let above = inverse.transform_point(Point3::new(1.0, 2.0, 3.2));
+        assert!((above.y - 0.4).abs() < 1e-5 && above.x.abs() < 1e-5 && above.z.abs() < 1e-5);
+    }
+}
@@ -0,0 +1,394 @@
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, Matrix4, Point3, Vector3};
+use atmosphere::{Fog, FOG_GLSL};
+use bounds::{BoundingSphere, Frustum};
+use color::Color;
+use gl_state::GlState;
+use logging;
+use renderer::SceneView;
+use renderer::ssao::SSAO_GLSL;
+use renderer::taa::allocate_target;
+use shader::Shader;
+use viewport::Viewport;
+
+/// Point lights `DeferredShading` shades per frame, the visible ones past it are dropped
+pub const MAX_POINT_LIGHTS: usize = 1024;
+
+/// `#include "reactor/gbuffer.glsl"`: the `DrawCommand::deferred` program of an opaque command
+/// calls `writeGBuffer` with its surface instead of writing a color output. `specular` scales
+/// the highlight, `roughness` in [0, 1] widens it and `occlusion` darkens the ambient light.
+pub const GBUFFER_GLSL: &str = r#"
+layout (location = 0) out vec4 GAlbedo;
+layout (location = 1) out vec4 GNormal;
+layout (location = 2) out vec4 GMaterial;
+
+void writeGBuffer(vec3 albedo, vec3 normal, float specular, float roughness, float occlusion)
+{
+    GAlbedo = vec4(albedo, 1.0);
+    GNormal = vec4(normalize(normal), 0.0);
+    GMaterial = vec4(specular, roughness, occlusion, 0.0);
+}
+"#;
+
+const LIGHTING_VERTEX_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+/// after the version line, `FOG_GLSL` and `SSAO_GLSL`
+const LIGHTING_FRAGMENT_BODY: &str = r#"
+out vec4 FragColor;
+
+uniform sampler2D gAlbedo;
+uniform sampler2D gNormal;
+uniform sampler2D gMaterial;
+uniform sampler2D gDepth;
+// row 0: position and radius, row 1: color times intensity
+uniform sampler2D pointLights;
+uniform int pointLightCount;
+uniform vec4 viewportRect;
+uniform mat4 inverseViewProjection;
+// direction the light travels in
+uniform vec3 lightDirection;
+uniform vec3 lightColor;
+uniform vec3 ambient;
+
+float attenuation(float distance, float radius)
+{
+    // inverse square, windowed to reach 0 at the radius
+    float window = clamp(1.0 - pow(distance / radius, 4.0), 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+vec3 shade(vec3 albedo, vec3 normal, vec3 toEye, vec3 toLight, vec3 radiance, vec4 material)
+{
+    float diffuse = max(dot(normal, toLight), 0.0);
+    float shininess = exp2(11.0 * (1.0 - material.g)) + 1.0;
+    float highlight = pow(max(dot(normal, normalize(toLight + toEye)), 0.0), shininess) * material.r;
+    return (albedo * diffuse + vec3(highlight * step(0.0, diffuse))) * radiance;
+}
+
+void main()
+{
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(gDepth, pixel, 0).r;
+    // nothing was drawn, keep the cleared background
+    if (depth >= 1.0)
+        discard;
+    vec2 ndc = (gl_FragCoord.xy - viewportRect.xy) / viewportRect.zw * 2.0 - 1.0;
+    vec4 world = inverseViewProjection * vec4(ndc, depth * 2.0 - 1.0, 1.0);
+    vec3 position = world.xyz / world.w;
+
+    vec3 albedo = texelFetch(gAlbedo, pixel, 0).rgb;
+    vec3 normal = normalize(texelFetch(gNormal, pixel, 0).xyz);
+    vec4 material = texelFetch(gMaterial, pixel, 0);
+    vec3 toEye = normalize(cameraPosition - position);
+
+    vec3 color = albedo * ambient * material.b * ambientOcclusion();
+    color += shade(albedo, normal, toEye, -normalize(lightDirection), lightColor, material);
+    for (int i = 0; i < pointLightCount; ++i) {
+        vec4 light = texelFetch(pointLights, ivec2(i, 0), 0);
+        vec3 toLight = light.xyz - position;
+        float distance = length(toLight);
+        if (distance >= light.w)
+            continue;
+        vec3 radiance = texelFetch(pointLights, ivec2(i, 1), 0).rgb * attenuation(distance, light.w);
+        color += shade(albedo, normal, toEye, toLight / max(distance, 1e-5), radiance, material);
+    }
+    FragColor = vec4(applyFog(color, position), 1.0);
+}
+"#;
+
+fn lighting_fragment_shader() -> String {
+    format!("#version 330 core\n{}{}{}", FOG_GLSL, SSAO_GLSL, LIGHTING_FRAGMENT_BODY)
+}
+
+/// Light shining in all directions from `position`, fading out to nothing at `radius`
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PointLight {
+    pub position: Point3,
+    pub color: Color,
+    pub intensity: Float,
+    pub radius: Float,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, color: Color, intensity: Float, radius: Float) -> PointLight {
+        PointLight { position, color, intensity, radius }
+    }
+
+    /// Fraction of the intensity reaching `distance`, as the lighting pass computes it
+    pub fn attenuation(&self, distance: Float) -> Float {
+        let window = (1.0 - (distance / self.radius).powi(4)).clamp(0.0, 1.0);
+        window * window / (distance * distance + 1.0)
+    }
+}
+
+/// Texels of the lights `frustum` can see, positions and radii then colors, one row each.
+/// Returns them with how many lights made it.
+fn pack_lights(lights: &[PointLight], frustum: &Frustum) -> (Vec<GLfloat>, usize) {
+    let visible: Vec<&PointLight> = lights.iter()
+        .filter(|light| light.radius > 0.0 && frustum.intersects_sphere(&BoundingSphere::new(light.position, light.radius)))
+        .take(MAX_POINT_LIGHTS)
+        .collect();
+    let mut texels = Vec::with_capacity(visible.len() * 8);
+    for light in visible.iter() {
+        let p = light.position;
+        texels.extend([p.x, p.y, p.z, light.radius].iter().map(|&v| v as GLfloat));
+    }
+    for light in visible.iter() {
+        let c = light.color;
+        texels.extend([c.r, c.g, c.b, 1.0].iter().map(|&v| (v * light.intensity) as GLfloat));
+    }
+    (texels, visible.len())
+}
+
+/// G-buffer and lighting pass of the deferred path, drawn while set as `Renderer::deferred`.
+/// Opaque commands with a `DrawCommand::deferred` program write their surface into the
+/// G-buffer, which is then lit by the directional light and every point light in view in one
+/// fullscreen pass, and its depth copied into the framebuffer. Everything else, the opaque
+/// commands without a deferred program and all the blended ones, is then drawn forward over
+/// the result as usual. The framebuffer must not be multisampled, for the depth copy.
+pub struct DeferredShading {
+    /// direction the light travels in
+    pub light_direction: Vector3,
+    pub light_color: Color,
+    pub ambient: Color,
+    pub lights: Vec<PointLight>,
+    pub fog: Fog,
+    fbo: u32,
+    /// RGBA8 albedo
+    albedo: u32,
+    /// RGBA16F world space normal
+    normal: u32,
+    /// RGBA8 specular, roughness and occlusion
+    material: u32,
+    /// DEPTH24_STENCIL8
+    depth: u32,
+    /// RGBA32F, see `pack_lights`
+    light_texture: u32,
+    size: (i32, i32),
+    shader: Shader,
+    vao: u32,
+}
+
+impl DeferredShading {
+    pub fn new() -> DeferredShading {
+        let mut deferred = DeferredShading {
+            light_direction: Vector3::new(-0.3, -1.0, -0.2),
+            light_color: Color::WHITE,
+            ambient: Color::linear(0.1, 0.1, 0.1, 1.0),
+            lights: vec![],
+            fog: Fog::default(),
+            fbo: 0,
+            albedo: 0,
+            normal: 0,
+            material: 0,
+            depth: 0,
+            light_texture: 0,
+            size: (0, 0),
+            shader: Shader::from_source(LIGHTING_VERTEX_SHADER, &lighting_fragment_shader()),
+            vao: 0,
+        };
+        unsafe {
+            gl::GenFramebuffers(1, &mut deferred.fbo);
+            gl::GenTextures(1, &mut deferred.albedo);
+            gl::GenTextures(1, &mut deferred.normal);
+            gl::GenTextures(1, &mut deferred.material);
+            gl::GenTextures(1, &mut deferred.depth);
+            gl::GenTextures(1, &mut deferred.light_texture);
+            gl::GenVertexArrays(1, &mut deferred.vao);
+        }
+        deferred
+    }
+
+    /// World space normals of the last frame's deferred surfaces, for screen-space effects like `Ssao`
+    pub fn normal_texture(&self) -> u32 {
+        self.normal
+    }
+
+    /// Depth of the last frame's deferred surfaces
+    pub fn depth_texture(&self) -> u32 {
+        self.depth
+    }
+
+    /// Binds and clears the G-buffer within `viewport`, returns the framebuffer drawn into before
+    pub(crate) fn begin(&mut self, state: &mut GlState, viewport: &Viewport) -> u32 {
+        let size = (viewport.x + viewport.width, viewport.y + viewport.height);
+        let mut framebuffer = 0;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            if size.0 > self.size.0 || size.1 > self.size.1 {
+                self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+                self.allocate();
+            }
+            // the textures were bound outside of the cache
+            state.invalidate();
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::DrawBuffers(3, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1, gl::COLOR_ATTACHMENT2].as_ptr());
+            state.color_mask(true);
+            state.depth_mask(true);
+            for attachment in 0..3 {
+                gl::ClearBufferfv(gl::COLOR, attachment, [0.0; 4].as_ptr());
+            }
+            gl::ClearBufferfi(gl::DEPTH_STENCIL, 0, 1.0, 0);
+        }
+        framebuffer as u32
+    }
+
+    unsafe fn allocate(&mut self) {
+        gl::BindTexture(gl::TEXTURE_2D, self.albedo);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, self.size.0, self.size.1, 0, gl::RGBA,
+                       gl::UNSIGNED_BYTE, ::std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        allocate_target(self.normal, gl::RGBA16F, self.size, gl::NEAREST);
+        gl::BindTexture(gl::TEXTURE_2D, self.material);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, self.size.0, self.size.1, 0, gl::RGBA,
+                       gl::UNSIGNED_BYTE, ::std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        gl::BindTexture(gl::TEXTURE_2D, self.depth);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH24_STENCIL8 as GLint, self.size.0, self.size.1, 0,
+                       gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8, ::std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.albedo, 0);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT1, gl::TEXTURE_2D, self.normal, 0);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT2, gl::TEXTURE_2D, self.material, 0);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, self.depth, 0);
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            engine_error!(logging::RENDERER, "g-buffer framebuffer is not complete: 0x{:x}", status);
+        }
+        engine_debug!(logging::RENDERER, "g-buffer of {}x{}", self.size.0, self.size.1);
+    }
+
+    /// Lights the G-buffer into `framebuffer`, the ambient light scaled by the `occlusion`
+    /// texture when there is one, and copies its depth there for the forward commands drawn
+    /// next. Leaves blending, depth testing and culling off.
+    pub(crate) fn end(&mut self, state: &mut GlState, view: &SceneView, framebuffer: u32, occlusion: Option<u32>) {
+        let v = view.viewport;
+        let view_projection = view.view_projection();
+        let (texels, count) = pack_lights(&self.lights, &Frustum::from_matrix(&view_projection));
+        if count < self.lights.len() {
+            engine_debug!(logging::RENDERER, "{} of {} point lights in view", count, self.lights.len());
+        }
+        let inverse_view_projection = view_projection.invert().unwrap_or_else(Matrix4::identity);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::BindTexture(gl::TEXTURE_2D, self.light_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA32F as GLint, count.max(1) as GLsizei, 2, 0, gl::RGBA,
+                           gl::FLOAT, if count > 0 { texels.as_ptr() as *const GLvoid } else { ::std::ptr::null() });
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+        }
+        // the texture was bound outside of the cache
+        state.invalidate();
+
+        let shader = self.shader;
+        state.use_program(shader.ID);
+        state.bind_vertex_array(self.vao);
+        let textures = [self.albedo, self.normal, self.material, self.depth, self.light_texture, occlusion.unwrap_or(0)];
+        for (unit, &texture) in textures.iter().enumerate() {
+            state.bind_texture(unit as u32, texture);
+        }
+        state.set_blend(false);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        state.color_mask(true);
+        self.fog.bind(&shader, view.camera.position);
+        unsafe {
+            shader.setInt(c_str!("gAlbedo"), 0);
+            shader.setInt(c_str!("gNormal"), 1);
+            shader.setInt(c_str!("gMaterial"), 2);
+            shader.setInt(c_str!("gDepth"), 3);
+            shader.setInt(c_str!("pointLights"), 4);
+            shader.setInt(c_str!("pointLightCount"), count as i32);
+            shader.setInt(c_str!("ssaoMap"), 5);
+            shader.setBool(c_str!("ssaoEnabled"), occlusion.is_some());
+            shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+            shader.setMat4(c_str!("inverseViewProjection"), &inverse_view_projection);
+            let d = self.light_direction;
+            shader.setVec3(c_str!("lightDirection"), d.x, d.y, d.z);
+            shader.setVec3(c_str!("lightColor"), self.light_color.r, self.light_color.g, self.light_color.b);
+            shader.setVec3(c_str!("ambient"), self.ambient.r, self.ambient.g, self.ambient.b);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+            let (x1, y1) = (v.x + v.width, v.y + v.height);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+            gl::BlitFramebuffer(v.x, v.y, x1, y1, v.x, v.y, x1, y1, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+        for unit in 0..textures.len() as u32 {
+            state.bind_texture(unit, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            for texture in [self.albedo, self.normal, self.material, self.depth, self.light_texture].iter() {
+                gl::DeleteTextures(1, texture);
+            }
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.fbo = 0;
+        self.size = (0, 0);
+    }
+}
+
+impl Default for DeferredShading {
+    fn default() -> DeferredShading {
+        DeferredShading::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Camera;
+    use renderer::DrawCommand;
+    use shader::preprocess::{Defines, Preprocessor};
+
+    #[test]
+    fn packs_the_point_lights_in_view() {
+        let light = |z: Float, radius: Float| PointLight::new(Point3::new(0.0, 0.0, z), Color::WHITE, 2.0, radius);
+        // the default camera looks down -Z from the origin
+        let camera = Camera::default();
+        let frustum = Frustum::from_matrix(&(camera.projection_matrix(800, 600) * camera.view_matrix()));
+        let (texels, count) = pack_lights(&[light(-5.0, 1.0), light(20.0, 1.0), light(3.0, 4.0), light(-5.0, 0.0)], &frustum);
+        // the light behind the camera reaches into view, the far one and the one without a radius don't
+        assert_eq!(count, 2);
+        assert_eq!(texels.len(), 16);
+        assert_eq!(&texels[..8], &[0.0, 0.0, -5.0, 1.0, 0.0, 0.0, 3.0, 4.0]);
+        assert_eq!(&texels[8..12], &[2.0, 2.0, 2.0, 2.0]);
+
+        let light = light(0.0, 10.0);
+        assert!((light.attenuation(0.0) - 1.0).abs() < 1e-6);
+        assert!(light.attenuation(5.0) < light.attenuation(4.0));
+        assert_eq!(light.attenuation(10.0), 0.0);
+        assert_eq!(light.attenuation(12.0), 0.0);
+
+        let command = DrawCommand::new(Shader { ID: 1 }, |_, _| {}).deferred(Shader { ID: 2 });
+        assert_eq!(command.deferred_shader, Some(Shader { ID: 2 }));
+        let source = "#version 330 core\n#include \"reactor/gbuffer.glsl\"\nvoid main() { writeGBuffer(vec3(1.0), vec3(0.0, 1.0, 0.0), 0.5, 0.5, 1.0); }\n";
+        let processed = Preprocessor::new().process("smoke.frag", source, &Defines::new()).unwrap();
+        assert!(processed.source.contains("out vec4 GMaterial;"));
+    }
+}
@@ -0,0 +1,64 @@
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use logging;
+use viewport::Viewport;
+
+/// Copy of the depth buffer being drawn into, for passes that read the scene's depth while
+/// drawing over it. Grows to the largest viewport it has copied.
+#[derive(Default, Debug)]
+pub(crate) struct DepthCopy {
+    fbo: u32,
+    pub texture: u32,
+    size: (i32, i32),
+}
+
+impl DepthCopy {
+    pub fn new() -> DepthCopy {
+        let mut copy = DepthCopy::default();
+        unsafe {
+            gl::GenFramebuffers(1, &mut copy.fbo);
+            gl::GenTextures(1, &mut copy.texture);
+        }
+        copy
+    }
+
+    /// Blits the depth within the viewport, `name` says which pass it is for in the log
+    pub fn copy(&mut self, v: &Viewport, name: &str) {
+        let size = (v.x + v.width, v.y + v.height);
+        unsafe {
+            if size.0 > self.size.0 || size.1 > self.size.1 {
+                self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+                gl::BindTexture(gl::TEXTURE_2D, self.texture);
+                gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH24_STENCIL8 as GLint, self.size.0, self.size.1,
+                               0, gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8, ptr::null());
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, self.texture, 0);
+                gl::DrawBuffer(gl::NONE);
+                gl::ReadBuffer(gl::NONE);
+                engine_debug!(logging::RENDERER, "{} depth copy of {}x{}", name, self.size.0, self.size.1);
+            }
+
+            let mut framebuffer = 0;
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer as u32);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.fbo);
+            gl::BlitFramebuffer(v.x, v.y, size.0, size.1, v.x, v.y, size.0, size.1,
+                                gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer as u32);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.texture);
+        }
+        *self = DepthCopy::default();
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+
+use gl;
+
+use lang::{Float, TimeSec};
+use gl_state::{ClearSpec, GlState};
+use logging;
+use render_target::RenderTarget;
+use viewport::Viewport;
+
+/// Frames of GPU queries in flight, results are read this many frames late
+const QUERY_FRAMES: usize = 3;
+
+/// Picks the render scale from recent GPU frame times: drops quickly when over budget, and
+/// climbs back slowly once there is headroom
+#[derive(Clone, PartialEq, Debug)]
+pub struct ResolutionController {
+    /// GPU time budget per frame in seconds
+    pub target_frame_time: TimeSec,
+    pub min_scale: Float,
+    pub max_scale: Float,
+    /// scale increase per adjustment when under budget
+    pub step: Float,
+    /// frames averaged before each adjustment
+    pub window: usize,
+    scale: Float,
+    history: VecDeque<TimeSec>,
+}
+
+impl ResolutionController {
+    pub fn new(target_frame_time: TimeSec) -> ResolutionController {
+        ResolutionController {
+            target_frame_time,
+            min_scale: 0.5,
+            max_scale: 1.0,
+            step: 0.05,
+            window: 10,
+            scale: 1.0,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Fraction of the output size, per axis, to render at
+    pub fn scale(&self) -> Float {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: Float) {
+        self.scale = scale.clamp(self.min_scale, self.max_scale);
+        self.history.clear();
+    }
+
+    /// Adds a measured GPU frame time, returns the (possibly changed) scale
+    pub fn update(&mut self, gpu_frame_time: TimeSec) -> Float {
+        self.history.push_back(gpu_frame_time);
+        if self.history.len() < self.window.max(1) {
+            return self.scale;
+        }
+        let average = self.history.iter().sum::<TimeSec>() / self.history.len() as TimeSec;
+        self.history.clear();
+
+        let scale = if average > self.target_frame_time {
+            // pixel count, and roughly the GPU time, goes with the square of the scale
+            self.scale * (self.target_frame_time / average).sqrt() as Float
+        } else if average < self.target_frame_time * 0.8 {
+            self.scale + self.step
+        } else {
+            self.scale
+        };
+        // quantized so small fluctuations don't cause visible changes every adjustment
+        let quantized = (scale / self.step + 1e-3).floor() * self.step;
+        let scale = quantized.clamp(self.min_scale, self.max_scale);
+        if scale != self.scale {
+            engine_debug!(logging::RENDERER, "render scale {:.2} -> {:.2} (gpu {:.2}ms)", self.scale, scale,
+                          average * 1000.0);
+            self.scale = scale;
+        }
+        self.scale
+    }
+}
+
+/// Renders the 3D scene into an offscreen target at a scale adjusted to the GPU frame time,
+/// then upscales it to the window. The target keeps the full output size and only a
+/// sub-rectangle is drawn into, so scale changes never reallocate.
+///
+/// Between `begin` and `end` draw the scene into `begin`'s viewport, use its aspect for the
+/// projection. UI drawn after `end` stays at full resolution.
+pub struct DynamicResolution {
+    pub controller: ResolutionController,
+    pub target: RenderTarget,
+    output: (i32, i32),
+    queries: [u32; QUERY_FRAMES],
+    query_frame: usize,
+    queries_issued: usize,
+}
+
+impl DynamicResolution {
+    pub fn new(width: i32, height: i32, samples: i32, target_frame_time: TimeSec) -> DynamicResolution {
+        let mut target = RenderTarget::new(width, height, samples);
+        target.clear = ClearSpec::default();
+        let mut queries = [0; QUERY_FRAMES];
+        unsafe {
+            gl::GenQueries(QUERY_FRAMES as i32, queries.as_mut_ptr());
+        }
+        DynamicResolution {
+            controller: ResolutionController::new(target_frame_time),
+            target,
+            output: (width, height),
+            queries,
+            query_frame: 0,
+            queries_issued: 0,
+        }
+    }
+
+    /// Call it when the window's framebuffer is resized
+    pub fn set_output_size(&mut self, width: i32, height: i32) {
+        self.output = (width, height);
+        self.target.resize(width, height);
+    }
+
+    /// Part of the target drawn into at the current scale
+    pub fn viewport(&self) -> Viewport {
+        let scale = self.controller.scale();
+        let (width, height) = self.output;
+        Viewport::full(((width as Float * scale).round() as i32).max(1),
+                       ((height as Float * scale).round() as i32).max(1))
+    }
+
+    /// Binds and clears the target, starts timing the frame and returns the viewport to draw into
+    pub fn begin(&mut self, state: &mut GlState) -> Viewport {
+        self.read_query();
+        self.target.begin(state);
+        let viewport = self.viewport();
+        viewport.apply();
+        unsafe {
+            gl::BeginQuery(gl::TIME_ELAPSED, self.queries[self.query_frame]);
+        }
+        viewport
+    }
+
+    /// Stops timing and upscales the drawn region to the default framebuffer
+    pub fn end(&mut self) {
+        unsafe {
+            gl::EndQuery(gl::TIME_ELAPSED);
+        }
+        self.query_frame = (self.query_frame + 1) % QUERY_FRAMES;
+        self.queries_issued += 1;
+
+        let (width, height) = self.output;
+        self.target.blit_region_to_screen(self.viewport(), width, height);
+        Viewport::full(width, height).apply();
+    }
+
+    /// feeds the oldest query's result to the controller, it belongs to the frame about to be reused
+    fn read_query(&mut self) {
+        if self.queries_issued < QUERY_FRAMES {
+            return;
+        }
+        let query = self.queries[self.query_frame];
+        let mut available = 0;
+        let mut elapsed: u64 = 0;
+        unsafe {
+            gl::GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+            if available == 0 {
+                return;
+            }
+            gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut elapsed);
+        }
+        self.controller.update(elapsed as TimeSec / 1e9);
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteQueries(QUERY_FRAMES as i32, self.queries.as_ptr());
+        }
+        self.target.delete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_down_when_over_budget_and_recovers() {
+        let mut controller = ResolutionController { window: 2, ..ResolutionController::new(1.0 / 60.0) };
+        for _ in 0..2 {
+            controller.update(1.0 / 30.0);
+        }
+        // twice the budget: sqrt(1/2) of the scale, quantized down
+        assert!((controller.scale() - 0.70).abs() < 1e-4);
+        for _ in 0..20 {
+            controller.update(1.0 / 30.0);
+        }
+        assert_eq!(controller.scale(), controller.min_scale);
+
+        for _ in 0..4 {
+            controller.update(1.0 / 200.0);
+        }
+        assert!((controller.scale() - 0.6).abs() < 1e-4);
+    }
+}
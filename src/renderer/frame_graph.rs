@@ -0,0 +1,428 @@
+use std::collections::BTreeSet;
+
+use error::{EngineError, EngineResult};
+use gl_state::{ClearSpec, GlState};
+use render_target::{ColorFormat, RenderTarget};
+use viewport::Viewport;
+
+/// Render target declared in a `FrameGraph`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResourceId(usize);
+
+/// Size, MSAA samples and color format of a transient target, targets with equal descs can
+/// share memory
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetDesc {
+    pub width: i32,
+    pub height: i32,
+    pub samples: i32,
+    pub format: ColorFormat,
+}
+
+impl TargetDesc {
+    pub fn new(width: i32, height: i32) -> TargetDesc {
+        TargetDesc { width, height, samples: 0, format: ColorFormat::Rgba8 }
+    }
+
+    pub fn samples(mut self, samples: i32) -> TargetDesc {
+        self.samples = samples;
+        self
+    }
+
+    pub fn format(mut self, format: ColorFormat) -> TargetDesc {
+        self.format = format;
+        self
+    }
+}
+
+enum Resource {
+    /// allocated by the graph for the frame, aliased with other transients
+    Transient(TargetDesc),
+    /// owned by the application, kept alive past the frame
+    Imported(RenderTarget),
+    /// the default framebuffer
+    Backbuffer(Viewport),
+}
+
+struct ResourceEntry {
+    name: String,
+    resource: Resource,
+}
+
+type PassFn<'a, C> = Box<dyn FnMut(&mut C, &PassResources) + 'a>;
+
+struct Pass<'a, C> {
+    name: String,
+    reads: Vec<ResourceId>,
+    target: Option<ResourceId>,
+    clear: Option<ClearSpec>,
+    /// runs even if nothing reads what it writes
+    side_effects: bool,
+    run: PassFn<'a, C>,
+}
+
+/// Textures and target a pass callback can use
+pub struct PassResources {
+    textures: Vec<(ResourceId, u32)>,
+    /// area of the bound framebuffer the pass draws into
+    pub viewport: Viewport,
+}
+
+impl PassResources {
+    /// Color texture of a target the pass declared it reads
+    pub fn texture(&self, id: ResourceId) -> u32 {
+        self.textures.iter().find(|&&(resource, _)| resource == id).map(|&(_, texture)| texture)
+            .expect("pass reads a resource it didn't declare")
+    }
+}
+
+/// Execution plan of a compiled graph, exposed for inspection and tests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledGraph {
+    /// pass indices in execution order, culled passes left out
+    pub order: Vec<usize>,
+    /// physical target slot of every transient resource, `None` for others and unused ones
+    pub slots: Vec<Option<usize>>,
+    pub slot_descs: Vec<TargetDesc>,
+}
+
+/// Frame graph: passes declare the targets they read and the one they draw into, the graph
+/// orders them, culls the ones nothing depends on, allocates transient targets, sharing
+/// them between passes whose lifetimes don't overlap, and binds and clears the target
+/// before each pass.
+///
+/// Build it every frame with `transient`, `import`, `backbuffer` and `pass`, then `execute`.
+/// `reset` drops the declarations but keeps the allocated targets for the next frame.
+/// Passes run with the `C` context given to `execute`, typically the `Renderer`.
+pub struct FrameGraph<'a, C> {
+    resources: Vec<ResourceEntry>,
+    passes: Vec<Pass<'a, C>>,
+    /// transient targets kept across frames
+    pool: Vec<RenderTarget>,
+}
+
+impl<'a, C> FrameGraph<'a, C> {
+    pub fn new() -> FrameGraph<'a, C> {
+        FrameGraph { resources: vec![], passes: vec![], pool: vec![] }
+    }
+
+    /// Target only alive during the frame
+    pub fn transient(&mut self, name: &str, desc: TargetDesc) -> ResourceId {
+        self.add_resource(name, Resource::Transient(desc))
+    }
+
+    /// Application owned target, e.g. the history buffer of a temporal effect. Passes
+    /// writing it are never culled.
+    pub fn import(&mut self, name: &str, target: RenderTarget) -> ResourceId {
+        self.add_resource(name, Resource::Imported(target))
+    }
+
+    /// Default framebuffer, drawn into the `viewport` area
+    pub fn backbuffer(&mut self, viewport: Viewport) -> ResourceId {
+        self.add_resource("backbuffer", Resource::Backbuffer(viewport))
+    }
+
+    pub fn pass(&mut self, name: &str) -> PassBuilder<'_, 'a, C> {
+        PassBuilder {
+            graph: self,
+            name: name.to_string(),
+            reads: vec![],
+            target: None,
+            clear: None,
+            side_effects: false,
+        }
+    }
+
+    pub fn resource_name(&self, id: ResourceId) -> &str {
+        &self.resources[id.0].name
+    }
+
+    /// Forgets passes and resources, keeps the transient targets' memory
+    pub fn reset(&mut self) {
+        self.resources.clear();
+        self.passes.clear();
+    }
+
+    /// Orders and culls the passes and assigns transients to target slots
+    pub fn compile(&self) -> EngineResult<CompiledGraph> {
+        let count = self.passes.len();
+        // a pass depends on every other pass drawing into what it reads, and passes drawing
+        // into the same target keep their declaration order
+        let mut dependencies = vec![BTreeSet::new(); count];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (other, writer) in self.passes.iter().enumerate() {
+                if other == index || writer.target.is_none() {
+                    continue;
+                }
+                if pass.reads.contains(&writer.target.unwrap()) || (writer.target == pass.target && other < index) {
+                    dependencies[index].insert(other);
+                }
+            }
+        }
+
+        let mut live = vec![false; count];
+        let mut stack: Vec<usize> = (0..count).filter(|&index| self.is_root(index)).collect();
+        while let Some(index) = stack.pop() {
+            if !live[index] {
+                live[index] = true;
+                stack.extend(dependencies[index].iter().cloned());
+            }
+        }
+
+        // Kahn's algorithm, picking the first declared ready pass for a stable order
+        let mut order = Vec::with_capacity(count);
+        let mut done = vec![false; count];
+        while order.len() < live.iter().filter(|&&alive| alive).count() {
+            let ready = (0..count).find(|&index| {
+                live[index] && !done[index] && dependencies[index].iter().all(|&dependency| done[dependency] || !live[dependency])
+            });
+            match ready {
+                Some(index) => {
+                    done[index] = true;
+                    order.push(index);
+                },
+                None => {
+                    let cycle: Vec<&str> = (0..count).filter(|&index| live[index] && !done[index])
+                        .map(|index| self.passes[index].name.as_str()).collect();
+                    return Err(EngineError::FrameGraph(format!("dependency cycle between passes {:?}", cycle)));
+                },
+            }
+        }
+
+        let (slots, slot_descs) = self.assign_slots(&order);
+        Ok(CompiledGraph { order, slots, slot_descs })
+    }
+
+    /// Runs the passes in order with `context`
+    pub fn execute(&mut self, context: &mut C) -> EngineResult<()> {
+        let compiled = self.compile()?;
+        self.allocate(&compiled);
+
+        let mut state = GlState::new();
+        for &index in &compiled.order {
+            let target = self.passes[index].target.map(|id| self.physical(&compiled, id));
+            let viewport = match target {
+                Some(Bound::Target(target)) => {
+                    target.bind();
+                    target.viewport()
+                },
+                Some(Bound::Backbuffer(viewport)) => {
+                    unsafe { ::gl::BindFramebuffer(::gl::FRAMEBUFFER, 0) };
+                    viewport.apply();
+                    viewport
+                },
+                None => Viewport::new(0, 0, 0, 0),
+            };
+            if let Some(clear) = self.passes[index].clear {
+                clear.apply(&mut state);
+            }
+
+            let textures = self.passes[index].reads.iter()
+                .filter_map(|&id| match self.physical(&compiled, id) {
+                    Bound::Target(target) => {
+                        target.resolve();
+                        Some((id, target.color_texture))
+                    },
+                    Bound::Backbuffer(_) => None,
+                })
+                .collect();
+            // resolving rebinds the default framebuffer
+            if let Some(Bound::Target(target)) = target {
+                target.bind();
+            }
+            let resources = PassResources { textures, viewport };
+            (self.passes[index].run)(context, &resources);
+        }
+        unsafe { ::gl::BindFramebuffer(::gl::FRAMEBUFFER, 0) };
+        Ok(())
+    }
+
+    /// Frees the pooled transient targets
+    pub fn delete(&mut self) {
+        for target in &mut self.pool {
+            target.delete();
+        }
+        self.pool.clear();
+    }
+
+    fn add_resource(&mut self, name: &str, resource: Resource) -> ResourceId {
+        self.resources.push(ResourceEntry { name: name.to_string(), resource });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// passes kept whatever reads them: drawing outside the graph or with side effects
+    fn is_root(&self, index: usize) -> bool {
+        let pass = &self.passes[index];
+        pass.side_effects || match pass.target {
+            Some(id) => !matches!(self.resources[id.0].resource, Resource::Transient(_)),
+            None => true,
+        }
+    }
+
+    /// greedy interval allocation: a slot is reused once its last pass ran
+    fn assign_slots(&self, order: &[usize]) -> (Vec<Option<usize>>, Vec<TargetDesc>) {
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (position, &index) in order.iter().enumerate() {
+            let pass = &self.passes[index];
+            for id in pass.reads.iter().chain(pass.target.iter()) {
+                let lifetime = lifetimes[id.0].get_or_insert((position, position));
+                lifetime.1 = position;
+            }
+        }
+
+        let mut transients: Vec<(usize, (usize, usize), TargetDesc)> = self.resources.iter().enumerate()
+            .filter_map(|(id, entry)| match (&entry.resource, lifetimes[id]) {
+                (&Resource::Transient(desc), Some(lifetime)) => Some((id, lifetime, desc)),
+                _ => None,
+            })
+            .collect();
+        transients.sort_by_key(|&(id, (first, _), _)| (first, id));
+
+        let mut slots = vec![None; self.resources.len()];
+        let mut slot_descs: Vec<TargetDesc> = vec![];
+        let mut slot_free_after: Vec<usize> = vec![];
+        for (id, (first, last), desc) in transients {
+            let reusable = (0..slot_descs.len()).find(|&slot| slot_descs[slot] == desc && slot_free_after[slot] < first);
+            let slot = reusable.unwrap_or_else(|| {
+                slot_descs.push(desc);
+                slot_free_after.push(0);
+                slot_descs.len() - 1
+            });
+            slot_free_after[slot] = last;
+            slots[id] = Some(slot);
+        }
+        (slots, slot_descs)
+    }
+
+    /// matches the pool to the slots, creating and resizing targets as needed
+    fn allocate(&mut self, compiled: &CompiledGraph) {
+        for (slot, desc) in compiled.slot_descs.iter().enumerate() {
+            match self.pool.get_mut(slot) {
+                Some(target) => {
+                    target.resize(desc.width, desc.height);
+                    target.set_samples(desc.samples);
+                    target.set_format(desc.format);
+                },
+                None => self.pool.push(RenderTarget::with_format(desc.width, desc.height, desc.samples, desc.format)),
+            }
+        }
+    }
+
+    fn physical(&self, compiled: &CompiledGraph, id: ResourceId) -> Bound {
+        match self.resources[id.0].resource {
+            Resource::Transient(_) => Bound::Target(self.pool[compiled.slots[id.0].expect("transient has no slot")]),
+            Resource::Imported(target) => Bound::Target(target),
+            Resource::Backbuffer(viewport) => Bound::Backbuffer(viewport),
+        }
+    }
+}
+
+impl<'a, C> Default for FrameGraph<'a, C> {
+    fn default() -> FrameGraph<'a, C> {
+        FrameGraph::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Bound {
+    Target(RenderTarget),
+    Backbuffer(Viewport),
+}
+
+/// Declares a pass, added to the graph by `execute`
+pub struct PassBuilder<'g, 'a: 'g, C: 'g> {
+    graph: &'g mut FrameGraph<'a, C>,
+    name: String,
+    reads: Vec<ResourceId>,
+    target: Option<ResourceId>,
+    clear: Option<ClearSpec>,
+    side_effects: bool,
+}
+
+impl<'g, 'a, C> PassBuilder<'g, 'a, C> {
+    /// Samples the color texture of `resource`
+    pub fn read(mut self, resource: ResourceId) -> Self {
+        self.reads.push(resource);
+        self
+    }
+
+    /// Draws into `resource`, a pass has at most one target
+    pub fn write(mut self, resource: ResourceId) -> Self {
+        self.target = Some(resource);
+        self
+    }
+
+    /// Clears the target before the pass runs
+    pub fn clear(mut self, clear: ClearSpec) -> Self {
+        self.clear = Some(clear);
+        self
+    }
+
+    /// Never culled, for passes doing work outside their target (readbacks, queries)
+    pub fn side_effects(mut self) -> Self {
+        self.side_effects = true;
+        self
+    }
+
+    pub fn execute<F: FnMut(&mut C, &PassResources) + 'a>(self, run: F) {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            reads: self.reads,
+            target: self.target,
+            clear: self.clear,
+            side_effects: self.side_effects,
+            run: Box::new(run),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_culls_and_aliases_passes() {
+        let mut graph: FrameGraph<()> = FrameGraph::new();
+        let desc = TargetDesc::new(256, 256);
+        let back = graph.backbuffer(Viewport::full(256, 256));
+        let (scene, bright, blur, bloom, unused) = (graph.transient("scene", desc), graph.transient("bright", desc),
+                                                    graph.transient("blur", desc), graph.transient("bloom", desc),
+                                                    graph.transient("unused", desc));
+        // declared out of order on purpose
+        graph.pass("composite").read(scene).read(bloom).write(back).execute(|_, _| {});
+        graph.pass("blur y").read(blur).write(bloom).execute(|_, _| {});
+        graph.pass("blur x").read(bright).write(blur).execute(|_, _| {});
+        graph.pass("bright").read(scene).write(bright).execute(|_, _| {});
+        graph.pass("scene").write(scene).execute(|_, _| {});
+        graph.pass("debug").write(unused).execute(|_, _| {});
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.order, vec![4, 3, 2, 1, 0]);
+        // bright is dead once blur x ran, so bloom reuses its slot; scene lives until the composite
+        assert_eq!(compiled.slot_descs.len(), 3);
+        assert_eq!(compiled.slots[bright.0], compiled.slots[bloom.0]);
+        assert_ne!(compiled.slots[scene.0], compiled.slots[blur.0]);
+        assert_eq!(compiled.slots[unused.0], None);
+
+        graph.pass("feedback").read(back).write(scene).side_effects().execute(|_, _| {});
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn only_equal_formats_alias() {
+        let mut graph: FrameGraph<()> = FrameGraph::new();
+        let desc = TargetDesc::new(64, 64);
+        let back = graph.backbuffer(Viewport::full(64, 64));
+        let (a, b, c) = (graph.transient("a", desc), graph.transient("b", desc),
+                         graph.transient("c", desc.format(ColorFormat::Rgba16F)));
+        graph.pass("a").write(a).execute(|_, _| {});
+        graph.pass("b").read(a).write(b).execute(|_, _| {});
+        graph.pass("c").read(b).write(c).execute(|_, _| {});
+        graph.pass("present").read(c).write(back).execute(|_, _| {});
+
+        // a is dead when c is written, but a half float target can't reuse its memory
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.slot_descs.len(), 3);
+        assert_ne!(compiled.slots[a.0], compiled.slots[c.0]);
+    }
+}
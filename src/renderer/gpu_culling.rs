@@ -0,0 +1,484 @@
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+
+use cgmath;
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{gl_float, gl_matrix4, Float, Matrix4, Vector3, Vector4};
+use bounds::{Aabb, Frustum};
+use compute::{dispatch, group_count, Barriers, StorageBuffer};
+use gl_state::GlState;
+use logging;
+use mesh::pool::MeshRange;
+use renderer::SceneView;
+use renderer::depth_copy::DepthCopy;
+use shader::Shader;
+use super::indirect::{DrawElementsIndirectCommand, DrawIndirectBuffer};
+
+const CULL_GROUP_SIZE: usize = 64;
+const HI_Z_GROUP_SIZE: usize = 8;
+
+const CULL_COMPUTE_SHADER: &str = r#"
+#version 430 core
+layout (local_size_x = 64) in;
+
+struct Instance {
+    mat4 transform;
+    vec4 boundsMin;
+    vec4 boundsMax;
+    uint draw;
+};
+
+struct Command {
+    uint count;
+    uint instanceCount;
+    uint firstIndex;
+    int baseVertex;
+    uint baseInstance;
+};
+
+layout (std430, binding = 0) readonly buffer Instances { Instance instances[]; };
+layout (std430, binding = 1) buffer Commands { Command commands[]; };
+layout (std430, binding = 2) writeonly buffer Visible { uint visible[]; };
+
+uniform vec4 frustum[6];
+uniform uint instanceCount;
+uniform bool occlusion;
+uniform sampler2D hiZ;
+uniform mat4 hiZViewProjection;
+uniform int hiZLevels;
+
+// whether the world-space box is behind the depth of the pyramid
+bool occluded(vec3 boundsMin, vec3 boundsMax)
+{
+    vec3 ndcMin = vec3(1.0);
+    vec3 ndcMax = vec3(-1.0);
+    for (int i = 0; i < 8; i++) {
+        vec3 corner = mix(boundsMin, boundsMax, vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1));
+        vec4 clip = hiZViewProjection * vec4(corner, 1.0);
+        // boxes crossing the camera plane can't be projected
+        if (clip.w <= 0.0)
+            return false;
+        ndcMin = min(ndcMin, clip.xyz / clip.w);
+        ndcMax = max(ndcMax, clip.xyz / clip.w);
+    }
+    vec2 uvMin = clamp(ndcMin.xy * 0.5 + 0.5, 0.0, 1.0);
+    vec2 uvMax = clamp(ndcMax.xy * 0.5 + 0.5, 0.0, 1.0);
+
+    // the level where the rectangle spans at most 2x2 texels
+    vec2 extent = (uvMax - uvMin) * vec2(textureSize(hiZ, 0));
+    int level = clamp(int(ceil(log2(max(max(extent.x, extent.y), 1.0)))), 0, hiZLevels - 1);
+    ivec2 size = textureSize(hiZ, level);
+    ivec2 a = min(ivec2(uvMin * vec2(size)), size - 1);
+    ivec2 b = min(ivec2(uvMax * vec2(size)), size - 1);
+    float farthest = max(max(texelFetch(hiZ, a, level).r, texelFetch(hiZ, ivec2(b.x, a.y), level).r),
+                         max(texelFetch(hiZ, ivec2(a.x, b.y), level).r, texelFetch(hiZ, b, level).r));
+    return ndcMin.z * 0.5 + 0.5 > farthest;
+}
+
+void main()
+{
+    uint i = gl_GlobalInvocationID.x;
+    if (i >= instanceCount)
+        return;
+    Instance instance = instances[i];
+
+    // world-space box around the transformed local one
+    vec3 center = (instance.transform * vec4((instance.boundsMin.xyz + instance.boundsMax.xyz) * 0.5, 1.0)).xyz;
+    vec3 halfSize = (instance.boundsMax.xyz - instance.boundsMin.xyz) * 0.5;
+    mat3 m = mat3(instance.transform);
+    vec3 extent = abs(m[0]) * halfSize.x + abs(m[1]) * halfSize.y + abs(m[2]) * halfSize.z;
+
+    for (int p = 0; p < 6; p++) {
+        if (dot(frustum[p].xyz, center) + frustum[p].w + dot(abs(frustum[p].xyz), extent) < 0.0)
+            return;
+    }
+    if (occlusion && occluded(center - extent, center + extent))
+        return;
+
+    uint slot = atomicAdd(commands[instance.draw].instanceCount, 1u);
+    visible[commands[instance.draw].baseInstance + slot] = i;
+}
+"#;
+
+const HI_Z_COMPUTE_SHADER: &str = r#"
+#version 430 core
+layout (local_size_x = 8, local_size_y = 8) in;
+
+layout (r32f, binding = 0) uniform writeonly image2D destination;
+uniform sampler2D sceneDepth;
+uniform sampler2D previous;
+uniform ivec2 offset;
+uniform ivec2 size;
+uniform int level;
+
+void main()
+{
+    ivec2 p = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(p, size)))
+        return;
+    float depth = 0.0;
+    if (level == 0) {
+        depth = texelFetch(sceneDepth, p + offset, 0).r;
+    } else {
+        // odd sizes fold their last row or column into the last texel
+        ivec2 source = textureSize(previous, level - 1);
+        ivec2 last = source - 1;
+        int width = p.x == size.x - 1 && source.x % 2 == 1 ? 3 : 2;
+        int height = p.y == size.y - 1 && source.y % 2 == 1 ? 3 : 2;
+        for (int y = 0; y < height; y++)
+            for (int x = 0; x < width; x++)
+                depth = max(depth, texelFetch(previous, min(p * 2 + ivec2(x, y), last), level - 1).r);
+    }
+    imageStore(destination, p, vec4(depth));
+}
+"#;
+
+/// One instance as the culling pass reads it from the GPU, `std430` layout
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct GpuInstance {
+    /// local to world, column major
+    pub transform: [[f32; 4]; 4],
+    /// bounds of the mesh in local space, `w` unused
+    pub bounds_min: [f32; 4],
+    pub bounds_max: [f32; 4],
+    /// index of the instance's mesh in the ranges passed to `GpuCuller::set_instances`
+    pub draw: u32,
+    padding: [u32; 3],
+}
+
+impl GpuInstance {
+    pub fn new(transform: &Matrix4, bounds: &Aabb, draw: u32) -> GpuInstance {
+        GpuInstance {
+            transform: gl_matrix4(transform).into(),
+            bounds_min: [gl_float(bounds.min.x), gl_float(bounds.min.y), gl_float(bounds.min.z), 0.0],
+            bounds_max: [gl_float(bounds.max.x), gl_float(bounds.max.y), gl_float(bounds.max.z), 0.0],
+            draw,
+            padding: [0; 3],
+        }
+    }
+
+    /// World-space bounds the way the culling shader computes them
+    pub fn world_bounds(&self) -> Aabb {
+        let point = |p: &[f32; 4]| cgmath::Point3::new(p[0], p[1], p[2]).cast::<Float>().unwrap();
+        let transform: Matrix4 = cgmath::Matrix4::from(self.transform).cast().unwrap();
+        let (min, max) = (point(&self.bounds_min), point(&self.bounds_max));
+        let center = transform.transform_point(min.midpoint(max));
+        let half_size = (max - min) * 0.5;
+        let abs = |v: Vector4| Vector3::new(v.x.abs(), v.y.abs(), v.z.abs());
+        let extent = abs(transform.x) * half_size.x + abs(transform.y) * half_size.y + abs(transform.z) * half_size.z;
+        Aabb::new(center - extent, center + extent)
+    }
+}
+
+/// One command per mesh with room for all of its instances: the `base_instance` are the
+/// prefix sums of the instance counts, which start at zero for the pass to fill in
+fn instance_slots(ranges: &[MeshRange], instances: &[GpuInstance]) -> Vec<DrawElementsIndirectCommand> {
+    let mut counts = vec![0u32; ranges.len()];
+    for instance in instances {
+        counts[instance.draw as usize] += 1;
+    }
+    let mut base_instance = 0;
+    ranges.iter().zip(counts)
+        .map(|(range, count)| {
+            let command = DrawElementsIndirectCommand::new(range, 0, base_instance);
+            base_instance += count;
+            command
+        })
+        .collect()
+}
+
+/// Max-depth mip pyramid of a view's depth buffer, the farthest depth of each texel's area
+#[derive(Debug)]
+pub struct HiZBuffer {
+    pub texture: u32,
+    pub width: i32,
+    pub height: i32,
+    pub levels: i32,
+    /// camera the pyramid was built with, boxes are projected with it
+    pub view_projection: Matrix4,
+    depth: DepthCopy,
+    shader: Shader,
+}
+
+impl HiZBuffer {
+    fn new() -> HiZBuffer {
+        HiZBuffer {
+            texture: 0,
+            width: 0,
+            height: 0,
+            levels: 0,
+            view_projection: Matrix4::identity(),
+            depth: DepthCopy::new(),
+            shader: Shader::compute(HI_Z_COMPUTE_SHADER),
+        }
+    }
+
+    fn build(&mut self, state: &mut GlState, view: &SceneView) {
+        let v = view.viewport;
+        if (v.width, v.height) != (self.width, self.height) {
+            unsafe {
+                gl::DeleteTextures(1, &self.texture);
+                self.width = v.width;
+                self.height = v.height;
+                self.levels = 32 - (v.width.max(v.height).max(1) as u32).leading_zeros() as i32;
+                gl::GenTextures(1, &mut self.texture);
+                gl::BindTexture(gl::TEXTURE_2D, self.texture);
+                gl::TexStorage2D(gl::TEXTURE_2D, self.levels, gl::R32F, v.width, v.height);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as GLint);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+            }
+            engine_debug!(logging::RENDERER, "hi-z pyramid of {}x{}, {} levels", v.width, v.height, self.levels);
+        }
+        self.depth.copy(&v, "hi-z");
+        self.view_projection = view.view_projection();
+
+        state.use_program(self.shader.ID);
+        state.bind_texture(0, self.depth.texture);
+        state.bind_texture(1, self.texture);
+        unsafe {
+            self.shader.setInt(c_str!("sceneDepth"), 0);
+            self.shader.setInt(c_str!("previous"), 1);
+            gl::Uniform2i(gl::GetUniformLocation(self.shader.ID, c_str!("offset").as_ptr()), v.x, v.y);
+            let (mut width, mut height) = (self.width, self.height);
+            for level in 0..self.levels {
+                gl::BindImageTexture(0, self.texture, level, gl::FALSE, 0, gl::WRITE_ONLY, gl::R32F);
+                self.shader.setInt(c_str!("level"), level);
+                gl::Uniform2i(gl::GetUniformLocation(self.shader.ID, c_str!("size").as_ptr()), width, height);
+                dispatch(group_count(width as usize, HI_Z_GROUP_SIZE), group_count(height as usize, HI_Z_GROUP_SIZE), 1);
+                (Barriers::TEXTURE_FETCH | Barriers::IMAGE_ACCESS).wait();
+                width = (width / 2).max(1);
+                height = (height / 2).max(1);
+            }
+        }
+    }
+
+    fn delete(&mut self) {
+        self.depth.delete();
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.texture = 0;
+    }
+}
+
+/// Frustum culling, and optionally Hi-Z occlusion culling, of many instances in a compute pass.
+///
+/// Instances of the meshes of a `MeshPool` are culled into `commands`, one command per mesh
+/// whose instance count the pass writes; the indices of the surviving instances are written
+/// to a buffer read by the vertex shader as an instanced attribute (`bind_instance_attribute`),
+/// which indexes the instance storage buffer (`bind_instances`). `cull` runs before
+/// `Renderer::render` and `DrawCommand::multi_draw(shader, pool, &culler.commands)` draws the
+/// result without the CPU touching a single instance.
+///
+/// With `occlusion` set, `build_hi_z` has to run after the opaque pass of every frame; boxes
+/// are tested against the pyramid built last, so newly revealed objects appear a frame late.
+pub struct GpuCuller {
+    pub commands: DrawIndirectBuffer,
+    pub occlusion: bool,
+    instances: Vec<GpuInstance>,
+    instance_buffer: StorageBuffer,
+    visible: StorageBuffer,
+    /// vertex array and location the visible indices are bound to
+    attribute: Option<(u32, u32)>,
+    hi_z: Option<HiZBuffer>,
+    shader: Shader,
+}
+
+impl GpuCuller {
+    pub fn new() -> GpuCuller {
+        GpuCuller {
+            commands: DrawIndirectBuffer::new(),
+            occlusion: false,
+            instances: Vec::new(),
+            instance_buffer: StorageBuffer::default(),
+            visible: StorageBuffer::default(),
+            attribute: None,
+            hi_z: None,
+            shader: Shader::compute(CULL_COMPUTE_SHADER),
+        }
+    }
+
+    pub fn occlusion(mut self, occlusion: bool) -> GpuCuller {
+        self.occlusion = occlusion;
+        self
+    }
+
+    /// Replaces the instances, `ranges` are the meshes their `draw` refers to
+    pub fn set_instances(&mut self, ranges: &[MeshRange], instances: &[GpuInstance]) {
+        assert!(instances.iter().all(|instance| (instance.draw as usize) < ranges.len()), "instance of an unknown mesh");
+        self.commands.clear();
+        for command in instance_slots(ranges, instances) {
+            self.commands.push(command);
+        }
+        self.instances = instances.to_vec();
+
+        let size = mem::size_of_val(instances);
+        if size > self.instance_buffer.size {
+            self.instance_buffer.delete();
+            self.visible.delete();
+            self.instance_buffer = StorageBuffer::with_data(instances);
+            self.visible = StorageBuffer::new(instances.len() * mem::size_of::<u32>());
+            if let Some((vao, location)) = self.attribute {
+                self.bind_instance_attribute(vao, location);
+            }
+            engine_debug!(logging::RENDERER, "gpu culling of {} instances", instances.len());
+        } else {
+            self.instance_buffer.upload(instances);
+        }
+    }
+
+    /// Overwrites instances starting at `first`, e.g. of moving objects; their `draw` must stay
+    pub fn update_instances(&mut self, first: usize, instances: &[GpuInstance]) {
+        let previous = &mut self.instances[first..first + instances.len()];
+        debug_assert!(previous.iter().zip(instances).all(|(a, b)| a.draw == b.draw), "instances changed mesh");
+        previous.copy_from_slice(instances);
+        unsafe {
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.instance_buffer.buffer);
+            gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, (first * mem::size_of::<GpuInstance>()) as GLintptr,
+                              mem::size_of_val(instances) as GLsizeiptr, instances.as_ptr() as *const GLvoid);
+            gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+    }
+
+    pub fn instances(&self) -> &[GpuInstance] {
+        &self.instances
+    }
+
+    /// Feeds the indices of the visible instances to `location` of the vertex array as a `uint`
+    /// attribute advancing per instance, `base_instance` offsets it for every mesh
+    pub fn bind_instance_attribute(&mut self, vao: u32, location: u32) {
+        self.attribute = Some((vao, location));
+        unsafe {
+            let mut previous = 0;
+            gl::GetIntegerv(gl::VERTEX_ARRAY_BINDING, &mut previous);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.visible.buffer);
+            gl::EnableVertexAttribArray(location);
+            gl::VertexAttribIPointer(location, 1, gl::UNSIGNED_INT, mem::size_of::<u32>() as GLsizei, ptr::null());
+            gl::VertexAttribDivisor(location, 1);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(previous as u32);
+        }
+    }
+
+    /// `layout(std430, binding = index)` buffer of `GpuInstance` for the vertex shader
+    pub fn bind_instances(&self, index: u32) {
+        self.instance_buffer.bind(index);
+    }
+
+    /// Builds the Hi-Z pyramid from the depth drawn so far within the view's viewport
+    pub fn build_hi_z(&mut self, state: &mut GlState, view: &SceneView) {
+        self.hi_z.get_or_insert_with(HiZBuffer::new).build(state, view);
+    }
+
+    pub fn hi_z(&self) -> Option<&HiZBuffer> {
+        self.hi_z.as_ref()
+    }
+
+    /// Resets the instance counts and culls every instance against the frustum of
+    /// `view_projection`, then waits for the results to be usable as draw arguments
+    pub fn cull(&mut self, state: &mut GlState, view_projection: &Matrix4) {
+        if self.instances.is_empty() {
+            return;
+        }
+        self.commands.upload();
+
+        state.use_program(self.shader.ID);
+        let frustum = Frustum::from_matrix(view_projection);
+        let hi_z = self.hi_z.as_ref().filter(|_| self.occlusion);
+        unsafe {
+            for (i, plane) in frustum.planes.iter().enumerate() {
+                let name = CString::new(format!("frustum[{}]", i)).unwrap();
+                let plane = plane.to_vector4();
+                self.shader.setVec4(&name, plane.x, plane.y, plane.z, plane.w);
+            }
+            self.shader.setUint(c_str!("instanceCount"), self.instances.len() as u32);
+            self.shader.setBool(c_str!("occlusion"), hi_z.is_some());
+            if let Some(hi_z) = hi_z {
+                state.bind_texture(0, hi_z.texture);
+                self.shader.setInt(c_str!("hiZ"), 0);
+                self.shader.setInt(c_str!("hiZLevels"), hi_z.levels);
+                self.shader.setMat4(c_str!("hiZViewProjection"), &hi_z.view_projection);
+            }
+        }
+        self.instance_buffer.bind(0);
+        self.commands.bind_storage(1);
+        self.visible.bind(2);
+        dispatch(group_count(self.instances.len(), CULL_GROUP_SIZE), 1, 1);
+        (Barriers::COMMAND | Barriers::VERTEX_ATTRIB | Barriers::SHADER_STORAGE).wait();
+    }
+
+    /// Instances that survived the last `cull`, per mesh. Reads the counts back, which stalls
+    /// until the pass is done: for statistics and debugging only.
+    pub fn visible_counts(&self) -> Vec<u32> {
+        let mut commands = vec![DrawElementsIndirectCommand::default(); self.commands.len()];
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.commands.buffer);
+            gl::GetBufferSubData(gl::DRAW_INDIRECT_BUFFER, 0, mem::size_of_val(&commands[..]) as GLsizeiptr,
+                                 commands.as_mut_ptr() as *mut GLvoid);
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        }
+        commands.iter().map(|command| command.instance_count).collect()
+    }
+
+    pub fn delete(&mut self) {
+        self.commands.delete();
+        self.instance_buffer.delete();
+        self.visible.delete();
+        if let Some(ref mut hi_z) = self.hi_z {
+            hi_z.delete();
+        }
+        self.hi_z = None;
+        unsafe {
+            gl::DeleteProgram(self.shader.ID);
+        }
+    }
+}
+
+impl Default for GpuCuller {
+    fn default() -> GpuCuller {
+        GpuCuller::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::{deg, Point3};
+
+    #[test]
+    fn instance_layout_and_slots() {
+        // mat4, two vec4s and a uint padded to the vec4 alignment
+        assert_eq!(mem::size_of::<GpuInstance>(), 112);
+
+        let bounds = Aabb::new(Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 2.0, 1.0));
+        let ranges = [MeshRange { first_index: 0, index_count: 36, base_vertex: 0 },
+                      MeshRange { first_index: 36, index_count: 6, base_vertex: 24 }];
+        let instances: Vec<GpuInstance> = [1, 0, 1, 1].iter()
+            .map(|&draw| GpuInstance::new(&Matrix4::identity(), &bounds, draw))
+            .collect();
+        let commands = instance_slots(&ranges, &instances);
+        assert_eq!(commands.iter().map(|c| (c.instance_count, c.base_instance)).collect::<Vec<_>>(),
+                   vec![(0, 0), (0, 1)]);
+        assert_eq!(commands[1].first_index, 36);
+    }
+
+    #[test]
+    fn world_bounds_match_the_transformed_box() {
+        let bounds = Aabb::new(Point3::new(-1.0, 0.0, -0.5), Point3::new(1.0, 2.0, 0.5));
+        let transform = Matrix4::from_translation(Vector3::new(3.0, -1.0, 2.0))
+            * Matrix4::from_angle_y(deg(30.0))
+            * Matrix4::from_scale(2.0);
+        let expected = bounds.transform(&transform);
+        let actual = GpuInstance::new(&transform, &bounds, 0).world_bounds();
+        assert!((actual.min - expected.min).magnitude() < 1e-4 && (actual.max - expected.max).magnitude() < 1e-4,
+                "{:?} != {:?}", actual, expected);
+    }
+}
@@ -0,0 +1,454 @@
+use gl;
+
+use lang::{Float, TimeSec};
+use gl_state::GlState;
+use logging;
+use renderer::taa::allocate_target;
+use shader::Shader;
+use viewport::Viewport;
+
+const FULLSCREEN_VERTEX_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+const BRIGHT_PASS_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform vec4 viewportRect;
+uniform float threshold;
+uniform float knee;
+
+void main()
+{
+    // each half resolution pixel averages a 2x2 block of the scene
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    ivec2 base = lo + ivec2(gl_FragCoord.xy) * 2;
+    vec3 color = vec3(0.0);
+    for (int i = 0; i < 4; ++i)
+        color += texelFetch(scene, clamp(base + ivec2(i & 1, i >> 1), lo, hi), 0).rgb;
+    color *= 0.25;
+
+    // fades in over threshold +- knee instead of cutting off at the threshold
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-5);
+    FragColor = vec4(color * contribution, 1.0);
+}
+"#;
+
+const BLUR_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D image;
+uniform vec2 direction;
+uniform vec2 area;
+
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main()
+{
+    // one direction of a separable 9 tap gaussian
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    ivec2 stride = ivec2(direction);
+    ivec2 hi = ivec2(area) - 1;
+    vec3 sum = texelFetch(image, p, 0).rgb * WEIGHTS[0];
+    for (int i = 1; i < 5; ++i) {
+        sum += texelFetch(image, clamp(p + stride * i, ivec2(0), hi), 0).rgb * WEIGHTS[i];
+        sum += texelFetch(image, clamp(p - stride * i, ivec2(0), hi), 0).rgb * WEIGHTS[i];
+    }
+    FragColor = vec4(sum, 1.0);
+}
+"#;
+
+const LUMINANCE_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out float LogLuminance;
+
+uniform sampler2D scene;
+uniform vec4 viewportRect;
+uniform float size;
+
+void main()
+{
+    ivec2 p = ivec2(viewportRect.xy + gl_FragCoord.xy / size * viewportRect.zw);
+    float luminance = dot(texelFetch(scene, p, 0).rgb, vec3(0.2126, 0.7152, 0.0722));
+    // the mip chain averages the log, a few bright lights don't darken the whole image
+    LogLuminance = log(luminance + 1e-4);
+}
+"#;
+
+const ADAPT_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out float Luminance;
+
+uniform sampler2D logLuminance;
+uniform sampler2D previous;
+uniform int level;
+uniform float rate;
+
+void main()
+{
+    float target = exp(texelFetch(logLuminance, ivec2(0), level).r);
+    // the first frame ignores the uninitialized previous value
+    float last = rate >= 1.0 ? target : texelFetch(previous, ivec2(0), 0).r;
+    Luminance = last + (target - last) * rate;
+}
+"#;
+
+const TONE_MAP_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform sampler2D bloom;
+uniform sampler2D adaptedLuminance;
+uniform vec4 viewportRect;
+uniform vec2 bloomArea;
+uniform float bloomIntensity;
+uniform int autoExposure;
+uniform float exposure;
+uniform float key;
+uniform float minExposure;
+uniform float maxExposure;
+uniform int toneMapOperator;
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x)
+{
+    return clamp(x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+    vec4 color = texelFetch(scene, ivec2(gl_FragCoord.xy), 0);
+    vec3 hdr = color.rgb;
+    if (bloomIntensity > 0.0) {
+        vec2 texel = clamp((gl_FragCoord.xy - viewportRect.xy) * 0.5, vec2(0.5), bloomArea - 0.5);
+        hdr += texture(bloom, texel / vec2(textureSize(bloom, 0))).rgb * bloomIntensity;
+    }
+    float scale = exposure;
+    if (autoExposure != 0)
+        scale = clamp(key / max(texelFetch(adaptedLuminance, ivec2(0), 0).r, 1e-4), minExposure, maxExposure);
+    hdr *= scale;
+    vec3 mapped = toneMapOperator == 0 ? hdr / (1.0 + hdr) : aces(hdr);
+    FragColor = vec4(mapped, color.a);
+}
+"#;
+
+/// Side of the log luminance texture averaged by its mip chain, a power of two
+const LUMINANCE_SIZE: i32 = 256;
+
+/// How `HdrPass` scales scene radiance before tone mapping
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exposure {
+    /// fixed scale
+    Manual(Float),
+    /// scales the average scene luminance to `key`, following changes over about `1 / speed`
+    /// seconds like an eye does, with the scale kept within `min..max`
+    Auto { key: Float, speed: Float, min: Float, max: Float },
+}
+
+impl Default for Exposure {
+    fn default() -> Exposure {
+        Exposure::Manual(1.0)
+    }
+}
+
+/// Curve mapping exposed HDR colors into 0..1
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ToneMapOperator {
+    Reinhard,
+    #[default]
+    Aces,
+}
+
+/// Glow around colors brighter than `threshold`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bloom {
+    pub threshold: Float,
+    /// width of the soft transition around the threshold
+    pub knee: Float,
+    /// weight of the glow added to the scene
+    pub intensity: Float,
+    /// blur iterations at half resolution, each one widens the glow
+    pub passes: i32,
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom {
+        Bloom { threshold: 1.0, knee: 0.5, intensity: 0.05, passes: 3 }
+    }
+}
+
+/// HDR resolve of a scene rendered into a float target such as `RenderTarget::hdr`: bloom
+/// from a bright pass and separable blur, exposure, then tone mapping. The output stays
+/// linear, draw it into an sRGB framebuffer to have it encoded.
+pub struct HdrPass {
+    pub exposure: Exposure,
+    pub operator: ToneMapOperator,
+    pub bloom: Option<Bloom>,
+    fbo: u32,
+    /// half resolution ping-pong of the blur
+    bloom_textures: [u32; 2],
+    bloom_size: (i32, i32),
+    luminance: u32,
+    /// 1x1 adapted luminance, the current one and the one written next
+    adapted: [u32; 2],
+    current: usize,
+    /// false until the first auto exposure frame, which adapts at once
+    adaptation_valid: bool,
+    bright_shader: Shader,
+    blur_shader: Shader,
+    luminance_shader: Shader,
+    adapt_shader: Shader,
+    tone_map_shader: Shader,
+    vao: u32,
+}
+
+impl HdrPass {
+    pub fn new() -> HdrPass {
+        let mut pass = HdrPass {
+            exposure: Exposure::default(),
+            operator: ToneMapOperator::default(),
+            bloom: Some(Bloom::default()),
+            fbo: 0,
+            bloom_textures: [0; 2],
+            bloom_size: (0, 0),
+            luminance: 0,
+            adapted: [0; 2],
+            current: 0,
+            adaptation_valid: false,
+            bright_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, BRIGHT_PASS_FRAGMENT_SHADER),
+            blur_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, BLUR_FRAGMENT_SHADER),
+            luminance_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, LUMINANCE_FRAGMENT_SHADER),
+            adapt_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, ADAPT_FRAGMENT_SHADER),
+            tone_map_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, TONE_MAP_FRAGMENT_SHADER),
+            vao: 0,
+        };
+        unsafe {
+            gl::GenFramebuffers(1, &mut pass.fbo);
+            gl::GenTextures(2, pass.bloom_textures.as_mut_ptr());
+            gl::GenTextures(1, &mut pass.luminance);
+            gl::GenTextures(2, pass.adapted.as_mut_ptr());
+            gl::GenVertexArrays(1, &mut pass.vao);
+
+            allocate_target(pass.luminance, gl::R16F, (LUMINANCE_SIZE, LUMINANCE_SIZE), gl::NEAREST);
+            gl::BindTexture(gl::TEXTURE_2D, pass.luminance);
+            // texelFetch reads the averaged level only from a mipmapped texture
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as i32);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            for &texture in pass.adapted.iter() {
+                allocate_target(texture, gl::R32F, (1, 1), gl::NEAREST);
+            }
+        }
+        pass
+    }
+
+    /// Forgets the adapted luminance so the next frame exposes for itself, call it on camera cuts
+    pub fn reset_adaptation(&mut self) {
+        self.adaptation_valid = false;
+    }
+
+    /// Tone maps the area `v` of the float texture `scene` into the same area of `output`,
+    /// adapting auto exposure over `delta_time`. Leaves blending, depth testing and culling
+    /// off, and `output` bound.
+    pub fn apply(&mut self, state: &mut GlState, v: &Viewport, scene: u32, output: u32, delta_time: TimeSec) {
+        state.bind_vertex_array(self.vao);
+        state.set_blend(false);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        state.color_mask(true);
+        let rect = (v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+
+        let area = bloom_area(v);
+        if let Some(bloom) = self.bloom {
+            self.grow_bloom(state, area);
+            let shader = self.bright_shader;
+            state.use_program(shader.ID);
+            state.bind_texture(0, scene);
+            unsafe {
+                self.attach(self.bloom_textures[0]);
+                gl::Viewport(0, 0, area.0, area.1);
+                shader.setInt(c_str!("scene"), 0);
+                shader.setVec4(c_str!("viewportRect"), rect.0, rect.1, rect.2, rect.3);
+                shader.setFloat(c_str!("threshold"), bloom.threshold);
+                shader.setFloat(c_str!("knee"), bloom.knee.max(0.0));
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            let shader = self.blur_shader;
+            state.use_program(shader.ID);
+            unsafe {
+                shader.setInt(c_str!("image"), 0);
+                shader.setVec2(c_str!("area"), area.0 as Float, area.1 as Float);
+            }
+            for _ in 0..bloom.passes.max(1) {
+                for &(from, to, x, y) in [(0, 1, 1.0, 0.0), (1, 0, 0.0, 1.0)].iter() {
+                    state.bind_texture(0, self.bloom_textures[from]);
+                    unsafe {
+                        self.attach(self.bloom_textures[to]);
+                        shader.setVec2(c_str!("direction"), x, y);
+                        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+                    }
+                }
+            }
+        }
+
+        let auto = match self.exposure {
+            Exposure::Auto { speed, .. } => Some(speed),
+            Exposure::Manual(_) => None,
+        };
+        if let Some(speed) = auto {
+            let shader = self.luminance_shader;
+            state.use_program(shader.ID);
+            state.bind_texture(0, scene);
+            unsafe {
+                self.attach(self.luminance);
+                gl::Viewport(0, 0, LUMINANCE_SIZE, LUMINANCE_SIZE);
+                shader.setInt(c_str!("scene"), 0);
+                shader.setVec4(c_str!("viewportRect"), rect.0, rect.1, rect.2, rect.3);
+                shader.setFloat(c_str!("size"), LUMINANCE_SIZE as Float);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            // the scene was on unit 0, so this binds and leaves unit 0 active for the mipmaps
+            state.bind_texture(0, self.luminance);
+            unsafe {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+
+            let next = 1 - self.current;
+            let shader = self.adapt_shader;
+            state.use_program(shader.ID);
+            state.bind_texture(1, self.adapted[self.current]);
+            unsafe {
+                self.attach(self.adapted[next]);
+                gl::Viewport(0, 0, 1, 1);
+                shader.setInt(c_str!("logLuminance"), 0);
+                shader.setInt(c_str!("previous"), 1);
+                shader.setInt(c_str!("level"), LUMINANCE_SIZE.trailing_zeros() as i32);
+                shader.setFloat(c_str!("rate"), adaptation_rate(delta_time, speed, self.adaptation_valid));
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            self.current = next;
+            self.adaptation_valid = true;
+        }
+
+        let shader = self.tone_map_shader;
+        state.use_program(shader.ID);
+        state.bind_texture(0, scene);
+        state.bind_texture(1, self.bloom_textures[0]);
+        state.bind_texture(2, self.adapted[self.current]);
+        let (scale, key, min, max) = match self.exposure {
+            Exposure::Manual(scale) => (scale, 0.0, 0.0, 0.0),
+            Exposure::Auto { key, min, max, .. } => (1.0, key, min, max),
+        };
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, output);
+            v.apply();
+            shader.setInt(c_str!("scene"), 0);
+            shader.setInt(c_str!("bloom"), 1);
+            shader.setInt(c_str!("adaptedLuminance"), 2);
+            shader.setVec4(c_str!("viewportRect"), rect.0, rect.1, rect.2, rect.3);
+            shader.setVec2(c_str!("bloomArea"), area.0 as Float, area.1 as Float);
+            shader.setFloat(c_str!("bloomIntensity"), self.bloom.map_or(0.0, |bloom| bloom.intensity));
+            shader.setInt(c_str!("autoExposure"), auto.is_some() as i32);
+            shader.setFloat(c_str!("exposure"), scale);
+            shader.setFloat(c_str!("key"), key);
+            shader.setFloat(c_str!("minExposure"), min);
+            shader.setFloat(c_str!("maxExposure"), max);
+            shader.setInt(c_str!("toneMapOperator"), match self.operator {
+                ToneMapOperator::Reinhard => 0,
+                ToneMapOperator::Aces => 1,
+            });
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+    }
+
+    /// Draws into `texture` with the pass framebuffer
+    unsafe fn attach(&self, texture: u32) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+    }
+
+    /// Grows the bloom textures to cover `area`
+    fn grow_bloom(&mut self, state: &mut GlState, area: (i32, i32)) {
+        if area.0 > self.bloom_size.0 || area.1 > self.bloom_size.1 {
+            self.bloom_size = (area.0.max(self.bloom_size.0), area.1.max(self.bloom_size.1));
+            for &texture in self.bloom_textures.iter() {
+                unsafe {
+                    allocate_target(texture, gl::RGBA16F, self.bloom_size, gl::LINEAR);
+                }
+            }
+            // the textures were bound outside of the cache
+            state.invalidate();
+            engine_debug!(logging::RENDERER, "bloom targets of {}x{}", self.bloom_size.0, self.bloom_size.1);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(2, self.bloom_textures.as_ptr());
+            gl::DeleteTextures(1, &self.luminance);
+            gl::DeleteTextures(2, self.adapted.as_ptr());
+            gl::DeleteVertexArrays(1, &self.vao);
+            for shader in [self.bright_shader, self.blur_shader, self.luminance_shader, self.adapt_shader,
+                           self.tone_map_shader].iter() {
+                gl::DeleteProgram(shader.ID);
+            }
+        }
+        self.fbo = 0;
+        self.bloom_size = (0, 0);
+        self.adaptation_valid = false;
+    }
+}
+
+impl Default for HdrPass {
+    fn default() -> HdrPass {
+        HdrPass::new()
+    }
+}
+
+/// Half resolution size of the bloom of viewport `v`, rounded up
+fn bloom_area(v: &Viewport) -> (i32, i32) {
+    ((v.width.max(1) + 1) / 2, (v.height.max(1) + 1) / 2)
+}
+
+/// Fraction of the way from the adapted to the measured luminance covered in `delta_time`,
+/// independent of the frame rate; all of it for the first frame
+fn adaptation_rate(delta_time: TimeSec, speed: Float, valid: bool) -> Float {
+    if !valid {
+        return 1.0;
+    }
+    (1.0 - (-delta_time.max(0.0) * speed.max(0.0) as TimeSec).exp()) as Float
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptation_is_frame_rate_independent() {
+        assert_eq!(adaptation_rate(0.016, 2.0, false), 1.0);
+        assert_eq!(adaptation_rate(-1.0, 2.0, true), 0.0);
+        // two 30 fps frames cover the same distance as four 60 fps frames
+        let remaining = |rate: Float, frames: i32| (1.0 - rate).powi(frames);
+        let slow = remaining(adaptation_rate(1.0 / 30.0, 2.0, true), 2);
+        let fast = remaining(adaptation_rate(1.0 / 60.0, 2.0, true), 4);
+        assert!((slow - fast).abs() < 1e-5, "{} {}", slow, fast);
+        assert!(adaptation_rate(10.0, 2.0, true) > 0.99);
+        assert_eq!(bloom_area(&Viewport::new(10, 20, 101, 64)), (51, 32));
+    }
+}
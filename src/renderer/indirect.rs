@@ -0,0 +1,175 @@
+use std::mem;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use buffer::supports;
+use gl_state::GlState;
+use mesh::pool::{MeshPool, MeshRange};
+use shader::Shader;
+use super::DrawCommand;
+
+/// GL 4.3 or `GL_ARB_multi_draw_indirect`
+pub fn supports_multi_draw_indirect() -> bool {
+    gl::MultiDrawElementsIndirect::is_loaded() && supports((4, 3), "GL_ARB_multi_draw_indirect")
+}
+
+/// Arguments of one draw of `glMultiDrawElementsIndirect`
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DrawElementsIndirectCommand {
+    pub count: u32,
+    /// 0 skips the draw, which is how culling removes it
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    /// first instance, readable as `gl_BaseInstance` / through instanced attributes
+    pub base_instance: u32,
+}
+
+impl DrawElementsIndirectCommand {
+    pub fn new(range: &MeshRange, instance_count: u32, base_instance: u32) -> DrawElementsIndirectCommand {
+        DrawElementsIndirectCommand {
+            count: range.index_count,
+            instance_count,
+            first_index: range.first_index,
+            base_vertex: range.base_vertex,
+            base_instance,
+        }
+    }
+}
+
+/// Draw commands in a GL buffer, drawn with one `glMultiDrawElementsIndirect` call.
+///
+/// Culling can run on the CPU through `set_visible` before `upload`, or on the GPU: bind the
+/// buffer with `bind_storage` and let a compute pass write the instance counts, then issue a
+/// `Barriers::COMMAND` before drawing.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct DrawIndirectBuffer {
+    pub buffer: u32,
+    pub commands: Vec<DrawElementsIndirectCommand>,
+    /// instance counts of the commands as pushed, restored by `set_visible(i, true)`
+    instance_counts: Vec<u32>,
+    capacity: usize,
+    /// `supports_multi_draw_indirect`, queried once in `new`
+    multi_draw: bool,
+}
+
+impl DrawIndirectBuffer {
+    pub fn new() -> DrawIndirectBuffer {
+        let mut buffer = DrawIndirectBuffer { multi_draw: supports_multi_draw_indirect(), ..Default::default() };
+        unsafe {
+            gl::GenBuffers(1, &mut buffer.buffer);
+        }
+        buffer
+    }
+
+    pub fn push(&mut self, command: DrawElementsIndirectCommand) -> usize {
+        self.commands.push(command);
+        self.instance_counts.push(command.instance_count);
+        self.commands.len() - 1
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+        self.instance_counts.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Culls or restores a command, takes effect on the next `upload`
+    pub fn set_visible(&mut self, index: usize, visible: bool) {
+        if let Some(command) = self.commands.get_mut(index) {
+            command.instance_count = if visible { self.instance_counts[index] } else { 0 };
+        }
+    }
+
+    /// Copies the commands into the GL buffer
+    pub fn upload(&mut self) {
+        let size = mem::size_of_val(&self.commands[..]) as GLsizeiptr;
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.buffer);
+            if self.commands.len() > self.capacity {
+                gl::BufferData(gl::DRAW_INDIRECT_BUFFER, size, self.commands.as_ptr() as *const GLvoid, gl::DYNAMIC_DRAW);
+                self.capacity = self.commands.len();
+            } else {
+                gl::BufferSubData(gl::DRAW_INDIRECT_BUFFER, 0, size, self.commands.as_ptr() as *const GLvoid);
+            }
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        }
+    }
+
+    /// Binds the commands as a `std430` storage buffer for a culling compute pass
+    pub fn bind_storage(&self, index: u32) {
+        unsafe {
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, index, self.buffer);
+        }
+    }
+
+    /// Draws all commands with the vertex array in use. Without multi-draw support they are
+    /// drawn one by one from the CPU copy, ignoring `base_instance` and GPU-written counts.
+    pub fn draw(&self, state: &mut GlState, vao: u32) {
+        if self.commands.is_empty() {
+            return;
+        }
+        state.bind_vertex_array(vao);
+        unsafe {
+            if self.multi_draw {
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.buffer);
+                gl::MultiDrawElementsIndirect(gl::TRIANGLES, gl::UNSIGNED_INT, ptr::null(),
+                                              self.commands.len() as GLsizei, 0);
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+            } else {
+                for command in self.commands.iter().filter(|command| command.instance_count > 0) {
+                    let offset = command.first_index as usize * mem::size_of::<GLuint>();
+                    gl::DrawElementsInstancedBaseVertex(gl::TRIANGLES, command.count as GLsizei, gl::UNSIGNED_INT,
+                                                        offset as *const GLvoid,
+                                                        command.instance_count as GLsizei, command.base_vertex);
+                }
+            }
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+        *self = DrawIndirectBuffer::default();
+    }
+}
+
+impl DrawCommand {
+    /// One command drawing every mesh of `buffer` from the pool in a single call, it sorts
+    /// and binds like any other command. `buffer` must be uploaded before the renderer runs.
+    pub fn multi_draw(shader: Shader, pool: &MeshPool, buffer: &DrawIndirectBuffer) -> DrawCommand {
+        let vao = pool.vao();
+        let buffer = buffer.clone();
+        DrawCommand::new(shader, move |_, state| buffer.draw(state, vao))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn culls_by_zeroing_instance_counts() {
+        let mut buffer = DrawIndirectBuffer::default();
+        let range = MeshRange { first_index: 6, index_count: 36, base_vertex: 24 };
+        buffer.push(DrawElementsIndirectCommand::new(&range, 3, 0));
+        buffer.push(DrawElementsIndirectCommand::new(&range, 1, 3));
+
+        buffer.set_visible(0, false);
+        assert_eq!(buffer.commands[0].instance_count, 0);
+        buffer.set_visible(0, true);
+        assert_eq!(buffer.commands[0].instance_count, 3);
+        assert_eq!(mem::size_of::<DrawElementsIndirectCommand>(), 20);
+    }
+}
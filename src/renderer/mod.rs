@@ -0,0 +1,645 @@
+pub mod capture;
+pub mod command_list;
+pub mod debug;
+pub mod decal;
+pub mod deferred;
+mod depth_copy;
+pub mod dynamic_resolution;
+pub mod frame_graph;
+pub mod gpu_culling;
+pub mod hdr;
+pub mod indirect;
+pub mod occlusion;
+pub mod oit;
+pub mod point_shadow;
+pub mod post;
+pub mod reflection;
+pub mod render_to_texture;
+pub mod shadow;
+pub mod ssao;
+pub mod taa;
+pub mod view;
+pub mod volume;
+
+use std::cmp::Ordering;
+
+use gl;
+use gl::types::GLenum;
+use serde::Serialize;
+
+use lang::{Float, Matrix4};
+use bounds::Aabb;
+use logging;
+use gl_state::{DepthState, GlState};
+use picking::NodeId;
+use shader::Shader;
+use self::oit::OitPass;
+use self::ssao::SSAO_TEXTURE_UNIT;
+use viewport::{self, Viewport};
+
+pub use self::capture::FrameCapture;
+pub use self::command_list::CommandList;
+pub use self::debug::{DebugMode, DebugView};
+pub use self::decal::{Decal, DecalId, Decals};
+pub use self::deferred::{DeferredShading, PointLight};
+pub use self::dynamic_resolution::{DynamicResolution, ResolutionController};
+pub use self::frame_graph::{FrameGraph, PassResources, ResourceId, TargetDesc};
+pub use self::gpu_culling::{GpuCuller, GpuInstance, HiZBuffer};
+pub use self::hdr::{Bloom, Exposure, HdrPass, ToneMapOperator};
+pub use self::indirect::{DrawElementsIndirectCommand, DrawIndirectBuffer};
+pub use self::occlusion::OcclusionCuller;
+pub use self::oit::TransparencyMode;
+pub use self::point_shadow::{CubeShadowMode, PointShadow};
+pub use self::post::{Antialiasing, PostProcess};
+pub use self::reflection::{PlanarReflection, ReflectionProbe};
+pub use self::render_to_texture::RenderToTexture;
+pub use self::shadow::{CascadeShadows, Cascade};
+pub use self::ssao::Ssao;
+pub use self::taa::{TemporalAA, VelocityBuffer};
+pub use self::view::SceneView;
+pub use self::volume::{TransferFunction, Volume, VolumeRenderer};
+
+/// Application defined material identifier, used to group draws sharing uniforms
+pub type MaterialId = u32;
+
+/// Sets the uniforms of a material on the shader in use
+pub type MaterialFn = Box<dyn FnMut(MaterialId, &Shader)>;
+
+/// Sets the uniforms of a view (camera matrices) on the shader in use
+pub type ViewFn = Box<dyn FnMut(&SceneView, &Shader)>;
+
+/// How a command's fragments are combined with the framebuffer
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize)]
+pub enum BlendMode {
+    /// no blending, drawn in the opaque pass
+    #[default]
+    Opaque,
+    /// `src * a + dst * (1 - a)`
+    Alpha,
+    /// `src * a + dst`
+    Additive,
+    /// `src + dst * (1 - a)`, for colors already multiplied by their alpha
+    Premultiplied,
+}
+
+impl BlendMode {
+    fn apply(self, state: &mut GlState) -> bool {
+        match self {
+            BlendMode::Opaque => false,
+            BlendMode::Alpha => state.blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => state.blend_func(gl::SRC_ALPHA, gl::ONE),
+            BlendMode::Premultiplied => state.blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+        }
+    }
+}
+
+/// Issues the actual draw calls of one renderable, runs on the GL thread with its
+/// shader in use and its texture bound to unit 0. State changes should go through the
+/// `GlState` so the cache stays in sync. It is `Send` so commands can be recorded on
+/// worker threads, share state with them through `ObjectPar`.
+pub type DrawFn = Box<dyn FnMut(&Shader, &mut GlState) + Send>;
+
+pub struct DrawCommand {
+    pub shader: Shader,
+    pub material: MaterialId,
+    /// GL texture bound to `texture_target` on unit 0, 0 means none
+    pub texture: u32,
+    /// `TEXTURE_2D` unless set by `texture_array`
+    pub texture_target: GLenum,
+    /// sampler object bound to unit 0, 0 uses the texture's parameters
+    pub sampler: u32,
+    /// view-space distance from the camera: opaque draws go roughly front-to-back,
+    /// blended ones strictly back-to-front
+    pub depth: Float,
+    pub blend: BlendMode,
+    /// id and world-space bounds when the command takes part in occlusion culling
+    pub occlusion: Option<(NodeId, Aabb)>,
+    /// program drawing the command with `TransparencyMode::WeightedBlended`, see `oit`
+    pub oit_shader: Option<Shader>,
+    /// program writing the command's motion into the `VelocityBuffer`, see `velocity`
+    pub velocity_shader: Option<Shader>,
+    /// program writing the command's surface into the G-buffer of `DeferredShading`, see `deferred`
+    pub deferred_shader: Option<Shader>,
+    pub draw: DrawFn,
+}
+
+impl DrawCommand {
+    pub fn new<F: FnMut(&Shader, &mut GlState) + Send + 'static>(shader: Shader, draw: F) -> DrawCommand {
+        DrawCommand {
+            shader,
+            material: 0,
+            texture: 0,
+            texture_target: gl::TEXTURE_2D,
+            sampler: 0,
+            depth: 0.0,
+            blend: BlendMode::Opaque,
+            occlusion: None,
+            oit_shader: None,
+            velocity_shader: None,
+            deferred_shader: None,
+            draw: Box::new(draw),
+        }
+    }
+
+    pub fn material(mut self, material: MaterialId) -> DrawCommand {
+        self.material = material;
+        self
+    }
+
+    pub fn texture(mut self, texture: u32) -> DrawCommand {
+        self.texture = texture;
+        self
+    }
+
+    /// Binds a `TEXTURE_2D_ARRAY` instead, draws picking their layer (`TextureArrays`) keep batching
+    pub fn texture_array(mut self, texture: u32) -> DrawCommand {
+        self.texture = texture;
+        self.texture_target = gl::TEXTURE_2D_ARRAY;
+        self
+    }
+
+    /// Samples the texture with a `Sampler` instead of its own filtering and wrapping
+    pub fn sampler(mut self, sampler: u32) -> DrawCommand {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn depth(mut self, depth: Float) -> DrawCommand {
+        self.depth = depth;
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> DrawCommand {
+        self.blend = blend;
+        self
+    }
+
+    /// Lets the renderer skip the command while its bounds are hidden behind other geometry
+    pub fn occludable(mut self, id: NodeId, bounds: Aabb) -> DrawCommand {
+        self.occlusion = Some((id, bounds));
+        self
+    }
+
+    /// Program writing through `reactor/oit.glsl` instead of a color output, with which the
+    /// command takes part in order-independent transparency
+    pub fn oit(mut self, shader: Shader) -> DrawCommand {
+        self.oit_shader = Some(shader);
+        self
+    }
+
+    /// Program writing through `reactor/velocity.glsl` instead of a color output, for the motion
+    /// of opaque commands that move on their own in the `VelocityBuffer`
+    pub fn velocity(mut self, shader: Shader) -> DrawCommand {
+        self.velocity_shader = Some(shader);
+        self
+    }
+
+    /// Program writing through `reactor/gbuffer.glsl` instead of a color output, with which an
+    /// opaque command is shaded by `Renderer::deferred` when set; `shader` still draws it forward
+    /// otherwise
+    pub fn deferred(mut self, shader: Shader) -> DrawCommand {
+        self.deferred_shader = Some(shader);
+        self
+    }
+
+    /// Whether the command is accumulated by the order-independent pass of `mode`
+    fn is_oit(&self, mode: TransparencyMode) -> bool {
+        mode == TransparencyMode::WeightedBlended && self.oit_shader.is_some()
+            && matches!(self.blend, BlendMode::Alpha | BlendMode::Premultiplied)
+    }
+
+    pub(crate) fn state_order(&self, other: &DrawCommand) -> Ordering {
+        (self.shader.ID, self.material, self.texture, self.sampler)
+            .cmp(&(other.shader.ID, other.material, other.texture, other.sampler))
+            .then(self.depth.partial_cmp(&other.depth).unwrap_or(Ordering::Equal))
+    }
+}
+
+/// Number of GL state changes issued by the last `Renderer::render`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct RenderStats {
+    pub draws: usize,
+    pub program_changes: usize,
+    pub material_changes: usize,
+    pub texture_changes: usize,
+    pub blend_changes: usize,
+    /// draws skipped by occlusion culling
+    pub occluded: usize,
+}
+
+/// Collects the frame's draw commands, then issues them sorted by
+/// shader, material, texture and depth to minimize state changes
+#[derive(Default)]
+pub struct Renderer {
+    /// culls `DrawCommand::occludable` commands when set
+    pub occlusion: Option<OcclusionCuller>,
+    /// projected onto the opaque geometry of each `render_views` view, before the blended pass
+    pub decals: Option<Decals>,
+    /// ray marched over the opaque geometry of each `render_views` view, after the decals
+    pub volumes: Option<VolumeRenderer>,
+    /// filled with the motion of the opaque geometry of the first `render_views` view, for `TemporalAA`
+    pub velocity: Option<VelocityBuffer>,
+    /// shades the opaque commands with a `DrawCommand::deferred` program in each `render_views`
+    /// view before the forward ones
+    pub deferred: Option<DeferredShading>,
+    /// ambient occlusion of the opaque geometry of each `render_views` view, which the
+    /// commands' programs read through `reactor/ssao.glsl`
+    pub ssao: Option<Ssao>,
+    /// invalidated at the start of every `render`, since the application may change GL state between frames
+    pub gl_state: GlState,
+    /// depth state of the opaque pass, the blended pass keeps the test but never writes depth
+    pub depth: DepthState,
+    /// wireframe, normals, overdraw and depth views, globally or per material
+    pub debug: DebugView,
+    queue: Vec<DrawCommand>,
+    transparency: TransparencyMode,
+    oit: Option<OitPass>,
+    stats: RenderStats,
+    bind_material: Option<MaterialFn>,
+    bind_view: Option<ViewFn>,
+    capture_requested: bool,
+    capture: Option<FrameCapture>,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer::default()
+    }
+
+    pub fn submit(&mut self, command: DrawCommand) {
+        self.queue.push(command);
+    }
+
+    /// Queues the commands of a list, e.g. one recorded on a worker thread
+    pub fn submit_list(&mut self, list: CommandList) {
+        self.queue.extend(list.commands);
+    }
+
+    /// Records commands for `items` in up to `threads` jobs of the global job system and queues
+    /// them.
+    /// `record` does the per-item CPU work (traversal, culling, LOD selection) off the GL thread,
+    /// it must not call GL itself.
+    pub fn record_parallel<T, F>(&mut self, items: &[T], threads: usize, record: F)
+        where T: Sync, F: Fn(&T, &mut CommandList) + Sync
+    {
+        let lists = command_list::record_parallel(items, threads, &record);
+        for list in lists {
+            self.submit_list(list);
+        }
+    }
+
+    /// Number of commands waiting for the next `render`
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Called whenever the next draw uses a different material (or program) than the previous one
+    pub fn on_material<F: FnMut(MaterialId, &Shader) + 'static>(&mut self, bind_material: F) {
+        self.bind_material = Some(Box::new(bind_material));
+    }
+
+    /// Called before the first draw with each program in `render_views`, to set the view's
+    /// camera uniforms (view and projection matrices)
+    pub fn on_view<F: FnMut(&SceneView, &Shader) + 'static>(&mut self, bind_view: F) {
+        self.bind_view = Some(Box::new(bind_view));
+    }
+
+    pub fn transparency(&self) -> TransparencyMode {
+        self.transparency
+    }
+
+    /// Sorted or order-independent blending of the transparent commands, the targets of the
+    /// order-independent pass are freed when going back to sorting
+    pub fn set_transparency(&mut self, mode: TransparencyMode) {
+        self.transparency = mode;
+        if mode == TransparencyMode::Sorted {
+            if let Some(mut oit) = self.oit.take() {
+                oit.delete();
+            }
+        }
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Records every draw call of the next `render` or `render_views`, with its program,
+    /// textures, uniforms and depth / blend state, see `take_capture`
+    pub fn capture_next_frame(&mut self) {
+        self.capture_requested = true;
+    }
+
+    /// The finished capture, `None` until a frame was rendered after `capture_next_frame`
+    pub fn take_capture(&mut self) -> Option<FrameCapture> {
+        self.capture.take()
+    }
+
+    /// Draws everything submitted since the last call, then empties the queue.
+    /// Opaque commands go first sorted by state, then blended commands back-to-front
+    /// with depth writes disabled.
+    pub fn render(&mut self) {
+        self.render_queue(&[]);
+    }
+
+    /// Draws the queue once per view into the view's viewport, e.g. one view per split-screen
+    /// player. The queue is sorted once for all views, and blended commands use their
+    /// submitted `depth`. Occlusion culling follows the first view's camera only, later views
+    /// draw every command.
+    pub fn render_views(&mut self, views: &[SceneView]) {
+        self.render_queue(views);
+        viewport::disable_scissor();
+    }
+
+    fn render_queue(&mut self, views: &[SceneView]) {
+        let mut queue = ::std::mem::take(&mut self.queue);
+        let split = partition(&mut queue, |command| command.blend == BlendMode::Opaque);
+        let (opaque, transparent) = queue.split_at_mut(split);
+
+        opaque.sort_by(|a, b| a.state_order(b));
+        // order-independent commands first, they are drawn by state
+        let mode = self.transparency;
+        transparent.sort_by(|a, b| b.is_oit(mode).cmp(&a.is_oit(mode))
+            .then_with(|| if a.is_oit(mode) { Ordering::Equal } else { b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal) })
+            .then(a.state_order(b)));
+
+        self.gl_state.invalidate();
+        let mut state = PassState::default();
+        if self.capture_requested {
+            self.capture_requested = false;
+            state.capture = Some(FrameCapture { frame: logging::frame(), ..FrameCapture::default() });
+        }
+
+        if views.is_empty() {
+            self.draw_view(opaque, transparent, None, true, &mut state);
+        }
+        for (i, view) in views.iter().enumerate() {
+            view.viewport.apply();
+            view.viewport.scissor();
+            if let Some(clear) = view.clear {
+                clear.apply(&mut self.gl_state);
+            }
+            if i == 0 {
+                if let Some(ref mut culler) = self.occlusion {
+                    culler.set_camera(view.view_projection(), view.camera.position);
+                }
+            }
+            state.material = None;
+            state.view_program = None;
+            state.view_index = Some(i);
+            self.draw_view(opaque, transparent, Some(view), i == 0, &mut state);
+        }
+
+        self.stats = state.stats;
+        if let Some(mut capture) = state.capture {
+            capture.occluded = state.stats.occluded;
+            engine_info!(logging::RENDERER, "captured {} draws", capture.draws.len());
+            self.capture = Some(capture);
+        }
+        // keep the allocation for the next frame
+        queue.clear();
+        self.queue = queue;
+    }
+
+    fn draw_view(&mut self, opaque: &mut [DrawCommand], transparent: &mut [DrawCommand],
+                 view: Option<&SceneView>, cull: bool, state: &mut PassState) {
+        self.gl_state.set_blend(false);
+        self.depth.apply(&mut self.gl_state);
+        state.ssao = false;
+        if let (Some(view), Some(mut deferred)) = (view, self.deferred.take()) {
+            if opaque.iter().any(|command| self.is_deferred(command)) {
+                let framebuffer = deferred.begin(&mut self.gl_state, &view.viewport);
+                self.depth.apply(&mut self.gl_state);
+                state.material = None;
+                state.view_program = None;
+                state.gbuffer = true;
+                self.draw_pass(opaque, Some(view), cull, state);
+                state.gbuffer = false;
+                state.deferred_drawn = true;
+                let mut occlusion = None;
+                if let Some(ref mut ssao) = self.ssao {
+                    ssao.compute(&mut self.gl_state, view, deferred.depth_texture(), Some(deferred.normal_texture()));
+                    occlusion = Some(ssao.texture());
+                }
+                state.ssao = occlusion.is_some();
+                deferred.end(&mut self.gl_state, view, framebuffer, occlusion);
+                self.restore_after_overlay(state);
+            }
+            self.deferred = Some(deferred);
+        }
+        if let (false, Some(view), Some(mut ssao)) = (state.ssao, view, self.ssao.take()) {
+            // depth only, the opaque pass then draws the same depths again
+            let occluded = state.stats.occluded;
+            self.gl_state.color_mask(false);
+            self.draw_pass(opaque, Some(view), cull, state);
+            self.gl_state.color_mask(true);
+            state.stats.occluded = occluded;
+            ssao.compute_from_framebuffer(&mut self.gl_state, view);
+            self.ssao = Some(ssao);
+            self.restore_after_overlay(state);
+            self.gl_state.depth_func(match self.depth.func {
+                gl::LESS => gl::LEQUAL,
+                gl::GREATER => gl::GEQUAL,
+                func => func,
+            });
+            state.ssao = true;
+        }
+        if let (true, Some(ssao)) = (state.ssao, self.ssao.as_ref()) {
+            self.gl_state.bind_texture(SSAO_TEXTURE_UNIT, ssao.texture());
+        }
+        self.draw_pass(opaque, view, cull, state);
+        state.deferred_drawn = false;
+
+        if let (true, Some(ref mut culler)) = (cull, self.occlusion.as_mut()) {
+            let occludable: Vec<(NodeId, Aabb)> = opaque.iter().chain(transparent.iter())
+                .filter_map(|command| command.occlusion)
+                .collect();
+            culler.issue_queries(&mut self.gl_state, &occludable);
+            self.gl_state.depth_mask(self.depth.write);
+            // the culler draws with its own program
+            state.material = None;
+            state.view_program = None;
+        }
+
+        if let (true, Some(view)) = (cull, view) {
+            if let Some(mut velocity) = self.velocity.take() {
+                let (framebuffer, matrices) = velocity.begin(&mut self.gl_state, view);
+                state.material = None;
+                state.view_program = None;
+                state.velocity = Some(matrices);
+                // hidden commands fail the depth test anyway
+                self.draw_pass(opaque, Some(view), false, state);
+                state.velocity = None;
+                velocity.end(framebuffer);
+                self.velocity = Some(velocity);
+                self.restore_after_overlay(state);
+            }
+        }
+
+        if let (Some(view), Some(ref mut decals)) = (view, self.decals.as_mut()) {
+            if !decals.is_empty() {
+                decals.draw(&mut self.gl_state, view);
+                self.restore_after_overlay(state);
+            }
+        }
+        if let (Some(view), Some(ref mut volumes)) = (view, self.volumes.as_mut()) {
+            if !volumes.is_empty() {
+                volumes.draw(&mut self.gl_state, view);
+                self.restore_after_overlay(state);
+            }
+        }
+
+        let split = transparent.iter().take_while(|command| command.is_oit(self.transparency)).count();
+        let (accumulated, transparent) = transparent.split_at_mut(split);
+        if !accumulated.is_empty() {
+            let viewport = view.map_or_else(Viewport::current, |view| view.viewport);
+            let mut oit = self.oit.take().unwrap_or_else(OitPass::new);
+            let framebuffer = oit.begin(&mut self.gl_state, &viewport);
+            self.depth.apply(&mut self.gl_state);
+            self.gl_state.depth_mask(false);
+            state.oit = true;
+            self.draw_pass(accumulated, view, cull, state);
+            state.oit = false;
+            oit.composite(&mut self.gl_state, framebuffer);
+            self.oit = Some(oit);
+            self.restore_after_overlay(state);
+        }
+
+        if !transparent.is_empty() {
+            self.gl_state.set_blend(true);
+            self.gl_state.depth_mask(false);
+            self.draw_pass(transparent, view, cull, state);
+            self.gl_state.depth_mask(self.depth.write);
+            self.gl_state.set_blend(false);
+        }
+    }
+
+    /// Whether an opaque command goes through the G-buffer, debug views draw it forward
+    fn is_deferred(&self, command: &DrawCommand) -> bool {
+        command.deferred_shader.is_some() && self.debug.mode_for(command.material) == DebugMode::Off
+    }
+
+    /// Back to the opaque pass state after velocities, decals or volumes, which draw with their own program
+    fn restore_after_overlay(&mut self, state: &mut PassState) {
+        self.gl_state.set_blend(false);
+        self.gl_state.set_cull_face(false);
+        self.gl_state.cull_mode(gl::BACK);
+        self.depth.apply(&mut self.gl_state);
+        state.material = None;
+        state.view_program = None;
+    }
+
+    fn draw_pass(&mut self, commands: &mut [DrawCommand], view: Option<&SceneView>, cull: bool,
+                 state: &mut PassState) {
+        for command in commands.iter_mut() {
+            let (mode, program) = match (state.velocity, command.velocity_shader) {
+                (Some(_), Some(velocity_shader)) => (DebugMode::Off, velocity_shader),
+                (Some(_), None) => continue,
+                _ if state.gbuffer => match command.deferred_shader {
+                    Some(deferred_shader) if self.is_deferred(command) => (DebugMode::Off, deferred_shader),
+                    _ => continue,
+                },
+                // already in the G-buffer
+                _ if state.deferred_drawn && self.is_deferred(command) => continue,
+                _ => match (state.oit, command.oit_shader) {
+                    (true, Some(oit_shader)) => (self.debug.mode_for(command.material), oit_shader),
+                    _ => (self.debug.mode_for(command.material), command.shader),
+                },
+            };
+            if let (true, Some(ref mut culler), Some((id, ref bounds))) = (cull, self.occlusion.as_mut(), command.occlusion) {
+                if !culler.is_visible(id, bounds) {
+                    state.stats.occluded += 1;
+                    continue;
+                }
+            }
+            let shader = self.debug.program(mode, program);
+            if self.gl_state.use_program(shader.ID) {
+                // uniforms are per program, so the material has to be set again
+                state.material = None;
+                state.stats.program_changes += 1;
+                self.debug.bind_program(mode, &shader);
+            }
+            if let Some(view) = view {
+                if state.view_program != Some(shader.ID) {
+                    if let Some(ref mut bind_view) = self.bind_view {
+                        bind_view(view, &shader);
+                    }
+                    if let Some((current, previous)) = state.velocity {
+                        unsafe {
+                            shader.setMat4(c_str!("velocityViewProjection"), &current);
+                            shader.setMat4(c_str!("previousViewProjection"), &previous);
+                        }
+                    }
+                    unsafe {
+                        shader.setInt(c_str!("ssaoMap"), SSAO_TEXTURE_UNIT as i32);
+                        shader.setBool(c_str!("ssaoEnabled"), state.ssao);
+                    }
+                    state.view_program = Some(shader.ID);
+                }
+            }
+            if state.material != Some(command.material) {
+                if let Some(ref mut bind_material) = self.bind_material {
+                    bind_material(command.material, &shader);
+                }
+                state.material = Some(command.material);
+                state.stats.material_changes += 1;
+            }
+            if self.gl_state.bind_texture_target(0, command.texture_target, command.texture) {
+                state.stats.texture_changes += 1;
+            }
+            self.gl_state.bind_sampler(0, command.sampler);
+            // the order-independent pass blends all commands the same way
+            if !state.oit && command.blend.apply(&mut self.gl_state) {
+                state.stats.blend_changes += 1;
+            }
+
+            self.debug.apply(mode, &mut self.gl_state);
+
+            (command.draw)(&shader, &mut self.gl_state);
+            state.stats.draws += 1;
+
+            let blended = command.blend != BlendMode::Opaque;
+            if let Some(ref mut capture) = state.capture {
+                capture.record(command, shader.ID, mode, state.view_index, blended, &self.depth);
+            }
+            if mode == DebugMode::Overdraw {
+                self.gl_state.set_blend(blended);
+                self.gl_state.set_depth_test(self.depth.test);
+            }
+        }
+        self.gl_state.polygon_mode(gl::FILL);
+    }
+}
+
+/// Per-frame bookkeeping not covered by the `GlState`
+#[derive(Default)]
+struct PassState {
+    material: Option<MaterialId>,
+    /// program the current view's uniforms were last set on
+    view_program: Option<u32>,
+    /// index of the view being drawn by `render_views`
+    view_index: Option<usize>,
+    /// drawing into the order-independent transparency targets
+    oit: bool,
+    /// drawing the deferred commands into the G-buffer
+    gbuffer: bool,
+    /// the deferred commands were shaded already, the forward pass skips them
+    deferred_drawn: bool,
+    /// the `Renderer::ssao` occlusion of the view is bound to `SSAO_TEXTURE_UNIT`
+    ssao: bool,
+    /// drawing into the velocity buffer, with the unjittered and previous view-projections
+    velocity: Option<(Matrix4, Matrix4)>,
+    stats: RenderStats,
+    capture: Option<FrameCapture>,
+}
+
+/// Moves the elements matching `predicate` to the front, returns how many there are
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], predicate: F) -> usize {
+    let mut split = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(split, i);
+            split += 1;
+        }
+    }
+    split
+}
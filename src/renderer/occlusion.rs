@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{Point3, Matrix4};
+use bounds::Aabb;
+use gl_state::GlState;
+use mesh::{Mesh, primitives};
+use picking::NodeId;
+use shader::Shader;
+
+const BOX_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+uniform mat4 mvp;
+
+void main()
+{
+    gl_Position = mvp * vec4(aPos, 1.0);
+}
+"#;
+
+const BOX_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+void main()
+{
+    FragColor = vec4(1.0);
+}
+"#;
+
+#[derive(Copy, Clone, Debug)]
+struct ObjectQuery {
+    query: u32,
+    pending: bool,
+    visible: bool,
+    last_frame: u64,
+}
+
+/// Hardware occlusion culling with one frame of latency: every frame the
+/// bounding boxes of the occludable commands are drawn (without color or depth
+/// writes) against the finished opaque depth buffer, and the results decide
+/// whether the objects are drawn in a later frame.
+pub struct OcclusionCuller {
+    queries: HashMap<NodeId, ObjectQuery>,
+    box_mesh: Mesh,
+    box_shader: Shader,
+    view_projection: Matrix4,
+    camera_position: Point3,
+    frame: u64,
+}
+
+impl OcclusionCuller {
+    pub fn new() -> OcclusionCuller {
+        OcclusionCuller {
+            queries: HashMap::new(),
+            box_mesh: Mesh::new(&primitives::cube(1.0)),
+            box_shader: Shader::from_source(BOX_VERTEX_SHADER, BOX_FRAGMENT_SHADER),
+            view_projection: Matrix4::identity(),
+            camera_position: Point3::origin(),
+            frame: 0,
+        }
+    }
+
+    /// Has to be called every frame before `Renderer::render`
+    pub fn set_camera(&mut self, view_projection: Matrix4, camera_position: Point3) {
+        self.view_projection = view_projection;
+        self.camera_position = camera_position;
+    }
+
+    /// Last known visibility, objects that were never tested are visible.
+    /// Finished queries are collected here without waiting on the GPU.
+    pub fn is_visible(&mut self, id: NodeId, bounds: &Aabb) -> bool {
+        // the box would be clipped by the near plane, the query can't be trusted
+        if bounds.contains(self.camera_position) {
+            return true;
+        }
+
+        match self.queries.get_mut(&id) {
+            Some(object) => {
+                if object.pending {
+                    let mut available: GLuint = 0;
+                    unsafe {
+                        gl::GetQueryObjectuiv(object.query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+                    }
+                    if available != 0 {
+                        let mut samples: GLuint = 0;
+                        unsafe {
+                            gl::GetQueryObjectuiv(object.query, gl::QUERY_RESULT, &mut samples);
+                        }
+                        object.visible = samples != 0;
+                        object.pending = false;
+                    }
+                }
+                object.visible
+            },
+            None => true,
+        }
+    }
+
+    /// issues queries for the objects without one in flight, depth testing has to be enabled
+    pub(crate) fn issue_queries(&mut self, state: &mut GlState, objects: &[(NodeId, Aabb)]) {
+        if objects.is_empty() {
+            return;
+        }
+
+        self.frame += 1;
+        state.color_mask(false);
+        state.depth_mask(false);
+        state.use_program(self.box_shader.ID);
+
+        for &(id, ref bounds) in objects.iter() {
+            let object = self.queries.entry(id).or_insert_with(|| {
+                let mut query = 0;
+                unsafe {
+                    gl::GenQueries(1, &mut query);
+                }
+                ObjectQuery { query, pending: false, visible: true, last_frame: 0 }
+            });
+            object.last_frame = self.frame;
+            if object.pending {
+                continue;
+            }
+
+            let model = Matrix4::from_translation(bounds.center().to_vec()) *
+                Matrix4::from_nonuniform_scale(bounds.extents().x * 2.0, bounds.extents().y * 2.0, bounds.extents().z * 2.0);
+            unsafe {
+                self.box_shader.setMat4(c_str!("mvp"), &(self.view_projection * model));
+                gl::BeginQuery(gl::ANY_SAMPLES_PASSED, object.query);
+                self.box_mesh.draw_with_state(state);
+                gl::EndQuery(gl::ANY_SAMPLES_PASSED);
+            }
+            object.pending = true;
+        }
+
+        state.depth_mask(true);
+        state.color_mask(true);
+
+        // forget objects that are not submitted anymore
+        let frame = self.frame;
+        self.queries.retain(|_, object| {
+            let keep = frame - object.last_frame < 60;
+            if !keep {
+                unsafe {
+                    gl::DeleteQueries(1, &object.query);
+                }
+            }
+            keep
+        });
+    }
+
+    pub fn delete(&mut self) {
+        for object in self.queries.values() {
+            unsafe {
+                gl::DeleteQueries(1, &object.query);
+            }
+        }
+        self.queries.clear();
+        self.box_mesh.delete();
+        unsafe {
+            gl::DeleteProgram(self.box_shader.ID);
+        }
+    }
+}
+
+impl Default for OcclusionCuller {
+    fn default() -> OcclusionCuller {
+        OcclusionCuller::new()
+    }
+}
@@ -0,0 +1,197 @@
+use std::ptr;
+
+use gl;
+use gl::types::*;
+use serde::Serialize;
+
+use gl_state::GlState;
+use logging;
+use renderer::depth_copy::DepthCopy;
+use shader::Shader;
+use viewport::Viewport;
+
+/// `#include "reactor/oit.glsl"`: weighted blended order-independent transparency (McGuire
+/// and Bavoil 2013). The `DrawCommand::oit` program of a transparent command calls
+/// `writeTransparent` with its straight-alpha color instead of writing a color output.
+pub const OIT_GLSL: &str = r#"
+layout (location = 0) out vec4 OitAccumulation;
+layout (location = 1) out vec4 OitWeight;
+
+void writeTransparent(vec4 color)
+{
+    // nearer and more opaque fragments count more
+    float weight = color.a * clamp(3e3 * pow(1.0 - gl_FragCoord.z, 3.0), 1e-2, 3e3);
+    OitAccumulation = vec4(color.rgb * weight, color.a);
+    OitWeight = vec4(weight);
+}
+"#;
+
+const COMPOSITE_VERTEX_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+const COMPOSITE_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D accumulation;
+uniform sampler2D weight;
+
+void main()
+{
+    ivec2 p = ivec2(gl_FragCoord.xy);
+    vec4 sum = texelFetch(accumulation, p, 0);
+    // the alpha channel holds the product of (1 - alpha), how much of the background shows
+    float revealage = sum.a;
+    if (revealage >= 1.0)
+        discard;
+    FragColor = vec4(sum.rgb / max(texelFetch(weight, p, 0).r, 1e-5), revealage);
+}
+"#;
+
+/// How `Renderer` draws blended commands
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize)]
+pub enum TransparencyMode {
+    /// back-to-front by `DrawCommand::depth`, objects pop when their order changes
+    #[default]
+    Sorted,
+    /// the `Alpha` and `Premultiplied` commands with an `oit` program are accumulated in any
+    /// order and composited once; the others are still sorted and drawn over the result
+    WeightedBlended,
+}
+
+/// Accumulation targets of weighted blended transparency, sharing a copy of the scene's depth
+/// so transparent fragments behind the opaque geometry are rejected
+pub(crate) struct OitPass {
+    fbo: u32,
+    /// premultiplied, weighted color sum in RGB and the revealage in alpha
+    accumulation: u32,
+    /// weight sum
+    weight: u32,
+    size: (i32, i32),
+    depth: DepthCopy,
+    composite: Shader,
+    vao: u32,
+}
+
+impl OitPass {
+    pub fn new() -> OitPass {
+        let mut pass = OitPass {
+            fbo: 0,
+            accumulation: 0,
+            weight: 0,
+            size: (0, 0),
+            depth: DepthCopy::new(),
+            composite: Shader::from_source(COMPOSITE_VERTEX_SHADER, COMPOSITE_FRAGMENT_SHADER),
+            vao: 0,
+        };
+        unsafe {
+            gl::GenFramebuffers(1, &mut pass.fbo);
+            gl::GenTextures(1, &mut pass.accumulation);
+            gl::GenTextures(1, &mut pass.weight);
+            gl::GenVertexArrays(1, &mut pass.vao);
+        }
+        pass
+    }
+
+    /// Binds and clears the targets within `viewport` and sets up their blending, returns the
+    /// framebuffer drawn into before
+    pub fn begin(&mut self, state: &mut GlState, viewport: &Viewport) -> u32 {
+        self.depth.copy(viewport, "oit");
+        let size = (viewport.x + viewport.width, viewport.y + viewport.height);
+        let mut framebuffer = 0;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            if size.0 > self.size.0 || size.1 > self.size.1 {
+                self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+                for &(texture, format) in [(self.accumulation, gl::RGBA16F), (self.weight, gl::R16F)].iter() {
+                    gl::BindTexture(gl::TEXTURE_2D, texture);
+                    gl::TexImage2D(gl::TEXTURE_2D, 0, format as GLint, self.size.0, self.size.1, 0, gl::RGBA, gl::FLOAT,
+                                   ptr::null());
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+                }
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.accumulation, 0);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT1, gl::TEXTURE_2D, self.weight, 0);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, self.depth.texture, 0);
+                engine_debug!(logging::RENDERER, "oit targets of {}x{}", self.size.0, self.size.1);
+            }
+            // the textures were bound outside of the cache
+            state.invalidate();
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::DrawBuffers(2, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1].as_ptr());
+            state.color_mask(true);
+            gl::ClearBufferfv(gl::COLOR, 0, [0.0, 0.0, 0.0, 1.0].as_ptr());
+            gl::ClearBufferfv(gl::COLOR, 1, [0.0; 4].as_ptr());
+        }
+        state.set_blend(true);
+        // color and weight add up, alpha multiplies by (1 - alpha)
+        state.blend_func_separate(gl::ONE, gl::ONE, gl::ZERO, gl::ONE_MINUS_SRC_ALPHA);
+        framebuffer as u32
+    }
+
+    /// Blends the accumulated transparency over `framebuffer`. Leaves blending on and depth
+    /// testing off.
+    pub fn composite(&mut self, state: &mut GlState, framebuffer: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+        state.use_program(self.composite.ID);
+        state.bind_vertex_array(self.vao);
+        state.bind_texture(0, self.accumulation);
+        state.bind_texture(1, self.weight);
+        state.set_blend(true);
+        state.blend_func(gl::ONE_MINUS_SRC_ALPHA, gl::SRC_ALPHA);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        unsafe {
+            self.composite.setInt(c_str!("accumulation"), 0);
+            self.composite.setInt(c_str!("weight"), 1);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        self.depth.delete();
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.accumulation);
+            gl::DeleteTextures(1, &self.weight);
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.composite.ID);
+        }
+        self.fbo = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use renderer::{BlendMode, DrawCommand};
+    use shader::preprocess::{Defines, Preprocessor};
+
+    #[test]
+    fn only_blended_commands_with_a_program_are_accumulated() {
+        let command = |blend: BlendMode| DrawCommand::new(Shader { ID: 1 }, |_, _| {}).blend(blend).oit(Shader { ID: 2 });
+        assert!(command(BlendMode::Alpha).is_oit(TransparencyMode::WeightedBlended));
+        assert!(command(BlendMode::Premultiplied).is_oit(TransparencyMode::WeightedBlended));
+        assert!(!command(BlendMode::Additive).is_oit(TransparencyMode::WeightedBlended));
+        assert!(!command(BlendMode::Alpha).is_oit(TransparencyMode::Sorted));
+        let plain = DrawCommand::new(Shader { ID: 1 }, |_, _| {}).blend(BlendMode::Alpha);
+        assert!(!plain.is_oit(TransparencyMode::WeightedBlended));
+
+        let source = "#version 330 core\n#include \"reactor/oit.glsl\"\nvoid main() { writeTransparent(vec4(1.0)); }\n";
+        let processed = Preprocessor::new().process("smoke.frag", source, &Defines::new()).unwrap();
+        assert!(processed.source.contains("out vec4 OitWeight;"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::ffi::CString;
+use std::ptr;
+
+use cgmath::perspective;
+use gl;
+use gl::types::*;
+
+use lang::{Float, Point3, Vector3, Matrix4, deg};
+use gl_state::{ClearSpec, GlState};
+use logging;
+use shader::Shader;
+use viewport::Viewport;
+
+/// `pointShadow(worldPos, bias)`, 1 lit and 0 in shadow, with the uniforms
+/// `PointShadow::bind` sets. The cube stores the distance to the light over `far`.
+pub const POINT_SHADOW_GLSL: &str = r#"
+uniform samplerCube pointShadowMap;
+uniform vec3 pointLightPosition;
+uniform float pointShadowFar;
+
+float pointShadow(vec3 worldPos, float bias)
+{
+    vec3 toFragment = worldPos - pointLightPosition;
+    float distance = length(toFragment);
+    if (distance >= pointShadowFar)
+        return 1.0;
+    // percentage closer filtering over the corners of a small cube around the direction
+    float radius = 0.002 * distance;
+    float lit = 0.0;
+    for (int i = 0; i < 8; ++i) {
+        vec3 offset = vec3(i & 1, (i >> 1) & 1, (i >> 2) & 1) * 2.0 - 1.0;
+        float closest = texture(pointShadowMap, toFragment + offset * radius).r * pointShadowFar;
+        lit += distance - bias > closest ? 0.0 : 1.0;
+    }
+    return lit / 8.0;
+}
+"#;
+
+/// How the six faces of a point light's shadow cube are drawn
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CubeShadowMode {
+    /// the scene is drawn once per face, with any depth shader that sets `gl_FragDepth`
+    /// from the distance; usually the fastest
+    #[default]
+    SixPass,
+    /// drawn once, a geometry shader emits every triangle to the six layers, fewer draw
+    /// calls for scenes with many small meshes
+    GeometryShader,
+}
+
+/// Direction and up vector of the cube map faces, in the `TEXTURE_CUBE_MAP_POSITIVE_X` order
+pub const CUBE_FACES: [([Float; 3], [Float; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+/// View-projection of each cube face seen from `position`
+pub fn cube_face_matrices(position: Point3, near: Float, far: Float) -> [Matrix4; 6] {
+    let projection = perspective(deg(90.0), 1.0, near, far);
+    let mut matrices = [Matrix4::from_scale(1.0); 6];
+    for (matrix, &(direction, up)) in matrices.iter_mut().zip(CUBE_FACES.iter()) {
+        let view = Matrix4::look_at(position, position + Vector3::from(direction), Vector3::from(up));
+        *matrix = projection * view;
+    }
+    matrices
+}
+
+/// Omnidirectional shadow of a point light: a depth cube map of the distance to the light,
+/// rendered with `render` and sampled by `POINT_SHADOW_GLSL`. Call `set_light` when the
+/// light moves.
+pub struct PointShadow {
+    pub resolution: i32,
+    pub mode: CubeShadowMode,
+    pub position: Point3,
+    pub near: Float,
+    /// shadows end at this distance from the light, usually its range
+    pub far: Float,
+    matrices: [Matrix4; 6],
+    texture: u32,
+    fbo: u32,
+}
+
+impl PointShadow {
+    pub fn new(resolution: i32, mode: CubeShadowMode) -> PointShadow {
+        let mut shadow = PointShadow {
+            resolution,
+            mode,
+            position: Point3::new(0.0, 0.0, 0.0),
+            near: 0.05,
+            far: 25.0,
+            matrices: [Matrix4::from_scale(1.0); 6],
+            texture: 0,
+            fbo: 0,
+        };
+        unsafe {
+            gl::GenTextures(1, &mut shadow.texture);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, shadow.texture);
+            for face in 0..6 {
+                gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, 0, gl::DEPTH_COMPONENT24 as GLint,
+                               resolution, resolution, 0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+            }
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            for &axis in &[gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::TexParameteri(gl::TEXTURE_CUBE_MAP, axis, gl::CLAMP_TO_EDGE as GLint);
+            }
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+
+            gl::GenFramebuffers(1, &mut shadow.fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, shadow.fbo);
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        shadow.set_light(shadow.position, shadow.far);
+        engine_debug!(logging::RENDERER, "point shadow cube of {}x{} ({:?})", resolution, resolution, mode);
+        shadow
+    }
+
+    /// Depth cube map
+    pub fn texture(&self) -> u32 {
+        self.texture
+    }
+
+    pub fn set_light(&mut self, position: Point3, far: Float) {
+        self.position = position;
+        self.far = far;
+        self.matrices = cube_face_matrices(position, self.near, far);
+    }
+
+    pub fn matrices(&self) -> &[Matrix4; 6] {
+        &self.matrices
+    }
+
+    /// Sets `lightPosition`, `farPlane` and, for the geometry shader mode, `shadowMatrices`
+    /// on the depth `shader`, which has to be in use
+    pub fn set_depth_uniforms(&self, shader: &Shader) {
+        let p = self.position;
+        unsafe {
+            shader.setVec3(c_str!("lightPosition"), p.x, p.y, p.z);
+            shader.setFloat(c_str!("farPlane"), self.far);
+            for (i, matrix) in self.matrices.iter().enumerate() {
+                let name = CString::new(format!("shadowMatrices[{}]", i)).unwrap();
+                shader.setMat4(&name, matrix);
+            }
+        }
+    }
+
+    /// Draws the casters with depth cleared: in `SixPass` mode `draw` is called per face with
+    /// its index, set `lightMatrix` to `matrices()[face]`; in `GeometryShader` mode it's called
+    /// once with `None` and the whole cube bound as a layered target.
+    pub fn render<F: FnMut(Option<usize>)>(&self, state: &mut GlState, mut draw: F) {
+        let mut previous = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, previous.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        }
+        Viewport::full(self.resolution, self.resolution).apply();
+        match self.mode {
+            CubeShadowMode::SixPass => {
+                for face in 0..6 {
+                    unsafe {
+                        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT,
+                                                 gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum, self.texture, 0);
+                    }
+                    ClearSpec::none().depth(1.0).apply(state);
+                    draw(Some(face));
+                }
+            },
+            CubeShadowMode::GeometryShader => {
+                unsafe {
+                    gl::FramebufferTexture(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.texture, 0);
+                }
+                ClearSpec::none().depth(1.0).apply(state);
+                draw(None);
+            },
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        Viewport::new(previous[0], previous[1], previous[2], previous[3]).apply();
+    }
+
+    /// Binds the cube on texture `unit` and sets the `POINT_SHADOW_GLSL` uniforms of `shader`,
+    /// which has to be in use
+    pub fn bind(&self, shader: &Shader, unit: u32) {
+        let p = self.position;
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture);
+            shader.setInt(c_str!("pointShadowMap"), unit as i32);
+            shader.setVec3(c_str!("pointLightPosition"), p.x, p.y, p.z);
+            shader.setFloat(c_str!("pointShadowFar"), self.far);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.texture);
+        }
+        self.fbo = 0;
+        self.texture = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_look_along_the_axes() {
+        let light = Point3::new(1.0, 2.0, 3.0);
+        let matrices = cube_face_matrices(light, 0.1, 10.0);
+        for (matrix, &(direction, _)) in matrices.iter().zip(CUBE_FACES.iter()) {
+            let ahead = light + Vector3::from(direction) * 5.0;
+            let ndc = Point3::from_homogeneous(matrix * ahead.to_homogeneous());
+            assert!(ndc.x.abs() < 1e-4 && ndc.y.abs() < 1e-4 && ndc.z > -1.0 && ndc.z < 1.0, "{:?}", ndc);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use cgmath::prelude::*;
+use gl;
+
+use lang::{Float, Matrix4, TimeSec};
+use camera::Camera;
+use gl_state::GlState;
+use logging;
+use render_target::RenderTarget;
+use renderer::SceneView;
+use renderer::depth_copy::DepthCopy;
+use renderer::taa::allocate_target;
+use renderer::hdr::HdrPass;
+use shader::Shader;
+
+const FULLSCREEN_VERTEX_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+const DEPTH_OF_FIELD_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform sampler2D sceneDepth;
+uniform vec4 viewportRect;
+uniform float near;
+uniform float far;
+uniform float focusDistance;
+// blur radius in pixels of points at infinity
+uniform float cocScale;
+uniform float maxCoc;
+
+const int TAPS = 32;
+const float GOLDEN_ANGLE = 2.39996323;
+
+float linearDepth(ivec2 p)
+{
+    float z = texelFetch(sceneDepth, p, 0).r * 2.0 - 1.0;
+    return 2.0 * near * far / (far + near - z * (far - near));
+}
+
+float coc(float depth)
+{
+    return min(cocScale * abs(depth - focusDistance) / depth, maxCoc);
+}
+
+void main()
+{
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    vec4 centerColor = texelFetch(scene, center, 0);
+    float centerDepth = linearDepth(center);
+    float centerCoc = coc(centerDepth);
+
+    vec3 sum = centerColor.rgb;
+    float total = 1.0;
+    for (int i = 0; i < TAPS; ++i) {
+        // a spiral covering the largest blur circle evenly
+        float radius = sqrt((float(i) + 0.5) / float(TAPS)) * maxCoc;
+        float angle = float(i) * GOLDEN_ANGLE;
+        ivec2 p = clamp(center + ivec2(round(vec2(cos(angle), sin(angle)) * radius)), lo, hi);
+        float depth = linearDepth(p);
+        float tapCoc = coc(depth);
+        // the blurred background doesn't spread over sharper things in front of it
+        if (depth > centerDepth)
+            tapCoc = min(tapCoc, centerCoc);
+        // the tap lands here when its own blur circle reaches this far
+        float weight = smoothstep(radius - 1.0, radius + 1.0, tapCoc);
+        sum += texelFetch(scene, p, 0).rgb * weight;
+        total += weight;
+    }
+    FragColor = vec4(sum / total, centerColor.a);
+}
+"#;
+
+const MOTION_BLUR_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform sampler2D sceneDepth;
+uniform vec4 viewportRect;
+uniform mat4 inverseViewProjection;
+uniform mat4 previousViewProjection;
+uniform float exposure;
+uniform float maxBlur;
+uniform int samples;
+
+void main()
+{
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    vec4 centerColor = texelFetch(scene, center, 0);
+
+    // where the point seen here was on the screen last frame, with the camera of last frame
+    vec2 ndc = (gl_FragCoord.xy - viewportRect.xy) / viewportRect.zw * 2.0 - 1.0;
+    float depth = texelFetch(sceneDepth, center, 0).r * 2.0 - 1.0;
+    vec4 world = inverseViewProjection * vec4(ndc, depth, 1.0);
+    vec4 previous = previousViewProjection * vec4(world.xyz / world.w, 1.0);
+    vec2 velocity = vec2(0.0);
+    if (previous.w > 0.0)
+        velocity = (ndc - previous.xy / previous.w) * 0.5 * viewportRect.zw * exposure;
+    float speed = length(velocity);
+    if (speed > maxBlur)
+        velocity *= maxBlur / speed;
+
+    vec3 sum = vec3(0.0);
+    for (int i = 0; i < samples; ++i) {
+        // centered on the pixel, half of the exposure on each side
+        float t = (float(i) + 0.5) / float(samples) - 0.5;
+        sum += texelFetch(scene, clamp(center + ivec2(round(velocity * t)), lo, hi), 0).rgb;
+    }
+    FragColor = vec4(sum / float(max(samples, 1)), centerColor.a);
+}
+"#;
+
+const FXAA_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform vec4 viewportRect;
+
+const float EDGE_THRESHOLD = 0.125;
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+const float SPAN_MAX = 8.0;
+
+vec2 lo;
+vec2 hi;
+vec2 size;
+
+vec3 fetch(vec2 p)
+{
+    // bilinear between pixels, never past the viewport into a neighbouring one
+    return texture(scene, clamp(p, lo, hi) / size).rgb;
+}
+
+float luma(vec3 color)
+{
+    // the input is linear, the square root is close to the perceived brightness FXAA expects
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+void main()
+{
+    lo = viewportRect.xy + 0.5;
+    hi = viewportRect.xy + viewportRect.zw - 0.5;
+    size = vec2(textureSize(scene, 0));
+    vec2 p = gl_FragCoord.xy;
+    vec4 center = texelFetch(scene, ivec2(p), 0);
+    float lumaM = luma(center.rgb);
+    float lumaNW = luma(fetch(p + vec2(-1.0, 1.0)));
+    float lumaNE = luma(fetch(p + vec2(1.0, 1.0)));
+    float lumaSW = luma(fetch(p + vec2(-1.0, -1.0)));
+    float lumaSE = luma(fetch(p + vec2(1.0, -1.0)));
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+    if (lumaMax - lumaMin < max(EDGE_THRESHOLD_MIN, lumaMax * EDGE_THRESHOLD)) {
+        FragColor = center;
+        return;
+    }
+
+    // blur along the edge, across the gradient of the corners
+    vec2 dir = vec2(lumaNW + lumaNE - lumaSW - lumaSE, lumaNW + lumaSW - lumaNE - lumaSE);
+    float reduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    dir = clamp(dir / (min(abs(dir.x), abs(dir.y)) + reduce), -SPAN_MAX, SPAN_MAX);
+    vec3 inner = 0.5 * (fetch(p + dir * (1.0 / 3.0 - 0.5)) + fetch(p + dir * (2.0 / 3.0 - 0.5)));
+    vec3 outer = inner * 0.5 + 0.25 * (fetch(p - dir * 0.5) + fetch(p + dir * 0.5));
+    // the wider blur crossed another edge when it leaves the local range
+    float lumaOuter = luma(outer);
+    FragColor = vec4(lumaOuter < lumaMin || lumaOuter > lumaMax ? inner : outer, center.a);
+}
+"#;
+
+/// Antialiasing of the scene, switchable at runtime
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Antialiasing {
+    #[default]
+    None,
+    /// multisampled scene target with this many samples, resolved by `PostProcess::apply`
+    Msaa(i32),
+    /// FXAA as the last effect of `PostProcess`, on the tone mapped colors
+    Fxaa,
+}
+
+impl Antialiasing {
+    /// MSAA samples of the scene target
+    pub fn samples(self) -> i32 {
+        match self {
+            Antialiasing::Msaa(samples) => samples.max(0),
+            Antialiasing::None | Antialiasing::Fxaa => 0,
+        }
+    }
+}
+
+/// Effects applied to a rendered scene, in order: depth of field from the focus and aperture
+/// of `Camera::lens`, camera motion blur, the `hdr` tone mapping, then FXAA when
+/// `antialiasing` asks for it. The motion blur reprojects the depth buffer with the
+/// view-projection of the previous frame, so objects are blurred by the camera's motion
+/// relative to them only; their own motion in the `VelocityBuffer` is not read. Depth of
+/// field and motion blur are off until enabled.
+pub struct PostProcess {
+    pub depth_of_field: bool,
+    pub motion_blur: bool,
+    /// bloom, exposure and tone mapping of a float source such as `RenderTarget::hdr`,
+    /// `None` passes the colors through
+    pub hdr: Option<HdrPass>,
+    /// applied to the scene target by `configure`
+    pub antialiasing: Antialiasing,
+    /// largest depth of field blur radius in pixels, also the gather radius
+    pub max_coc: Float,
+    /// largest motion blur length in pixels
+    pub max_motion: Float,
+    pub motion_samples: i32,
+    /// view-projection of the last `apply`, `None` after `reset_history`
+    previous_view_projection: Option<Matrix4>,
+    depth: DepthCopy,
+    /// RGBA16F ping-pong between the effects, the last one draws into the output
+    fbos: [u32; 2],
+    intermediates: [u32; 2],
+    current: usize,
+    size: (i32, i32),
+    depth_of_field_shader: Shader,
+    motion_blur_shader: Shader,
+    fxaa_shader: Shader,
+    vao: u32,
+}
+
+impl PostProcess {
+    pub fn new() -> PostProcess {
+        let mut post = PostProcess {
+            depth_of_field: false,
+            motion_blur: false,
+            hdr: None,
+            antialiasing: Antialiasing::default(),
+            max_coc: 12.0,
+            max_motion: 32.0,
+            motion_samples: 12,
+            previous_view_projection: None,
+            depth: DepthCopy::new(),
+            fbos: [0; 2],
+            intermediates: [0; 2],
+            current: 0,
+            size: (0, 0),
+            depth_of_field_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, DEPTH_OF_FIELD_FRAGMENT_SHADER),
+            motion_blur_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, MOTION_BLUR_FRAGMENT_SHADER),
+            fxaa_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, FXAA_FRAGMENT_SHADER),
+            vao: 0,
+        };
+        unsafe {
+            gl::GenFramebuffers(2, post.fbos.as_mut_ptr());
+            gl::GenTextures(2, post.intermediates.as_mut_ptr());
+            gl::GenVertexArrays(1, &mut post.vao);
+        }
+        post
+    }
+
+    /// Forgets the previous camera so the next frame has no motion blur and exposes for itself,
+    /// call it on camera cuts
+    pub fn reset_history(&mut self) {
+        self.previous_view_projection = None;
+        if let Some(hdr) = self.hdr.as_mut() {
+            hdr.reset_adaptation();
+        }
+    }
+
+    /// Gives `target` the MSAA samples of `antialiasing`, call it before rendering the scene
+    /// into the target after switching modes
+    pub fn configure(&self, target: &mut RenderTarget) {
+        target.set_samples(self.antialiasing.samples());
+    }
+
+    /// Draws the `view.viewport` area of `source`, where `view` was rendered, into the same
+    /// area of `output` with the enabled effects, `delta_time` after the previous frame.
+    /// Resolves `source` first. Leaves blending, depth testing and culling off, and `output`
+    /// bound.
+    pub fn apply(&mut self, state: &mut GlState, view: &SceneView, source: &RenderTarget, output: u32,
+                 delta_time: TimeSec) {
+        let view_projection = view.view_projection();
+        let previous_view_projection = self.previous_view_projection.replace(view_projection);
+        let v = view.viewport;
+        source.resolve();
+        let fxaa = self.antialiasing == Antialiasing::Fxaa;
+        let mut remaining = self.depth_of_field as usize + self.motion_blur as usize + self.hdr.is_some() as usize
+            + fxaa as usize;
+        if remaining == 0 {
+            unsafe {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, source.fbo);
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, output);
+                gl::BlitFramebuffer(v.x, v.y, v.x + v.width, v.y + v.height, v.x, v.y, v.x + v.width, v.y + v.height,
+                                    gl::COLOR_BUFFER_BIT, gl::NEAREST);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, output);
+            }
+            return;
+        }
+
+        if self.depth_of_field || self.motion_blur {
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, source.draw_fbo());
+            }
+            self.depth.copy(&v, "post");
+        }
+        state.bind_vertex_array(self.vao);
+        state.set_blend(false);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        state.color_mask(true);
+        v.apply();
+
+        let size = (v.x + v.width, v.y + v.height);
+        let mut scene = source.color_texture;
+        if self.depth_of_field {
+            let (target, result) = self.next_target(state, size, &mut remaining, output);
+            let (focus, coc_scale) = coc_uniforms(&view.camera, v.height);
+            let shader = self.depth_of_field_shader;
+            state.use_program(shader.ID);
+            state.bind_texture(0, scene);
+            state.bind_texture(1, self.depth.texture);
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+                shader.setInt(c_str!("scene"), 0);
+                shader.setInt(c_str!("sceneDepth"), 1);
+                shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+                shader.setFloat(c_str!("near"), view.camera.near);
+                shader.setFloat(c_str!("far"), view.camera.far);
+                shader.setFloat(c_str!("focusDistance"), focus);
+                shader.setFloat(c_str!("cocScale"), coc_scale);
+                shader.setFloat(c_str!("maxCoc"), self.max_coc);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            scene = result;
+        }
+
+        if self.motion_blur {
+            let (target, result) = self.next_target(state, size, &mut remaining, output);
+            let inverse_view_projection = view_projection.invert().unwrap_or_else(Matrix4::identity);
+            let shader = self.motion_blur_shader;
+            state.use_program(shader.ID);
+            state.bind_texture(0, scene);
+            state.bind_texture(1, self.depth.texture);
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+                shader.setInt(c_str!("scene"), 0);
+                shader.setInt(c_str!("sceneDepth"), 1);
+                shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+                shader.setMat4(c_str!("inverseViewProjection"), &inverse_view_projection);
+                shader.setMat4(c_str!("previousViewProjection"), &previous_view_projection.unwrap_or(view_projection));
+                shader.setFloat(c_str!("exposure"), view.camera.lens.exposure());
+                shader.setFloat(c_str!("maxBlur"), self.max_motion);
+                shader.setInt(c_str!("samples"), self.motion_samples.max(1));
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+            scene = result;
+        }
+
+        if self.hdr.is_some() {
+            let (target, result) = self.next_target(state, size, &mut remaining, output);
+            if let Some(hdr) = self.hdr.as_mut() {
+                hdr.apply(state, &v, scene, target, delta_time);
+            }
+            scene = result;
+        }
+
+        if fxaa {
+            let (target, _) = self.next_target(state, size, &mut remaining, output);
+            let shader = self.fxaa_shader;
+            state.use_program(shader.ID);
+            state.bind_texture(0, scene);
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, target);
+                shader.setInt(c_str!("scene"), 0);
+                shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+        }
+    }
+
+    /// Framebuffer the next effect draws into, `output` for the last one, and the texture the
+    /// effect after it reads. Grows the intermediate targets to cover `size`.
+    fn next_target(&mut self, state: &mut GlState, size: (i32, i32), remaining: &mut usize, output: u32) -> (u32, u32) {
+        *remaining -= 1;
+        if *remaining == 0 {
+            return (output, 0);
+        }
+        if size.0 > self.size.0 || size.1 > self.size.1 {
+            self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+            for i in 0..2 {
+                unsafe {
+                    // linear for FXAA, the other effects fetch texels
+                    allocate_target(self.intermediates[i], gl::RGBA16F, self.size, gl::LINEAR);
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbos[i]);
+                    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D,
+                                             self.intermediates[i], 0);
+                }
+            }
+            // the textures were bound outside of the cache
+            state.invalidate();
+            engine_debug!(logging::RENDERER, "post targets of {}x{}", self.size.0, self.size.1);
+        }
+        self.current = 1 - self.current;
+        (self.fbos[self.current], self.intermediates[self.current])
+    }
+
+    pub fn delete(&mut self) {
+        self.depth.delete();
+        if let Some(hdr) = self.hdr.as_mut() {
+            hdr.delete();
+        }
+        unsafe {
+            gl::DeleteFramebuffers(2, self.fbos.as_ptr());
+            gl::DeleteTextures(2, self.intermediates.as_ptr());
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.depth_of_field_shader.ID);
+            gl::DeleteProgram(self.motion_blur_shader.ID);
+            gl::DeleteProgram(self.fxaa_shader.ID);
+        }
+        self.fbos = [0; 2];
+        self.size = (0, 0);
+        self.previous_view_projection = None;
+    }
+}
+
+impl Default for PostProcess {
+    fn default() -> PostProcess {
+        PostProcess::new()
+    }
+}
+
+/// Focus distance and the blur radius in pixels of points at infinity, for a viewport
+/// `height` pixels high: the blur radius at linear depth `z` is `scale * |z - focus| / z`
+fn coc_uniforms(camera: &Camera, height: i32) -> (Float, Float) {
+    let lens = &camera.lens;
+    let pixels_per_meter = height as Float / lens.sensor_height;
+    (lens.focus(camera.zoom), lens.circle_at_infinity(camera.zoom) * 0.5 * pixels_per_meter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Lens;
+
+    #[test]
+    fn shader_blur_radius_matches_the_lens() {
+        let camera = Camera { lens: Lens { focus_distance: 3.0, f_stop: 2.0, ..Lens::default() }, ..Camera::default() };
+        let (focus, scale) = coc_uniforms(&camera, 1080);
+        for &z in [0.5, 3.0, 10.0, 90.0].iter() {
+            let radius = scale * (z - focus).abs() / z;
+            let expected = camera.lens.circle_of_confusion(z, camera.zoom) / camera.lens.sensor_height * 1080.0 / 2.0;
+            assert!((radius - expected).abs() < 1e-6 * expected.max(1.0), "{} {}", radius, expected);
+        }
+        assert!(scale > 1.0, "an f/2 lens at 3 m blurs the background by {} pixels", scale);
+    }
+
+    #[test]
+    fn only_msaa_multisamples_the_scene() {
+        assert_eq!(Antialiasing::Msaa(4).samples(), 4);
+        assert_eq!(Antialiasing::Msaa(-2).samples(), 0);
+        assert_eq!(Antialiasing::Fxaa.samples(), 0);
+        assert_eq!(Antialiasing::default().samples(), 0);
+    }
+}
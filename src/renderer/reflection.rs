@@ -0,0 +1,319 @@
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use lang::{Float, Point3, Vector3, Vector4};
+use bounds::{Aabb, Plane};
+use camera::Camera;
+use gl_state::ClearSpec;
+use logging;
+use renderer::point_shadow::CUBE_FACES;
+use renderer::{Renderer, RenderToTexture, SceneView};
+use shader::Shader;
+use viewport::Viewport;
+
+/// `planarReflection(distortion)`, the color of a flat reflective surface at the current
+/// fragment with the uniforms `PlanarReflection::bind` sets; `distortion` offsets the lookup,
+/// for ripples, and is 0 for a mirror.
+pub const PLANAR_REFLECTION_GLSL: &str = r#"
+uniform sampler2D planarReflectionMap;
+uniform vec4 planarReflectionViewport;
+
+vec3 planarReflection(vec2 distortion)
+{
+    vec2 uv = (gl_FragCoord.xy - planarReflectionViewport.xy) / planarReflectionViewport.zw;
+    // the mirrored camera renders the reflection flipped horizontally
+    uv.x = 1.0 - uv.x;
+    return texture(planarReflectionMap, clamp(uv + distortion, 0.001, 0.999)).rgb;
+}
+"#;
+
+/// `probeReflection(worldPos, normal, toCamera, roughness)`, the environment seen in the
+/// mirror direction from the local probe `ReflectionProbe::bind` sets, parallax corrected
+/// against its box and blurrier along the mip chain as `roughness` goes to 1.
+pub const REFLECTION_PROBE_GLSL: &str = r#"
+uniform samplerCube probeMap;
+uniform vec3 probePosition;
+uniform vec3 probeBoxMin;
+uniform vec3 probeBoxMax;
+uniform float probeMipLevels;
+
+vec3 boxProject(vec3 worldPos, vec3 direction)
+{
+    vec3 first = (probeBoxMax - worldPos) / direction;
+    vec3 second = (probeBoxMin - worldPos) / direction;
+    vec3 furthest = max(first, second);
+    float distance = min(min(furthest.x, furthest.y), furthest.z);
+    return worldPos + direction * distance - probePosition;
+}
+
+vec3 probeReflection(vec3 worldPos, vec3 normal, vec3 toCamera, float roughness)
+{
+    vec3 direction = boxProject(worldPos, reflect(-toCamera, normal));
+    return textureLod(probeMap, direction, roughness * (probeMipLevels - 1.0)).rgb;
+}
+"#;
+
+/// Camera seeing what `camera` sees in a mirror on `plane`. The image comes out flipped
+/// horizontally but keeps the triangle winding, so culling needs no change.
+/// Only the view is mirrored, the camera isn't meant to be moved with input.
+pub fn mirror_camera(camera: &Camera, plane: &Plane) -> Camera {
+    let mut mirrored = camera.clone();
+    mirrored.position = plane.reflect_point(camera.position);
+    mirrored.front = plane.reflect_vector(camera.front);
+    mirrored.up = plane.reflect_vector(camera.up);
+    mirrored.right = plane.reflect_vector(camera.right);
+    mirrored
+}
+
+/// CPU version of the shader's `boxProject`: the vector from `probe` to where the ray from
+/// `position` along `direction` leaves `bounds`, which `position` has to be inside of
+pub fn box_project(bounds: &Aabb, probe: Point3, position: Point3, direction: Vector3) -> Vector3 {
+    let distance = (0..3)
+        .map(|axis| {
+            let first = (bounds.max[axis] - position[axis]) / direction[axis];
+            let second = (bounds.min[axis] - position[axis]) / direction[axis];
+            first.max(second)
+        })
+        .fold(Float::INFINITY, Float::min);
+    position + direction * distance - probe
+}
+
+/// Reflection of a flat surface, a mirror or a water plane: the scene is drawn from the
+/// mirrored camera into a texture, with the geometry behind the plane clipped away.
+/// Shaders drawn into it write `gl_ClipDistance[0] = dot(vec4(worldPos, 1.0), clipPlane)`
+/// with `clip_plane()`, and the surface samples the result with `PLANAR_REFLECTION_GLSL`.
+pub struct PlanarReflection {
+    pub plane: Plane,
+    /// moves the clip plane below the surface, hides seams where geometry meets it
+    pub clip_offset: Float,
+    pub target: RenderToTexture,
+}
+
+impl PlanarReflection {
+    pub fn new(plane: Plane, size: (i32, i32)) -> PlanarReflection {
+        engine_debug!(logging::RENDERER, "planar reflection of {}x{}", size.0, size.1);
+        PlanarReflection {
+            plane,
+            clip_offset: 0.01,
+            target: RenderToTexture::new(Camera::default(), size),
+        }
+    }
+
+    pub fn texture(&self) -> u32 {
+        self.target.texture()
+    }
+
+    /// Call it with the main viewport size, the reflection is sampled in screen space
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.target.resize(width, height);
+    }
+
+    /// `(normal, distance)` of the plane keeping what's in front of the surface
+    pub fn clip_plane(&self) -> Vector4 {
+        self.plane.normal.extend(self.plane.distance + self.clip_offset)
+    }
+
+    /// Draws what `camera` sees reflected, before the main pass like `RenderToTexture::render`,
+    /// with clip distance 0 enabled while `submit`'s commands are drawn
+    pub fn render<F: FnOnce(&SceneView, &mut Renderer)>(&mut self, renderer: &mut Renderer, camera: &Camera, submit: F) {
+        self.target.view.camera = mirror_camera(camera, &self.plane);
+        unsafe {
+            gl::Enable(gl::CLIP_DISTANCE0);
+        }
+        self.target.render(renderer, submit);
+        unsafe {
+            gl::Disable(gl::CLIP_DISTANCE0);
+        }
+    }
+
+    /// Binds the reflection on texture `unit` and sets the `PLANAR_REFLECTION_GLSL` uniforms of
+    /// `shader`, which has to be in use; `viewport` is where the surface is drawn
+    pub fn bind(&self, shader: &Shader, unit: u32, viewport: &Viewport) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture());
+            shader.setInt(c_str!("planarReflectionMap"), unit as i32);
+            shader.setVec4(c_str!("planarReflectionViewport"), viewport.x as Float, viewport.y as Float,
+                           viewport.width as Float, viewport.height as Float);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        self.target.delete();
+    }
+}
+
+/// Local reflections of a room or an area: the scene captured into a cube map from `position`,
+/// looked up with a box projection so the reflections line up with the walls of `bounds`.
+/// Capture it once, or again when the surroundings change, and sample it with
+/// `REFLECTION_PROBE_GLSL`.
+pub struct ReflectionProbe {
+    pub position: Point3,
+    /// the volume the probe covers, usually the walls of the room
+    pub bounds: Aabb,
+    pub resolution: i32,
+    pub near: Float,
+    pub far: Float,
+    pub clear: ClearSpec,
+    mip_levels: i32,
+    texture: u32,
+    fbo: u32,
+    depth_rbo: u32,
+}
+
+impl ReflectionProbe {
+    pub fn new(position: Point3, bounds: Aabb, resolution: i32) -> ReflectionProbe {
+        let mip_levels = 1 + (resolution.max(1) as f32).log2().floor() as i32;
+        let mut probe = ReflectionProbe {
+            position,
+            bounds,
+            resolution,
+            near: 0.05,
+            far: 100.0,
+            clear: ClearSpec::default(),
+            mip_levels,
+            texture: 0,
+            fbo: 0,
+            depth_rbo: 0,
+        };
+        unsafe {
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+            gl::GenTextures(1, &mut probe.texture);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, probe.texture);
+            for face in 0..6 {
+                gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, 0, gl::RGBA16F as GLint,
+                               resolution, resolution, 0, gl::RGBA, gl::FLOAT, ptr::null());
+            }
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            for &axis in &[gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::TexParameteri(gl::TEXTURE_CUBE_MAP, axis, gl::CLAMP_TO_EDGE as GLint);
+            }
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+
+            gl::GenRenderbuffers(1, &mut probe.depth_rbo);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, probe.depth_rbo);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, resolution, resolution);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+            gl::GenFramebuffers(1, &mut probe.fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, probe.fbo);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, probe.depth_rbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        engine_debug!(logging::RENDERER, "reflection probe of {}x{} at {:?}", resolution, resolution, position);
+        probe
+    }
+
+    /// Color cube map with its mip chain
+    pub fn texture(&self) -> u32 {
+        self.texture
+    }
+
+    /// Views of the six faces, 90 degrees square cameras at `position`
+    pub fn face_views(&self) -> Vec<SceneView> {
+        CUBE_FACES.iter()
+            .map(|&(direction, up)| {
+                let mut camera = Camera::default();
+                camera.position = self.position;
+                camera.front = Vector3::from(direction);
+                camera.up = Vector3::from(up);
+                camera.right = camera.front.cross(camera.up);
+                camera.zoom = 90.0;
+                camera.near = self.near;
+                camera.far = self.far;
+                SceneView::new(camera, Viewport::full(self.resolution, self.resolution)).clear(self.clear)
+            })
+            .collect()
+    }
+
+    /// Captures the surroundings: `submit` queues the commands of each face's view, which are
+    /// drawn into it, then the mip chain is rebuilt. The viewport is restored afterwards.
+    pub fn capture<F: FnMut(&SceneView, &mut Renderer)>(&mut self, renderer: &mut Renderer, mut submit: F) {
+        let mut previous = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, previous.as_mut_ptr());
+        }
+        for (face, view) in self.face_views().iter().enumerate() {
+            submit(view, renderer);
+            unsafe {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0,
+                                         gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum, self.texture, 0);
+            }
+            renderer.render_views(::std::slice::from_ref(view));
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture);
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+        Viewport::new(previous[0], previous[1], previous[2], previous[3]).apply();
+    }
+
+    /// Binds the cube on texture `unit` and sets the `REFLECTION_PROBE_GLSL` uniforms of
+    /// `shader`, which has to be in use
+    pub fn bind(&self, shader: &Shader, unit: u32) {
+        let (p, min, max) = (self.position, self.bounds.min, self.bounds.max);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture);
+            shader.setInt(c_str!("probeMap"), unit as i32);
+            shader.setVec3(c_str!("probePosition"), p.x, p.y, p.z);
+            shader.setVec3(c_str!("probeBoxMin"), min.x, min.y, min.z);
+            shader.setVec3(c_str!("probeBoxMax"), max.x, max.y, max.z);
+            shader.setFloat(c_str!("probeMipLevels"), self.mip_levels as Float);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(1, &self.depth_rbo);
+            gl::DeleteTextures(1, &self.texture);
+        }
+        self.fbo = 0;
+        self.depth_rbo = 0;
+        self.texture = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn mirrored_view_is_the_reflection_flipped_horizontally() {
+        let mut camera = Camera::default();
+        camera.position = Point3::new(1.0, 2.0, 5.0);
+        camera.front = (Point3::new(0.0, 0.0, 0.0) - camera.position).normalize();
+        camera.right = camera.front.cross(Vector3::unit_y()).normalize();
+        camera.up = camera.right.cross(camera.front);
+
+        let water = Plane::new(Vector3::unit_y(), Point3::new(0.0, 0.0, 0.0));
+        let mirrored = mirror_camera(&camera, &water);
+        assert_eq!(mirrored.position, Point3::new(1.0, -2.0, 5.0));
+
+        let point = Point3::new(-0.5, 1.5, 1.0);
+        let seen = camera.view_matrix().transform_point(water.reflect_point(point));
+        let reflected = mirrored.view_matrix().transform_point(point);
+        assert!((seen.x + reflected.x).abs() < 1e-4 && (seen.y - reflected.y).abs() < 1e-4
+            && (seen.z - reflected.z).abs() < 1e-4, "{:?} {:?}", seen, reflected);
+    }
+
+    #[test]
+    fn box_projection_hits_the_walls() {
+        let room = Aabb::new(Point3::new(-2.0, 0.0, -2.0), Point3::new(2.0, 3.0, 2.0));
+        let probe = Point3::new(0.0, 1.0, 0.0);
+        let hit = box_project(&room, probe, Point3::new(1.0, 1.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(hit, Vector3::new(2.0, 0.0, 0.0));
+        let corner = box_project(&room, probe, Point3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 1.0, 1.0));
+        assert_eq!(corner, Vector3::new(0.0, 2.0, 2.0));
+    }
+}
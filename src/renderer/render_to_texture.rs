@@ -0,0 +1,57 @@
+use gl;
+
+use camera::Camera;
+use gl_state::ClearSpec;
+use render_target::RenderTarget;
+use viewport::Viewport;
+use super::{Renderer, SceneView};
+
+/// Renders the scene from a secondary camera into a texture every frame, for mirrors, portals,
+/// monitors and minimaps. Use `texture()` as the material texture of the surface showing it.
+pub struct RenderToTexture {
+    pub target: RenderTarget,
+    pub view: SceneView,
+}
+
+impl RenderToTexture {
+    pub fn new(camera: Camera, (width, height): (i32, i32)) -> RenderToTexture {
+        let mut target = RenderTarget::new(width, height, 0);
+        target.clear = ClearSpec::default();
+        RenderToTexture {
+            target,
+            view: SceneView::new(camera, Viewport::full(width, height)),
+        }
+    }
+
+    /// Sampleable color texture holding the last render
+    pub fn texture(&self) -> u32 {
+        self.target.color_texture
+    }
+
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.target.resize(width, height);
+        self.view.viewport = Viewport::full(width, height);
+    }
+
+    /// Lets `submit` queue the commands visible from `view` and draws them into the texture.
+    /// Call it before submitting the main pass: the renderer's whole queue is drawn and emptied.
+    /// The previously bound viewport is restored and the default framebuffer bound afterwards.
+    pub fn render<F: FnOnce(&SceneView, &mut Renderer)>(&mut self, renderer: &mut Renderer, submit: F) {
+        let mut previous = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, previous.as_mut_ptr());
+        }
+
+        submit(&self.view, renderer);
+        self.target.begin(&mut renderer.gl_state);
+        renderer.render_views(::std::slice::from_ref(&self.view));
+        self.target.resolve();
+        self.target.unbind();
+
+        Viewport::new(previous[0], previous[1], previous[2], previous[3]).apply();
+    }
+
+    pub fn delete(&mut self) {
+        self.target.delete();
+    }
+}
@@ -0,0 +1,262 @@
+use std::ffi::CString;
+use std::ptr;
+
+use cgmath::ortho;
+use gl;
+use gl::types::*;
+
+use lang::prelude::*;
+use lang::{Float, Point3, Vector3, Vector4, Matrix4};
+use camera::Camera;
+use gl_state::{ClearSpec, GlState};
+use logging;
+use shader::Shader;
+use viewport::Viewport;
+
+/// Cascades the shaders can sample, `CascadeShadows` allocates at most this many
+pub const MAX_CASCADES: usize = 4;
+
+/// `cascadeShadow(worldPos, viewDepth, bias)`, 1 lit and 0 in shadow, with the uniforms
+/// `CascadeShadows::bind` sets. Paste it into fragment shaders after the `#version` line.
+pub const CASCADE_SHADOW_GLSL: &str = r#"
+#define MAX_CASCADES 4
+uniform sampler2DArrayShadow shadowMap;
+uniform mat4 cascadeMatrices[MAX_CASCADES];
+uniform float cascadeSplits[MAX_CASCADES];
+uniform int cascadeCount;
+uniform float cascadeBlend;
+
+float cascadeSample(int cascade, vec3 worldPos, float bias)
+{
+    vec4 lightPos = cascadeMatrices[cascade] * vec4(worldPos, 1.0);
+    vec3 coords = lightPos.xyz / lightPos.w * 0.5 + 0.5;
+    if (coords.z > 1.0)
+        return 1.0;
+    vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0).xy);
+    float lit = 0.0;
+    for (int x = -1; x <= 1; ++x)
+        for (int y = -1; y <= 1; ++y)
+            lit += texture(shadowMap, vec4(coords.xy + vec2(x, y) * texel, float(cascade), coords.z - bias));
+    return lit / 9.0;
+}
+
+// viewDepth is the distance along the camera's view direction
+float cascadeShadow(vec3 worldPos, float viewDepth, float bias)
+{
+    for (int i = 0; i < cascadeCount; ++i) {
+        if (viewDepth < cascadeSplits[i]) {
+            float lit = cascadeSample(i, worldPos, bias);
+            // fade into the next cascade over the end of this one, hiding the seam
+            float start = cascadeSplits[i] * (1.0 - cascadeBlend);
+            if (i + 1 < cascadeCount && viewDepth > start)
+                lit = mix(lit, cascadeSample(i + 1, worldPos, bias), (viewDepth - start) / (cascadeSplits[i] - start));
+            return lit;
+        }
+    }
+    return 1.0;
+}
+"#;
+
+/// Split distances of `count` cascades between `near` and `far`, `near` first: `lambda` 0
+/// splits uniformly, 1 logarithmically, in between blends the two (the "practical" scheme)
+pub fn cascade_splits(near: Float, far: Float, count: usize, lambda: Float) -> Vec<Float> {
+    let mut splits = Vec::with_capacity(count + 1);
+    splits.push(near);
+    for i in 1..=count {
+        let p = i as Float / count as Float;
+        let log = near * (far / near).powf(p);
+        let uniform = near + (far - near) * p;
+        splits.push(lambda * log + (1.0 - lambda) * uniform);
+    }
+    splits
+}
+
+/// World-space corners of the camera frustum between the distances `near` and `far`
+pub fn slice_corners(camera: &Camera, aspect: Float, near: Float, far: Float) -> [Point3; 8] {
+    let tan = (camera.zoom.to_radians() * 0.5).tan();
+    let mut corners = [Point3::origin(); 8];
+    for (i, &distance) in [near, far].iter().enumerate() {
+        let center = camera.position + camera.front * distance;
+        let (up, right) = (camera.up * tan * distance, camera.right * tan * aspect * distance);
+        corners[i * 4] = center - right - up;
+        corners[i * 4 + 1] = center + right - up;
+        corners[i * 4 + 2] = center + right + up;
+        corners[i * 4 + 3] = center - right + up;
+    }
+    corners
+}
+
+/// Light view-projection covering `corners` for a light shining along `direction`, fitted
+/// to their bounding sphere and snapped to shadow map texels so the shadows don't shimmer
+/// as the camera moves. `caster_margin` extends the box towards the light for casters
+/// outside the view.
+pub fn cascade_matrix(corners: &[Point3; 8], direction: Vector3, resolution: i32, caster_margin: Float) -> Matrix4 {
+    let center = Point3::from_vec(corners.iter().fold(Vector3::zero(), |sum, corner| sum + corner.to_vec()) / 8.0);
+    let radius = corners.iter().map(|&corner| (corner - center).magnitude()).fold(0.0, Float::max);
+    // rounded so the box size, and with it the texel size, doesn't change as the camera turns
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let direction = direction.normalize();
+    let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let eye = center - direction * (radius + caster_margin);
+    let view = Matrix4::look_at(eye, center, up);
+    let mut projection = ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + caster_margin);
+
+    let origin = projection * view * Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let half = resolution as Float * 0.5;
+    let (x, y) = (origin.x * half, origin.y * half);
+    projection.w.x += (x.round() - x) / half;
+    projection.w.y += (y.round() - y) / half;
+    projection * view
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cascade {
+    /// view distance this cascade covers up to
+    pub far: Float,
+    pub matrix: Matrix4,
+}
+
+/// Cascaded shadow maps of a directional light: each frame `update` splits the camera frustum
+/// and fits a light matrix to every slice, `render` draws the casters into a layer of a depth
+/// texture array per cascade, and `bind` sets what `CASCADE_SHADOW_GLSL` samples.
+pub struct CascadeShadows {
+    pub resolution: i32,
+    /// see `cascade_splits`
+    pub lambda: Float,
+    /// shadows end at this view distance, or the camera's far plane if closer
+    pub max_distance: Float,
+    /// fraction of each cascade blended into the next one
+    pub blend: Float,
+    pub caster_margin: Float,
+    pub cascades: Vec<Cascade>,
+    texture: u32,
+    fbo: u32,
+}
+
+impl CascadeShadows {
+    pub fn new(count: usize, resolution: i32) -> CascadeShadows {
+        let count = count.clamp(1, MAX_CASCADES);
+        let mut shadows = CascadeShadows {
+            resolution,
+            lambda: 0.75,
+            max_distance: 100.0,
+            blend: 0.1,
+            caster_margin: 50.0,
+            cascades: vec![Cascade { far: 0.0, matrix: Matrix4::identity() }; count],
+            texture: 0,
+            fbo: 0,
+        };
+        unsafe {
+            gl::GenTextures(1, &mut shadows.texture);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, shadows.texture);
+            gl::TexImage3D(gl::TEXTURE_2D_ARRAY, 0, gl::DEPTH_COMPONENT24 as GLint, resolution, resolution, count as GLsizei,
+                           0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+            gl::TexParameterfv(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_BORDER_COLOR, [1.0f32; 4].as_ptr());
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+
+            gl::GenFramebuffers(1, &mut shadows.fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, shadows.fbo);
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        engine_debug!(logging::RENDERER, "{} shadow cascades of {}x{}", count, resolution, resolution);
+        shadows
+    }
+
+    /// Depth texture array, one layer per cascade
+    pub fn texture(&self) -> u32 {
+        self.texture
+    }
+
+    /// Fits the cascades to `camera` seen with `aspect` and a light shining along `direction`
+    pub fn update(&mut self, camera: &Camera, aspect: Float, direction: Vector3) {
+        let far = camera.far.min(self.max_distance);
+        let splits = cascade_splits(camera.near, far, self.cascades.len(), self.lambda);
+        for (i, cascade) in self.cascades.iter_mut().enumerate() {
+            let corners = slice_corners(camera, aspect, splits[i], splits[i + 1]);
+            cascade.far = splits[i + 1];
+            cascade.matrix = cascade_matrix(&corners, direction, self.resolution, self.caster_margin);
+        }
+    }
+
+    /// Calls `draw` once per cascade with the cascade index and its light matrix, bound to the
+    /// cascade's layer with depth cleared. Use a depth-only shader such as the built-in
+    /// `shadow_depth` with `lightMatrix` set to the matrix.
+    pub fn render<F: FnMut(usize, &Matrix4)>(&self, state: &mut GlState, mut draw: F) {
+        let mut previous = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, previous.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        }
+        Viewport::full(self.resolution, self.resolution).apply();
+        for (i, cascade) in self.cascades.iter().enumerate() {
+            unsafe {
+                gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.texture, 0, i as GLint);
+            }
+            ClearSpec::none().depth(1.0).apply(state);
+            draw(i, &cascade.matrix);
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        Viewport::new(previous[0], previous[1], previous[2], previous[3]).apply();
+    }
+
+    /// Binds the shadow maps on texture `unit` and sets the cascade uniforms of `shader`,
+    /// which has to be in use
+    pub fn bind(&self, shader: &Shader, unit: u32) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.texture);
+            shader.setInt(c_str!("shadowMap"), unit as i32);
+            shader.setInt(c_str!("cascadeCount"), self.cascades.len() as i32);
+            shader.setFloat(c_str!("cascadeBlend"), self.blend);
+            for (i, cascade) in self.cascades.iter().enumerate() {
+                let matrix = CString::new(format!("cascadeMatrices[{}]", i)).unwrap();
+                let split = CString::new(format!("cascadeSplits[{}]", i)).unwrap();
+                shader.setMat4(&matrix, &cascade.matrix);
+                shader.setFloat(&split, cascade.far);
+            }
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.texture);
+        }
+        self.fbo = 0;
+        self.texture = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascades_cover_their_slices() {
+        let splits = cascade_splits(0.1, 100.0, 4, 0.75);
+        assert_eq!(splits.len(), 5);
+        assert!((splits[4] - 100.0).abs() < 1e-3);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]));
+        // logarithmic weighting keeps the first cascade short
+        assert!(splits[1] < 10.0);
+
+        let camera = Camera::default();
+        let corners = slice_corners(&camera, 16.0 / 9.0, splits[1], splits[2]);
+        let matrix = cascade_matrix(&corners, Vector3::new(-0.3, -1.0, -0.2), 2048, 50.0);
+        for corner in &corners {
+            let ndc = Point3::from_homogeneous(matrix * corner.to_homogeneous());
+            assert!(ndc.x.abs() <= 1.002 && ndc.y.abs() <= 1.002 && ndc.z.abs() <= 1.0, "{:?}", ndc);
+        }
+    }
+}
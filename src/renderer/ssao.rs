@@ -0,0 +1,359 @@
+use std::ffi::CString;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{Float, Matrix4, Vector3};
+use lang::rng::Rng;
+use gl_state::GlState;
+use logging;
+use renderer::SceneView;
+use renderer::depth_copy::DepthCopy;
+use renderer::taa::allocate_target;
+use shader::Shader;
+
+/// Samples the shader takes, longer kernels are cut
+pub const MAX_KERNEL_SIZE: usize = 64;
+
+/// Unit the renderer binds the occlusion to for `SSAO_GLSL`, the last one GL 3.3 guarantees
+pub const SSAO_TEXTURE_UNIT: u32 = 15;
+
+/// Side of the tile of random kernel rotations, also the blur's
+const NOISE_SIZE: usize = 4;
+
+/// `#include "reactor/ssao.glsl"`: `ambientOcclusion()` is the occlusion of the opaque geometry
+/// at the fragment, 1 unoccluded, to scale the ambient light with. The renderer binds it while
+/// `Renderer::ssao` is set, and it is 1 everywhere otherwise.
+pub const SSAO_GLSL: &str = r#"
+uniform sampler2D ssaoMap;
+uniform bool ssaoEnabled;
+
+float ambientOcclusion()
+{
+    return ssaoEnabled ? texelFetch(ssaoMap, ivec2(gl_FragCoord.xy), 0).r : 1.0;
+}
+"#;
+
+const FULLSCREEN_VERTEX_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+const OCCLUSION_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out float Occlusion;
+
+uniform sampler2D sceneDepth;
+// world space, without them the normals are reconstructed from the depth
+uniform sampler2D sceneNormals;
+uniform bool hasNormals;
+uniform sampler2D noise;
+uniform vec3 kernel[64];
+uniform int kernelSize;
+uniform vec4 viewportRect;
+uniform mat4 view;
+uniform mat4 viewProjection;
+uniform mat4 inverseViewProjection;
+uniform vec3 cameraPosition;
+uniform float radius;
+uniform float bias;
+uniform float intensity;
+
+vec3 worldPosition(vec2 fragCoord, float depth)
+{
+    vec2 ndc = (fragCoord - viewportRect.xy) / viewportRect.zw * 2.0 - 1.0;
+    vec4 world = inverseViewProjection * vec4(ndc, depth * 2.0 - 1.0, 1.0);
+    return world.xyz / world.w;
+}
+
+void main()
+{
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(sceneDepth, pixel, 0).r;
+    if (depth >= 1.0) {
+        Occlusion = 1.0;
+        return;
+    }
+    vec3 position = worldPosition(gl_FragCoord.xy, depth);
+    vec3 normal = hasNormals ? texelFetch(sceneNormals, pixel, 0).xyz : cross(dFdx(position), dFdy(position));
+    normal = normalize(normal);
+    if (dot(normal, cameraPosition - position) < 0.0)
+        normal = -normal;
+
+    // the hemisphere around the normal, turned by the pixel's entry of the noise tile
+    vec3 turn = vec3(texelFetch(noise, pixel % 4, 0).xy, 0.0);
+    vec3 tangent = normalize(turn - normal * dot(turn, normal));
+    mat3 frame = mat3(tangent, cross(normal, tangent), normal);
+    float depthHere = (view * vec4(position, 1.0)).z;
+
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    float occlusion = 0.0;
+    for (int i = 0; i < kernelSize; ++i) {
+        vec3 probe = position + frame * kernel[i] * radius;
+        vec4 clip = viewProjection * vec4(probe, 1.0);
+        vec2 fragCoord = viewportRect.xy + (clip.xy / clip.w * 0.5 + 0.5) * viewportRect.zw;
+        ivec2 at = clamp(ivec2(fragCoord), lo, hi);
+        vec3 surface = worldPosition(vec2(at) + 0.5, texelFetch(sceneDepth, at, 0).r);
+        // view space z grows towards the camera
+        float probeDepth = (view * vec4(probe, 1.0)).z;
+        float surfaceDepth = (view * vec4(surface, 1.0)).z;
+        // surfaces far in front of the point don't shadow it
+        float range = smoothstep(0.0, 1.0, radius / abs(depthHere - surfaceDepth));
+        occlusion += (surfaceDepth >= probeDepth + bias ? 1.0 : 0.0) * range;
+    }
+    Occlusion = pow(1.0 - occlusion / float(max(kernelSize, 1)), intensity);
+}
+"#;
+
+const BLUR_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out float Occlusion;
+
+uniform sampler2D occlusion;
+uniform vec4 viewportRect;
+
+void main()
+{
+    // averaging over the noise tile hides its pattern
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    float sum = 0.0;
+    for (int y = -2; y < 2; ++y) {
+        for (int x = -2; x < 2; ++x) {
+            sum += texelFetch(occlusion, clamp(ivec2(gl_FragCoord.xy) + ivec2(x, y), lo, hi), 0).r;
+        }
+    }
+    Occlusion = sum / 16.0;
+}
+"#;
+
+/// `size` sample offsets in the unit hemisphere around +Z, denser towards its center
+pub fn hemisphere_kernel(size: usize, seed: u64) -> Vec<Vector3> {
+    let mut rng = Rng::new(seed);
+    (0..size)
+        .map(|i| {
+            let mut direction = rng.unit_vector();
+            direction.z = direction.z.abs();
+            // keep the samples off the surface's own plane
+            direction.z = direction.z.max(0.15);
+            let t = i as Float / size as Float;
+            direction.normalize() * rng.next_float() * (0.1 + 0.9 * t * t)
+        })
+        .collect()
+}
+
+/// Random rotations of the kernel around the normal, the xy of unit vectors in the plane
+fn noise_texels(seed: u64) -> Vec<GLfloat> {
+    let mut rng = Rng::new(seed);
+    (0..NOISE_SIZE * NOISE_SIZE)
+        .flat_map(|_| {
+            let turn = rng.unit_vector2();
+            vec![turn.x as GLfloat, turn.y as GLfloat, 0.0, 0.0]
+        })
+        .collect()
+}
+
+/// Screen space ambient occlusion of the opaque geometry, computed while set as
+/// `Renderer::ssao`: after the G-buffer of `DeferredShading` from its depth and normals, or
+/// else from a depth-only pass over the opaque commands drawn before them, with normals
+/// taken from the depth. A blur over the noise tile follows, and `SSAO_GLSL` reads the result.
+pub struct Ssao {
+    /// world space reach of the samples
+    pub radius: Float,
+    /// depth difference ignored, against self-occlusion
+    pub bias: Float,
+    /// exponent of the result, higher darkens more
+    pub intensity: Float,
+    kernel: Vec<Vector3>,
+    /// R8 raw and blurred occlusion
+    textures: [u32; 2],
+    fbos: [u32; 2],
+    noise: u32,
+    size: (i32, i32),
+    depth: DepthCopy,
+    occlusion_shader: Shader,
+    blur_shader: Shader,
+    vao: u32,
+}
+
+impl Ssao {
+    pub fn new() -> Ssao {
+        let mut ssao = Ssao {
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+            kernel: hemisphere_kernel(16, 0),
+            textures: [0; 2],
+            fbos: [0; 2],
+            noise: 0,
+            size: (0, 0),
+            depth: DepthCopy::new(),
+            occlusion_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, OCCLUSION_FRAGMENT_SHADER),
+            blur_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, BLUR_FRAGMENT_SHADER),
+            vao: 0,
+        };
+        let noise = noise_texels(1);
+        unsafe {
+            gl::GenTextures(2, ssao.textures.as_mut_ptr());
+            gl::GenFramebuffers(2, ssao.fbos.as_mut_ptr());
+            gl::GenVertexArrays(1, &mut ssao.vao);
+            gl::GenTextures(1, &mut ssao.noise);
+            gl::BindTexture(gl::TEXTURE_2D, ssao.noise);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG16F as GLint, NOISE_SIZE as GLsizei, NOISE_SIZE as GLsizei, 0,
+                           gl::RGBA, gl::FLOAT, noise.as_ptr() as *const GLvoid);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        ssao
+    }
+
+    /// Samples per pixel, up to `MAX_KERNEL_SIZE`
+    pub fn set_kernel_size(&mut self, size: usize) {
+        self.kernel = hemisphere_kernel(size.clamp(1, MAX_KERNEL_SIZE), 0);
+    }
+
+    pub fn kernel_size(&self) -> usize {
+        self.kernel.len()
+    }
+
+    /// Blurred occlusion of the last computed view, R8
+    pub fn texture(&self) -> u32 {
+        self.textures[1]
+    }
+
+    /// Computes the occlusion of the depth (and normals) drawn so far into the framebuffer bound
+    pub(crate) fn compute_from_framebuffer(&mut self, state: &mut GlState, view: &SceneView) {
+        self.depth.copy(&view.viewport, "ssao");
+        let depth = self.depth.texture;
+        self.compute(state, view, depth, None);
+    }
+
+    /// Computes the occlusion within `view.viewport` from a depth texture and optionally world
+    /// space normals of the same size, and binds the framebuffer drawn into before again.
+    /// Leaves blending, depth testing and culling off.
+    pub(crate) fn compute(&mut self, state: &mut GlState, view: &SceneView, depth: u32, normals: Option<u32>) {
+        let v = view.viewport;
+        let size = (v.x + v.width, v.y + v.height);
+        let mut framebuffer = 0;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            if size.0 > self.size.0 || size.1 > self.size.1 {
+                self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+                for (&texture, &fbo) in self.textures.iter().zip(self.fbos.iter()) {
+                    allocate_target(texture, gl::R8, self.size, gl::NEAREST);
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+                    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+                }
+                engine_debug!(logging::RENDERER, "ssao targets of {}x{}", self.size.0, self.size.1);
+            }
+        }
+        // the textures were bound outside of the cache
+        state.invalidate();
+        state.bind_vertex_array(self.vao);
+        state.set_blend(false);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        state.color_mask(true);
+
+        let view_projection = view.view_projection();
+        let inverse_view_projection = view_projection.invert().unwrap_or_else(Matrix4::identity);
+        let c = view.camera.position;
+        let shader = self.occlusion_shader;
+        state.use_program(shader.ID);
+        state.bind_texture(0, depth);
+        state.bind_texture(1, normals.unwrap_or(0));
+        state.bind_texture(2, self.noise);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbos[0]);
+            shader.setInt(c_str!("sceneDepth"), 0);
+            shader.setInt(c_str!("sceneNormals"), 1);
+            shader.setBool(c_str!("hasNormals"), normals.is_some());
+            shader.setInt(c_str!("noise"), 2);
+            shader.setInt(c_str!("kernelSize"), self.kernel.len() as i32);
+            for (i, sample) in self.kernel.iter().enumerate() {
+                let name = CString::new(format!("kernel[{}]", i)).unwrap();
+                shader.setVec3(&name, sample.x, sample.y, sample.z);
+            }
+            shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+            shader.setMat4(c_str!("view"), &view.camera.view_matrix());
+            shader.setMat4(c_str!("viewProjection"), &view_projection);
+            shader.setMat4(c_str!("inverseViewProjection"), &inverse_view_projection);
+            shader.setVec3(c_str!("cameraPosition"), c.x, c.y, c.z);
+            shader.setFloat(c_str!("radius"), self.radius);
+            shader.setFloat(c_str!("bias"), self.bias);
+            shader.setFloat(c_str!("intensity"), self.intensity);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+
+        let shader = self.blur_shader;
+        state.use_program(shader.ID);
+        state.bind_texture(0, self.textures[0]);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbos[1]);
+            shader.setInt(c_str!("occlusion"), 0);
+            shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer as u32);
+        }
+        for unit in 0..3 {
+            state.bind_texture(unit, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        self.depth.delete();
+        unsafe {
+            gl::DeleteTextures(2, self.textures.as_ptr());
+            gl::DeleteTextures(1, &self.noise);
+            gl::DeleteFramebuffers(2, self.fbos.as_ptr());
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.occlusion_shader.ID);
+            gl::DeleteProgram(self.blur_shader.ID);
+        }
+        self.fbos = [0; 2];
+        self.size = (0, 0);
+    }
+}
+
+impl Default for Ssao {
+    fn default() -> Ssao {
+        Ssao::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shader::preprocess::{Defines, Preprocessor};
+
+    #[test]
+    fn kernel_fills_the_hemisphere_towards_its_center() {
+        let kernel = hemisphere_kernel(32, 7);
+        assert_eq!(kernel.len(), 32);
+        assert_eq!(kernel, hemisphere_kernel(32, 7));
+        for sample in kernel.iter() {
+            assert!(sample.z >= 0.0 && sample.magnitude() <= 1.0 + 1e-5, "{:?}", sample);
+        }
+        // the scale grows with the index
+        let first: Float = kernel[..8].iter().map(|s| s.magnitude()).sum();
+        let last: Float = kernel[24..].iter().map(|s| s.magnitude()).sum();
+        assert!(first < last);
+
+        let noise = noise_texels(1);
+        assert_eq!(noise.len(), NOISE_SIZE * NOISE_SIZE * 4);
+        assert!(noise.chunks(4).all(|t| ((t[0] * t[0] + t[1] * t[1]) - 1.0).abs() < 1e-4 && t[2] == 0.0));
+
+        let source = "#version 330 core\n#include \"reactor/ssao.glsl\"\nout vec4 FragColor;\nvoid main() { FragColor = vec4(ambientOcclusion()); }\n";
+        let processed = Preprocessor::new().process("smoke.frag", source, &Defines::new()).unwrap();
+        assert!(processed.source.contains("uniform sampler2D ssaoMap;"));
+    }
+}
@@ -0,0 +1,453 @@
+use std::ptr;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{Float, Matrix4, Vector2};
+use camera::Camera;
+use gl_state::GlState;
+use logging;
+use render_target::RenderTarget;
+use renderer::SceneView;
+use renderer::depth_copy::DepthCopy;
+use shader::Shader;
+
+/// `#include "reactor/velocity.glsl"`: motion vectors of the `DrawCommand::velocity` program of
+/// an opaque command. Its vertex shader projects the vertex with `velocityViewProjection` and
+/// the model matrix, and with `previousViewProjection` and the model matrix of the previous
+/// frame, both set by the renderer except the model matrices. The fragment shader includes
+/// this and calls `writeVelocity` with the two clip positions.
+pub const VELOCITY_GLSL: &str = r#"
+layout (location = 0) out vec2 Velocity;
+
+void writeVelocity(vec4 currentClip, vec4 previousClip)
+{
+    // in viewport UV units, from last frame's position to this frame's
+    Velocity = (currentClip.xy / currentClip.w - previousClip.xy / previousClip.w) * 0.5;
+}
+"#;
+
+const FULLSCREEN_VERTEX_SHADER: &str = r#"
+#version 330 core
+
+void main()
+{
+    // one triangle covering the screen
+    vec2 ndc = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
+"#;
+
+const CAMERA_VELOCITY_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) out vec2 Velocity;
+
+uniform sampler2D sceneDepth;
+uniform vec4 viewportRect;
+// of the jittered projection the depth was drawn with
+uniform mat4 inverseViewProjection;
+uniform mat4 velocityViewProjection;
+uniform mat4 previousViewProjection;
+
+void main()
+{
+    vec2 ndc = (gl_FragCoord.xy - viewportRect.xy) / viewportRect.zw * 2.0 - 1.0;
+    float depth = texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r * 2.0 - 1.0;
+    vec4 world = inverseViewProjection * vec4(ndc, depth, 1.0);
+    world /= world.w;
+    vec4 current = velocityViewProjection * world;
+    vec4 previous = previousViewProjection * world;
+    Velocity = previous.w > 0.0 ? (current.xy / current.w - previous.xy / previous.w) * 0.5 : vec2(0.0);
+}
+"#;
+
+const RESOLVE_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D scene;
+uniform sampler2D history;
+uniform sampler2D velocity;
+uniform vec4 viewportRect;
+uniform vec2 historySize;
+uniform float feedback;
+
+void main()
+{
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    vec4 current = texelFetch(scene, center, 0);
+
+    // the history is only trusted within the colors around the pixel this frame, which
+    // rejects disoccluded and changed surfaces
+    vec3 minimum = current.rgb;
+    vec3 maximum = current.rgb;
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            vec3 neighbour = texelFetch(scene, clamp(center + ivec2(x, y), lo, hi), 0).rgb;
+            minimum = min(minimum, neighbour);
+            maximum = max(maximum, neighbour);
+        }
+    }
+
+    vec2 previous = gl_FragCoord.xy - texelFetch(velocity, center, 0).xy * viewportRect.zw;
+    bool inside = all(greaterThanEqual(previous, viewportRect.xy))
+        && all(lessThan(previous, viewportRect.xy + viewportRect.zw));
+    vec3 past = clamp(texture(history, previous / historySize).rgb, minimum, maximum);
+    FragColor = vec4(mix(current.rgb, past, inside ? feedback : 0.0), current.a);
+}
+"#;
+
+const SHARPEN_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler2D resolved;
+uniform vec4 viewportRect;
+uniform float sharpness;
+
+void main()
+{
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    ivec2 lo = ivec2(viewportRect.xy);
+    ivec2 hi = lo + ivec2(viewportRect.zw) - 1;
+    vec4 color = texelFetch(resolved, center, 0);
+    vec3 cross = texelFetch(resolved, clamp(center + ivec2(1, 0), lo, hi), 0).rgb
+        + texelFetch(resolved, clamp(center - ivec2(1, 0), lo, hi), 0).rgb
+        + texelFetch(resolved, clamp(center + ivec2(0, 1), lo, hi), 0).rgb
+        + texelFetch(resolved, clamp(center - ivec2(0, 1), lo, hi), 0).rgb;
+    // unsharp mask against the average of the four neighbours
+    FragColor = vec4(max(color.rgb + (color.rgb - cross * 0.25) * sharpness, 0.0), color.a);
+}
+"#;
+
+/// Allocates or grows a float texture of `format`, such as RGBA16F or RG16F, filtered by `filter`
+pub(crate) unsafe fn allocate_target(texture: u32, format: GLenum, size: (i32, i32), filter: GLenum) {
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, format as GLint, size.0, size.1, 0, gl::RGBA, gl::FLOAT, ptr::null());
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+}
+
+/// Screen-space motion of the opaque geometry of the first `Renderer::render_views` view,
+/// filled while set as `Renderer::velocity`. Every pixel gets the motion of the camera
+/// reprojecting its depth, then commands with a `DrawCommand::velocity` program overwrite it
+/// with their own motion.
+pub struct VelocityBuffer {
+    /// RG16F motion in viewport UV units since the previous frame
+    pub texture: u32,
+    /// writes the camera motion, without a depth attachment since it samples the depth
+    camera_fbo: u32,
+    /// the velocity programs, depth tested against the scene
+    fbo: u32,
+    size: (i32, i32),
+    depth: DepthCopy,
+    camera_shader: Shader,
+    vao: u32,
+    previous_view_projection: Option<Matrix4>,
+}
+
+impl VelocityBuffer {
+    pub fn new() -> VelocityBuffer {
+        let mut buffer = VelocityBuffer {
+            texture: 0,
+            camera_fbo: 0,
+            fbo: 0,
+            size: (0, 0),
+            depth: DepthCopy::new(),
+            camera_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, CAMERA_VELOCITY_FRAGMENT_SHADER),
+            vao: 0,
+            previous_view_projection: None,
+        };
+        unsafe {
+            gl::GenTextures(1, &mut buffer.texture);
+            gl::GenFramebuffers(1, &mut buffer.camera_fbo);
+            gl::GenFramebuffers(1, &mut buffer.fbo);
+            gl::GenVertexArrays(1, &mut buffer.vao);
+        }
+        buffer
+    }
+
+    /// Forgets the previous camera so the next frame has no camera motion, call it on camera cuts
+    pub fn reset_history(&mut self) {
+        self.previous_view_projection = None;
+    }
+
+    /// Writes the camera motion of `view`, then binds the target for the velocity programs with
+    /// depth testing against the scene and no depth writes. Returns the framebuffer drawn into
+    /// before, with the unjittered and previous view-projections the programs need.
+    pub(crate) fn begin(&mut self, state: &mut GlState, view: &SceneView) -> (u32, (Matrix4, Matrix4)) {
+        let current = view.unjittered_view_projection();
+        let previous = self.previous_view_projection.replace(current).unwrap_or(current);
+        let inverse_view_projection = view.view_projection().invert().unwrap_or_else(Matrix4::identity);
+        let v = view.viewport;
+        self.depth.copy(&v, "velocity");
+        let size = (v.x + v.width, v.y + v.height);
+        let mut framebuffer = 0;
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut framebuffer);
+            if size.0 > self.size.0 || size.1 > self.size.1 {
+                self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+                allocate_target(self.texture, gl::RG16F, self.size, gl::NEAREST);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.camera_fbo);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.texture, 0);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.texture, 0);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, self.depth.texture, 0);
+                engine_debug!(logging::RENDERER, "velocity buffer of {}x{}", self.size.0, self.size.1);
+            }
+            // the texture was bound outside of the cache
+            state.invalidate();
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.camera_fbo);
+        }
+
+        state.use_program(self.camera_shader.ID);
+        state.bind_vertex_array(self.vao);
+        state.bind_texture(0, self.depth.texture);
+        state.set_blend(false);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        state.color_mask(true);
+        unsafe {
+            self.camera_shader.setInt(c_str!("sceneDepth"), 0);
+            self.camera_shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+            self.camera_shader.setMat4(c_str!("inverseViewProjection"), &inverse_view_projection);
+            self.camera_shader.setMat4(c_str!("velocityViewProjection"), &current);
+            self.camera_shader.setMat4(c_str!("previousViewProjection"), &previous);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        }
+        state.bind_texture(0, 0);
+        state.set_depth_test(true);
+        state.depth_func(gl::LEQUAL);
+        state.depth_mask(false);
+        (framebuffer as u32, (current, previous))
+    }
+
+    pub(crate) fn end(&self, framebuffer: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        self.depth.delete();
+        unsafe {
+            gl::DeleteTextures(1, &self.texture);
+            gl::DeleteFramebuffers(1, &self.camera_fbo);
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.camera_shader.ID);
+        }
+        self.texture = 0;
+        self.previous_view_projection = None;
+    }
+}
+
+impl Default for VelocityBuffer {
+    fn default() -> VelocityBuffer {
+        VelocityBuffer::new()
+    }
+}
+
+/// Temporal anti-aliasing: `jitter` moves the camera's projection by a different sub-pixel
+/// offset every frame, and `apply` blends each frame into a history reprojected with a
+/// `VelocityBuffer`. Unlike MSAA it also smooths specular and shader aliasing.
+pub struct TemporalAA {
+    /// weight of the history, higher is smoother but blurs motion more
+    pub feedback: Float,
+    /// unsharp mask strength applied to the output, 0 disables it
+    pub sharpness: Float,
+    /// length of the jitter sequence
+    pub jitter_samples: u32,
+    frame: u32,
+    history: [u32; 2],
+    fbos: [u32; 2],
+    /// index of the last written history
+    current: usize,
+    size: (i32, i32),
+    history_valid: bool,
+    resolve_shader: Shader,
+    sharpen_shader: Shader,
+    vao: u32,
+}
+
+impl TemporalAA {
+    pub fn new() -> TemporalAA {
+        let mut taa = TemporalAA {
+            feedback: 0.9,
+            sharpness: 0.25,
+            jitter_samples: 8,
+            frame: 0,
+            history: [0; 2],
+            fbos: [0; 2],
+            current: 0,
+            size: (0, 0),
+            history_valid: false,
+            resolve_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, RESOLVE_FRAGMENT_SHADER),
+            sharpen_shader: Shader::from_source(FULLSCREEN_VERTEX_SHADER, SHARPEN_FRAGMENT_SHADER),
+            vao: 0,
+        };
+        unsafe {
+            gl::GenTextures(2, taa.history.as_mut_ptr());
+            gl::GenFramebuffers(2, taa.fbos.as_mut_ptr());
+            gl::GenVertexArrays(1, &mut taa.vao);
+        }
+        taa
+    }
+
+    /// Sets the camera's jitter for the next frame, call it once per frame before rendering
+    pub fn jitter(&mut self, camera: &mut Camera) {
+        self.frame = self.frame.wrapping_add(1);
+        camera.jitter = jitter_offset(self.frame % self.jitter_samples.max(1));
+    }
+
+    /// Drops the history so the next frame starts over, call it on camera cuts
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    /// Blends the `view.viewport` area of `source`, rendered from `view` with the jitter of the
+    /// last `jitter`, into the history and draws the sharpened result into the same area of
+    /// `output`. Resolves `source` first. Leaves blending, depth testing and culling off, and
+    /// `output` bound.
+    pub fn apply(&mut self, state: &mut GlState, view: &SceneView, source: &RenderTarget, velocity: &VelocityBuffer,
+                 output: u32) {
+        source.resolve();
+        let v = view.viewport;
+        self.grow(state, (v.x + v.width, v.y + v.height));
+        let (read, write) = (self.current, 1 - self.current);
+        state.bind_vertex_array(self.vao);
+        state.set_blend(false);
+        state.set_depth_test(false);
+        state.set_cull_face(false);
+        state.color_mask(true);
+        v.apply();
+
+        let shader = self.resolve_shader;
+        state.use_program(shader.ID);
+        state.bind_texture(0, source.color_texture);
+        state.bind_texture(1, self.history[read]);
+        state.bind_texture(2, velocity.texture);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbos[write]);
+            shader.setInt(c_str!("scene"), 0);
+            shader.setInt(c_str!("history"), 1);
+            shader.setInt(c_str!("velocity"), 2);
+            shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+            shader.setVec2(c_str!("historySize"), self.size.0 as Float, self.size.1 as Float);
+            shader.setFloat(c_str!("feedback"), if self.history_valid { self.feedback } else { 0.0 });
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+
+        // the history stays unsharpened so sharpening doesn't build up over frames
+        let shader = self.sharpen_shader;
+        state.use_program(shader.ID);
+        state.bind_texture(0, self.history[write]);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, output);
+            shader.setInt(c_str!("resolved"), 0);
+            shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+            shader.setFloat(c_str!("sharpness"), self.sharpness);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+        self.current = write;
+        self.history_valid = true;
+    }
+
+    /// Grows the history to cover `size`, which drops it
+    fn grow(&mut self, state: &mut GlState, size: (i32, i32)) {
+        if size.0 <= self.size.0 && size.1 <= self.size.1 {
+            return;
+        }
+        self.size = (size.0.max(self.size.0), size.1.max(self.size.1));
+        unsafe {
+            for (&texture, &fbo) in self.history.iter().zip(self.fbos.iter()) {
+                allocate_target(texture, gl::RGBA16F, self.size, gl::LINEAR);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture, 0);
+            }
+        }
+        // the textures were bound outside of the cache
+        state.invalidate();
+        self.history_valid = false;
+        engine_debug!(logging::RENDERER, "taa history of {}x{}", self.size.0, self.size.1);
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteTextures(2, self.history.as_ptr());
+            gl::DeleteFramebuffers(2, self.fbos.as_ptr());
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.resolve_shader.ID);
+            gl::DeleteProgram(self.sharpen_shader.ID);
+        }
+        self.fbos = [0; 2];
+        self.history_valid = false;
+    }
+}
+
+impl Default for TemporalAA {
+    fn default() -> TemporalAA {
+        TemporalAA::new()
+    }
+}
+
+/// `index`th element of the Halton low-discrepancy sequence in `base`, in [0, 1)
+fn halton(mut index: u32, base: u32) -> Float {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as Float;
+        result += fraction * (index % base) as Float;
+        index /= base;
+    }
+    result
+}
+
+/// Sub-pixel jitter of frame `index` of the sequence, within half a pixel of the center
+fn jitter_offset(index: u32) -> Vector2 {
+    // starting at 1 skips the (0, 0) of the sequence, which would sit in a corner
+    Vector2::new(halton(index + 1, 2) - 0.5, halton(index + 1, 3) - 0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::Point3;
+    use shader::preprocess::{Defines, Preprocessor};
+    use viewport::Viewport;
+
+    #[test]
+    fn jitter_shifts_the_projection_by_sub_pixels() {
+        assert_eq!(halton(1, 2), 0.5);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-6);
+        for index in 0..8 {
+            let offset = jitter_offset(index);
+            assert!(offset.x.abs() <= 0.5 && offset.y.abs() <= 0.5);
+        }
+
+        let mut camera = Camera::default();
+        let viewport = Viewport::new(0, 0, 800, 600);
+        let point = Point3::new(0.3, -0.2, -2.0);
+        let project = |camera: &Camera| {
+            let clip = camera.projection_matrix(800, 600) * camera.view_matrix() * point.to_homogeneous();
+            Vector2::new(clip.x / clip.w * 400.0, clip.y / clip.w * 300.0)
+        };
+        let still = project(&camera);
+        camera.jitter = jitter_offset(3);
+        let moved = project(&camera) - still;
+        assert!((moved - camera.jitter).magnitude() < 1e-3, "{:?} {:?}", moved, camera.jitter);
+        // picking ignores the jitter
+        assert_eq!(camera.project(point, viewport), Camera { jitter: Vector2::zero(), ..camera.clone() }.project(point, viewport));
+
+        let source = "#version 330 core\n#include \"reactor/velocity.glsl\"\nvoid main() { writeVelocity(vec4(1.0), vec4(1.0)); }\n";
+        let processed = Preprocessor::new().process("smoke.frag", source, &Defines::new()).unwrap();
+        assert!(processed.source.contains("out vec2 Velocity;"));
+    }
+}
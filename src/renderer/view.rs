@@ -0,0 +1,37 @@
+use camera::Camera;
+use gl_state::ClearSpec;
+use lang::Matrix4;
+use viewport::Viewport;
+
+/// A camera drawing into a region of the framebuffer, see `Renderer::render_views`
+#[derive(Clone, PartialEq, Debug)]
+pub struct SceneView {
+    pub camera: Camera,
+    pub viewport: Viewport,
+    /// applied to the viewport's area before the view is drawn
+    pub clear: Option<ClearSpec>,
+}
+
+impl SceneView {
+    pub fn new(camera: Camera, viewport: Viewport) -> SceneView {
+        SceneView { camera, viewport, clear: None }
+    }
+
+    pub fn clear(mut self, clear: ClearSpec) -> SceneView {
+        self.clear = Some(clear);
+        self
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4 {
+        self.camera.projection_matrix(self.viewport.width, self.viewport.height)
+    }
+
+    pub fn view_projection(&self) -> Matrix4 {
+        self.projection_matrix() * self.camera.view_matrix()
+    }
+
+    /// `view_projection` without the camera's TAA jitter
+    pub fn unjittered_view_projection(&self) -> Matrix4 {
+        self.camera.unjittered_projection_matrix(self.viewport.width, self.viewport.height) * self.camera.view_matrix()
+    }
+}
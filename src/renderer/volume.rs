@@ -0,0 +1,320 @@
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+
+use lang::{gl_float, Float, Matrix4};
+use color::Color;
+use gl_state::GlState;
+use mesh::{primitives, Mesh};
+use renderer::SceneView;
+use renderer::depth_copy::DepthCopy;
+use shader::Shader;
+use texture::Texture3D;
+
+/// Texels of the transfer function texture
+pub const TRANSFER_RESOLUTION: usize = 256;
+
+const VOLUME_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+
+uniform mat4 model;
+uniform mat4 viewProjection;
+
+void main()
+{
+    gl_Position = viewProjection * model * vec4(aPos, 1.0);
+}
+"#;
+
+const VOLUME_FRAGMENT_SHADER: &str = r#"
+#version 330 core
+out vec4 FragColor;
+
+uniform sampler3D volume;
+uniform sampler2D transferFunction;
+uniform sampler2D sceneDepth;
+uniform mat4 inverseViewProjection;
+uniform mat4 inverseModel;
+uniform vec4 viewportRect;
+uniform vec2 valueRange;
+uniform float density;
+uniform int stepCount;
+
+vec3 toLocal(vec2 ndc, float depth)
+{
+    vec4 world = inverseViewProjection * vec4(ndc, depth, 1.0);
+    return (inverseModel * vec4(world.xyz / world.w, 1.0)).xyz;
+}
+
+void main()
+{
+    // the pixel's ray from the near to the far plane in the unit cube's space, t in [0, 1]
+    vec2 ndc = (gl_FragCoord.xy - viewportRect.xy) / viewportRect.zw * 2.0 - 1.0;
+    vec3 origin = toLocal(ndc, -1.0);
+    vec3 direction = toLocal(ndc, 1.0) - origin;
+
+    vec3 t0 = (vec3(-0.5) - origin) / direction;
+    vec3 t1 = (vec3(0.5) - origin) / direction;
+    vec3 tMin = min(t0, t1);
+    vec3 tMax = max(t0, t1);
+    float enter = max(max(max(tMin.x, tMin.y), tMin.z), 0.0);
+    float exit = min(min(min(tMax.x, tMax.y), tMax.z), 1.0);
+
+    // the ray stops at the opaque geometry
+    float depth = texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r;
+    vec3 surface = toLocal(ndc, depth * 2.0 - 1.0);
+    exit = min(exit, dot(surface - origin, direction) / dot(direction, direction));
+    if (exit <= enter)
+        discard;
+
+    // stepCount samples along the cube's diagonal, starting with a per-pixel offset that
+    // turns the banding of the fixed step into noise
+    float stepLength = sqrt(3.0) / float(stepCount);
+    float dt = stepLength / length(direction);
+    float jitter = fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715))));
+
+    vec4 sum = vec4(0.0);
+    for (float t = enter + dt * jitter; t < exit && sum.a < 0.99; t += dt) {
+        float value = (texture(volume, origin + direction * t + 0.5).r - valueRange.x) / (valueRange.y - valueRange.x);
+        vec4 color = texture(transferFunction, vec2(clamp(value, 0.0, 1.0), 0.5));
+        // the transfer alpha is the opacity across one side of the volume, corrected for the step
+        float alpha = 1.0 - pow(1.0 - min(color.a, 0.9999), stepLength * density);
+        sum += (1.0 - sum.a) * vec4(color.rgb * alpha, alpha);
+    }
+    FragColor = sum;
+}
+"#;
+
+/// Maps normalized voxel values to a linear color and opacity, interpolating between the
+/// control points and holding the first and last one beyond them
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TransferFunction {
+    /// sorted by value
+    points: Vec<(Float, Color)>,
+}
+
+impl TransferFunction {
+    pub fn new() -> TransferFunction {
+        TransferFunction::default()
+    }
+
+    /// From transparent black at 0 to opaque white at 1, a starting point for density data
+    pub fn ramp() -> TransferFunction {
+        TransferFunction::new().point(0.0, Color::TRANSPARENT).point(1.0, Color::WHITE)
+    }
+
+    pub fn point(mut self, value: Float, color: Color) -> TransferFunction {
+        let index = self.points.partition_point(|&(other, _)| other <= value);
+        self.points.insert(index, (value, color));
+        self
+    }
+
+    /// Transparent without control points
+    pub fn sample(&self, value: Float) -> Color {
+        let index = self.points.partition_point(|&(other, _)| other <= value);
+        match (index.checked_sub(1).map(|i| self.points[i]), self.points.get(index)) {
+            (Some((a, from)), Some(&(b, to))) => from.lerp(to, (value - a) / (b - a)),
+            (Some((_, color)), None) | (None, Some(&(_, color))) => color,
+            (None, None) => Color::TRANSPARENT,
+        }
+    }
+
+    /// `resolution` RGBA samples over `[0, 1]`, texel centers included
+    pub fn texels(&self, resolution: usize) -> Vec<f32> {
+        (0..resolution)
+            .flat_map(|i| self.sample((i as Float + 0.5) / resolution as Float).to_array().to_vec())
+            .map(gl_float)
+            .collect()
+    }
+}
+
+/// A `Texture3D` filling the unit cube centered at the origin, placed in the world by `transform`
+#[derive(Debug)]
+pub struct Volume {
+    pub texture: Texture3D,
+    pub transform: Matrix4,
+    /// samples along the diagonal of the volume, more is smoother and slower
+    pub step_count: i32,
+    /// scales the opacity of the transfer function
+    pub density: Float,
+    /// voxel values mapped to the transfer function's `[0, 1]`, e.g. a window of a scan
+    pub value_range: (Float, Float),
+    transfer_texture: u32,
+}
+
+impl Volume {
+    pub fn new(texture: Texture3D, transform: Matrix4, transfer: &TransferFunction) -> Volume {
+        let mut volume = Volume {
+            texture,
+            transform,
+            step_count: 128,
+            density: 1.0,
+            value_range: (0.0, 1.0),
+            transfer_texture: 0,
+        };
+        unsafe {
+            gl::GenTextures(1, &mut volume.transfer_texture);
+            gl::BindTexture(gl::TEXTURE_2D, volume.transfer_texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        volume.set_transfer(transfer);
+        volume
+    }
+
+    pub fn step_count(mut self, step_count: i32) -> Volume {
+        self.step_count = step_count.max(1);
+        self
+    }
+
+    pub fn density(mut self, density: Float) -> Volume {
+        self.density = density;
+        self
+    }
+
+    pub fn value_range(mut self, min: Float, max: Float) -> Volume {
+        self.value_range = (min, max);
+        self
+    }
+
+    /// Uploads a new transfer function, e.g. while an editor drags its control points
+    pub fn set_transfer(&mut self, transfer: &TransferFunction) {
+        let texels = transfer.texels(TRANSFER_RESOLUTION);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.transfer_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA32F as GLint, TRANSFER_RESOLUTION as i32, 1, 0,
+                           gl::RGBA, gl::FLOAT, texels.as_ptr() as *const GLvoid);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+    }
+
+    /// Deletes the transfer function and the volume's texture
+    pub fn delete(&mut self) {
+        self.texture.delete();
+        unsafe {
+            gl::DeleteTextures(1, &self.transfer_texture);
+        }
+        self.transfer_texture = 0;
+    }
+}
+
+/// Ray marches `volumes` over the opaque geometry of a view, each one stopping at the
+/// geometry's depth. Set it as `Renderer::volumes` to have it drawn by `render_views` after
+/// the decals and before the blended commands.
+pub struct VolumeRenderer {
+    pub volumes: Vec<Volume>,
+    shader: Shader,
+    box_mesh: Mesh,
+    depth: DepthCopy,
+}
+
+impl VolumeRenderer {
+    pub fn new() -> VolumeRenderer {
+        VolumeRenderer {
+            volumes: Vec::new(),
+            shader: Shader::from_source(VOLUME_VERTEX_SHADER, VOLUME_FRAGMENT_SHADER),
+            box_mesh: Mesh::new(&primitives::cube(1.0)),
+            depth: DepthCopy::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.volumes.is_empty()
+    }
+
+    /// Copies the depth of the framebuffer being drawn into, then blends the volumes over it
+    /// back to front with premultiplied alpha. Leaves blending on, depth testing off and front
+    /// faces culled.
+    pub fn draw(&mut self, state: &mut GlState, view: &SceneView) {
+        let view_projection = view.view_projection();
+        let inverse_view_projection = match view_projection.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        self.depth.copy(&view.viewport, "volume");
+
+        state.use_program(self.shader.ID);
+        state.set_blend(true);
+        state.blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+        state.set_depth_test(false);
+        state.depth_mask(false);
+        // back faces still cover the screen when the camera is inside a volume
+        state.set_cull_face(true);
+        state.cull_mode(gl::FRONT);
+        state.bind_texture(2, self.depth.texture);
+        let v = view.viewport;
+        unsafe {
+            self.shader.setInt(c_str!("volume"), 0);
+            self.shader.setInt(c_str!("transferFunction"), 1);
+            self.shader.setInt(c_str!("sceneDepth"), 2);
+            self.shader.setMat4(c_str!("viewProjection"), &view_projection);
+            self.shader.setMat4(c_str!("inverseViewProjection"), &inverse_view_projection);
+            self.shader.setVec4(c_str!("viewportRect"), v.x as Float, v.y as Float, v.width as Float, v.height as Float);
+        }
+
+        let camera = view.camera.position;
+        let mut order: Vec<(Float, &Volume)> = self.volumes.iter()
+            .map(|volume| (volume.transform.w.truncate().distance2(camera.to_vec()), volume))
+            .collect();
+        order.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(::std::cmp::Ordering::Equal));
+        for (_, volume) in order {
+            let inverse = match volume.transform.invert() {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+            state.bind_texture_target(0, gl::TEXTURE_3D, volume.texture.id);
+            state.bind_texture(1, volume.transfer_texture);
+            unsafe {
+                self.shader.setMat4(c_str!("model"), &volume.transform);
+                self.shader.setMat4(c_str!("inverseModel"), &inverse);
+                self.shader.setVec2(c_str!("valueRange"), volume.value_range.0, volume.value_range.1);
+                self.shader.setFloat(c_str!("density"), volume.density);
+                self.shader.setInt(c_str!("stepCount"), volume.step_count);
+            }
+            self.box_mesh.draw_with_state(state);
+        }
+        state.bind_texture_target(0, gl::TEXTURE_3D, 0);
+    }
+
+    /// Deletes the GL objects of the pass, and those of the volumes
+    pub fn delete(&mut self) {
+        for volume in self.volumes.iter_mut() {
+            volume.delete();
+        }
+        self.volumes.clear();
+        self.box_mesh.delete();
+        unsafe {
+            gl::DeleteProgram(self.shader.ID);
+        }
+        self.depth.delete();
+    }
+}
+
+impl Default for VolumeRenderer {
+    fn default() -> VolumeRenderer {
+        VolumeRenderer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_function_interpolates_and_clamps() {
+        let red = Color::linear(1.0, 0.0, 0.0, 0.5);
+        let transfer = TransferFunction::new().point(0.75, red).point(0.25, Color::TRANSPARENT);
+        assert_eq!(transfer.sample(0.0), Color::TRANSPARENT);
+        assert_eq!(transfer.sample(0.5), Color::linear(0.5, 0.0, 0.0, 0.25));
+        assert_eq!(transfer.sample(0.9), red);
+        assert_eq!(TransferFunction::new().sample(0.5), Color::TRANSPARENT);
+
+        let texels = TransferFunction::ramp().texels(4);
+        assert_eq!(texels.len(), 16);
+        assert_eq!(&texels[4..8], &[0.375; 4]);
+    }
+}
@@ -0,0 +1,341 @@
+//! On-disk cache of linked program binaries (`glGetProgramBinary`)
+//!
+//! Entries are keyed by a hash of the driver string and every stage source after
+//! preprocessing, so editing a file or changing a define simply misses and relinks.
+//! Entries written by another driver are dropped when the cache is opened and any
+//! binary the driver refuses to load is deleted and rebuilt.
+#![allow(non_snake_case)]
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gl;
+use gl::types::*;
+
+use buffer;
+use error::{EngineError, EngineResult};
+use logging;
+
+use super::{Defines, Preprocessed, Preprocessor, Shader};
+
+const MAGIC: &[u8; 4] = b"RPBN";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 4 + 4 + 8 + 8 + 4;
+const EXTENSION: &str = "bin";
+
+/// Program binaries stored in `dir`, one file per key
+#[derive(Debug)]
+pub struct ProgramCache {
+    dir: PathBuf,
+    driver: String,
+    driver_hash: u64,
+    enabled: bool,
+    used: HashSet<u64>,
+    hits: usize,
+    misses: usize,
+}
+
+impl ProgramCache {
+    /// Opens (and creates) the cache directory for the current context's driver, dropping
+    /// entries from other drivers. Without GL 4.1 / `GL_ARB_get_program_binary` every
+    /// program is simply built from source
+    pub fn new<P: Into<PathBuf>>(dir: P) -> EngineResult<ProgramCache> {
+        let (driver, enabled) = unsafe {
+            let mut formats = 0;
+            let supported = buffer::supports((4, 1), "GL_ARB_get_program_binary");
+            if supported {
+                gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+            }
+            let driver = [gl::VENDOR, gl::RENDERER, gl::VERSION].iter()
+                .map(|&name| gl_string(name))
+                .collect::<Vec<_>>()
+                .join(" / ");
+            (driver, supported && formats > 0)
+        };
+
+        let mut cache = ProgramCache::with_driver(dir, &driver)?;
+        cache.enabled = enabled;
+        if enabled {
+            let stale = cache.remove_where(|header| header.is_none_or(|(driver_hash, _)| driver_hash != cache.driver_hash))?;
+            engine_info!(logging::SHADER, "program cache at {} for {}, {} stale entries dropped",
+                         cache.dir.display(), driver, stale);
+        } else {
+            engine_info!(logging::SHADER, "program binaries unsupported by {}, program cache disabled", driver);
+        }
+        Ok(cache)
+    }
+
+    /// Cache for an explicit driver string without touching GL, keys and files only
+    pub fn with_driver<P: Into<PathBuf>>(dir: P, driver: &str) -> EngineResult<ProgramCache> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|error| EngineError::asset_io(&dir, error))?;
+        Ok(ProgramCache {
+            dir,
+            driver: driver.to_owned(),
+            driver_hash: fnv1a(driver.as_bytes(), FNV_OFFSET),
+            enabled: false,
+            used: HashSet::new(),
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn driver(&self) -> &str {
+        &self.driver
+    }
+
+    /// Whether binaries are loaded and stored at all
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Programs loaded from disk so far
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Programs built from source so far
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Key of a program made of `stages` (kind and final source) for this driver
+    pub fn key(&self, stages: &[(GLenum, &str)]) -> u64 {
+        stages.iter().fold(self.driver_hash, |hash, &(kind, source)| {
+            let hash = fnv1a(&kind.to_le_bytes(), hash);
+            let hash = fnv1a(&(source.len() as u64).to_le_bytes(), hash);
+            fnv1a(source.as_bytes(), hash)
+        })
+    }
+
+    pub fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", key, EXTENSION))
+    }
+
+    /// Like `Shader::try_from_source`, loading the binary when it's cached
+    pub fn from_source(&mut self, vertexCode: &str, fragmentCode: &str) -> EngineResult<Shader> {
+        self.build(&[(gl::VERTEX_SHADER, "VERTEX", vertexCode), (gl::FRAGMENT_SHADER, "FRAGMENT", fragmentCode)])
+    }
+
+    /// Like `Shader::try_preprocessed`; the key covers the expanded sources, so includes
+    /// and `defines` are part of it
+    pub fn preprocessed(&mut self, preprocessor: &Preprocessor, vertexPath: &str, fragmentPath: &str,
+                        defines: &Defines) -> EngineResult<Shader> {
+        let vertex = preprocessor.load(vertexPath, defines)?;
+        let fragment = preprocessor.load(fragmentPath, defines)?;
+        self.from_preprocessed(&vertex, &fragment)
+    }
+
+    /// Like `Shader::try_from_preprocessed`
+    pub fn from_preprocessed(&mut self, vertex: &Preprocessed, fragment: &Preprocessed) -> EngineResult<Shader> {
+        self.build_mapped(&[(gl::VERTEX_SHADER, "VERTEX", &vertex.source), (gl::FRAGMENT_SHADER, "FRAGMENT", &fragment.source)],
+                          &|stage, log| if stage == "VERTEX" { vertex.remap_log(&log) } else { fragment.remap_log(&log) })
+    }
+
+    /// Loads the program for `stages` or builds and stores it
+    pub fn build(&mut self, stages: &[(GLenum, &'static str, &str)]) -> EngineResult<Shader> {
+        self.build_mapped(stages, &|_, log| log)
+    }
+
+    fn build_mapped(&mut self, stages: &[(GLenum, &'static str, &str)],
+                    remap: &dyn Fn(&'static str, String) -> String) -> EngineResult<Shader> {
+        if !self.enabled {
+            return Shader::build_mapped(stages, remap, false);
+        }
+
+        let key = self.key(&stages.iter().map(|&(kind, _, source)| (kind, source)).collect::<Vec<_>>());
+        self.used.insert(key);
+        if let Some(shader) = self.load(key) {
+            self.hits += 1;
+            return Ok(shader);
+        }
+
+        self.misses += 1;
+        let shader = Shader::build_mapped(stages, remap, true)?;
+        // a failed write only costs the next startup a compile
+        if let Err(error) = self.store(key, &shader) {
+            engine_error!(logging::SHADER, "couldn't cache program {}: {}", shader.ID, error);
+        }
+        Ok(shader)
+    }
+
+    /// Program for `key` from disk, `None` (and the file removed) if it's missing, was
+    /// written for another driver or the driver rejects it
+    pub fn load(&self, key: u64) -> Option<Shader> {
+        let path = self.path(key);
+        let bytes = fs::read(&path).ok()?;
+        let loaded = decode(&bytes, self.driver_hash, key).and_then(|(format, binary)| unsafe {
+            let ID = gl::CreateProgram();
+            gl::ProgramBinary(ID, format, binary.as_ptr() as *const _, binary.len() as GLsizei);
+            let mut success = gl::FALSE as GLint;
+            gl::GetProgramiv(ID, gl::LINK_STATUS, &mut success);
+            if success == gl::TRUE as GLint {
+                Some(Shader { ID })
+            } else {
+                gl::DeleteProgram(ID);
+                None
+            }
+        });
+
+        match loaded {
+            Some(shader) => engine_debug!(logging::SHADER, "loaded program {} from {}", shader.ID, path.display()),
+            None => {
+                engine_info!(logging::SHADER, "dropping stale program binary {}", path.display());
+                let _ = fs::remove_file(&path);
+            },
+        }
+        loaded
+    }
+
+    /// Writes the binary of `shader`, which has to be linked with the retrievable hint
+    pub fn store(&self, key: u64, shader: &Shader) -> EngineResult<()> {
+        let (format, binary) = unsafe {
+            let mut length = 0;
+            gl::GetProgramiv(shader.ID, gl::PROGRAM_BINARY_LENGTH, &mut length);
+            if length <= 0 {
+                return Err(EngineError::InvalidSource(format!("program {} has no binary", shader.ID)));
+            }
+            let mut binary = vec![0u8; length as usize];
+            let (mut written, mut format) = (0, 0);
+            gl::GetProgramBinary(shader.ID, length, &mut written, &mut format, binary.as_mut_ptr() as *mut _);
+            binary.truncate(written.max(0) as usize);
+            (format, binary)
+        };
+
+        // write then rename so a crash never leaves a truncated entry behind
+        let path = self.path(key);
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, encode(self.driver_hash, key, format, &binary))
+            .and_then(|()| fs::rename(&temporary, &path))
+            .map_err(|error| EngineError::asset_io(&path, error))
+    }
+
+    /// Removes the entries no program asked for since the cache was opened, call once
+    /// the startup shaders are built to get rid of old source versions
+    pub fn prune_unused(&self) -> EngineResult<usize> {
+        let used = &self.used;
+        let removed = self.remove_where(|header| header.is_none_or(|(_, key)| !used.contains(&key)))?;
+        if removed > 0 {
+            engine_debug!(logging::SHADER, "pruned {} unused program binaries", removed);
+        }
+        Ok(removed)
+    }
+
+    /// Removes every entry
+    pub fn clear(&self) -> EngineResult<usize> {
+        self.remove_where(|_| true)
+    }
+
+    /// Removes the entries whose header (driver hash and key, `None` if unreadable) matches
+    fn remove_where<F: Fn(Option<(u64, u64)>) -> bool>(&self, matches: F) -> EngineResult<usize> {
+        let entries = fs::read_dir(&self.dir).map_err(|error| EngineError::asset_io(&self.dir, error))?;
+        let mut removed = 0;
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != EXTENSION) {
+                continue;
+            }
+            let header = fs::read(&path).ok().and_then(|bytes| read_header(&bytes));
+            if matches(header) && fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+unsafe fn gl_string(name: GLenum) -> String {
+    let string = gl::GetString(name);
+    if string.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(string as *const _).to_string_lossy().into_owned()
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(bytes: &[u8], hash: u64) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// magic, version, driver hash, key and binary format followed by the binary
+fn encode(driver_hash: u64, key: u64, format: GLenum, binary: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + binary.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&driver_hash.to_le_bytes());
+    bytes.extend_from_slice(&key.to_le_bytes());
+    bytes.extend_from_slice(&format.to_le_bytes());
+    bytes.extend_from_slice(binary);
+    bytes
+}
+
+/// Driver hash and key of an entry with a valid header
+fn read_header(bytes: &[u8]) -> Option<(u64, u64)> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC || u32_at(bytes, 4) != VERSION {
+        return None;
+    }
+    Some((u64_at(bytes, 8), u64_at(bytes, 16)))
+}
+
+/// Binary format and data if the entry was written for `driver_hash` and `key`
+fn decode(bytes: &[u8], driver_hash: u64, key: u64) -> Option<(GLenum, &[u8])> {
+    match read_header(bytes) {
+        Some(header) if header == (driver_hash, key) && bytes.len() > HEADER_LEN => {
+            Some((u32_at(bytes, 24), &bytes[HEADER_LEN..]))
+        },
+        _ => None,
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn keys_and_entries() {
+        let dir = env::temp_dir().join(format!("reactor_program_cache_{}", std::process::id()));
+        let cache = ProgramCache::with_driver(&dir, "Mesa / llvmpipe / 4.5").unwrap();
+        let other = ProgramCache::with_driver(&dir, "NVIDIA / RTX / 4.6").unwrap();
+
+        let stages = [(gl::VERTEX_SHADER, "void main() {}"), (gl::FRAGMENT_SHADER, "#define FOG 1\nvoid main() {}")];
+        let key = cache.key(&stages);
+        assert_eq!(key, cache.key(&stages));
+        assert_ne!(key, other.key(&stages));
+        assert_ne!(key, cache.key(&[stages[0], (gl::FRAGMENT_SHADER, "#define FOG 0\nvoid main() {}")]));
+        // the stage a source belongs to is part of the key
+        assert_ne!(key, cache.key(&[(stages[0].0, stages[1].1), (stages[1].0, stages[0].1)]));
+
+        let bytes = encode(cache.driver_hash, key, 0x8741, &[1, 2, 3]);
+        assert_eq!(decode(&bytes, cache.driver_hash, key), Some((0x8741, &[1u8, 2, 3][..])));
+        assert_eq!(decode(&bytes, other.driver_hash, key), None);
+        assert_eq!(decode(&bytes, cache.driver_hash, key + 1), None);
+        assert_eq!(decode(&bytes[..HEADER_LEN - 1], cache.driver_hash, key), None);
+
+        fs::write(cache.path(key), &bytes).unwrap();
+        fs::write(cache.path(key + 1), b"garbage").unwrap();
+        fs::write(dir.join("notes.txt"), b"kept").unwrap();
+        assert_eq!(cache.prune_unused().unwrap(), 2);
+        assert!(dir.join("notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
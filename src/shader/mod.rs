@@ -0,0 +1,263 @@
+#![allow(non_snake_case)]
+use std::ffi::{CString, CStr};
+use std::fs::File;
+use std::io::Read;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use cgmath::prelude::*;
+
+use color::Color;
+use error::{EngineError, EngineResult};
+use lang::{Float, Vector3, Matrix4, gl_float, gl_vector3, gl_matrix4};
+use logging;
+use vfs::Vfs;
+
+pub mod cache;
+pub mod preprocess;
+pub mod reflect;
+
+pub use self::cache::ProgramCache;
+pub use self::preprocess::{Defines, Preprocessed, Preprocessor};
+pub use self::reflect::{SamplerType, ShaderReflection, UniformType, UniformValue};
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Shader {
+    pub ID: u32,
+}
+
+/// NOTE: mixture of `shader_s.h` and `shader_m.h` (the latter just contains
+/// a few more setters for uniforms)
+#[allow(dead_code)]
+impl Shader {
+    /// Panics if a file can't be read or the program doesn't build, see `try_new`
+    pub fn new(vertexPath: &str, fragmentPath: &str) -> Shader {
+        Shader::try_new(vertexPath, fragmentPath).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_new(vertexPath: &str, fragmentPath: &str) -> EngineResult<Shader> {
+        // 1. retrieve the vertex/fragment source code from filesystem
+        let vertexCode = read_source(vertexPath)?;
+        let fragmentCode = read_source(fragmentPath)?;
+        Shader::try_from_source(&vertexCode, &fragmentCode)
+    }
+
+    /// Like `try_new` with the sources read through the virtual filesystem
+    pub fn try_from_vfs(vfs: &Vfs, vertexPath: &str, fragmentPath: &str) -> EngineResult<Shader> {
+        let vertexCode = vfs.read_to_string(vertexPath)?;
+        let fragmentCode = vfs.read_to_string(fragmentPath)?;
+        Shader::try_from_source(&vertexCode, &fragmentCode)
+    }
+
+    /// Like `try_from_vfs` with the sources run through `preprocessor` with `defines`; compile
+    /// errors point at the included file and line they come from
+    pub fn try_preprocessed(preprocessor: &Preprocessor, vertexPath: &str, fragmentPath: &str, defines: &Defines) -> EngineResult<Shader> {
+        let vertex = preprocessor.load(vertexPath, defines)?;
+        let fragment = preprocessor.load(fragmentPath, defines)?;
+        Shader::try_from_preprocessed(&vertex, &fragment)
+    }
+
+    /// Builds already preprocessed sources, with compile errors mapped back to their files
+    pub fn try_from_preprocessed(vertex: &Preprocessed, fragment: &Preprocessed) -> EngineResult<Shader> {
+        Shader::build_mapped(&[(gl::VERTEX_SHADER, "VERTEX", &vertex.source), (gl::FRAGMENT_SHADER, "FRAGMENT", &fragment.source)],
+                             &|stage, log| if stage == "VERTEX" { vertex.remap_log(&log) } else { fragment.remap_log(&log) },
+                             false)
+    }
+
+    /// Compiles and links a program from in-memory vertex/fragment sources,
+    /// panics if it doesn't build, see `try_from_source`
+    pub fn from_source(vertexCode: &str, fragmentCode: &str) -> Shader {
+        Shader::try_from_source(vertexCode, fragmentCode).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_from_source(vertexCode: &str, fragmentCode: &str) -> EngineResult<Shader> {
+        Shader::build(&[(gl::VERTEX_SHADER, "VERTEX", vertexCode), (gl::FRAGMENT_SHADER, "FRAGMENT", fragmentCode)])
+    }
+
+    /// Like `from_source` with a geometry stage
+    pub fn from_source_with_geometry(vertexCode: &str, fragmentCode: &str, geometryCode: &str) -> Shader {
+        Shader::try_from_source_with_geometry(vertexCode, fragmentCode, geometryCode)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_from_source_with_geometry(vertexCode: &str, fragmentCode: &str, geometryCode: &str) -> EngineResult<Shader> {
+        Shader::build(&[(gl::VERTEX_SHADER, "VERTEX", vertexCode),
+                        (gl::FRAGMENT_SHADER, "FRAGMENT", fragmentCode),
+                        (gl::GEOMETRY_SHADER, "GEOMETRY", geometryCode)])
+    }
+
+    /// 2. compiles the stages and links them into a program
+    fn build(stages: &[(GLenum, &'static str, &str)]) -> EngineResult<Shader> {
+        Shader::build_mapped(stages, &|_, log| log, false)
+    }
+
+    /// `build` with the compile logs passed through `remap`, see `Preprocessed::remap_log`;
+    /// `retrievable` asks the driver to keep the linked binary around for `ProgramCache`
+    fn build_mapped(stages: &[(GLenum, &'static str, &str)], remap: &dyn Fn(&'static str, String) -> String,
+                    retrievable: bool) -> EngineResult<Shader> {
+        let mut sources = Vec::with_capacity(stages.len());
+        for &(_, stage, code) in stages {
+            sources.push(CString::new(code.as_bytes())
+                .map_err(|_| EngineError::InvalidSource(format!("{} shader contains a nul byte", stage)))?);
+        }
+
+        unsafe {
+            let mut shaders = Vec::with_capacity(stages.len());
+            let mut result = Ok(());
+            for (&(kind, stage, _), source) in stages.iter().zip(sources.iter()) {
+                let shader = gl::CreateShader(kind);
+                gl::ShaderSource(shader, 1, &source.as_ptr(), ptr::null());
+                gl::CompileShader(shader);
+                shaders.push(shader);
+                if let Err(log) = checkCompileErrors(shader, false) {
+                    result = Err(EngineError::ShaderCompile { stage, log: remap(stage, log) });
+                    break;
+                }
+            }
+
+            // shader Program
+            let ID = gl::CreateProgram();
+            if result.is_ok() {
+                for &shader in shaders.iter() {
+                    gl::AttachShader(ID, shader);
+                }
+                if retrievable {
+                    gl::ProgramParameteri(ID, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as GLint);
+                }
+                gl::LinkProgram(ID);
+                result = checkCompileErrors(ID, true).map_err(|log| EngineError::ShaderLink { log });
+            }
+            // delete the shaders as they're linked into our program now and no longer necessary
+            for &shader in shaders.iter() {
+                gl::DeleteShader(shader);
+            }
+
+            match result {
+                Ok(()) => {
+                    engine_debug!(logging::SHADER, "built program {}", ID);
+                    Ok(Shader { ID })
+                },
+                Err(error) => {
+                    engine_error!(logging::SHADER, "{}", error);
+                    gl::DeleteProgram(ID);
+                    Err(error)
+                },
+            }
+        }
+    }
+
+    /// Compute program, panics if it doesn't build, see `try_compute`
+    pub fn compute(computeCode: &str) -> Shader {
+        Shader::try_compute(computeCode).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Needs GL 4.3 or `ARB_compute_shader`, see `compute::supports_compute`
+    pub fn try_compute(computeCode: &str) -> EngineResult<Shader> {
+        Shader::build(&[(gl::COMPUTE_SHADER, "COMPUTE", computeCode)])
+    }
+
+    /// activate the shader
+    /// ------------------------------------------------------------------------
+    pub unsafe fn useProgram(&self) {
+        gl::UseProgram(self.ID)
+    }
+
+    /// utility uniform functions
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setBool(&self, name: &CStr, value: bool) {
+        gl::Uniform1i(gl::GetUniformLocation(self.ID, name.as_ptr()), value as i32);
+    }
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setInt(&self, name: &CStr, value: i32) {
+        gl::Uniform1i(gl::GetUniformLocation(self.ID, name.as_ptr()), value);
+    }
+    /// ------------------------------------------------------------------------
+    /// # Safety
+    /// Needs a current GL context, with this program in use.
+    pub unsafe fn setUint(&self, name: &CStr, value: u32) {
+        gl::Uniform1ui(gl::GetUniformLocation(self.ID, name.as_ptr()), value);
+    }
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setFloat(&self, name: &CStr, value: Float) {
+        gl::Uniform1f(gl::GetUniformLocation(self.ID, name.as_ptr()), gl_float(value));
+    }
+    /// ------------------------------------------------------------------------
+    /// # Safety
+    /// Needs a current GL context, with this program in use.
+    pub unsafe fn setVec2(&self, name: &CStr, x: Float, y: Float) {
+        gl::Uniform2f(gl::GetUniformLocation(self.ID, name.as_ptr()), gl_float(x), gl_float(y));
+    }
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setVector3(&self, name: &CStr, value: &Vector3) {
+        gl::Uniform3fv(gl::GetUniformLocation(self.ID, name.as_ptr()), 1, gl_vector3(value).as_ptr());
+    }
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setVec3(&self, name: &CStr, x: Float, y: Float, z: Float) {
+        gl::Uniform3f(gl::GetUniformLocation(self.ID, name.as_ptr()), gl_float(x), gl_float(y), gl_float(z));
+    }
+    /// ------------------------------------------------------------------------
+    pub unsafe fn setVec4(&self, name: &CStr, x: Float, y: Float, z: Float, w: Float) {
+        gl::Uniform4f(gl::GetUniformLocation(self.ID, name.as_ptr()), gl_float(x), gl_float(y), gl_float(z), gl_float(w));
+    }
+    /// ------------------------------------------------------------------------
+    /// Linear channels of the color as a vec4
+    ///
+    /// # Safety
+    /// Needs a current GL context, with this program in use.
+    pub unsafe fn setColor(&self, name: &CStr, color: &Color) {
+        self.setVec4(name, color.r, color.g, color.b, color.a);
+    }
+
+    pub unsafe fn setMat4(&self, name: &CStr, mat: &Matrix4) {
+        gl::UniformMatrix4fv(gl::GetUniformLocation(self.ID, name.as_ptr()), 1, gl::FALSE, gl_matrix4(mat).as_ptr());
+    }
+
+    /// Only used in 4.9 Geometry shaders - ignore until then (shader.h in original C++)
+    pub fn with_geometry_shader(vertexPath: &str, fragmentPath: &str, geometryPath: &str) -> Self {
+        Shader::try_with_geometry_shader(vertexPath, fragmentPath, geometryPath)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_with_geometry_shader(vertexPath: &str, fragmentPath: &str, geometryPath: &str) -> EngineResult<Shader> {
+        let vertexCode = read_source(vertexPath)?;
+        let fragmentCode = read_source(fragmentPath)?;
+        let geometryCode = read_source(geometryPath)?;
+        Shader::build(&[(gl::VERTEX_SHADER, "VERTEX", &vertexCode),
+                        (gl::FRAGMENT_SHADER, "FRAGMENT", &fragmentCode),
+                        (gl::GEOMETRY_SHADER, "GEOMETRY", &geometryCode)])
+    }
+}
+
+fn read_source(path: &str) -> EngineResult<String> {
+    let mut code = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut code))
+        .map_err(|error| EngineError::asset_io(path, error))?;
+    Ok(code)
+}
+
+/// utility function for checking shader compilation/linking errors, returns the info log on failure.
+/// ------------------------------------------------------------------------
+unsafe fn checkCompileErrors(object: u32, program: bool) -> Result<(), String> {
+    let mut success = gl::FALSE as GLint;
+    if program {
+        gl::GetProgramiv(object, gl::LINK_STATUS, &mut success);
+    } else {
+        gl::GetShaderiv(object, gl::COMPILE_STATUS, &mut success);
+    }
+    if success == gl::TRUE as GLint {
+        return Ok(());
+    }
+
+    let mut infoLog = vec![0u8; 1024];
+    let mut length = 0;
+    if program {
+        gl::GetProgramInfoLog(object, 1024, &mut length, infoLog.as_mut_ptr() as *mut GLchar);
+    } else {
+        gl::GetShaderInfoLog(object, 1024, &mut length, infoLog.as_mut_ptr() as *mut GLchar);
+    }
+    infoLog.truncate(length.max(0) as usize);
+    Err(String::from_utf8_lossy(&infoLog).into_owned())
+}
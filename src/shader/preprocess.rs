@@ -0,0 +1,305 @@
+//! GLSL preprocessing before compilation: `#include "file"` pulled in from the virtual
+//! filesystem or from the engine's snippets, `#define`s injected from Rust for shader
+//! variants, and `#line` directives so compile errors can be mapped back to the file and line
+//! they come from.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use atmosphere::FOG_GLSL;
+use builtin::NORMAL_MAP_GLSL;
+use error::{EngineError, EngineResult};
+use mesh::lod::LOD_DITHER_GLSL;
+use mesh::morph::MORPH_GLSL;
+use noise::NOISE_GLSL;
+use renderer::deferred::GBUFFER_GLSL;
+use renderer::oit::OIT_GLSL;
+use renderer::point_shadow::POINT_SHADOW_GLSL;
+use renderer::reflection::{PLANAR_REFLECTION_GLSL, REFLECTION_PROBE_GLSL};
+use renderer::shadow::CASCADE_SHADOW_GLSL;
+use renderer::ssao::SSAO_GLSL;
+use renderer::taa::VELOCITY_GLSL;
+use terrain::SPLAT_GLSL;
+use vfs::{self, Vfs};
+
+/// Preprocessor symbols of a shader variant, sorted by name so equal sets give equal sources
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Defines {
+    values: BTreeMap<String, String>,
+}
+
+impl Defines {
+    pub fn new() -> Defines {
+        Defines::default()
+    }
+
+    /// `#define name 1`, for `#ifdef` switches
+    pub fn define(self, name: &str) -> Defines {
+        self.set(name, 1)
+    }
+
+    /// `#define name value`
+    pub fn set<V: Display>(mut self, name: &str, value: V) -> Defines {
+        self.values.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|value| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// Source ready for the compiler, with the files its `#line` directives number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preprocessed {
+    pub source: String,
+    /// source string numbers of the `#line` directives, the main file is 0
+    pub files: Vec<String>,
+}
+
+impl Preprocessed {
+    /// Rewrites the `source:line` locations of a driver's info log (`0:12(5)` for Mesa,
+    /// `0(12)` for NVIDIA, `ERROR: 0:12:` for AMD) to `file:line`
+    pub fn remap_log(&self, log: &str) -> String {
+        log.lines().map(|line| self.remap_line(line)).collect::<Vec<_>>().join("\n")
+    }
+
+    fn remap_line(&self, line: &str) -> String {
+        let body = line.trim_start();
+        let prefix = ["ERROR: ", "WARNING: "].iter().find(|prefix| body.starts_with(*prefix)).map_or("", |prefix| *prefix);
+        let rest = &body[prefix.len()..];
+
+        let digits = |text: &str| text.bytes().take_while(|byte| byte.is_ascii_digit()).count();
+        let source_len = digits(rest);
+        if source_len == 0 || source_len == rest.len() {
+            return line.to_string();
+        }
+        let separator = rest.as_bytes()[source_len];
+        if separator != b':' && separator != b'(' {
+            return line.to_string();
+        }
+        let after = &rest[source_len + 1..];
+        let line_len = digits(after);
+        if line_len == 0 {
+            return line.to_string();
+        }
+        let mut end = source_len + 1 + line_len;
+        if separator == b'(' {
+            if !after[line_len..].starts_with(')') {
+                return line.to_string();
+            }
+            end += 1;
+        }
+        let file = match rest[..source_len].parse::<usize>().ok().and_then(|index| self.files.get(index)) {
+            Some(file) => file,
+            None => return line.to_string(),
+        };
+        format!("{}{}:{}{}", prefix, file, &after[..line_len], &rest[end..])
+    }
+}
+
+/// Resolves includes against engine snippets first, then the virtual filesystem. Engine
+/// snippets are under `reactor/`, e.g. `#include "reactor/noise.glsl"` for `NOISE_GLSL`.
+pub struct Preprocessor<'a> {
+    vfs: Option<&'a Vfs>,
+    snippets: HashMap<String, String>,
+}
+
+impl<'a> Default for Preprocessor<'a> {
+    fn default() -> Preprocessor<'a> {
+        Preprocessor::new()
+    }
+}
+
+impl<'a> Preprocessor<'a> {
+    /// Preprocessor with only the engine snippets
+    pub fn new() -> Preprocessor<'a> {
+        let mut preprocessor = Preprocessor { vfs: None, snippets: HashMap::new() };
+        for &(name, source) in [
+            ("reactor/fog.glsl", FOG_GLSL),
+            ("reactor/noise.glsl", NOISE_GLSL),
+            ("reactor/morph.glsl", MORPH_GLSL),
+            ("reactor/lod_dither.glsl", LOD_DITHER_GLSL),
+            ("reactor/cascade_shadow.glsl", CASCADE_SHADOW_GLSL),
+            ("reactor/normal_map.glsl", NORMAL_MAP_GLSL),
+            ("reactor/point_shadow.glsl", POINT_SHADOW_GLSL),
+            ("reactor/planar_reflection.glsl", PLANAR_REFLECTION_GLSL),
+            ("reactor/reflection_probe.glsl", REFLECTION_PROBE_GLSL),
+            ("reactor/oit.glsl", OIT_GLSL),
+            ("reactor/velocity.glsl", VELOCITY_GLSL),
+            ("reactor/gbuffer.glsl", GBUFFER_GLSL),
+            ("reactor/ssao.glsl", SSAO_GLSL),
+            ("reactor/splat.glsl", SPLAT_GLSL),
+        ].iter() {
+            preprocessor.snippets.insert(name.to_string(), source.to_string());
+        }
+        preprocessor
+    }
+
+    pub fn with_vfs(vfs: &'a Vfs) -> Preprocessor<'a> {
+        Preprocessor { vfs: Some(vfs), ..Preprocessor::new() }
+    }
+
+    /// Makes `source` includable as `name`, ahead of the files with the same path
+    pub fn add_snippet(&mut self, name: &str, source: &str) {
+        let name = vfs::normalize(name).unwrap_or_else(|| name.to_string());
+        self.snippets.insert(name, source.to_string());
+    }
+
+    /// Reads `path` and preprocesses it
+    pub fn load(&self, path: &str, defines: &Defines) -> EngineResult<Preprocessed> {
+        let path = vfs::normalize(path).ok_or_else(|| EngineError::InvalidSource(format!("shader path {}", path)))?;
+        let source = self.read(&path, &path)?;
+        self.process(&path, &source, defines)
+    }
+
+    /// Expands the includes of `source`, named `name` in error messages, and inserts the
+    /// defines after its `#version` line. Each file is included once, later includes of it
+    /// are dropped.
+    pub fn process(&self, name: &str, source: &str, defines: &Defines) -> EngineResult<Preprocessed> {
+        let mut output = Preprocessed { source: String::new(), files: vec![name.to_string()] };
+        let mut lines = source.lines().enumerate().peekable();
+
+        // the version has to come first, blank lines and comments may precede it
+        let mut first_line = 1;
+        if let Some((index, line)) = lines.clone().find(|&(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with("//")) {
+            if line.trim_start().starts_with("#version") {
+                while lines.peek().is_some_and(|&(i, _)| i < index) {
+                    lines.next();
+                }
+                lines.next();
+                output.source.push_str(line.trim());
+                output.source.push('\n');
+                first_line = index + 2;
+            }
+        }
+        for (name, value) in defines.iter() {
+            output.source.push_str(&format!("#define {} {}\n", name, value));
+        }
+        output.source.push_str(&format!("#line {} 0\n", first_line));
+
+        let mut stack = vec![name.to_string()];
+        let rest: Vec<&str> = lines.map(|(_, line)| line).collect();
+        self.expand(name, &rest, first_line, 0, &mut stack, &mut output)?;
+        Ok(output)
+    }
+
+    fn expand(&self, path: &str, lines: &[&str], first_line: usize, file: usize,
+              stack: &mut Vec<String>, output: &mut Preprocessed) -> EngineResult<()> {
+        for (offset, line) in lines.iter().enumerate() {
+            let number = first_line + offset;
+            let trimmed = line.trim();
+            if trimmed == "#pragma once" {
+                output.source.push('\n');
+                continue;
+            }
+            let target = match include_target(trimmed) {
+                Some(target) => target,
+                None => {
+                    output.source.push_str(line);
+                    output.source.push('\n');
+                    continue;
+                }
+            };
+            let target = resolve(path, &target)
+                .ok_or_else(|| EngineError::InvalidSource(format!("{}:{}: include {} escapes the root", path, number, target)))?;
+            if stack.contains(&target) {
+                return Err(EngineError::InvalidSource(format!("{}:{}: include cycle {} -> {}", path, number, stack.join(" -> "), target)));
+            }
+            if !output.files.contains(&target) {
+                let source = self.read(&target, path)?;
+                let index = output.files.len();
+                output.files.push(target.clone());
+                output.source.push_str(&format!("#line 1 {}\n", index));
+                stack.push(target.clone());
+                let included: Vec<&str> = source.lines().collect();
+                self.expand(&target, &included, 1, index, stack, output)?;
+                stack.pop();
+            }
+            output.source.push_str(&format!("#line {} {}\n", number + 1, file));
+        }
+        Ok(())
+    }
+
+    fn read(&self, path: &str, from: &str) -> EngineResult<String> {
+        if let Some(snippet) = self.snippets.get(path) {
+            return Ok(snippet.clone());
+        }
+        match self.vfs {
+            Some(vfs) => vfs.read_to_string(path),
+            None => Err(EngineError::InvalidSource(format!("{} includes {}, which isn't an engine snippet", from, path))),
+        }
+    }
+}
+
+/// The quoted or bracketed path of an `#include` line
+fn include_target(line: &str) -> Option<String> {
+    let rest = line.strip_prefix('#')?.trim_start().strip_prefix("include")?.trim();
+    let (open, close) = match rest.chars().next()? {
+        '"' => ('"', '"'),
+        '<' => ('<', '>'),
+        _ => return None,
+    };
+    let inner = rest.strip_prefix(open)?;
+    inner.find(close).map(|end| inner[..end].to_string())
+}
+
+/// Engine snippets and absolute paths as they are, the rest relative to the including file
+fn resolve(from: &str, target: &str) -> Option<String> {
+    if target.starts_with("reactor/") || target.starts_with('/') {
+        return vfs::normalize(target);
+    }
+    match from.rfind('/') {
+        Some(slash) => vfs::normalize(&format!("{}/{}", &from[..slash], target)),
+        None => vfs::normalize(target),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vfs::MemoryFiles;
+
+    fn files() -> Vfs {
+        let mut files = MemoryFiles::new();
+        files.add_static("shaders/lit.frag", b"#version 330 core\n#include \"common/light.glsl\"\n#include \"common/math.glsl\"\nout vec4 FragColor;\nvoid main() { FragColor = vec4(light(), 1.0); }\n");
+        files.add_static("shaders/common/light.glsl", b"#pragma once\n#include \"math.glsl\"\nvec3 light() { return vec3(PI); }\n");
+        files.add_static("shaders/common/math.glsl", b"const float PI = 3.14159;\n");
+        files.add_static("shaders/cycle_a.glsl", b"#include \"cycle_b.glsl\"\n");
+        files.add_static("shaders/cycle_b.glsl", b"#include \"cycle_a.glsl\"\n");
+        let mut vfs = Vfs::new();
+        vfs.mount("/", files);
+        vfs
+    }
+
+    #[test]
+    fn expands_includes_with_defines_and_line_numbers() {
+        let vfs = files();
+        let preprocessor = Preprocessor::with_vfs(&vfs);
+        let defines = Defines::new().define("USE_SHADOWS").set("MAX_LIGHTS", 8);
+        let shader = preprocessor.load("shaders/lit.frag", &defines).unwrap();
+        assert_eq!(shader.files, vec!["shaders/lit.frag", "shaders/common/light.glsl", "shaders/common/math.glsl"]);
+        let lines: Vec<&str> = shader.source.lines().collect();
+        assert_eq!(&lines[..4], &["#version 330 core", "#define MAX_LIGHTS 8", "#define USE_SHADOWS 1", "#line 2 0"]);
+        // math.glsl comes with light.glsl, the main file's own include of it is dropped
+        assert_eq!(&lines[4..10], &["#line 1 1", "", "#line 1 2", "const float PI = 3.14159;", "#line 3 1", "vec3 light() { return vec3(PI); }"]);
+        assert_eq!(&lines[10..13], &["#line 3 0", "#line 4 0", "out vec4 FragColor;"]);
+
+        let log = shader.remap_log("0:3(12): error: FragColor undeclared\n2(1) : error C0000: syntax error\nERROR: 1:3: 'light' : redefinition");
+        assert_eq!(log, "shaders/lit.frag:3(12): error: FragColor undeclared\nshaders/common/math.glsl:1 : error C0000: syntax error\nERROR: shaders/common/light.glsl:3: 'light' : redefinition");
+
+        let error = preprocessor.load("shaders/cycle_a.glsl", &Defines::new()).unwrap_err();
+        assert!(format!("{}", error).contains("include cycle"), "{}", error);
+        let snippet = Preprocessor::new().process("inline", "#include \"reactor/noise.glsl\"\nvoid main() {}", &Defines::new()).unwrap();
+        assert!(snippet.source.contains("float simplexNoise3"));
+        assert_eq!(snippet.files[1], "reactor/noise.glsl");
+    }
+}
@@ -0,0 +1,465 @@
+//! Active uniforms, uniform blocks and samplers of a linked program
+//!
+//! Material code and property editors walk `ShaderReflection::parameters` instead of
+//! hard-coding uniform names; `UniformValue` holds an editable value and converts itself
+//! to whatever type the program declares when applied.
+use std::ffi::CString;
+
+use gl;
+use gl::types::*;
+use serde::{Serialize, Deserialize};
+
+use super::Shader;
+
+/// Sampler uniform types, see `UniformType::Sampler`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize)]
+pub enum SamplerType {
+    Sampler2D,
+    Sampler3D,
+    SamplerCube,
+    Sampler2DArray,
+    SamplerCubeArray,
+    Sampler2DMultisample,
+    SamplerBuffer,
+    Sampler2DShadow,
+    Sampler2DArrayShadow,
+    SamplerCubeShadow,
+    IntSampler2D,
+    UIntSampler2D,
+}
+
+impl SamplerType {
+    pub fn from_gl(kind: GLenum) -> Option<SamplerType> {
+        Some(match kind {
+            gl::SAMPLER_2D => SamplerType::Sampler2D,
+            gl::SAMPLER_3D => SamplerType::Sampler3D,
+            gl::SAMPLER_CUBE => SamplerType::SamplerCube,
+            gl::SAMPLER_2D_ARRAY => SamplerType::Sampler2DArray,
+            gl::SAMPLER_CUBE_MAP_ARRAY => SamplerType::SamplerCubeArray,
+            gl::SAMPLER_2D_MULTISAMPLE => SamplerType::Sampler2DMultisample,
+            gl::SAMPLER_BUFFER => SamplerType::SamplerBuffer,
+            gl::SAMPLER_2D_SHADOW => SamplerType::Sampler2DShadow,
+            gl::SAMPLER_2D_ARRAY_SHADOW => SamplerType::Sampler2DArrayShadow,
+            gl::SAMPLER_CUBE_SHADOW => SamplerType::SamplerCubeShadow,
+            gl::INT_SAMPLER_2D => SamplerType::IntSampler2D,
+            gl::UNSIGNED_INT_SAMPLER_2D => SamplerType::UIntSampler2D,
+            _ => return None,
+        })
+    }
+
+    /// Texture target the sampler reads from
+    pub fn target(self) -> GLenum {
+        match self {
+            SamplerType::Sampler2D | SamplerType::Sampler2DShadow | SamplerType::IntSampler2D
+                | SamplerType::UIntSampler2D => gl::TEXTURE_2D,
+            SamplerType::Sampler3D => gl::TEXTURE_3D,
+            SamplerType::SamplerCube | SamplerType::SamplerCubeShadow => gl::TEXTURE_CUBE_MAP,
+            SamplerType::Sampler2DArray | SamplerType::Sampler2DArrayShadow => gl::TEXTURE_2D_ARRAY,
+            SamplerType::SamplerCubeArray => gl::TEXTURE_CUBE_MAP_ARRAY,
+            SamplerType::Sampler2DMultisample => gl::TEXTURE_2D_MULTISAMPLE,
+            SamplerType::SamplerBuffer => gl::TEXTURE_BUFFER,
+        }
+    }
+
+    pub fn glsl(self) -> &'static str {
+        match self {
+            SamplerType::Sampler2D => "sampler2D",
+            SamplerType::Sampler3D => "sampler3D",
+            SamplerType::SamplerCube => "samplerCube",
+            SamplerType::Sampler2DArray => "sampler2DArray",
+            SamplerType::SamplerCubeArray => "samplerCubeArray",
+            SamplerType::Sampler2DMultisample => "sampler2DMS",
+            SamplerType::SamplerBuffer => "samplerBuffer",
+            SamplerType::Sampler2DShadow => "sampler2DShadow",
+            SamplerType::Sampler2DArrayShadow => "sampler2DArrayShadow",
+            SamplerType::SamplerCubeShadow => "samplerCubeShadow",
+            SamplerType::IntSampler2D => "isampler2D",
+            SamplerType::UIntSampler2D => "usampler2D",
+        }
+    }
+}
+
+/// GLSL type of a uniform, `Other` keeps the GL enum of anything not listed
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize)]
+pub enum UniformType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    Int,
+    IVec2,
+    IVec3,
+    IVec4,
+    UInt,
+    UVec2,
+    UVec3,
+    UVec4,
+    Bool,
+    BVec2,
+    BVec3,
+    BVec4,
+    Mat2,
+    Mat3,
+    Mat4,
+    Sampler(SamplerType),
+    Other(u32),
+}
+
+impl UniformType {
+    pub fn from_gl(kind: GLenum) -> UniformType {
+        match kind {
+            gl::FLOAT => UniformType::Float,
+            gl::FLOAT_VEC2 => UniformType::Vec2,
+            gl::FLOAT_VEC3 => UniformType::Vec3,
+            gl::FLOAT_VEC4 => UniformType::Vec4,
+            gl::INT => UniformType::Int,
+            gl::INT_VEC2 => UniformType::IVec2,
+            gl::INT_VEC3 => UniformType::IVec3,
+            gl::INT_VEC4 => UniformType::IVec4,
+            gl::UNSIGNED_INT => UniformType::UInt,
+            gl::UNSIGNED_INT_VEC2 => UniformType::UVec2,
+            gl::UNSIGNED_INT_VEC3 => UniformType::UVec3,
+            gl::UNSIGNED_INT_VEC4 => UniformType::UVec4,
+            gl::BOOL => UniformType::Bool,
+            gl::BOOL_VEC2 => UniformType::BVec2,
+            gl::BOOL_VEC3 => UniformType::BVec3,
+            gl::BOOL_VEC4 => UniformType::BVec4,
+            gl::FLOAT_MAT2 => UniformType::Mat2,
+            gl::FLOAT_MAT3 => UniformType::Mat3,
+            gl::FLOAT_MAT4 => UniformType::Mat4,
+            _ => SamplerType::from_gl(kind).map_or(UniformType::Other(kind), UniformType::Sampler),
+        }
+    }
+
+    /// Scalars per element, 1 for samplers (the texture unit) and 0 for `Other`
+    pub fn components(self) -> usize {
+        match self {
+            UniformType::Float | UniformType::Int | UniformType::UInt | UniformType::Bool
+                | UniformType::Sampler(_) => 1,
+            UniformType::Vec2 | UniformType::IVec2 | UniformType::UVec2 | UniformType::BVec2 => 2,
+            UniformType::Vec3 | UniformType::IVec3 | UniformType::UVec3 | UniformType::BVec3 => 3,
+            UniformType::Vec4 | UniformType::IVec4 | UniformType::UVec4 | UniformType::BVec4
+                | UniformType::Mat2 => 4,
+            UniformType::Mat3 => 9,
+            UniformType::Mat4 => 16,
+            UniformType::Other(_) => 0,
+        }
+    }
+
+    /// Whether the values are read and written as floats rather than ints
+    pub fn is_float(self) -> bool {
+        matches!(self, UniformType::Float | UniformType::Vec2 | UniformType::Vec3 | UniformType::Vec4
+                 | UniformType::Mat2 | UniformType::Mat3 | UniformType::Mat4)
+    }
+
+    pub fn is_sampler(self) -> bool {
+        matches!(self, UniformType::Sampler(_))
+    }
+
+    /// Type spelled `name` in GLSL, the inverse of `glsl`
+    pub fn from_glsl(name: &str) -> Option<UniformType> {
+        use self::SamplerType::*;
+        use self::UniformType::*;
+        const TYPES: [UniformType; 19] = [Float, Vec2, Vec3, Vec4, Int, IVec2, IVec3, IVec4, UInt, UVec2, UVec3, UVec4,
+                                          Bool, BVec2, BVec3, BVec4, Mat2, Mat3, Mat4];
+        const SAMPLERS: [SamplerType; 12] = [Sampler2D, Sampler3D, SamplerCube, Sampler2DArray, SamplerCubeArray,
+                                             Sampler2DMultisample, SamplerBuffer, Sampler2DShadow, Sampler2DArrayShadow,
+                                             SamplerCubeShadow, IntSampler2D, UIntSampler2D];
+        TYPES.iter().cloned()
+            .chain(SAMPLERS.iter().cloned().map(Sampler))
+            .find(|ty| ty.glsl() == Some(name))
+    }
+
+    /// GLSL spelling, `None` for `Other`
+    pub fn glsl(self) -> Option<&'static str> {
+        Some(match self {
+            UniformType::Float => "float",
+            UniformType::Vec2 => "vec2",
+            UniformType::Vec3 => "vec3",
+            UniformType::Vec4 => "vec4",
+            UniformType::Int => "int",
+            UniformType::IVec2 => "ivec2",
+            UniformType::IVec3 => "ivec3",
+            UniformType::IVec4 => "ivec4",
+            UniformType::UInt => "uint",
+            UniformType::UVec2 => "uvec2",
+            UniformType::UVec3 => "uvec3",
+            UniformType::UVec4 => "uvec4",
+            UniformType::Bool => "bool",
+            UniformType::BVec2 => "bvec2",
+            UniformType::BVec3 => "bvec3",
+            UniformType::BVec4 => "bvec4",
+            UniformType::Mat2 => "mat2",
+            UniformType::Mat3 => "mat3",
+            UniformType::Mat4 => "mat4",
+            UniformType::Sampler(sampler) => sampler.glsl(),
+            UniformType::Other(_) => return None,
+        })
+    }
+}
+
+/// Value of a uniform as written in material files and property editors; applying it
+/// converts to the declared type, so `1` sets a float and `[1, 0, 0]` an ivec3 alike
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UniformValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Vector(Vec<f32>),
+}
+
+impl UniformValue {
+    /// Zero of `ty`, one float per component
+    pub fn zero(ty: UniformType) -> UniformValue {
+        match ty.components() {
+            1 if ty == UniformType::Bool => UniformValue::Bool(false),
+            1 if ty.is_float() => UniformValue::Float(0.0),
+            1 => UniformValue::Int(0),
+            components => UniformValue::Vector(vec![0.0; components]),
+        }
+    }
+
+    /// `components` floats, missing ones are 0 and extra ones dropped
+    pub fn floats(&self, components: usize) -> Vec<f32> {
+        let mut floats = match *self {
+            UniformValue::Bool(value) => vec![if value { 1.0 } else { 0.0 }],
+            UniformValue::Int(value) => vec![value as f32],
+            UniformValue::Float(value) => vec![value],
+            UniformValue::Vector(ref values) => values.clone(),
+        };
+        floats.resize(components, 0.0);
+        floats
+    }
+
+    /// Sets the uniform at `location` of the program in use as `ty`
+    pub fn apply(&self, location: GLint, ty: UniformType) {
+        if location < 0 {
+            return;
+        }
+        let floats = self.floats(ty.components());
+        let ints: Vec<i32> = floats.iter().map(|&value| value.round() as i32).collect();
+        let uints: Vec<u32> = ints.iter().map(|&value| value.max(0) as u32).collect();
+        unsafe {
+            match ty {
+                UniformType::Float => gl::Uniform1fv(location, 1, floats.as_ptr()),
+                UniformType::Vec2 => gl::Uniform2fv(location, 1, floats.as_ptr()),
+                UniformType::Vec3 => gl::Uniform3fv(location, 1, floats.as_ptr()),
+                UniformType::Vec4 => gl::Uniform4fv(location, 1, floats.as_ptr()),
+                UniformType::Mat2 => gl::UniformMatrix2fv(location, 1, gl::FALSE, floats.as_ptr()),
+                UniformType::Mat3 => gl::UniformMatrix3fv(location, 1, gl::FALSE, floats.as_ptr()),
+                UniformType::Mat4 => gl::UniformMatrix4fv(location, 1, gl::FALSE, floats.as_ptr()),
+                UniformType::Int | UniformType::Bool | UniformType::Sampler(_) => gl::Uniform1iv(location, 1, ints.as_ptr()),
+                UniformType::IVec2 | UniformType::BVec2 => gl::Uniform2iv(location, 1, ints.as_ptr()),
+                UniformType::IVec3 | UniformType::BVec3 => gl::Uniform3iv(location, 1, ints.as_ptr()),
+                UniformType::IVec4 | UniformType::BVec4 => gl::Uniform4iv(location, 1, ints.as_ptr()),
+                UniformType::UInt => gl::Uniform1uiv(location, 1, uints.as_ptr()),
+                UniformType::UVec2 => gl::Uniform2uiv(location, 1, uints.as_ptr()),
+                UniformType::UVec3 => gl::Uniform3uiv(location, 1, uints.as_ptr()),
+                UniformType::UVec4 => gl::Uniform4uiv(location, 1, uints.as_ptr()),
+                UniformType::Other(_) => (),
+            }
+        }
+    }
+}
+
+/// A uniform outside of the samplers, in the default block or a uniform block
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct UniformInfo {
+    /// without the `[0]` GL appends to arrays
+    pub name: String,
+    pub ty: UniformType,
+    /// 1 unless it's an array
+    pub array_size: usize,
+    /// -1 for block members
+    pub location: GLint,
+    /// index into `ShaderReflection::blocks`
+    pub block: Option<usize>,
+    /// byte layout inside the block, -1 in the default block
+    pub offset: GLint,
+    pub array_stride: GLint,
+    pub matrix_stride: GLint,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct UniformBlockInfo {
+    pub name: String,
+    /// block index in the program
+    pub index: GLuint,
+    /// uniform buffer binding point
+    pub binding: GLuint,
+    /// bytes the buffer needs
+    pub size: usize,
+    /// indices into `ShaderReflection::uniforms`
+    pub members: Vec<usize>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct SamplerInfo {
+    pub name: String,
+    pub ty: SamplerType,
+    pub array_size: usize,
+    pub location: GLint,
+    /// texture unit the sampler currently reads
+    pub unit: GLint,
+}
+
+/// Everything a program declares, see `Shader::reflect`
+#[derive(Clone, PartialEq, Debug, Default, Serialize)]
+pub struct ShaderReflection {
+    pub uniforms: Vec<UniformInfo>,
+    pub blocks: Vec<UniformBlockInfo>,
+    pub samplers: Vec<SamplerInfo>,
+}
+
+impl ShaderReflection {
+    /// Introspects a linked `program`
+    pub fn of(program: GLuint) -> ShaderReflection {
+        let mut reflection = ShaderReflection::default();
+        unsafe {
+            let mut count = 0;
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_BLOCKS, &mut count);
+            for index in 0..count.max(0) as GLuint {
+                let mut length = 0;
+                gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_NAME_LENGTH, &mut length);
+                let mut name = vec![0u8; length.max(1) as usize];
+                gl::GetActiveUniformBlockName(program, index, name.len() as GLsizei, &mut length, name.as_mut_ptr() as *mut GLchar);
+                name.truncate(length.max(0) as usize);
+                let (mut binding, mut size) = (0, 0);
+                gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_BINDING, &mut binding);
+                gl::GetActiveUniformBlockiv(program, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut size);
+                reflection.blocks.push(UniformBlockInfo {
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                    index,
+                    binding: binding.max(0) as GLuint,
+                    size: size.max(0) as usize,
+                    members: vec![],
+                });
+            }
+
+            let mut maxLength = 0;
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+            gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut maxLength);
+            for index in 0..count.max(0) as GLuint {
+                let mut name = vec![0u8; maxLength.max(1) as usize];
+                let mut length = 0;
+                gl::GetActiveUniformName(program, index, name.len() as GLsizei, &mut length, name.as_mut_ptr() as *mut GLchar);
+                name.truncate(length.max(0) as usize);
+                let name = String::from_utf8_lossy(&name).into_owned();
+                let query = |pname| {
+                    let mut value = 0;
+                    gl::GetActiveUniformsiv(program, 1, &index, pname, &mut value);
+                    value
+                };
+                let ty = UniformType::from_gl(query(gl::UNIFORM_TYPE) as GLenum);
+                let array_size = query(gl::UNIFORM_SIZE).max(1) as usize;
+                let block = query(gl::UNIFORM_BLOCK_INDEX);
+                let location = CString::new(name.as_bytes())
+                    .map_or(-1, |cname| gl::GetUniformLocation(program, cname.as_ptr()));
+                let name = base_name(&name).to_owned();
+
+                if let UniformType::Sampler(sampler) = ty {
+                    let mut unit = 0;
+                    if location >= 0 {
+                        gl::GetUniformiv(program, location, &mut unit);
+                    }
+                    reflection.samplers.push(SamplerInfo { name, ty: sampler, array_size, location, unit });
+                    continue;
+                }
+
+                let block = reflection.blocks.iter().position(|info| block >= 0 && info.index == block as GLuint);
+                if let Some(block) = block {
+                    reflection.blocks[block].members.push(reflection.uniforms.len());
+                }
+                reflection.uniforms.push(UniformInfo {
+                    name,
+                    ty,
+                    array_size,
+                    location,
+                    block,
+                    offset: query(gl::UNIFORM_OFFSET),
+                    array_stride: query(gl::UNIFORM_ARRAY_STRIDE),
+                    matrix_stride: query(gl::UNIFORM_MATRIX_STRIDE),
+                });
+            }
+        }
+        reflection
+    }
+
+    pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+        self.uniforms.iter().find(|uniform| uniform.name == name)
+    }
+
+    pub fn block(&self, name: &str) -> Option<&UniformBlockInfo> {
+        self.blocks.iter().find(|block| block.name == name)
+    }
+
+    pub fn sampler(&self, name: &str) -> Option<&SamplerInfo> {
+        self.samplers.iter().find(|sampler| sampler.name == name)
+    }
+
+    /// Default block uniforms an editor can expose, skipping built-ins (`gl_`) and the
+    /// ones the engine sets itself, listed in `engine`
+    pub fn parameters<'a>(&'a self, engine: &'a [&str]) -> impl Iterator<Item = &'a UniformInfo> + 'a {
+        self.uniforms.iter().filter(move |uniform| {
+            uniform.block.is_none() && !uniform.name.starts_with("gl_") && !engine.contains(&uniform.name.as_str())
+        })
+    }
+}
+
+impl Shader {
+    /// Active uniforms, blocks and samplers of the program
+    pub fn reflect(&self) -> ShaderReflection {
+        ShaderReflection::of(self.ID)
+    }
+}
+
+/// `lights[0]` is reported for `lights`, members of struct arrays keep their index
+fn base_name(name: &str) -> &str {
+    name.strip_suffix("[0]").unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn uniform(name: &str, ty: UniformType, block: Option<usize>) -> UniformInfo {
+        UniformInfo { name: name.into(), ty, array_size: 1, location: -1, block, offset: -1, array_stride: -1, matrix_stride: -1 }
+    }
+
+    #[test]
+    fn types_and_parameters() {
+        assert_eq!(UniformType::from_gl(gl::FLOAT_VEC3), UniformType::Vec3);
+        assert_eq!(UniformType::from_gl(gl::SAMPLER_2D_SHADOW), UniformType::Sampler(SamplerType::Sampler2DShadow));
+        assert_eq!(UniformType::from_gl(gl::DOUBLE), UniformType::Other(gl::DOUBLE));
+        assert_eq!(UniformType::Mat3.components(), 9);
+        assert_eq!(UniformType::Sampler(SamplerType::SamplerCube).glsl(), Some("samplerCube"));
+        assert_eq!(UniformType::from_glsl("isampler2D"), Some(UniformType::Sampler(SamplerType::IntSampler2D)));
+        assert_eq!(UniformType::from_glsl("mat4"), Some(UniformType::Mat4));
+        assert_eq!(UniformType::from_glsl("double"), None);
+        assert_eq!(base_name("weights[0]"), "weights");
+        assert_eq!(base_name("lights[0].color"), "lights[0].color");
+
+        let reflection = ShaderReflection {
+            uniforms: vec![uniform("model", UniformType::Mat4, None), uniform("roughness", UniformType::Float, None),
+                           uniform("tint", UniformType::Vec3, None), uniform("viewPos", UniformType::Vec3, Some(0)),
+                           uniform("gl_DepthRange.near", UniformType::Float, None)],
+            ..ShaderReflection::default()
+        };
+        let names: Vec<_> = reflection.parameters(&["model"]).map(|uniform| uniform.name.as_str()).collect();
+        assert_eq!(names, vec!["roughness", "tint"]);
+    }
+
+    #[test]
+    fn values_convert_to_the_declared_type() {
+        let values: Vec<UniformValue> = serde_json::from_str("[true, 2, 0.5, [1, 0.5, 0]]").unwrap();
+        assert_eq!(values, vec![UniformValue::Bool(true), UniformValue::Int(2), UniformValue::Float(0.5),
+                                UniformValue::Vector(vec![1.0, 0.5, 0.0])]);
+        assert_eq!(values[1].floats(1), vec![2.0]);
+        assert_eq!(values[3].floats(4), vec![1.0, 0.5, 0.0, 0.0]);
+        assert_eq!(values[3].floats(2), vec![1.0, 0.5]);
+        assert_eq!(UniformValue::zero(UniformType::Vec3), UniformValue::Vector(vec![0.0; 3]));
+        assert_eq!(UniformValue::zero(UniformType::Bool), UniformValue::Bool(false));
+    }
+}
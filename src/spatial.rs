@@ -0,0 +1,454 @@
+//! Spatial partitioning of object bounds, so culling, picking and proximity queries visit the
+//! few objects near the query instead of all of them.
+
+use std::collections::HashMap;
+
+use bounds::{Aabb, Frustum};
+use lang::{Float, Point3};
+use picking::NodeId;
+use ray::Ray;
+
+/// Bounds are stored this much larger, so small moves don't touch the tree
+pub const DEFAULT_MARGIN: Float = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+struct BvhNode {
+    /// the leaf's fattened bounds, or the union of the children
+    bounds: Aabb,
+    parent: Option<usize>,
+    children: Option<[usize; 2]>,
+    /// leaves are height 0
+    height: i32,
+    id: Option<NodeId>,
+}
+
+/// Dynamic bounding volume hierarchy: a binary tree of boxes with an object in each leaf.
+/// Inserting picks the sibling growing the tree's surface area the least and rotations keep it
+/// balanced; `update` only reinserts an object once it leaves its fattened bounds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bvh {
+    pub margin: Float,
+    nodes: Vec<BvhNode>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    /// leaf and exact bounds of each object
+    leaves: HashMap<NodeId, (usize, Aabb)>,
+}
+
+impl Default for Bvh {
+    fn default() -> Bvh {
+        Bvh::new(DEFAULT_MARGIN)
+    }
+}
+
+impl Bvh {
+    pub fn new(margin: Float) -> Bvh {
+        Bvh { margin, nodes: Vec::new(), free: Vec::new(), root: None, leaves: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.leaves.contains_key(&id)
+    }
+
+    /// Exact bounds `id` was inserted or last updated with
+    pub fn bounds(&self, id: NodeId) -> Option<Aabb> {
+        self.leaves.get(&id).map(|&(_, bounds)| bounds)
+    }
+
+    /// Bounds of everything in the tree
+    pub fn root_bounds(&self) -> Aabb {
+        self.root.map_or_else(Aabb::empty, |root| self.nodes[root].bounds)
+    }
+
+    /// Levels below the root, about log2 of the object count while the tree is balanced
+    pub fn height(&self) -> i32 {
+        self.root.map_or(0, |root| self.nodes[root].height)
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.leaves.clear();
+    }
+
+    /// Adds `id`, replacing its previous bounds when it is already in the tree
+    pub fn insert(&mut self, id: NodeId, bounds: Aabb) {
+        self.remove(id);
+        let leaf = self.allocate(BvhNode {
+            bounds: bounds.expand(self.margin),
+            parent: None,
+            children: None,
+            height: 0,
+            id: Some(id),
+        });
+        self.insert_leaf(leaf);
+        self.leaves.insert(id, (leaf, bounds));
+    }
+
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        match self.leaves.remove(&id) {
+            Some((leaf, _)) => {
+                self.remove_leaf(leaf);
+                self.release(leaf);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves `id` to `bounds`, returns whether the tree changed: objects still inside their
+    /// fattened bounds only get their exact bounds updated. Inserts unknown ids.
+    pub fn update(&mut self, id: NodeId, bounds: Aabb) -> bool {
+        let leaf = match self.leaves.get_mut(&id) {
+            Some(entry) => {
+                entry.1 = bounds;
+                entry.0
+            }
+            None => {
+                self.insert(id, bounds);
+                return true;
+            }
+        };
+        let fat = self.nodes[leaf].bounds;
+        if fat.contains(bounds.min) && fat.contains(bounds.max) {
+            return false;
+        }
+        self.remove_leaf(leaf);
+        self.nodes[leaf].bounds = bounds.expand(self.margin);
+        self.insert_leaf(leaf);
+        true
+    }
+
+    /// Objects whose bounds overlap `aabb`
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<NodeId> {
+        self.query(|bounds| bounds.intersects(aabb))
+    }
+
+    /// Objects whose bounds are at least partly in the frustum, for view culling
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<NodeId> {
+        self.query(|bounds| frustum.intersects_aabb(bounds))
+    }
+
+    /// Objects whose bounds come within `radius` of `center`
+    pub fn query_sphere(&self, center: Point3, radius: Float) -> Vec<NodeId> {
+        self.query(|bounds| bounds.distance_to(center) <= radius)
+    }
+
+    /// Nearest object along the ray within `max_distance`. `test` gives the exact distance to
+    /// an object whose bounds the ray hits, or `None` when it misses the object itself.
+    pub fn cast_ray<F>(&self, ray: &Ray, max_distance: Float, mut test: F) -> Option<(NodeId, Float)>
+        where F: FnMut(NodeId) -> Option<Float>
+    {
+        let mut nearest: Option<(NodeId, Float)> = None;
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let limit = nearest.map_or(max_distance, |(_, distance)| distance);
+            if !node.bounds.intersect_ray(ray).is_some_and(|distance| distance <= limit) {
+                continue;
+            }
+            match (node.children, node.id) {
+                (Some(children), _) => stack.extend(children.iter()),
+                (None, Some(id)) => {
+                    if let Some(distance) = test(id) {
+                        if distance <= limit {
+                            nearest = Some((id, distance));
+                        }
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        nearest
+    }
+
+    /// Object whose bounds are closest to `point`, within `max_distance`
+    pub fn nearest(&self, point: Point3, max_distance: Float) -> Option<(NodeId, Float)> {
+        let mut nearest: Option<(NodeId, Float)> = None;
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let limit = nearest.map_or(max_distance, |(_, distance)| distance);
+            if node.bounds.distance_to(point) > limit {
+                continue;
+            }
+            match (node.children, node.id) {
+                (Some([a, b]), _) => {
+                    // visit the closer child first, it likely tightens the limit
+                    let (near, far) = if self.nodes[a].bounds.distance_to(point) <= self.nodes[b].bounds.distance_to(point) {
+                        (a, b)
+                    } else {
+                        (b, a)
+                    };
+                    stack.push(far);
+                    stack.push(near);
+                }
+                (None, Some(id)) => {
+                    let distance = self.leaves[&id].1.distance_to(point);
+                    if distance <= limit && nearest.is_none_or(|(_, best)| distance < best) {
+                        nearest = Some((id, distance));
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        nearest
+    }
+
+    /// Ids of the leaves whose exact bounds pass `overlaps`, descending into the nodes whose
+    /// bounds pass it
+    fn query<F: Fn(&Aabb) -> bool>(&self, overlaps: F) -> Vec<NodeId> {
+        let mut result = Vec::new();
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !overlaps(&node.bounds) {
+                continue;
+            }
+            match (node.children, node.id) {
+                (Some(children), _) => stack.extend(children.iter()),
+                (None, Some(id)) => {
+                    if overlaps(&self.leaves[&id].1) {
+                        result.push(id);
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        result
+    }
+
+    fn allocate(&mut self, node: BvhNode) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, index: usize) {
+        self.nodes[index].children = None;
+        self.nodes[index].id = None;
+        self.nodes[index].parent = None;
+        self.free.push(index);
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(leaf);
+                self.nodes[leaf].parent = None;
+                return;
+            }
+        };
+
+        // descend to the sibling whose parent adds the least surface area
+        let bounds = self.nodes[leaf].bounds;
+        let mut sibling = root;
+        while let Some([a, b]) = self.nodes[sibling].children {
+            let area = self.nodes[sibling].bounds.surface_area();
+            let combined = self.nodes[sibling].bounds.merge(&bounds).surface_area();
+            // making a new parent here, versus the growth pushed down to a child
+            let cost = 2.0 * combined;
+            let inherited = 2.0 * (combined - area);
+            let child_cost = |child: usize| {
+                let node = &self.nodes[child];
+                let merged = node.bounds.merge(&bounds).surface_area();
+                if node.children.is_some() {
+                    merged - node.bounds.surface_area() + inherited
+                } else {
+                    merged + inherited
+                }
+            };
+            let (cost_a, cost_b) = (child_cost(a), child_cost(b));
+            if cost < cost_a && cost < cost_b {
+                break;
+            }
+            sibling = if cost_a < cost_b { a } else { b };
+        }
+
+        let old_parent = self.nodes[sibling].parent;
+        let parent = self.allocate(BvhNode {
+            bounds: self.nodes[sibling].bounds.merge(&bounds),
+            parent: old_parent,
+            children: Some([sibling, leaf]),
+            height: self.nodes[sibling].height + 1,
+            id: None,
+        });
+        match old_parent {
+            Some(old_parent) => self.replace_child(old_parent, sibling, parent),
+            None => self.root = Some(parent),
+        }
+        self.nodes[sibling].parent = Some(parent);
+        self.nodes[leaf].parent = Some(parent);
+        self.refit(Some(parent));
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if self.root == Some(leaf) {
+            self.root = None;
+            return;
+        }
+        let parent = self.nodes[leaf].parent.expect("leaf below the root without a parent");
+        let [a, b] = self.nodes[parent].children.expect("parent without children");
+        let sibling = if a == leaf { b } else { a };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        match grandparent {
+            Some(grandparent) => self.replace_child(grandparent, parent, sibling),
+            None => self.root = Some(sibling),
+        }
+        self.release(parent);
+        self.nodes[leaf].parent = None;
+        self.refit(grandparent);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if let Some(ref mut children) = self.nodes[parent].children {
+            for child in children.iter_mut() {
+                if *child == old {
+                    *child = new;
+                }
+            }
+        }
+    }
+
+    /// Rebalances and recomputes bounds and heights from `index` up to the root
+    fn refit(&mut self, mut index: Option<usize>) {
+        while let Some(current) = index {
+            let current = self.balance(current);
+            let [a, b] = self.nodes[current].children.expect("refitting a leaf");
+            self.nodes[current].bounds = self.nodes[a].bounds.merge(&self.nodes[b].bounds);
+            self.nodes[current].height = 1 + self.nodes[a].height.max(self.nodes[b].height);
+            index = self.nodes[current].parent;
+        }
+    }
+
+    /// Rotates the taller grandchild up when the children's heights differ by more than one,
+    /// returns the node now in `index`'s place
+    fn balance(&mut self, index: usize) -> usize {
+        let [b, c] = match self.nodes[index].children {
+            Some(children) if self.nodes[index].height >= 2 => children,
+            _ => return index,
+        };
+        let difference = self.nodes[c].height - self.nodes[b].height;
+        if difference > 1 {
+            self.rotate_up(index, c, b)
+        } else if difference < -1 {
+            self.rotate_up(index, b, c)
+        } else {
+            index
+        }
+    }
+
+    /// `tall` takes the place of `index`, which keeps `short` and the shorter of `tall`'s
+    /// children
+    fn rotate_up(&mut self, index: usize, tall: usize, short: usize) -> usize {
+        let [f, g] = self.nodes[tall].children.expect("rotating a leaf up");
+        let parent = self.nodes[index].parent;
+        self.nodes[tall].parent = parent;
+        match parent {
+            Some(parent) => self.replace_child(parent, index, tall),
+            None => self.root = Some(tall),
+        }
+        self.nodes[index].parent = Some(tall);
+
+        let (keep, moved) = if self.nodes[f].height > self.nodes[g].height { (f, g) } else { (g, f) };
+        self.nodes[tall].children = Some([index, keep]);
+        self.nodes[index].children = Some([short, moved]);
+        self.nodes[moved].parent = Some(index);
+
+        self.nodes[index].bounds = self.nodes[short].bounds.merge(&self.nodes[moved].bounds);
+        self.nodes[index].height = 1 + self.nodes[short].height.max(self.nodes[moved].height);
+        self.nodes[tall].bounds = self.nodes[index].bounds.merge(&self.nodes[keep].bounds);
+        self.nodes[tall].height = 1 + self.nodes[index].height.max(self.nodes[keep].height);
+        tall
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lang::{Vector3, Matrix4};
+
+    fn unit_box(x: Float, z: Float) -> Aabb {
+        Aabb::new(Point3::new(x, 0.0, z), Point3::new(x + 1.0, 1.0, z + 1.0))
+    }
+
+    /// 32 x 32 boxes with a gap of one between them
+    fn grid() -> Bvh {
+        let mut bvh = Bvh::default();
+        for i in 0..32 * 32 {
+            bvh.insert(i, unit_box((i % 32) as Float * 2.0, (i / 32) as Float * 2.0));
+        }
+        bvh
+    }
+
+    fn sorted(mut ids: Vec<NodeId>) -> Vec<NodeId> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn stays_balanced_and_answers_queries_like_a_scan() {
+        let mut bvh = grid();
+        assert_eq!(bvh.len(), 1024);
+        assert!(bvh.height() <= 20, "{}", bvh.height());
+
+        let area = Aabb::new(Point3::new(3.5, 0.0, 3.5), Point3::new(8.5, 1.0, 4.5));
+        let scan: Vec<NodeId> = (0..1024).filter(|&i| bvh.bounds(i).unwrap().intersects(&area)).collect();
+        assert_eq!(sorted(bvh.query_aabb(&area)), scan);
+        assert_eq!(scan, vec![66, 67, 68]);
+        assert_eq!(sorted(bvh.query_sphere(Point3::new(1.5, 0.5, 0.5), 1.0)), vec![0, 1]);
+
+        let view = ::cgmath::perspective(::cgmath::Deg(30.0), 1.0, 0.5, 100.0)
+            * Matrix4::look_at(Point3::new(-10.0, 0.5, 0.5), Point3::new(0.0, 0.5, 0.5), Vector3::unit_y());
+        let frustum = Frustum::from_matrix(&view);
+        let scan: Vec<NodeId> = (0..1024).filter(|&i| frustum.intersects_aabb(&bvh.bounds(i).unwrap())).collect();
+        assert_eq!(sorted(bvh.query_frustum(&frustum)), scan);
+        assert!(scan.contains(&31) && !scan.contains(&1023));
+
+        // moving within the margin leaves the tree alone
+        let nudged = Aabb::new(Point3::new(0.05, 0.0, 0.0), Point3::new(1.05, 1.0, 1.0));
+        assert!(!bvh.update(0, nudged));
+        assert_eq!(bvh.bounds(0), Some(nudged));
+        assert!(bvh.update(0, unit_box(100.0, 100.0)));
+        assert_eq!(bvh.nearest(Point3::new(99.0, 0.5, 100.5), 5.0), Some((0, 1.0)));
+        assert_eq!(bvh.query_sphere(Point3::new(0.5, 0.5, 0.5), 0.4), Vec::<NodeId>::new());
+
+        for i in 0..1000 {
+            assert!(bvh.remove(i));
+        }
+        assert!(!bvh.remove(0));
+        assert_eq!(bvh.len(), 24);
+        assert_eq!(sorted(bvh.query_aabb(&bvh.root_bounds())), (1000..1024).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn casts_rays_to_the_nearest_hit() {
+        let bvh = grid();
+        let ray = Ray::new(Point3::new(-5.0, 0.5, 0.5), Vector3::unit_x());
+        let hit = bvh.cast_ray(&ray, 1000.0, |id| bvh.bounds(id).unwrap().intersect_ray(&ray));
+        assert_eq!(hit, Some((0, 5.0)));
+
+        // the exact test rejects the first boxes, e.g. a mesh with holes
+        let hit = bvh.cast_ray(&ray, 1000.0, |id| if id < 3 { None } else { bvh.bounds(id).unwrap().intersect_ray(&ray) });
+        assert_eq!(hit, Some((3, 11.0)));
+        assert_eq!(bvh.cast_ray(&ray, 4.0, |id| bvh.bounds(id).unwrap().intersect_ray(&ray)), None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Level streaming: the world is split into square chunks on the XZ plane, loaded in jobs of
+//! the global job system when the camera comes near and unloaded when it moves away. What a
+//! chunk holds is up to the application's `ChunkLoader`; GL uploads stay on the main thread
+//! within a per-frame budget, so crossing into new chunks doesn't stall a frame.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use serde::{Serialize, Deserialize};
+
+use error::EngineResult;
+use jobs;
+use lang::{Float, Point3};
+use logging;
+
+/// Chunk indices along X and Z, chunk `(x, z)` covers `[x, x + 1) * chunk_size` on X
+pub type ChunkCoord = (i32, i32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamingConfig {
+    /// side of a chunk in world units
+    pub chunk_size: Float,
+    /// chunks closer to the camera than this get loaded
+    pub load_radius: Float,
+    /// chunks farther than this get unloaded, larger than `load_radius` so moving back and
+    /// forth along a border doesn't reload them
+    pub unload_radius: Float,
+    /// upload cost, e.g. bytes, spent per frame; one upload always goes through so larger
+    /// chunks still arrive
+    pub upload_budget: usize,
+    /// chunks being loaded at once, the nearest are requested first
+    pub max_in_flight: usize,
+    /// load in jobs, or on the calling thread during `update` when false
+    pub background: bool,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> StreamingConfig {
+        StreamingConfig {
+            chunk_size: 64.0,
+            load_radius: 256.0,
+            unload_radius: 320.0,
+            upload_budget: 4 * 1024 * 1024,
+            max_in_flight: 8,
+            background: true,
+        }
+    }
+}
+
+/// Application hooks for the content of a chunk. `load` runs in a job and does the
+/// file reading and decoding, `upload` and `unload` run on the GL thread in `update`.
+pub trait ChunkLoader: Send + Sync + 'static {
+    /// CPU side content, e.g. mesh data and decoded textures
+    type Data: Send + 'static;
+    /// What a loaded chunk keeps, e.g. meshes and textures
+    type Resident;
+
+    fn load(&self, coord: ChunkCoord) -> EngineResult<Self::Data>;
+
+    /// Share of `StreamingConfig::upload_budget` uploading `data` takes
+    fn upload_cost(&self, data: &Self::Data) -> usize;
+
+    fn upload(&self, coord: ChunkCoord, data: Self::Data) -> Self::Resident;
+
+    fn unload(&self, coord: ChunkCoord, resident: Self::Resident);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkState {
+    Loading,
+    /// loaded, waiting for upload budget
+    Ready,
+    Resident,
+    /// not retried until the chunk goes out of range and comes back
+    Failed,
+}
+
+/// What an `update` did
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct StreamingStats {
+    pub requested: usize,
+    pub uploaded: usize,
+    pub upload_cost: usize,
+    pub unloaded: usize,
+    pub in_flight: usize,
+    pub resident: usize,
+}
+
+enum Slot<L: ChunkLoader> {
+    Loading,
+    Ready(L::Data),
+    Resident(L::Resident),
+    Failed,
+}
+
+type LoadResult<L> = (ChunkCoord, EngineResult<<L as ChunkLoader>::Data>);
+
+/// Keeps the chunks around the camera loaded, see `ChunkLoader`. Call `update` once a frame.
+pub struct LevelStreamer<L: ChunkLoader> {
+    pub config: StreamingConfig,
+    loader: Arc<L>,
+    chunks: HashMap<ChunkCoord, Slot<L>>,
+    results: Receiver<LoadResult<L>>,
+    result_sender: Sender<LoadResult<L>>,
+    /// loads requested and not received, cancelled ones included as they still keep a
+    /// job busy
+    in_flight: usize,
+}
+
+impl<L: ChunkLoader> LevelStreamer<L> {
+    pub fn new(config: StreamingConfig, loader: L) -> LevelStreamer<L> {
+        let (result_sender, results) = mpsc::channel();
+        LevelStreamer { config, loader: Arc::new(loader), chunks: HashMap::new(), results, result_sender, in_flight: 0 }
+    }
+
+    pub fn loader(&self) -> &L {
+        &self.loader
+    }
+
+    pub fn chunk_at(&self, point: Point3) -> ChunkCoord {
+        let size = self.config.chunk_size;
+        ((point.x / size).floor() as i32, (point.z / size).floor() as i32)
+    }
+
+    /// Horizontal distance from `point` to the closest point of the chunk, 0 inside it
+    pub fn distance_to(&self, coord: ChunkCoord, point: Point3) -> Float {
+        let size = self.config.chunk_size;
+        let axis = |index: i32, value: Float| {
+            let (min, max) = (index as Float * size, (index + 1) as Float * size);
+            (min - value).max(value - max).max(0.0)
+        };
+        let (dx, dz) = (axis(coord.0, point.x), axis(coord.1, point.z));
+        (dx * dx + dz * dz).sqrt()
+    }
+
+    pub fn state(&self, coord: ChunkCoord) -> Option<ChunkState> {
+        self.chunks.get(&coord).map(|slot| match *slot {
+            Slot::Loading => ChunkState::Loading,
+            Slot::Ready(_) => ChunkState::Ready,
+            Slot::Resident(_) => ChunkState::Resident,
+            Slot::Failed => ChunkState::Failed,
+        })
+    }
+
+    pub fn resident(&self, coord: ChunkCoord) -> Option<&L::Resident> {
+        match self.chunks.get(&coord) {
+            Some(Slot::Resident(resident)) => Some(resident),
+            _ => None,
+        }
+    }
+
+    /// Loaded chunks, in no particular order
+    pub fn resident_chunks(&self) -> impl Iterator<Item = (ChunkCoord, &L::Resident)> {
+        self.chunks.iter().filter_map(|(&coord, slot)| match slot {
+            Slot::Resident(resident) => Some((coord, resident)),
+            _ => None,
+        })
+    }
+
+    /// Receives finished loads, unloads the chunks out of range, requests the missing ones
+    /// and uploads what fits in the budget, nearest to `camera` first
+    pub fn update(&mut self, camera: Point3) -> StreamingStats {
+        let mut stats = StreamingStats::default();
+
+        let mut unloaded: Vec<ChunkCoord> = self.chunks.keys().cloned()
+            .filter(|&coord| self.distance_to(coord, camera) > self.config.unload_radius)
+            .collect();
+        unloaded.sort();
+        for coord in unloaded {
+            if let Some(Slot::Resident(resident)) = self.chunks.remove(&coord) {
+                self.loader.unload(coord, resident);
+                stats.unloaded += 1;
+            }
+        }
+
+        for coord in self.wanted(camera) {
+            if self.in_flight >= self.config.max_in_flight {
+                break;
+            }
+            if self.chunks.contains_key(&coord) {
+                continue;
+            }
+            self.chunks.insert(coord, Slot::Loading);
+            self.in_flight += 1;
+            stats.requested += 1;
+            if self.config.background {
+                let (loader, results) = (self.loader.clone(), self.result_sender.clone());
+                jobs::spawn(move || {
+                    let _ = results.send((coord, loader.load(coord)));
+                });
+            } else {
+                let _ = self.result_sender.send((coord, self.loader.load(coord)));
+            }
+        }
+
+        self.receive();
+
+        let mut ready: Vec<(Float, ChunkCoord)> = self.chunks.iter()
+            .filter(|&(_, slot)| matches!(slot, Slot::Ready(_)))
+            .map(|(&coord, _)| (self.distance_to(coord, camera), coord))
+            .collect();
+        ready.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (_, coord) in ready {
+            let data = match self.chunks.remove(&coord) {
+                Some(Slot::Ready(data)) => data,
+                _ => continue,
+            };
+            let cost = self.loader.upload_cost(&data);
+            if stats.uploaded > 0 && stats.upload_cost + cost > self.config.upload_budget {
+                self.chunks.insert(coord, Slot::Ready(data));
+                break;
+            }
+            self.chunks.insert(coord, Slot::Resident(self.loader.upload(coord, data)));
+            stats.uploaded += 1;
+            stats.upload_cost += cost;
+        }
+
+        stats.in_flight = self.in_flight;
+        stats.resident = self.chunks.values().filter(|slot| matches!(slot, Slot::Resident(_))).count();
+        stats
+    }
+
+    /// Unloads every chunk and forgets the pending ones, e.g. before leaving a level
+    pub fn unload_all(&mut self) {
+        for (coord, slot) in self.chunks.drain() {
+            if let Slot::Resident(resident) = slot {
+                self.loader.unload(coord, resident);
+            }
+        }
+    }
+
+    /// Chunks within the load radius, nearest first
+    fn wanted(&self, camera: Point3) -> Vec<ChunkCoord> {
+        let reach = (self.config.load_radius / self.config.chunk_size).ceil() as i32;
+        let center = self.chunk_at(camera);
+        let mut wanted: Vec<(Float, ChunkCoord)> = (-reach..=reach)
+            .flat_map(|dz| (-reach..=reach).map(move |dx| (center.0 + dx, center.1 + dz)))
+            .map(|coord| (self.distance_to(coord, camera), coord))
+            .filter(|&(distance, _)| distance <= self.config.load_radius)
+            .collect();
+        wanted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        wanted.into_iter().map(|(_, coord)| coord).collect()
+    }
+
+    fn receive(&mut self) {
+        while let Ok((coord, result)) = self.results.try_recv() {
+            self.in_flight -= 1;
+            // a chunk that went out of range meanwhile, or was requested again, is dropped
+            if !matches!(self.chunks.get(&coord), Some(Slot::Loading)) {
+                continue;
+            }
+            let slot = match result {
+                Ok(data) => Slot::Ready(data),
+                Err(error) => {
+                    engine_error!(logging::RESOURCES, "Failed to load chunk {:?}: {}", coord, error);
+                    Slot::Failed
+                }
+            };
+            self.chunks.insert(coord, slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use error::EngineError;
+
+    #[derive(Default)]
+    struct Recorder {
+        uploads: Mutex<Vec<ChunkCoord>>,
+        unloads: Mutex<Vec<ChunkCoord>>,
+    }
+
+    impl ChunkLoader for Recorder {
+        type Data = ChunkCoord;
+        type Resident = String;
+
+        fn load(&self, coord: ChunkCoord) -> EngineResult<ChunkCoord> {
+            if coord == (5, 5) {
+                return Err(EngineError::Config("missing chunk".to_string()));
+            }
+            Ok(coord)
+        }
+
+        fn upload_cost(&self, _data: &ChunkCoord) -> usize {
+            10
+        }
+
+        fn upload(&self, coord: ChunkCoord, data: ChunkCoord) -> String {
+            self.uploads.lock().unwrap().push(coord);
+            format!("{:?}", data)
+        }
+
+        fn unload(&self, coord: ChunkCoord, _resident: String) {
+            self.unloads.lock().unwrap().push(coord);
+        }
+    }
+
+    fn config(background: bool) -> StreamingConfig {
+        StreamingConfig {
+            chunk_size: 10.0,
+            load_radius: 10.0,
+            unload_radius: 15.0,
+            upload_budget: 25,
+            max_in_flight: 16,
+            background,
+        }
+    }
+
+    #[test]
+    fn loads_near_chunks_within_the_upload_budget() {
+        let mut streamer = LevelStreamer::new(config(false), Recorder::default());
+        let camera = Point3::new(5.0, 0.0, 5.0);
+        // the chunk under the camera, its 8 neighbours (farthest corners 7.07 away) and the
+        // next ones along the axes at 15 are out
+        let stats = streamer.update(camera);
+        assert_eq!(stats.requested, 9);
+        assert_eq!((stats.uploaded, stats.upload_cost), (2, 20));
+        assert_eq!(streamer.loader().uploads.lock().unwrap()[0], (0, 0));
+        assert_eq!(streamer.resident((0, 0)).map(|s| s.as_str()), Some("(0, 0)"));
+
+        while streamer.update(camera).uploaded > 0 {}
+        assert_eq!(streamer.resident_chunks().count(), 9);
+
+        // far enough that the old chunks leave the unload radius
+        let stats = streamer.update(Point3::new(45.0, 0.0, 5.0));
+        assert_eq!(stats.unloaded, 9);
+        assert_eq!(streamer.state((0, 0)), None);
+        assert_eq!(streamer.state((4, 0)), Some(ChunkState::Resident));
+
+        streamer.update(Point3::new(55.0, 0.0, 55.0));
+        assert_eq!(streamer.state((5, 5)), Some(ChunkState::Failed));
+        streamer.unload_all();
+        assert_eq!(streamer.resident_chunks().count(), 0);
+    }
+
+    #[test]
+    fn loads_in_jobs() {
+        let mut streamer = LevelStreamer::new(config(true), Recorder::default());
+        let camera = Point3::new(5.0, 0.0, 5.0);
+        for _ in 0..200 {
+            if streamer.update(camera).resident == 9 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(streamer.resident_chunks().count(), 9);
+        assert_eq!(streamer.update(camera).in_flight, 0);
+    }
+}
@@ -0,0 +1,534 @@
+//! Heightmap terrain: the map is cut into square chunks, each holding every level of detail of
+//! its grid in one mesh (geo-mipmapping, level `n` skips `2^n - 1` samples out of `2^n`), with
+//! skirts hanging from the chunk borders to hide the cracks between neighbouring levels. The
+//! surface is textured by blending four tiled layers with the weights of a splat map.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use cgmath::prelude::*;
+use gl;
+
+use lang::{Float, Point3, Vector2, Vector3};
+use atmosphere::{Fog, FOG_GLSL};
+use bounds::{Aabb, Frustum};
+use camera::Camera;
+use color::Color;
+use error::{EngineError, EngineResult};
+use gl_state::GlState;
+use logging;
+use mesh::lod::{LodGroup, LodSelection};
+use mesh::{Mesh, MeshData};
+use shader::Shader;
+use viewport::Viewport;
+
+/// Grid of heights in [0, 1], `width` samples along +X by `depth` along +Z, row by row
+#[derive(Clone, PartialEq, Debug)]
+pub struct Heightmap {
+    pub width: usize,
+    pub depth: usize,
+    pub heights: Vec<Float>,
+}
+
+impl Heightmap {
+    pub fn new(width: usize, depth: usize, heights: Vec<Float>) -> Heightmap {
+        assert_eq!(heights.len(), width * depth, "expected one height per sample");
+        Heightmap { width, depth, heights }
+    }
+
+    /// 8 bit grey levels, one byte per sample
+    pub fn from_luminance(width: usize, depth: usize, pixels: &[u8]) -> Heightmap {
+        Heightmap::new(width, depth, pixels.iter().map(|&p| p as Float / 255.0).collect())
+    }
+
+    /// Headerless 16 bit little endian samples, the usual `.raw` heightmap export
+    pub fn from_raw16(width: usize, depth: usize, bytes: &[u8]) -> io::Result<Heightmap> {
+        if bytes.len() != width * depth * 2 {
+            return Err(invalid_data("raw heightmap size doesn't match its dimensions"));
+        }
+        let heights = bytes.chunks(2).map(|s| u16::from_le_bytes([s[0], s[1]]) as Float / 65535.0).collect();
+        Ok(Heightmap::new(width, depth, heights))
+    }
+
+    /// Reads a binary (P5) PGM, 8 bit or 16 bit (big endian) deep, first row at z = 0
+    pub fn read_pgm<R: Read>(reader: R) -> io::Result<Heightmap> {
+        let mut bytes = vec![];
+        BufReader::new(reader).read_to_end(&mut bytes)?;
+
+        // header: magic, width, height, max value, separated by whitespace with # comments
+        let mut fields = vec![];
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+                if bytes[pos] == b'#' {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    pos += 1;
+                }
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid_data("truncated PGM header"));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+        pos += 1;
+
+        if fields[0] != "P5" {
+            return Err(invalid_data("not a binary PGM"));
+        }
+        let parse = |field: &String| field.parse::<usize>().map_err(|_| invalid_data("invalid PGM header"));
+        let (width, depth, max_value) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_data("invalid PGM max value"));
+        }
+        let sample_size = if max_value > 255 { 2 } else { 1 };
+        let size = width.checked_mul(depth).and_then(|n| n.checked_mul(sample_size))
+            .ok_or_else(|| invalid_data("invalid PGM header"))?;
+        if bytes.len() < pos + size {
+            return Err(invalid_data("truncated PGM pixel data"));
+        }
+
+        let data = &bytes[pos..pos + size];
+        let heights = if sample_size == 1 {
+            data.iter().map(|&p| p as Float / max_value as Float).collect()
+        } else {
+            data.chunks(2).map(|s| u16::from_be_bytes([s[0], s[1]]) as Float / max_value as Float).collect()
+        };
+        Ok(Heightmap::new(width, depth, heights))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> EngineResult<Heightmap> {
+        let path = path.as_ref();
+        File::open(path).and_then(Heightmap::read_pgm).map_err(|error| EngineError::asset_io(path, error))
+    }
+
+    /// Height of the sample, coordinates clamped to the map
+    pub fn sample(&self, x: isize, z: isize) -> Float {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let z = z.clamp(0, self.depth as isize - 1) as usize;
+        self.heights[z * self.width + x]
+    }
+
+    /// Bilinear height between the samples, `x` and `z` in samples
+    pub fn height_at(&self, x: Float, z: Float) -> Float {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (x0, z0) = (x0 as isize, z0 as isize);
+        let near = self.sample(x0, z0) * (1.0 - fx) + self.sample(x0 + 1, z0) * fx;
+        let far = self.sample(x0, z0 + 1) * (1.0 - fx) + self.sample(x0 + 1, z0 + 1) * fx;
+        near * (1.0 - fz) + far * fz
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// How a heightmap is turned into geometry
+#[derive(Clone, PartialEq, Debug)]
+pub struct TerrainDesc {
+    /// world units between two samples
+    pub spacing: Float,
+    /// world height of a sample at 1
+    pub height_scale: Float,
+    /// quads along a chunk side at full detail, a power of two
+    pub chunk_size: usize,
+    /// detail levels per chunk, each halving the samples of the previous one
+    pub levels: u32,
+    /// the full detail level is used up to this distance, each further level twice as far
+    pub lod_distance: Float,
+    /// how far the skirts reach below the chunk borders
+    pub skirt_depth: Float,
+}
+
+impl Default for TerrainDesc {
+    fn default() -> TerrainDesc {
+        TerrainDesc {
+            spacing: 1.0,
+            height_scale: 50.0,
+            chunk_size: 32,
+            levels: 4,
+            lod_distance: 64.0,
+            skirt_depth: 2.0,
+        }
+    }
+}
+
+impl TerrainDesc {
+    /// Levels that fit the chunk size, at least one
+    fn level_count(&self) -> u32 {
+        let max = self.chunk_size.max(1).trailing_zeros() + 1;
+        self.levels.clamp(1, max)
+    }
+
+    /// LOD group of the index ranges `(first, count)` of each level in a chunk mesh
+    pub fn lod_group(&self, ranges: &[(u32, u32)]) -> LodGroup<(u32, u32)> {
+        let last = ranges.len().saturating_sub(1);
+        ranges.iter().enumerate().fold(LodGroup::new(), |group, (level, &range)| {
+            let max_distance = if level == last { Float::INFINITY } else { self.lod_distance * (1 << level) as Float };
+            group.level(range, max_distance)
+        })
+    }
+}
+
+/// Chunks a heightmap cuts into with the quads per side of `desc`
+pub fn chunk_count(heightmap: &Heightmap, desc: &TerrainDesc) -> (usize, usize) {
+    let size = desc.chunk_size.max(1);
+    let count = |samples: usize| samples.saturating_sub(1).div_ceil(size);
+    (count(heightmap.width).max(1), count(heightmap.depth).max(1))
+}
+
+/// Mesh of the chunk at `(chunk_x, chunk_z)` with every detail level of `desc` one after the
+/// other, and each level's index range `(first, count)`. Samples past the map's edge are
+/// clamped to it, so all chunks have the same ranges. World positions start at the origin
+/// for sample `(0, 0)`, UVs span the whole terrain for the splat map.
+pub fn chunk_mesh(heightmap: &Heightmap, desc: &TerrainDesc, chunk_x: usize, chunk_z: usize) -> (MeshData, Vec<(u32, u32)>) {
+    let mut mesh = MeshData::new();
+    let ranges = (0..desc.level_count())
+        .map(|level| {
+            let first = mesh.indices.len() as u32;
+            chunk_level(&mut mesh, heightmap, desc, chunk_x, chunk_z, 1 << level);
+            (first, mesh.indices.len() as u32 - first)
+        })
+        .collect();
+    (mesh, ranges)
+}
+
+fn chunk_level(mesh: &mut MeshData, heightmap: &Heightmap, desc: &TerrainDesc, chunk_x: usize, chunk_z: usize, step: usize) {
+    let size = desc.chunk_size.max(1);
+    let n = size / step;
+    let row = n as u32 + 1;
+    let first = mesh.vertex_count() as u32;
+    let sample = |i: usize, base: usize, samples: usize| (base * size + i * step).min(samples - 1);
+
+    for j in 0..=n {
+        for i in 0..=n {
+            let x = sample(i, chunk_x, heightmap.width);
+            let z = sample(j, chunk_z, heightmap.depth);
+            push_sample(mesh, heightmap, desc, x, z);
+        }
+    }
+    for j in 0..n as u32 {
+        for i in 0..n as u32 {
+            let a = first + j * row + i;
+            let (b, c, d) = (a + 1, a + row + 1, a + row);
+            mesh.push_triangle(a, c, b);
+            mesh.push_triangle(a, d, c);
+        }
+    }
+
+    // skirts: the border vertices again, lowered, facing out of the chunk
+    let edges: [(Vec<u32>, Vector3); 4] = [
+        ((0..row).map(|i| first + i).collect(), -Vector3::unit_z()),
+        ((0..row).map(|i| first + n as u32 * row + i).collect(), Vector3::unit_z()),
+        ((0..row).map(|j| first + j * row).collect(), -Vector3::unit_x()),
+        ((0..row).map(|j| first + j * row + n as u32).collect(), Vector3::unit_x()),
+    ];
+    for (border, outward) in edges.iter() {
+        let lowered: Vec<u32> = border.iter().map(|&top| {
+            let i = top as usize;
+            mesh.positions.push(mesh.positions[i] - Vector3::unit_y() * desc.skirt_depth);
+            mesh.normals.push(mesh.normals[i]);
+            mesh.uvs.push(mesh.uvs[i]);
+            mesh.tangents.push(mesh.tangents[i]);
+            mesh.bitangents.push(mesh.bitangents[i]);
+            (mesh.positions.len() - 1) as u32
+        }).collect();
+        for k in 0..border.len() - 1 {
+            let (t0, t1, b0, b1) = (border[k], border[k + 1], lowered[k], lowered[k + 1]);
+            let along = mesh.positions[t1 as usize] - mesh.positions[t0 as usize];
+            // (t0, b0, t1) faces `-Y x along`, the clamped edge of a partial chunk is degenerate
+            if (-Vector3::unit_y()).cross(along).dot(*outward) >= 0.0 {
+                mesh.push_triangle(t0, b0, t1);
+                mesh.push_triangle(t1, b0, b1);
+            } else {
+                mesh.push_triangle(t0, t1, b0);
+                mesh.push_triangle(t1, b1, b0);
+            }
+        }
+    }
+}
+
+/// Pushes the vertex of sample `(x, z)`, with the normal and tangents of the full resolution
+/// map so they match between levels and chunks
+fn push_sample(mesh: &mut MeshData, heightmap: &Heightmap, desc: &TerrainDesc, x: usize, z: usize) {
+    let (xi, zi) = (x as isize, z as isize);
+    let height = |x: isize, z: isize| heightmap.sample(x, z) * desc.height_scale;
+    // central differences, one-sided at the border
+    let span = |i: isize, samples: usize| ((i + 1).min(samples as isize - 1) - (i - 1).max(0)).max(1) as Float * desc.spacing;
+    let dx = (height(xi + 1, zi) - height(xi - 1, zi)) / span(xi, heightmap.width);
+    let dz = (height(xi, zi + 1) - height(xi, zi - 1)) / span(zi, heightmap.depth);
+
+    mesh.positions.push(Point3::new(x as Float * desc.spacing, height(xi, zi), z as Float * desc.spacing));
+    mesh.normals.push(Vector3::new(-dx, 1.0, -dz).normalize());
+    mesh.uvs.push(Vector2::new(x as Float / (heightmap.width.max(2) - 1) as Float,
+                               z as Float / (heightmap.depth.max(2) - 1) as Float));
+    mesh.tangents.push(Vector3::new(1.0, dx, 0.0).normalize());
+    mesh.bitangents.push(Vector3::new(0.0, dz, 1.0).normalize());
+}
+
+const TERRAIN_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec3 aNormal;
+layout (location = 2) in vec2 aTexCoords;
+out vec3 WorldPos;
+out vec3 Normal;
+out vec2 TexCoords;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+
+void main()
+{
+    vec4 world = model * vec4(aPos, 1.0);
+    WorldPos = world.xyz;
+    Normal = mat3(model) * aNormal;
+    TexCoords = aTexCoords;
+    gl_Position = projection * view * world;
+}
+"#;
+
+/// `#include "reactor/splat.glsl"`: `splatColor(uv)` blends the four tiled layers by the
+/// RGBA weights of the splat map at `uv`, which spans the whole terrain
+pub const SPLAT_GLSL: &str = r#"
+uniform sampler2D splatMap;
+uniform sampler2D layer0;
+uniform sampler2D layer1;
+uniform sampler2D layer2;
+uniform sampler2D layer3;
+// repeats of each layer over the terrain
+uniform vec4 layerTiling;
+
+vec3 splatColor(vec2 uv)
+{
+    vec4 weights = texture(splatMap, uv);
+    weights /= max(weights.r + weights.g + weights.b + weights.a, 1e-5);
+    return texture(layer0, uv * layerTiling.x).rgb * weights.r
+         + texture(layer1, uv * layerTiling.y).rgb * weights.g
+         + texture(layer2, uv * layerTiling.z).rgb * weights.b
+         + texture(layer3, uv * layerTiling.w).rgb * weights.a;
+}
+"#;
+
+/// after the version line, `FOG_GLSL` and `SPLAT_GLSL`
+const TERRAIN_FRAGMENT_BODY: &str = r#"
+out vec4 FragColor;
+in vec3 WorldPos;
+in vec3 Normal;
+in vec2 TexCoords;
+
+// direction the light travels in
+uniform vec3 lightDirection;
+uniform vec3 lightColor;
+uniform vec3 ambient;
+
+void main()
+{
+    vec3 normal = normalize(Normal);
+    float diffuse = max(dot(normal, -normalize(lightDirection)), 0.0);
+    vec3 color = splatColor(TexCoords) * (ambient + lightColor * diffuse);
+    FragColor = vec4(applyFog(color, WorldPos), 1.0);
+}
+"#;
+
+fn terrain_fragment_shader() -> String {
+    format!("#version 330 core\n{}{}{}", FOG_GLSL, SPLAT_GLSL, TERRAIN_FRAGMENT_BODY)
+}
+
+struct TerrainChunk {
+    mesh: Mesh,
+    /// world space, before `Terrain::position`
+    bounds: Aabb,
+}
+
+/// Heightmap terrain drawn chunk by chunk, each at the detail level of its distance to the
+/// camera and skipped outside the frustum. Lit by one directional light, without shadows.
+pub struct Terrain {
+    /// corner of sample `(0, 0)`
+    pub position: Point3,
+    /// RGBA weights of the layers, 0 draws the first layer alone
+    pub splat_map: u32,
+    pub layers: [u32; 4],
+    /// repeats of each layer over the whole terrain
+    pub layer_tiling: [Float; 4],
+    /// direction the light travels in
+    pub light_direction: Vector3,
+    pub light_color: Color,
+    pub ambient: Color,
+    pub fog: Fog,
+    /// index ranges of the detail levels, by distance to the nearest point of a chunk
+    pub lod: LodGroup<(u32, u32)>,
+    heightmap: Heightmap,
+    desc: TerrainDesc,
+    chunks: Vec<TerrainChunk>,
+    shader: Shader,
+}
+
+impl Terrain {
+    pub fn new(heightmap: Heightmap, desc: TerrainDesc) -> Terrain {
+        let (count_x, count_z) = chunk_count(&heightmap, &desc);
+        let mut ranges = vec![];
+        let mut chunks = Vec::with_capacity(count_x * count_z);
+        for chunk_z in 0..count_z {
+            for chunk_x in 0..count_x {
+                let (data, levels) = chunk_mesh(&heightmap, &desc, chunk_x, chunk_z);
+                chunks.push(TerrainChunk { mesh: Mesh::new(&data), bounds: data.aabb() });
+                ranges = levels;
+            }
+        }
+        engine_debug!(logging::RENDERER, "terrain of {}x{} samples in {}x{} chunks, {} levels",
+                      heightmap.width, heightmap.depth, count_x, count_z, ranges.len());
+        Terrain {
+            position: Point3::new(0.0, 0.0, 0.0),
+            splat_map: 0,
+            layers: [0; 4],
+            layer_tiling: [32.0; 4],
+            light_direction: Vector3::new(-0.3, -1.0, -0.2),
+            light_color: Color::WHITE,
+            ambient: Color::linear(0.15, 0.15, 0.15, 1.0),
+            fog: Fog::default(),
+            lod: desc.lod_group(&ranges),
+            heightmap,
+            desc,
+            chunks,
+            shader: Shader::from_source(TERRAIN_VERTEX_SHADER, &terrain_fragment_shader()),
+        }
+    }
+
+    /// World height of the surface under world `(x, z)`, as drawn at full detail
+    pub fn height_at(&self, x: Float, z: Float) -> Float {
+        let (sx, sz) = ((x - self.position.x) / self.desc.spacing, (z - self.position.z) / self.desc.spacing);
+        self.position.y + self.heightmap.height_at(sx, sz) * self.desc.height_scale
+    }
+
+    /// Draws the visible chunks seen by `camera` in `viewport`. Uses texture units 0 to 4.
+    pub fn draw(&self, state: &mut GlState, camera: &Camera, viewport: &Viewport) {
+        let model = ::lang::Matrix4::from_translation(self.position.to_vec());
+        let projection = camera.projection_matrix(viewport.width, viewport.height);
+        let view = camera.view_matrix();
+        let frustum = Frustum::from_matrix(&(projection * view * model));
+        let eye = camera.position - self.position.to_vec();
+
+        state.use_program(self.shader.ID);
+        self.fog.bind(&self.shader, camera.position);
+        let splat_map = if self.splat_map != 0 { self.splat_map } else { self.layers[0] };
+        for (unit, &texture) in [splat_map].iter().chain(self.layers.iter()).enumerate() {
+            state.bind_texture(unit as u32, texture);
+        }
+        unsafe {
+            let shader = &self.shader;
+            shader.setInt(c_str!("splatMap"), 0);
+            shader.setInt(c_str!("layer0"), 1);
+            shader.setInt(c_str!("layer1"), 2);
+            shader.setInt(c_str!("layer2"), 3);
+            shader.setInt(c_str!("layer3"), 4);
+            let tiling = self.layer_tiling;
+            shader.setVec4(c_str!("layerTiling"), tiling[0], tiling[1], tiling[2], tiling[3]);
+            let d = self.light_direction;
+            shader.setVec3(c_str!("lightDirection"), d.x, d.y, d.z);
+            shader.setVec3(c_str!("lightColor"), self.light_color.r, self.light_color.g, self.light_color.b);
+            shader.setVec3(c_str!("ambient"), self.ambient.r, self.ambient.g, self.ambient.b);
+            shader.setMat4(c_str!("model"), &model);
+            shader.setMat4(c_str!("view"), &view);
+            shader.setMat4(c_str!("projection"), &projection);
+        }
+
+        for chunk in self.chunks.iter().filter(|chunk| frustum.intersects_aabb(&chunk.bounds)) {
+            let level = match self.lod.select(chunk.bounds.distance_to(eye)) {
+                Some(LodSelection::Single(level)) | Some(LodSelection::CrossFade { from: level, .. }) => level,
+                None => continue,
+            };
+            let (first, count) = self.lod.levels[level].mesh;
+            state.bind_vertex_array(chunk.mesh.vao);
+            unsafe {
+                gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_INT,
+                                 (first as usize * ::std::mem::size_of::<u32>()) as *const _);
+            }
+        }
+    }
+
+    pub fn delete(&mut self) {
+        for chunk in self.chunks.iter_mut() {
+            chunk.mesh.delete();
+        }
+        self.chunks.clear();
+        unsafe {
+            gl::DeleteProgram(self.shader.ID);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_8_and_16_bit_pgm_heightmaps() {
+        let mut pgm = b"P5\n# heights\n3 2\n255\n".to_vec();
+        pgm.extend_from_slice(&[0, 51, 255, 102, 153, 204]);
+        let map = Heightmap::read_pgm(&pgm[..]).unwrap();
+        assert_eq!((map.width, map.depth), (3, 2));
+        assert_eq!(map.sample(1, 0), 0.2);
+        // clamped to the edge
+        assert_eq!(map.sample(5, -1), 1.0);
+        assert!((map.height_at(0.5, 0.5) - (0.0 + 0.2 + 0.4 + 0.6) / 4.0).abs() < 1e-6);
+
+        let mut deep = b"P5 1 1 65535 ".to_vec();
+        deep.extend_from_slice(&[0x80, 0x00]);
+        assert!((Heightmap::read_pgm(&deep[..]).unwrap().heights[0] - 32768.0 / 65535.0).abs() < 1e-6);
+        assert!(Heightmap::read_pgm(&b"P6 1 1 255 \0\0\0"[..]).is_err());
+        assert!(Heightmap::read_pgm(&b"P5 4 4 255 \0"[..]).is_err());
+        assert!(Heightmap::from_raw16(2, 2, &[0; 6]).is_err());
+    }
+
+    #[test]
+    fn chunks_hold_every_level_with_skirts() {
+        let map = Heightmap::new(9, 5, (0..45).map(|i| (i % 9) as Float / 8.0).collect());
+        let desc = TerrainDesc { chunk_size: 4, levels: 8, height_scale: 8.0, ..TerrainDesc::default() };
+        assert_eq!(chunk_count(&map, &desc), (2, 1));
+
+        let (mesh, ranges) = chunk_mesh(&map, &desc, 1, 0);
+        // 4, 2 and 1 quads a side: two triangles each plus two per skirt segment on four sides
+        let triangles = |n: u32| (n * n * 2 + 4 * n * 2) * 3;
+        assert_eq!(ranges, vec![(0, triangles(4)), (triangles(4), triangles(2)),
+                                (triangles(4) + triangles(2), triangles(1))]);
+        assert_eq!(mesh.indices.len() as u32, ranges.iter().map(|r| r.1).sum::<u32>());
+
+        // a ramp rising along +X one unit per sample, the normal leans back
+        let corner = mesh.positions[0];
+        assert_eq!(corner, Point3::new(4.0, 4.0, 0.0));
+        let expected = Vector3::new(-1.0, 1.0, 0.0).normalize();
+        assert!((mesh.normals[0] - expected).magnitude() < 1e-5);
+        assert_eq!(mesh.uvs[0], Vector2::new(0.5, 0.0));
+        assert!(mesh.positions.iter().any(|p| *p == Point3::new(4.0, 4.0 - desc.skirt_depth, 0.0)));
+
+        // surface triangles face up, skirt triangles out of the chunk
+        let center = mesh.aabb().center();
+        for [a, b, c] in mesh.triangles() {
+            let (pa, pb, pc) = (mesh.positions[a as usize], mesh.positions[b as usize], mesh.positions[c as usize]);
+            let face = (pb - pa).cross(pc - pa);
+            let side = Vector3::new(pa.x - center.x, 0.0, pa.z - center.z);
+            assert!(face.y > 0.0 || face.dot(side) > 0.0, "triangle faces inward: {:?}", face);
+        }
+    }
+
+    #[test]
+    fn levels_double_in_distance() {
+        let desc = TerrainDesc { lod_distance: 10.0, ..TerrainDesc::default() };
+        let group = desc.lod_group(&[(0, 6), (6, 3), (9, 1)]);
+        assert_eq!(group.select(5.0), Some(LodSelection::Single(0)));
+        assert_eq!(group.select(15.0), Some(LodSelection::Single(1)));
+        // the coarsest level is kept however far the chunk is
+        assert_eq!(group.select(1e6), Some(LodSelection::Single(2)));
+        assert_eq!(group.levels[1].mesh, (6, 3));
+    }
+}
@@ -0,0 +1,273 @@
+//! Golden-image testing: render a frame offscreen, read it back and compare it against a
+//! reference image stored as binary PPM.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use gl;
+use gl::types::*;
+
+use gl_state::GlState;
+use render_target::RenderTarget;
+use window::GlfwBackend;
+
+/// Set to update the reference images instead of comparing against them
+pub const UPDATE_GOLDEN_VAR: &str = "REACTOR_UPDATE_GOLDEN";
+
+/// 8 bit RGB image, rows top to bottom
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![0; width * height * 3] }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * 3;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&rgb);
+    }
+
+    /// Swaps the rows top to bottom, converting from and to GL's bottom-up order
+    pub fn flip_vertical(&mut self) {
+        let row = self.width * 3;
+        for y in 0..self.height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((self.height - 1 - y) * row);
+            top[y * row..(y + 1) * row].swap_with_slice(&mut bottom[..row]);
+        }
+    }
+
+    /// Reads a binary (P6) PPM with a max value of 255
+    pub fn read_ppm<R: Read>(reader: R) -> io::Result<Image> {
+        let mut bytes = vec![];
+        BufReader::new(reader).read_to_end(&mut bytes)?;
+
+        // header: magic, width, height, max value, separated by whitespace with # comments
+        let mut fields = vec![];
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+                if bytes[pos] == b'#' {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    pos += 1;
+                }
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid_data("truncated PPM header"));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+        // a single whitespace byte separates the header from the pixels
+        pos += 1;
+
+        if fields[0] != "P6" {
+            return Err(invalid_data("not a binary PPM"));
+        }
+        let parse = |field: &String| field.parse::<usize>().map_err(|_| invalid_data("invalid PPM header"));
+        let (width, height, max_value) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+        if max_value != 255 {
+            return Err(invalid_data("only 8 bit PPMs are supported"));
+        }
+        let size = width * height * 3;
+        if bytes.len() < pos + size {
+            return Err(invalid_data("truncated PPM pixel data"));
+        }
+
+        Ok(Image { width, height, pixels: bytes[pos..pos + size].to_vec() })
+    }
+
+    pub fn write_ppm<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.pixels)?;
+        writer.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        Image::read_ppm(File::open(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_ppm(File::create(path)?)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the (resolved) color attachment of the target, synchronously; outside of tests
+/// use `AsyncReadback`
+pub fn read_pixels(target: &RenderTarget) -> Image {
+    let (width, height) = (target.width as usize, target.height as usize);
+    let mut image = Image::new(width, height);
+    unsafe {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, target.fbo);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(0, 0, target.width, target.height, gl::RGB, gl::UNSIGNED_BYTE,
+                       image.pixels.as_mut_ptr() as *mut GLvoid);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    }
+
+    // GL rows go bottom to top
+    image.flip_vertical();
+    image
+}
+
+/// Hidden window providing the GL context for offscreen renders
+pub struct Harness {
+    pub backend: GlfwBackend,
+}
+
+impl Harness {
+    pub fn new() -> Harness {
+        Harness { backend: GlfwBackend::hidden("reactor test", 64, 64) }
+    }
+
+    /// Calls `draw` with a cleared `width` x `height` target bound and returns the resulting image
+    pub fn render<F: FnOnce()>(&mut self, width: i32, height: i32, draw: F) -> Image {
+        let mut target = RenderTarget::new(width, height, 0);
+        target.begin(&mut GlState::new());
+        draw();
+        target.resolve();
+        target.unbind();
+        let image = read_pixels(&target);
+        target.delete();
+        image
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Harness {
+        Harness::new()
+    }
+}
+
+/// Result of comparing two images of the same size
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Comparison {
+    /// pixels with a channel differing by more than the tolerance
+    pub mismatched: usize,
+    pub max_difference: u8,
+    /// mismatched pixels in red over a darkened copy of the reference
+    pub diff: Image,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+/// Per-pixel comparison, a pixel matches if no channel differs by more than `tolerance`.
+/// Panics if the sizes differ.
+pub fn compare(actual: &Image, reference: &Image, tolerance: u8) -> Comparison {
+    assert_eq!((actual.width, actual.height), (reference.width, reference.height), "image sizes differ");
+
+    let mut diff = Image::new(actual.width, actual.height);
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    for y in 0..actual.height {
+        for x in 0..actual.width {
+            let (a, r) = (actual.pixel(x, y), reference.pixel(x, y));
+            let difference = (0..3).map(|c| (a[c] as i16 - r[c] as i16).unsigned_abs() as u8).max().unwrap();
+            max_difference = max_difference.max(difference);
+            if difference > tolerance {
+                mismatched += 1;
+                diff.set_pixel(x, y, [255, 0, 0]);
+            } else {
+                diff.set_pixel(x, y, [r[0] / 4, r[1] / 4, r[2] / 4]);
+            }
+        }
+    }
+
+    Comparison { mismatched, max_difference, diff }
+}
+
+/// Compares `actual` with the reference image at `path`. On a mismatch `<path>.actual.ppm`
+/// and `<path>.diff.ppm` are written next to it and the test panics. With the
+/// `REACTOR_UPDATE_GOLDEN` environment variable set the reference is written instead, a missing
+/// reference fails the test otherwise.
+pub fn assert_golden<P: AsRef<Path>>(actual: &Image, path: P, tolerance: u8) {
+    let path = path.as_ref();
+    if env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).expect("Failed to create golden image directory");
+        }
+        actual.save(path).expect("Failed to write golden image");
+        return;
+    }
+    if !path.exists() {
+        panic!("{}: no reference image, set {} to write it", path.display(), UPDATE_GOLDEN_VAR);
+    }
+
+    let reference = Image::load(path).expect("Failed to read golden image");
+    if (actual.width, actual.height) != (reference.width, reference.height) {
+        actual.save(sibling(path, "actual")).expect("Failed to write actual image");
+        panic!("{}: expected {}x{}, got {}x{}", path.display(),
+               reference.width, reference.height, actual.width, actual.height);
+    }
+
+    let comparison = compare(actual, &reference, tolerance);
+    if !comparison.matches() {
+        actual.save(sibling(path, "actual")).expect("Failed to write actual image");
+        comparison.diff.save(sibling(path, "diff")).expect("Failed to write diff image");
+        panic!("{}: {} pixels differ by more than {} (max {})", path.display(),
+               comparison.mismatched, tolerance, comparison.max_difference);
+    }
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.ppm", suffix));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_with_tolerance_and_round_trips_ppm() {
+        let mut reference = Image::new(2, 2);
+        reference.set_pixel(1, 0, [100, 150, 200]);
+        let mut actual = reference.clone();
+        actual.set_pixel(1, 0, [102, 150, 200]);
+        actual.set_pixel(0, 1, [0, 0, 9]);
+
+        let comparison = compare(&actual, &reference, 2);
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.max_difference, 9);
+        assert_eq!(comparison.diff.pixel(0, 1), [255, 0, 0]);
+
+        let mut bytes = vec![];
+        actual.write_ppm(&mut bytes).unwrap();
+        assert_eq!(Image::read_ppm(&bytes[..]).unwrap(), actual);
+        assert!(Image::read_ppm(&b"P6\n# comment\n2 2\n255\nshort"[..]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "no reference image")]
+    fn missing_reference_fails() {
+        let path = env::temp_dir().join("reactor_missing_golden").join("frame.ppm");
+        assert_golden(&Image::new(1, 1), &path, 0);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::ptr;
+
+use gl;
+use gl::types::*;
+
+use color::ColorSpace;
+use logging;
+use super::{set_filtering, unregister};
+
+/// Layers allocated per array before `TextureArrays` starts another one
+pub const DEFAULT_LAYERS: i32 = 64;
+
+/// RGBA8 `TEXTURE_2D_ARRAY` of same-sized images. Shaders sample it as a `sampler2DArray`
+/// with the layer as third coordinate, so draws using different images can share the binding.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TextureArray {
+    pub id: u32,
+    pub width: i32,
+    pub height: i32,
+    pub layers: i32,
+    pub color_space: ColorSpace,
+    free: Vec<i32>,
+    next: i32,
+    mipmaps_dirty: bool,
+}
+
+impl TextureArray {
+    pub fn new(width: i32, height: i32, layers: i32) -> TextureArray {
+        TextureArray::with_color_space(width, height, layers, ColorSpace::Srgb)
+    }
+
+    pub fn with_color_space(width: i32, height: i32, layers: i32, color_space: ColorSpace) -> TextureArray {
+        let mut array = TextureArray { width, height, layers, color_space, ..TextureArray::default() };
+        unsafe {
+            gl::GenTextures(1, &mut array.id);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, array.id);
+            gl::TexImage3D(gl::TEXTURE_2D_ARRAY, 0, color_space.internal_format() as GLint, width, height, layers, 0,
+                           gl::RGBA, gl::UNSIGNED_BYTE, ptr::null());
+            set_filtering(gl::TEXTURE_2D_ARRAY, array.id);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+        }
+        array
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.free.is_empty() && self.next >= self.layers
+    }
+
+    /// Uploads the pixels into a free layer, `None` when the array is full
+    pub fn add(&mut self, pixels: &[u8]) -> Option<i32> {
+        let layer = self.allocate()?;
+        self.replace(layer, pixels);
+        Some(layer)
+    }
+
+    /// Overwrites a layer, e.g. to stream or animate it
+    pub fn replace(&mut self, layer: i32, pixels: &[u8]) {
+        assert_eq!(pixels.len(), (self.width * self.height * 4) as usize, "expected RGBA8 pixels");
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage3D(gl::TEXTURE_2D_ARRAY, 0, 0, 0, layer, self.width, self.height, 1,
+                              gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const GLvoid);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+        }
+        self.mipmaps_dirty = true;
+    }
+
+    /// Frees the layer for reuse, its pixels are left in place until overwritten
+    pub fn remove(&mut self, layer: i32) {
+        debug_assert!(layer < self.next && !self.free.contains(&layer), "layer {} is not allocated", layer);
+        self.free.push(layer);
+    }
+
+    /// Regenerates the mipmaps if layers changed since the last call, once per batch of uploads
+    pub fn flush(&mut self) {
+        if !self.mipmaps_dirty {
+            return;
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.id);
+            gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+        }
+        self.mipmaps_dirty = false;
+    }
+
+    pub fn delete(&mut self) {
+        unregister(self.id);
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+        *self = TextureArray::default();
+    }
+
+    fn allocate(&mut self) -> Option<i32> {
+        if let Some(layer) = self.free.pop() {
+            return Some(layer);
+        }
+        if self.next < self.layers {
+            self.next += 1;
+            return Some(self.next - 1);
+        }
+        None
+    }
+}
+
+/// Where an image added to `TextureArrays` lives: bind `texture` with `DrawCommand::texture_array`
+/// and pass `layer` to the shader per instance or per vertex
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ArrayLayer {
+    pub texture: u32,
+    pub layer: i32,
+}
+
+/// Keeps images in texture arrays grouped by size, so that sprites and instanced meshes with
+/// different textures sort into a handful of bindings instead of one per image. Images of a size
+/// that's already used share its arrays, a new array is started when they are all full.
+///
+/// The loaded GL bindings don't include `ARB_bindless_texture`, arrays work on every GL 3 driver.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TextureArrays {
+    /// layers of each array created from now on
+    pub layers_per_array: i32,
+    /// sRGB unless the images are data, the arrays of both spaces can't be mixed
+    pub color_space: ColorSpace,
+    arrays: BTreeMap<(i32, i32), Vec<TextureArray>>,
+}
+
+impl TextureArrays {
+    pub fn new() -> TextureArrays {
+        TextureArrays { layers_per_array: DEFAULT_LAYERS, color_space: ColorSpace::Srgb, arrays: BTreeMap::new() }
+    }
+
+    pub fn add(&mut self, width: i32, height: i32, pixels: &[u8]) -> ArrayLayer {
+        let (layers_per_array, color_space) = (self.layers_per_array, self.color_space);
+        let arrays = self.arrays.entry((width, height)).or_default();
+        if arrays.iter().all(TextureArray::is_full) {
+            engine_debug!(logging::RENDERER, "new {}x{} texture array ({} layers)", width, height, layers_per_array);
+            arrays.push(TextureArray::with_color_space(width, height, layers_per_array, color_space));
+        }
+        let array = arrays.iter_mut().find(|array| !array.is_full()).unwrap();
+        let layer = array.add(pixels).unwrap();
+        ArrayLayer { texture: array.id, layer }
+    }
+
+    pub fn replace(&mut self, image: ArrayLayer, pixels: &[u8]) {
+        if let Some(array) = self.array_mut(image.texture) {
+            array.replace(image.layer, pixels);
+        }
+    }
+
+    pub fn remove(&mut self, image: ArrayLayer) {
+        if let Some(array) = self.array_mut(image.texture) {
+            array.remove(image.layer);
+        }
+    }
+
+    /// Number of texture bindings the images are spread over
+    pub fn array_count(&self) -> usize {
+        self.arrays.values().map(Vec::len).sum()
+    }
+
+    /// Regenerates the mipmaps of the arrays changed since the last call, call it after adding images
+    pub fn flush(&mut self) {
+        for array in self.arrays.values_mut().flat_map(|arrays| arrays.iter_mut()) {
+            array.flush();
+        }
+    }
+
+    pub fn delete(&mut self) {
+        for array in self.arrays.values_mut().flat_map(|arrays| arrays.iter_mut()) {
+            array.delete();
+        }
+        self.arrays.clear();
+    }
+
+    fn array_mut(&mut self, texture: u32) -> Option<&mut TextureArray> {
+        self.arrays.values_mut().flat_map(|arrays| arrays.iter_mut()).find(|array| array.id == texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_freed_layers() {
+        let mut array = TextureArray { layers: 2, ..TextureArray::default() };
+        assert_eq!(array.allocate(), Some(0));
+        assert_eq!(array.allocate(), Some(1));
+        assert!(array.is_full());
+        assert_eq!(array.allocate(), None);
+
+        array.remove(0);
+        assert!(!array.is_full());
+        assert_eq!(array.allocate(), Some(0));
+    }
+}
@@ -0,0 +1,320 @@
+//! Packs many small images into a few large pages so sprites, glyphs and particle textures
+//! can share one binding. Placement uses a bottom-left skyline, which fills pages densely
+//! without keeping a list of free rectangles. Every image is surrounded by `padding` pixels
+//! copied from its edges, so bilinear filtering and the first mips don't bleed in neighbours.
+
+use gl;
+use gl::types::*;
+
+use color::ColorSpace;
+use lang::{Float, Vector2};
+use logging;
+use super::Texture;
+
+/// Bottom-left skyline over a `width` x `height` area
+#[derive(Clone, PartialEq, Debug)]
+pub struct SkylinePacker {
+    width: i32,
+    height: i32,
+    /// `(x, y, width)` of the top edge segments, left to right, covering the full width
+    skyline: Vec<(i32, i32, i32)>,
+    used: i64,
+}
+
+impl SkylinePacker {
+    pub fn new(width: i32, height: i32) -> SkylinePacker {
+        SkylinePacker { width, height, skyline: vec![(0, 0, width)], used: 0 }
+    }
+
+    /// Lower-left corner for a `width` x `height` rectangle, `None` when it doesn't fit anymore
+    pub fn pack(&mut self, width: i32, height: i32) -> Option<(i32, i32)> {
+        let mut best: Option<(usize, i32, i32)> = None;
+        for i in 0..self.skyline.len() {
+            if let Some(y) = self.fit(i, width, height) {
+                let x = self.skyline[i].0;
+                if best.is_none_or(|(_, best_x, best_y)| (y + height, x) < (best_y + height, best_x)) {
+                    best = Some((i, x, y));
+                }
+            }
+        }
+        let (index, x, y) = best?;
+
+        self.skyline.insert(index, (x, y + height, width));
+        let right = x + width;
+        let mut i = index + 1;
+        while i < self.skyline.len() && self.skyline[i].0 < right {
+            let (sx, sy, sw) = self.skyline[i];
+            if sx + sw <= right {
+                self.skyline.remove(i);
+            } else {
+                self.skyline[i] = (right, sy, sx + sw - right);
+                i += 1;
+            }
+        }
+        self.skyline.dedup_by(|next, previous| {
+            let merge = next.1 == previous.1;
+            if merge {
+                previous.2 += next.2;
+            }
+            merge
+        });
+        self.used += i64::from(width) * i64::from(height);
+        Some((x, y))
+    }
+
+    /// Height the rectangle would sit at with its left edge on segment `index`
+    fn fit(&self, index: usize, width: i32, height: i32) -> Option<i32> {
+        let x = self.skyline[index].0;
+        if x + width > self.width {
+            return None;
+        }
+        let y = self.skyline[index..].iter()
+            .take_while(|segment| segment.0 < x + width)
+            .map(|segment| segment.1)
+            .max()?;
+        if y + height > self.height { None } else { Some(y) }
+    }
+
+    /// Fraction of the area covered by packed rectangles
+    pub fn occupancy(&self) -> Float {
+        self.used as Float / (i64::from(self.width) * i64::from(self.height)) as Float
+    }
+}
+
+/// Where an image added to a `TextureAtlas` lives
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AtlasRect {
+    pub page: usize,
+    /// pixel rectangle of the image, without padding, rows counted from the bottom
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub uv_min: Vector2,
+    pub uv_max: Vector2,
+}
+
+#[derive(Debug)]
+struct AtlasPage {
+    packer: SkylinePacker,
+    pixels: Vec<u8>,
+    texture: Texture,
+    dirty: bool,
+}
+
+/// RGBA8 pages of `page_size` pixels, added to as images arrive and uploaded by `flush`.
+/// Images are never moved; `clear` starts over, e.g. when a glyph cache is full.
+#[derive(Debug)]
+pub struct TextureAtlas {
+    pub page_size: i32,
+    pub padding: i32,
+    pub color_space: ColorSpace,
+    pages: Vec<AtlasPage>,
+}
+
+impl TextureAtlas {
+    pub fn new(page_size: i32, padding: i32) -> TextureAtlas {
+        TextureAtlas { page_size, padding, color_space: ColorSpace::Srgb, pages: Vec::new() }
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> TextureAtlas {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Copies the image (4 bytes per pixel, bottom row first) into the first page with room,
+    /// starting a page when none has; `None` when it is larger than a page. Empty images, such
+    /// as the glyph of a space, get a zero-area rectangle on page 0 without being packed.
+    pub fn add(&mut self, width: i32, height: i32, pixels: &[u8]) -> Option<AtlasRect> {
+        if width <= 0 || height <= 0 {
+            let (width, height) = (width.max(0), height.max(0));
+            return Some(AtlasRect { page: 0, x: 0, y: 0, width, height, uv_min: Vector2::new(0.0, 0.0), uv_max: Vector2::new(0.0, 0.0) });
+        }
+        assert_eq!(pixels.len(), (width * height * 4) as usize, "expected RGBA8 pixels");
+        let (padded_width, padded_height) = (width + 2 * self.padding, height + 2 * self.padding);
+        if padded_width > self.page_size || padded_height > self.page_size {
+            return None;
+        }
+
+        let found = self.pages.iter_mut().enumerate()
+            .find_map(|(page, atlas_page)| atlas_page.packer.pack(padded_width, padded_height).map(|at| (page, at)));
+        let (page, (x, y)) = match found {
+            Some(found) => found,
+            None => {
+                let size = self.page_size;
+                engine_debug!(logging::RENDERER, "atlas page {} ({}x{})", self.pages.len(), size, size);
+                let mut packer = SkylinePacker::new(size, size);
+                let at = packer.pack(padded_width, padded_height)?;
+                self.pages.push(AtlasPage {
+                    packer,
+                    pixels: vec![0; (size * size * 4) as usize],
+                    texture: Texture::default(),
+                    dirty: true,
+                });
+                (self.pages.len() - 1, at)
+            }
+        };
+
+        let (x, y) = (x + self.padding, y + self.padding);
+        self.blit(page, x, y, width, height, pixels);
+        let size = self.page_size as Float;
+        Some(AtlasRect {
+            page,
+            x,
+            y,
+            width,
+            height,
+            uv_min: Vector2::new(x as Float / size, y as Float / size),
+            uv_max: Vector2::new((x + width) as Float / size, (y + height) as Float / size),
+        })
+    }
+
+    /// Adds the images tallest first, which packs tighter than arrival order;
+    /// the rectangles are returned in the order of `images`
+    pub fn add_all(&mut self, images: &[(i32, i32, &[u8])]) -> Vec<Option<AtlasRect>> {
+        let mut order: Vec<usize> = (0..images.len()).collect();
+        order.sort_by_key(|&i| (-images[i].1, -images[i].0));
+        let mut rects = vec![None; images.len()];
+        for i in order {
+            let (width, height, pixels) = images[i];
+            rects[i] = self.add(width, height, pixels);
+        }
+        rects
+    }
+
+    /// Writes the image and extrudes its border rows and columns into the padding
+    fn blit(&mut self, page: usize, x: i32, y: i32, width: i32, height: i32, pixels: &[u8]) {
+        let (size, padding) = (self.page_size, self.padding);
+        let page = &mut self.pages[page];
+        for py in -padding..height + padding {
+            let sy = py.clamp(0, height - 1);
+            for px in -padding..width + padding {
+                let sx = px.clamp(0, width - 1);
+                let from = ((sy * width + sx) * 4) as usize;
+                let to = (((y + py) * size + x + px) * 4) as usize;
+                page.pixels[to..to + 4].copy_from_slice(&pixels[from..from + 4]);
+            }
+        }
+        page.dirty = true;
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Texture of a page as of the last `flush`
+    pub fn texture(&self, page: usize) -> Texture {
+        self.pages[page].texture
+    }
+
+    /// RGBA8 pixels of a page, bottom row first
+    pub fn pixels(&self, page: usize) -> &[u8] {
+        &self.pages[page].pixels
+    }
+
+    pub fn occupancy(&self, page: usize) -> Float {
+        self.pages[page].packer.occupancy()
+    }
+
+    /// Uploads the pages changed since the last call and regenerates their mipmaps
+    pub fn flush(&mut self) {
+        let size = self.page_size;
+        for page in self.pages.iter_mut().filter(|page| page.dirty) {
+            if page.texture.id == 0 {
+                page.texture = Texture::with_color_space(size, size, &page.pixels, self.color_space);
+            } else {
+                unsafe {
+                    gl::BindTexture(gl::TEXTURE_2D, page.texture.id);
+                    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+                    gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, size, size, gl::RGBA, gl::UNSIGNED_BYTE,
+                                      page.pixels.as_ptr() as *const GLvoid);
+                    gl::GenerateMipmap(gl::TEXTURE_2D);
+                    gl::BindTexture(gl::TEXTURE_2D, 0);
+                }
+            }
+            page.dirty = false;
+        }
+    }
+
+    /// Forgets every image, keeping the page textures to refill
+    pub fn clear(&mut self) {
+        let size = self.page_size;
+        for page in self.pages.iter_mut() {
+            page.packer = SkylinePacker::new(size, size);
+            page.pixels.iter_mut().for_each(|byte| *byte = 0);
+            page.dirty = true;
+        }
+    }
+
+    pub fn delete(&mut self) {
+        for page in self.pages.iter_mut() {
+            if page.texture.id != 0 {
+                page.texture.delete();
+            }
+        }
+        self.pages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: (i32, i32, i32, i32), b: (i32, i32, i32, i32)) -> bool {
+        a.0 < b.0 + b.2 && b.0 < a.0 + a.2 && a.1 < b.1 + b.3 && b.1 < a.1 + a.3
+    }
+
+    #[test]
+    fn skyline_fills_without_overlap() {
+        let mut packer = SkylinePacker::new(128, 128);
+        for _ in 0..64 {
+            assert!(packer.pack(16, 16).is_some());
+        }
+        assert_eq!(packer.pack(1, 1), None);
+        assert_eq!(packer.occupancy(), 1.0);
+
+        let mut packer = SkylinePacker::new(256, 256);
+        let mut placed = Vec::new();
+        for i in 0..200 {
+            let (w, h) = (5 + (i * 7) % 23, 4 + (i * 11) % 19);
+            if let Some((x, y)) = packer.pack(w, h) {
+                let rect = (x, y, w, h);
+                assert!(x + w <= 256 && y + h <= 256);
+                assert!(placed.iter().all(|&other| !overlaps(rect, other)));
+                placed.push(rect);
+            }
+        }
+        // about 63% of the area, all of it fits even in arrival order
+        assert_eq!(placed.len(), 200);
+    }
+
+    #[test]
+    fn pads_with_edge_pixels() {
+        let mut atlas = TextureAtlas::new(16, 2);
+        // 2x1: red then green
+        let rect = atlas.add(2, 1, &[255, 0, 0, 255, 0, 255, 0, 255]).unwrap();
+        assert_eq!((rect.page, rect.x, rect.y), (0, 2, 2));
+        assert_eq!(rect.uv_min, Vector2::new(2.0 / 16.0, 2.0 / 16.0));
+        assert_eq!(rect.uv_max, Vector2::new(4.0 / 16.0, 3.0 / 16.0));
+
+        let pixel = |x: i32, y: i32| &atlas.pixels(0)[((y * 16 + x) * 4) as usize..((y * 16 + x) * 4 + 4) as usize];
+        assert_eq!(pixel(0, 0), &[255, 0, 0, 255]);
+        assert_eq!(pixel(5, 4), &[0, 255, 0, 255]);
+        assert_eq!(pixel(6, 0), &[0, 0, 0, 0]);
+
+        assert!(atlas.add(13, 1, &[0; 13 * 4]).is_none());
+        let rects = atlas.add_all(&[(4, 4, &[0; 64][..]), (8, 8, &[0; 256][..])]);
+        assert!(rects.iter().all(Option::is_some));
+        assert_eq!(atlas.page_count(), 2);
+    }
+
+    #[test]
+    fn empty_images_are_not_packed() {
+        let mut atlas = TextureAtlas::new(16, 2);
+        let space = atlas.add(0, 12, &[]).unwrap();
+        assert_eq!((space.width, space.height), (0, 12));
+        assert_eq!(space.uv_min, space.uv_max);
+        assert!(atlas.add(5, 0, &[]).is_some());
+        assert_eq!(atlas.page_count(), 0);
+    }
+}
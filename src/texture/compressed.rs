@@ -0,0 +1,717 @@
+//! Block-compressed textures from DDS and KTX2 containers. BCn and ETC2 levels are uploaded
+//! as stored when the driver supports the format; BC1 to BC5 are otherwise decoded to RGBA8
+//! when loading, which is also the offline path for tools. KTX2 levels may be zlib
+//! supercompressed; BasisLZ and Zstandard files have to be transcoded to a block format
+//! first. Both containers store the top row first, see `CompressedImage::top_down`.
+
+use std::convert::TryFrom;
+use std::io;
+
+use gl;
+use gl::types::*;
+
+use buffer::supports;
+use color::ColorSpace;
+use error::{EngineError, EngineResult};
+use logging;
+use vfs::Vfs;
+use super::{set_filtering, Texture};
+
+const COMPRESSED_RGBA_S3TC_DXT1: GLenum = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT5: GLenum = 0x83F3;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: GLenum = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: GLenum = 0x8C4F;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CompressedFormat {
+    /// RGB with 1 bit alpha, 8 bytes per 4x4 block
+    Bc1,
+    /// RGBA, BC1 color with a BC4 alpha
+    Bc3,
+    /// single channel
+    Bc4,
+    /// two channels, for normal maps
+    Bc5,
+    /// high quality RGBA
+    Bc7,
+    Etc2Rgb,
+    Etc2Rgba,
+}
+
+impl CompressedFormat {
+    pub fn block_bytes(self) -> usize {
+        match self {
+            CompressedFormat::Bc1 | CompressedFormat::Bc4 | CompressedFormat::Etc2Rgb => 8,
+            _ => 16,
+        }
+    }
+
+    /// Bytes of a `width` x `height` level, in whole 4x4 blocks
+    pub fn level_size(self, width: usize, height: usize) -> usize {
+        width.div_ceil(4).max(1) * height.div_ceil(4).max(1) * self.block_bytes()
+    }
+
+    /// GL internal format, the sRGB variant for color data where one exists
+    pub fn internal_format(self, color_space: ColorSpace) -> GLenum {
+        let srgb = color_space == ColorSpace::Srgb;
+        match self {
+            CompressedFormat::Bc1 if srgb => COMPRESSED_SRGB_ALPHA_S3TC_DXT1,
+            CompressedFormat::Bc1 => COMPRESSED_RGBA_S3TC_DXT1,
+            CompressedFormat::Bc3 if srgb => COMPRESSED_SRGB_ALPHA_S3TC_DXT5,
+            CompressedFormat::Bc3 => COMPRESSED_RGBA_S3TC_DXT5,
+            CompressedFormat::Bc4 => gl::COMPRESSED_RED_RGTC1,
+            CompressedFormat::Bc5 => gl::COMPRESSED_RG_RGTC2,
+            CompressedFormat::Bc7 if srgb => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+            CompressedFormat::Bc7 => gl::COMPRESSED_RGBA_BPTC_UNORM,
+            CompressedFormat::Etc2Rgb if srgb => gl::COMPRESSED_SRGB8_ETC2,
+            CompressedFormat::Etc2Rgb => gl::COMPRESSED_RGB8_ETC2,
+            CompressedFormat::Etc2Rgba if srgb => gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC,
+            CompressedFormat::Etc2Rgba => gl::COMPRESSED_RGBA8_ETC2_EAC,
+        }
+    }
+
+    /// Whether the context can sample the format directly
+    pub fn is_supported(self) -> bool {
+        match self {
+            CompressedFormat::Bc1 | CompressedFormat::Bc3 => supports((99, 0), "GL_EXT_texture_compression_s3tc"),
+            CompressedFormat::Bc4 | CompressedFormat::Bc5 => true,
+            CompressedFormat::Bc7 => supports((4, 2), "GL_ARB_texture_compression_bptc"),
+            CompressedFormat::Etc2Rgb | CompressedFormat::Etc2Rgba => supports((4, 3), "GL_ARB_ES3_compatibility"),
+        }
+    }
+
+    /// Whether `decode` can turn it into RGBA8
+    pub fn can_decode(self) -> bool {
+        matches!(self, CompressedFormat::Bc1 | CompressedFormat::Bc3 | CompressedFormat::Bc4 | CompressedFormat::Bc5)
+    }
+}
+
+/// Full mip chain of a 2D compressed texture, largest level first
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompressedImage {
+    pub format: CompressedFormat,
+    pub width: usize,
+    pub height: usize,
+    pub levels: Vec<Vec<u8>>,
+    /// the color space the container declares, if it does
+    pub color_space: Option<ColorSpace>,
+    /// the first row is the top of the image, as DDS and KTX2 store it unless authored otherwise
+    pub top_down: bool,
+}
+
+impl CompressedImage {
+    /// Parses a DDS or KTX2 file, told apart by their magic bytes
+    pub fn parse(bytes: &[u8]) -> io::Result<CompressedImage> {
+        if bytes.starts_with(b"DDS ") {
+            CompressedImage::from_dds(bytes)
+        } else if bytes.starts_with(&KTX2_IDENTIFIER) {
+            CompressedImage::from_ktx2(bytes)
+        } else {
+            Err(invalid("not a DDS or KTX2 file"))
+        }
+    }
+
+    pub fn load(vfs: &Vfs, path: &str) -> EngineResult<CompressedImage> {
+        let bytes = vfs.read(path)?;
+        CompressedImage::parse(&bytes).map_err(|error| EngineError::asset_io(path, error))
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> io::Result<CompressedImage> {
+        if bytes.len() < 128 || !bytes.starts_with(b"DDS ") || read_u32(bytes, 4)? != 124 {
+            return Err(invalid("invalid DDS header"));
+        }
+        let (height, width) = (read_u32(bytes, 12)? as usize, read_u32(bytes, 16)? as usize);
+        let levels = mip_levels(read_u32(bytes, 28)?, width, height)?;
+        let caps2 = read_u32(bytes, 112)?;
+        if caps2 & 0x200 != 0 || caps2 & 0x200000 != 0 {
+            return Err(invalid("DDS cube maps and volumes are not supported"));
+        }
+
+        let mut offset = 128;
+        let (format, color_space) = match &bytes[84..88] {
+            b"DXT1" => (CompressedFormat::Bc1, None),
+            b"DXT5" => (CompressedFormat::Bc3, None),
+            b"ATI1" | b"BC4U" => (CompressedFormat::Bc4, Some(ColorSpace::Linear)),
+            b"ATI2" | b"BC5U" => (CompressedFormat::Bc5, Some(ColorSpace::Linear)),
+            b"DX10" => {
+                offset += 20;
+                if read_u32(bytes, 140)? > 1 {
+                    return Err(invalid("DDS texture arrays are not supported"));
+                }
+                match read_u32(bytes, 128)? {
+                    71 => (CompressedFormat::Bc1, Some(ColorSpace::Linear)),
+                    72 => (CompressedFormat::Bc1, Some(ColorSpace::Srgb)),
+                    77 => (CompressedFormat::Bc3, Some(ColorSpace::Linear)),
+                    78 => (CompressedFormat::Bc3, Some(ColorSpace::Srgb)),
+                    80 => (CompressedFormat::Bc4, Some(ColorSpace::Linear)),
+                    83 => (CompressedFormat::Bc5, Some(ColorSpace::Linear)),
+                    98 => (CompressedFormat::Bc7, Some(ColorSpace::Linear)),
+                    99 => (CompressedFormat::Bc7, Some(ColorSpace::Srgb)),
+                    other => return Err(invalid(&format!("unsupported DXGI format {}", other))),
+                }
+            }
+            other => return Err(invalid(&format!("unsupported DDS format {:?}", String::from_utf8_lossy(other)))),
+        };
+
+        let mut image = CompressedImage { format, width, height, levels: Vec::with_capacity(levels), color_space, top_down: true };
+        for level in 0..levels {
+            let (w, h) = image.level_dimensions(level);
+            let size = format.level_size(w, h);
+            image.levels.push(range(bytes, offset, size, "truncated DDS level")?.to_vec());
+            offset += size;
+        }
+        Ok(image)
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> io::Result<CompressedImage> {
+        if bytes.len() < 80 || !bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Err(invalid("invalid KTX2 header"));
+        }
+        let (format, color_space) = match read_u32(bytes, 12)? {
+            131 | 133 => (CompressedFormat::Bc1, ColorSpace::Linear),
+            132 | 134 => (CompressedFormat::Bc1, ColorSpace::Srgb),
+            137 => (CompressedFormat::Bc3, ColorSpace::Linear),
+            138 => (CompressedFormat::Bc3, ColorSpace::Srgb),
+            139 => (CompressedFormat::Bc4, ColorSpace::Linear),
+            141 => (CompressedFormat::Bc5, ColorSpace::Linear),
+            145 => (CompressedFormat::Bc7, ColorSpace::Linear),
+            146 => (CompressedFormat::Bc7, ColorSpace::Srgb),
+            147 => (CompressedFormat::Etc2Rgb, ColorSpace::Linear),
+            148 => (CompressedFormat::Etc2Rgb, ColorSpace::Srgb),
+            151 => (CompressedFormat::Etc2Rgba, ColorSpace::Linear),
+            152 => (CompressedFormat::Etc2Rgba, ColorSpace::Srgb),
+            0 => return Err(invalid("KTX2 without a block format (Basis Universal) has to be transcoded first")),
+            other => return Err(invalid(&format!("unsupported KTX2 vkFormat {}", other))),
+        };
+        let (width, height) = (read_u32(bytes, 20)? as usize, read_u32(bytes, 24)? as usize);
+        if read_u32(bytes, 28)? > 1 || read_u32(bytes, 32)? > 1 || read_u32(bytes, 36)? != 1 {
+            return Err(invalid("only 2D KTX2 textures are supported"));
+        }
+        let levels = mip_levels(read_u32(bytes, 40)?, width, height)?;
+        let scheme = read_u32(bytes, 44)?;
+        if scheme != 0 && scheme != 3 {
+            let name = match scheme { 1 => "BasisLZ", 2 => "Zstandard", _ => "unknown" };
+            return Err(invalid(&format!("KTX2 {} supercompression is not supported, transcode the file first", name)));
+        }
+
+        let mut top_down = true;
+        let (kvd_offset, kvd_length) = (read_u32(bytes, 56)? as usize, read_u32(bytes, 60)? as usize);
+        let kvd = range(bytes, kvd_offset, kvd_length, "truncated KTX2 key/values")?;
+        let mut at = 0;
+        while at + 4 <= kvd.len() {
+            let length = read_u32(kvd, at)? as usize;
+            let entry = range(kvd, at + 4, length, "truncated KTX2 key/value")?;
+            if let Some(value) = entry.strip_prefix(b"KTXorientation\0".as_ref()) {
+                top_down = value.get(1) != Some(&b'u');
+            }
+            at += 4 + length.div_ceil(4) * 4;
+        }
+
+        let mut image = CompressedImage {
+            format,
+            width,
+            height,
+            levels: Vec::with_capacity(levels),
+            color_space: Some(color_space),
+            top_down,
+        };
+        for level in 0..levels {
+            let entry = 80 + level * 24;
+            let offset = read_u64(bytes, entry)?;
+            let length = read_u64(bytes, entry + 8)?;
+            let data = match (usize::try_from(offset), usize::try_from(length)) {
+                (Ok(offset), Ok(length)) => range(bytes, offset, length, "truncated KTX2 level")?,
+                _ => return Err(invalid("truncated KTX2 level")),
+            };
+            let data = if scheme == 3 { inflate_zlib(data)? } else { data.to_vec() };
+            let (w, h) = image.level_dimensions(level);
+            if data.len() != format.level_size(w, h) {
+                return Err(invalid("KTX2 level size does not match its dimensions"));
+            }
+            image.levels.push(data);
+        }
+        Ok(image)
+    }
+
+    pub fn level_dimensions(&self, level: usize) -> (usize, usize) {
+        let shift = |size: usize| size.checked_shr(level.min(u32::MAX as usize) as u32).unwrap_or(0).max(1);
+        (shift(self.width), shift(self.height))
+    }
+
+    /// RGBA8 pixels of every level in the stored row order, `None` for formats `can_decode`
+    /// rejects. BC4 and BC5 decode like GL samples them, missing channels 0 and alpha opaque.
+    pub fn decode(&self) -> Option<Vec<Vec<u8>>> {
+        if !self.format.can_decode() {
+            return None;
+        }
+        let levels = self.levels.iter().enumerate()
+            .map(|(level, data)| {
+                let (width, height) = self.level_dimensions(level);
+                decode_level(self.format, data, width, height)
+            })
+            .collect();
+        Some(levels)
+    }
+}
+
+impl Texture {
+    /// Uploads the levels as stored, or decoded to RGBA8 when the driver lacks the format.
+    /// `color_space` applies when the container doesn't declare one. Fails for formats
+    /// neither supported nor decodable, e.g. BC7 before GL 4.2.
+    pub fn from_compressed(image: &CompressedImage, color_space: ColorSpace) -> EngineResult<Texture> {
+        let color_space = image.color_space.unwrap_or(color_space);
+        let supported = image.format.is_supported();
+        let decoded = if supported { None } else { image.decode() };
+        if !supported && decoded.is_none() {
+            return Err(EngineError::InvalidSource(format!("{:?} textures are not supported by the driver", image.format)));
+        }
+
+        let mut texture = Texture { width: image.width as i32, height: image.height as i32, color_space, ..Texture::default() };
+        unsafe {
+            gl::GenTextures(1, &mut texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            for level in 0..image.levels.len() {
+                let (width, height) = image.level_dimensions(level);
+                match decoded {
+                    Some(ref levels) => {
+                        gl::TexImage2D(gl::TEXTURE_2D, level as GLint, color_space.internal_format() as GLint,
+                                       width as GLsizei, height as GLsizei, 0, gl::RGBA, gl::UNSIGNED_BYTE,
+                                       levels[level].as_ptr() as *const GLvoid);
+                    }
+                    None => {
+                        let data = &image.levels[level];
+                        gl::CompressedTexImage2D(gl::TEXTURE_2D, level as GLint, image.format.internal_format(color_space),
+                                                 width as GLsizei, height as GLsizei, 0, data.len() as GLsizei,
+                                                 data.as_ptr() as *const GLvoid);
+                    }
+                }
+            }
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, image.levels.len() as GLint - 1);
+            set_filtering(gl::TEXTURE_2D, texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        if decoded.is_some() {
+            engine_info!(logging::RENDERER, "{:?} is not supported, decoded a {}x{} texture to RGBA8",
+                         image.format, image.width, image.height);
+        }
+        Ok(texture)
+    }
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn read_u32(bytes: &[u8], at: usize) -> io::Result<u32> {
+    let b = bytes.get(at..at + 4).ok_or_else(|| invalid("truncated header"))?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> io::Result<u64> {
+    Ok(u64::from(read_u32(bytes, at)?) | (u64::from(read_u32(bytes, at + 4)?) << 32))
+}
+
+/// Largest width or height accepted from a header, well above what GL can allocate
+const MAX_DIMENSION: usize = 1 << 16;
+
+/// Level count of a header, at least 1 and at most a full chain down to 1x1, so a corrupt
+/// count never sizes an allocation
+fn mip_levels(levels: u32, width: usize, height: usize) -> io::Result<usize> {
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(invalid(&format!("unsupported texture size {}x{}", width, height)));
+    }
+    let full_chain = (usize::BITS - width.max(height).leading_zeros()) as usize;
+    match levels as usize {
+        levels if levels > full_chain => Err(invalid(&format!("{} mip levels for a {}x{} texture", levels, width, height))),
+        levels => Ok(levels.max(1)),
+    }
+}
+
+/// `length` bytes at `offset`, `reason` when they are past the end
+fn range<'a>(bytes: &'a [u8], offset: usize, length: usize, reason: &str) -> io::Result<&'a [u8]> {
+    offset.checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| invalid(reason))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+fn decode_level(format: CompressedFormat, data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = vec![0; width * height * 4];
+    let blocks_x = width.div_ceil(4);
+    for (b, block) in data.chunks(format.block_bytes()).enumerate() {
+        let mut texels = [[0, 0, 0, 255]; 16];
+        match format {
+            CompressedFormat::Bc1 => decode_bc1(block, &mut texels, true),
+            CompressedFormat::Bc3 => {
+                decode_bc1(&block[8..], &mut texels, false);
+                for (texel, alpha) in texels.iter_mut().zip(decode_bc4(&block[..8]).iter()) {
+                    texel[3] = *alpha;
+                }
+            }
+            CompressedFormat::Bc4 => {
+                for (texel, red) in texels.iter_mut().zip(decode_bc4(block).iter()) {
+                    texel[0] = *red;
+                }
+            }
+            CompressedFormat::Bc5 => {
+                let (red, green) = (decode_bc4(&block[..8]), decode_bc4(&block[8..]));
+                for (i, texel) in texels.iter_mut().enumerate() {
+                    texel[0] = red[i];
+                    texel[1] = green[i];
+                }
+            }
+            _ => unreachable!("{:?} cannot be decoded", format),
+        }
+
+        let (bx, by) = (b % blocks_x * 4, b / blocks_x * 4);
+        for (i, texel) in texels.iter().enumerate() {
+            let (x, y) = (bx + i % 4, by + i / 4);
+            if x < width && y < height {
+                let at = (y * width + x) * 4;
+                pixels[at..at + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    pixels
+}
+
+fn rgb565(color: u16) -> [u32; 3] {
+    let (r, g, b) = (u32::from(color >> 11), u32::from((color >> 5) & 0x3F), u32::from(color & 0x1F));
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// `allow_transparent` is false for the color half of BC3, which always uses four colors
+fn decode_bc1(block: &[u8], texels: &mut [[u8; 4]; 16], allow_transparent: bool) {
+    let (c0, c1) = (u16::from_le_bytes([block[0], block[1]]), u16::from_le_bytes([block[2], block[3]]));
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32| -> [u8; 4] {
+        let total = wa + wb;
+        [((a[0] * wa + b[0] * wb) / total) as u8, ((a[1] * wa + b[1] * wb) / total) as u8,
+         ((a[2] * wa + b[2] * wb) / total) as u8, 255]
+    };
+    let palette = if c0 > c1 || !allow_transparent {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0, 0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (i * 2)) & 3) as usize];
+    }
+}
+
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (r0, r1) = (u32::from(block[0]), u32::from(block[1]));
+    let mut palette = [0u8; 8];
+    palette[0] = r0 as u8;
+    palette[1] = r1 as u8;
+    if r0 > r1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * r0 + i as u32 * r1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * r0 + i as u32 * r1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+    let bits = block[2..8].iter().rev().fold(0u64, |bits, &byte| (bits << 8) | u64::from(byte));
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((bits >> (i * 3)) & 7) as usize];
+    }
+    values
+}
+
+/// zlib stream (RFC 1950) around DEFLATE data (RFC 1951), the checksum is not verified
+fn inflate_zlib(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 2 || data[0] & 0x0F != 8 || (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 != 0 {
+        return Err(invalid("invalid zlib header"));
+    }
+    let mut bits = Bits { data, position: 2, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.buffer = 0;
+                bits.count = 0;
+                let header = data.get(bits.position..bits.position + 4).ok_or_else(|| invalid("truncated stored block"))?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let stored = data.get(bits.position + 4..bits.position + 4 + length)
+                    .ok_or_else(|| invalid("truncated stored block"))?;
+                out.extend_from_slice(stored);
+                bits.position += 4 + length;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (symbol, length) in lengths.iter_mut().enumerate() {
+                    *length = match symbol { 0..=143 => 8, 144..=255 => 9, 256..=279 => 7, _ => 8 };
+                }
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_tables(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    /// The next `n` bits, least significant first
+    fn take(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.position).ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.buffer |= u32::from(byte) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+}
+
+/// Canonical Huffman code as symbol counts per length, decoded bit by bit
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate().filter(|&(_, &length)| length != 0) {
+            symbols[offsets[length as usize] as usize] = symbol as u16;
+            offsets[length as usize] += 1;
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+                                115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+                                  1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11,
+                                  12, 12, 13, 13];
+
+fn read_dynamic_tables(bits: &mut Bits) -> io::Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_count = bits.take(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &symbol in ORDER.iter().take(code_count) {
+        code_lengths[symbol] = bits.take(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match codes.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| invalid("repeat without a previous length"))?, 3 + bits.take(2)?),
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        lengths.extend((0..repeat).map(|_| value));
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err(invalid("code lengths overflow"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length code"));
+                }
+                let length = LENGTH_BASE[code] as usize + bits.take(u32::from(LENGTH_EXTRA[code]))? as usize;
+                let code = distances.decode(bits)? as usize;
+                if code >= DISTANCE_BASE.len() {
+                    return Err(invalid("invalid distance code"));
+                }
+                let distance = DISTANCE_BASE[code] as usize + bits.take(u32::from(DISTANCE_EXTRA[code]))? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance before the start of the stream"));
+                }
+                // byte by byte, matches may overlap what they produce
+                let start = out.len() - distance;
+                for k in 0..length {
+                    let byte = out[start + k];
+                    out.push(byte);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Red, blue and the two thirds in between, one per row
+    const BC1_BLOCK: [u8; 8] = [0x00, 0xF8, 0x1F, 0x00, 0x00, 0x55, 0xAA, 0xFF];
+
+    fn dds(fourcc: &[u8; 4], width: u32, height: u32, levels: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 128];
+        bytes[..4].copy_from_slice(b"DDS ");
+        bytes[4..8].copy_from_slice(&124u32.to_le_bytes());
+        bytes[12..16].copy_from_slice(&height.to_le_bytes());
+        bytes[16..20].copy_from_slice(&width.to_le_bytes());
+        bytes[28..32].copy_from_slice(&levels.to_le_bytes());
+        bytes[84..88].copy_from_slice(fourcc);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn parses_dds_mip_chain_and_decodes_bc1() {
+        // 8x4 base level of two blocks, then 4x2 and 2x1 levels of one block each
+        let data: Vec<u8> = (0..4).flat_map(|_| BC1_BLOCK.iter().cloned()).collect();
+        let image = CompressedImage::parse(&dds(b"DXT1", 8, 4, 3, &data)).unwrap();
+        assert_eq!((image.format, image.width, image.height), (CompressedFormat::Bc1, 8, 4));
+        assert_eq!(image.levels.iter().map(Vec::len).collect::<Vec<_>>(), vec![16, 8, 8]);
+        assert!(CompressedImage::parse(&dds(b"DXT1", 8, 4, 3, &data[..20])).is_err());
+
+        let levels = image.decode().unwrap();
+        let pixel = |x: usize, y: usize| &levels[0][(y * 8 + x) * 4..(y * 8 + x) * 4 + 4];
+        assert_eq!(pixel(5, 0), &[255, 0, 0, 255]);
+        assert_eq!(pixel(5, 1), &[0, 0, 255, 255]);
+        assert_eq!(pixel(1, 2), &[170, 0, 85, 255]);
+        assert_eq!(pixel(1, 3), &[85, 0, 170, 255]);
+        assert_eq!(levels[2].len(), 2 * 4);
+    }
+
+    #[test]
+    fn rejects_corrupt_level_counts_and_offsets() {
+        let data: Vec<u8> = (0..4).flat_map(|_| BC1_BLOCK.iter().cloned()).collect();
+        assert!(CompressedImage::parse(&dds(b"DXT1", 8, 4, 0xFFFF_FFFF, &data)).is_err());
+        assert!(CompressedImage::parse(&dds(b"DXT1", 8, 4, 5, &data)).is_err());
+        assert!(CompressedImage::parse(&dds(b"DXT1", 0, 4, 1, &data)).is_err());
+        assert_eq!(CompressedImage::parse(&dds(b"DXT1", 8, 4, 0, &data)).unwrap().levels.len(), 1);
+        assert!(range(&data, usize::MAX, 2, "overflow").is_err());
+
+        let image = CompressedImage::parse(&dds(b"DXT1", 8, 4, 1, &data)).unwrap();
+        assert_eq!(image.level_dimensions(200), (1, 1));
+
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for value in [133u32, 1, 4, 4, 0, 0, 1, 1, 0, 0, 0, 0, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 16]);
+        for value in [u64::MAX, 8, 8] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        assert_eq!(CompressedImage::parse(&bytes).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decodes_bc4_palettes() {
+        // indices 0, 1, 2
+        let eight = decode_bc4(&[200, 100, 0b1000_1000, 0, 0, 0, 0, 0]);
+        assert_eq!(&eight[..3], &[200, 100, 185]);
+        // indices 6, 7, 0: the explicit black and white of the six value mode
+        let six = decode_bc4(&[100, 200, 0b0011_1110, 0, 0, 0, 0, 0]);
+        assert_eq!(&six[..3], &[0, 255, 100]);
+    }
+
+    #[test]
+    fn reads_zlib_supercompressed_ktx2() {
+        let base: Vec<u8> = BC1_BLOCK.to_vec();
+        // zlib stream with a single stored block
+        let mut level = vec![0x78, 0x01, 0x01, 8, 0, !8, !0];
+        level.extend_from_slice(&base);
+        level.extend_from_slice(&[0, 0, 0, 0]);
+
+        let orientation = b"KTXorientation\0ru\0";
+        let kvd_offset = 80 + 24;
+        let level_offset = kvd_offset + 4 + 20;
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for value in [133u32, 1, 4, 4, 0, 0, 1, 1, 3, 0, 0, kvd_offset as u32, (4 + orientation.len()) as u32] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 16]);
+        for value in [level_offset as u64, level.len() as u64, 8] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(orientation.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(orientation);
+        bytes.resize(level_offset, 0);
+        bytes.extend_from_slice(&level);
+
+        let image = CompressedImage::parse(&bytes).unwrap();
+        assert_eq!(image.format, CompressedFormat::Bc1);
+        assert_eq!(image.color_space, Some(ColorSpace::Linear));
+        assert!(!image.top_down);
+        assert_eq!(image.levels, vec![base]);
+    }
+
+    #[test]
+    fn inflates_huffman_blocks() {
+        let fixed = [0x78, 0x9c, 0x4b, 0xca, 0xc9, 0x4f, 0xce, 0x56, 0x48, 0x42, 0x90, 0x00, 0x39, 0x5f, 0x06, 0x62];
+        assert_eq!(inflate_zlib(&fixed).unwrap(), b"block block block".to_vec());
+
+        let dynamic = [
+            0x78, 0xda, 0x6d, 0x8f, 0x41, 0x0a, 0x80, 0x30, 0x0c, 0x04, 0xbf, 0xd2, 0xb7, 0x45, 0x72, 0x10, 0x23,
+            0x7a, 0x10, 0xf1, 0xf9, 0xd2, 0x06, 0xdc, 0x21, 0xf5, 0xb2, 0x24, 0x9b, 0xcd, 0xb4, 0xb1, 0x38, 0x96,
+            0xad, 0xd9, 0xd0, 0x7d, 0x3d, 0x5b, 0xf8, 0xed, 0xd1, 0x2e, 0x7f, 0x3e, 0x4d, 0xa7, 0xcf, 0x0c, 0xd9,
+            0x74, 0xa9, 0x9c, 0x32, 0x43, 0x56, 0xfa, 0x59, 0x77, 0xa2, 0xaa, 0x99, 0x37, 0xb3, 0xb5, 0x41, 0x5a,
+            0x4d, 0xe8, 0xbf, 0x24, 0xa8, 0xaf, 0x2c, 0x5d, 0x26, 0x4f, 0x7d, 0x7d, 0x93, 0xcc, 0x7a, 0x07, 0xf9,
+            0xff, 0x97, 0x91, 0x84, 0x9d, 0x17, 0xa9, 0x48, 0x8e, 0xd4,
+        ];
+        let text = String::from_utf8(inflate_zlib(&dynamic).unwrap()).unwrap();
+        assert_eq!(text.len(), 389);
+        assert!(text.starts_with("block block mip level texel"));
+        assert_eq!(text.split(' ').filter(|word| ["block", "mip", "level", "texel"].contains(word)).count(), 70);
+    }
+}
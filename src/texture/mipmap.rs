@@ -0,0 +1,245 @@
+//! CPU mip chains with a choice of downsampling filter, and upload of chains authored by
+//! hand. Levels are filtered from the previous one in linear floating point, sRGB textures
+//! are decoded first, and ringing from the Kaiser filter is clamped at every level.
+
+use gl;
+use gl::types::*;
+
+use color::{linear_to_srgb, srgb_to_linear, ColorSpace};
+use config::MipFilter;
+use lang::{Float, PI};
+use super::{set_filtering, Texture};
+
+/// Half-width of the Kaiser kernel in pixels of the smaller level
+const KAISER_WIDTH: Float = 3.0;
+const KAISER_ALPHA: Float = 4.0;
+
+/// One level of a mip chain, RGBA8 pixels bottom row first
+#[derive(Clone, PartialEq, Debug)]
+pub struct MipLevel {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u8>,
+}
+
+/// Dimensions of every level down to 1x1, the base level first
+pub fn level_sizes(width: i32, height: i32) -> Vec<(i32, i32)> {
+    let mut sizes = vec![(width, height)];
+    let (mut w, mut h) = (width, height);
+    while w > 1 || h > 1 {
+        w = (w / 2).max(1);
+        h = (h / 2).max(1);
+        sizes.push((w, h));
+    }
+    sizes
+}
+
+/// Full chain of `pixels`, starting with a copy of the base level. `MipFilter::Gl` can only
+/// run on the driver and is box filtered here.
+pub fn generate(width: i32, height: i32, pixels: &[u8], filter: MipFilter, color_space: ColorSpace) -> Vec<MipLevel> {
+    assert_eq!(pixels.len(), (width * height * 4) as usize, "expected RGBA8 pixels");
+    let normal_map = filter == MipFilter::NormalMap;
+    let decode_srgb = color_space == ColorSpace::Srgb && !normal_map;
+
+    let mut image: Vec<[Float; 4]> = pixels.chunks(4)
+        .map(|p| {
+            let mut texel = [0.0; 4];
+            for (channel, &value) in p.iter().enumerate() {
+                let value = Float::from(value) / 255.0;
+                texel[channel] = match channel {
+                    3 => value,
+                    _ if normal_map => value * 2.0 - 1.0,
+                    _ if decode_srgb => srgb_to_linear(value),
+                    _ => value,
+                };
+            }
+            texel
+        })
+        .collect();
+
+    let mut levels = vec![MipLevel { width, height, pixels: pixels.to_vec() }];
+    for &(w, h) in level_sizes(width, height).iter().skip(1) {
+        let previous = levels.last().unwrap();
+        let (kernel, support): (fn(Float) -> Float, Float) = match filter {
+            MipFilter::Kaiser => (kaiser, KAISER_WIDTH),
+            _ => (box_kernel, 0.5),
+        };
+        let rows = resample(&image, previous.width, previous.height, w, true, kernel, support);
+        image = resample(&rows, w, previous.height, h, false, kernel, support);
+
+        for texel in image.iter_mut() {
+            if normal_map {
+                let length = (texel[0] * texel[0] + texel[1] * texel[1] + texel[2] * texel[2]).sqrt();
+                if length > 0.0 {
+                    for value in texel.iter_mut().take(3) {
+                        *value /= length;
+                    }
+                }
+            } else {
+                for value in texel.iter_mut() {
+                    *value = value.clamp(0.0, 1.0);
+                }
+            }
+            texel[3] = texel[3].clamp(0.0, 1.0);
+        }
+
+        let pixels = image.iter()
+            .flat_map(|texel| {
+                let encode = move |channel: usize| -> u8 {
+                    let value = match channel {
+                        3 => texel[3],
+                        _ if normal_map => texel[channel] * 0.5 + 0.5,
+                        _ if decode_srgb => linear_to_srgb(texel[channel]),
+                        _ => texel[channel],
+                    };
+                    (value * 255.0).round().clamp(0.0, 255.0) as u8
+                };
+                (0..4).map(encode)
+            })
+            .collect();
+        levels.push(MipLevel { width: w, height: h, pixels });
+    }
+    levels
+}
+
+fn box_kernel(x: Float) -> Float {
+    if x.abs() <= 0.5 { 1.0 } else { 0.0 }
+}
+
+fn kaiser(x: Float) -> Float {
+    let ratio = x / KAISER_WIDTH;
+    if ratio.abs() >= 1.0 {
+        return 0.0;
+    }
+    let window = bessel_i0(KAISER_ALPHA * (1.0 - ratio * ratio).sqrt()) / bessel_i0(KAISER_ALPHA);
+    let sinc = if x.abs() < 1e-6 { 1.0 } else { (PI * x).sin() / (PI * x) };
+    sinc * window
+}
+
+/// Modified Bessel function of the first kind, order 0, by its power series
+fn bessel_i0(x: Float) -> Float {
+    let (mut sum, mut term) = (1.0, 1.0);
+    let quarter = x * x / 4.0;
+    for k in 1..32 {
+        term *= quarter / (k * k) as Float;
+        sum += term;
+        if term < sum * 1e-9 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Resamples one axis to `size`, the kernel is in pixels of the output and edges clamp
+fn resample(image: &[[Float; 4]], width: i32, height: i32, size: i32, horizontal: bool,
+            kernel: fn(Float) -> Float, support: Float) -> Vec<[Float; 4]> {
+    let (source, other) = if horizontal { (width, height) } else { (height, width) };
+    let scale = source as Float / size as Float;
+    let mut out = vec![[0.0; 4]; (size * other) as usize];
+
+    // the taps only depend on the output position along the axis
+    let taps: Vec<Vec<(i32, Float)>> = (0..size)
+        .map(|i| {
+            let centre = (i as Float + 0.5) * scale;
+            let first = (centre - support * scale).floor() as i32;
+            let last = (centre + support * scale).ceil() as i32;
+            let mut taps: Vec<(i32, Float)> = (first..=last)
+                .map(|j| (j.clamp(0, source - 1), kernel((j as Float + 0.5 - centre) / scale)))
+                .filter(|&(_, weight)| weight != 0.0)
+                .collect();
+            let total: Float = taps.iter().map(|tap| tap.1).sum();
+            for tap in taps.iter_mut() {
+                tap.1 /= total;
+            }
+            taps
+        })
+        .collect();
+
+    for o in 0..other {
+        for (i, taps) in taps.iter().enumerate() {
+            let mut sum = [0.0; 4];
+            for &(j, weight) in taps {
+                let texel = if horizontal { image[(o * width + j) as usize] } else { image[(j * width + o) as usize] };
+                for c in 0..4 {
+                    sum[c] += texel[c] * weight;
+                }
+            }
+            let at = if horizontal { o * size + i as i32 } else { i as i32 * width + o };
+            out[at as usize] = sum;
+        }
+    }
+    out
+}
+
+impl Texture {
+    /// Texture whose mipmaps are built with `filter`, e.g. `MipFilter::NormalMap` for normal maps
+    pub fn with_mip_filter(width: i32, height: i32, pixels: &[u8], color_space: ColorSpace, filter: MipFilter) -> Texture {
+        if filter == MipFilter::Gl {
+            return Texture::with_gl_mipmaps(width, height, pixels, color_space);
+        }
+        Texture::from_mip_chain(&generate(width, height, pixels, filter, color_space), color_space)
+    }
+
+    /// Uploads authored levels as they are. The chain may stop before 1x1, but every level
+    /// has to halve the previous one (rounding down, at least 1).
+    pub fn from_mip_chain(levels: &[MipLevel], color_space: ColorSpace) -> Texture {
+        assert!(!levels.is_empty(), "a mip chain needs a base level");
+        let sizes = level_sizes(levels[0].width, levels[0].height);
+        for (level, (mip, &size)) in levels.iter().zip(sizes.iter()).enumerate() {
+            assert_eq!((mip.width, mip.height), size, "mip level {} has the wrong size", level);
+            assert_eq!(mip.pixels.len(), (mip.width * mip.height * 4) as usize, "expected RGBA8 pixels");
+        }
+        assert!(levels.len() <= sizes.len(), "more mip levels than the base level allows");
+
+        let mut texture = Texture { width: levels[0].width, height: levels[0].height, color_space, ..Texture::default() };
+        unsafe {
+            gl::GenTextures(1, &mut texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            for (level, mip) in levels.iter().enumerate() {
+                gl::TexImage2D(gl::TEXTURE_2D, level as GLint, color_space.internal_format() as GLint, mip.width,
+                               mip.height, 0, gl::RGBA, gl::UNSIGNED_BYTE, mip.pixels.as_ptr() as *const GLvoid);
+            }
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, levels.len() as GLint - 1);
+            set_filtering(gl::TEXTURE_2D, texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        texture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_sizes_halve_to_one() {
+        assert_eq!(level_sizes(8, 2), vec![(8, 2), (4, 1), (2, 1), (1, 1)]);
+        let levels = generate(5, 3, &[128; 5 * 3 * 4], MipFilter::Kaiser, ColorSpace::Linear);
+        assert_eq!(levels.iter().map(|level| (level.width, level.height)).collect::<Vec<_>>(),
+                   vec![(5, 3), (2, 1), (1, 1)]);
+        // the weights are normalized, flat images stay flat
+        assert!(levels.iter().all(|level| level.pixels.iter().all(|&value| value == 128)));
+    }
+
+    #[test]
+    fn box_averages_in_linear_space() {
+        let pixels = [0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255];
+        let linear = generate(2, 2, &pixels, MipFilter::Box, ColorSpace::Linear);
+        assert_eq!(&linear[1].pixels[..], &[128, 128, 128, 255]);
+        let srgb = generate(2, 2, &pixels, MipFilter::Box, ColorSpace::Srgb);
+        assert_eq!(&srgb[1].pixels[..], &[188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn renormalizes_normals() {
+        // +x and +y, twice
+        let pixels = [255, 128, 128, 255, 128, 255, 128, 255, 255, 128, 128, 255, 128, 255, 128, 255];
+        let levels = generate(2, 2, &pixels, MipFilter::NormalMap, ColorSpace::Linear);
+        let n = &levels[1].pixels;
+        let decoded: Vec<Float> = n[..3].iter().map(|&c| Float::from(c) / 255.0 * 2.0 - 1.0).collect();
+        let length = decoded.iter().map(|c| c * c).sum::<Float>().sqrt();
+        assert!((length - 1.0).abs() < 0.01, "{:?}", decoded);
+        assert_eq!(n[0], n[1]);
+    }
+}
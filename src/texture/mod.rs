@@ -0,0 +1,160 @@
+pub mod array;
+pub mod atlas;
+pub mod compressed;
+pub mod mipmap;
+pub mod sampler;
+pub mod volume;
+
+use std::cell::RefCell;
+
+use gl;
+use gl::types::*;
+
+use buffer::supports;
+use color::ColorSpace;
+use config::{TextureConfig, TextureFilter};
+use logging;
+
+pub use self::array::{ArrayLayer, TextureArray, TextureArrays};
+pub use self::atlas::{AtlasRect, TextureAtlas};
+pub use self::compressed::{CompressedFormat, CompressedImage};
+pub use self::mipmap::MipLevel;
+pub use self::sampler::{Sampler, SamplerDesc};
+pub use self::volume::{Texture3D, VolumeFormat};
+
+/// RGBA8 2D texture with mipmaps, uploaded from tightly packed pixels (4 bytes each, bottom row first)
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Texture {
+    pub id: u32,
+    pub width: i32,
+    pub height: i32,
+    pub color_space: ColorSpace,
+}
+
+impl Texture {
+    /// Color image in sRGB, sampled as linear
+    pub fn new(width: i32, height: i32, pixels: &[u8]) -> Texture {
+        Texture::with_color_space(width, height, pixels, ColorSpace::Srgb)
+    }
+
+    /// Non-color data such as normal maps, use `ColorSpace::Linear`. Mipmaps are built with
+    /// the `mip_filter` of the texture quality settings.
+    pub fn with_color_space(width: i32, height: i32, pixels: &[u8], color_space: ColorSpace) -> Texture {
+        Texture::with_mip_filter(width, height, pixels, color_space, quality().mip_filter)
+    }
+
+    fn with_gl_mipmaps(width: i32, height: i32, pixels: &[u8], color_space: ColorSpace) -> Texture {
+        assert_eq!(pixels.len(), (width * height * 4) as usize, "expected RGBA8 pixels");
+        let mut texture = Texture { width, height, color_space, ..Texture::default() };
+        unsafe {
+            gl::GenTextures(1, &mut texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, texture.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, color_space.internal_format() as GLint, width, height, 0,
+                           gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const GLvoid);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            set_filtering(gl::TEXTURE_2D, texture.id);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        texture
+    }
+
+    pub fn delete(&mut self) {
+        unregister(self.id);
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+        *self = Texture::default();
+    }
+}
+
+/// `GL_TEXTURE_MAX_ANISOTROPY`, core in 4.6 and missing from the loaded bindings
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+thread_local! {
+    /// GL objects are only used on the context's thread, so the settings live there too
+    static QUALITY: RefCell<Quality> = RefCell::new(Quality::default());
+}
+
+#[derive(Default)]
+struct Quality {
+    config: TextureConfig,
+    /// (target, texture) of every live texture, for `set_quality` to update
+    textures: Vec<(GLenum, u32)>,
+    /// queried on first use, the context has to exist
+    max_anisotropy: Option<f32>,
+}
+
+impl Quality {
+    unsafe fn apply(&mut self, target: GLenum) {
+        let max = *self.max_anisotropy.get_or_insert_with(max_anisotropy);
+        let (min, mag) = filter_params(self.config.filter);
+        gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, min as GLint);
+        gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, mag as GLint);
+        gl::TexParameterf(target, gl::TEXTURE_LOD_BIAS, self.config.mip_bias);
+        if max > 1.0 {
+            gl::TexParameterf(target, TEXTURE_MAX_ANISOTROPY, self.config.anisotropy.clamp(1.0, max));
+        }
+    }
+}
+
+/// The sampling settings textures are created with
+pub fn quality() -> TextureConfig {
+    QUALITY.with(|quality| quality.borrow().config.clone())
+}
+
+/// Changes the sampling settings of all existing and future textures, e.g. from an options menu
+pub fn set_quality(config: &TextureConfig) {
+    QUALITY.with(|quality| {
+        let mut quality = quality.borrow_mut();
+        quality.config = config.clone();
+        engine_info!(logging::RENDERER, "texture quality: {:?} filtering, {}x anisotropy, {} mip bias ({} textures)",
+                     config.filter, config.anisotropy, config.mip_bias, quality.textures.len());
+        for (target, texture) in quality.textures.clone() {
+            unsafe {
+                gl::BindTexture(target, texture);
+                quality.apply(target);
+                gl::BindTexture(target, 0);
+            }
+        }
+    });
+}
+
+/// Min / mag filter pair of a filter mode
+pub fn filter_params(filter: TextureFilter) -> (GLenum, GLenum) {
+    match filter {
+        TextureFilter::Nearest => (gl::NEAREST_MIPMAP_NEAREST, gl::NEAREST),
+        TextureFilter::Bilinear => (gl::LINEAR_MIPMAP_NEAREST, gl::LINEAR),
+        TextureFilter::Trilinear => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR),
+    }
+}
+
+/// max anisotropy of the driver, 1 when anisotropic filtering isn't supported
+pub fn max_anisotropy() -> f32 {
+    if !supports((4, 6), "GL_ARB_texture_filter_anisotropic") && !supports((4, 6), "GL_EXT_texture_filter_anisotropic") {
+        return 1.0;
+    }
+    let mut max = 1.0;
+    unsafe {
+        gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max);
+    }
+    max
+}
+
+/// Applies the quality settings and repeat wrapping to the texture bound to `target`,
+/// and registers it so `set_quality` can update it
+unsafe fn set_filtering(target: GLenum, texture: u32) {
+    gl::TexParameteri(target, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+    gl::TexParameteri(target, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+    QUALITY.with(|quality| {
+        let mut quality = quality.borrow_mut();
+        quality.apply(target);
+        quality.textures.push((target, texture));
+    });
+}
+
+/// forgets a deleted texture
+fn unregister(texture: u32) {
+    QUALITY.with(|quality| quality.borrow_mut().textures.retain(|&(_, id)| id != texture));
+}
@@ -0,0 +1,157 @@
+use gl;
+use gl::types::*;
+
+use config::TextureConfig;
+use super::{filter_params, max_anisotropy, TEXTURE_MAX_ANISOTROPY};
+
+/// Sampling state of a `Sampler`, built with the chained setters
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SamplerDesc {
+    pub min_filter: GLenum,
+    pub mag_filter: GLenum,
+    /// applied to S, T and R
+    pub wrap: GLenum,
+    /// used with `CLAMP_TO_BORDER`
+    pub border: [f32; 4],
+    /// depth comparison function for `sampler2DShadow`, `None` samples depth values
+    pub compare: Option<GLenum>,
+    pub anisotropy: f32,
+    pub lod_bias: f32,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> SamplerDesc {
+        SamplerDesc {
+            min_filter: gl::LINEAR_MIPMAP_LINEAR,
+            mag_filter: gl::LINEAR,
+            wrap: gl::REPEAT,
+            border: [0.0, 0.0, 0.0, 0.0],
+            compare: None,
+            anisotropy: 1.0,
+            lod_bias: 0.0,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// The global texture quality settings with repeat wrapping
+    pub fn from_config(config: &TextureConfig) -> SamplerDesc {
+        let (min_filter, mag_filter) = filter_params(config.filter);
+        SamplerDesc { min_filter, mag_filter, anisotropy: config.anisotropy, lod_bias: config.mip_bias, ..SamplerDesc::default() }
+    }
+
+    /// Hardware 2x2 PCF for shadow maps: linear depth comparisons, outside the map is lit
+    pub fn shadow_pcf() -> SamplerDesc {
+        SamplerDesc::default()
+            .filter(gl::LINEAR, gl::LINEAR)
+            .border(gl::CLAMP_TO_BORDER, [1.0, 1.0, 1.0, 1.0])
+            .compare(gl::LEQUAL)
+    }
+
+    /// UI and text: no mipmaps, clamped so atlas edges don't bleed
+    pub fn ui() -> SamplerDesc {
+        SamplerDesc::default().filter(gl::LINEAR, gl::LINEAR).wrap(gl::CLAMP_TO_EDGE)
+    }
+
+    /// Pixel art and lookup textures
+    pub fn nearest() -> SamplerDesc {
+        SamplerDesc::default().filter(gl::NEAREST, gl::NEAREST).wrap(gl::CLAMP_TO_EDGE)
+    }
+
+    pub fn filter(mut self, min: GLenum, mag: GLenum) -> SamplerDesc {
+        self.min_filter = min;
+        self.mag_filter = mag;
+        self
+    }
+
+    pub fn wrap(mut self, wrap: GLenum) -> SamplerDesc {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn border(mut self, wrap: GLenum, color: [f32; 4]) -> SamplerDesc {
+        self.wrap = wrap;
+        self.border = color;
+        self
+    }
+
+    pub fn compare(mut self, func: GLenum) -> SamplerDesc {
+        self.compare = Some(func);
+        self
+    }
+
+    pub fn anisotropy(mut self, anisotropy: f32) -> SamplerDesc {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub fn lod_bias(mut self, bias: f32) -> SamplerDesc {
+        self.lod_bias = bias;
+        self
+    }
+}
+
+/// GL sampler object. Bound to a texture unit it overrides the sampling parameters of whatever
+/// texture is bound there, so one texture can be read with different settings in different passes.
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
+pub struct Sampler {
+    pub id: u32,
+}
+
+impl Sampler {
+    pub fn new(desc: &SamplerDesc) -> Sampler {
+        let mut sampler = Sampler::default();
+        unsafe {
+            gl::GenSamplers(1, &mut sampler.id);
+        }
+        sampler.set(desc);
+        sampler
+    }
+
+    /// Changes the parameters in place, units it's bound to use them from the next draw
+    pub fn set(&self, desc: &SamplerDesc) {
+        let id = self.id;
+        unsafe {
+            gl::SamplerParameteri(id, gl::TEXTURE_MIN_FILTER, desc.min_filter as GLint);
+            gl::SamplerParameteri(id, gl::TEXTURE_MAG_FILTER, desc.mag_filter as GLint);
+            for &axis in &[gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::SamplerParameteri(id, axis, desc.wrap as GLint);
+            }
+            gl::SamplerParameterfv(id, gl::TEXTURE_BORDER_COLOR, desc.border.as_ptr());
+            match desc.compare {
+                Some(func) => {
+                    gl::SamplerParameteri(id, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+                    gl::SamplerParameteri(id, gl::TEXTURE_COMPARE_FUNC, func as GLint);
+                }
+                None => gl::SamplerParameteri(id, gl::TEXTURE_COMPARE_MODE, gl::NONE as GLint),
+            }
+            gl::SamplerParameterf(id, gl::TEXTURE_LOD_BIAS, desc.lod_bias);
+            let max = max_anisotropy();
+            if max > 1.0 {
+                gl::SamplerParameterf(id, TEXTURE_MAX_ANISOTROPY, desc.anisotropy.clamp(1.0, max));
+            }
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteSamplers(1, &self.id);
+        }
+        *self = Sampler::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::TextureFilter;
+
+    #[test]
+    fn builds_from_config() {
+        let config = TextureConfig { filter: TextureFilter::Bilinear, anisotropy: 4.0, mip_bias: -0.5, ..TextureConfig::default() };
+        let desc = SamplerDesc::from_config(&config);
+        assert_eq!((desc.min_filter, desc.mag_filter), (gl::LINEAR_MIPMAP_NEAREST, gl::LINEAR));
+        assert_eq!(desc.lod_bias, -0.5);
+        assert_eq!(SamplerDesc::shadow_pcf().compare, Some(gl::LEQUAL));
+    }
+}
@@ -0,0 +1,122 @@
+//! Single-channel 3D textures for volumetric data such as CT scans, simulations or density
+//! fields, sampled as a `sampler3D` with trilinear filtering and clamped edges.
+
+use gl;
+use gl::types::*;
+
+use logging;
+
+/// Voxel formats of a `Texture3D`, all normalized or float so shaders read `[0, 1]` or raw values
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum VolumeFormat {
+    #[default]
+    R8,
+    /// 16-bit normalized, e.g. medical scans
+    R16,
+    R32F,
+}
+
+impl VolumeFormat {
+    pub fn bytes_per_voxel(self) -> usize {
+        match self {
+            VolumeFormat::R8 => 1,
+            VolumeFormat::R16 => 2,
+            VolumeFormat::R32F => 4,
+        }
+    }
+
+    /// internal format, pixel format and type of uploads
+    fn gl_formats(self) -> (GLenum, GLenum, GLenum) {
+        match self {
+            VolumeFormat::R8 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+            VolumeFormat::R16 => (gl::R16, gl::RED, gl::UNSIGNED_SHORT),
+            VolumeFormat::R32F => (gl::R32F, gl::RED, gl::FLOAT),
+        }
+    }
+}
+
+/// `TEXTURE_3D` of `width` x `height` x `depth` voxels, slices stored one after another with
+/// x varying fastest. There are no mipmaps: volumes are ray marched at a fixed step.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Texture3D {
+    pub id: u32,
+    pub width: i32,
+    pub height: i32,
+    pub depth: i32,
+    pub format: VolumeFormat,
+}
+
+impl Texture3D {
+    /// Voxels as raw bytes in `format`, native endian for the wider formats
+    pub fn new(width: i32, height: i32, depth: i32, format: VolumeFormat, voxels: &[u8]) -> Texture3D {
+        let mut texture = Texture3D { width, height, depth, format, ..Texture3D::default() };
+        assert_eq!(voxels.len(), texture.slice_bytes() * depth as usize, "expected {:?} voxels", format);
+        let (internal, pixel_format, kind) = format.gl_formats();
+        unsafe {
+            gl::GenTextures(1, &mut texture.id);
+            gl::BindTexture(gl::TEXTURE_3D, texture.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage3D(gl::TEXTURE_3D, 0, internal as GLint, width, height, depth, 0, pixel_format, kind,
+                           voxels.as_ptr() as *const GLvoid);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAX_LEVEL, 0);
+            for wrap in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R].iter() {
+                gl::TexParameteri(gl::TEXTURE_3D, *wrap, gl::CLAMP_TO_EDGE as GLint);
+            }
+            gl::BindTexture(gl::TEXTURE_3D, 0);
+        }
+        engine_debug!(logging::RENDERER, "{}x{}x{} {:?} volume", width, height, depth, format);
+        texture
+    }
+
+    pub fn from_u16(width: i32, height: i32, depth: i32, voxels: &[u16]) -> Texture3D {
+        let bytes: Vec<u8> = voxels.iter().flat_map(|voxel| voxel.to_ne_bytes()).collect();
+        Texture3D::new(width, height, depth, VolumeFormat::R16, &bytes)
+    }
+
+    pub fn from_f32(width: i32, height: i32, depth: i32, voxels: &[f32]) -> Texture3D {
+        let bytes: Vec<u8> = voxels.iter().flat_map(|voxel| voxel.to_ne_bytes()).collect();
+        Texture3D::new(width, height, depth, VolumeFormat::R32F, &bytes)
+    }
+
+    /// Bytes of one z slice
+    pub fn slice_bytes(&self) -> usize {
+        (self.width * self.height) as usize * self.format.bytes_per_voxel()
+    }
+
+    /// Overwrites whole slices starting at `first_slice`, e.g. to stream a time series
+    pub fn update_slices(&mut self, first_slice: i32, voxels: &[u8]) {
+        let slices = voxels.len() / self.slice_bytes().max(1);
+        assert_eq!(voxels.len(), slices * self.slice_bytes(), "expected whole slices");
+        assert!(first_slice >= 0 && first_slice + slices as i32 <= self.depth, "slices out of range");
+        let (_, pixel_format, kind) = self.format.gl_formats();
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_3D, self.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage3D(gl::TEXTURE_3D, 0, 0, 0, first_slice, self.width, self.height, slices as i32,
+                              pixel_format, kind, voxels.as_ptr() as *const GLvoid);
+            gl::BindTexture(gl::TEXTURE_3D, 0);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+        *self = Texture3D::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_sizes_follow_the_format() {
+        let texture = Texture3D { width: 4, height: 3, depth: 2, format: VolumeFormat::R16, ..Texture3D::default() };
+        assert_eq!(texture.slice_bytes(), 24);
+        assert_eq!(VolumeFormat::R32F.bytes_per_voxel(), 4);
+        assert_eq!(VolumeFormat::default(), VolumeFormat::R8);
+    }
+}
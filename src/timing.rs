@@ -6,3 +6,56 @@ pub struct Timing {
     pub delta_time: TimeSec,
     pub last_frame: TimeSec,
 }
+
+/// Frame rate averaged over `interval` seconds, for on-screen or title bar stats
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FrameStats {
+    pub interval: TimeSec,
+    /// frames per second over the last full interval
+    pub fps: f64,
+    /// average frame time in milliseconds over the last full interval
+    pub frame_ms: f64,
+    frames: u32,
+    elapsed: TimeSec,
+}
+
+impl FrameStats {
+    pub fn new(interval: TimeSec) -> FrameStats {
+        FrameStats { interval, fps: 0.0, frame_ms: 0.0, frames: 0, elapsed: 0.0 }
+    }
+
+    /// Counts a frame, returns true when the interval elapsed and `fps` / `frame_ms` changed
+    pub fn record(&mut self, delta_time: TimeSec) -> bool {
+        self.frames += 1;
+        self.elapsed += delta_time;
+        if self.elapsed < self.interval || self.elapsed <= 0.0 {
+            return false;
+        }
+        self.fps = f64::from(self.frames) / self.elapsed;
+        self.frame_ms = self.elapsed * 1000.0 / f64::from(self.frames);
+        self.frames = 0;
+        self.elapsed = 0.0;
+        true
+    }
+}
+
+impl Default for FrameStats {
+    /// four updates per second
+    fn default() -> FrameStats {
+        FrameStats::new(0.25)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_over_interval() {
+        let mut stats = FrameStats::new(0.25);
+        let updates = (0..20).filter(|_| stats.record(0.031_25)).count();
+        assert_eq!(updates, 2);
+        assert!((stats.fps - 32.0).abs() < 1e-3);
+        assert!((stats.frame_ms - 31.25).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+
+use super::{normalize, FileSource};
+
+/// Files held in memory, typically compiled into the binary:
+/// `files.add_static("shaders/sky.frag", include_bytes!("../assets/sky.frag"))`
+#[derive(Default, Debug, Clone)]
+pub struct MemoryFiles {
+    files: BTreeMap<String, Cow<'static, [u8]>>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> MemoryFiles {
+        MemoryFiles::default()
+    }
+
+    /// Adds data borrowed for the whole program, without copying it
+    pub fn add_static(&mut self, path: &str, data: &'static [u8]) {
+        self.insert(path, Cow::Borrowed(data));
+    }
+
+    pub fn add(&mut self, path: &str, data: Vec<u8>) {
+        self.insert(path, Cow::Owned(data));
+    }
+
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        normalize(path).and_then(|path| self.files.get(&path)).map(|data| &data[..])
+    }
+
+    fn insert(&mut self, path: &str, data: Cow<'static, [u8]>) {
+        let path = normalize(path).expect("memory file paths can't leave the root");
+        self.files.insert(path, data);
+    }
+}
+
+impl FileSource for MemoryFiles {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        self.files.get(path).map(|data| Ok(data.to_vec()))
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn files(&self) -> Vec<String> {
+        self.files.keys().cloned().collect()
+    }
+}
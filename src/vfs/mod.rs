@@ -0,0 +1,206 @@
+pub mod memory;
+pub mod pack;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use config::EngineConfig;
+use error::{EngineError, EngineResult};
+use logging;
+
+pub use self::memory::MemoryFiles;
+pub use self::pack::{PackFile, PackWriter};
+
+/// Something files can be read from, mounted into a `Vfs`. Paths are relative to the mount
+/// point, `/`-separated and already normalized.
+pub trait FileSource: Send + Sync {
+    /// `None` when the source doesn't have the file, so the next mount is tried
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>>;
+
+    fn contains(&self, path: &str) -> bool;
+
+    /// Every file path in the source, for listing and debugging
+    fn files(&self) -> Vec<String>;
+}
+
+/// Loose files under a directory
+pub struct Directory {
+    pub root: PathBuf,
+}
+
+impl Directory {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Directory {
+        Directory { root: root.into() }
+    }
+}
+
+impl FileSource for Directory {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        let path = self.root.join(path);
+        if path.is_file() { Some(fs::read(path)) } else { None }
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        collect_files(&self.root, "", &mut files);
+        files
+    }
+}
+
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, &format!("{}/", name), files);
+        } else {
+            files.push(name);
+        }
+    }
+}
+
+struct Mount {
+    /// normalized, empty for the root
+    point: String,
+    source: Box<dyn FileSource>,
+}
+
+/// Virtual filesystem assets are loaded through: sources are mounted at virtual directories,
+/// and mounts added later overlay earlier ones, so a patch pack or a loose `mods` directory
+/// can replace files of the base pack without touching it.
+#[derive(Default)]
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    pub fn new() -> Vfs {
+        Vfs::default()
+    }
+
+    /// `asset_root` mounted at the root, read as a pack if it is a file and as loose files otherwise
+    pub fn from_config(config: &EngineConfig) -> EngineResult<Vfs> {
+        let mut vfs = Vfs::new();
+        let root = &config.asset_root;
+        if root.is_file() {
+            vfs.mount("", PackFile::open(root)?);
+        } else {
+            vfs.mount("", Directory::new(root.clone()));
+        }
+        Ok(vfs)
+    }
+
+    /// Mounts the source at `point` (`""` or `"/"` for the root) on top of the existing mounts
+    pub fn mount<S: FileSource + 'static>(&mut self, point: &str, source: S) {
+        let point = normalize(point).unwrap_or_default();
+        engine_info!(logging::RESOURCES, "mounted {} files at /{}", source.files().len(), point);
+        self.mounts.push(Mount { point, source: Box::new(source) });
+    }
+
+    /// Removes the mounts at `point`, returns how many there were
+    pub fn unmount(&mut self, point: &str) -> usize {
+        let point = normalize(point).unwrap_or_default();
+        let count = self.mounts.len();
+        self.mounts.retain(|mount| mount.point != point);
+        count - self.mounts.len()
+    }
+
+    /// Reads the file from the topmost mount that has it
+    pub fn read(&self, path: &str) -> EngineResult<Vec<u8>> {
+        let normalized = normalize(path).ok_or_else(|| not_found(path, "invalid path"))?;
+        for (mount, relative) in self.candidates(&normalized) {
+            if let Some(result) = mount.source.read(relative) {
+                return result.map_err(|error| EngineError::asset_io(path, error));
+            }
+        }
+        Err(not_found(path, "not in any mount"))
+    }
+
+    pub fn read_to_string(&self, path: &str) -> EngineResult<String> {
+        let bytes = self.read(path)?;
+        String::from_utf8(bytes).map_err(|_| EngineError::asset_io(path, io::Error::new(io::ErrorKind::InvalidData, "not UTF-8")))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        normalize(path).is_some_and(|path| self.candidates(&path).any(|(mount, relative)| mount.source.contains(relative)))
+    }
+
+    /// Virtual paths of all visible files, sorted, overlaid duplicates once
+    pub fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self.mounts.iter()
+            .flat_map(|mount| mount.source.files().into_iter().map(move |file| join(&mount.point, &file)))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// mounts that may contain the path, topmost first, with the path relative to them
+    fn candidates<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a Mount, &'a str)> + 'a {
+        self.mounts.iter().rev().filter_map(move |mount| {
+            if mount.point.is_empty() {
+                Some((mount, path))
+            } else {
+                path.strip_prefix(mount.point.as_str()).and_then(|rest| rest.strip_prefix('/')).map(|rest| (mount, rest))
+            }
+        })
+    }
+}
+
+/// `/`-separated path without empty or `.` components, `None` if it escapes the root with `..`
+pub fn normalize(path: &str) -> Option<String> {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => {},
+            ".." => {
+                components.pop()?;
+            },
+            component => components.push(component),
+        }
+    }
+    Some(components.join("/"))
+}
+
+fn join(point: &str, path: &str) -> String {
+    if point.is_empty() { path.to_string() } else { format!("{}/{}", point, path) }
+}
+
+fn not_found(path: &str, reason: &str) -> EngineError {
+    EngineError::asset_io(path, io::Error::new(io::ErrorKind::NotFound, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_mounts_overlay_earlier_ones() {
+        let mut base = PackWriter::new();
+        base.add("textures/wood.png", b"base wood".to_vec());
+        base.add("textures/stone.png", b"base stone".to_vec());
+        let mut patch = PackWriter::new();
+        patch.add("wood.png", b"patched wood".to_vec());
+
+        let mut vfs = Vfs::new();
+        vfs.mount("/", PackFile::from_bytes(base.to_bytes()).unwrap());
+        vfs.mount("textures", PackFile::from_bytes(patch.to_bytes()).unwrap());
+
+        assert_eq!(vfs.read("textures/wood.png").unwrap(), b"patched wood");
+        assert_eq!(vfs.read("/textures/./stone.png").unwrap(), b"base stone");
+        assert!(vfs.read("textures/../../etc/passwd").is_err());
+        assert_eq!(vfs.files(), vec!["textures/stone.png", "textures/wood.png"]);
+
+        assert_eq!(vfs.unmount("textures"), 1);
+        assert_eq!(vfs.read("textures/wood.png").unwrap(), b"base wood");
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use error::{EngineError, EngineResult};
+use super::{normalize, FileSource};
+
+const MAGIC: &[u8; 4] = b"RPAK";
+const VERSION: u32 = 1;
+
+/// Where one file is stored in the pack
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Entry {
+    offset: u64,
+    size: u32,
+    /// less than `size` when the data is compressed
+    stored_size: u32,
+}
+
+enum Data {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
+
+/// Read-only asset archive written by `PackWriter`.
+///
+/// Layout, little-endian: `RPAK`, version u32, entry count u32, then per entry the name length
+/// u16, the UTF-8 name, data offset u64, size u32 and stored size u32, followed by the data.
+/// Only the index is read on open, files are read from disk when requested.
+pub struct PackFile {
+    data: Data,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl PackFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> EngineResult<PackFile> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(|error| EngineError::asset_io(path, error))?;
+        let entries = file.metadata()
+            .and_then(|metadata| read_index(&mut file, metadata.len()))
+            .map_err(|error| EngineError::asset_io(path, error))?;
+        Ok(PackFile { data: Data::File(path.to_path_buf()), entries })
+    }
+
+    /// A pack held in memory, e.g. embedded in the binary
+    pub fn from_bytes(bytes: Vec<u8>) -> EngineResult<PackFile> {
+        let entries = read_index(&mut io::Cursor::new(&bytes), bytes.len() as u64)
+            .map_err(|error| EngineError::asset_io("<memory>", error))?;
+        Ok(PackFile { data: Data::Memory(bytes), entries })
+    }
+
+    /// The index was checked against the data's length, reading stays bounded if the file
+    /// changed since
+    fn read_entry(&self, entry: &Entry) -> io::Result<Vec<u8>> {
+        let stored = match self.data {
+            Data::File(ref path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(entry.offset))?;
+                let mut stored = Vec::new();
+                file.take(entry.stored_size as u64).read_to_end(&mut stored)?;
+                if stored.len() != entry.stored_size as usize {
+                    return Err(invalid("entry out of bounds"));
+                }
+                stored
+            },
+            Data::Memory(ref bytes) => {
+                let start = entry.offset as usize;
+                entry.offset.checked_add(entry.stored_size as u64)
+                    .filter(|&end| end <= bytes.len() as u64)
+                    .and_then(|end| bytes.get(start..end as usize))
+                    .ok_or_else(|| invalid("entry out of bounds"))?
+                    .to_vec()
+            },
+        };
+        if entry.stored_size == entry.size {
+            Ok(stored)
+        } else {
+            decompress(&stored, entry.size as usize)
+        }
+    }
+}
+
+impl FileSource for PackFile {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        self.entries.get(path).map(|entry| self.read_entry(entry))
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn files(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+}
+
+/// Reads the index of a pack of `pack_length` bytes, every entry has to lie within them
+fn read_index<R: Read>(reader: &mut R, pack_length: u64) -> io::Result<BTreeMap<String, Entry>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a pack file"));
+    }
+    if read_u32(reader)? != VERSION {
+        return Err(invalid("unsupported pack version"));
+    }
+    let count = read_u32(reader)?;
+    let mut entries = BTreeMap::new();
+    for _ in 0..count {
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let mut name = vec![0; u16::from_le_bytes(length) as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid("entry name is not UTF-8"))?;
+        let mut offset = [0; 8];
+        reader.read_exact(&mut offset)?;
+        let entry = Entry { offset: u64::from_le_bytes(offset), size: read_u32(reader)?, stored_size: read_u32(reader)? };
+        if entry.offset.checked_add(entry.stored_size as u64).is_none_or(|end| end > pack_length) {
+            return Err(invalid("entry out of bounds"));
+        }
+        // the writer only stores compressed data when it is smaller
+        if entry.stored_size > entry.size {
+            return Err(invalid("entry larger than its contents"));
+        }
+        entries.insert(name, entry);
+    }
+    Ok(entries)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Builds a `PackFile`, usually from a build script or asset tool. Files are compressed
+/// when it makes them smaller.
+#[derive(Default)]
+pub struct PackWriter {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl PackWriter {
+    pub fn new() -> PackWriter {
+        PackWriter::default()
+    }
+
+    /// Adds or replaces a file, the path is normalized like `Vfs` paths
+    pub fn add(&mut self, path: &str, data: Vec<u8>) {
+        let path = normalize(path).expect("pack paths can't leave the root");
+        self.files.insert(path, data);
+    }
+
+    /// Adds every file under the directory, with paths relative to it
+    pub fn add_directory<P: AsRef<Path>>(&mut self, dir: P) -> EngineResult<()> {
+        let dir = dir.as_ref();
+        for path in super::Directory::new(dir).files() {
+            let full = dir.join(&path);
+            let data = ::std::fs::read(&full).map_err(|error| EngineError::asset_io(full, error))?;
+            self.add(&path, data);
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let stored: Vec<(&String, &Vec<u8>, Vec<u8>)> = self.files.iter()
+            .map(|(name, data)| (name, data, compress(data)))
+            .collect();
+        let index_size: usize = stored.iter().map(|&(name, _, _)| 2 + name.len() + 16).sum();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        let mut offset = (bytes.len() + index_size) as u64;
+        for &(name, data, ref compressed) in &stored {
+            let stored_size = compressed.len().min(data.len());
+            bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(stored_size as u32).to_le_bytes());
+            offset += stored_size as u64;
+        }
+        for &(_, data, ref compressed) in &stored {
+            bytes.extend_from_slice(if compressed.len() < data.len() { compressed } else { data });
+        }
+        bytes
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> EngineResult<()> {
+        let path = path.as_ref();
+        ::std::fs::write(path, self.to_bytes()).map_err(|error| EngineError::asset_io(path, error))
+    }
+}
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 127;
+const WINDOW: usize = 0xFFFF;
+/// Most bytes one stored byte decompresses to, a 3 byte back reference of `MAX_MATCH`
+const MAX_EXPANSION: usize = MAX_MATCH / 3 + 1;
+
+/// LZ77 with a single-entry hash table: a token byte below 0x80 is followed by that many + 1
+/// literals, above it is a back reference of `token - 0x80 + MIN_MATCH` bytes at the u16
+/// offset that follows
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let mut table = vec![usize::MAX; 1 << 14];
+    let mut literals = 0..0;
+    let mut i = 0;
+    while i < data.len() {
+        let mut length = 0;
+        if i + MIN_MATCH <= data.len() {
+            let key = &data[i..i + MIN_MATCH];
+            let hash = (u32::from_le_bytes([key[0], key[1], key[2], key[3]]).wrapping_mul(2_654_435_761) >> 18) as usize;
+            let candidate = table[hash];
+            table[hash] = i;
+            if candidate != usize::MAX && i - candidate <= WINDOW {
+                while length < MAX_MATCH && i + length < data.len() && data[candidate + length] == data[i + length] {
+                    length += 1;
+                }
+            }
+            if length >= MIN_MATCH {
+                flush_literals(&mut out, &data[literals.clone()]);
+                out.push(0x80 + (length - MIN_MATCH) as u8);
+                out.extend_from_slice(&((i - candidate) as u16).to_le_bytes());
+                i += length;
+                literals = i..i;
+                continue;
+            }
+        }
+        i += 1;
+        literals.end = i;
+    }
+    flush_literals(&mut out, &data[literals]);
+    out
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(128) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn decompress(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    // a corrupt size can't reserve more than the data could produce
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(MAX_EXPANSION)));
+    let mut i = 0;
+    while i < data.len() {
+        if out.len() > size {
+            return Err(invalid("decompressed size mismatch"));
+        }
+        let token = data[i] as usize;
+        i += 1;
+        if token < 0x80 {
+            let literals = data.get(i..i + token + 1).ok_or_else(|| invalid("truncated literals"))?;
+            out.extend_from_slice(literals);
+            i += token + 1;
+        } else {
+            let offset = data.get(i..i + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+                .ok_or_else(|| invalid("truncated match"))?;
+            i += 2;
+            if offset == 0 || offset > out.len() {
+                return Err(invalid("match before the start of the file"));
+            }
+            // byte by byte, matches may overlap what they produce
+            let start = out.len() - offset;
+            for k in 0..token - 0x80 + MIN_MATCH {
+                let byte = out[start + k];
+                out.push(byte);
+            }
+        }
+    }
+    if out.len() != size {
+        return Err(invalid("decompressed size mismatch"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compresses_round_trip() {
+        let text = "uniform mat4 model; uniform mat4 view; uniform mat4 projection;\n".repeat(20);
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() < text.len() / 4);
+        assert_eq!(decompress(&compressed, text.len()).unwrap(), text.as_bytes());
+
+        let noise: Vec<u8> = (0..1000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        assert_eq!(decompress(&compress(&noise), noise.len()).unwrap(), noise);
+    }
+
+    #[test]
+    fn rejects_entries_outside_the_pack() {
+        let mut writer = PackWriter::new();
+        writer.add("a.txt", b"hello".to_vec());
+        let bytes = writer.to_bytes();
+        assert_eq!(PackFile::from_bytes(bytes.clone()).unwrap().read("a.txt").unwrap().unwrap(), b"hello");
+
+        // the entry's offset, size and stored size follow the 12 byte header and the name
+        let offset = 12 + 2 + "a.txt".len();
+        let with = |at: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[at..at + value.len()].copy_from_slice(value);
+            PackFile::from_bytes(bytes)
+        };
+        assert!(with(offset, &u64::MAX.to_le_bytes()).is_err());
+        assert!(with(offset, &(bytes.len() as u64 - 2).to_le_bytes()).is_err());
+        assert!(with(offset + 8, &[0xFF; 8]).is_err());
+        // stored size larger than the contents
+        assert!(with(offset + 12, &4u32.to_le_bytes()).is_ok());
+        assert!(with(offset + 8, &4u32.to_le_bytes()).is_err());
+
+        // a corrupt size can't make decompression reserve gigabytes
+        assert!(decompress(&compress(b"abcdabcdabcd"), u32::MAX as usize).is_err());
+    }
+}
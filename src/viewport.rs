@@ -0,0 +1,125 @@
+use gl;
+
+use lang::Float;
+
+/// Rectangle of a framebuffer in pixels, with GL's bottom-left origin
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Viewport {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Viewport {
+        Viewport { x, y, width, height }
+    }
+
+    /// The whole `width` x `height` framebuffer
+    pub fn full(width: i32, height: i32) -> Viewport {
+        Viewport::new(0, 0, width, height)
+    }
+
+    /// The viewport set in GL
+    pub fn current() -> Viewport {
+        let mut rect = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, rect.as_mut_ptr());
+        }
+        Viewport::new(rect[0], rect[1], rect[2], rect[3])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn aspect(&self) -> Float {
+        if self.height == 0 { 0.0 } else { self.width as Float / self.height as Float }
+    }
+
+    /// Sub-rectangle given in fractions of this one, `(0, 0)` is the bottom left corner
+    pub fn sub(&self, x: Float, y: Float, width: Float, height: Float) -> Viewport {
+        let left = self.x + (x * self.width as Float).round() as i32;
+        let bottom = self.y + (y * self.height as Float).round() as i32;
+        let right = self.x + ((x + width) * self.width as Float).round() as i32;
+        let top = self.y + ((y + height) * self.height as Float).round() as i32;
+        Viewport::new(left, bottom, right - left, top - bottom)
+    }
+
+    /// Splits into a `columns` x `rows` grid, ordered left to right and top to bottom
+    /// (reading order, as split-screen players are usually numbered)
+    pub fn split(&self, columns: usize, rows: usize) -> Vec<Viewport> {
+        let (cell_width, cell_height) = (1.0 / columns as Float, 1.0 / rows as Float);
+        let mut cells = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let y = 1.0 - (row + 1) as Float * cell_height;
+                cells.push(self.sub(column as Float * cell_width, y, cell_width, cell_height));
+            }
+        }
+        cells
+    }
+
+    /// Largest centered rectangle with the given width / height ratio: bars at the top and
+    /// bottom (letterbox) or at the sides (pillarbox)
+    pub fn letterbox(&self, aspect: Float) -> Viewport {
+        if self.is_empty() || aspect <= 0.0 {
+            return *self;
+        }
+        if self.aspect() > aspect {
+            let width = (self.height as Float * aspect).round() as i32;
+            Viewport::new(self.x + (self.width - width) / 2, self.y, width, self.height)
+        } else {
+            let height = (self.width as Float / aspect).round() as i32;
+            Viewport::new(self.x, self.y + (self.height - height) / 2, self.width, height)
+        }
+    }
+
+    /// Whether the pixel `(x, y)`, in window coordinates with a top-left origin, lies inside
+    /// when the framebuffer is `framebuffer_height` pixels high
+    pub fn contains_window_point(&self, x: Float, y: Float, framebuffer_height: i32) -> bool {
+        let y = framebuffer_height as Float - y;
+        x >= self.x as Float && x < (self.x + self.width) as Float &&
+            y >= self.y as Float && y < (self.y + self.height) as Float
+    }
+
+    /// Sets the GL viewport
+    pub fn apply(&self) {
+        unsafe {
+            gl::Viewport(self.x, self.y, self.width, self.height);
+        }
+    }
+
+    /// Restricts drawing and clears to this rectangle, until `disable_scissor`
+    pub fn scissor(&self) {
+        unsafe {
+            gl::Enable(gl::SCISSOR_TEST);
+            gl::Scissor(self.x, self.y, self.width, self.height);
+        }
+    }
+}
+
+pub fn disable_scissor() {
+    unsafe {
+        gl::Disable(gl::SCISSOR_TEST);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letterboxes_and_splits() {
+        let screen = Viewport::full(1920, 1200);
+        assert_eq!(screen.letterbox(16.0 / 9.0), Viewport::new(0, 60, 1920, 1080));
+        assert_eq!(Viewport::full(1000, 500).letterbox(1.0), Viewport::new(250, 0, 500, 500));
+
+        let cells = Viewport::full(800, 600).split(2, 2);
+        assert_eq!(cells[0], Viewport::new(0, 300, 400, 300));
+        assert_eq!(cells[3], Viewport::new(400, 0, 400, 300));
+        assert!(cells[0].contains_window_point(10.0, 10.0, 600));
+        assert!(!cells[0].contains_window_point(10.0, 310.0, 600));
+    }
+}
@@ -0,0 +1,487 @@
+use std::ffi::CString;
+use std::ptr;
+
+use cgmath::prelude::*;
+use gl;
+use gl::types::*;
+use serde::{Serialize, Deserialize};
+
+use lang::{Float, Point3, Vector2, Vector3, Vector4, Matrix4, TimeSec, PI};
+use atmosphere::{Fog, FOG_GLSL};
+use bounds::Plane;
+use camera::Camera;
+use color::Color;
+use gl_state::{ClearSpec, GlState};
+use logging;
+use mesh::{primitives, Mesh};
+use renderer::reflection::{PlanarReflection, PLANAR_REFLECTION_GLSL};
+use renderer::{Renderer, SceneView};
+use shader::Shader;
+use viewport::Viewport;
+
+/// Waves the surface shader sums, extra ones are ignored
+pub const MAX_WAVES: usize = 4;
+
+const GRAVITY: Float = 9.8;
+
+/// One Gerstner wave: the surface moves in circles, gathering into sharper crests as
+/// `steepness` goes to 1. The speed follows from the wavelength, as for deep water.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GerstnerWave {
+    /// degrees around Y the wave travels to, 0 along +X and 90 along +Z
+    pub direction: Float,
+    pub wavelength: Float,
+    /// 0 is flat, above 1 the crests loop over themselves
+    pub steepness: Float,
+}
+
+impl GerstnerWave {
+    pub fn new(direction: Float, wavelength: Float, steepness: Float) -> GerstnerWave {
+        GerstnerWave { direction, wavelength, steepness }
+    }
+
+    fn heading(&self) -> Vector2 {
+        let radians = self.direction.to_radians();
+        Vector2::new(radians.cos(), radians.sin())
+    }
+
+    fn wave_number(&self) -> Float {
+        2.0 * PI / self.wavelength
+    }
+
+    /// Vertical distance between a crest and a trough
+    pub fn height(&self) -> Float {
+        2.0 * self.steepness / self.wave_number()
+    }
+
+    /// Offset of the surface point resting at `(x, z)`
+    pub fn displacement(&self, x: Float, z: Float, time: TimeSec) -> Vector3 {
+        let d = self.heading();
+        let k = self.wave_number();
+        let f = k * (d.x * x + d.y * z - (GRAVITY / k).sqrt() * time as Float);
+        let a = self.steepness / k;
+        Vector3::new(d.x * a * f.cos(), a * f.sin(), d.y * a * f.cos())
+    }
+}
+
+/// Sum of `waves` at the surface point resting at `(x, z)`, as the water vertex shader moves it
+pub fn displacement(waves: &[GerstnerWave], x: Float, z: Float, time: TimeSec) -> Vector3 {
+    waves.iter().take(MAX_WAVES).fold(Vector3::zero(), |sum, wave| sum + wave.displacement(x, z, time))
+}
+
+/// Height of the waves above the rest level at world `(x, z)`, for floating objects. The waves
+/// also move the surface sideways, so the point displaced onto `(x, z)` is searched for.
+pub fn wave_height(waves: &[GerstnerWave], x: Float, z: Float, time: TimeSec) -> Float {
+    let mut rest = Vector2::new(x, z);
+    for _ in 0..4 {
+        let offset = displacement(waves, rest.x, rest.y, time);
+        rest = Vector2::new(x - offset.x, z - offset.z);
+    }
+    displacement(waves, rest.x, rest.y, time).y
+}
+
+const WATER_VERTEX_SHADER: &str = r#"
+#version 330 core
+layout (location = 0) in vec3 aPos;
+out vec3 WorldPos;
+out vec3 Normal;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+uniform float time;
+uniform int waveCount;
+// heading x and z, steepness, wavelength
+uniform vec4 waves[4];
+
+const float GRAVITY = 9.8;
+
+void main()
+{
+    vec3 rest = (model * vec4(aPos, 1.0)).xyz;
+    vec3 world = rest;
+    vec3 tangent = vec3(1.0, 0.0, 0.0);
+    vec3 binormal = vec3(0.0, 0.0, 1.0);
+    for (int i = 0; i < waveCount; ++i) {
+        vec2 d = waves[i].xy;
+        float steepness = waves[i].z;
+        float k = 6.2831853 / waves[i].w;
+        float f = k * (dot(d, rest.xz) - sqrt(GRAVITY / k) * time);
+        float a = steepness / k;
+        world += vec3(d.x * a * cos(f), a * sin(f), d.y * a * cos(f));
+        tangent += vec3(-d.x * d.x * steepness * sin(f), d.x * steepness * cos(f), -d.x * d.y * steepness * sin(f));
+        binormal += vec3(-d.x * d.y * steepness * sin(f), d.y * steepness * cos(f), -d.y * d.y * steepness * sin(f));
+    }
+    WorldPos = world;
+    Normal = normalize(cross(binormal, tangent));
+    gl_Position = projection * view * vec4(world, 1.0);
+}
+"#;
+
+/// after the version line, `FOG_GLSL` and `PLANAR_REFLECTION_GLSL`
+const WATER_FRAGMENT_BODY: &str = r#"
+out vec4 FragColor;
+in vec3 WorldPos;
+in vec3 Normal;
+
+uniform sampler2D refractionMap;
+uniform sampler2D refractionDepth;
+uniform sampler2D normalMap;
+uniform bool hasNormalMap;
+uniform vec2 normalScroll;
+uniform float normalTiling;
+uniform float time;
+uniform vec3 shallowColor;
+uniform vec3 deepColor;
+uniform float depthFade;
+uniform float shoreFade;
+uniform float reflectance;
+uniform float fresnelPower;
+uniform float distortion;
+uniform float cameraNear;
+uniform float cameraFar;
+
+float linearDepth(float depth)
+{
+    float z = depth * 2.0 - 1.0;
+    return 2.0 * cameraNear * cameraFar / (cameraFar + cameraNear - z * (cameraFar - cameraNear));
+}
+
+void main()
+{
+    vec3 normal = normalize(Normal);
+    if (hasNormalMap) {
+        // two layers scrolling against each other hide the tiling
+        vec2 uv = WorldPos.xz * normalTiling;
+        vec3 first = texture(normalMap, uv + normalScroll * time).rgb * 2.0 - 1.0;
+        vec3 second = texture(normalMap, uv * 0.7 - normalScroll * time * 0.8).rgb * 2.0 - 1.0;
+        vec3 detail = normalize(first + second);
+        normal = normalize(vec3(normal.x + detail.x, normal.y * detail.z, normal.z + detail.y));
+    }
+
+    vec2 screen = (gl_FragCoord.xy - planarReflectionViewport.xy) / planarReflectionViewport.zw;
+    vec2 offset = normal.xz * distortion;
+    vec2 refractedUV = clamp(screen + offset, 0.001, 0.999);
+    float surface = linearDepth(gl_FragCoord.z);
+    float waterDepth = linearDepth(texture(refractionDepth, refractedUV).r) - surface;
+    if (waterDepth < 0.0) {
+        // the distorted lookup caught something in front of the water
+        refractedUV = screen;
+        waterDepth = linearDepth(texture(refractionDepth, screen).r) - surface;
+    }
+
+    vec3 refracted = texture(refractionMap, refractedUV).rgb * shallowColor;
+    refracted = mix(refracted, deepColor, clamp(waterDepth / depthFade, 0.0, 1.0));
+    vec3 reflected = planarReflection(offset);
+
+    vec3 toCamera = normalize(cameraPosition - WorldPos);
+    float fresnel = reflectance + (1.0 - reflectance) * pow(1.0 - max(dot(normal, toCamera), 0.0), fresnelPower);
+    vec3 color = mix(refracted, reflected, fresnel);
+    FragColor = vec4(applyFog(color, WorldPos), clamp(waterDepth / shoreFade, 0.0, 1.0));
+}
+"#;
+
+fn water_fragment_shader() -> String {
+    format!("#version 330 core\n{}{}{}", FOG_GLSL, PLANAR_REFLECTION_GLSL, WATER_FRAGMENT_BODY)
+}
+
+/// What's below the water surface, color and a sampleable depth texture of the same size,
+/// the depth giving how much water covers each pixel
+pub struct RefractionTarget {
+    pub width: i32,
+    pub height: i32,
+    pub clear: ClearSpec,
+    fbo: u32,
+    color_texture: u32,
+    depth_texture: u32,
+}
+
+impl RefractionTarget {
+    pub fn new(width: i32, height: i32) -> RefractionTarget {
+        let mut target = RefractionTarget {
+            width,
+            height,
+            clear: ClearSpec::default(),
+            fbo: 0,
+            color_texture: 0,
+            depth_texture: 0,
+        };
+        unsafe {
+            gl::GenFramebuffers(1, &mut target.fbo);
+            gl::GenTextures(1, &mut target.color_texture);
+            gl::GenTextures(1, &mut target.depth_texture);
+        }
+        target.allocate();
+        target
+    }
+
+    pub fn color_texture(&self) -> u32 {
+        self.color_texture
+    }
+
+    pub fn depth_texture(&self) -> u32 {
+        self.depth_texture
+    }
+
+    pub fn resize(&mut self, width: i32, height: i32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.allocate();
+    }
+
+    fn allocate(&mut self) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.color_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, self.width, self.height, 0,
+                           gl::RGBA, gl::UNSIGNED_BYTE, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+
+            gl::BindTexture(gl::TEXTURE_2D, self.depth_texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as GLint, self.width, self.height, 0,
+                           gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.color_texture, 0);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, self.depth_texture, 0);
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                engine_error!(logging::RENDERER, "refraction framebuffer is not complete: 0x{:x}", status);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// Draws the commands `submit` queues for the view of `camera` into the target, with clip
+    /// distance 0 enabled. The previous viewport is restored afterwards.
+    pub fn render<F: FnOnce(&SceneView, &mut Renderer)>(&mut self, renderer: &mut Renderer, camera: &Camera, submit: F) {
+        let mut previous = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, previous.as_mut_ptr());
+        }
+        let view = SceneView::new(camera.clone(), Viewport::full(self.width, self.height)).clear(self.clear);
+        submit(&view, renderer);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Enable(gl::CLIP_DISTANCE0);
+        }
+        renderer.render_views(::std::slice::from_ref(&view));
+        unsafe {
+            gl::Disable(gl::CLIP_DISTANCE0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        Viewport::new(previous[0], previous[1], previous[2], previous[3]).apply();
+    }
+
+    pub fn delete(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteTextures(1, &self.color_texture);
+            gl::DeleteTextures(1, &self.depth_texture);
+        }
+        self.fbo = 0;
+        self.color_texture = 0;
+        self.depth_texture = 0;
+    }
+}
+
+/// Animated water plane: Gerstner waves with optional scrolling detail normals, blending the
+/// planar reflection and the refraction by the Fresnel term, darkening with depth and fading
+/// out at the shore. Each frame call `render_targets` before the main pass, then `draw` after
+/// the opaque geometry.
+pub struct Water {
+    /// center of the surface, its `y` is the rest level
+    pub position: Point3,
+    pub waves: Vec<GerstnerWave>,
+    /// tints what's seen through shallow water
+    pub shallow_color: Color,
+    /// color of deep water, reached `depth_fade` below the surface
+    pub deep_color: Color,
+    pub depth_fade: Float,
+    /// the surface is transparent where it meets the ground, opaque this deep
+    pub shore_fade: Float,
+    /// reflected fraction looking straight down, 0.02 for water
+    pub reflectance: Float,
+    pub fresnel_power: Float,
+    /// how far the surface normals shift the reflection and refraction lookups
+    pub distortion: Float,
+    /// tangent space normal map texture, 0 for none
+    pub normal_map: u32,
+    /// normal map UVs per second
+    pub normal_scroll: Vector2,
+    /// normal map repeats per world unit
+    pub normal_tiling: Float,
+    pub fog: Fog,
+    pub reflection: PlanarReflection,
+    pub refraction: RefractionTarget,
+    shader: Shader,
+    mesh: Mesh,
+}
+
+impl Water {
+    /// Square surface `extent` wide, split into `segments` quads a side for the waves to move,
+    /// with reflection and refraction targets of the main viewport's `size`
+    pub fn new(extent: Float, segments: u32, size: (i32, i32)) -> Water {
+        let position = Point3::new(0.0, 0.0, 0.0);
+        engine_debug!(logging::RENDERER, "water surface of {} with {} segments", extent, segments);
+        Water {
+            position,
+            waves: vec![
+                GerstnerWave::new(0.0, 12.0, 0.15),
+                GerstnerWave::new(50.0, 7.0, 0.12),
+                GerstnerWave::new(-35.0, 3.5, 0.08),
+            ],
+            shallow_color: Color::srgb(0.85, 0.95, 0.95, 1.0),
+            deep_color: Color::srgb(0.02, 0.12, 0.18, 1.0),
+            depth_fade: 6.0,
+            shore_fade: 0.3,
+            reflectance: 0.02,
+            fresnel_power: 5.0,
+            distortion: 0.02,
+            normal_map: 0,
+            normal_scroll: Vector2::new(0.02, 0.01),
+            normal_tiling: 0.1,
+            fog: Fog::default(),
+            reflection: PlanarReflection::new(water_plane(position), size),
+            refraction: RefractionTarget::new(size.0, size.1),
+            shader: Shader::from_source(WATER_VERTEX_SHADER, &water_fragment_shader()),
+            mesh: Mesh::new(&primitives::grid(extent, extent, segments, segments)),
+        }
+    }
+
+    /// Call it with the main viewport size
+    pub fn resize(&mut self, width: i32, height: i32) {
+        self.reflection.resize(width, height);
+        self.refraction.resize(width, height);
+    }
+
+    /// Height of the surface at world `(x, z)`, see `wave_height`
+    pub fn height_at(&self, x: Float, z: Float, time: TimeSec) -> Float {
+        self.position.y + wave_height(&self.waves, x, z, time)
+    }
+
+    /// Clip planes of the reflection and the refraction, keeping what's above and below the
+    /// surface; they reach over the wave height so no geometry goes missing under the crests
+    pub fn clip_planes(&self) -> (Vector4, Vector4) {
+        let reach = self.waves.iter().map(GerstnerWave::height).sum::<Float>() * 0.5;
+        let level = self.position.y;
+        (Vector4::new(0.0, 1.0, 0.0, reach - level), Vector4::new(0.0, -1.0, 0.0, level + reach))
+    }
+
+    /// Draws the scene into the reflection and refraction targets. `submit` is called once for
+    /// each with the view and the clip plane, which shaders drawn there write to
+    /// `gl_ClipDistance[0]`; the built-in shaders take it as `clipPlane`.
+    pub fn render_targets<F>(&mut self, renderer: &mut Renderer, camera: &Camera, mut submit: F)
+        where F: FnMut(&SceneView, Vector4, &mut Renderer)
+    {
+        let (above, below) = self.clip_planes();
+        self.reflection.plane = water_plane(self.position);
+        self.reflection.render(renderer, camera, |view, renderer| submit(view, above, renderer));
+        self.refraction.render(renderer, camera, |view, renderer| submit(view, below, renderer));
+    }
+
+    /// Draws the surface blended over the opaque geometry, seen by `camera` in `viewport`.
+    /// Uses texture units 0 to 3.
+    pub fn draw(&self, state: &mut GlState, camera: &Camera, viewport: &Viewport, time: TimeSec) {
+        let model = Matrix4::from_translation(self.position.to_vec());
+        let projection = camera.projection_matrix(viewport.width, viewport.height);
+        let c = camera.position;
+
+        state.use_program(self.shader.ID);
+        state.set_blend(true);
+        state.blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        state.set_cull_face(false);
+        self.reflection.bind(&self.shader, 0, viewport);
+        self.fog.bind(&self.shader, c);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, self.refraction.color_texture());
+            gl::ActiveTexture(gl::TEXTURE2);
+            gl::BindTexture(gl::TEXTURE_2D, self.refraction.depth_texture());
+            gl::ActiveTexture(gl::TEXTURE3);
+            gl::BindTexture(gl::TEXTURE_2D, self.normal_map);
+            let shader = &self.shader;
+            shader.setInt(c_str!("refractionMap"), 1);
+            shader.setInt(c_str!("refractionDepth"), 2);
+            shader.setInt(c_str!("normalMap"), 3);
+            shader.setInt(c_str!("hasNormalMap"), (self.normal_map != 0) as i32);
+
+            shader.setMat4(c_str!("model"), &model);
+            shader.setMat4(c_str!("view"), &camera.view_matrix());
+            shader.setMat4(c_str!("projection"), &projection);
+            shader.setFloat(c_str!("time"), time as Float);
+            let waves = self.waves.len().min(MAX_WAVES);
+            shader.setInt(c_str!("waveCount"), waves as i32);
+            for (i, wave) in self.waves.iter().take(waves).enumerate() {
+                let d = wave.heading();
+                let name = CString::new(format!("waves[{}]", i)).unwrap();
+                shader.setVec4(&name, d.x, d.y, wave.steepness, wave.wavelength);
+            }
+
+            shader.setVec2(c_str!("normalScroll"), self.normal_scroll.x, self.normal_scroll.y);
+            shader.setFloat(c_str!("normalTiling"), self.normal_tiling);
+            shader.setColor(c_str!("shallowColor"), &self.shallow_color);
+            shader.setColor(c_str!("deepColor"), &self.deep_color);
+            shader.setFloat(c_str!("depthFade"), self.depth_fade);
+            shader.setFloat(c_str!("shoreFade"), self.shore_fade);
+            shader.setFloat(c_str!("reflectance"), self.reflectance);
+            shader.setFloat(c_str!("fresnelPower"), self.fresnel_power);
+            shader.setFloat(c_str!("distortion"), self.distortion);
+            shader.setFloat(c_str!("cameraNear"), camera.near);
+            shader.setFloat(c_str!("cameraFar"), camera.far);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+        self.mesh.draw_with_state(state);
+        state.set_blend(false);
+    }
+
+    pub fn delete(&mut self) {
+        self.reflection.delete();
+        self.refraction.delete();
+        self.mesh.delete();
+        unsafe {
+            gl::DeleteProgram(self.shader.ID);
+        }
+    }
+}
+
+fn water_plane(position: Point3) -> Plane {
+    Plane::new(Vector3::unit_y(), position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gerstner_waves_and_surface_height() {
+        let wave = GerstnerWave::new(0.0, 2.0 * PI, 0.5);
+        assert!((wave.height() - 1.0).abs() < 1e-5);
+        // phase of a quarter period at the start, the crest
+        let crest = wave.displacement(PI / 2.0, 3.0, 0.0);
+        assert!(crest.x.abs() < 1e-5 && (crest.y - 0.5).abs() < 1e-5 && crest.z == 0.0);
+
+        // the surface point displaced onto x, found by brute force along the wave
+        let (x, time) = (0.7, 2.5);
+        let rest = (0..20000)
+            .map(|i| -3.0 + i as Float * 0.0003)
+            .min_by(|a, b| {
+                let miss = |r: Float| (r + wave.displacement(r, 0.0, time).x - x).abs();
+                miss(*a).partial_cmp(&miss(*b)).unwrap()
+            })
+            .unwrap();
+        let expected = wave.displacement(rest, 0.0, time).y;
+        assert!((wave_height(&[wave], x, 0.0, time) - expected).abs() < 1e-2);
+        assert_eq!(wave_height(&[], x, 1.0, time), 0.0);
+    }
+}
@@ -0,0 +1,54 @@
+use glfw::{Key, MouseButton, Action};
+
+use lang::TimeSec;
+use input::{KeyEvent, MouseButtonEvent, TouchPoint};
+
+/// Platform independent window event, produced by a `WindowBackend`
+#[derive(Clone, PartialEq, Debug)]
+pub enum BackendEvent {
+    FramebufferSize(i32, i32),
+    CursorPos(f64, f64),
+    Scroll(f64, f64),
+    MouseButton(MouseButtonEvent),
+    Key(KeyEvent),
+    /// text input, already translated by the keyboard layout
+    Char(char),
+    Touch(TouchPoint),
+}
+
+/// Immediate key and mouse button state, queried by `InputControl::on_input`
+pub trait InputState {
+    fn get_key(&self, key: Key) -> Action;
+    fn get_mouse_button(&self, button: MouseButton) -> Action;
+}
+
+/// Windowing system the engine `Window` facade is running on top of. `GlfwBackend` is the
+/// only implementation: a winit backend also needs a GL context crate, and the key and mouse
+/// button types of `KeyEvent` and `InputState` are still glfw's.
+pub trait WindowBackend: InputState {
+    fn should_close(&self) -> bool;
+    fn set_should_close(&mut self, value: bool);
+
+    /// time in seconds since the backend was initialized
+    fn time(&self) -> TimeSec;
+
+    /// size of the default framebuffer in pixels
+    fn framebuffer_size(&self) -> (i32, i32);
+
+    fn swap_buffers(&mut self);
+
+    /// poll IO events (keys pressed/released, mouse moved etc.)
+    fn poll_events(&mut self);
+
+    /// take all the events received since the last call
+    fn flush_events(&mut self) -> Vec<BackendEvent>;
+
+    /// window title, ignored by backends without one
+    fn set_title(&mut self, _title: &str) {}
+
+    /// Hides the cursor and locks it to the window for mouse look, with raw (unaccelerated)
+    /// motion where the platform supports it. Returns whether the motion is raw.
+    fn set_cursor_grabbed(&mut self, _grabbed: bool) -> bool {
+        false
+    }
+}
@@ -0,0 +1,192 @@
+use std::os::raw::c_int;
+use std::sync::mpsc::Receiver;
+
+use gl;
+use glfw::{self, ffi, Glfw, Context, CursorMode, Key, MouseButton, Action, Window as GlfwWindow, WindowEvent};
+
+use color;
+use config::WindowConfig;
+use error::{EngineError, EngineResult};
+use lang::TimeSec;
+use logging;
+use input::{KeyEvent, MouseButtonEvent};
+use super::backend::{BackendEvent, InputState, WindowBackend};
+
+type Events = Receiver<(f64, WindowEvent)>;
+
+/// glfw 3.3 input mode, not exposed by the `glfw` crate
+const RAW_MOUSE_MOTION: c_int = 0x0003_3005;
+
+extern "C" {
+    fn glfwRawMouseMotionSupported() -> c_int;
+}
+
+pub struct GlfwBackend {
+    glfw: Glfw,
+    window: GlfwWindow,
+    events: Events,
+}
+
+impl GlfwBackend {
+    /// Panics if GLFW or the window can't be initialized, see `try_new`
+    pub fn new(title: &str, width: u32, height: u32) -> GlfwBackend {
+        GlfwBackend::try_new(title, width, height).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_new(title: &str, width: u32, height: u32) -> EngineResult<GlfwBackend> {
+        GlfwBackend::with_config(&WindowConfig { title: title.to_string(), width, height, ..WindowConfig::default() })
+    }
+
+    /// Window with the size, vsync, MSAA and sRGB settings of the config
+    pub fn with_config(config: &WindowConfig) -> EngineResult<GlfwBackend> {
+        GlfwBackend::create(config, true)
+    }
+
+    /// Invisible window, for offscreen rendering with a GL context
+    pub fn hidden(title: &str, width: u32, height: u32) -> GlfwBackend {
+        GlfwBackend::try_hidden(title, width, height).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub fn try_hidden(title: &str, width: u32, height: u32) -> EngineResult<GlfwBackend> {
+        GlfwBackend::create(&WindowConfig { title: title.to_string(), width, height, ..WindowConfig::default() }, false)
+    }
+
+    fn create(config: &WindowConfig, visible: bool) -> EngineResult<GlfwBackend> {
+        let (title, width, height) = (config.title.as_str(), config.width, config.height);
+        // ------------------------------
+        // glfw: initialize and configure
+        // ------------------------------
+        let mut glfw = glfw::init(glfw::LOG_ERRORS)?;
+        glfw.window_hint(glfw::WindowHint::ContextVersion(3, 3));
+        glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+        glfw.window_hint(glfw::WindowHint::Samples(if config.msaa > 0 { Some(config.msaa) } else { None }));
+        glfw.window_hint(glfw::WindowHint::SRgbCapable(config.srgb));
+        glfw.window_hint(glfw::WindowHint::Visible(visible));
+        #[cfg(target_os = "macos")]
+            glfw.window_hint(glfw::WindowHint::OpenGlForwardCompat(true));
+
+        // --------------------
+        // glfw window creation
+        // --------------------
+        let (mut window, events) = glfw.create_window(width, height, title, glfw::WindowMode::Windowed)
+            .ok_or_else(|| EngineError::WindowCreation(format!("{}x{} \"{}\"", width, height, title)))?;
+
+        window.make_current();
+        glfw.set_swap_interval(if config.vsync { glfw::SwapInterval::Sync(1) } else { glfw::SwapInterval::None });
+        window.set_key_polling(true);
+        window.set_char_polling(true);
+        window.set_cursor_pos_polling(true);
+        window.set_scroll_polling(true);
+        window.set_framebuffer_size_polling(true);
+
+        // -------------------------------------
+        // gl: load all OpenGL function pointers
+        // -------------------------------------
+        gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
+        color::set_framebuffer_srgb(config.srgb);
+        engine_info!(logging::WINDOW, "created {}x{} window \"{}\"", width, height, title);
+
+        Ok(GlfwBackend {
+            glfw,
+            window,
+            events,
+        })
+    }
+
+    pub fn glfw(&self) -> &Glfw {
+        &self.glfw
+    }
+
+    pub fn glfw_window(&self) -> &GlfwWindow {
+        &self.window
+    }
+
+    pub fn glfw_window_mut(&mut self) -> &mut GlfwWindow {
+        &mut self.window
+    }
+
+    /// Whether the platform can report unaccelerated mouse motion
+    pub fn raw_mouse_motion_supported() -> bool {
+        unsafe { glfwRawMouseMotionSupported() != 0 }
+    }
+}
+
+impl InputState for GlfwWindow {
+    fn get_key(&self, key: Key) -> Action {
+        GlfwWindow::get_key(self, key)
+    }
+
+    fn get_mouse_button(&self, button: MouseButton) -> Action {
+        GlfwWindow::get_mouse_button(self, button)
+    }
+}
+
+impl InputState for GlfwBackend {
+    fn get_key(&self, key: Key) -> Action {
+        self.window.get_key(key)
+    }
+
+    fn get_mouse_button(&self, button: MouseButton) -> Action {
+        self.window.get_mouse_button(button)
+    }
+}
+
+impl WindowBackend for GlfwBackend {
+    fn should_close(&self) -> bool {
+        self.window.should_close()
+    }
+
+    fn set_should_close(&mut self, value: bool) {
+        self.window.set_should_close(value)
+    }
+
+    fn time(&self) -> TimeSec {
+        self.glfw.get_time() as TimeSec
+    }
+
+    fn framebuffer_size(&self) -> (i32, i32) {
+        self.window.get_framebuffer_size()
+    }
+
+    fn swap_buffers(&mut self) {
+        self.window.swap_buffers();
+    }
+
+    fn poll_events(&mut self) {
+        self.glfw.poll_events();
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn set_cursor_grabbed(&mut self, grabbed: bool) -> bool {
+        self.window.set_cursor_mode(if grabbed { CursorMode::Disabled } else { CursorMode::Normal });
+        if !GlfwBackend::raw_mouse_motion_supported() {
+            if grabbed {
+                engine_warn!(logging::INPUT, "raw mouse motion not supported, mouse deltas are accelerated");
+            }
+            return false;
+        }
+        unsafe { ffi::glfwSetInputMode(self.window.window_ptr(), RAW_MOUSE_MOTION, grabbed as c_int) };
+        grabbed
+    }
+
+    fn flush_events(&mut self) -> Vec<BackendEvent> {
+        glfw::flush_messages(&self.events)
+            .filter_map(|(_, event)| match event {
+                WindowEvent::FramebufferSize(width, height) => Some(BackendEvent::FramebufferSize(width, height)),
+                WindowEvent::CursorPos(x_pos, y_pos) => Some(BackendEvent::CursorPos(x_pos, y_pos)),
+                WindowEvent::Scroll(x_offset, y_offset) => Some(BackendEvent::Scroll(x_offset, y_offset)),
+                WindowEvent::MouseButton(button, action, modifiers) => {
+                    Some(BackendEvent::MouseButton(MouseButtonEvent(button, action, modifiers)))
+                },
+                WindowEvent::Key(key, code, action, modifiers) => {
+                    Some(BackendEvent::Key(KeyEvent(key, code, action, modifiers)))
+                },
+                WindowEvent::Char(character) => Some(BackendEvent::Char(character)),
+                _ => None
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,363 @@
+pub mod backend;
+pub mod glfw_backend;
+
+use glfw::{Key, Action, Window as GlfwWindow};
+
+use color::Color;
+use config::EngineConfig;
+use error::EngineResult;
+use logging;
+use lang::{ObjectPar, Float, RasterFloat};
+use gl_state::{ClearSpec, GlState};
+use input::{MouseEvent, KeyEvent, InputEvent, InputControl, InputContextStack, KeyboardState, TouchGestures, TouchPoint};
+use texture;
+use timing::{FrameStats, Timing};
+use viewport::Viewport;
+
+pub use self::backend::{BackendEvent, InputState, WindowBackend};
+pub use self::glfw_backend::GlfwBackend;
+
+pub struct Window<B: WindowBackend = GlfwBackend> {
+    /// always receive input, whatever the contexts
+    pub controls: Vec<ObjectPar<InputControl>>,
+    /// controls grouped in contexts, only the active ones receive input
+    pub contexts: InputContextStack,
+    /// held keys, set `keyboard.long_press_threshold` to tune long presses
+    pub keyboard: KeyboardState,
+    pub timing: Timing,
+    backend: B,
+    last_mouse_pos: Option<(RasterFloat, RasterFloat)>,
+    clear: Option<ClearSpec>,
+    /// state the clears go through, invalidated after each render since that changes GL state
+    gl_state: GlState,
+    framebuffer_size: (i32, i32),
+    fixed_aspect: Option<Float>,
+    title: String,
+    title_stats: Option<FrameStats>,
+    raw_mouse: bool,
+    gestures: TouchGestures,
+}
+
+impl Window<GlfwBackend> {
+    /// Panics if the window can't be created, see `try_new`
+    pub fn new(title: &str, width: u32, height: u32) -> Window {
+        Window::with_backend(GlfwBackend::new(title, width, height)).titled(title)
+    }
+
+    pub fn try_new(title: &str, width: u32, height: u32) -> EngineResult<Window> {
+        Ok(Window::with_backend(GlfwBackend::try_new(title, width, height)?).titled(title))
+    }
+
+    pub fn from_config(config: &EngineConfig) -> EngineResult<Window> {
+        let window = Window::with_backend(GlfwBackend::with_config(&config.window)?).titled(&config.window.title);
+        texture::set_quality(&config.textures);
+        Ok(window)
+    }
+
+    pub fn glfw_window(&self) -> &GlfwWindow {
+        self.backend.glfw_window()
+    }
+
+    pub fn glfw_window_mut(&mut self) -> &mut GlfwWindow {
+        self.backend.glfw_window_mut()
+    }
+}
+
+impl<B: WindowBackend> Window<B> {
+    pub fn with_backend(backend: B) -> Window<B> {
+        let framebuffer_size = backend.framebuffer_size();
+        Window {
+            controls: vec![],
+            contexts: InputContextStack::new(),
+            keyboard: KeyboardState::default(),
+            timing: Timing::default(),
+            backend,
+            last_mouse_pos: None,
+            clear: None,
+            gl_state: GlState::new(),
+            framebuffer_size,
+            fixed_aspect: None,
+            title: String::new(),
+            title_stats: None,
+            raw_mouse: false,
+            gestures: TouchGestures::new(),
+        }
+    }
+
+    /// records the title the backend was created with, without setting it again
+    fn titled(mut self, title: &str) -> Window<B> {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    pub fn framebuffer_size(&self) -> (i32, i32) {
+        self.framebuffer_size
+    }
+
+    /// Keeps the viewport at the given width / height ratio, with letterbox or pillarbox bars
+    /// in the clear color around it. `None` fills the framebuffer.
+    pub fn set_fixed_aspect(&mut self, aspect: Option<Float>) {
+        self.fixed_aspect = aspect;
+        self.viewport().apply();
+    }
+
+    /// Area of the default framebuffer the scene is drawn into, split it with `Viewport::split`
+    /// or `Viewport::sub` for split-screen
+    pub fn viewport(&self) -> Viewport {
+        let (width, height) = self.framebuffer_size;
+        let full = Viewport::full(width, height);
+        match self.fixed_aspect {
+            Some(aspect) => full.letterbox(aspect),
+            None => full,
+        }
+    }
+
+    /// Clears the default framebuffer with `clear` at the start of every frame, before the
+    /// render callback runs
+    pub fn set_clear(&mut self, clear: ClearSpec) {
+        self.clear = Some(clear);
+    }
+
+    /// Hides and locks the cursor for mouse look, see `WindowBackend::set_cursor_grabbed`.
+    /// Returns whether `MouseEvent` offsets are now raw.
+    pub fn set_cursor_grabbed(&mut self, grabbed: bool) -> bool {
+        self.raw_mouse = self.backend.set_cursor_grabbed(grabbed);
+        // the cursor jumps when its mode changes, don't report that as motion
+        self.last_mouse_pos = None;
+        self.raw_mouse
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+        let title = self.formatted_title();
+        self.backend.set_title(&title);
+    }
+
+    /// Appends the frame rate and frame time to the title, e.g. `Game - 60.0 fps (16.67 ms)`,
+    /// refreshed a few times per second rather than every frame
+    pub fn set_title_stats(&mut self, enabled: bool) {
+        self.title_stats = if enabled { Some(FrameStats::default()) } else { None };
+        let title = self.formatted_title();
+        self.backend.set_title(&title);
+    }
+
+    /// Frame rate over the last interval, while title stats are enabled
+    pub fn frame_stats(&self) -> Option<&FrameStats> {
+        self.title_stats.as_ref()
+    }
+
+    fn formatted_title(&self) -> String {
+        match self.title_stats {
+            Some(ref stats) if stats.fps > 0.0 => {
+                format!("{} - {:.1} fps ({:.2} ms)", self.title, stats.fps, stats.frame_ms)
+            },
+            _ => self.title.clone(),
+        }
+    }
+
+    pub fn events_loop<F: FnMut(&mut Window<B>)>(&mut self, mut render: Option<F>) {
+        while !self.backend.should_close() {
+            logging::next_frame();
+            self.timing();
+
+            // ## events
+            self.process_events();
+
+            // ## process input
+            self.process_input();
+
+            // ## render
+            if let Some(clear) = self.clear {
+                clear.apply(&mut self.gl_state);
+            }
+            if let Some(ref mut render) = render {
+                render(self);
+            } else {
+                self.render();
+            }
+            self.gl_state.invalidate();
+            self.backend.swap_buffers();
+
+            // ## poll IO events (keys pressed/released, mouse moved etc.)
+            self.backend.poll_events();
+        }
+    }
+
+    /// per-frame time logic
+    fn timing(&mut self) {
+        let current_frame = self.backend.time();
+        self.timing.delta_time = current_frame - self.timing.last_frame;
+        self.timing.last_frame = current_frame;
+
+        let delta_time = self.timing.delta_time;
+        if self.title_stats.as_mut().is_some_and(|stats| stats.record(delta_time)) {
+            let title = self.formatted_title();
+            self.backend.set_title(&title);
+        }
+    }
+
+    fn process_events(&mut self) {
+        for event in self.backend.flush_events() {
+            match event {
+                BackendEvent::FramebufferSize(width, height) => {
+                    // make sure the viewport matches the new window dimensions; note that width and
+                    // height will be significantly larger than specified on retina displays.
+                    engine_debug!(logging::WINDOW, "framebuffer resized to {}x{}", width, height);
+                    self.framebuffer_size = (width, height);
+                    self.viewport().apply();
+                },
+                BackendEvent::CursorPos(x_pos, y_pos) => {
+                    let (x_pos, y_pos) = (x_pos as RasterFloat, y_pos as RasterFloat);
+
+                    if self.last_mouse_pos.is_none() {
+                        self.last_mouse_pos = Some((x_pos, y_pos));
+                    }
+
+                    let (x_last, y_last) = self.last_mouse_pos.unwrap();
+                    let x_offset = x_pos - x_last;
+                    let y_offset = y_last - y_pos; // reversed since y-coordinates go from bottom to top
+
+                    self.last_mouse_pos = Some((x_pos, y_pos));
+
+                    self.mouse_event(MouseEvent {
+                        x_pos,
+                        y_pos,
+                        x_offset,
+                        y_offset,
+                        is_scroll: false,
+                        button_event: None,
+                        is_raw: self.raw_mouse,
+                    });
+                },
+                BackendEvent::Scroll(x_offset, y_offset) => {
+                    let (x_pos, y_pos) = self.last_mouse_pos.unwrap_or((0.0, 0.0));
+                    self.mouse_event(MouseEvent {
+                        x_pos,
+                        y_pos,
+                        x_offset: x_offset as RasterFloat,
+                        y_offset: y_offset as RasterFloat,
+                        is_scroll: true,
+                        button_event: None,
+                        is_raw: false,
+                    });
+                },
+                // This is not work (why?), use InputState::get_mouse_button in process_input instead
+                BackendEvent::MouseButton(button_event) => {
+                    let (x_pos, y_pos) = self.last_mouse_pos.unwrap_or((0.0, 0.0));
+                    self.mouse_event(MouseEvent {
+                        x_pos,
+                        y_pos,
+                        x_offset: 0.0,
+                        y_offset: 0.0,
+                        is_scroll: false,
+                        button_event: Some(button_event),
+                        is_raw: false,
+                    });
+                },
+                BackendEvent::Key(key_event) => {
+                    self.keyboard_event(key_event)
+                },
+                BackendEvent::Char(character) => {
+                    self.char_event(character)
+                },
+                BackendEvent::Touch(touch) => {
+                    self.touch_event(touch)
+                },
+            }
+        }
+    }
+
+    /// the global controls, then the active contexts' from the top of the stack
+    fn routed_controls(&self) -> impl Iterator<Item = &ObjectPar<dyn InputControl>> {
+        self.controls.iter().chain(self.contexts.active_controls())
+    }
+
+    fn process_input(&mut self) {
+        let long_presses = self.keyboard.long_presses(self.timing.last_frame);
+        for control in self.routed_controls() {
+            match control.lock() {
+                Ok(mut control) => {
+                    for &key in &long_presses {
+                        control.on_long_press(key, self.timing.delta_time);
+                    }
+                    control.on_input(&self.backend, self.timing.delta_time)
+                },
+                Err(_) => engine_warn!(logging::INPUT, "skipping input control with a poisoned lock"),
+            }
+        }
+    }
+
+    fn render(&mut self) {
+        if self.clear.is_none() {
+            // the color of `ClearSpec::default`, encoded like the sRGB framebuffer expects
+            ClearSpec::none().clear_color(Color::srgb(0.2, 0.3, 0.3, 1.0)).apply(&mut self.gl_state);
+        }
+    }
+}
+
+impl<B: WindowBackend> InputEvent for Window<B> {
+    fn mouse_event(&mut self, event: MouseEvent) {
+        for control in self.routed_controls() {
+            match control.lock() {
+                Ok(mut control) => control.on_mouse(event.clone(), self.timing.delta_time),
+                Err(_) => engine_warn!(logging::INPUT, "dropping mouse event for a control with a poisoned lock"),
+            }
+        }
+    }
+
+    fn keyboard_event(&mut self, event: KeyEvent) {
+        match event {
+            KeyEvent(Key::Escape, _, Action::Press, _) => self.backend.set_should_close(true),
+            _ => ()
+        }
+        self.keyboard.on_key(&event, self.timing.last_frame);
+
+        for control in self.routed_controls() {
+            match control.lock() {
+                Ok(mut control) if event.2 == Action::Repeat => control.on_key_repeat(event.clone(), self.timing.delta_time),
+                Ok(mut control) => control.on_keyboard(event.clone(), self.timing.delta_time),
+                Err(_) => engine_warn!(logging::INPUT, "dropping key event for a control with a poisoned lock"),
+            }
+        }
+    }
+
+    fn char_event(&mut self, character: char) {
+        for control in self.routed_controls() {
+            match control.lock() {
+                Ok(mut control) => control.on_char(character, self.timing.delta_time),
+                Err(_) => engine_warn!(logging::INPUT, "dropping text input for a control with a poisoned lock"),
+            }
+        }
+    }
+
+    fn touch_event(&mut self, touch: TouchPoint) {
+        let gestures = self.gestures.update(&touch);
+        for control in self.routed_controls() {
+            match control.lock() {
+                Ok(mut control) => {
+                    control.on_touch(touch, self.timing.delta_time);
+                    for &gesture in &gestures {
+                        control.on_gesture(gesture, self.timing.delta_time);
+                    }
+                },
+                Err(_) => engine_warn!(logging::INPUT, "dropping touch input for a control with a poisoned lock"),
+            }
+        }
+    }
+}